@@ -0,0 +1,128 @@
+// `cargo bench --bench command_line`: times command mode's line reader over a busy stream of command lines and
+// counts what it allocates, next to the String it used to build each line in and hand off, so the reused buffer
+// can be seen to pay for itself.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use touchppp::at::{classify, ByteClass, CommandLine, Line};
+
+// About what a WebTV sends from power on to the dial, with the odd backspace and bit of line noise.
+const LINES: &[&[u8]] = &[
+    b"AT\r",
+    b"ATE0Q0V0&C1&D2S0=0\r",
+    b"ATS7=60L3\r",
+    b"AT&FE0V0+MS=V34,1,2400,28800;S51=31\r",
+    b"ATDT18006138199\x08\x089\r",
+    b"\x00\xffATD\r",
+];
+
+// How long each gets, once it's warmed up.
+const RUN_FOR: Duration = Duration::from_secs(2);
+
+// Every allocation and reallocation, however big.
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct Counted;
+
+#[global_allocator]
+static ALLOCATOR: Counted = Counted;
+
+unsafe impl GlobalAlloc for Counted {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+// How command lines were read before: a String that's pushed a char at a time and taken for every line.
+struct OldCommandLine {
+    line: String,
+    max_length: usize,
+    is_too_long: bool,
+}
+
+impl OldCommandLine {
+    // Kept out of line the way a call into the library is, so neither gets folded into the loop.
+    #[inline(never)]
+    fn push(&mut self, byte: u8) -> Option<Line> {
+        match classify(byte) {
+            ByteClass::End if self.is_too_long => {
+                self.is_too_long = false;
+
+                Some(Line::TooLong)
+            },
+            ByteClass::End => {
+                self.line.push('\r');
+
+                Some(Line::Command(std::mem::take(&mut self.line)))
+            },
+            ByteClass::Erase => {
+                self.line.pop();
+
+                None
+            },
+            ByteClass::Command if byte == b' ' && self.line.is_empty() => None,
+            ByteClass::Command => {
+                if self.is_too_long || self.line.len() + 1 >= self.max_length {
+                    self.line.clear();
+                    self.is_too_long = true;
+                } else {
+                    self.line.push(byte as char);
+                }
+
+                None
+            },
+            ByteClass::Noise => None,
+        }
+    }
+}
+
+// Bytes read per second and allocations per line, pushing `stream` through `push` over and over for RUN_FOR.
+fn time(stream: &[u8], mut push: impl FnMut(u8) -> Option<Line>) -> (f64, f64) {
+    let mut bytes = 0;
+    let mut lines = 0;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+
+    while started.elapsed() < RUN_FOR {
+        for &byte in stream {
+            if black_box(push(black_box(byte))).is_some() {
+                lines += 1;
+            }
+        }
+        bytes += stream.len();
+    }
+
+    let bytes_per_second = bytes as f64 / started.elapsed().as_secs_f64();
+    let allocations_per_line = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / lines as f64;
+
+    (bytes_per_second, allocations_per_line)
+}
+
+fn main() {
+    let stream: Vec<u8> = LINES.iter().cycle().take(LINES.len() * 1000).flat_map(|line| line.iter().copied()).collect();
+
+    let mut old = OldCommandLine { line: String::new(), max_length: 255, is_too_long: false };
+    let mut new = CommandLine::new(255);
+
+    for (name, (bytes_per_second, allocations_per_line)) in [
+        ("String per line", time(&stream, |byte| old.push(byte))),
+        ("reused buffer", time(&stream, |byte| new.push(byte))),
+    ] {
+        println!("{name:<16} {:>8.1} MB/s {allocations_per_line:>6.2} allocations a line", bytes_per_second / 1_000_000.0);
+    }
+}
@@ -0,0 +1,52 @@
+// Whatever MAME types in command mode, a byte at a time into a command line and each line it makes into a modem,
+// the way a session takes it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use touchppp::at::{self, CommandLine, Line};
+use touchppp::modem::ModemSession;
+use touchppp_fuzz::holds_at_most;
+
+// A line as long as it can be, a modem with every S register set and saved to both &W slots, and the &V that lists
+// it all. None of it grows with how much is typed.
+const MOST_HELD: usize = 256 << 10;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the max line length, the way --max-command-length would, and which modem gets it.
+    let Some((&setup, typed)) = data.split_first() else {
+        return;
+    };
+    let max_length = (setup & 0x7f) as usize + 1;
+    let profile = if setup & 0x80 == 0 { &at::WEBTV } else { &at::GENERIC };
+
+    let _kept = holds_at_most(MOST_HELD, || {
+        let mut command_line = CommandLine::new(max_length);
+        let mut modem = ModemSession::new().with_profile(profile);
+
+        for &byte in typed {
+            match command_line.push(byte) {
+                Some(Line::Command(line)) => {
+                    assert_eq!(byte, b'\r');
+                    assert!(line.len() <= max_length, "{line:?} is longer than {max_length}");
+                    assert!(line.bytes().all(|byte| byte == b'\r' || at::classify(byte) == at::ByteClass::Command), "{line:?}");
+
+                    modem.answer(&line);
+                },
+                Some(Line::TooLong) => assert_eq!(byte, b'\r'),
+                None => {},
+            }
+        }
+
+        (command_line, modem)
+    });
+
+    // Anything else that reads command lines, on lines that never went through CommandLine.
+    let line = String::from_utf8_lossy(typed);
+    let _ = at::tokenize(&line);
+    let _ = at::is_well_formed(&line);
+    let _ = at::settings(&line);
+    let _ = at::s_register_writes(&line);
+    let _ = at::s_register_query(&line);
+    let _ = at::parse_as(&line, &at::GENERIC);
+});
@@ -0,0 +1,55 @@
+// Whatever comes off the link, cut into reads and fed to everything that picks HDLC frames out of it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use touchppp::hdlc::{self, Deframer, FrameSpotter, Splitter, FLAG};
+use touchppp_fuzz::holds_at_most;
+
+// The deframer's and splitter's frames so far, neither of which can get past 4096 bytes before it's dropped.
+const MOST_HELD: usize = 16 << 10;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte says how long each read is.
+    let Some((&read_length, link)) = data.split_first() else {
+        return;
+    };
+
+    let _kept = holds_at_most(MOST_HELD, || {
+        let mut deframer = Deframer::default();
+        let mut splitter = Splitter::default();
+        let mut spotter = FrameSpotter::default();
+        let mut frames = 0;
+
+        for read in link.chunks(read_length as usize + 1) {
+            for frame in splitter.push(read) {
+                assert!(frame.len() > 2 && frame[0] == FLAG && frame[frame.len() - 1] == FLAG, "{frame:02x?}");
+                assert!(!frame[1..frame.len() - 1].contains(&FLAG), "{frame:02x?}");
+            }
+
+            if let Some(spotted) = spotter.push(read) {
+                assert_eq!(spotted[0], FLAG);
+                spotter = FrameSpotter::default();
+            }
+
+            for &byte in read {
+                let Some(frame) = deframer.push(byte) else {
+                    continue;
+                };
+                assert_eq!(byte, FLAG);
+                frames += 1;
+
+                // A good frame framed up again comes back out the same.
+                if let Some((protocol, information)) = hdlc::split(&frame) {
+                    let mut again = Deframer::default();
+                    let reframed: Vec<Vec<u8>> = hdlc::frame(protocol, information, hdlc::ALL_ESCAPED).into_iter().filter_map(|byte| again.push(byte)).collect();
+                    assert_eq!(reframed.iter().map(|frame| hdlc::split(frame)).collect::<Vec<_>>(), [Some((protocol, information))]);
+                }
+            }
+        }
+
+        assert!(frames <= link.iter().filter(|byte| **byte == FLAG).count());
+
+        (deframer, splitter, spotter)
+    });
+});
@@ -0,0 +1,31 @@
+// Whatever comes off the link from a box that dials in with SLIP.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use touchppp::slip::{self, Decoder};
+use touchppp_fuzz::holds_at_most;
+
+// The packet so far, which can't get past 4096 bytes before it's dropped.
+const MOST_HELD: usize = 16 << 10;
+
+fuzz_target!(|data: &[u8]| {
+    let _kept = holds_at_most(MOST_HELD, || {
+        let mut decoder = Decoder::default();
+
+        for &byte in data {
+            let Some(packet) = decoder.push(byte) else {
+                continue;
+            };
+            assert_eq!(byte, slip::END);
+            assert!(!packet.is_empty());
+
+            // Encoded again, it decodes to the same thing.
+            let mut again = Decoder::default();
+            let decoded: Vec<Vec<u8>> = slip::encode(&packet).into_iter().filter_map(|byte| again.push(byte)).collect();
+            assert_eq!(decoded, [packet]);
+        }
+
+        decoder
+    });
+});
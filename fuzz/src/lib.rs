@@ -0,0 +1,76 @@
+// What the fuzz targets share: an allocator that keeps count, so a target can tell when a parser's holding on to
+// more than it should, however much it's been fed.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Past this, allocating fails, which aborts the run the same as a crash. Nothing one input does comes close.
+const CAP: usize = 64 << 20;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Counted;
+
+#[global_allocator]
+static ALLOCATOR: Counted = Counted;
+
+// Counts `size` more bytes, unless that goes past the cap.
+fn take(size: usize) -> bool {
+    if LIVE.fetch_add(size, Ordering::Relaxed) + size > CAP {
+        LIVE.fetch_sub(size, Ordering::Relaxed);
+
+        return false;
+    }
+
+    true
+}
+
+unsafe impl GlobalAlloc for Counted {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match take(layout.size()) {
+            true => System.alloc(layout),
+            false => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match take(layout.size()) {
+            true => System.alloc_zeroed(layout),
+            false => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() && !take(new_size - layout.size()) {
+            return std::ptr::null_mut();
+        }
+
+        let moved = System.realloc(ptr, layout, new_size);
+
+        match (moved.is_null(), new_size > layout.size()) {
+            (true, true) => LIVE.fetch_sub(new_size - layout.size(), Ordering::Relaxed),
+            (false, false) => LIVE.fetch_sub(layout.size() - new_size, Ordering::Relaxed),
+            _ => 0,
+        };
+
+        moved
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+/// Runs `feed`, which makes a parser and feeds it, and checks that what it gives back (the parser, once it's been
+/// fed everything) holds on to no more than `most` bytes. Whatever the parser gave out along the way has to be
+/// dropped by then.
+pub fn holds_at_most<T>(most: usize, feed: impl FnOnce() -> T) -> T {
+    let before = LIVE.load(Ordering::Relaxed);
+    let fed = feed();
+    let held = LIVE.load(Ordering::Relaxed).saturating_sub(before);
+
+    assert!(held <= most, "held on to {held} bytes, more than {most}");
+
+    fed
+}
@@ -0,0 +1,460 @@
+use std::fmt;
+use std::net::Ipv6Addr;
+
+pub const DEFAULT_IP: &str = "127.0.0.1";
+
+// Where -c goes when it's only given a host.
+pub const DEFAULT_REMOTE_PORT: u16 = 2323;
+
+const LISTEN_EXAMPLE: &str = "-l 1122, -l 0.0.0.0:1122 or -l [::1]:1122";
+const REMOTE_EXAMPLE: &str = "-c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323";
+const PIPE_EXAMPLE: &str = "-l pipe:\\\\.\\pipe\\touchppp";
+const ADMIN_EXAMPLE: &str = "--admin 1123, --admin 127.0.0.1:1123 or --admin /run/touchppp.sock";
+const SSH_EXAMPLE: &str = "--remote-ssh ppp@gateway.example.com or --remote-ssh ppp@10.0.0.2:2222";
+const SYSLOG_EXAMPLE: &str = "--log-syslog /dev/log, --log-syslog logs.example.com or --log-syslog tcp://10.0.0.5:601";
+
+// Where --remote-ssh goes when it's only given a host.
+pub const DEFAULT_SSH_PORT: u16 = 22;
+
+// Where --log-syslog goes with no address.
+pub const DEFAULT_SYSLOG_PATH: &str = "/dev/log";
+const DEFAULT_SYSLOG_PORT: u16 = 514;
+
+// -l pipe:\\.\pipe\NAME listens on a Windows named pipe instead of TCP.
+pub const PIPE_PREFIX: &str = "pipe:";
+const PIPE_NAMESPACE: &str = "\\\\.\\pipe\\";
+
+#[derive(Debug, PartialEq)]
+pub struct AddressError {
+    given: String,
+    problem: String,
+    example: &'static str,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' {}. Try something like {}", self.given, self.problem, self.example)
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+// Where MAME connects to us.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListenAddr {
+    pub host: String,
+    pub port: u16,
+}
+
+// A PPP server we connect to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteAddr {
+    pub host: String,
+    pub port: u16,
+}
+
+// An SSH server to reach PPP servers through, and who to log in to it as.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SshAddr {
+    pub user: String,
+    pub server: RemoteAddr,
+}
+
+// Where the admin socket listens: [HOST:]PORT like -l, or a unix socket path.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdminAddr {
+    Tcp(ListenAddr),
+    Unix(String),
+}
+
+// Where syslog messages go: the local syslog socket, or a syslog server over UDP or TCP.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyslogAddr {
+    Unix(String),
+    Udp(RemoteAddr),
+    Tcp(RemoteAddr),
+}
+
+fn format_host_port(f: &mut fmt::Formatter, host: &str, port: u16) -> fmt::Result {
+    if host.contains(':') {
+        write!(f, "[{}]:{}", host, port)
+    } else {
+        write!(f, "{}:{}", host, port)
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        format_host_port(f, &self.host, self.port)
+    }
+}
+
+impl fmt::Display for RemoteAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        format_host_port(f, &self.host, self.port)
+    }
+}
+
+impl fmt::Display for SshAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.user, self.server)
+    }
+}
+
+impl fmt::Display for AdminAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdminAddr::Tcp(listen_address) => listen_address.fmt(f),
+            AdminAddr::Unix(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+impl fmt::Display for SyslogAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyslogAddr::Unix(path) => write!(f, "{path}"),
+            SyslogAddr::Udp(remote_address) => write!(f, "udp://{remote_address}"),
+            SyslogAddr::Tcp(remote_address) => write!(f, "tcp://{remote_address}"),
+        }
+    }
+}
+
+impl ListenAddr {
+    pub fn target(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+}
+
+impl RemoteAddr {
+    pub fn target(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+}
+
+fn is_host_name(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 253
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+        && !host.starts_with(['.', '-'])
+}
+
+// Splits HOST:PORT, [IPV6]:PORT, HOST, [IPV6] or PORT. A missing host or port comes back as None.
+fn split_host_port(value: &str, example: &'static str) -> Result<(Option<String>, Option<String>), AddressError> {
+    let error = |problem: &str| AddressError {
+        given: value.to_string(),
+        problem: problem.to_string(),
+        example,
+    };
+
+    if value.trim().is_empty() {
+        return Err(error("is empty"));
+    }
+
+    if value.trim() != value {
+        return Err(error("has spaces around it"));
+    }
+
+    if let Some(rest) = value.strip_prefix('[') {
+        let Some((host, after)) = rest.split_once(']') else {
+            return Err(error("has a '[' without a matching ']'"));
+        };
+
+        if host.parse::<Ipv6Addr>().is_err() {
+            return Err(error("has something between the brackets that isn't an IPv6 address"));
+        }
+
+        return match after {
+            "" => Ok((Some(host.to_string()), None)),
+            _ => match after.strip_prefix(':') {
+                Some(port) => Ok((Some(host.to_string()), Some(port.to_string()))),
+                None => Err(error("has something other than ':PORT' after the ']'")),
+            },
+        };
+    }
+
+    if value.contains(']') || value.contains('[') {
+        return Err(error("has a ']' without a matching '['"));
+    }
+
+    match value.matches(':').count() {
+        0 => {
+            if value.chars().all(|c| c.is_ascii_digit()) {
+                Ok((None, Some(value.to_string())))
+            } else {
+                Ok((Some(value.to_string()), None))
+            }
+        },
+        1 => {
+            let (host, port) = value.split_once(':').unwrap_or_default();
+
+            if host.is_empty() {
+                return Err(error("is missing the host before the ':'"));
+            }
+
+            Ok((Some(host.to_string()), Some(port.to_string())))
+        },
+        _ => Err(error("looks like an IPv6 address without brackets around it")),
+    }
+}
+
+fn parse_port(value: &str, port: &str, allow_zero: bool, example: &'static str) -> Result<u16, AddressError> {
+    let lowest = if allow_zero { 0 } else { 1 };
+
+    match port.parse::<u16>() {
+        Ok(port) if port >= lowest => Ok(port),
+        _ => Err(AddressError {
+            given: value.to_string(),
+            problem: format!("has a port that isn't a number from {lowest} to 65535"),
+            example,
+        }),
+    }
+}
+
+fn check_host(value: &str, host: &str, example: &'static str) -> Result<(), AddressError> {
+    if host.contains(':') || is_host_name(host) {
+        Ok(())
+    } else {
+        Err(AddressError {
+            given: value.to_string(),
+            problem: format!("has a host '{host}' that isn't an IP address or host name"),
+            example,
+        })
+    }
+}
+
+fn parse_listen_with(value: &str, example: &'static str) -> Result<ListenAddr, AddressError> {
+    let (host, port) = split_host_port(value, example)?;
+
+    let Some(port) = port else {
+        return Err(AddressError {
+            given: value.to_string(),
+            problem: "is missing a port".to_string(),
+            example,
+        });
+    };
+
+    let host = host.unwrap_or_else(|| DEFAULT_IP.to_string());
+    check_host(value, &host, example)?;
+
+    Ok(ListenAddr {
+        port: parse_port(value, &port, true, example)?,
+        host,
+    })
+}
+
+// [HOST:]PORT, where a bare port listens on 127.0.0.1. Port 0 picks any free port.
+pub fn parse_listen(value: &str) -> Result<ListenAddr, AddressError> {
+    parse_listen_with(value, LISTEN_EXAMPLE)
+}
+
+// Anything with a / in it is a unix socket path, everything else is [HOST:]PORT.
+pub fn parse_admin(value: &str) -> Result<AdminAddr, AddressError> {
+    if value.contains('/') {
+        return Ok(AdminAddr::Unix(value.to_string()));
+    }
+
+    Ok(AdminAddr::Tcp(parse_listen_with(value, ADMIN_EXAMPLE)?))
+}
+
+fn parse_remote_with(value: &str, default_port: Option<u16>) -> Result<RemoteAddr, AddressError> {
+    let (host, port) = split_host_port(value, REMOTE_EXAMPLE)?;
+
+    let host = host.unwrap_or_else(|| DEFAULT_IP.to_string());
+    check_host(value, &host, REMOTE_EXAMPLE)?;
+
+    let port = match (port, default_port) {
+        (Some(port), _) => parse_port(value, &port, false, REMOTE_EXAMPLE)?,
+        (None, Some(port)) => port,
+        (None, None) => {
+            return Err(AddressError {
+                given: value.to_string(),
+                problem: "is missing a port".to_string(),
+                example: REMOTE_EXAMPLE,
+            })
+        },
+    };
+
+    Ok(RemoteAddr { host, port })
+}
+
+// [HOST:]PORT or HOST. A bare port means a PPP server on 127.0.0.1, same as -l, and a bare host gets port 2323.
+pub fn parse_remote(value: &str) -> Result<RemoteAddr, AddressError> {
+    parse_remote_with(value, Some(DEFAULT_REMOTE_PORT))
+}
+
+// [HOST:]PORT for reaching TouchPPP itself, which has no port everyone agrees on.
+pub fn parse_touchppp(value: &str) -> Result<RemoteAddr, AddressError> {
+    parse_remote_with(value, None)
+}
+
+// USER@HOST[:PORT], where port 22 is the default.
+pub fn parse_ssh(value: &str) -> Result<SshAddr, AddressError> {
+    let Some((user, host_port)) = value.rsplit_once('@').filter(|(user, _)| !user.is_empty()) else {
+        return Err(AddressError {
+            given: value.to_string(),
+            problem: "is missing the user to log in as before an '@'".to_string(),
+            example: SSH_EXAMPLE,
+        });
+    };
+
+    let (host, port) = split_host_port(host_port, SSH_EXAMPLE)?;
+
+    let Some(host) = host else {
+        return Err(AddressError {
+            given: value.to_string(),
+            problem: "is missing the host".to_string(),
+            example: SSH_EXAMPLE,
+        });
+    };
+    check_host(value, &host, SSH_EXAMPLE)?;
+
+    let port = match port {
+        Some(port) => parse_port(value, &port, false, SSH_EXAMPLE)?,
+        None => DEFAULT_SSH_PORT,
+    };
+
+    Ok(SshAddr { user: user.to_string(), server: RemoteAddr { host, port } })
+}
+
+// A path with a / in it, or [udp://|tcp://]HOST[:PORT] where UDP and port 514 are the defaults.
+pub fn parse_syslog(value: &str) -> Result<SyslogAddr, AddressError> {
+    let (is_tcp, host_port) = match (value.strip_prefix("tcp://"), value.strip_prefix("udp://")) {
+        (Some(host_port), _) => (true, host_port),
+        (_, Some(host_port)) => (false, host_port),
+        _ if value.contains('/') => return Ok(SyslogAddr::Unix(value.to_string())),
+        _ => (false, value),
+    };
+
+    let (host, port) = split_host_port(host_port, SYSLOG_EXAMPLE)?;
+
+    let host = host.unwrap_or_else(|| DEFAULT_IP.to_string());
+    check_host(value, &host, SYSLOG_EXAMPLE)?;
+
+    let port = match port {
+        Some(port) => parse_port(value, &port, false, SYSLOG_EXAMPLE)?,
+        None => DEFAULT_SYSLOG_PORT,
+    };
+
+    let remote_address = RemoteAddr { host, port };
+
+    Ok(if is_tcp { SyslogAddr::Tcp(remote_address) } else { SyslogAddr::Udp(remote_address) })
+}
+
+// pipe:\\.\pipe\NAME, giving back the \\.\pipe\NAME part.
+pub fn parse_pipe(value: &str) -> Result<String, AddressError> {
+    let error = |problem: &str| AddressError {
+        given: value.to_string(),
+        problem: problem.to_string(),
+        example: PIPE_EXAMPLE,
+    };
+
+    let Some(name) = value.strip_prefix(PIPE_PREFIX) else {
+        return Err(error("doesn't start with pipe:"));
+    };
+
+    match name.strip_prefix(PIPE_NAMESPACE) {
+        Some("") => Err(error("is missing the pipe name")),
+        Some(pipe_name) if pipe_name.contains('\\') => Err(error("has a '\\' in the pipe name")),
+        Some(_) => Ok(name.to_string()),
+        None => Err(error("isn't in the \\\\.\\pipe\\ namespace")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen_error(value: &str) -> String {
+        parse_listen(value).unwrap_err().to_string()
+    }
+
+    fn remote_error(value: &str) -> String {
+        parse_remote(value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accepts_good_addresses() {
+        assert_eq!(parse_listen("1122").unwrap().to_string(), "127.0.0.1:1122");
+        assert_eq!(parse_listen("0.0.0.0:0").unwrap().to_string(), "0.0.0.0:0");
+        assert_eq!(parse_listen("[::1]:1122").unwrap().to_string(), "[::1]:1122");
+        assert_eq!(parse_remote("2323").unwrap().to_string(), "127.0.0.1:2323");
+        assert_eq!(parse_remote("ppp.cool.com:2323").unwrap().to_string(), "ppp.cool.com:2323");
+        assert_eq!(parse_remote("[fd00::2]:2323").unwrap().target(), ("fd00::2", 2323));
+    }
+
+    #[test]
+    fn bare_port_remote_doesnt_depend_on_listen() {
+        // -c used to get its default host based on whether -l had a ':' in it.
+        assert_eq!(parse_remote("2323").unwrap().host, DEFAULT_IP);
+        assert_eq!(parse_remote("10.0.0.2:2323").unwrap().host, "10.0.0.2");
+    }
+
+    #[test]
+    fn remote_without_a_port_gets_the_default() {
+        assert_eq!(parse_remote("ppp.cool.com").unwrap().target(), ("ppp.cool.com", DEFAULT_REMOTE_PORT));
+        assert_eq!(parse_remote("10.0.0.2").unwrap().target(), ("10.0.0.2", DEFAULT_REMOTE_PORT));
+        assert_eq!(parse_remote("[fd00::2]").unwrap().to_string(), "[fd00::2]:2323");
+        assert_eq!(parse_remote("ppp.cool.com:5000").unwrap().target(), ("ppp.cool.com", 5000));
+        assert_eq!(parse_remote("[fd00::2]:5000").unwrap().to_string(), "[fd00::2]:5000");
+        assert_eq!(parse_touchppp("ppp.cool.com").unwrap_err().to_string(), "'ppp.cool.com' is missing a port. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(parse_touchppp("1122").unwrap().to_string(), "127.0.0.1:1122");
+        assert_eq!(remote_error("fd00::2"), "'fd00::2' looks like an IPv6 address without brackets around it. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+    }
+
+    #[test]
+    fn malformed_listen_addresses() {
+        assert_eq!(listen_error(""), "'' is empty. Try something like -l 1122, -l 0.0.0.0:1122 or -l [::1]:1122");
+        assert_eq!(listen_error(" 1122"), "' 1122' has spaces around it. Try something like -l 1122, -l 0.0.0.0:1122 or -l [::1]:1122");
+        assert_eq!(listen_error("70000"), "'70000' has a port that isn't a number from 0 to 65535. Try something like -l 1122, -l 0.0.0.0:1122 or -l [::1]:1122");
+        assert_eq!(listen_error("0.0.0.0"), "'0.0.0.0' is missing a port. Try something like -l 1122, -l 0.0.0.0:1122 or -l [::1]:1122");
+        assert_eq!(listen_error(":1122"), "':1122' is missing the host before the ':'. Try something like -l 1122, -l 0.0.0.0:1122 or -l [::1]:1122");
+        assert_eq!(listen_error("[::1:1122"), "'[::1:1122' has a '[' without a matching ']'. Try something like -l 1122, -l 0.0.0.0:1122 or -l [::1]:1122");
+        assert_eq!(listen_error("::1]:1122"), "'::1]:1122' has a ']' without a matching '['. Try something like -l 1122, -l 0.0.0.0:1122 or -l [::1]:1122");
+    }
+
+    #[test]
+    fn admin_addresses() {
+        assert_eq!(parse_admin("1123").unwrap(), AdminAddr::Tcp(ListenAddr { host: DEFAULT_IP.to_string(), port: 1123 }));
+        assert_eq!(parse_admin("/run/touchppp.sock").unwrap(), AdminAddr::Unix("/run/touchppp.sock".to_string()));
+        assert_eq!(parse_admin("./admin.sock").unwrap().to_string(), "./admin.sock");
+        assert_eq!(parse_admin("localhost").unwrap_err().to_string(), "'localhost' is missing a port. Try something like --admin 1123, --admin 127.0.0.1:1123 or --admin /run/touchppp.sock");
+    }
+
+    #[test]
+    fn syslog_addresses() {
+        assert_eq!(parse_syslog("/dev/log").unwrap(), SyslogAddr::Unix("/dev/log".to_string()));
+        assert_eq!(parse_syslog("logs.example.com").unwrap().to_string(), "udp://logs.example.com:514");
+        assert_eq!(parse_syslog("udp://10.0.0.5:5514").unwrap().to_string(), "udp://10.0.0.5:5514");
+        assert_eq!(parse_syslog("tcp://[fd00::5]").unwrap().to_string(), "tcp://[fd00::5]:514");
+        assert_eq!(parse_syslog("tcp://logs.example.com:0").unwrap_err().to_string(), "'tcp://logs.example.com:0' has a port that isn't a number from 1 to 65535. Try something like --log-syslog /dev/log, --log-syslog logs.example.com or --log-syslog tcp://10.0.0.5:601");
+    }
+
+    #[test]
+    fn ssh_addresses() {
+        assert_eq!(parse_ssh("ppp@gateway.example.com").unwrap().to_string(), "ppp@gateway.example.com:22");
+        assert_eq!(parse_ssh("ppp@[fd00::2]:2222").unwrap(), SshAddr { user: "ppp".to_string(), server: RemoteAddr { host: "fd00::2".to_string(), port: 2222 } });
+        assert_eq!(parse_ssh("gateway.example.com").unwrap_err().to_string(), "'gateway.example.com' is missing the user to log in as before an '@'. Try something like --remote-ssh ppp@gateway.example.com or --remote-ssh ppp@10.0.0.2:2222");
+        assert_eq!(parse_ssh("ppp@2222").unwrap_err().to_string(), "'ppp@2222' is missing the host. Try something like --remote-ssh ppp@gateway.example.com or --remote-ssh ppp@10.0.0.2:2222");
+    }
+
+    #[test]
+    fn pipe_names() {
+        assert_eq!(parse_pipe(r"pipe:\\.\pipe\touchppp").unwrap(), r"\\.\pipe\touchppp");
+        assert_eq!(parse_pipe(r"pipe:\\.\pipe\").unwrap_err().to_string(), r"'pipe:\\.\pipe\' is missing the pipe name. Try something like -l pipe:\\.\pipe\touchppp");
+        assert_eq!(parse_pipe(r"pipe:touchppp").unwrap_err().to_string(), r"'pipe:touchppp' isn't in the \\.\pipe\ namespace. Try something like -l pipe:\\.\pipe\touchppp");
+        assert_eq!(parse_pipe(r"pipe:\\.\pipe\a\b").unwrap_err().to_string(), r"'pipe:\\.\pipe\a\b' has a '\' in the pipe name. Try something like -l pipe:\\.\pipe\touchppp");
+    }
+
+    #[test]
+    fn malformed_remote_addresses() {
+        assert_eq!(remote_error("ppp.cool.com:0"), "'ppp.cool.com:0' has a port that isn't a number from 1 to 65535. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(remote_error("ppp.cool.com:ppp"), "'ppp.cool.com:ppp' has a port that isn't a number from 1 to 65535. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(remote_error("ppp.cool.com:"), "'ppp.cool.com:' has a port that isn't a number from 1 to 65535. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(remote_error("fd00::2:2323"), "'fd00::2:2323' looks like an IPv6 address without brackets around it. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(remote_error("[ppp.cool.com]:2323"), "'[ppp.cool.com]:2323' has something between the brackets that isn't an IPv6 address. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(remote_error("[fd00::2]2323"), "'[fd00::2]2323' has something other than ':PORT' after the ']'. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(remote_error("ppp cool.com:2323"), "'ppp cool.com:2323' has a host 'ppp cool.com' that isn't an IP address or host name. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+        assert_eq!(remote_error("-ppp:2323"), "'-ppp:2323' has a host '-ppp' that isn't an IP address or host name. Try something like -c ppp.cool.com:2323, -c 10.0.0.2:2323 or -c [fd00::2]:2323");
+    }
+}
@@ -0,0 +1,453 @@
+// --admin: a line protocol for looking at and managing a running TouchPPP.
+//
+//   list       one line per active session, then OK (sessions works too)
+//   kill ID    hang up a session, then OK
+//   drop [ID]  drop the carrier on a session's call, or the only call that's up, then OK
+//   stats      the running totals since we started, then the AT commands we've been sent by name and a few
+//              lines that weren't well formed, then OK (status works too)
+//   log FILTER log what FILTER says from now on, like --log-filter (debug, touchppp::at=debug), then OK
+//   drain      take no new calls, and stop once the ones that are up have ended, then OK
+//   pause ID   hold a session's call still both ways, without dropping the carrier, then OK
+//   resume ID  let a paused call carry on where it stopped, then OK
+//   inject ID mame|backend HEX
+//              send the bytes HEX spells out to one end of a session's call, between whatever else is going that
+//              way, then OK. Only with --enable-inject
+//   quit       close the connection
+//
+// Anything that goes wrong gets ERROR and a reason instead of OK. The console on stdin takes the same commands.
+
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::{info, warn};
+
+use crate::address::AdminAddr;
+use crate::server;
+use crate::stats::{self, InjectTo, SessionCommand, SessionState, Stats};
+
+// Bound before --daemon forks so a port that's taken is reported on the terminal, like -l.
+pub enum AdminListener {
+    Tcp(std::net::TcpListener),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+pub fn bind(admin: &AdminAddr) -> Result<AdminListener, String> {
+    match admin {
+        AdminAddr::Tcp(listen_address) => {
+            let listener = std::net::TcpListener::bind(listen_address.target())
+                .map_err(|e| format!("can't listen for admin commands on {listen_address}: {e}"))?;
+            listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+            Ok(AdminListener::Tcp(listener))
+        },
+        #[cfg(unix)]
+        AdminAddr::Unix(path) => {
+            use std::os::unix::net::{UnixListener, UnixStream};
+
+            // A socket file left behind by a TouchPPP that didn't get to clean up is fine to replace, one that
+            // something's still answering on isn't.
+            if std::path::Path::new(path).exists() {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(format!("something is already taking admin commands on {path}"));
+                }
+
+                std::fs::remove_file(path).map_err(|e| format!("can't remove old admin socket {path}: {e}"))?;
+            }
+
+            let listener = UnixListener::bind(path).map_err(|e| format!("can't listen for admin commands on {path}: {e}"))?;
+            listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+            Ok(AdminListener::Unix(listener))
+        },
+        #[cfg(not(unix))]
+        AdminAddr::Unix(path) => Err(format!("--admin {path}: unix sockets only work on unix, use [HOST:]PORT")),
+    }
+}
+
+// What to send back for one command line, or None to hang up.
+pub async fn reply(line: &str, stats: &Stats) -> Option<String> {
+    let mut words = line.split_whitespace();
+
+    let command = match words.next() {
+        Some("sessions") => Some("list"),
+        Some("status") => Some("stats"),
+        command => command,
+    };
+
+    let reply = match (command, words.next(), words.next()) {
+        (None, _, _) => return Some(String::new()),
+        (Some("quit"), None, _) => return None,
+        (Some("list"), None, _) => {
+            let mut reply = String::new();
+
+            for session in stats.snapshot().sessions {
+                let (number, backend) = match &session.dial {
+                    Some(dial) => (dial.number.as_str(), dial.backend.as_str()),
+                    None => ("-", "-"),
+                };
+
+                reply.push_str(&format!(
+                    "{} client={} state={} number={number} backend={backend} bytes_up={} bytes_down={} connected={} caller={}\n",
+                    session.id,
+                    session.client,
+                    session.state,
+                    session.bytes_up,
+                    session.bytes_down,
+                    stats::format_duration(session.duration),
+                    session.caller.map_or("-".to_string(), |caller| caller.to_string()),
+                ));
+            }
+
+            reply + "OK\n"
+        },
+        (Some("kill"), Some(id), None) => match id.parse::<u64>() {
+            Ok(id) if stats.send(id, SessionCommand::Kill).await => {
+                info!("Admin asked to hang up session {id}.");
+
+                "OK\n".to_string()
+            },
+            Ok(id) => format!("ERROR no session {id}\n"),
+            Err(_) => format!("ERROR '{id}' isn't a session id\n"),
+        },
+        (Some("drop"), id, None) => {
+            let online: Vec<u64> = stats.snapshot().sessions.iter().filter(|session| session.state.has_call()).map(|session| session.id).collect();
+
+            let id = match (id, online.as_slice()) {
+                (None, [id]) => Ok(*id),
+                (None, []) => Err("ERROR no session has a call up\n".to_string()),
+                (None, _) => Err(format!("ERROR {} sessions have a call up, say which\n", online.len())),
+                (Some(id), _) => match id.parse::<u64>() {
+                    Ok(id) if online.contains(&id) => Ok(id),
+                    Ok(id) => Err(format!("ERROR session {id} doesn't have a call up\n")),
+                    Err(_) => Err(format!("ERROR '{id}' isn't a session id\n")),
+                },
+            };
+
+            match id {
+                Ok(id) if stats.send(id, SessionCommand::DropCarrier).await => {
+                    info!("Admin asked to drop the carrier on session {id}.");
+
+                    "OK\n".to_string()
+                },
+                Ok(id) => format!("ERROR no session {id}\n"),
+                Err(reply) => reply,
+            }
+        },
+        (Some("log"), Some(filter), None) => match server::set_log_filter(filter) {
+            Ok(()) => {
+                info!("Admin asked to log {filter}.");
+
+                "OK\n".to_string()
+            },
+            Err(e) => format!("ERROR {e}\n"),
+        },
+        (Some("stats"), None, _) => {
+            let snapshot = stats.snapshot();
+
+            let started = snapshot.started_at.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+
+            let at_commands: String = snapshot.at_commands.iter().map(|(name, count)| format!(" {name}={count}")).collect();
+            let unknown_at: String = snapshot.unknown_at.iter().map(|line| format!(" {line:?}")).collect();
+
+            let draining = snapshot.draining.map(|calls| format!(" draining={calls}")).unwrap_or_default();
+
+            format!(
+                "uptime={} started={started} sessions={} active={} bytes_up={} bytes_down={} dials_connected={} dials_busy={} dials_failed={} dials_in_use={} refused_accepts={}{draining}\nat_commands{at_commands}\nat_unknown{unknown_at}\nOK\n",
+                stats::format_duration(snapshot.uptime),
+                snapshot.total_sessions,
+                snapshot.sessions.len(),
+                snapshot.bytes_up,
+                snapshot.bytes_down,
+                snapshot.dials.connected,
+                snapshot.dials.busy,
+                snapshot.dials.failed,
+                snapshot.dials.in_use,
+                snapshot.refused_accepts,
+            )
+        },
+        (Some("drain"), None, _) => {
+            stats.drain();
+            info!("Admin asked to drain.");
+
+            "OK\n".to_string()
+        },
+        (Some(command @ ("pause" | "resume")), Some(id), None) => pause(stats, command, id).await,
+        (Some("inject"), Some(id), Some(to)) if words.clone().next().is_some() => inject(stats, id, to, &words.collect::<String>()).await,
+        (Some(command @ ("list" | "kill" | "drop" | "stats" | "log" | "drain" | "pause" | "resume" | "inject" | "quit")), _, _) => format!("ERROR wrong arguments for {command}\n"),
+        (Some(command), _, _) => format!("ERROR unknown command '{command}', try list, kill ID, drop [ID], stats, log FILTER, drain, pause ID, resume ID, inject ID mame|backend HEX or quit\n"),
+    };
+
+    Some(reply)
+}
+
+// pause ID or resume ID, as `command` says.
+async fn pause(stats: &Stats, command: &str, id: &str) -> String {
+    let id = match id.parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => return format!("ERROR '{id}' isn't a session id\n"),
+    };
+
+    let state = stats.snapshot().sessions.iter().find(|session| session.id == id).map(|session| session.state);
+    let (wanted, session_command) = match command {
+        "pause" => (SessionState::Online, SessionCommand::Pause),
+        _ => (SessionState::Paused, SessionCommand::Resume),
+    };
+
+    match state {
+        None => return format!("ERROR no session {id}\n"),
+        Some(SessionState::Command) => return format!("ERROR session {id} doesn't have a call up\n"),
+        Some(state) if state != wanted => return format!("ERROR session {id} is already {state}\n"),
+        Some(_) => {},
+    }
+
+    match stats.send(id, session_command).await {
+        true => {
+            info!("Admin asked to {command} session {id}.");
+
+            "OK\n".to_string()
+        },
+        false => format!("ERROR no session {id}\n"),
+    }
+}
+
+async fn inject(stats: &Stats, id: &str, to: &str, hex: &str) -> String {
+    if !stats.is_inject_enabled() {
+        return "ERROR injecting bytes takes --enable-inject\n".to_string();
+    }
+
+    let to = match to {
+        "mame" => InjectTo::Mame,
+        "backend" => InjectTo::Backend,
+        _ => return format!("ERROR '{to}' isn't mame or backend\n"),
+    };
+
+    let bytes = match parse_hex(hex) {
+        Ok(bytes) => bytes,
+        Err(e) => return format!("ERROR {e}\n"),
+    };
+
+    let id = match id.parse::<u64>() {
+        Ok(id) => id,
+        Err(_) => return format!("ERROR '{id}' isn't a session id\n"),
+    };
+
+    let is_online = stats.snapshot().sessions.iter().any(|session| session.id == id && session.state.has_call());
+    if !is_online {
+        return format!("ERROR session {id} doesn't have a call up\n");
+    }
+
+    let length = bytes.len();
+    match stats.send(id, SessionCommand::Inject(to, bytes)).await {
+        true => {
+            info!("Admin injected {length} bytes toward {to} on session {id}.");
+
+            "OK\n".to_string()
+        },
+        false => format!("ERROR no session {id}\n"),
+    }
+}
+
+// Two hex digits a byte, which can be split up with spaces: 7e ff 03 c0 21.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = hex.bytes().filter(|digit| !digit.is_ascii_whitespace()).collect();
+
+    if !digits.iter().all(u8::is_ascii_hexdigit) {
+        return Err(format!("'{hex}' isn't hex"));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("'{hex}' is an odd number of hex digits"));
+    }
+
+    Ok(digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect())
+}
+
+async fn handle<S: AsyncRead + AsyncWrite + Unpin>(admin: S, stats: Arc<Stats>) -> std::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(admin);
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        match reply(&line, &stats).await {
+            Some(reply) => writer.write_all(reply.as_bytes()).await?,
+            None => break,
+        }
+    }
+
+    Ok(())
+}
+
+// Each admin connection gets its own task, so a slow one never holds up MAME's listener or another admin.
+pub async fn serve(listener: AdminListener, stats: Arc<Stats>) {
+    let result: std::io::Result<()> = async {
+        match listener {
+            AdminListener::Tcp(listener) => {
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+
+                loop {
+                    let (admin, _) = listener.accept().await?;
+
+                    tokio::spawn(handle(admin, stats.clone()));
+                }
+            },
+            #[cfg(unix)]
+            AdminListener::Unix(listener) => {
+                let listener = tokio::net::UnixListener::from_std(listener)?;
+
+                loop {
+                    let (admin, _) = listener.accept().await?;
+
+                    tokio::spawn(handle(admin, stats.clone()));
+                }
+            },
+        }
+    }.await;
+
+    if let Err(e) = result {
+        warn!("Stopped taking admin commands: error={e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replies_to_commands() {
+        let stats = Stats::new();
+
+        assert_eq!(reply("list", &stats).await.unwrap(), "OK\n");
+        assert_eq!(reply("kill 7", &stats).await.unwrap(), "ERROR no session 7\n");
+        assert_eq!(reply("kill seven", &stats).await.unwrap(), "ERROR 'seven' isn't a session id\n");
+        assert_eq!(reply("kill", &stats).await.unwrap(), "ERROR wrong arguments for kill\n");
+        assert_eq!(reply("dance", &stats).await.unwrap(), "ERROR unknown command 'dance', try list, kill ID, drop [ID], stats, log FILTER, drain, pause ID, resume ID, inject ID mame|backend HEX or quit\n");
+        assert_eq!(reply("", &stats).await.unwrap(), "");
+        assert_eq!(reply("quit", &stats).await, None);
+
+        let (_session, mut commands) = stats.open_session(3, "127.0.0.1:40000");
+
+        assert!(reply("list", &stats).await.unwrap().starts_with("3 client=127.0.0.1:40000 state=command number=- backend=- bytes_up=0 bytes_down=0 "));
+        assert!(reply("stats", &stats).await.unwrap().contains(" sessions=1 active=1 bytes_up=0 bytes_down=0 dials_connected=0 dials_busy=0 dials_failed=0 dials_in_use=0 refused_accepts=0\nat_commands\nat_unknown\nOK\n"));
+
+        stats.record_at("AT&F&C1&D2\r");
+        stats.record_at("AT$X\r");
+        assert!(reply("stats", &stats).await.unwrap().ends_with("\nat_commands $=1 &C=1 &D=1 &F=1 X=1\nat_unknown \"AT$X\"\nOK\n"));
+
+        assert_eq!(reply("kill 3", &stats).await.unwrap(), "OK\n");
+        assert!(matches!(commands.recv().await, Some(SessionCommand::Kill)));
+    }
+
+    #[tokio::test]
+    async fn takes_the_console_names_too() {
+        let stats = Stats::new();
+        let _session = stats.open_session(3, "127.0.0.1:40000");
+
+        assert_eq!(reply("sessions", &stats).await, reply("list", &stats).await);
+        assert!(reply("status", &stats).await.unwrap().contains(" sessions=1 active=1 "));
+        assert_eq!(reply("sessions 3", &stats).await.unwrap(), "ERROR wrong arguments for list\n");
+    }
+
+    #[tokio::test]
+    async fn drops_the_carrier_on_calls_that_are_up() {
+        let stats = Stats::new();
+
+        assert_eq!(reply("drop", &stats).await.unwrap(), "ERROR no session has a call up\n");
+
+        let (first, mut first_commands) = stats.open_session(3, "127.0.0.1:40000");
+        let (second, mut second_commands) = stats.open_session(4, "127.0.0.1:40001");
+
+        assert_eq!(reply("drop 3", &stats).await.unwrap(), "ERROR session 3 doesn't have a call up\n");
+        assert_eq!(reply("drop three", &stats).await.unwrap(), "ERROR 'three' isn't a session id\n");
+        assert_eq!(reply("drop 3 4", &stats).await.unwrap(), "ERROR wrong arguments for drop\n");
+
+        // With just the one call up, it's the one to drop.
+        *first.state.lock().unwrap() = SessionState::Online;
+        assert_eq!(reply("drop", &stats).await.unwrap(), "OK\n");
+        assert!(matches!(first_commands.recv().await, Some(SessionCommand::DropCarrier)));
+
+        *second.state.lock().unwrap() = SessionState::Online;
+        assert_eq!(reply("drop", &stats).await.unwrap(), "ERROR 2 sessions have a call up, say which\n");
+        assert_eq!(reply("drop 4", &stats).await.unwrap(), "OK\n");
+        assert!(matches!(second_commands.recv().await, Some(SessionCommand::DropCarrier)));
+    }
+
+    #[tokio::test]
+    async fn injects_into_calls_that_are_up_once_its_enabled() {
+        let stats = Stats::new();
+        let (session, mut commands) = stats.open_session(3, "127.0.0.1:40000");
+        *session.state.lock().unwrap() = SessionState::Online;
+
+        assert_eq!(reply("inject 3 mame 7e", &stats).await.unwrap(), "ERROR injecting bytes takes --enable-inject\n");
+
+        stats.enable_inject();
+        assert_eq!(reply("inject 3 mame", &stats).await.unwrap(), "ERROR wrong arguments for inject\n");
+        assert_eq!(reply("inject 3 box 7e", &stats).await.unwrap(), "ERROR 'box' isn't mame or backend\n");
+        assert_eq!(reply("inject 3 mame 7g", &stats).await.unwrap(), "ERROR '7g' isn't hex\n");
+        assert_eq!(reply("inject 3 mame 7e7", &stats).await.unwrap(), "ERROR '7e7' is an odd number of hex digits\n");
+        assert_eq!(reply("inject 4 mame 7e", &stats).await.unwrap(), "ERROR session 4 doesn't have a call up\n");
+
+        assert_eq!(reply("inject 3 backend 7e ff 03 C0 21", &stats).await.unwrap(), "OK\n");
+        assert!(matches!(commands.recv().await, Some(SessionCommand::Inject(InjectTo::Backend, bytes)) if bytes == [0x7e, 0xff, 0x03, 0xc0, 0x21]));
+
+        *session.state.lock().unwrap() = SessionState::Command;
+        assert_eq!(reply("inject 3 mame 7e", &stats).await.unwrap(), "ERROR session 3 doesn't have a call up\n");
+    }
+
+    #[tokio::test]
+    async fn pauses_and_resumes_calls_that_are_up() {
+        let stats = Stats::new();
+        let (session, mut commands) = stats.open_session(3, "127.0.0.1:40000");
+
+        assert_eq!(reply("pause 4", &stats).await.unwrap(), "ERROR no session 4\n");
+        assert_eq!(reply("pause three", &stats).await.unwrap(), "ERROR 'three' isn't a session id\n");
+        assert_eq!(reply("pause", &stats).await.unwrap(), "ERROR wrong arguments for pause\n");
+        assert_eq!(reply("pause 3", &stats).await.unwrap(), "ERROR session 3 doesn't have a call up\n");
+
+        session.set_state(SessionState::Online);
+        assert_eq!(reply("resume 3", &stats).await.unwrap(), "ERROR session 3 is already online\n");
+        assert_eq!(reply("pause 3", &stats).await.unwrap(), "OK\n");
+        assert!(matches!(commands.recv().await, Some(SessionCommand::Pause)));
+
+        // The session's own task is what pauses it.
+        assert!(session.pause());
+        assert!(reply("list", &stats).await.unwrap().starts_with("3 client=127.0.0.1:40000 state=paused "));
+        assert_eq!(reply("pause 3", &stats).await.unwrap(), "ERROR session 3 is already paused\n");
+
+        // A paused call's still up, to drop or inject into.
+        stats.enable_inject();
+        assert_eq!(reply("inject 3 mame 7e", &stats).await.unwrap(), "OK\n");
+        assert!(matches!(commands.recv().await, Some(SessionCommand::Inject(InjectTo::Mame, _))));
+
+        assert_eq!(reply("resume 3", &stats).await.unwrap(), "OK\n");
+        assert!(matches!(commands.recv().await, Some(SessionCommand::Resume)));
+
+        // Going back to commands forgets the pause.
+        let mut pause = session.watch_pause();
+        session.set_state(SessionState::Command);
+        assert!(!*pause.borrow_and_update());
+    }
+
+    #[tokio::test]
+    async fn drains_and_says_how_many_calls_are_left() {
+        let stats = Stats::new();
+        let (session, _commands) = stats.open_session(3, "127.0.0.1:40000");
+        session.set_state(SessionState::Online);
+
+        assert!(reply("stats", &stats).await.unwrap().contains(" refused_accepts=0\n"));
+        assert_eq!(reply("drain", &stats).await.unwrap(), "OK\n");
+        assert!(stats.is_draining());
+        assert!(reply("stats", &stats).await.unwrap().contains(" refused_accepts=0 draining=1\n"));
+
+        session.set_state(SessionState::Command);
+        assert!(reply("stats", &stats).await.unwrap().contains(" draining=0\n"));
+        assert_eq!(reply("drain now", &stats).await.unwrap(), "ERROR wrong arguments for drain\n");
+    }
+
+    #[tokio::test]
+    async fn log_filters_need_our_logging() {
+        let stats = Stats::new();
+
+        // The tests never set up logging the way the binary does.
+        assert_eq!(reply("log debug", &stats).await.unwrap(), "ERROR logging isn't ours to change\n");
+        assert_eq!(reply("log", &stats).await.unwrap(), "ERROR wrong arguments for log\n");
+    }
+}
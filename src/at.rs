@@ -0,0 +1,1320 @@
+// The few AT command lines a WebTV box sends, and the numeric (V0) result codes we answer them with.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::TouchPppError;
+
+/// OK, answered to the init string.
+pub const OK: &[u8] = b"OK\x0d\x0a";
+
+/// OK again, but in V0 form. Dial setup strings get this.
+pub const SETUP_OK: &[u8] = b"\x0d\x0a0\x0d\x0a";
+
+/// OK for a dial string.
+pub const DIAL_OK: &[u8] = b"0\x0d\x0a";
+
+/// BUSY.
+pub const BUSY: &[u8] = b"7\x0d\x0a";
+
+/// NO CARRIER, sent when we hang up on a call that's online.
+pub const NO_CARRIER: &[u8] = b"3\x0d\x0a";
+
+/// NO DIALTONE, for a number that goes nowhere.
+pub const NO_DIALTONE: &[u8] = b"6\x0d\x0a";
+
+/// ERROR, for a command that can't be done right now.
+pub const ERROR: &[u8] = b"4\x0d\x0a";
+
+/// DELAYED, for a number that's failed too many times in a row to be dialed again just yet.
+pub const DELAYED: &[u8] = b"24\x0d\x0a";
+
+/// BLACKLISTED, for a number that's never dialed.
+pub const BLACKLISTED: &[u8] = b"32\x0d\x0a";
+
+// 79: CARRIER 33600
+// 67: COMPRESSION: V.42 bis
+// 19: CONECTED 115200
+/// The CONNECT sequence that puts MAME into data mode, unless the config asks for different speeds.
+pub const CONNECT: &[u8] = b"79\x0d\x0a67\x0d\x0a19\x0d\x0a";
+
+/// The carrier speed CONNECT reports unless told otherwise.
+pub const DEFAULT_CARRIER_SPEED: u32 = 33600;
+
+/// The DTE rate CONNECT reports unless told otherwise.
+pub const DEFAULT_CONNECT_SPEED: u32 = 115200;
+
+// The extended result codes a Rockwell-style modem sends with V0, both ways round. Only what a connect sequence
+// can use is here.
+const RESULT_CODES: &[(u8, &str)] = &[
+    (1, "CONNECT"),
+    (5, "CONNECT 1200"),
+    (10, "CONNECT 2400"),
+    (11, "CONNECT 4800"),
+    (12, "CONNECT 9600"),
+    (13, "CONNECT 7200"),
+    (14, "CONNECT 12000"),
+    (15, "CONNECT 14400"),
+    (16, "CONNECT 19200"),
+    (17, "CONNECT 38400"),
+    (18, "CONNECT 57600"),
+    (19, "CONNECT 115200"),
+    (20, "CONNECT 230400"),
+    (59, "CONNECT 16800"),
+    (61, "CONNECT 21600"),
+    (62, "CONNECT 24000"),
+    (63, "CONNECT 26400"),
+    (64, "CONNECT 28800"),
+    (84, "CONNECT 33600"),
+    (91, "CONNECT 31200"),
+    (40, "CARRIER 300"),
+    (46, "CARRIER 1200"),
+    (47, "CARRIER 2400"),
+    (48, "CARRIER 4800"),
+    (49, "CARRIER 7200"),
+    (50, "CARRIER 9600"),
+    (51, "CARRIER 12000"),
+    (52, "CARRIER 14400"),
+    (53, "CARRIER 16800"),
+    (54, "CARRIER 19200"),
+    (55, "CARRIER 21600"),
+    (56, "CARRIER 24000"),
+    (57, "CARRIER 26400"),
+    (58, "CARRIER 28800"),
+    (78, "CARRIER 31200"),
+    (79, "CARRIER 33600"),
+    (150, "CARRIER 32000"),
+    (151, "CARRIER 34000"),
+    (152, "CARRIER 36000"),
+    (153, "CARRIER 38000"),
+    (154, "CARRIER 40000"),
+    (155, "CARRIER 42000"),
+    (156, "CARRIER 44000"),
+    (157, "CARRIER 46000"),
+    (158, "CARRIER 48000"),
+    (159, "CARRIER 50000"),
+    (160, "CARRIER 52000"),
+    (161, "CARRIER 54000"),
+    (162, "CARRIER 56000"),
+    (66, "COMPRESSION: CLASS 5"),
+    (67, "COMPRESSION: V.42 bis"),
+    (69, "COMPRESSION: NONE"),
+    (70, "PROTOCOL: NONE"),
+    (77, "PROTOCOL: LAPM"),
+];
+
+// The basic result codes every Hayes modem has, plus the two a WebTV box knows about for refused dials.
+const BASIC_RESULT_CODES: &[(u8, &str)] = &[
+    (0, "OK"),
+    (1, "CONNECT"),
+    (2, "RING"),
+    (3, "NO CARRIER"),
+    (4, "ERROR"),
+    (6, "NO DIALTONE"),
+    (7, "BUSY"),
+    (8, "NO ANSWER"),
+    (24, "DELAYED"),
+    (32, "BLACKLISTED"),
+];
+
+/// The numeric code for a verbose result like "CARRIER 31200".
+pub fn result_code(text: &str) -> Option<u8> {
+    BASIC_RESULT_CODES.iter().chain(RESULT_CODES).find(|(_, known)| *known == text).map(|(code, _)| *code)
+}
+
+/// The verbose result a numeric code stands for.
+pub fn result_text(code: u8) -> Option<&'static str> {
+    BASIC_RESULT_CODES.iter().chain(RESULT_CODES).find(|(known, _)| *known == code).map(|(_, text)| *text)
+}
+
+/// Which modem MAME (or whatever else is calling) gets to talk to, like --profile.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    /// What a WebTV box expects: canned V0 answers and a dial in two steps, ATDT then ATD.
+    #[default]
+    Webtv,
+    /// A plain Hayes modem, for DOS and Windows dialers and other emulators.
+    Generic,
+}
+
+impl Profile {
+    pub fn modem(&self) -> &'static ModemProfile {
+        match self {
+            Profile::Webtv => &WEBTV,
+            Profile::Generic => &GENERIC,
+        }
+    }
+}
+
+impl std::str::FromStr for Profile {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Profile, String> {
+        match value.to_lowercase().as_str() {
+            "webtv" => Ok(Profile::Webtv),
+            "generic" => Ok(Profile::Generic),
+            _ => Err("use webtv or generic".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Profile::Webtv => write!(f, "webtv"),
+            Profile::Generic => write!(f, "generic"),
+        }
+    }
+}
+
+/// How a modem behaves, as data, so another preset is just another one of these.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ModemProfile {
+    /// A line with E0 in it is the init string and gets a verbose OK whatever else is in it.
+    pub has_init_string: bool,
+    /// ATDT with a number dials it right away. Otherwise the number's only kept until a bare ATD.
+    pub dial_goes_online: bool,
+    /// Results follow E, V, Q and X (and start out as E1 V1 Q0 X4) instead of always being the canned V0 ones.
+    pub follows_result_settings: bool,
+    /// Anything that isn't a Hayes command gets ERROR instead of OK.
+    pub rejects_unknown_commands: bool,
+    /// CONNECT comes with CARRIER, PROTOCOL and COMPRESSION ahead of it.
+    pub reports_intermediates: bool,
+    /// ATI and Sn? get answered from the --modem-profile. Otherwise they're just another command.
+    pub answers_queries: bool,
+}
+
+pub const WEBTV: ModemProfile = ModemProfile {
+    has_init_string: true,
+    dial_goes_online: false,
+    follows_result_settings: false,
+    rejects_unknown_commands: false,
+    reports_intermediates: true,
+    answers_queries: false,
+};
+
+pub const GENERIC: ModemProfile = ModemProfile {
+    has_init_string: false,
+    dial_goes_online: true,
+    follows_result_settings: true,
+    rejects_unknown_commands: true,
+    reports_intermediates: false,
+    answers_queries: true,
+};
+
+/// How results go out under a profile that follows E, V, Q and X.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ResultForm {
+    /// V1: words on lines of their own. V0: numbers, each ending in a \r.
+    pub verbose: bool,
+    /// Q1: no results at all.
+    pub quiet: bool,
+    /// X0 to X4, how much a result tells. X0 is a bare CONNECT, X1 adds the speed, X2 adds NO DIALTONE and X3
+    /// adds BUSY. Both are NO CARRIER otherwise.
+    pub level: u8,
+    /// Whether CARRIER, PROTOCOL and COMPRESSION come ahead of CONNECT.
+    pub intermediates: bool,
+    /// Tacked onto CONNECT when it's words, from the --modem-profile.
+    pub connect_suffix: &'static str,
+}
+
+impl Default for ResultForm {
+    fn default() -> ResultForm {
+        ResultForm { verbose: true, quiet: false, level: 4, intermediates: false, connect_suffix: "" }
+    }
+}
+
+/// Rewrites canned V0 results (like "\r\n0\r\n" or "79\r\n67\r\n19\r\n") the way `form` says to send them.
+pub fn hayes_result(canned: &[u8], form: ResultForm) -> Vec<u8> {
+    let mut result = Vec::new();
+    if form.quiet {
+        return result;
+    }
+
+    for line in canned.split(|byte| *byte == b'\r' || *byte == b'\n').filter(|line| !line.is_empty()) {
+        let line = String::from_utf8_lossy(line);
+        let text = match line.parse::<u8>() {
+            Ok(code) => result_text(code).map(str::to_string).unwrap_or_else(|| line.to_string()),
+            Err(_) => line.to_string(),
+        };
+
+        if !form.intermediates && ["CARRIER", "PROTOCOL", "COMPRESSION"].iter().any(|intermediate| text.starts_with(intermediate)) {
+            continue;
+        }
+
+        let text = match text.as_str() {
+            _ if form.level == 0 && text.starts_with("CONNECT") => "CONNECT".to_string(),
+            "BUSY" if form.level < 3 => "NO CARRIER".to_string(),
+            "NO DIALTONE" if form.level != 2 && form.level < 4 => "NO CARRIER".to_string(),
+            _ => text,
+        };
+
+        match (form.verbose, result_code(&text)) {
+            (false, Some(code)) => result.extend_from_slice(format!("{code}\r").as_bytes()),
+            _ if text.starts_with("CONNECT") => result.extend_from_slice(format!("\r\n{text}{}\r\n", form.connect_suffix).as_bytes()),
+            _ => result.extend_from_slice(format!("\r\n{text}\r\n").as_bytes()),
+        }
+    }
+
+    result
+}
+
+/// The error correction a PROTOCOL line reports.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    None,
+    Lapm,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Protocol, String> {
+        match value.to_lowercase().as_str() {
+            "none" => Ok(Protocol::None),
+            "lapm" => Ok(Protocol::Lapm),
+            _ => Err("use lapm or none".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Protocol::None => write!(f, "none"),
+            Protocol::Lapm => write!(f, "lapm"),
+        }
+    }
+}
+
+/// What MAME's told when a call goes through, line by line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConnectReport {
+    pub carrier_speed: u32,
+    /// Whether the box's +MS and S51 can bring CARRIER down from carrier_speed, like --carrier-speed auto.
+    pub follows_modulation: bool,
+    /// The DTE rate.
+    pub connect_speed: u32,
+    /// The PROTOCOL line, if there is one.
+    pub protocol: Option<Protocol>,
+    /// V.42 bis, or NONE.
+    pub compression: bool,
+    /// False leaves out everything between CARRIER and CONNECT.
+    pub intermediates: bool,
+}
+
+impl Default for ConnectReport {
+    fn default() -> ConnectReport {
+        ConnectReport {
+            carrier_speed: DEFAULT_CARRIER_SPEED,
+            follows_modulation: true,
+            connect_speed: DEFAULT_CONNECT_SPEED,
+            protocol: None,
+            compression: true,
+            intermediates: true,
+        }
+    }
+}
+
+impl ConnectReport {
+    /// The whole sequence: CARRIER, then PROTOCOL (if any) and COMPRESSION unless they're left out, then CONNECT
+    /// with the DTE rate. Fails for a speed no code stands for.
+    pub fn sequence(&self) -> Result<Vec<u8>, String> {
+        let mut lines = vec![format!("CARRIER {}", self.carrier_speed)];
+        if self.intermediates {
+            if let Some(protocol) = self.protocol {
+                lines.push(format!("PROTOCOL: {}", protocol.to_string().to_uppercase()));
+            }
+
+            lines.push(match self.compression {
+                true => "COMPRESSION: V.42 bis".to_string(),
+                false => "COMPRESSION: NONE".to_string(),
+            });
+        }
+        lines.push(format!("CONNECT {}", self.connect_speed));
+
+        let mut sequence = Vec::new();
+        for line in lines {
+            let code = result_code(&line).ok_or_else(|| format!("there's no result code for {line}"))?;
+
+            sequence.extend_from_slice(format!("{code}\x0d\x0a").as_bytes());
+        }
+
+        Ok(sequence)
+    }
+}
+
+/// The informational line --report-throughput sends ahead of NO CARRIER, with how the call did up and down in
+/// bits per second.
+pub fn throughput_line(average: (u64, u64), peak: (u64, u64)) -> Vec<u8> {
+    format!("\x0d\x0aTHROUGHPUT {}/{} BPS, PEAK {}/{} BPS\x0d\x0a", average.0, average.1, peak.0, peak.1).into_bytes()
+}
+
+/// The fastest CARRIER there's a result code for that isn't over `speed`, or 300 if even that's too fast.
+pub fn carrier_at_most(speed: u32) -> u32 {
+    RESULT_CODES
+        .iter()
+        .filter_map(|(_, text)| text.strip_prefix("CARRIER ")?.parse::<u32>().ok())
+        .filter(|carrier| *carrier <= speed)
+        .max()
+        .unwrap_or(300)
+}
+
+/// A carrier +MS can pick, slowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Modulation {
+    V21,
+    V22,
+    V22bis,
+    V32,
+    V32bis,
+    V34,
+    /// V.90, and K56flex and V.92 along with it, since they top out the same.
+    V90,
+}
+
+impl Modulation {
+    // +MS takes a carrier by number (the Rockwell and Conexant ones) or by name.
+    fn from_ms(value: &str) -> Option<Modulation> {
+        match value {
+            "0" | "V21" => Some(Modulation::V21),
+            "1" | "V22" => Some(Modulation::V22),
+            "2" | "V22B" => Some(Modulation::V22bis),
+            "9" | "V32" => Some(Modulation::V32),
+            "10" | "V32B" => Some(Modulation::V32bis),
+            "11" | "V34" => Some(Modulation::V34),
+            "12" | "56" | "V90" | "K56" | "V92" => Some(Modulation::V90),
+            _ => None,
+        }
+    }
+
+    /// The fastest the carrier goes, downstream.
+    pub fn top_speed(&self) -> u32 {
+        match self {
+            Modulation::V21 => 300,
+            Modulation::V22 => 1200,
+            Modulation::V22bis => 2400,
+            Modulation::V32 => 9600,
+            Modulation::V32bis => 14400,
+            Modulation::V34 => 33600,
+            Modulation::V90 => 56000,
+        }
+    }
+}
+
+/// S51=31 is what WebTV init strings use to turn 56k off.
+pub const S51_NO_56K: u32 = 31;
+
+/// What +MS=carrier[,automode[,min_rate[,max_rate]]] and S51 say the modem may connect with. Nothing set means
+/// anything goes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ModulationConfig {
+    pub carrier: Option<Modulation>,
+    /// Whether slower carriers than `carrier` are fine too.
+    pub automode: bool,
+    pub min_rate: Option<u32>,
+    pub max_rate: Option<u32>,
+    /// The last value written to S51.
+    pub s51: Option<u32>,
+}
+
+impl Default for ModulationConfig {
+    fn default() -> ModulationConfig {
+        ModulationConfig {
+            carrier: None,
+            automode: true,
+            min_rate: None,
+            max_rate: None,
+            s51: None,
+        }
+    }
+}
+
+impl ModulationConfig {
+    /// 56k is off if S51 says so, +MS picked a carrier short of V.90, or the max rate is V.34's or lower.
+    pub fn allows_56k(&self) -> bool {
+        self.s51 != Some(S51_NO_56K) && self.carrier.is_none_or(|carrier| carrier == Modulation::V90) && self.max_rate.is_none_or(|rate| rate > Modulation::V34.top_speed())
+    }
+
+    /// The fastest carrier this allows, if it says anything about it at all.
+    pub fn top_speed(&self) -> Option<u32> {
+        let mut limits = vec![self.carrier.map(|carrier| carrier.top_speed()), self.max_rate];
+        if !self.allows_56k() {
+            limits.push(Some(Modulation::V34.top_speed()));
+        }
+
+        limits.into_iter().flatten().min()
+    }
+}
+
+// +MS's set form, without the =. Queries (+MS? and +MS=?) and carriers we don't know aren't settings.
+fn parse_ms(argument: &str) -> Option<ModulationConfig> {
+    let fields = argument.strip_prefix('=')?.split(',').map(str::trim).collect::<Vec<_>>();
+    let rate = |at: usize| fields.get(at).and_then(|rate| rate.parse::<u32>().ok()).filter(|rate| *rate > 0);
+
+    Some(ModulationConfig {
+        carrier: Some(Modulation::from_ms(fields.first()?)?),
+        automode: fields.get(1).is_none_or(|automode| *automode != "0"),
+        min_rate: rate(2),
+        max_rate: rate(3),
+        s51: None,
+    })
+}
+
+/// What a command line sets that changes the connect report: %C (compression), \N (error correction), +MS and
+/// S51 (modulation). None for anything it doesn't mention. `reset` is Z or &F, putting it all back the way it
+/// started first.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Settings {
+    pub reset: bool,
+    /// Which &W slot a Z resets to (Z is Z0) instead of the factory settings. Never set alongside &F.
+    pub recall: Option<u32>,
+    /// &Wn (&W is &W0), saving the settings once the rest of the line's been taken on.
+    pub store: Option<u32>,
+    pub compression: Option<bool>,
+    pub error_correction: Option<bool>,
+    /// A whole +MS, which replaces the last one.
+    pub modulation: Option<ModulationConfig>,
+    pub s51: Option<u32>,
+    /// E, V, Q and X, which only matter to a profile that follows them.
+    pub echo: Option<bool>,
+    pub verbose: Option<bool>,
+    pub quiet: Option<bool>,
+    pub level: Option<u8>,
+}
+
+/// The last S register a modem has.
+pub const MAX_S_REGISTER: u32 = 255;
+
+/// Every Sn=v in a command line, in order. Ones past [`MAX_S_REGISTER`] are left out, so a modem never has to
+/// hold on to more registers than there are.
+pub fn s_register_writes(at_string: &str) -> Vec<(u32, u32)> {
+    tokenize(at_string)
+        .iter()
+        .filter(|token| token.name == "S")
+        .filter_map(|token| {
+            let (register, value) = token.argument.split_once('=')?;
+
+            Some((register.parse().ok().filter(|register| *register <= MAX_S_REGISTER)?, value.parse().ok()?))
+        })
+        .collect()
+}
+
+/// The register a line that's nothing but Sn? asks about.
+pub fn s_register_query(at_string: &str) -> Option<u32> {
+    match &tokenize(at_string)[..] {
+        [token] if token.name == "S" => token.argument.strip_suffix('?')?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Picks %Cn and \Nn out of a command line. %C0 turns compression off and \N0 or \N1 (normal and direct mode)
+/// turn error correction off. Any other digit turns them back on. The last one given wins.
+pub fn settings(at_string: &str) -> Settings {
+    let tokens = tokenize(at_string);
+    let mode_of = |name: &str| tokens.iter().filter(|token| token.name == name).filter_map(|token| token.argument.parse::<u32>().ok()).next_back();
+    let slot_of = |name: &str| tokens.iter().filter(|token| token.name == name).map(|token| token.argument.parse::<u32>().unwrap_or(0)).next_back();
+    let is_factory = tokens.iter().any(|token| token.name == "&F");
+
+    Settings {
+        reset: is_factory || tokens.iter().any(|token| token.name == "Z"),
+        recall: slot_of("Z").filter(|_| !is_factory),
+        store: slot_of("&W"),
+        compression: mode_of("%C").map(|mode| mode != 0),
+        error_correction: mode_of("\\N").map(|mode| mode > 1),
+        modulation: tokens.iter().filter(|token| token.name == "+MS").filter_map(|token| parse_ms(&token.argument)).next_back(),
+        s51: tokens
+            .iter()
+            .filter(|token| token.name == "S")
+            .filter_map(|token| token.argument.strip_prefix("51=")?.parse::<u32>().ok())
+            .next_back(),
+        echo: mode_of("E").map(|mode| mode != 0),
+        verbose: mode_of("V").map(|mode| mode != 0),
+        quiet: mode_of("Q").map(|mode| mode != 0),
+        level: mode_of("X").map(|mode| mode.min(4) as u8),
+    }
+}
+
+/// What's calling, going by its init string.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Caller {
+    /// A WebTV box, which wants its results as numbers (E0 and V0).
+    WebtvOs,
+    /// Windows CE's Unimodem, which asks for words (V1) and can't make anything of WebTV's numbers.
+    WindowsCe,
+    /// Anything else with an init string, like Windows and DOS dialers.
+    Generic,
+}
+
+impl std::fmt::Display for Caller {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Caller::WebtvOs => write!(f, "webtv-os"),
+            Caller::WindowsCe => write!(f, "windows-ce"),
+            Caller::Generic => write!(f, "generic"),
+        }
+    }
+}
+
+// What each caller's init string has in it, command by command (a name and its argument, or the start of it) in
+// the order they come, with anything else allowed in between. The first one that fits wins. CE's Unimodem always
+// builds its string the same way: &F, E0 and V1, then S7 (how long to wait for carrier) and S0, with whatever the
+// modem's .inf adds wherever it likes.
+const CALLERS: &[(Caller, &[&str])] = &[
+    (Caller::WindowsCe, &["&F", "E0", "V1", "S7=", "S0="]),
+    (Caller::WebtvOs, &["E0", "V0"]),
+    (Caller::Generic, &["E0"]),
+];
+
+/// Who a command line says is calling, if it's an init string. Anything that doesn't turn echo off isn't one.
+pub fn caller(at_string: &str) -> Option<Caller> {
+    let commands: Vec<String> = tokenize(at_string).into_iter().map(|token| token.name + &token.argument).collect();
+
+    CALLERS.iter().find_map(|(caller, signature)| {
+        let mut commands = commands.iter();
+
+        signature.iter().all(|wanted| commands.any(|command| command.starts_with(wanted))).then_some(*caller)
+    })
+}
+
+/// What a byte from MAME means to a command line.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ByteClass {
+    /// Printable ASCII, 0x20 through 0x7e, upper or lower case.
+    Command,
+    /// The \r that ends a line.
+    End,
+    /// Backspace, taking back the byte before it.
+    Erase,
+    /// Everything else: control characters, \n, DEL and anything past ASCII.
+    Noise,
+}
+
+/// Sorts out one byte of a command line. The only place that decides what counts as part of a command.
+pub fn classify(byte: u8) -> ByteClass {
+    match byte {
+        b'\r' => ByteClass::End,
+        0x08 => ByteClass::Erase,
+        0x20..=0x7e => ByteClass::Command,
+        _ => ByteClass::Noise,
+    }
+}
+
+/// A line from [`CommandLine`], ready to answer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    /// The whole command line, up to and including the \r.
+    Command(String),
+    /// More than the max length came before the \r, so there's nothing left of it to parse.
+    TooLong,
+}
+
+/// The most a command line buffer grabs up front, however long a line is allowed to get.
+const MAX_PREALLOCATED: usize = 4096;
+
+/// What MAME has typed so far in command mode, taken a byte at a time so a read can hold any number of lines (or
+/// none). Bytes are kept or dropped by [`classify`], less any spaces ahead of the line, and a \r ends the line
+/// wherever it lands.
+pub struct CommandLine {
+    line: Vec<u8>,
+    max_length: usize,
+    is_too_long: bool,
+}
+
+impl CommandLine {
+    /// A line can be up to `max_length` bytes, counting the \r.
+    pub fn new(max_length: usize) -> CommandLine {
+        CommandLine {
+            // Sized up front and reused, so a busy line doesn't allocate per byte or per command.
+            line: Vec::with_capacity(max_length.min(MAX_PREALLOCATED)),
+            max_length,
+            is_too_long: false,
+        }
+    }
+
+    /// Takes one byte, giving back the line if that's the one that ends it.
+    pub fn push(&mut self, byte: u8) -> Option<Line> {
+        match classify(byte) {
+            ByteClass::End if self.is_too_long => {
+                self.is_too_long = false;
+
+                Some(Line::TooLong)
+            },
+            ByteClass::End => {
+                self.line.push(b'\r');
+                // Only printable ASCII gets this far, so this never has to replace anything.
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+
+                Some(Line::Command(line))
+            },
+            ByteClass::Erase => {
+                self.line.pop();
+
+                None
+            },
+            // Left over from after the last \r, most likely.
+            ByteClass::Command if byte == b' ' && self.line.is_empty() => None,
+            ByteClass::Command => {
+                // Thrown away rather than kept, so something that never sends a \r can't grow it forever.
+                if self.is_too_long || self.line.len() + 1 >= self.max_length {
+                    self.line.clear();
+                    self.is_too_long = true;
+                } else {
+                    self.line.push(byte);
+                }
+
+                None
+            },
+            ByteClass::Noise => None,
+        }
+    }
+
+    /// Whether the line so far has already gone past the max length.
+    pub fn is_too_long(&self) -> bool {
+        self.is_too_long
+    }
+
+    /// Forgets the line so far, for when data mode takes over.
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.is_too_long = false;
+    }
+}
+
+/// What a whole AT command line (up to and including the \r) asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// The init string, which always turns echo off.
+    Init,
+    /// A dial setup string, without a phone number or echo value.
+    DialSetup,
+    /// A dial string, with the number that was dialed.
+    Dial(String),
+    /// ATD (or ATDT) with nothing to dial, asking to go into data mode.
+    DataMode,
+    /// ATDL, dialing the last number again.
+    Redial,
+    /// ATO, asking to go back online to a call that was escaped from.
+    Resume,
+    /// ATH, hanging up.
+    HangUp,
+}
+
+/// One command out of an AT command line: its name (a letter, with any &, %, \\ or + in front of it) and what
+/// came after it up to the next one. D takes the rest of the line as its dial string.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Token {
+    pub name: String,
+    pub argument: String,
+}
+
+fn is_prefix(c: char) -> bool {
+    matches!(c, '&' | '%' | '\\' | '+')
+}
+
+/// Splits a command line into its commands, upper cased and without spaces. The AT in front is optional, so a
+/// bare line still comes apart the same way.
+pub fn tokenize(at_string: &str) -> Vec<Token> {
+    let line = at_string.trim_end_matches(['\x0d', '\x0a']).to_ascii_uppercase();
+    let line = line.strip_prefix("AT").unwrap_or(&line);
+
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().filter(|c| *c != ' ').peekable();
+
+    while let Some(c) = chars.next() {
+        let mut name = c.to_string();
+        if is_prefix(c) {
+            // Extended (+) commands are whole words, the rest are one letter.
+            match c {
+                '+' => while let Some(letter) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                    name.push(letter);
+                },
+                _ => name.extend(chars.next_if(|c| c.is_ascii_alphabetic())),
+            }
+        }
+
+        let argument = match name.as_str() {
+            "D" => chars.by_ref().collect(),
+            // Extended commands go on until a ;, which is dropped.
+            _ if c == '+' => chars.by_ref().take_while(|c| *c != ';').collect(),
+            _ => std::iter::from_fn(|| chars.next_if(|c| !c.is_ascii_alphabetic() && !is_prefix(*c))).collect(),
+        };
+
+        tokens.push(Token { name, argument });
+    }
+
+    tokens
+}
+
+// What a dial string can have in it: digits, the keypad, tone and pulse, and pauses and waits.
+fn is_dial_character(c: char) -> bool {
+    c.is_ascii_digit() || matches!(c, '*' | '#' | 'A'..='D' | 'T' | 'P' | 'W' | ',' | '@' | '!' | ';')
+}
+
+// The one letter commands a Hayes modem takes. The prefixed ones (&, %, \ and +) are too many to list.
+const HAYES_COMMANDS: &str = "ABCDEFHILMNOPQSTVWXYZ";
+
+/// Whether a command line looks like something a Hayes modem would take: AT first, then commands it knows with
+/// numbers (or ?) after them, and S registers read with Sn? or set with Sn=v.
+pub fn is_well_formed(at_string: &str) -> bool {
+    let line = at_string.trim_matches(['\x0d', '\x0a', ' ']);
+    if !line.get(..2).is_some_and(|at| at.eq_ignore_ascii_case("AT")) {
+        return false;
+    }
+
+    let is_number = |argument: &str| argument.chars().all(|c| c.is_ascii_digit());
+
+    tokenize(at_string).iter().all(|token| match token.name.as_str() {
+        // Dial strings get checked by parse.
+        "D" => true,
+        "S" => match token.argument.split_once(['=', '?']) {
+            Some((register, value)) => is_number(register) && register.parse().is_ok_and(|register: u32| register <= MAX_S_REGISTER) && is_number(value),
+            None => false,
+        },
+        name if name.len() == 1 => HAYES_COMMANDS.contains(name) && (token.argument == "?" || is_number(&token.argument)),
+        _ => true,
+    })
+}
+
+/// Works out what a command line asks for. The checks go in the order the box's strings need: an init string
+/// can have anything in it, so E0 wins over everything else.
+pub fn parse(at_string: &str) -> Result<Command, TouchPppError> {
+    parse_as(at_string, &WEBTV)
+}
+
+/// [`parse`] for a modem that might not have an init string.
+pub fn parse_as(at_string: &str, profile: &ModemProfile) -> Result<Command, TouchPppError> {
+    let tokens = tokenize(at_string);
+    let is_only = |name: &str| matches!(&tokens[..], [token] if token.name == name && matches!(token.argument.as_str(), "" | "0"));
+
+    let command = if profile.has_init_string && tokens.iter().any(|token| token.name == "E" && token.argument == "0") {
+        Command::Init
+    } else if is_only("O") {
+        Command::Resume
+    } else if is_only("H") {
+        Command::HangUp
+    } else if let Some(dial) = tokens.iter().find(|token| token.name == "D") {
+        if dial.argument.trim_end_matches(';') == "L" {
+            return Ok(Command::Redial);
+        }
+
+        if !dial.argument.chars().all(is_dial_character) {
+            return Err(TouchPppError::AtParse(at_string.to_string()));
+        }
+
+        // T or P only says how to dial and a ; on the end only says to stay in command mode after, so D with
+        // nothing else goes straight to data mode.
+        let number = dial.argument.strip_prefix(['T', 'P']).unwrap_or(&dial.argument).trim_end_matches(';');
+        match number {
+            "" => Command::DataMode,
+            number => Command::Dial(number.to_string()),
+        }
+    } else {
+        Command::DialSetup
+    };
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_the_box_sends() {
+        assert_eq!(parse("ATE0Q0V0&C1&D2S0=0\r").unwrap(), Command::Init);
+        assert_eq!(parse("ATS7=60L3\r").unwrap(), Command::DialSetup);
+        assert_eq!(parse("ATZ\r").unwrap(), Command::DialSetup);
+        assert_eq!(parse("ATDT18006138199\r").unwrap(), Command::Dial("18006138199".to_string()));
+        assert_eq!(parse("ATD\r").unwrap(), Command::DataMode);
+        assert_eq!(parse("ATDTD\r").unwrap(), Command::Dial("D".to_string()));
+        assert_eq!(parse("ATO\r").unwrap(), Command::Resume);
+        assert_eq!(parse("ATH0\r").unwrap(), Command::HangUp);
+    }
+
+    #[test]
+    fn splits_command_lines_into_commands() {
+        let tokens = |at_string| tokenize(at_string).into_iter().map(|token| format!("{}:{}", token.name, token.argument)).collect::<Vec<_>>();
+
+        assert_eq!(tokens("ATE0Q0V0&C1&D2S0=0\r"), ["E:0", "Q:0", "V:0", "&C:1", "&D:2", "S:0=0"]);
+        assert_eq!(tokens("ATS7=60L3\r"), ["S:7=60", "L:3"]);
+        assert_eq!(tokens("at%c0\\n3+MS=V34;E0\r"), ["%C:0", "\\N:3", "+MS:=V34", "E:0"]);
+        assert_eq!(tokens("ATL3DT 555 1212\r"), ["L:3", "D:T5551212"]);
+        assert_eq!(tokens("AT\r"), Vec::<String>::new());
+
+        // An I and a 3 next to each other are only I3 when that's the whole command.
+        assert_eq!(tokens("ATI3\r"), ["I:3"]);
+        assert_eq!(tokens("ATI33\r"), ["I:33"]);
+        assert_eq!(tokens("ATS13=5\r"), ["S:13=5"]);
+        assert_eq!(parse("ATI33\r").unwrap(), Command::DialSetup);
+        assert_eq!(parse("ATS13=5\r").unwrap(), Command::DialSetup);
+    }
+
+    #[test]
+    fn splits_every_form_a_command_comes_in() {
+        let tokens = |at_string| tokenize(at_string).into_iter().map(|token| format!("{}:{}", token.name, token.argument)).collect::<Vec<_>>();
+
+        // Queries, and commands with nothing after them.
+        assert_eq!(tokens("ATI\r"), ["I:"]);
+        assert_eq!(tokens("ATI?\r"), ["I:?"]);
+        assert_eq!(tokens("ATS7?\r"), ["S:7?"]);
+        assert_eq!(tokens("AT+MS?\r"), ["+MS:?"]);
+        assert_eq!(tokens("at&v&w0\r"), ["&V:", "&W:0"]);
+
+        // Spaces can go anywhere, even in the middle of an argument.
+        assert_eq!(tokens("ATI 3\r"), ["I:3"]);
+        assert_eq!(tokens("AT S 7 = 45 E 0\r"), ["S:7=45", "E:0"]);
+
+        // A number only belongs to the command in front of it.
+        assert_eq!(tokens("ATI3E0\r"), ["I:3", "E:0"]);
+        assert_eq!(tokens("ATS13=5I3\r"), ["S:13=5", "I:3"]);
+
+        // Extended commands run to their ;, and can be followed by more of either kind.
+        assert_eq!(tokens("AT+MS=V90;+ES=3,0,2;I3\r"), ["+MS:=V90", "+ES:=3,0,2", "I:3"]);
+        assert_eq!(tokens("AT+MS=V34\r"), ["+MS:=V34"]);
+
+        // D has the rest of the line, ; and all.
+        assert_eq!(tokens("ATDT5551212;\r"), ["D:T5551212;"]);
+        assert_eq!(tokens("ATDTI3E0\r"), ["D:TI3E0"]);
+
+        // Without the AT, or with a \n after the \r.
+        assert_eq!(tokens("E0V0\r\n"), ["E:0", "V:0"]);
+
+        assert_eq!(parse("ATI3\r").unwrap(), Command::DialSetup);
+        assert_eq!(parse("ATI3E0\r").unwrap(), Command::Init);
+        assert_eq!(parse("AT+MS=V90;+ES=3,0,2;I3\r").unwrap(), Command::DialSetup);
+    }
+
+    #[test]
+    fn dials_are_a_d_command_wherever_it_is() {
+        // These all have a D command in them, even if not right after the AT.
+        assert_eq!(parse("ATD5551212\r").unwrap(), Command::Dial("5551212".to_string()));
+        assert_eq!(parse("ATD 5551212\r").unwrap(), Command::Dial("5551212".to_string()));
+        assert_eq!(parse("ATDP5551212\r").unwrap(), Command::Dial("5551212".to_string()));
+        assert_eq!(parse("ATTD5\r").unwrap(), Command::Dial("5".to_string()));
+        assert_eq!(parse("ATS7=60DT18006138199\r").unwrap(), Command::Dial("18006138199".to_string()));
+        assert_eq!(parse("atdt18006138199\r").unwrap(), Command::Dial("18006138199".to_string()));
+        assert_eq!(parse("ATDT\r").unwrap(), Command::DataMode);
+        assert_eq!(parse("ATX3D\r").unwrap(), Command::DataMode);
+
+        // A bare D, however it's dressed up, goes online and anything with a number dials it.
+        assert_eq!(parse("ATD \r").unwrap(), Command::DataMode);
+        assert_eq!(parse("ATD;\r").unwrap(), Command::DataMode);
+        assert_eq!(parse("ATDT;\r").unwrap(), Command::DataMode);
+        assert_eq!(parse("ATDT123;\r").unwrap(), Command::Dial("123".to_string()));
+        assert_eq!(parse("ATDL\r").unwrap(), Command::Redial);
+        assert_eq!(parse("atdl;\r").unwrap(), Command::Redial);
+        assert!(parse("ATDTL\r").is_err());
+
+        // And these don't, even with a D and a T next to each other.
+        assert_eq!(parse("AT&D2T\r").unwrap(), Command::DialSetup);
+        assert_eq!(parse("AT&DT\r").unwrap(), Command::DialSetup);
+        assert_eq!(parse("ATX4&D\r").unwrap(), Command::DialSetup);
+
+        // A dial string has to be something a modem could dial.
+        assert!(matches!(parse("ATDX\r"), Err(TouchPppError::AtParse(_))));
+    }
+
+    #[test]
+    fn result_codes_go_both_ways() {
+        for (code, text) in RESULT_CODES {
+            assert_eq!(result_code(text), Some(*code), "{text}");
+            assert_eq!(result_text(*code), Some(*text), "{code}");
+        }
+
+        assert_eq!(result_code("CARRIER 31200"), Some(78));
+        assert_eq!(result_text(19), Some("CONNECT 115200"));
+        assert_eq!(result_code("CONNECT 1234"), None);
+    }
+
+    #[test]
+    fn builds_connect_sequences() {
+        let report = |carrier_speed, connect_speed, intermediates| ConnectReport { carrier_speed, connect_speed, intermediates, ..Default::default() };
+
+        assert_eq!(ConnectReport::default().sequence().unwrap(), CONNECT);
+        assert_eq!(report(31200, 57600, true).sequence().unwrap(), b"78\r\n67\r\n18\r\n");
+        assert_eq!(report(31200, 115200, false).sequence().unwrap(), b"78\r\n19\r\n");
+        assert_eq!(report(31250, 115200, true).sequence().unwrap_err(), "there's no result code for CARRIER 31250");
+
+        let lapm = ConnectReport { protocol: Some(Protocol::Lapm), ..Default::default() };
+        assert_eq!(lapm.sequence().unwrap(), b"79\r\n77\r\n67\r\n19\r\n");
+
+        let nothing = ConnectReport { protocol: Some(Protocol::None), compression: false, ..Default::default() };
+        assert_eq!(nothing.sequence().unwrap(), b"79\r\n70\r\n69\r\n19\r\n");
+    }
+
+    #[test]
+    fn classifies_every_byte() {
+        for byte in [b'A', b'T', b'a', b't', b'z', b'Z', b'~', b' ', b'&', b'%', b'\\', b'='] {
+            assert_eq!(classify(byte), ByteClass::Command, "{byte:#04x}");
+        }
+        for byte in [0x00, 0x07, b'\n', 0x1b, 0x1f, 0x7f, 0x80, 0xff] {
+            assert_eq!(classify(byte), ByteClass::Noise, "{byte:#04x}");
+        }
+        assert_eq!(classify(b'\r'), ByteClass::End);
+        assert_eq!(classify(0x08), ByteClass::Erase);
+    }
+
+    fn lines(command_line: &mut CommandLine, bytes: &[u8]) -> Vec<Line> {
+        bytes.iter().filter_map(|&byte| command_line.push(byte)).collect()
+    }
+
+    #[test]
+    fn command_lines_end_at_any_cr() {
+        let mut command_line = CommandLine::new(255);
+
+        assert_eq!(lines(&mut command_line, b"\x00ATZ\r"), [Line::Command("ATZ\r".to_string())]);
+        assert_eq!(lines(&mut command_line, b"AT\x00DT123\r"), [Line::Command("ATDT123\r".to_string())]);
+        assert_eq!(lines(&mut command_line, b"ATZ\rAT\r\n"), [Line::Command("ATZ\r".to_string()), Line::Command("AT\r".to_string())]);
+
+        // A CR that's the only byte in a read ends what came before it.
+        assert!(lines(&mut command_line, b"ATI").is_empty());
+        assert_eq!(lines(&mut command_line, b"3"), []);
+        assert_eq!(lines(&mut command_line, b"\r"), [Line::Command("ATI3\r".to_string())]);
+
+        // A stray space after the CR isn't the start of the next line.
+        assert_eq!(lines(&mut command_line, b"ATZ\r "), [Line::Command("ATZ\r".to_string())]);
+        assert_eq!(lines(&mut command_line, b" ATH\r"), [Line::Command("ATH\r".to_string())]);
+        assert_eq!(lines(&mut command_line, b"ATS7=60 L3\r"), [Line::Command("ATS7=60 L3\r".to_string())]);
+
+        // Lower case is kept as is, and backspace takes back what it follows.
+        assert_eq!(lines(&mut command_line, b"atz\r"), [Line::Command("atz\r".to_string())]);
+        assert_eq!(lines(&mut command_line, b"ATX\x08Z\r"), [Line::Command("ATZ\r".to_string())]);
+        assert_eq!(lines(&mut command_line, b"\x08\x08AT\r"), [Line::Command("AT\r".to_string())]);
+
+        // The rest waits for its \r.
+        assert_eq!(lines(&mut command_line, b"ATZ\rjunk\xff"), [Line::Command("ATZ\r".to_string())]);
+        assert_eq!(lines(&mut command_line, b"\r"), [Line::Command("junk\r".to_string())]);
+    }
+
+    #[test]
+    fn command_lines_past_the_max_length_are_thrown_away() {
+        let mut command_line = CommandLine::new(5);
+
+        assert_eq!(lines(&mut command_line, b"ATE0\r"), [Line::Command("ATE0\r".to_string())]);
+        assert!(lines(&mut command_line, b"ATE0Q").is_empty());
+        assert!(command_line.is_too_long());
+        assert_eq!(lines(&mut command_line, b"0V0\rATZ\r"), [Line::TooLong, Line::Command("ATZ\r".to_string())]);
+
+        assert!(lines(&mut command_line, b"ATDT1800").is_empty());
+        command_line.clear();
+        assert_eq!(lines(&mut command_line, b"AT\r"), [Line::Command("AT\r".to_string())]);
+    }
+
+    #[test]
+    fn command_lines_reuse_one_buffer() {
+        let mut command_line = CommandLine::new(255);
+        let capacity = command_line.line.capacity();
+        assert!(capacity >= 255);
+
+        for _ in 0..10_000 {
+            lines(&mut command_line, b"AT&FE0V0+MS=V34,1,2400,28800;S51=31\r");
+            lines(&mut command_line, &[b'X'; 300]);
+        }
+        assert_eq!(command_line.line.capacity(), capacity);
+    }
+
+    #[test]
+    fn command_lines_match_the_bytes_split_at_each_cr() {
+        // A small LCG stands in for random input so every run sees the same bytes.
+        let mut seed = 0x2323_u32;
+        let bytes: Vec<u8> = (0..100_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                match seed >> 28 {
+                    0 => b'\r',
+                    1 => 0x08,
+                    2 => b' ',
+                    _ => (seed >> 16) as u8,
+                }
+            })
+            .collect();
+
+        let mut expected = Vec::new();
+        let mut line = String::new();
+        for &byte in &bytes {
+            match byte {
+                b'\r' => {
+                    line.push('\r');
+                    expected.push(Line::Command(std::mem::take(&mut line)));
+                },
+                0x08 => {
+                    line.pop();
+                },
+                b' ' if line.is_empty() => {},
+                0x20..=0x7e => line.push(byte as char),
+                _ => {},
+            }
+        }
+
+        let mut command_line = CommandLine::new(usize::MAX);
+        assert_eq!(lines(&mut command_line, &bytes), expected);
+    }
+
+    #[test]
+    fn reads_modulation_out_of_real_init_strings() {
+        let ms = |carrier, automode, min_rate, max_rate| Some(ModulationConfig { carrier: Some(carrier), automode, min_rate, max_rate, s51: None });
+
+        for (at_string, modulation, s51) in [
+            // What WebTV boxes send to stay off 56k.
+            ("ATS51=31\r", None, Some(31)),
+            ("AT+MS=11,1\r", ms(Modulation::V34, true, None, None), None),
+            // Newer Conexant strings.
+            ("AT+MS=V90,1\r", ms(Modulation::V90, true, None, None), None),
+            ("AT+MS=V34,1,300,33600\r", ms(Modulation::V34, true, Some(300), Some(33600)), None),
+            ("AT+MS=11,1,300,33600\r", ms(Modulation::V34, true, Some(300), Some(33600)), None),
+            ("AT+MS=12,1,300,56000\r", ms(Modulation::V90, true, Some(300), Some(56000)), None),
+            ("at+ms=v32b,0\r", ms(Modulation::V32bis, false, None, None), None),
+            ("AT&FE0V0+MS=V34,1,2400,28800;S51=0\r", ms(Modulation::V34, true, Some(2400), Some(28800)), Some(0)),
+            ("AT+MS=56,1,0,0\r", ms(Modulation::V90, true, None, None), None),
+            ("ATS7=60S51=31S0=0\r", None, Some(31)),
+            // Queries and carriers we've never heard of set nothing.
+            ("AT+MS?\r", None, None),
+            ("AT+MS=?\r", None, None),
+            ("AT+MS=B212\r", None, None),
+            ("ATS51?\r", None, None),
+        ] {
+            let settings = settings(at_string);
+
+            assert_eq!(settings.modulation, modulation, "{at_string:?}");
+            assert_eq!(settings.s51, s51, "{at_string:?}");
+        }
+    }
+
+    #[test]
+    fn decides_on_56k_from_the_modulation() {
+        let config = |at_string| {
+            let settings = settings(at_string);
+            ModulationConfig { s51: settings.s51, ..settings.modulation.unwrap_or_default() }
+        };
+
+        assert!(ModulationConfig::default().allows_56k());
+        assert_eq!(ModulationConfig::default().top_speed(), None);
+
+        for at_string in ["ATS51=31\r", "AT+MS=11,1\r", "AT+MS=V90,1,300,33600\r"] {
+            assert!(!config(at_string).allows_56k(), "{at_string:?}");
+            assert_eq!(config(at_string).top_speed(), Some(33600), "{at_string:?}");
+        }
+
+        assert!(config("AT+MS=V90,1\r").allows_56k());
+        assert_eq!(config("AT+MS=V90,1\r").top_speed(), Some(56000));
+        assert_eq!(config("AT+MS=V34,1,300,28800\r").top_speed(), Some(28800));
+        assert_eq!(config("AT+MS=V32B\r").top_speed(), Some(14400));
+    }
+
+    #[test]
+    fn finds_the_carrier_at_or_under_a_speed() {
+        assert_eq!(carrier_at_most(33600), 33600);
+        assert_eq!(carrier_at_most(30000), 28800);
+        assert_eq!(carrier_at_most(14400), 14400);
+        assert_eq!(carrier_at_most(100), 300);
+    }
+
+    #[test]
+    fn picks_out_compression_and_error_correction() {
+        assert_eq!(settings("ATE0Q0V0&C1&D2S0=0\r"), Settings { echo: Some(false), quiet: Some(false), verbose: Some(false), ..Default::default() });
+        assert_eq!(settings("AT%C0\\N0\r"), Settings { compression: Some(false), error_correction: Some(false), ..Default::default() });
+        assert_eq!(settings("ATE1V1X3\r"), Settings { echo: Some(true), verbose: Some(true), level: Some(3), ..Default::default() });
+        assert_eq!(settings("AT%C3\\N1\r"), Settings { compression: Some(true), error_correction: Some(false), ..Default::default() });
+        assert_eq!(settings("AT\\N3%C0%C1\r"), Settings { compression: Some(true), error_correction: Some(true), ..Default::default() });
+        assert_eq!(settings("at%c0\r").compression, Some(false));
+        // &C isn't %C.
+        assert_eq!(settings("AT&C0%C\r").compression, None);
+
+        assert!(settings("ATZ\r").reset);
+        assert!(settings("at&f\r").reset);
+        assert_eq!(settings("AT&F%C0\r"), Settings { reset: true, compression: Some(false), error_correction: None, ..Default::default() });
+        assert!(!settings("ATE0Q0V0&C1&D2S0=0\r").reset);
+
+        // Z picks a stored profile, &F never does.
+        assert_eq!((settings("ATZ\r").recall, settings("ATZ1\r").recall, settings("ATZ&F\r").recall), (Some(0), Some(1), None));
+        assert_eq!((settings("ATE0S7=60&W\r").store, settings("AT&W1\r").store, settings("ATE0\r").store), (Some(0), Some(1), None));
+    }
+
+    #[test]
+    fn tells_hayes_commands_from_junk() {
+        for good in ["AT\r", "ATZ\r", "at&f e0 v1 &d2 &c1 s0=0\r", "ATS7=60L3M1\r", "ATS0?\r", "ATE?\r", "ATI3\r", "ATDT5551212\r", "AT+MS=V34\r", "ATX4W2\r"] {
+            assert!(is_well_formed(good), "{good:?} should be fine");
+        }
+        for bad in ["HELLO\r", "A\r", "ATG1\r", "ATE=1\r", "ATS7\r", "ATS=5\r", "ATS256=1\r", "ATKJ\r"] {
+            assert!(!is_well_formed(bad), "{bad:?} should be an error");
+        }
+    }
+
+    #[test]
+    fn results_follow_v_q_and_x() {
+        let form = ResultForm::default();
+
+        assert_eq!(hayes_result(SETUP_OK, form), b"\r\nOK\r\n");
+        assert_eq!(hayes_result(OK, form), b"\r\nOK\r\n");
+        assert_eq!(hayes_result(CONNECT, form), b"\r\nCONNECT 115200\r\n");
+        assert_eq!(hayes_result(CONNECT, ResultForm { intermediates: true, ..form }), b"\r\nCARRIER 33600\r\n\r\nCOMPRESSION: V.42 bis\r\n\r\nCONNECT 115200\r\n");
+        assert_eq!(hayes_result(BUSY, form), b"\r\nBUSY\r\n");
+
+        assert_eq!(hayes_result(OK, ResultForm { verbose: false, ..form }), b"0\r");
+        assert_eq!(hayes_result(CONNECT, ResultForm { verbose: false, ..form }), b"19\r");
+        assert_eq!(hayes_result(ERROR, ResultForm { quiet: true, ..form }), b"");
+
+        assert_eq!(hayes_result(CONNECT, ResultForm { level: 0, ..form }), b"\r\nCONNECT\r\n");
+        assert_eq!(hayes_result(BUSY, ResultForm { level: 1, ..form }), b"\r\nNO CARRIER\r\n");
+        assert_eq!(hayes_result(NO_DIALTONE, ResultForm { level: 2, ..form }), b"\r\nNO DIALTONE\r\n");
+        assert_eq!(hayes_result(NO_DIALTONE, ResultForm { level: 3, ..form }), b"\r\nNO CARRIER\r\n");
+        assert_eq!(hayes_result(BUSY, ResultForm { level: 3, ..form }), b"\r\nBUSY\r\n");
+
+        // A Courier says how the call went on the end of CONNECT, but only in words.
+        let courier = ResultForm { connect_suffix: "/ARQ", ..form };
+        assert_eq!(hayes_result(CONNECT, courier), b"\r\nCONNECT 115200/ARQ\r\n");
+        assert_eq!(hayes_result(CONNECT, ResultForm { verbose: false, ..courier }), b"19\r");
+    }
+
+    #[test]
+    fn picks_out_s_registers() {
+        assert_eq!(s_register_writes("ATS7=60S30=0L0M1S51=31\r"), [(7, 60), (30, 0), (51, 31)]);
+        assert_eq!(s_register_writes("ATS0?\r"), []);
+        // There's no S256, however many digits it's written with.
+        assert_eq!(s_register_writes("ATS255=1S256=1S99999999999=1\r"), [(255, 1)]);
+
+        assert_eq!(s_register_query("ATS0?\r"), Some(0));
+        assert_eq!(s_register_query("ats51?\r"), Some(51));
+        assert_eq!(s_register_query("ATS0?S1?\r"), None);
+        assert_eq!(s_register_query("ATS7=60\r"), None);
+    }
+
+    #[test]
+    fn tells_callers_apart_by_their_init_strings() {
+        for (at_string, expected) in [
+            ("ATE0Q0V0&C1&D2S0=0\r", Some(Caller::WebtvOs)),
+            ("AT&FE0V0+MS=V34,1,2400,28800;S51=31\r", Some(Caller::WebtvOs)),
+            ("AT&FE0V1&C1&D2S7=60S0=0\r", Some(Caller::WindowsCe)),
+            ("at&f e0 v1 &c1 &d2 \\n3 s7=50 s0=0\r", Some(Caller::WindowsCe)),
+            // Desktop Windows, which never says how long to wait for carrier.
+            ("AT &F E0 V1 &D2 &C1 W2 S95=47 S0=0\r", Some(Caller::Generic)),
+            ("AT&FE0V1S0=0S7=60\r", Some(Caller::Generic)),
+            // F0 is in plenty of strings that aren't init strings at all.
+            ("AT&F0\r", None),
+            ("AT%E0F0\r", None),
+            ("ATS7=60L3\r", None),
+            ("ATDT5551212\r", None),
+        ] {
+            assert_eq!(caller(at_string), expected, "{at_string:?}");
+        }
+    }
+
+    // What MAME types can be anything at all, so these take whatever proptest comes up with.
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        // Mostly things that look like command lines, with the odd one that doesn't look like anything.
+        const LINE: &str = "(?i)(AT)?[ -~]{0,60}\r?|(?s).{0,40}";
+
+        // Lines the way the box sends them, which is all the first touchppp had to tell apart.
+        const BOX_LINE: &str = "AT(E0|E1|Q0|V0|V1|&C1|&D2|&F|L3|M0|X4|Z|S[0-9]{1,2}=[0-9]{1,3}){0,8}\r|AT(E0|L3)?DT[0-9]{1,11}\r|ATD\r";
+
+        // What the first touchppp made of a line, with nothing but contains to go on.
+        fn answered_before(at_string: &str) -> Option<&'static str> {
+            if at_string.contains("E0") {
+                Some("init")
+            } else if !at_string.contains("DT") && !at_string.contains("TD") {
+                Some("dial setup")
+            } else if at_string.contains("DT") {
+                Some("dial")
+            } else if at_string.contains("TD\r") {
+                Some("data mode")
+            } else {
+                None
+            }
+        }
+
+        fn answered_now(at_string: &str) -> Option<&'static str> {
+            match parse(at_string).ok()? {
+                Command::Init => Some("init"),
+                Command::DialSetup => Some("dial setup"),
+                Command::Dial(_) => Some("dial"),
+                Command::DataMode => Some("data mode"),
+                _ => None,
+            }
+        }
+
+        // A box line with noise ahead of its bytes and spaces ahead of the line, which the command line drops.
+        fn noisy(line: String) -> impl Strategy<Value = (String, Vec<u8>)> {
+            let noise = proptest::collection::vec(prop_oneof![0x00_u8..0x08, 0x09_u8..0x0d, 0x0e_u8..0x20, 0x7f_u8..=0xff], 0..3);
+
+            (" {0,2}", proptest::collection::vec(noise, line.len())).prop_map(move |(spaces, noise)| {
+                let mut bytes = spaces.into_bytes();
+                for (byte, noise) in line.bytes().zip(noise) {
+                    bytes.extend(noise);
+                    bytes.push(byte);
+                }
+
+                (line.clone(), bytes)
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn command_lines_only_hold_what_was_typed(bytes in proptest::collection::vec(any::<u8>(), 0..2048), max_length in 1_usize..300) {
+                let mut command_line = CommandLine::new(max_length);
+                let mut count = 0;
+
+                for &byte in &bytes {
+                    if let Some(Line::Command(line)) = command_line.push(byte) {
+                        let (typed, end) = line.split_at(line.len() - 1);
+
+                        prop_assert_eq!(end, "\r");
+                        prop_assert!(typed.bytes().all(|byte| classify(byte) == ByteClass::Command), "{:?}", line);
+                        prop_assert!(!typed.starts_with(' '), "{:?}", line);
+                        prop_assert!(line.len() <= max_length, "{:?}", line);
+                        count += 1;
+                    }
+
+                    prop_assert!(command_line.line.len() < max_length);
+                }
+
+                prop_assert!(count <= bytes.iter().filter(|byte| **byte == b'\r').count());
+            }
+
+            #[test]
+            fn box_lines_are_answered_the_way_they_always_were(lines in proptest::collection::vec(BOX_LINE.prop_flat_map(noisy), 1..20)) {
+                let mut command_line = CommandLine::new(255);
+                let read: Vec<Line> = lines.iter().flat_map(|(_, bytes)| bytes.clone()).filter_map(|byte| command_line.push(byte)).collect();
+
+                prop_assert_eq!(read.len(), lines.len());
+                for ((line, _), read) in lines.iter().zip(read) {
+                    prop_assert_eq!(&read, &Line::Command(line.clone()));
+                    prop_assert_eq!(answered_now(line), answered_before(line), "{:?}", line);
+                }
+            }
+
+            #[test]
+            fn tokens_are_the_whole_line(line in LINE) {
+                let unspaced: String = line.trim_end_matches(['\x0d', '\x0a']).to_ascii_uppercase().chars().filter(|c| *c != ' ').collect();
+                let commands = unspaced.strip_prefix("AT").unwrap_or(&unspaced);
+                let tokens = tokenize(&line);
+
+                prop_assert!(tokens.iter().all(|token| !token.name.is_empty()));
+
+                // Only the ; ending an extended command is left out.
+                let joined: String = tokens.iter().flat_map(|token| [token.name.as_str(), token.argument.as_str()]).collect();
+                prop_assert_eq!(joined.replace(';', ""), commands.replace(';', ""));
+            }
+
+            #[test]
+            fn any_line_can_be_read(line in LINE) {
+                let _ = is_well_formed(&line);
+                let _ = settings(&line);
+                let _ = s_register_writes(&line);
+                let _ = s_register_query(&line);
+
+                for profile in [Profile::Webtv, Profile::Generic] {
+                    if let Ok(Command::Dial(number)) = parse_as(&line, profile.modem()) {
+                        prop_assert!(!number.is_empty() && line.to_ascii_uppercase().replace(' ', "").contains(&number), "{:?} dialed {:?}", line, number);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,673 @@
+// How each kind of backend gets reached: launching a local PPP program, connecting to a remote PPP server (with
+// retries, failover and health checks, and maybe through SSH), or the built-in echo and null. Each is a PppBackend, so the bridge doesn't care
+// which it's copying to.
+
+use std::future::Future;
+use std::io;
+use std::io::ErrorKind::NotFound;
+use std::net::SocketAddr;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, BufWriter};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::process::{Child, Command};
+use tracing::{debug, info, warn};
+
+use crate::address::RemoteAddr;
+use crate::bridge;
+use crate::config::{Backend, BackendKind, Config, DialSettings, LinkProtocol, LocalPpp, RemotePpp, NO_WORKING_REMOTE};
+use crate::error::TouchPppError;
+use crate::listener;
+use crate::resolve::NameResolver;
+
+const CONNECT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+// How long one of a server's addresses gets to connect before the next is tried alongside it.
+const NEXT_ADDRESS_DELAY: Duration = Duration::from_millis(250);
+// How long a local PPP program gets to exit after SIGTERM before it's killed.
+const PPP_EXIT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What a backend gets told about the dial it's answering.
+pub struct DialContext<'a> {
+    pub number: &'a str,
+    pub session: u64,
+    pub link_protocol: LinkProtocol,
+    /// How the dial goes, phone book entry and all.
+    pub settings: &'a DialSettings,
+    /// The server that answered the last call to this number that went through, if the backend said.
+    pub endpoint: Option<&'a str>,
+}
+
+/// The backend's end of a call: what it sends MAME, where MAME's bytes go, and whatever has to happen once the
+/// call's over. Dropping it part way through hangs up too.
+pub struct BackendStream {
+    pub reader: Box<dyn AsyncRead + Unpin + Send>,
+    pub writer: Box<dyn AsyncWrite + Unpin + Send>,
+    pub cleanup: BoxFuture<'static, ()>,
+    /// Which server answered, for backends with more than one.
+    pub endpoint: Option<String>,
+}
+
+impl BackendStream {
+    /// A stream that needs nothing done when the call's over beyond dropping its halves.
+    pub fn new(reader: impl AsyncRead + Unpin + Send + 'static, writer: impl AsyncWrite + Unpin + Send + 'static) -> BackendStream {
+        BackendStream {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            cleanup: futures::future::ready(()).boxed(),
+            endpoint: None,
+        }
+    }
+
+    pub fn with_cleanup(mut self, cleanup: impl Future<Output = ()> + Send + 'static) -> BackendStream {
+        self.cleanup = cleanup.boxed();
+
+        self
+    }
+
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> BackendStream {
+        self.endpoint = Some(endpoint.into());
+
+        self
+    }
+}
+
+/// Something PPP can be reached through. The bridge copies MAME's bytes to and from whatever this hands back.
+pub trait PppBackend: Send + Sync {
+    fn establish<'a>(&'a self, context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>>;
+}
+
+impl PppBackend for LocalPpp {
+    fn establish<'a>(&'a self, _context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            info!("Launching then touching some PPP! '{}'", self.command);
+
+            let mut the_args = self.command.split(' ');
+            let first: &str = the_args.next().unwrap();
+            let rest: Vec<&str> = the_args.collect::<Vec<&str>>();
+
+            debug!(target: "touchppp::backend", "Got it? '{}'", first);
+            debug!(target: "touchppp::backend", "Got it2? '{}'", self.command);
+
+            let mut ppp = Command::new(first)
+                .args(rest)
+                .envs(&self.env)
+                .stdout(Stdio::piped())
+                .stdin(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|source| TouchPppError::BackendSpawn { command: self.command.clone(), source })?;
+
+            let ppp_reader = BufReader::new(ppp.stdout.take().expect("No PPP STDOUT?"));
+            let ppp_writer = BufWriter::new(ppp.stdin.take().expect("No PPP STDIN?"));
+
+            Ok(BackendStream::new(ppp_reader, ppp_writer).with_cleanup(hang_up_on(ppp)))
+        }.boxed()
+    }
+}
+
+// Asks a local PPP program to exit so it can say goodbye to its peer, killing it if it won't. Either way it's
+// reaped before this returns.
+async fn hang_up_on(mut ppp: Child) {
+    #[cfg(unix)]
+    if let Some(pid) = ppp.id() {
+        use nix::sys::signal::{kill, Signal};
+        use nix::unistd::Pid;
+
+        let _ = kill(Pid::from_raw(pid as i32), Signal::SIGTERM);
+
+        if tokio::time::timeout(PPP_EXIT_TIMEOUT, ppp.wait()).await.is_ok() {
+            return;
+        }
+
+        warn!("PPP didn't exit within {}s of SIGTERM, killing it.", PPP_EXIT_TIMEOUT.as_secs());
+    }
+
+    let _ = ppp.kill().await;
+}
+
+// Which order to try the servers in. Sticky backends go back to the one that last answered this number (from
+// --state-file), or failing that the one that last answered anyone.
+pub(crate) fn try_order(remote_ppp: &RemotePpp, endpoint: Option<&str>) -> Vec<usize> {
+    let mut try_order: Vec<usize> = (0..remote_ppp.socket_addresses.len()).collect();
+
+    let remembered = endpoint.and_then(|endpoint| remote_ppp.socket_addresses.iter().position(|address| address.to_string() == endpoint));
+    let first = remembered.unwrap_or(remote_ppp.last_working.load(Ordering::SeqCst));
+    if remote_ppp.is_sticky && first < try_order.len() {
+        try_order.retain(|index| *index != first);
+        try_order.insert(0, first);
+    }
+
+    try_order
+}
+
+/// Connects to whichever of `server`'s addresses (as `resolver` finds them) answers first, happy eyeballs style: the addresses take turns by
+/// family, and each gets `NEXT_ADDRESS_DELAY` (or until it fails) before the next one's started alongside it. An
+/// IPv6 address that never answers holds a dual-stack server up by a moment instead of the whole connect timeout,
+/// which whoever's calling still bounds it by.
+pub(crate) async fn resolve_and_connect(server: &RemoteAddr, resolver: &NameResolver, bind_device: Option<&str>) -> io::Result<TcpStream> {
+    let addresses = by_turns(resolver.lookup(server).await?);
+    let (ppp, address) = race(&addresses, bind_device, NEXT_ADDRESS_DELAY).await?;
+
+    if addresses.len() > 1 {
+        debug!(target: "touchppp::backend", "{} won the race to {server}: {address}", if address.is_ipv6() { "IPv6" } else { "IPv4" });
+    }
+
+    Ok(ppp)
+}
+
+// The addresses in the order they came, but swapping families each time, starting with whichever came first.
+fn by_turns(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(is_ipv6) = addresses.first().map(SocketAddr::is_ipv6) else {
+        return addresses;
+    };
+
+    let (firsts, seconds): (Vec<_>, Vec<_>) = addresses.into_iter().partition(|address| address.is_ipv6() == is_ipv6);
+    let mut seconds = seconds.into_iter();
+    let mut turns = Vec::new();
+
+    for address in firsts {
+        turns.push(address);
+        turns.extend(seconds.next());
+    }
+    turns.extend(seconds);
+
+    turns
+}
+
+// Starts on the addresses in order, one every `delay` or as soon as the last one failed, and takes the first to
+// connect. The rest are dropped, which gives up on them.
+async fn race(addresses: &[SocketAddr], bind_device: Option<&str>, delay: Duration) -> io::Result<(TcpStream, SocketAddr)> {
+    let mut last_error = io::Error::new(NotFound, "no addresses to touch");
+    let mut waiting = addresses.iter();
+    let mut attempts = FuturesUnordered::new();
+    let attempt = |address: SocketAddr| async move { (address, connect_over(address, bind_device).await) };
+
+    loop {
+        if attempts.is_empty() {
+            match waiting.next() {
+                Some(address) => attempts.push(attempt(*address)),
+                None => return Err(last_error),
+            }
+        }
+
+        tokio::select! {
+            Some((address, connected)) = attempts.next() => match connected {
+                Ok(ppp) => return Ok((ppp, address)),
+                Err(e) => {
+                    debug!(target: "touchppp::backend", "Couldn't touch {address}: error={e}");
+
+                    last_error = e;
+                    if let Some(address) = waiting.next() {
+                        attempts.push(attempt(*address));
+                    }
+                },
+            },
+            _ = tokio::time::sleep(delay), if !waiting.as_slice().is_empty() => {
+                if let Some(address) = waiting.next() {
+                    attempts.push(attempt(*address));
+                }
+            },
+        }
+    }
+}
+
+// A plain connect, but over --bind-device's interface when there is one.
+async fn connect_over(address: SocketAddr, bind_device: Option<&str>) -> io::Result<TcpStream> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    if let Some(device) = bind_device {
+        listener::bind_device(socket2::SockRef::from(&socket), device)?;
+    }
+
+    socket.connect(address).await
+}
+
+pub(crate) async fn connect_remote(remote_ppp: &RemotePpp, endpoint: Option<&str>) -> tokio::io::Result<(TcpStream, RemoteAddr)> {
+    let last_working = remote_ppp.last_working.load(Ordering::SeqCst);
+
+    let mut last_error = std::io::Error::new(NotFound, "no remote PPP servers to touch");
+
+    for index in try_order(remote_ppp, endpoint) {
+        let remote_socket_address = &remote_ppp.socket_addresses[index];
+        let mut backoff = CONNECT_RETRY_BACKOFF;
+
+        // Retries are per server so a flaky primary gets its chances before we fall over to the backup.
+        for attempt in 0..=remote_ppp.connect_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            match tokio::time::timeout(remote_ppp.connect_timeout, resolve_and_connect(remote_socket_address, &remote_ppp.resolver, remote_ppp.bind_device.as_deref())).await {
+                Ok(Ok(ppp)) => {
+                    remote_ppp.last_working.store(index, Ordering::SeqCst);
+
+                    // Not worth hanging up over.
+                    if let Err(e) = remote_ppp.socket_options.apply(&ppp) {
+                        warn!(target: "touchppp::backend", "Couldn't set socket options for PPP @ {remote_socket_address}: error={e}");
+                    }
+
+                    return Ok((ppp, remote_socket_address.clone()));
+                },
+                Ok(Err(e)) => {
+                    warn!(target: "touchppp::backend", "Couldn't touch PPP @ {remote_socket_address} (attempt {}/{}): error={e}", attempt + 1, remote_ppp.connect_retries + 1);
+
+                    last_error = e;
+                },
+                Err(e) => {
+                    warn!(target: "touchppp::backend", "Couldn't touch PPP @ {remote_socket_address} (attempt {}/{}): error={e}", attempt + 1, remote_ppp.connect_retries + 1);
+
+                    last_error = e.into();
+                },
+            }
+        }
+
+        if index == last_working {
+            remote_ppp.last_working.store(NO_WORKING_REMOTE, Ordering::SeqCst);
+        }
+    }
+
+    Err(last_error)
+}
+
+impl PppBackend for RemotePpp {
+    fn establish<'a>(&'a self, context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            info!("Touching PPP! '{}'", self.describe_addresses());
+
+            #[cfg(all(unix, feature = "ssh"))]
+            if let Some(tunnel) = &self.ssh {
+                return tunnel.connect_remote(self, context.endpoint).await;
+            }
+
+            let (ppp, remote_socket_address) = connect_remote(self, context.endpoint).await
+                .map_err(|source| TouchPppError::BackendConnect { endpoint: self.describe_addresses(), source })?;
+
+            info!("Touched PPP @ {remote_socket_address}");
+
+            let (ppp_reader, ppp_writer) = ppp.into_split();
+
+            Ok(BackendStream::new(ppp_reader, ppp_writer).with_endpoint(remote_socket_address.to_string()))
+        }.boxed()
+    }
+}
+
+/// Sends whatever MAME sends straight back, so the modem side can be checked without any PPP at all.
+pub struct Echo;
+
+impl PppBackend for Echo {
+    fn establish<'a>(&'a self, _context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            info!("Touching the built-in echo.");
+
+            // Whatever's written to one end of the pipe comes out the other.
+            let (to_echo, from_echo) = tokio::io::duplex(bridge::BUFFER_SIZE);
+            let (_, writer) = tokio::io::split(to_echo);
+            let (reader, _) = tokio::io::split(from_echo);
+
+            Ok(BackendStream::new(reader, writer))
+        }.boxed()
+    }
+}
+
+/// Takes whatever MAME sends and never says anything back, like PPP that answered then went quiet.
+pub struct Null;
+
+impl PppBackend for Null {
+    fn establish<'a>(&'a self, _context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            info!("Touching the built-in null.");
+
+            // Nothing's ever written to the other end, so reads wait forever instead of ending the call. It's held
+            // until the call's over.
+            let (quiet, nothing) = tokio::io::duplex(1);
+
+            Ok(BackendStream::new(quiet, tokio::io::sink()).with_cleanup(async move { drop(nothing) }))
+        }.boxed()
+    }
+}
+
+// Keeps a remote backend's count of sessions right however the bridge ends, including being dropped mid-copy.
+pub(crate) struct ActiveSession<'a>(&'a AtomicUsize);
+
+impl<'a> ActiveSession<'a> {
+    pub(crate) fn start(active_sessions: &'a AtomicUsize) -> ActiveSession<'a> {
+        active_sessions.fetch_add(1, Ordering::SeqCst);
+
+        ActiveSession(active_sessions)
+    }
+}
+
+impl Drop for ActiveSession<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Connects to each of a remote backend's servers in turn, hanging up right away, and gives back the first one
+/// that answered.
+pub async fn probe_remote(remote_ppp: &RemotePpp) -> tokio::io::Result<RemoteAddr> {
+    let mut last_error = std::io::Error::new(NotFound, "no remote PPP servers to touch");
+
+    // Only the SSH server can be reached from here, so it's the one that has to answer. Logging in waits for a dial.
+    #[cfg(all(unix, feature = "ssh"))]
+    let socket_addresses = match &remote_ppp.ssh {
+        Some(tunnel) => std::slice::from_ref(&tunnel.server.server),
+        None => &remote_ppp.socket_addresses[..],
+    };
+    #[cfg(not(all(unix, feature = "ssh")))]
+    let socket_addresses = &remote_ppp.socket_addresses[..];
+
+    for remote_socket_address in socket_addresses.iter() {
+        // Connect then immediately hang up; we only care that something answered.
+        match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, resolve_and_connect(remote_socket_address, &remote_ppp.resolver, remote_ppp.bind_device.as_deref())).await {
+            Ok(Ok(ppp)) => {
+                drop(ppp);
+
+                return Ok(remote_socket_address.clone());
+            },
+            Ok(Err(e)) => last_error = e,
+            Err(e) => last_error = e.into(),
+        }
+    }
+
+    Err(last_error)
+}
+
+async fn health_check_loop(backend: Weak<Backend>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        // The backend is gone once a config reload replaced it and the last session using it hung up.
+        let Some(backend) = backend.upgrade() else {
+            return;
+        };
+
+        let BackendKind::Remote(remote_ppp) = &backend.kind else {
+            return;
+        };
+
+        let remote_socket_address = remote_ppp.describe_addresses();
+
+        // A session that's touching PPP right now is all the proof we need, so don't add to its load.
+        if remote_ppp.active_sessions.load(Ordering::SeqCst) > 0 {
+            continue;
+        }
+
+        let is_healthy = match probe_remote(remote_ppp).await {
+            Ok(_) => true,
+            Err(e) => {
+                if remote_ppp.healthy.load(Ordering::SeqCst) {
+                    warn!("PPP @ {remote_socket_address} stopped answering! Dials will get BUSY until it's back. error={e}");
+                }
+
+                false
+            }
+        };
+
+        if is_healthy && !remote_ppp.healthy.load(Ordering::SeqCst) {
+            info!("PPP @ {remote_socket_address} is answering again.");
+        }
+
+        remote_ppp.healthy.store(is_healthy, Ordering::SeqCst);
+    }
+}
+
+/// Checks every remote backend the config can reach once now, then keeps checking the ones with
+/// --health-check-interval in the background.
+pub async fn start_health_checks(config: &Config) {
+    for backend in config.reachable_backends() {
+        let BackendKind::Remote(remote_ppp) = &backend.kind else {
+            continue;
+        };
+
+        let is_healthy = match probe_remote(remote_ppp).await {
+            Ok(answered_socket_address) => {
+                info!("PPP @ {answered_socket_address} is answering for backend {}.", backend.name);
+
+                true
+            },
+            Err(e) => {
+                warn!("********** Couldn't touch PPP for backend {}! Dials will fail until it's reachable. error={e} **********", backend.describe());
+
+                false
+            }
+        };
+
+        if let Some(interval) = config.health_check_interval {
+            remote_ppp.healthy.store(is_healthy, Ordering::SeqCst);
+
+            tokio::spawn(health_check_loop(Arc::downgrade(&backend), interval));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::net::{TcpListener, TcpSocket};
+
+    #[tokio::test]
+    async fn remote_servers_get_the_socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        for low_latency in [false, true] {
+            let config = Config::builder().connect(address.to_string()).low_latency(low_latency).build().unwrap();
+            let Some(BackendKind::Remote(remote_ppp)) = config.cli_backend.as_ref().map(|backend| &backend.kind) else {
+                panic!("-c should be remote");
+            };
+
+            let (ppp, _) = connect_remote(remote_ppp, None).await.unwrap();
+
+            assert_eq!(ppp.nodelay().unwrap(), low_latency);
+            assert_eq!(remote_ppp.coalesce.is_none(), low_latency);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn remote_servers_are_reached_over_the_bind_device() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        for device in ["lo", "touchppp0"] {
+            let config = Config::builder().connect(address.to_string()).bind_device(device).build().unwrap();
+            let Some(BackendKind::Remote(remote_ppp)) = config.cli_backend.as_ref().map(|backend| &backend.kind) else {
+                panic!("-c should be remote");
+            };
+
+            // lo can only fail for want of permission, which still has to say why.
+            match connect_remote(remote_ppp, None).await {
+                Ok(_) => assert_eq!(device, "lo"),
+                Err(e) => assert!(e.to_string().starts_with(&format!("can't send over {device}: ")), "{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn addresses_take_turns_by_family() {
+        let [a, b, c, x, y]: [SocketAddr; 5] = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"].map(|address| address.parse().unwrap());
+
+        assert_eq!(by_turns(vec![a, b, c, x, y]), [a, x, b, y, c]);
+        assert_eq!(by_turns(vec![x, a, b, c, y]), [x, a, y, b, c]);
+        assert_eq!(by_turns(vec![x, y]), [x, y]);
+    }
+
+    #[tokio::test]
+    async fn a_blackholed_address_loses_the_race() {
+        // A listener whose queue is full drops anything else that tries to connect, so it never answers.
+        let blackholed = TcpSocket::new_v4().unwrap();
+        blackholed.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let blackholed = blackholed.listen(1).unwrap();
+        let blackholed_address = blackholed.local_addr().unwrap();
+
+        let mut queued = Vec::new();
+        while let Ok(connected) = tokio::time::timeout(Duration::from_millis(200), TcpStream::connect(blackholed_address)).await {
+            queued.push(connected.unwrap());
+            assert!(queued.len() < 16, "the listener never filled up");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let working = listener.local_addr().unwrap();
+
+        let started = Instant::now();
+        let (_ppp, won) = race(&[blackholed_address, working], None, NEXT_ADDRESS_DELAY).await.unwrap();
+
+        assert_eq!(won, working);
+        assert!(started.elapsed() < Duration::from_secs(1), "took {:?}", started.elapsed());
+        assert!(started.elapsed() >= NEXT_ADDRESS_DELAY);
+    }
+
+    #[tokio::test]
+    async fn a_refused_address_hands_over_straight_away() {
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let working = listener.local_addr().unwrap();
+
+        let started = Instant::now();
+        let (_ppp, won) = race(&[refused, working], None, Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(won, working);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let e = race(&[refused], None, Duration::from_secs(5)).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    fn remote_ppp(config: &Config) -> &RemotePpp {
+        match config.cli_backend.as_ref().map(|backend| &backend.kind) {
+            Some(BackendKind::Remote(remote_ppp)) => remote_ppp,
+            _ => panic!("-c should be remote"),
+        }
+    }
+
+    fn failing_over(addresses: &[SocketAddr], is_sticky: bool) -> Config {
+        addresses.iter().fold(Config::builder(), |builder, address| builder.connect(address.to_string())).connect_retries(0).remote_sticky(is_sticky).build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn probing_tells_an_answering_server_from_a_refusing_one() {
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let answering = listener.local_addr().unwrap();
+
+        // Any one of them answering is enough.
+        let config = failing_over(&[refused, answering], false);
+        let answered = probe_remote(remote_ppp(&config)).await.unwrap();
+        assert_eq!(answered.to_string(), answering.to_string());
+
+        drop(listener);
+        assert!(probe_remote(remote_ppp(&failing_over(&[answering], false))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_refusing_server_fails_over_to_the_next() {
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let working = listener.local_addr().unwrap();
+
+        let config = failing_over(&[refused, working], false);
+        let remote_ppp = remote_ppp(&config);
+
+        for _ in 0..2 {
+            let (_ppp, answered) = connect_remote(remote_ppp, None).await.unwrap();
+            assert_eq!(answered.to_string(), working.to_string());
+        }
+
+        // Without --remote-sticky, the first one's always tried first.
+        assert_eq!(try_order(remote_ppp, None), [0, 1]);
+
+        drop(listener);
+        let e = connect_remote(remote_ppp, None).await.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn a_sticky_backend_goes_back_to_the_server_that_answered() {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let third = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addresses = [first, second.local_addr().unwrap(), third.local_addr().unwrap()];
+
+        let config = failing_over(&addresses, true);
+        let remote_ppp = remote_ppp(&config);
+
+        let (_ppp, answered) = connect_remote(remote_ppp, None).await.unwrap();
+        assert_eq!(answered.to_string(), addresses[1].to_string());
+
+        // The first one's back, but the second one answered last, so it still gets the call.
+        let _first = TcpListener::bind(first).await.unwrap();
+        for _ in 0..2 {
+            let (_ppp, answered) = connect_remote(remote_ppp, None).await.unwrap();
+            assert_eq!(answered.to_string(), addresses[1].to_string());
+            assert_eq!(try_order(remote_ppp, None), [1, 0, 2]);
+        }
+
+        // The server that last answered this number wins over the one that last answered anyone.
+        let (_ppp, answered) = connect_remote(remote_ppp, Some(&addresses[2].to_string())).await.unwrap();
+        assert_eq!(answered.to_string(), addresses[2].to_string());
+
+        // Once the one it sticks to stops answering, it's the next one in line, then that one's stuck to.
+        drop(third);
+        let (_ppp, answered) = connect_remote(remote_ppp, None).await.unwrap();
+        assert_eq!(answered.to_string(), addresses[0].to_string());
+        assert_eq!(try_order(remote_ppp, None), [0, 1, 2]);
+    }
+
+    fn health_checked(address: SocketAddr) -> Config {
+        Config::builder().connect(address.to_string()).health_check_interval(5).build().unwrap()
+    }
+
+    // Waits for the health check to come around to `healthy`.
+    async fn until_healthy(remote_ppp: &RemotePpp, healthy: bool) {
+        let checked = tokio::time::timeout(Duration::from_secs(5), async {
+            while remote_ppp.healthy.load(Ordering::SeqCst) != healthy {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        checked.await.unwrap_or_else(|_| panic!("never went healthy={healthy}"));
+    }
+
+    #[tokio::test]
+    async fn health_checks_follow_a_server_going_up_and_down() {
+        let address = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let config = health_checked(address);
+        let Some(backend) = &config.cli_backend else {
+            panic!("-c should have a backend");
+        };
+
+        start_health_checks(&config).await;
+        assert!(!remote_ppp(&config).healthy.load(Ordering::SeqCst));
+
+        // Quicker than --health-check-interval can go, so the test doesn't wait on it.
+        tokio::spawn(health_check_loop(Arc::downgrade(backend), Duration::from_millis(20)));
+
+        let listener = TcpListener::bind(address).await.unwrap();
+        until_healthy(remote_ppp(&config), true).await;
+
+        drop(listener);
+        until_healthy(remote_ppp(&config), false).await;
+
+        // A call that's up is proof enough, so it's left alone while there is one.
+        let listener = TcpListener::bind(address).await.unwrap();
+        let session = ActiveSession::start(&remote_ppp(&config).active_sessions);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!remote_ppp(&config).healthy.load(Ordering::SeqCst));
+
+        drop(session);
+        until_healthy(remote_ppp(&config), true).await;
+        drop(listener);
+    }
+}
@@ -0,0 +1,253 @@
+// `touchppp bench`: calls an in-process TouchPPP over TCP and times what comes back, so changes to the modem or
+// the bridge can be measured instead of guessed at. The measuring is here in the library so anything else that
+// wants the numbers (a criterion bench, say) gets the same ones.
+
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
+
+use crate::config::{Builtin, Config};
+use crate::selftest::{read_expected, CALL};
+use crate::server::Server;
+use crate::StartError;
+
+// Anything slower than this is stuck, not slow.
+const ROUND_TRIP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Sent over and over in command mode. It's answered without going anywhere near a backend.
+const COMMAND: &[u8] = b"ATS7=60L3\r";
+const COMMAND_REPLY: &[u8] = b"\r\n0\r\n";
+
+/// What a bench call does once it's through.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Stays in command mode, sending a dial setup string and waiting for each OK.
+    Commands,
+    /// Data mode against the built-in echo.
+    Echo,
+    /// Data mode against the built-in echo, held to the 33600 carrier.
+    Throttled,
+    /// Data mode against an echo server over TCP, with MAME's bytes coalesced on the way.
+    Coalesced,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 4] = [Mode::Commands, Mode::Echo, Mode::Throttled, Mode::Coalesced];
+}
+
+impl std::str::FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Mode, String> {
+        match value {
+            "commands" => Ok(Mode::Commands),
+            "echo" => Ok(Mode::Echo),
+            "throttled" => Ok(Mode::Throttled),
+            "coalesced" => Ok(Mode::Coalesced),
+            _ => Err("use commands, echo, throttled or coalesced".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mode::Commands => write!(f, "commands"),
+            Mode::Echo => write!(f, "echo"),
+            Mode::Throttled => write!(f, "throttled"),
+            Mode::Coalesced => write!(f, "coalesced"),
+        }
+    }
+}
+
+/// One bench run: how long to keep going, and how much to send each time (ignored for [`Mode::Commands`]).
+#[derive(Clone, Copy, Debug)]
+pub struct BenchOptions {
+    pub mode: Mode,
+    pub payload: usize,
+    pub duration: Duration,
+}
+
+/// How a bench run went. Every round trip is one payload (or command) sent and all of it back.
+pub struct BenchResult {
+    pub bytes: u64,
+    pub elapsed: Duration,
+    // Sorted, shortest first.
+    latencies: Vec<Duration>,
+}
+
+impl BenchResult {
+    pub fn round_trips(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// Bytes that made it there and back, per second.
+    pub fn bytes_per_second(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// The round trip time `percent` of them were at or under.
+    pub fn latency(&self, percent: usize) -> Duration {
+        match self.latencies.len() {
+            0 => Duration::ZERO,
+            len => self.latencies[(len - 1) * percent.min(100) / 100],
+        }
+    }
+}
+
+// A PPP server that sends everything straight back, for the coalesced mode.
+async fn echo_server() -> std::io::Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+
+    tokio::spawn(async move {
+        while let Ok((mut ppp, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = ppp.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    Ok(port)
+}
+
+async fn config_for(mode: Mode) -> Result<Config, Box<dyn std::error::Error>> {
+    let builder = Config::builder().listen("127.0.0.1:0");
+
+    let builder = match mode {
+        Mode::Commands | Mode::Echo => builder.builtin(Builtin::Echo),
+        Mode::Throttled => builder.builtin(Builtin::Echo).throttle_to_carrier(true),
+        Mode::Coalesced => builder.connect(format!("127.0.0.1:{}", echo_server().await?)),
+    };
+
+    Ok(builder.build()?)
+}
+
+// Gets through the init string and dial, stopping short of data mode for Commands.
+async fn call(modem: &mut TcpStream, mode: Mode) -> Result<(), String> {
+    let steps = match mode {
+        Mode::Commands => &CALL[..1],
+        _ => &CALL[..],
+    };
+
+    for step in steps {
+        modem.write_all(step.send.as_bytes()).await.map_err(|e| format!("{}: couldn't send: {e}", step.name))?;
+
+        let (received, problem) = read_expected(modem, step.expect.len()).await;
+        if received != step.expect {
+            return Err(format!("{}: got '{}'{}", step.name, String::from_utf8_lossy(&received).escape_debug(), problem.map(|p| format!(" then {p}")).unwrap_or_default()));
+        }
+    }
+
+    Ok(())
+}
+
+// Sends `send` and waits for all of `expect`, giving back how long that took.
+async fn round_trip(modem: &mut TcpStream, send: &[u8], expect: &mut [u8]) -> Result<Duration, String> {
+    let started = Instant::now();
+
+    modem.write_all(send).await.map_err(|e| format!("couldn't send: {e}"))?;
+
+    match tokio::time::timeout(ROUND_TRIP_TIMEOUT, modem.read_exact(expect)).await {
+        Ok(Ok(_)) => Ok(started.elapsed()),
+        Ok(Err(e)) => Err(format!("couldn't read: {e}")),
+        Err(_) => Err(format!("nothing back after {} seconds", ROUND_TRIP_TIMEOUT.as_secs())),
+    }
+}
+
+// Calls the TouchPPP at `address` and sends round trips until `options.duration` is up.
+async fn bench_call(address: std::net::SocketAddr, options: BenchOptions) -> Result<BenchResult, Box<dyn std::error::Error>> {
+    let mut modem = TcpStream::connect(address).await?;
+    modem.set_nodelay(true)?;
+    call(&mut modem, options.mode).await?;
+
+    let (send, mut expect) = match options.mode {
+        Mode::Commands => (COMMAND.to_vec(), vec![0; COMMAND_REPLY.len()]),
+        _ => ((0..options.payload.max(1)).map(|i| (i % 251) as u8).collect(), vec![0; options.payload.max(1)]),
+    };
+
+    let mut latencies = Vec::new();
+    let mut bytes = 0;
+    let started = Instant::now();
+    while started.elapsed() < options.duration {
+        latencies.push(round_trip(&mut modem, &send, &mut expect).await?);
+        bytes += send.len() as u64;
+    }
+    let elapsed = started.elapsed();
+
+    latencies.sort();
+
+    Ok(BenchResult { bytes, elapsed, latencies })
+}
+
+/// Starts a TouchPPP set up for `options.mode`, calls it and sends round trips until `options.duration` is up.
+pub async fn measure(options: BenchOptions) -> Result<BenchResult, Box<dyn std::error::Error>> {
+    let server = Server::bind(config_for(options.mode).await?).await.map_err(|e| e.to_string())?;
+    let address = server.local_addr().ok_or("the bench server isn't listening on TCP")?;
+
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let measuring = async {
+        let measured = bench_call(address, options).await;
+        let _ = stop.send(());
+
+        measured
+    };
+
+    let (_, measured) = tokio::join!(server.run_until(async { let _ = stopped.await; }), measuring);
+
+    measured
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Runs every mode with every payload size for `duration` each, printing a table row as each one finishes.
+#[tokio::main]
+pub async fn run(modes: &[Mode], payloads: &[usize], duration: Duration) -> Result<(), StartError> {
+    println!("{:<10} {:>8} {:>12} {:>14} {:>10} {:>10} {:>10} {:>10}", "mode", "payload", "round trips", "bytes/s", "p50", "p90", "p99", "max");
+
+    for &mode in modes {
+        // Commands are the same size whatever the payload.
+        let payloads = if mode == Mode::Commands { &payloads[..payloads.len().min(1)] } else { payloads };
+
+        for &payload in payloads {
+            let result = measure(BenchOptions { mode, payload, duration }).await
+                .map_err(|e| StartError::Runtime(format!("bench {mode} with {payload} bytes failed: {e}").into()))?;
+
+            let payload = if mode == Mode::Commands { "-".to_string() } else { payload.to_string() };
+            println!(
+                "{:<10} {:>8} {:>12} {:>14.0} {:>10} {:>10} {:>10} {:>10}",
+                mode.to_string(),
+                payload,
+                result.round_trips(),
+                result.bytes_per_second(),
+                millis(result.latency(50)),
+                millis(result.latency(90)),
+                millis(result.latency(99)),
+                millis(result.latency(100)),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_short_bench_moves_some_bytes() {
+        for mode in Mode::ALL {
+            let result = measure(BenchOptions { mode, payload: 64, duration: Duration::from_millis(300) }).await.unwrap();
+
+            assert!(result.round_trips() > 0, "{mode} made no round trips");
+            assert!(result.bytes_per_second() > 0.0, "{mode} moved nothing");
+            assert!(result.latency(50) > Duration::ZERO, "{mode} took no time at all");
+            assert!(result.latency(50) <= result.latency(100));
+        }
+    }
+}
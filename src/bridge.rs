@@ -0,0 +1,481 @@
+// Data mode: shoveling bytes between MAME and whichever backend the dial went to.
+
+use std::io::ErrorKind::{ConnectionAborted, ConnectionReset};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::trace;
+
+use crate::backend::BackendStream;
+use crate::config::Throttle;
+use crate::error::TouchPppError;
+use crate::ppp::hdlc::Deframer;
+use crate::ppp::watch::{Direction, IpcpWatch};
+use crate::stats;
+
+pub(crate) const BUFFER_SIZE: usize = 0x1000;
+
+fn hexdump(data: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (line_index, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line.iter().map(|b| if (0x20..0x7f).contains(b) { *b as char } else { '.' }).collect();
+
+        dump.push_str(&format!("\n{:04x}: {:<47}  {}", line_index * 16, hex.join(" "), ascii));
+    }
+
+    dump
+}
+
+// Holds one direction of a call to a bit rate, the way the modem's carrier would. Bytes go in small chunks so
+// nothing arrives in bursts bigger than a modem could manage in a twentieth of a second.
+pub(crate) struct Pacer {
+    bytes_per_second: u64,
+    started: Instant,
+    sent: u64,
+}
+
+impl Pacer {
+    pub(crate) fn new(bits_per_second: u32) -> Pacer {
+        Pacer {
+            bytes_per_second: (bits_per_second as u64 / 8).max(1),
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    fn chunk_size(&self) -> usize {
+        ((self.bytes_per_second / 20) as usize).clamp(1, BUFFER_SIZE)
+    }
+
+    // Waits until `bytes` more would have made it across at this rate.
+    async fn pace(&mut self, bytes: usize) {
+        self.sent += bytes as u64;
+
+        tokio::time::sleep_until(self.started + Duration::from_secs_f64(self.sent as f64 / self.bytes_per_second as f64)).await;
+    }
+}
+
+/// Holds MAME's bytes back until there are `bytes` of them or `wait` has gone by since the first, so a byte at a
+/// time from the bitbanger doesn't become a packet at a time to a remote PPP server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coalesce {
+    pub bytes: usize,
+    pub wait: Duration,
+}
+
+// A connection reset or abort mid call just means the other end hung up.
+fn hung_up_is_eof(result: tokio::io::Result<usize>) -> tokio::io::Result<usize> {
+    result.or_else(|e| match e.kind() {
+        ConnectionReset | ConnectionAborted => Ok(0),
+        _ => Err(e)
+    })
+}
+
+// What the admin interface can do to one way of a call as it goes.
+pub(crate) struct Controls<'a> {
+    // True while the call's paused.
+    pub(crate) pause: watch::Receiver<bool>,
+    // Bytes to send between reads.
+    pub(crate) inject: &'a mut mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+// The next bytes injected from the admin interface, or never without a queue to take them from.
+async fn next_injected(inject: Option<&mut mpsc::UnboundedReceiver<Vec<u8>>>) -> Option<Vec<u8>> {
+    match inject {
+        Some(inject) => inject.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Waits for the call to be `paused`, or not. One that can't be paused never is.
+async fn until_paused(pause: Option<&mut watch::Receiver<bool>>, paused: bool) {
+    let is_there = match pause {
+        Some(pause) => pause.wait_for(|is_paused| *is_paused == paused).await.is_ok(),
+        None => false,
+    };
+
+    if paused && !is_there {
+        std::future::pending::<()>().await;
+    }
+}
+
+// Copies until `read` runs dry or `cancel` fires, whichever's first, even part way through a write. With
+// `controls`, anything injected goes out between reads, so it never lands in the middle of one, and nothing's read
+// at all while the call's paused.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn copy_loop<R, W>(
+    direction: &str,
+    read: &mut R,
+    write: &mut W,
+    copied: &AtomicU64,
+    cancel: CancellationToken,
+    mut pacer: Option<Pacer>,
+    coalesce: Option<Coalesce>,
+    controls: Option<Controls<'_>>,
+) -> tokio::io::Result<usize>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut copied_bytes = 0;
+    let mut buf = [0u8; BUFFER_SIZE];
+    let chunk_size = pacer.as_ref().map_or(BUFFER_SIZE, Pacer::chunk_size);
+    let (mut pause, mut inject) = match controls {
+        Some(Controls { pause, inject }) => (Some(pause), Some(inject)),
+        None => (None, None),
+    };
+    let mut is_done = false;
+    while !is_done {
+        // Paused, nothing's read, so TCP pushes back on whoever's sending instead of it piling up here. The pacer
+        // doesn't count the time, or it'd make up for it in a burst.
+        if pause.as_ref().is_some_and(|pause| *pause.borrow()) {
+            let paused_at = Instant::now();
+
+            tokio::select! {
+                _ = until_paused(pause.as_mut(), false) => {},
+                _ = cancel.cancelled() => break,
+            }
+
+            if let Some(pacer) = pacer.as_mut() {
+                pacer.started += paused_at.elapsed();
+            }
+        }
+
+        let mut bytes_found = 0;
+        let injected;
+        tokio::select! {
+            biased;
+
+            // A read that's still waiting when the call's paused is let go, and nothing it would have read is lost.
+            _ = until_paused(pause.as_mut(), true) => continue,
+            Some(bytes) = next_injected(inject.as_deref_mut()) => {
+                injected = Some(bytes);
+            },
+            result = read.read(&mut buf[..chunk_size]) => {
+                bytes_found = hung_up_is_eof(result)?;
+                injected = None;
+            },
+            _ = cancel.cancelled() => {
+                break;
+            }
+        }
+
+        if injected.is_none() && bytes_found == 0 {
+            break;
+        }
+
+        // The first byte starts the clock. Whatever's held back when it runs out (or the read side ends) goes as is.
+        if let (Some(coalesce), None) = (coalesce, &injected) {
+            let deadline = Instant::now() + coalesce.wait;
+            let wanted = coalesce.bytes.min(chunk_size);
+
+            while bytes_found < wanted {
+                tokio::select! {
+                    biased;
+
+                    result = read.read(&mut buf[bytes_found..wanted]) => {
+                        match hung_up_is_eof(result)? {
+                            0 => {
+                                is_done = true;
+                                break;
+                            },
+                            more => bytes_found += more,
+                        }
+                    },
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    // What's held back was read already, so it still goes.
+                    _ = cancel.cancelled() => {
+                        is_done = true;
+                        break;
+                    },
+                }
+            }
+        }
+
+        //thread::sleep(time::Duration::from_millis(10));
+
+        let (data, kind) = match &injected {
+            Some(bytes) => (&bytes[..], " injected"),
+            None => (&buf[0..bytes_found], ""),
+        };
+
+        // Only pay for the formatting when someone asked for -vv.
+        if tracing::enabled!(target: "touchppp::bridge", tracing::Level::TRACE) {
+            trace!(target: "touchppp::bridge", "{direction} {}{kind} bytes:{}", data.len(), hexdump(data));
+        }
+
+        tokio::select! {
+            // A write that can go right away still does once we're cancelled, so nothing that was read is dropped.
+            // One that's stuck doesn't hold the cancel up.
+            biased;
+
+            // Flushed every time, or a buffered writer (like a local PPP program's stdin) would sit on it.
+            result = async { write.write_all(data).await?; write.flush().await } => result?,
+            _ = cancel.cancelled() => {
+                break;
+            }
+        }
+
+        copied_bytes += data.len();
+        copied.fetch_add(data.len() as u64, Ordering::SeqCst);
+
+        if let Some(pacer) = pacer.as_mut() {
+            tokio::select! {
+                _ = pacer.pace(data.len()) => {},
+                _ = cancel.cancelled() => {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(copied_bytes)
+}
+
+// Passes whatever's written straight through, picking out PPP frames as they go by so the session hears what
+// IPCP settled on.
+struct Watched<'a, W> {
+    writer: W,
+    direction: Direction,
+    deframer: Deframer,
+    watch: Option<&'a Mutex<IpcpWatch>>,
+    session: &'a stats::SessionGuard,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Watched<'_, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let written = Pin::new(&mut this.writer).poll_write(cx, buf);
+
+        if let (Poll::Ready(Ok(bytes_written)), Some(watch)) = (&written, this.watch) {
+            for byte in &buf[..*bytes_written] {
+                let Some(frame) = this.deframer.push(*byte) else {
+                    continue;
+                };
+
+                let negotiated = watch.lock().unwrap().frame(this.direction, &frame);
+                if let Some(ip) = negotiated {
+                    this.session.record_ip(ip);
+                }
+            }
+        }
+
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.writer).poll_shutdown(cx)
+    }
+}
+
+/// Data mode: MAME's bytes go to the backend and back until one of them hangs up or `cancel` fires, giving back
+/// how many bytes went each way (MAME to PPP first). Either way the backend's cleanup has run by the time this
+/// returns. With `throttle`, each way is held to its own bits per second. With `coalesce`, MAME's bytes are
+/// gathered up before they're sent on to PPP. With `watch_ipcp`, the session's told which addresses IPCP settles on.
+pub async fn bridge<S: AsyncRead + AsyncWrite + Unpin>(mame: &mut S, ppp: BackendStream, session: &stats::SessionGuard, cancel: CancellationToken, throttle: Throttle, coalesce: Option<Coalesce>, watch_ipcp: bool) -> Result<(usize, usize), TouchPppError> {
+    let (mut mame_reader, mame_writer) = tokio::io::split(mame);
+    let BackendStream { reader: mut ppp_reader, writer: ppp_writer, cleanup, .. } = ppp;
+
+    let watch = Mutex::new(IpcpWatch::default());
+    let watch = watch_ipcp.then_some(&watch);
+    let mut mame_writer = Watched { writer: mame_writer, direction: Direction::ToBox, deframer: Deframer::default(), watch, session };
+    let mut ppp_writer = Watched { writer: ppp_writer, direction: Direction::FromBox, deframer: Deframer::default(), watch, session };
+
+    // One side ending stops the other, without touching whatever `cancel` belongs to.
+    let done = cancel.child_token();
+
+    // What the admin interface injects goes out with the rest of the traffic each way, and a pause holds both.
+    let mut inject = session.open_inject_queues();
+    let pause = session.watch_pause();
+
+    let (ppp_to_mame_copied_bytes, mame_to_ppp_copied_bytes) = tokio::join!{
+        async {
+            let copied = copy_loop("PPP->MAME", &mut ppp_reader, &mut mame_writer, &session.bytes_down, done.clone(), throttle.down.map(Pacer::new), None, Some(Controls { pause: pause.clone(), inject: &mut inject.mame })).await;
+            done.cancel();
+
+            copied
+        },
+        async {
+            let copied = copy_loop("MAME->PPP", &mut mame_reader, &mut ppp_writer, &session.bytes_up, done.clone(), throttle.up.map(Pacer::new), coalesce, Some(Controls { pause: pause.clone(), inject: &mut inject.backend })).await;
+            done.cancel();
+
+            copied
+        },
+    };
+
+    // PPP sees its end of the pipes close before its cleanup asks it to go.
+    drop(ppp_reader);
+    drop(ppp_writer);
+
+    cleanup.await;
+
+    Ok((mame_to_ppp_copied_bytes?, ppp_to_mame_copied_bytes?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn cancelling_stops_a_copy_stuck_writing() {
+        let (mut from_mame, mut mame) = tokio::io::duplex(BUFFER_SIZE);
+        // Nobody reads the other end, so the first write never finishes.
+        let (mut to_ppp, _ppp) = tokio::io::duplex(1);
+
+        mame.write_all(b"~ppp~").await.unwrap();
+
+        let copied = AtomicU64::new(0);
+        let cancel = CancellationToken::new();
+
+        let copying = copy_loop("MAME->PPP", &mut from_mame, &mut to_ppp, &copied, cancel.clone(), None, None, None);
+        tokio::pin!(copying);
+
+        assert!(tokio::time::timeout(Duration::from_millis(50), &mut copying).await.is_err());
+
+        cancel.cancel();
+
+        let copied_bytes = tokio::time::timeout(Duration::from_secs(5), copying).await.expect("still copying").unwrap();
+        assert_eq!(copied_bytes, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelling_while_coalescing_still_sends_what_was_held() {
+        let (mut from_mame, mut mame) = tokio::io::duplex(BUFFER_SIZE);
+        let mut to_ppp = Writes::default();
+
+        let copied = AtomicU64::new(0);
+        let cancel = CancellationToken::new();
+        let coalesce = Some(Coalesce { bytes: 256, wait: Duration::from_secs(60) });
+
+        let copied_bytes = {
+            let copying = copy_loop("MAME->PPP", &mut from_mame, &mut to_ppp, &copied, cancel.clone(), None, coalesce, None);
+            tokio::pin!(copying);
+
+            // Well short of the size, and well inside the wait.
+            mame.write_all(b"~\xff\x03").await.unwrap();
+            assert!(tokio::time::timeout(Duration::from_millis(10), &mut copying).await.is_err());
+            mame.write_all(b"\xc0\x21~").await.unwrap();
+            assert!(tokio::time::timeout(Duration::from_millis(10), &mut copying).await.is_err());
+
+            cancel.cancel();
+
+            tokio::time::timeout(Duration::from_secs(5), copying).await.expect("still copying").unwrap()
+        };
+        assert_eq!(copied_bytes, 6);
+        assert_eq!(copied.load(Ordering::SeqCst), 6);
+        assert_eq!(to_ppp.0.concat(), b"~\xff\x03\xc0\x21~");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pacing_holds_to_the_bit_rate() {
+        let (mut from_mame, mut mame) = tokio::io::duplex(BUFFER_SIZE * 4);
+        let (mut to_ppp, mut ppp) = tokio::io::duplex(BUFFER_SIZE * 4);
+
+        // Two seconds' worth at 33600.
+        mame.write_all(&[0x7e; 8400]).await.unwrap();
+        drop(mame);
+
+        let copied = AtomicU64::new(0);
+        let started = Instant::now();
+
+        let copied_bytes = copy_loop("MAME->PPP", &mut from_mame, &mut to_ppp, &copied, CancellationToken::new(), Some(Pacer::new(33600)), None, None).await.unwrap();
+
+        assert_eq!(copied_bytes, 8400);
+        assert_eq!(started.elapsed().as_millis(), 2000);
+
+        let mut got = vec![0; 8400];
+        ppp.read_exact(&mut got).await.unwrap();
+    }
+
+    // Keeps each write it gets separately, so a test can see how MAME's bytes were bunched up.
+    #[derive(Default)]
+    struct Writes(Vec<Vec<u8>>);
+
+    impl AsyncWrite for Writes {
+        fn poll_write(mut self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+            self.0.push(buf.to_vec());
+
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    // A thousand bytes, one per write, the way MAME's bitbanger hands them over.
+    async fn drip(coalesce: Option<Coalesce>, every: Duration) -> Writes {
+        let (mut from_mame, mut mame) = tokio::io::duplex(BUFFER_SIZE);
+        let mut to_ppp = Writes::default();
+
+        tokio::spawn(async move {
+            for i in 0..1000_u32 {
+                mame.write_all(&[i as u8]).await.unwrap();
+                tokio::time::sleep(every).await;
+            }
+        });
+
+        let copied = AtomicU64::new(0);
+        let copied_bytes = copy_loop("MAME->PPP", &mut from_mame, &mut to_ppp, &copied, CancellationToken::new(), None, coalesce, None).await.unwrap();
+
+        assert_eq!(copied_bytes, 1000);
+        assert_eq!(to_ppp.0.concat(), (0..1000_u32).map(|i| i as u8).collect::<Vec<u8>>());
+
+        to_ppp
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalescing_sends_fewer_bigger_writes() {
+        let coalesce = Some(Coalesce { bytes: 256, wait: Duration::from_millis(5) });
+
+        assert_eq!(drip(None, Duration::from_millis(1)).await.0.len(), 1000);
+
+        // Fast enough that the size fills first.
+        let writes = drip(coalesce, Duration::ZERO).await;
+        assert!(writes.0.len() <= 5, "{} writes", writes.0.len());
+        assert!(writes.0.iter().all(|write| write.len() <= 256));
+
+        // Slow enough that the wait runs out first, about every fifth byte.
+        let writes = drip(coalesce, Duration::from_millis(1)).await;
+        assert!(writes.0.len() <= 250, "{} writes", writes.0.len());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalescing_holds_bytes_no_longer_than_the_wait() {
+        let (mut from_mame, mut mame) = tokio::io::duplex(BUFFER_SIZE);
+        let (mut to_ppp, mut ppp) = tokio::io::duplex(BUFFER_SIZE);
+
+        let copied = AtomicU64::new(0);
+        let copying = tokio::spawn(async move {
+            copy_loop("MAME->PPP", &mut from_mame, &mut to_ppp, &copied, CancellationToken::new(), None, Some(Coalesce { bytes: 256, wait: Duration::from_millis(5) }), None).await
+        });
+
+        let started = Instant::now();
+        mame.write_all(b"~").await.unwrap();
+
+        let mut got = [0; 1];
+        ppp.read_exact(&mut got).await.unwrap();
+        assert_eq!(&got, b"~");
+        assert_eq!(started.elapsed().as_millis(), 5);
+
+        drop(mame);
+        assert_eq!(copying.await.unwrap().unwrap(), 1);
+    }
+}
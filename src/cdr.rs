@@ -0,0 +1,283 @@
+// --cdr-file: a call detail record for every session once it's over, as one JSON object, for billing-style
+// tooling rather than people. A file gets a line per session; a directory gets a file per session, each one only
+// showing up once it's whole. Records are written on a thread of their own, so a slow disk never holds up a call.
+//
+//   {"v":1,"session":1,"started_ms":1700000000000,"ended_ms":1700000061234,"duration_ms":61234,
+//    "client":"127.0.0.1:51234","caller":"webtv-os","modem":"default","number":"18006138199","backend":"default",
+//    "endpoint":"127.0.0.1:2323","outcome":"connected","connect_speed":115200,"carrier_speed":33600,
+//    "bytes_up":1234,"bytes_down":56789,"reason":"MAME hung up",
+//    "ip":{"client_ip":"10.0.0.2","server_ip":"10.0.0.1","dns":["10.0.0.1"]}}
+//
+// Fields only ever get added; anything that changes what one that's already there means bumps v.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::at::Caller;
+use crate::stats::{self, NegotiatedIp, Session, Summary};
+
+/// The schema version every record carries as `v`.
+pub const VERSION: u32 = 1;
+
+/// One session, start to end.
+#[derive(Serialize, Debug)]
+pub struct Record {
+    pub v: u32,
+    pub session: u64,
+    // Milliseconds since the epoch.
+    pub started_ms: u64,
+    pub ended_ms: u64,
+    pub duration_ms: u64,
+    pub client: String,
+    // webtv-os, windows-ce or generic, going by its init string, if it sent one.
+    pub caller: Option<Caller>,
+    pub modem: String,
+    // The latest dial, if there was one, and the remote PPP server it went to if it connected to one.
+    pub number: Option<String>,
+    pub backend: Option<String>,
+    pub endpoint: Option<String>,
+    // connected, failed, or none if MAME never dialed.
+    pub outcome: &'static str,
+    pub connect_speed: Option<u32>,
+    pub carrier_speed: Option<u32>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub reason: String,
+    // Only if PPP got as far as IPCP.
+    pub ip: Option<NegotiatedIp>,
+}
+
+impl Record {
+    /// The record for `session`, which has just ended the way `summary` says.
+    pub fn new(session: &Session, summary: &Summary) -> Record {
+        let ended_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0);
+
+        let outcome = match session.dial.lock().unwrap().as_ref() {
+            Some(dial) if dial.outcome == stats::CONNECTED => "connected",
+            Some(_) => "failed",
+            None => "none",
+        };
+
+        Record {
+            v: VERSION,
+            session: summary.session,
+            started_ms: ended_ms.saturating_sub(summary.duration_ms),
+            ended_ms,
+            duration_ms: summary.duration_ms,
+            client: summary.client.clone(),
+            caller: summary.caller,
+            modem: session.modem.clone(),
+            number: summary.number.clone(),
+            backend: summary.backend.clone(),
+            endpoint: session.endpoint.lock().unwrap().clone(),
+            outcome,
+            connect_speed: summary.connect_speed,
+            carrier_speed: summary.carrier_speed,
+            bytes_up: summary.bytes_up,
+            bytes_down: summary.bytes_down,
+            reason: summary.reason.clone(),
+            ip: summary.ip.clone(),
+        }
+    }
+}
+
+enum Target {
+    // Appended to, a line at a time.
+    File(File),
+    // A file per record.
+    Directory(PathBuf),
+}
+
+struct Writer {
+    records: mpsc::Sender<Record>,
+    thread: JoinHandle<()>,
+}
+
+pub struct CdrLog {
+    target: Target,
+    // Started with the first record rather than when we open, since --daemon forks in between and a thread
+    // doesn't make it across.
+    writer: Mutex<Option<Writer>>,
+}
+
+impl CdrLog {
+    /// A directory that's already there gets a file per record, anything else is a file to append to, made if it
+    /// isn't there.
+    pub fn open(path: &str) -> Result<CdrLog, String> {
+        let target = match Path::new(path).is_dir() {
+            true => Target::Directory(PathBuf::from(path)),
+            false => Target::File(OpenOptions::new().create(true).append(true).open(path).map_err(|e| format!("can't open CDR file '{path}': {e}"))?),
+        };
+
+        Ok(CdrLog {
+            target,
+            writer: Mutex::new(None),
+        })
+    }
+
+    /// Hands `record` to the writer thread. Never waits on the disk.
+    pub fn write(&self, record: Record) {
+        let mut writer = self.writer.lock().unwrap();
+
+        if writer.is_none() {
+            match self.start() {
+                Ok(started) => *writer = Some(started),
+                Err(e) => {
+                    warn!("Can't start writing CDRs, so session {}'s is lost: error={e}", record.session);
+                    return;
+                },
+            }
+        }
+
+        if let Some(writer) = writer.as_ref() {
+            let session = record.session;
+
+            if writer.records.send(record).is_err() {
+                warn!("The CDR writer is gone, so session {session}'s is lost.");
+            }
+        }
+    }
+
+    /// Waits for every record so far to be written, for just before we exit.
+    pub fn finish(&self) {
+        let Some(writer) = self.writer.lock().unwrap().take() else {
+            return;
+        };
+
+        drop(writer.records);
+
+        if writer.thread.join().is_err() {
+            warn!("The CDR writer panicked, so some CDRs may be lost.");
+        }
+    }
+
+    fn start(&self) -> std::io::Result<Writer> {
+        let target = match &self.target {
+            Target::File(file) => Target::File(file.try_clone()?),
+            Target::Directory(dir) => Target::Directory(dir.clone()),
+        };
+
+        let (records, receiver) = mpsc::channel::<Record>();
+
+        let thread = std::thread::Builder::new().name("cdr".to_string()).spawn(move || {
+            for record in receiver {
+                if let Err(e) = write_record(&target, &record) {
+                    warn!("Can't write session {}'s CDR: error={e}", record.session);
+                }
+            }
+        })?;
+
+        Ok(Writer { records, thread })
+    }
+}
+
+// One write for the whole line, so lines from another process appending to the same file never end up
+// interleaved. In a directory, the record's written under a name nobody's looking for and renamed into place.
+fn write_record(target: &Target, record: &Record) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    match target {
+        Target::File(file) => (&*file).write_all(&line),
+        Target::Directory(dir) => {
+            let name = format!("{}-session-{}.json", record.started_ms, record.session);
+            let partial = dir.join(format!(".{name}.partial"));
+
+            let mut file = File::create(&partial)?;
+            file.write_all(&line)?;
+            file.sync_data()?;
+
+            std::fs::rename(&partial, dir.join(name))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("touchppp-cdr-{}-{name}", std::process::id()))
+    }
+
+    fn record(session: u64) -> Record {
+        Record {
+            v: VERSION,
+            session,
+            started_ms: 1_700_000_000_000,
+            ended_ms: 1_700_000_061_234,
+            duration_ms: 61_234,
+            client: "127.0.0.1:51234".to_string(),
+            caller: Some(Caller::WebtvOs),
+            modem: "default".to_string(),
+            number: Some("18006138199".to_string()),
+            backend: Some("default".to_string()),
+            endpoint: None,
+            outcome: "connected",
+            connect_speed: Some(115200),
+            carrier_speed: Some(33600),
+            bytes_up: 1234,
+            bytes_down: 56789,
+            reason: "MAME hung up".to_string(),
+            ip: None,
+        }
+    }
+
+    #[test]
+    fn a_file_gets_a_line_per_record() {
+        let path = scratch("cdrs.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let cdrs = CdrLog::open(path.to_str().unwrap()).unwrap();
+        cdrs.write(record(1));
+        cdrs.write(record(2));
+        cdrs.finish();
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<Value> = lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1]["session"], 2);
+        assert_eq!(records[0]["v"], VERSION);
+        assert_eq!(records[0]["caller"], "webtv-os");
+        assert_eq!(records[0]["endpoint"], Value::Null);
+        assert_eq!(records[0]["ip"], Value::Null);
+    }
+
+    #[test]
+    fn a_directory_gets_a_file_per_record_and_nothing_half_written() {
+        let dir = scratch("cdrs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let cdrs = CdrLog::open(dir.to_str().unwrap()).unwrap();
+        cdrs.write(record(1));
+        cdrs.write(record(2));
+        cdrs.finish();
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+
+        let first: Value = serde_json::from_str(&std::fs::read_to_string(dir.join(&names[0])).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(names, ["1700000000000-session-1.json", "1700000000000-session-2.json"]);
+        assert_eq!(first["session"], 1);
+    }
+
+    #[test]
+    fn a_file_that_cant_be_opened_is_caught() {
+        let e = CdrLog::open("/nonexistent/dir/cdrs.jsonl").err().unwrap();
+
+        assert!(e.contains("can't open CDR file '/nonexistent/dir/cdrs.jsonl'"), "{e}");
+    }
+}
@@ -0,0 +1,206 @@
+// Startup validation, shared by Server::bind and `touchppp --check`.
+
+use std::net::ToSocketAddrs;
+use std::path::Path;
+
+use crate::config::{BackendKind, Config};
+use crate::StartError;
+
+// Finds the program a command line starts with the same way spawning it would: as a path if it has a
+// separator in it, otherwise somewhere on PATH.
+fn find_program(command: &str) -> bool {
+    let Some(program) = command.split(' ').next().filter(|program| !program.is_empty()) else {
+        return false;
+    };
+
+    let is_runnable = |path: &Path| {
+        path.is_file() || (cfg!(windows) && path.extension().is_none() && path.with_extension("exe").is_file())
+    };
+
+    if program.contains(std::path::MAIN_SEPARATOR) || program.contains('/') {
+        return is_runnable(Path::new(program));
+    }
+
+    match std::env::var_os("PATH") {
+        Some(paths) => std::env::split_paths(&paths).any(|dir| is_runnable(&dir.join(program))),
+        None => false,
+    }
+}
+
+// Catches typos in host names now rather than when the WebTV dials.
+fn resolve_problems(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if config.listen_pipe.is_none() {
+        if let Err(e) = config.listen_address.target().to_socket_addrs() {
+            problems.push(format!("can't resolve listen address '{}': {e}", config.listen_address));
+        }
+    }
+
+    for backend in config.reachable_backends() {
+        let BackendKind::Remote(remote_ppp) = &backend.kind else {
+            continue;
+        };
+
+        for remote_socket_address in remote_ppp.socket_addresses.iter() {
+            if let Err(e) = remote_ppp.resolver.lookup_blocking(remote_socket_address) {
+                problems.push(format!("can't resolve '{remote_socket_address}' for backend {}: {e}", backend.name));
+            }
+        }
+    }
+
+    problems
+}
+
+// Everything wrong with the config that can be found without taking a call. Starting up only looks at what's
+// sure to stop us (and host names with --resolve-at-start); a thorough check also looks for the programs we'd
+// run, the log file's directory and whether the listen address is free.
+pub fn problems(config: &Config, thorough: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if thorough || config.resolve_at_start {
+        problems.extend(resolve_problems(config));
+    }
+
+    #[cfg(feature = "nat")]
+    if config.link_protocol == crate::config::LinkProtocol::Slip {
+        for backend in config.reachable_backends() {
+            if let BackendKind::Nat(_) = &backend.kind {
+                problems.push(format!("backend {} is the built-in nat, which only speaks PPP, not --link-protocol slip", backend.name));
+            }
+        }
+    }
+
+    // SLIP has no framing worth spotting, so there'd be nothing for it to go on.
+    if config.auto_data.is_some() && config.link_protocol == crate::config::LinkProtocol::Slip {
+        problems.push("--auto-data only spots PPP, not --link-protocol slip".to_string());
+    }
+
+    // The backend's modem is the one watching for PPP then.
+    if config.auto_data.is_some() && config.at_passthrough {
+        problems.push("--auto-data doesn't do anything with --at-passthrough".to_string());
+    }
+
+    // Creating the device is the only way to know we're allowed to.
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    for backend in config.reachable_backends() {
+        if let BackendKind::Tun(tun) = &backend.kind {
+            if let Err(e) = tun.check_access() {
+                problems.push(e);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(pid_file) = &config.pid_file {
+        if let Err(e) = crate::daemon::check_pid_file(pid_file) {
+            problems.push(e);
+        }
+    }
+
+    if !thorough {
+        return problems;
+    }
+
+    for backend in config.reachable_backends() {
+        if let BackendKind::Exec(local_ppp) = &backend.kind {
+            if !find_program(&local_ppp.command) {
+                problems.push(format!("can't find the program to run for backend {}", backend.describe()));
+            }
+        }
+    }
+
+    if let Some(command) = &config.launch_mame {
+        if !find_program(command) {
+            problems.push(format!("can't find the program to run for --launch-mame '{command}'"));
+        }
+    }
+
+    if let Some(log_file) = &config.log_file {
+        let directory = Path::new(log_file).parent().filter(|directory| !directory.as_os_str().is_empty());
+
+        if directory.is_some_and(|directory| !directory.is_dir()) {
+            problems.push(format!("the directory for log file '{log_file}' doesn't exist"));
+        }
+    }
+
+    if let Some(dir) = &config.at_transcript {
+        if !Path::new(dir).is_dir() {
+            problems.push(format!("the directory for AT transcripts '{dir}' doesn't exist"));
+        }
+    }
+
+    // With socket activation systemd holds the port, so it's sure to look taken.
+    if config.listen_pipe.is_none() && std::env::var_os("LISTEN_FDS").is_none() {
+        if let Err(e) = std::net::TcpListener::bind(config.listen_address.target()) {
+            problems.push(format!("can't listen on {}: {e}", config.listen_address));
+        }
+    }
+
+    problems
+}
+
+// `touchppp --check`: load everything, say what we'd do and whether anything's wrong, then exit without serving.
+#[tokio::main]
+pub async fn run(params: &clap::ArgMatches) -> Result<(), StartError> {
+    let configs = Config::load_all(params).map_err(|e| StartError::Runtime(format!("bad config: {e}").into()))?;
+
+    let mut problems = Vec::new();
+
+    for config in configs.iter() {
+        if let Some(modem) = &config.modem {
+            println!("Modem {modem}:");
+        }
+
+        match &config.listen_pipe {
+            Some(pipe_name) => println!("Would listen on {pipe_name}."),
+            None if std::env::var_os("LISTEN_FDS").is_some() => println!("Would listen on the sockets systemd passes in."),
+            None => println!("Would listen on {}.", config.listen_address),
+        }
+
+        match &config.cli_backend {
+            Some(backend) => println!("Every dial goes to backend {}.", backend.describe()),
+            None => {
+                println!("Dials go to backend {} unless the phone book says otherwise.", config.default_backend.describe());
+                println!("Phone book entries: {}, named backends: {}.", config.phone_book.len(), config.backends.len());
+            },
+        }
+
+        if let Some(command) = &config.launch_mame {
+            println!("Would launch MAME with '{command}'.");
+        }
+
+        // A PPP server that's down right now is worth knowing about but doesn't make the config wrong.
+        if config.health_check {
+            for backend in config.reachable_backends() {
+                let BackendKind::Remote(remote_ppp) = &backend.kind else {
+                    continue;
+                };
+
+                match crate::backend::probe_remote(remote_ppp).await {
+                    Ok(answered_socket_address) => println!("PPP @ {answered_socket_address} is answering for backend {}.", backend.name),
+                    Err(e) => println!("Warning: couldn't touch PPP for backend {}: {e}", backend.describe()),
+                }
+            }
+        }
+
+        problems.extend(self::problems(config, true).into_iter().map(|problem| match &config.modem {
+            Some(modem) => format!("[[modem]] '{modem}': {problem}"),
+            None => problem,
+        }));
+    }
+
+    for problem in problems.iter() {
+        println!("Problem: {problem}");
+    }
+
+    match problems.len() {
+        0 => {
+            println!("Config is OK.");
+
+            Ok(())
+        },
+        1 => Err(StartError::Runtime("the config has a problem".into())),
+        count => Err(StartError::Runtime(format!("the config has {count} problems").into())),
+    }
+}
@@ -0,0 +1,820 @@
+use std::path::PathBuf;
+use clap::{ArgAction, Args, Parser, Subcommand};
+use clap_complete::Shell;
+
+use crate::address;
+use crate::at::{Profile, Protocol};
+use crate::bench;
+use crate::config::{self, AutoData, Builtin, CarrierDrop, CarrierSpeed, Color, LinkProtocol, LogFormat, MameSlot};
+use crate::logfile;
+#[cfg(feature = "nat")]
+use crate::nat::{self, NatPool};
+use crate::preset;
+use crate::server;
+use crate::soak;
+
+const DESCRIPTION: &str = concat!(
+    "WebTV Touch PPP v1.0.0: ",
+    "Provides a way for the WebTV MAME driver to talk with PPP using its null modem.",
+);
+
+const AFTER_HELP: &str = concat!(
+    "Every serve option can also be set with a TOUCHPPP_ environment variable named after its long name ",
+    "(--connect-timeout is TOUCHPPP_CONNECT_TIMEOUT). Flags take 1/true/yes or 0/false/no and lists like --connect ",
+    "are comma separated. The command line beats the environment, which beats the config file. The tcpser options only work ",
+    "on the command line.\n",
+    "\n",
+    "Examples:\n",
+    "  touchppp -l 1122 -c 127.0.0.1:2323\n",
+    "  touchppp -e '/usr/sbin/pppd notty' --launch-mame 'mame wtv1sony -window'\n",
+    "  touchppp --check --config /etc/touchppp.toml\n",
+    "  touchppp dial 5551212\n",
+    "  touchppp test\n",
+    "\n",
+    "Special thanks to: Zefie, MattMan, and others in the WebTV hacking community!",
+);
+
+pub const DEFAULT_DIAL_TO: &str = "127.0.0.1:1122";
+
+// The options kept so tcpser command lines from guides work as-is.
+const TCPSER_HEADING: &str = "tcpser compatibility";
+
+#[derive(Parser)]
+#[command(name = "touchppp", about = DESCRIPTION, after_help = AFTER_HELP, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // So the plain `touchppp -l 1122` from before subcommands still means serve.
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Answer MAME's calls and touch some PPP. This is what happens without a subcommand.
+    #[command(after_help = AFTER_HELP)]
+    Serve(Box<ServeArgs>),
+    /// Send the AT commands in a file to a running TouchPPP, one per line, and print what comes back.
+    Replay(ReplayArgs),
+    /// Call a running TouchPPP like a WebTV would and report whether it connects.
+    Dial(DialArgs),
+    /// Check that this build works by making a call to itself, with a built-in echo standing in for PPP. Needs no MAME or PPP server.
+    Test,
+    /// Time calls to an in-process TouchPPP (commands, and data through the built-in echo) and print a table of throughput and round trip times.
+    Bench(BenchArgs),
+    /// Make one long call to an in-process TouchPPP with a known byte sequence going each way, check every byte that comes out the other end, and fail with a report if any were changed, lost or made up.
+    Soak(SoakArgs),
+    /// Print a shell completion script.
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Run TouchPPP as a Windows service.
+    #[cfg_attr(not(windows), command(hide = true))]
+    Service {
+        #[command(subcommand)]
+        action: crate::service::ServiceCommand,
+    },
+}
+
+// -l can be [HOST:]PORT or pipe:\\.\pipe\NAME. The value is kept as given, Config does the real parsing.
+fn listen_value(value: &str) -> Result<String, String> {
+    if value.starts_with(address::PIPE_PREFIX) {
+        address::parse_pipe(value).map_err(|e| e.to_string())?;
+    } else {
+        address::parse_listen(value).map_err(|e| e.to_string())?;
+    }
+
+    Ok(value.to_string())
+}
+
+fn remote_value(value: &str) -> Result<String, String> {
+    address::parse_remote(value).map_err(|e| e.to_string())?;
+
+    Ok(value.to_string())
+}
+
+#[cfg(feature = "nat")]
+fn nat_dns_value(value: &str) -> Result<String, String> {
+    nat::parse_dns(value)?;
+
+    Ok(value.to_string())
+}
+
+fn touchppp_value(value: &str) -> Result<String, String> {
+    address::parse_touchppp(value).map_err(|e| e.to_string())?;
+
+    Ok(value.to_string())
+}
+
+fn admin_value(value: &str) -> Result<String, String> {
+    address::parse_admin(value).map_err(|e| e.to_string())?;
+
+    Ok(value.to_string())
+}
+
+fn syslog_value(value: &str) -> Result<String, String> {
+    address::parse_syslog(value).map_err(|e| e.to_string())?;
+
+    Ok(value.to_string())
+}
+
+fn log_filter_value(value: &str) -> Result<String, String> {
+    server::log_filter("info", value)?;
+
+    Ok(value.to_string())
+}
+
+fn size_value(value: &str) -> Result<String, String> {
+    logfile::parse_size(value)?;
+
+    Ok(value.to_string())
+}
+
+// Digits plus the usual dial modifiers. Spaces and dashes are allowed so a number can be pasted in.
+fn modem_profile_value(value: &str) -> Result<String, String> {
+    preset::find(value)?;
+
+    Ok(value.to_string())
+}
+
+fn number_value(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err("the number is empty".to_string());
+    }
+
+    match value.chars().find(|c| !(c.is_ascii_digit() || "*#,WwPpTt!@ -".contains(*c))) {
+        Some(c) => Err(format!("'{c}' can't be dialed")),
+        None => Ok(value.to_string()),
+    }
+}
+
+// tcpser's trace flags. They all turn on the same byte logging here.
+fn trace_flags_value(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err("give at least one of s, S, i or I".to_string());
+    }
+
+    match value.chars().find(|c| !"sSiI".contains(*c)) {
+        Some(c) => Err(format!("'{c}' isn't a trace flag, use s, S, i or I")),
+        None => Ok(value.to_string()),
+    }
+}
+
+fn command_delay_value(value: &str) -> Result<String, String> {
+    config::parse_command_delay(value)?;
+
+    Ok(value.to_string())
+}
+
+fn phone_book_value(value: &str) -> Result<String, String> {
+    let (number, remote) = value.split_once('=').ok_or("use NUMBER=HOST:PORT")?;
+
+    number_value(number)?;
+    remote_value(remote)?;
+
+    Ok(value.to_string())
+}
+
+// Setting ids are the long names with - turned into _, which is how Config looks them up.
+#[derive(Args)]
+pub struct ServeArgs {
+    /// The socket address to listen on. This defaults to 127.0.0.1:1122. 127.0.0.1 is used as the IP if just the port is given. On Windows, pipe:\\.\pipe\NAME listens on a named pipe instead.
+    ///
+    /// Example: -l 6400
+    #[arg(short = 'l', long, value_name = "[HOST:]PORT", value_parser = listen_value)]
+    pub listen: Option<String>,
+
+    /// The remote server that provides PPP communication. This defaults to 127.0.0.1:2323, and a host with no port gets 2323. Can be given more than once; servers are tried in order until one answers. Overrides the config file's phone book and default backend.
+    ///
+    /// Example: -c ppp.cool.com:2323 -c backup.cool.com:2323
+    #[arg(short = 'c', long, value_name = "HOST[:PORT]", value_parser = remote_value)]
+    pub connect: Vec<String>,
+
+    /// TOML config file with settings, named backends ([backend.NAME] tables with connect or exec) and a [phonebook] mapping dialed numbers to backends ("1800*" = "NAME"). Command line options win over the file.
+    ///
+    /// Example: --config touchppp.toml
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Name of the config file backend to use when the dialed number isn't in the phone book.
+    ///
+    /// Example: --backend openisp
+    #[arg(long, value_name = "NAME")]
+    pub backend: Option<String>,
+
+    /// How long to wait for each remote PPP server to answer. This defaults to 10 seconds.
+    ///
+    /// Example: --connect-timeout 5
+    #[arg(long, value_name = "SECONDS")]
+    pub connect_timeout: Option<u64>,
+
+    /// How many more times to try each remote PPP server before moving on to the next one. Retries are per server, waiting half a second and doubling between attempts. This defaults to 0.
+    ///
+    /// Example: --connect-retries 2
+    #[arg(long, value_name = "COUNT")]
+    pub connect_retries: Option<u32>,
+
+    /// Try the last remote PPP server that worked first on the next dial. It's forgotten as soon as it fails.
+    #[arg(long)]
+    pub remote_sticky: bool,
+
+    /// Let a remote PPP server take one call at a time: a dial while another session's online with it gets BUSY without it hearing about it.
+    #[arg(long)]
+    pub remote_exclusive: bool,
+
+    /// Let an -e backend take more than one call at a time. Without it, a dial while another session's online with it gets BUSY, since most pppd setups only have the one unit to give out.
+    #[arg(long)]
+    pub exec_concurrent: bool,
+
+    /// Reach remote PPP servers (-c, or connect in the config file) through this SSH server instead of connecting to them straight. It's logged in to on the first dial, with --ssh-key or whatever the SSH agent has, and has to be in ~/.ssh/known_hosts.
+    ///
+    /// Example: --remote-ssh ppp@gateway.example.com:2222
+    #[cfg(all(unix, feature = "ssh"))]
+    #[arg(long, value_name = "USER@HOST[:PORT]")]
+    pub remote_ssh: Option<String>,
+
+    /// The private key to log in to --remote-ssh with, instead of the SSH agent.
+    ///
+    /// Example: --ssh-key ~/.ssh/id_ed25519
+    #[cfg(all(unix, feature = "ssh"))]
+    #[arg(long, value_name = "FILE")]
+    pub ssh_key: Option<String>,
+
+    /// Don't check --remote-ssh's host key against ~/.ssh/known_hosts. Anyone in the middle could see the calls, so only use it for testing.
+    #[cfg(all(unix, feature = "ssh"))]
+    #[arg(long)]
+    pub ssh_insecure: bool,
+
+    /// Stay logged in to --remote-ssh between dials instead of logging in again for each one.
+    #[cfg(all(unix, feature = "ssh"))]
+    #[arg(long)]
+    pub ssh_persist: bool,
+
+    /// Gather up to this many of MAME's bytes before sending them to a remote PPP server, instead of a packet for every byte. 0 turns it off. This defaults to 256, and doesn't apply to -e.
+    ///
+    /// Example: --coalesce-bytes 512
+    #[arg(long, value_name = "BYTES")]
+    pub coalesce_bytes: Option<usize>,
+
+    /// The longest MAME's bytes are held back for --coalesce-bytes, counting from the first one. 0 turns it off. This defaults to 5 and can be up to 1000.
+    ///
+    /// Example: --coalesce-ms 10
+    #[arg(long, value_name = "MS")]
+    pub coalesce_ms: Option<u64>,
+
+    /// Send every byte on the moment it shows up, for interactive use like telnet over PPP: sets TCP_NODELAY on MAME's connection and the remote PPP server's, and turns --coalesce-bytes off.
+    #[arg(long)]
+    pub low_latency: bool,
+
+    /// Ask the kernel for a receive buffer this big on MAME's connection and the remote PPP server's, for a far away server on a fast link. The kernel may round it. Named pipes don't have one.
+    ///
+    /// Example: --so-rcvbuf 262144
+    #[arg(long, value_name = "BYTES")]
+    pub so_rcvbuf: Option<usize>,
+
+    /// Ask the kernel for a send buffer this big on MAME's connection and the remote PPP server's. The kernel may round it. Named pipes don't have one.
+    ///
+    /// Example: --so-sndbuf 262144
+    #[arg(long, value_name = "BYTES")]
+    pub so_sndbuf: Option<usize>,
+
+    /// Send everything for remote PPP servers (-c, --remote-udp, --remote-ssh and the config file's backends) out over this network interface, whatever the routing tables say, like a VLAN just for them. Linux only, and some kernels want root or CAP_NET_RAW for it. Webhooks and syslog go the usual way.
+    ///
+    /// Example: --bind-device eth0.20
+    #[arg(long, value_name = "IFNAME")]
+    pub bind_device: Option<String>,
+
+    /// Look NAME up as IP instead of asking DNS, for remote PPP servers (-c, --remote-udp and the config file's backends). Can be given more than once. Handy on a LAN with no name server of its own.
+    ///
+    /// Example: --resolve wtv-ppp.lan=192.168.1.20
+    #[arg(long, value_name = "NAME=IP")]
+    pub resolve: Vec<String>,
+
+    /// Look remote PPP servers' names up with this name server instead of the system's resolver. --resolve still comes first.
+    ///
+    /// Example: --dns 192.168.1.1
+    #[arg(long, value_name = "IP[:PORT]")]
+    pub dns: Option<String>,
+
+    /// PPP command to run for direct PPP communication. Overrides the config file's phone book and default backend.
+    ///
+    /// Example: -e '/usr/sbin/pppd notty'
+    #[arg(short = 'e', long, value_name = "'/path/to/exe exe_options'")]
+    pub exec: Option<String>,
+
+    /// A remote PPP server that takes PPP a frame per UDP datagram instead of as a TCP stream, and sends it back the same way. A host with no port gets 2323. Frames too long to be real are dropped, and the call's dropped if the server says it isn't there. Overrides the config file's phone book and default backend.
+    ///
+    /// Example: --remote-udp ppp.cool.com:2323
+    #[arg(long, value_name = "HOST[:PORT]", value_parser = remote_value)]
+    pub remote_udp: Option<String>,
+
+    /// Answer every call with something built in instead of a PPP server: echo sends back whatever MAME sends, null takes it and never answers, and nat answers PPP itself and gets the box online through this machine, with no pppd or root needed. Builds with the tun feature add tun, which answers PPP itself onto a TUN device for this machine to route (Linux, with root or CAP_NET_ADMIN). Echo and null are handy for checking MAME and the null modem are plumbed in right, or for demos. Overrides the config file's phone book and default backend, and -c or -e given the same way.
+    ///
+    /// Example: --backend-builtin nat
+    #[arg(long, value_name = "echo|null|nat")]
+    pub backend_builtin: Option<Builtin>,
+
+    /// The network --backend-builtin nat hands out addresses from. The first address is the gateway, which is also the box's DNS server and stands in for this machine (so 10.0.2.1:8080 is port 8080 here); each call gets one of the rest. This defaults to 10.0.2.0/24, same as slirp.
+    ///
+    /// Example: --nat-pool 192.168.7.0/24
+    #[cfg(feature = "nat")]
+    #[arg(long, value_name = "CIDR")]
+    pub nat_pool: Option<NatPool>,
+
+    /// Where --backend-builtin nat sends the box's DNS lookups, as IP[:PORT]. Can be given more than once, though only the first is used for now. This defaults to the IPv4 name servers in /etc/resolv.conf.
+    ///
+    /// Example: --nat-dns 1.1.1.1
+    #[cfg(feature = "nat")]
+    #[arg(long, value_name = "IP[:PORT]", value_parser = nat_dns_value)]
+    pub nat_dns: Vec<String>,
+
+    /// What to call the TUN device --backend-builtin tun creates for each call. It's gone again once the call's over.
+    ///
+    /// Example: --tun-name webtv0
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    #[arg(long, value_name = "NAME")]
+    pub tun_name: Option<String>,
+
+    /// Our end of --backend-builtin tun's link, which the box routes everything through. This defaults to 10.0.3.1.
+    ///
+    /// Example: --tun-local 192.168.8.1
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    #[arg(long, value_name = "IP")]
+    pub tun_local: Option<std::net::Ipv4Addr>,
+
+    /// The address --backend-builtin tun gives the box. Routing and NAT for it are up to this machine's firewall. This defaults to 10.0.3.2.
+    ///
+    /// Example: --tun-peer 192.168.8.2
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    #[arg(long, value_name = "IP")]
+    pub tun_peer: Option<std::net::Ipv4Addr>,
+
+    /// A name server --backend-builtin tun tells the box about. Can be given twice, for its primary and secondary. This defaults to the IPv4 name servers in /etc/resolv.conf that aren't loopback.
+    ///
+    /// Example: --tun-dns 1.1.1.1 --tun-dns 9.9.9.9
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    #[arg(long, value_name = "IP")]
+    pub tun_dns: Vec<std::net::Ipv4Addr>,
+
+    /// The DTE rate the final CONNECT line reports. It has to be one a result code exists for, like 57600 or 115200. This defaults to 115200.
+    ///
+    /// Example: --connect-speed 57600
+    #[arg(long, value_name = "BPS")]
+    pub connect_speed: Option<u32>,
+
+    /// The CARRIER speed reported before CONNECT: auto (33600, what TouchPPP has always said, or less if the init string holds the box to a slower carrier with +MS) or a speed a result code exists for, from 300 up to 33600 or a 56k rate like 50000.
+    ///
+    /// Example: --carrier-speed 31200
+    #[arg(long, value_name = "auto|BPS")]
+    pub carrier_speed: Option<CarrierSpeed>,
+
+    /// Leave the COMPRESSION (and PROTOCOL) line out of what's reported on connect, for firmware that shows these lines as-is.
+    #[arg(long)]
+    pub suppress_intermediates: bool,
+
+    /// Report a PROTOCOL line on connect, before COMPRESSION. There's none unless this is given or an AT command turns error correction off with \N0, which reports none.
+    ///
+    /// Example: --protocol-line lapm
+    #[arg(long, value_name = "lapm|none")]
+    pub protocol_line: Option<Protocol>,
+
+    /// Hold data mode to this many bits per second each way, instead of going as fast as the network allows. Short for --throttle-down and --throttle-up together; either given on its own wins for its way.
+    ///
+    /// Example: --throttle 28800
+    #[arg(long, value_name = "BPS")]
+    pub throttle: Option<u32>,
+
+    /// Hold what goes to the box in data mode to this many bits per second.
+    ///
+    /// Example: --throttle-down 56000
+    #[arg(long, value_name = "BPS")]
+    pub throttle_down: Option<u32>,
+
+    /// Hold what the box sends in data mode to this many bits per second, for a lopsided link or a crippled upload.
+    ///
+    /// Example: --throttle-up 33600
+    #[arg(long, value_name = "BPS")]
+    pub throttle_up: Option<u32>,
+
+    /// Hold data mode to the carrier speed reported on connect instead of going as fast as the network allows. A 56k carrier only goes that fast to the box, with the box's end held to 33600 like a real V.90 call. --throttle, --throttle-down and --throttle-up win for whichever way they cover.
+    #[arg(long)]
+    pub throttle_to_carrier: bool,
+
+    /// Send a THROUGHPUT line with the call's average and peak rates up and down, in bits per second, ahead of NO CARRIER. Off by default since it isn't a result code and a strict parser may choke on it.
+    #[arg(long)]
+    pub report_throughput: bool,
+
+    /// Answer DELAYED instead of dialing a number that's failed this many times in a row (within --delay-window), until --delay-cooldown is up. Off unless given.
+    ///
+    /// Example: --delay-after 3
+    #[arg(long, value_name = "COUNT")]
+    pub delay_after: Option<u32>,
+
+    /// How close together failed dials have to be to count as in a row for --delay-after. This defaults to 600 seconds.
+    ///
+    /// Example: --delay-window 120
+    #[arg(long, value_name = "SECONDS")]
+    pub delay_window: Option<u64>,
+
+    /// How long a number stays DELAYED once --delay-after kicks in. This defaults to 300 seconds.
+    ///
+    /// Example: --delay-cooldown 60
+    #[arg(long, value_name = "SECONDS")]
+    pub delay_cooldown: Option<u64>,
+
+    /// Answer BLACKLISTED to a number, or a pattern like 1900*, instead of dialing it. Can be given more than once.
+    ///
+    /// Example: --blacklist 1900* --blacklist 5551212
+    #[arg(long, value_name = "NUMBER", value_parser = number_value)]
+    pub blacklist: Vec<String>,
+
+    /// Report a 56k CARRIER on every dial, whatever the init string, the number, the phone book or --modem-profile say. With --carrier-speed auto, that's 50000.
+    #[arg(long, conflicts_with = "no_56k")]
+    pub force_56k: bool,
+
+    /// Never report a 56k CARRIER, whatever the init string, the number, the phone book or --modem-profile say.
+    #[arg(long)]
+    pub no_56k: bool,
+
+    /// Report 56k to a number, or a pattern like 1800*, even where it'd be left off otherwise: a bootstrap number (1800*), S51=31, the phone book or --modem-profile. --force-56k and --no-56k still win. Can be given more than once.
+    ///
+    /// Example: --56k-numbers 18006138199
+    #[arg(long = "56k-numbers", id = "56k_numbers", value_name = "NUMBER", value_parser = number_value)]
+    pub numbers_56k: Vec<String>,
+
+    /// Never report 56k to a number, or a pattern like 1888*, on top of the bootstrap numbers that never get it. Beats the phone book and --modem-profile, and --56k-numbers beats it. Can be given more than once.
+    ///
+    /// Example: --no-56k-numbers 1888*
+    #[arg(long, value_name = "NUMBER", value_parser = number_value)]
+    pub no_56k_numbers: Vec<String>,
+
+    /// Keep what's learned about each number in this file so it survives a restart, instead of starting over each run: --delay-after's failure counts, which of a --remote-sticky backend's servers last answered it, whether it got 56k and when it last connected.
+    ///
+    /// Example: --state-file /var/lib/touchppp/dials.toml
+    #[arg(long, value_name = "PATH", alias = "persist-dial-state")]
+    pub state_file: Option<String>,
+
+    /// Drop the carrier this many seconds after CONNECT, give or take up to JITTER seconds either way, sending NO CARRIER and hanging up on PPP. The box can dial again afterwards. For testing how the box copes with a line that drops.
+    ///
+    /// Example: --drop-after 60,15
+    #[arg(long, value_name = "SECONDS[,JITTER]")]
+    pub drop_after: Option<CarrierDrop>,
+
+    /// Only drop the carrier this many times for --drop-after before leaving calls alone, for as long as TouchPPP runs.
+    ///
+    /// Example: --drop-count 1
+    #[arg(long, value_name = "COUNT")]
+    pub drop_count: Option<u32>,
+
+    /// The longest AT command line to take, in bytes. Anything longer is thrown away and answered with ERROR, so something that never sends a carriage return can't pile up forever. This defaults to 255.
+    ///
+    /// Example: --max-command-length 1024
+    #[arg(long, value_name = "BYTES")]
+    pub max_command_length: Option<usize>,
+
+    /// How many connections each address gets to make, as COUNT/SECONDS, before more from it are closed straight away. off lets every one through. This defaults to 5/10.
+    ///
+    /// Example: --accept-rate 20/60
+    #[arg(long, value_name = "COUNT/SECONDS")]
+    pub accept_rate: Option<String>,
+
+    /// How many connections can be waiting to send their first AT command at once. Any more are closed straight away. 0 doesn't limit them. This defaults to 16.
+    ///
+    /// Example: --max-pending 4
+    #[arg(long, value_name = "COUNT")]
+    pub max_pending: Option<usize>,
+
+    /// How long to wait before sending each result code, in milliseconds, like a real modem taking its time over a command. For firmware that trips over an answer that comes back the instant it's asked. Data mode isn't held up. This defaults to 0.
+    ///
+    /// Example: --command-delay 30
+    #[arg(long, value_name = "MS")]
+    pub command_delay: Option<u64>,
+
+    /// Wait this long instead of --command-delay to answer a line with this command in it, named the way it's typed after AT: I3, &F, +MS, or D for everything a dial gets back. Can be given more than once; the longest one that matches wins.
+    ///
+    /// Example: --command-delay-for I3=250
+    #[arg(long, value_name = "COMMAND=MS", value_parser = command_delay_value)]
+    pub command_delay_for: Vec<String>,
+
+    /// Which modem callers talk to. webtv (the default) answers the way a WebTV box expects. generic is a plain Hayes modem for DOS and Windows dialers and other emulators: it echoes, follows E, V, Q and X, answers ATI, dials on ATDT and answers ERROR to anything it doesn't know.
+    ///
+    /// Example: --profile generic
+    #[arg(long, value_name = "webtv|generic")]
+    pub profile: Option<Profile>,
+
+    /// Which modem it is: webtv-k56 (the default, what TouchPPP has always been), rockwell-v34 (never 56k), softmodem (56k until the box's S51=31 turns it off) or usr-courier. Decides what ATI and Sn? answer with --profile generic, what the S registers start out as, the CARRIER --carrier-speed auto reports and how CONNECT reads. --carrier-speed, a phone book entry's force_56k and the 56k flags still beat it.
+    ///
+    /// Example: --modem-profile rockwell-v34
+    #[arg(long, value_name = "NAME", value_parser = modem_profile_value)]
+    pub modem_profile: Option<String>,
+
+    /// Have ATZ and AT&F forget the last number dialed, so ATDL gets ERROR until there's another. Without it, the number's kept over a reset like most Hayes modems do.
+    #[arg(long)]
+    pub reset_forgets_number: bool,
+
+    /// What the box speaks once it's online. ppp (the default) or slip, for older firmware and other systems that use SLIP instead. With slip, each packet the box sends is checked and framed up again before it goes to the backend, and the tun backend takes the packets itself; there's nothing to negotiate, so the box needs to know its address (the tun backend's --tun-peer) already. The nat backend only speaks PPP.
+    ///
+    /// Example: --link-protocol slip
+    #[arg(long, value_name = "ppp|slip")]
+    pub link_protocol: Option<LinkProtocol>,
+
+    /// Go online without a dial when PPP shows up in command mode, for firmware and other clients that skip ATD and start sending LCP straight away. The box gets CONNECT first unless it's quiet, and the PPP it's already sent goes to the backend ahead of the rest. Only for --link-protocol ppp.
+    ///
+    /// Example: --auto-data quiet
+    #[arg(long, value_name = "connect|quiet", num_args = 0..=1, default_missing_value = "connect")]
+    pub auto_data: Option<AutoData>,
+
+    /// Connect to the backend as soon as MAME does and let it answer the AT commands, for a backend that's a modem itself (tcpser, or a device server in front of a real one). TouchPPP passes everything through and takes over once the backend says CONNECT. A dial to a number the phone book has a remote backend for is sent on as a dial to that backend's address.
+    #[arg(long)]
+    pub at_passthrough: bool,
+
+    /// Launch MAME once we're listening, with the null modem arguments added on the end. TouchPPP exits when MAME does.
+    ///
+    /// Example: --launch-mame 'mame wtv1sony -window'
+    #[arg(long, value_name = "'/path/to/mame mame_options'")]
+    pub launch_mame: Option<String>,
+
+    /// The slot the null modem goes in for --launch-mame: spot for wtv1 boxes, solo for wtv2 boxes. Defaults to spot.
+    ///
+    /// Example: --mame-slot solo
+    #[arg(long, value_name = "spot|solo")]
+    pub mame_slot: Option<MameSlot>,
+
+    /// Launch MAME again when it exits instead of shutting down.
+    #[arg(long)]
+    pub mame_restart: bool,
+
+    /// Go into the background once we're listening. Needs --log-file. Unix only.
+    ///
+    /// Example: --daemon --log-file /var/log/touchppp.log
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Write our pid to this file and remove it when we exit. Refuses to start if the pid in it is still running.
+    ///
+    /// Example: --pid-file /run/touchppp.pid
+    #[arg(long, value_name = "/path/to/touchppp.pid")]
+    pub pid_file: Option<String>,
+
+    /// Take admin commands on this [HOST:]PORT, or unix socket path if it has a / in it. It's a line protocol: list shows the active sessions, kill ID hangs one up, stats shows the running totals and quit closes the connection. There's no password, so keep it on 127.0.0.1 or a socket only you can get to.
+    ///
+    /// Example: --admin /run/touchppp/admin.sock
+    #[arg(long, value_name = "[HOST:]PORT|PATH", value_parser = admin_value)]
+    pub admin: Option<String>,
+
+    /// Let the admin interface (and the console) inject raw bytes into a call with inject ID mame|backend HEX, for poking at the box's PPP stack or the backend by hand. Anything the admin can reach can then say anything to either end, so only for debugging.
+    #[arg(long)]
+    pub enable_inject: bool,
+
+    /// Serve a status page on this [HOST:]PORT: uptime, what we're listening on, the backend, active sessions with their byte counts and the last 20 dials. /healthz answers 200 while we're taking calls.
+    ///
+    /// Example: --status-http 127.0.0.1:8080
+    #[arg(long, value_name = "[HOST:]PORT", value_parser = listen_value)]
+    pub status_http: Option<String>,
+
+    /// POST a JSON event to this URL when a session connects, a dial fails and a session disconnects. Sending never holds up a session; one that still fails after --webhook-retries is logged and dropped.
+    ///
+    /// Example: --webhook https://example.com/touchppp
+    #[arg(long, value_name = "URL")]
+    pub webhook: Option<String>,
+
+    /// Sign each --webhook POST with HMAC-SHA256 using this secret, sent as X-TouchPPP-Signature: sha256=HEX.
+    ///
+    /// Example: --webhook-secret hunter2
+    #[arg(long, value_name = "SECRET")]
+    pub webhook_secret: Option<String>,
+
+    /// How many more times to try a --webhook POST that failed, waiting 1s, then 2s and so on in between. Defaults to 3.
+    ///
+    /// Example: --webhook-retries 5
+    #[arg(long, value_name = "COUNT")]
+    pub webhook_retries: Option<u32>,
+
+    /// Append a JSON line to this file for every dial: when, which session and client, the number, the backend it went to, how it went (connected, busy, no-carrier, no-dialtone, delayed, blacklisted or failed) and the speeds it connected at. A call that connected gets a disconnect line with the same attempt id once it's over, saying how long it lasted. Unlike the log, the lines always look the same, for billing and auditing a shared instance.
+    ///
+    /// Example: --dial-log /var/log/touchppp/dials.jsonl
+    #[arg(long, value_name = "PATH")]
+    pub dial_log: Option<String>,
+
+    /// Sync the --dial-log to disk after every line, so a crash can't lose any.
+    #[arg(long)]
+    pub dial_log_sync: bool,
+
+    /// Write a call detail record for every session once it's over: one JSON object with a schema version (v), the session, when it started and ended, the client, the number, backend and remote PPP server of its latest dial, whether that connected (outcome), the speeds, bytes each way, why it ended and the IP addresses IPCP settled on. A file gets a line per session; a directory that's already there gets a file per session.
+    ///
+    /// Example: --cdr-file /var/log/touchppp/cdrs.jsonl
+    #[arg(long, value_name = "PATH")]
+    pub cdr_file: Option<String>,
+
+    /// Write a transcript of each session to a file in this directory: every AT command line MAME sent, every result code sent back and which backend a dial went to. Handy for "it won't dial" reports.
+    ///
+    /// Example: --at-transcript /var/log/touchppp/at
+    #[arg(long, value_name = "DIR")]
+    pub at_transcript: Option<String>,
+
+    /// Look up every host name at startup and refuse to start if one doesn't resolve.
+    #[arg(long)]
+    pub resolve_at_start: bool,
+
+    /// Try to touch the remote PPP server at startup and warn if it can't be reached. Ignored with -e.
+    #[arg(long)]
+    pub health_check: bool,
+
+    /// Keep checking the remote PPP server every SECONDS (minimum 5) while no session is using it. Dials are answered with BUSY while it's down. Implies --health-check.
+    ///
+    /// Example: --health-check-interval 30
+    #[arg(long, value_name = "SECONDS")]
+    pub health_check_interval: Option<u64>,
+
+    /// How long calls get to hang up when TouchPPP's asked to stop (Ctrl-C or SIGTERM) before they're cut off and TouchPPP exits with an error. A second Ctrl-C cuts them off right away. This defaults to 10 seconds.
+    ///
+    /// Example: --shutdown-timeout 30
+    #[arg(long, value_name = "SECONDS")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// How long a drain (the admin drain command, or SIGUSR2) waits for the calls that are up to end on their own. After that they're hung up on the way a stop would. Without it, a drain waits as long as they take.
+    ///
+    /// Example: --drain-timeout 600
+    #[arg(long, value_name = "SECONDS")]
+    pub drain_timeout: Option<u64>,
+
+    /// Stop after this many sessions (from MAME connecting to hanging up), the way a stop would once the last one's over. No more connections are taken after the last one, and if any of them ended in an error we exit with 3 instead of 0. For scripted runs, with --launch-mame.
+    ///
+    /// Example: --exit-after-sessions 2
+    #[arg(long, value_name = "N")]
+    pub exit_after_sessions: Option<u64>,
+
+    /// Print more. -v adds AT command transcripts and backend decisions, -vv adds hexdumps of the PPP traffic. RUST_LOG filters (like touchppp::at=debug) work too; the areas are touchppp::at, touchppp::backend, touchppp::bridge, touchppp::config and touchppp::mame.
+    ///
+    /// Example: -vv
+    #[arg(short = 'v', long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Write the log to this file instead of the terminal. It's rotated to PATH.1, PATH.2, ... when it gets too big.
+    ///
+    /// Example: --log-file /var/log/touchppp.log
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<String>,
+
+    /// How big the log file can get before it's rotated. This defaults to 10M.
+    ///
+    /// Example: --log-max-size 512K
+    #[arg(long, value_name = "SIZE", value_parser = size_value)]
+    pub log_max_size: Option<String>,
+
+    /// How many rotated log files to keep around. This defaults to 5.
+    ///
+    /// Example: --log-keep 2
+    #[arg(long, value_name = "COUNT")]
+    pub log_keep: Option<usize>,
+
+    /// How log lines look: text (the default) or json for one JSON object per line with ts, level, session, event and msg plus any other details as their own fields.
+    ///
+    /// Example: --log-format json
+    #[arg(long, value_name = "text|json")]
+    pub log_format: Option<LogFormat>,
+
+    /// What to log from where, in RUST_LOG's syntax, on top of the level -q or -v gives everything else. The targets are touchppp::at (AT commands), touchppp::backend, touchppp::bridge (hexdumps), touchppp::config, touchppp::mame, touchppp::modem, touchppp::nat, touchppp::ppp, touchppp::socket and touchppp::tun; anything else logs as touchppp::MODULE.
+    ///
+    /// Example: --log-filter touchppp::at=debug,touchppp::bridge=warn
+    #[arg(long, value_name = "FILTER", value_parser = log_filter_value)]
+    pub log_filter: Option<String>,
+
+    /// Color text logs by level and session: auto (the default) does on a terminal unless NO_COLOR is set. Log files never get color.
+    ///
+    /// Example: --color never
+    #[arg(long, value_name = "auto|always|never")]
+    pub color: Option<Color>,
+
+    /// Keep printing to the terminal when --log-file is used.
+    #[arg(long)]
+    pub log_stdout: bool,
+
+    /// Send log lines to syslog too: the local /dev/log with no ADDR, or a syslog server at [udp://|tcp://]HOST[:PORT] (UDP port 514 by default). Works alongside --log-file and the terminal.
+    ///
+    /// Example: --log-syslog tcp://logs.example.com:601
+    #[arg(long, value_name = "ADDR", num_args = 0..=1, default_missing_value = address::DEFAULT_SYSLOG_PATH, value_parser = syslog_value)]
+    pub log_syslog: Option<String>,
+
+    /// The syslog facility for --log-syslog. Defaults to daemon.
+    ///
+    /// Example: --syslog-facility local3
+    #[arg(long, value_name = "FACILITY")]
+    pub syslog_facility: Option<String>,
+
+    /// Load the config, check it over (host names, programs to run, the phone book, whether the listen address is free) and say what we'd do, then exit without serving. Exits with 1 if anything's wrong. With --health-check it also tries the remote PPP servers, but one being down is only a warning.
+    ///
+    /// Example: --check --config /etc/touchppp.toml
+    #[arg(long, visible_alias = "dry-run")]
+    pub check: bool,
+
+    /// Print the config TouchPPP ends up with after the config file, environment and command line are put together, noting where each setting came from, then exit.
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Don't print anything unless it's a fatal exception. -h ignores this.
+    #[arg(short = 'q', long)]
+    pub silent: bool,
+
+    /// tcpser's -s: the speed the final CONNECT line reports, same as --connect-speed.
+    ///
+    /// Example: -s 57600
+    #[arg(short = 's', value_name = "SPEED", help_heading = TCPSER_HEADING)]
+    pub tcpser_speed: Option<u32>,
+
+    /// tcpser's -p: the port to listen on, same as -l PORT.
+    ///
+    /// Example: -p 6400
+    #[arg(short = 'p', value_name = "PORT", value_parser = clap::value_parser!(u16), help_heading = TCPSER_HEADING)]
+    pub tcpser_port: Option<u16>,
+
+    /// tcpser's -t: trace flags. Any of s, S, i or I logs the bytes going each way, same as -vv.
+    ///
+    /// Example: -tSs
+    #[arg(short = 't', value_name = "FLAGS", value_parser = trace_flags_value, help_heading = TCPSER_HEADING)]
+    pub tcpser_trace: Option<String>,
+
+    /// tcpser's -n: send a dialed number to a remote PPP server, like a phone book entry pointing at a backend with just that server. Can be given more than once.
+    ///
+    /// Example: -n 5551212=ppp.cool.com:2323
+    #[arg(short = 'n', value_name = "NUMBER=HOST:PORT", value_parser = phone_book_value, help_heading = TCPSER_HEADING)]
+    pub tcpser_number: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// A file of AT commands, one per line. Blank lines and lines starting with # are skipped.
+    #[arg(value_name = "FILE")]
+    pub file: PathBuf,
+
+    /// Where TouchPPP is listening.
+    #[arg(long, value_name = "[HOST:]PORT", default_value = DEFAULT_DIAL_TO, value_parser = touchppp_value)]
+    pub to: String,
+
+    /// How long to wait for each reply.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: u64,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// How long to run each mode and payload size for.
+    #[arg(long, value_name = "SECONDS", default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub seconds: u64,
+
+    /// How many bytes to send each round trip in data mode. Can be given more than once.
+    ///
+    /// Example: --payload 1 --payload 1500
+    #[arg(long, value_name = "BYTES", default_values_t = [1, 64, 1500], value_parser = clap::value_parser!(u64).range(1..=0x10000))]
+    pub payload: Vec<u64>,
+
+    /// Which setups to time: commands (AT commands in command mode), echo, throttled (held to the carrier speed) or coalesced (through a remote server with --coalesce-bytes). Can be given more than once. This defaults to all of them.
+    ///
+    /// Example: --mode echo --mode coalesced
+    #[arg(long, value_name = "MODE")]
+    pub mode: Vec<bench::Mode>,
+}
+
+#[derive(Args)]
+pub struct SoakArgs {
+    /// How long to keep both ways busy, in seconds or with s, m or h after it.
+    ///
+    /// Example: --duration 10m
+    #[arg(long, value_name = "DURATION", default_value = "60s", value_parser = soak::parse_duration)]
+    pub duration: std::time::Duration,
+
+    /// How many bytes a second to send each way, with k or m after it for thousands or millions.
+    ///
+    /// Example: --rate 50k
+    #[arg(long, value_name = "BYTES", default_value = "10k", value_parser = soak::parse_rate)]
+    pub rate: u64,
+
+    /// Hold the call to this many bits per second each way, like serve's --throttle.
+    ///
+    /// Example: --throttle 28800
+    #[arg(long, value_name = "BPS")]
+    pub throttle: Option<u32>,
+
+    /// Gather up to this many of the box's bytes on the way to PPP, like serve's --coalesce-bytes. 0 turns it off.
+    ///
+    /// Example: --coalesce-bytes 0
+    #[arg(long, value_name = "BYTES")]
+    pub coalesce_bytes: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct DialArgs {
+    /// The number to dial, which picks the backend through the phone book.
+    #[arg(value_name = "NUMBER", value_parser = number_value)]
+    pub number: String,
+
+    /// Where TouchPPP is listening.
+    #[arg(long, value_name = "[HOST:]PORT", default_value = DEFAULT_DIAL_TO, value_parser = touchppp_value)]
+    pub to: String,
+
+    /// How long to wait for each reply.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: u64,
+}
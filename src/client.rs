@@ -0,0 +1,93 @@
+// `touchppp dial` and `touchppp replay`: play the WebTV side against a running TouchPPP.
+
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::address;
+use crate::cli::{DialArgs, ReplayArgs};
+use crate::StartError;
+
+// Once a reply starts, it's over when nothing more shows up for this long.
+const REPLY_GAP: Duration = Duration::from_millis(300);
+
+// What the WebTV sends before dialing.
+const INIT_STRING: &str = "ATE0";
+
+// Numeric result codes that mean the call went through (CONNECT and CONNECT 115200).
+const CONNECT_CODES: [&str; 2] = ["1", "19"];
+
+async fn connect(to: &str) -> Result<TcpStream, StartError> {
+    let to = address::parse_touchppp(to).map_err(|e| StartError::Usage(e.to_string()))?;
+
+    TcpStream::connect(to.target()).await.map_err(|e| StartError::Runtime(format!("can't reach TouchPPP @ {to}: {e}").into()))
+}
+
+// Waits up to timeout for a reply, then keeps collecting until it goes quiet.
+async fn read_reply(modem: &mut TcpStream, timeout: Duration) -> std::io::Result<String> {
+    let mut reply = Vec::new();
+    let mut buf = [0; 0x400];
+    let mut wait = timeout;
+
+    loop {
+        match tokio::time::timeout(wait, modem.read(&mut buf)).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(n)) => reply.extend_from_slice(&buf[0..n]),
+            Ok(Err(e)) => return Err(e),
+        }
+
+        wait = REPLY_GAP;
+    }
+
+    Ok(String::from_utf8_lossy(&reply).to_string())
+}
+
+async fn command(modem: &mut TcpStream, line: &str, timeout: Duration) -> Result<String, StartError> {
+    modem.write_all(format!("{line}\r").as_bytes()).await?;
+
+    let reply = read_reply(modem, timeout).await?;
+
+    println!("> {line}");
+    println!("< {}", reply.escape_debug());
+
+    Ok(reply)
+}
+
+fn result_codes(reply: &str) -> Vec<&str> {
+    reply.split(['\r', '\n']).map(|code| code.trim()).filter(|code| !code.is_empty()).collect()
+}
+
+#[tokio::main]
+pub async fn dial(args: &DialArgs) -> Result<(), StartError> {
+    let timeout = Duration::from_secs(args.timeout);
+    let mut modem = connect(&args.to).await?;
+
+    command(&mut modem, INIT_STRING, timeout).await?;
+    command(&mut modem, &format!("ATDT{}", args.number), timeout).await?;
+
+    // The bare ATD is what actually asks for data mode.
+    let reply = command(&mut modem, "ATD", timeout).await?;
+
+    if result_codes(&reply).iter().any(|code| CONNECT_CODES.contains(code) || code.starts_with("CONNECT")) {
+        println!("Connected to {}.", args.number);
+
+        Ok(())
+    } else {
+        Err(StartError::Runtime(format!("{} didn't connect: got '{}'", args.number, reply.escape_debug()).into()))
+    }
+}
+
+#[tokio::main]
+pub async fn replay(args: &ReplayArgs) -> Result<(), StartError> {
+    let transcript = std::fs::read_to_string(&args.file)
+        .map_err(|e| StartError::Usage(format!("can't read '{}': {e}", args.file.display())))?;
+
+    let timeout = Duration::from_secs(args.timeout);
+    let mut modem = connect(&args.to).await?;
+
+    for line in transcript.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        command(&mut modem, line, timeout).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,2268 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+#[cfg(all(target_os = "linux", feature = "tun"))]
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::time::Duration;
+use serde::Deserialize;
+
+use crate::at::{self, Profile, Protocol};
+use crate::preset::{self, ModemPreset};
+use crate::address::{self, AdminAddr, ListenAddr, RemoteAddr, SyslogAddr, DEFAULT_IP, DEFAULT_REMOTE_PORT};
+use crate::backend::{Echo, Null, PppBackend};
+use crate::bridge::{Coalesce, BUFFER_SIZE};
+use crate::error::TouchPppError;
+use crate::listener::SocketOptions;
+use crate::logfile;
+use crate::resolve::NameResolver;
+#[cfg(feature = "nat")]
+use crate::nat::{self, Nat, NatPool};
+#[cfg(all(target_os = "linux", feature = "tun"))]
+use crate::tun::{self, Tun};
+use crate::syslog::{self, Facility};
+use crate::webhook;
+
+mod builder;
+
+pub use builder::ConfigBuilder;
+
+
+const DEFAULT_LISTEN_PORT: u16 = 1122;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+// About a PPP frame's worth, without holding a keystroke back long enough to notice.
+const DEFAULT_COALESCE_BYTES: usize = 256;
+const DEFAULT_COALESCE_MS: u64 = 5;
+const MAX_COALESCE_MS: u64 = 1000;
+// Anything outside this is a typo (or a unit mixup), not tuning.
+const MIN_SOCKET_BUFFER: usize = 1024;
+const MAX_SOCKET_BUFFER: usize = 64 * 1024 * 1024;
+// Keep probes from hammering the PPP server (each probe may spawn a pppd on the other end).
+const HEALTH_CHECK_MIN_INTERVAL: u64 = 5;
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+const DEFAULT_DELAY_WINDOW: u64 = 600;
+const DEFAULT_DELAY_COOLDOWN: u64 = 300;
+// Same as a real modem's command buffer.
+const DEFAULT_MAX_COMMAND_LENGTH: usize = 255;
+// Plenty for a few boxes coming up at once, not so many that a scan ties up much.
+const DEFAULT_MAX_PENDING: usize = 16;
+
+pub const NO_WORKING_REMOTE: usize = usize::MAX;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(s) => vec![s],
+            OneOrMany::Many(v) => v,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    listen: Option<String>,
+    connect: Option<OneOrMany>,
+    exec: Option<String>,
+    remote_udp: Option<String>,
+    backend_builtin: Option<Builtin>,
+    #[cfg(feature = "nat")]
+    nat_pool: Option<NatPool>,
+    #[cfg(feature = "nat")]
+    nat_dns: Option<OneOrMany>,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    tun_name: Option<String>,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    tun_local: Option<Ipv4Addr>,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    tun_peer: Option<Ipv4Addr>,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    tun_dns: Option<OneOrMany>,
+    default_backend: Option<String>,
+    connect_timeout: Option<u64>,
+    connect_retries: Option<u32>,
+    remote_sticky: Option<bool>,
+    remote_exclusive: Option<bool>,
+    exec_concurrent: Option<bool>,
+    #[cfg(all(unix, feature = "ssh"))]
+    remote_ssh: Option<String>,
+    #[cfg(all(unix, feature = "ssh"))]
+    ssh_key: Option<String>,
+    #[cfg(all(unix, feature = "ssh"))]
+    ssh_insecure: Option<bool>,
+    #[cfg(all(unix, feature = "ssh"))]
+    ssh_persist: Option<bool>,
+    coalesce_bytes: Option<usize>,
+    coalesce_ms: Option<u64>,
+    low_latency: Option<bool>,
+    so_rcvbuf: Option<usize>,
+    so_sndbuf: Option<usize>,
+    bind_device: Option<String>,
+    resolve: Option<OneOrMany>,
+    dns: Option<String>,
+    health_check: Option<bool>,
+    resolve_at_start: Option<bool>,
+    health_check_interval: Option<u64>,
+    shutdown_timeout: Option<u64>,
+    drain_timeout: Option<u64>,
+    exit_after_sessions: Option<u64>,
+    connect_speed: Option<u32>,
+    carrier_speed: Option<CarrierSpeed>,
+    suppress_intermediates: Option<bool>,
+    protocol_line: Option<Protocol>,
+    throttle: Option<u32>,
+    throttle_down: Option<u32>,
+    throttle_up: Option<u32>,
+    throttle_to_carrier: Option<bool>,
+    report_throughput: Option<bool>,
+    delay_after: Option<u32>,
+    delay_window: Option<u64>,
+    delay_cooldown: Option<u64>,
+    blacklist: Option<OneOrMany>,
+    force_56k: Option<bool>,
+    #[serde(rename = "56k_numbers")]
+    numbers_56k: Option<OneOrMany>,
+    no_56k_numbers: Option<OneOrMany>,
+    #[serde(alias = "persist_dial_state")]
+    state_file: Option<String>,
+    drop_after: Option<CarrierDrop>,
+    drop_count: Option<u32>,
+    max_command_length: Option<usize>,
+    accept_rate: Option<AcceptRate>,
+    max_pending: Option<usize>,
+    command_delay: Option<u64>,
+    command_delay_for: Option<OneOrMany>,
+    profile: Option<Profile>,
+    modem_profile: Option<String>,
+    reset_forgets_number: Option<bool>,
+    link_protocol: Option<LinkProtocol>,
+    auto_data: Option<AutoData>,
+    at_passthrough: Option<bool>,
+    silent: Option<bool>,
+    verbose: Option<u8>,
+    log_file: Option<String>,
+    log_max_size: Option<String>,
+    log_keep: Option<usize>,
+    log_stdout: Option<bool>,
+    log_format: Option<LogFormat>,
+    log_filter: Option<String>,
+    color: Option<Color>,
+    log_syslog: Option<String>,
+    syslog_facility: Option<String>,
+    launch_mame: Option<String>,
+    mame_slot: Option<MameSlot>,
+    mame_restart: Option<bool>,
+    daemon: Option<bool>,
+    pid_file: Option<String>,
+    admin: Option<String>,
+    enable_inject: Option<bool>,
+    status_http: Option<String>,
+    webhook: Option<String>,
+    webhook_secret: Option<String>,
+    webhook_retries: Option<u32>,
+    dial_log: Option<String>,
+    dial_log_sync: Option<bool>,
+    cdr_file: Option<String>,
+    at_transcript: Option<String>,
+    #[serde(default)]
+    backend: BTreeMap<String, BackendProfile>,
+    #[serde(default)]
+    phonebook: BTreeMap<String, PhoneBookEntry>,
+}
+
+// A phone book entry is a backend's name, or a table that can change how dials to its numbers go as well (and
+// leave the backend to the rest of the phone book).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PhoneBookEntry {
+    Backend(String),
+    Table(PhoneBookTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PhoneBookTable {
+    backend: Option<String>,
+    connect_speed: Option<u32>,
+    carrier_speed: Option<CarrierSpeed>,
+    force_56k: Option<bool>,
+    dial_delay: Option<u64>,
+    throttle: Option<u32>,
+    throttle_down: Option<u32>,
+    throttle_up: Option<u32>,
+}
+
+/// What a phone book entry can change about dials to its numbers. Anything left at None is whatever the command
+/// line (or the default) says.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct DialOverrides {
+    pub connect_speed: Option<u32>,
+    pub carrier_speed: Option<CarrierSpeed>,
+    /// Reports a 56k CARRIER whatever the box's init string says about it, or never does.
+    pub force_56k: Option<bool>,
+    /// How long dialing takes before the backend's tried.
+    pub dial_delay: Option<Duration>,
+    /// Holds the call to this many bits per second each way.
+    pub throttle: Option<u32>,
+    /// Holds just one way to this many bits per second, whatever `throttle` says.
+    pub throttle_down: Option<u32>,
+    pub throttle_up: Option<u32>,
+}
+
+/// The bits per second data mode's held to each way. None goes as fast as it can.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Throttle {
+    pub down: Option<u32>,
+    pub up: Option<u32>,
+}
+
+impl Throttle {
+    /// The same both ways.
+    pub fn both(bits_per_second: u32) -> Throttle {
+        Throttle { down: Some(bits_per_second), up: Some(bits_per_second) }
+    }
+
+    /// What a real modem reporting `carrier_speed` would manage: 56k is only ever 56k downstream, and the box
+    /// sends at V.34 speeds at best.
+    pub fn for_carrier(carrier_speed: u32) -> Throttle {
+        Throttle { down: Some(carrier_speed), up: Some(carrier_speed.min(at::Modulation::V34.top_speed())) }
+    }
+
+    /// `fallback` for whichever way this has nothing to say about.
+    pub fn or(self, fallback: Throttle) -> Throttle {
+        Throttle { down: self.down.or(fallback.down), up: self.up.or(fallback.up) }
+    }
+}
+
+/// How a dial goes once its phone book entry's had its say.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct DialSettings {
+    pub connect_report: at::ConnectReport,
+    pub dial_delay: Duration,
+    // What the entry or --throttle holds each way to. --throttle-to-carrier fills in the rest once the carrier's
+    // known.
+    pub throttle: Throttle,
+    pub is_56k: Decision56k,
+}
+
+/// Whether a dial gets 56k, and what decided it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Decision56k {
+    pub is_56k: bool,
+    pub reason: &'static str,
+    // Pinned either way, rather than left to the carrier speed and whatever the box's init string says next.
+    is_forced: bool,
+}
+
+impl Decision56k {
+    fn forced(is_56k: bool, reason: &'static str) -> Decision56k {
+        Decision56k { is_56k, reason, is_forced: true }
+    }
+
+    fn inferred(is_56k: bool, reason: &'static str) -> Decision56k {
+        Decision56k { is_56k, reason, is_forced: false }
+    }
+
+    /// The force_56k to report with: Some either way if it's pinned, None if it's inferred.
+    pub fn force_56k(&self) -> Option<bool> {
+        self.is_forced.then_some(self.is_56k)
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct BackendProfile {
+    connect: Option<OneOrMany>,
+    exec: Option<String>,
+    udp: Option<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    connect_timeout: Option<u64>,
+    connect_retries: Option<u32>,
+    remote_sticky: Option<bool>,
+    // Whether a second call gets BUSY while one's online. Defaults to remote_exclusive or !exec_concurrent.
+    exclusive: Option<bool>,
+    coalesce_bytes: Option<usize>,
+    coalesce_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<LogFormat, String> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("use text or json".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+// Whether text logs get ANSI colors: auto is only on a terminal, and only without NO_COLOR.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Color, String> {
+        match value {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err("use auto, always or never".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Color::Auto => write!(f, "auto"),
+            Color::Always => write!(f, "always"),
+            Color::Never => write!(f, "never"),
+        }
+    }
+}
+
+// The slot the null modem plugs into. wtv1 boxes have an spot slot, wtv2 boxes have a solo slot.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MameSlot {
+    Spot,
+    Solo,
+}
+
+impl std::str::FromStr for MameSlot {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<MameSlot, String> {
+        match value {
+            "spot" => Ok(MameSlot::Spot),
+            "solo" => Ok(MameSlot::Solo),
+            _ => Err("use spot (wtv1) or solo (wtv2)".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for MameSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MameSlot::Spot => write!(f, "spot"),
+            MameSlot::Solo => write!(f, "solo"),
+        }
+    }
+}
+
+// What the box speaks once it's online. Only the built-in backends that answer it themselves care; the rest are
+// handed bytes either way.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkProtocol {
+    #[default]
+    Ppp,
+    Slip,
+}
+
+impl std::str::FromStr for LinkProtocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<LinkProtocol, String> {
+        match value.to_lowercase().as_str() {
+            "ppp" => Ok(LinkProtocol::Ppp),
+            "slip" => Ok(LinkProtocol::Slip),
+            _ => Err("use ppp or slip".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for LinkProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LinkProtocol::Ppp => write!(f, "ppp"),
+            LinkProtocol::Slip => write!(f, "slip"),
+        }
+    }
+}
+
+// What --auto-data does when PPP shows up without a dial: tell the box CONNECT first, or go straight to it.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoData {
+    Connect,
+    Quiet,
+}
+
+impl std::str::FromStr for AutoData {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<AutoData, String> {
+        match value.to_lowercase().as_str() {
+            "connect" => Ok(AutoData::Connect),
+            "quiet" => Ok(AutoData::Quiet),
+            _ => Err("use connect or quiet".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for AutoData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AutoData::Connect => write!(f, "connect"),
+            AutoData::Quiet => write!(f, "quiet"),
+        }
+    }
+}
+
+// The CARRIER speed CONNECT reports. Auto is what TouchPPP has always said.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "toml::Value")]
+pub enum CarrierSpeed {
+    Auto,
+    Fixed(u32),
+}
+
+impl CarrierSpeed {
+    pub fn speed(&self) -> u32 {
+        match self {
+            CarrierSpeed::Auto => at::DEFAULT_CARRIER_SPEED,
+            CarrierSpeed::Fixed(speed) => *speed,
+        }
+    }
+}
+
+impl std::str::FromStr for CarrierSpeed {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<CarrierSpeed, String> {
+        match value {
+            "auto" => Ok(CarrierSpeed::Auto),
+            _ => value.parse().map(CarrierSpeed::Fixed).map_err(|_| "use auto or a speed in bits per second, like 31200".to_string()),
+        }
+    }
+}
+
+// So the config file can say carrier_speed = 31200 as well as "auto".
+impl TryFrom<toml::Value> for CarrierSpeed {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<CarrierSpeed, String> {
+        match value {
+            toml::Value::Integer(speed) => u32::try_from(speed).map(CarrierSpeed::Fixed).map_err(|_| format!("{speed} isn't a speed")),
+            toml::Value::String(value) => value.parse(),
+            _ => Err("use auto or a speed in bits per second, like 31200".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for CarrierSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CarrierSpeed::Auto => write!(f, "auto"),
+            CarrierSpeed::Fixed(speed) => write!(f, "{speed}"),
+        }
+    }
+}
+
+// The CARRIER a phone book entry's force_56k = true (or --force-56k) reports when the carrier speed's left at auto,
+// about what V.90 managed on a good line.
+const FORCED_56K_CARRIER_SPEED: u32 = 50000;
+
+// What a box dials to sign up and download its settings, which never got a 56k call. --56k-numbers says otherwise.
+const BOOTSTRAP_NUMBERS: &[&str] = &["1800*"];
+
+// What CONNECT says with these speeds. A 56k carrier that's forced on is reported whatever the box's init string
+// says; forced off, the carrier's held to V.34's. Auto and whether it's forced either way are up to `preset` unless
+// they're given.
+fn connect_report_for(connect_speed: u32, carrier_speed: CarrierSpeed, force_56k: Option<bool>, preset: &ModemPreset, protocol: Option<Protocol>, intermediates: bool) -> at::ConnectReport {
+    let force_56k = force_56k.or(preset.force_56k);
+    let speed = match carrier_speed {
+        CarrierSpeed::Auto => preset.carrier_speed,
+        CarrierSpeed::Fixed(speed) => speed,
+    };
+
+    let carrier = match (force_56k, carrier_speed) {
+        (Some(true), CarrierSpeed::Auto) => FORCED_56K_CARRIER_SPEED,
+        (Some(false), _) => at::carrier_at_most(speed.min(at::Modulation::V34.top_speed())),
+        (_, _) => speed,
+    };
+
+    at::ConnectReport {
+        carrier_speed: carrier,
+        follows_modulation: carrier_speed == CarrierSpeed::Auto && force_56k != Some(true),
+        connect_speed,
+        protocol,
+        compression: true,
+        intermediates,
+    }
+}
+
+// --drop-after: how long after CONNECT to drop the carrier, give or take up to `jitter` either way.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "toml::Value")]
+pub struct CarrierDrop {
+    pub after: Duration,
+    pub jitter: Duration,
+}
+
+impl CarrierDrop {
+    // Somewhere in after ± jitter, picked by `seed`, so each call drops at its own time.
+    pub fn pick(&self, seed: u64) -> Duration {
+        let span = self.jitter.as_millis() as u64 * 2;
+        let offset = Duration::from_millis(seed.checked_rem(span + 1).unwrap_or(0));
+
+        (self.after + offset).saturating_sub(self.jitter)
+    }
+}
+
+impl std::str::FromStr for CarrierDrop {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<CarrierDrop, String> {
+        let (after, jitter) = value.split_once(',').unwrap_or((value, "0"));
+
+        match (after.trim().parse(), jitter.trim().parse()) {
+            (Ok(after), Ok(jitter)) => Ok(CarrierDrop {
+                after: Duration::from_secs(after),
+                jitter: Duration::from_secs(jitter),
+            }),
+            _ => Err("use SECONDS or SECONDS,JITTER, like 30 or 30,10".to_string()),
+        }
+    }
+}
+
+// So the config file can say drop_after = 30 as well as "30,10".
+impl TryFrom<toml::Value> for CarrierDrop {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<CarrierDrop, String> {
+        match value {
+            toml::Value::Integer(seconds) => u64::try_from(seconds).map(|after| CarrierDrop { after: Duration::from_secs(after), jitter: Duration::ZERO }).map_err(|_| format!("{seconds} isn't a number of seconds")),
+            toml::Value::String(value) => value.parse(),
+            _ => Err("use SECONDS or SECONDS,JITTER, like 30 or 30,10".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for CarrierDrop {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.jitter.is_zero() {
+            write!(f, "{}", self.after.as_secs())
+        } else {
+            write!(f, "{},{}", self.after.as_secs(), self.jitter.as_secs())
+        }
+    }
+}
+
+// --accept-rate: how many connections each address can make in how long. A count of 0 is off.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct AcceptRate {
+    pub count: u32,
+    pub per: Duration,
+}
+
+impl AcceptRate {
+    pub const DEFAULT: AcceptRate = AcceptRate { count: 5, per: Duration::from_secs(10) };
+    pub const OFF: AcceptRate = AcceptRate { count: 0, per: Duration::from_secs(1) };
+
+    pub fn is_off(&self) -> bool {
+        self.count == 0
+    }
+}
+
+impl std::str::FromStr for AcceptRate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<AcceptRate, String> {
+        if value == "off" {
+            return Ok(AcceptRate::OFF);
+        }
+
+        let (count, per) = value.split_once('/').unwrap_or((value, ""));
+
+        match (count.trim().parse(), per.trim().parse()) {
+            (Ok(count), Ok(per)) if count > 0 && per > 0 => Ok(AcceptRate { count, per: Duration::from_secs(per) }),
+            _ => Err("use COUNT/SECONDS or off, like 5/10".to_string()),
+        }
+    }
+}
+
+impl TryFrom<String> for AcceptRate {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<AcceptRate, String> {
+        value.parse()
+    }
+}
+
+impl std::fmt::Display for AcceptRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_off() {
+            write!(f, "off")
+        } else {
+            write!(f, "{}/{}", self.count, self.per.as_secs())
+        }
+    }
+}
+
+// What --backend-builtin stands in for PPP with.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Builtin {
+    Echo,
+    Null,
+    #[cfg(feature = "nat")]
+    Nat,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    Tun,
+}
+
+// Only the built-ins this build has.
+fn builtin_usage() -> String {
+    let builtins: Vec<&str> = [
+        Some("echo (sends everything back)"),
+        Some("null (sends nothing)"),
+        cfg!(feature = "nat").then_some("nat (answers PPP itself)"),
+        cfg!(all(target_os = "linux", feature = "tun")).then_some("tun (answers PPP itself onto a TUN device)"),
+    ].into_iter().flatten().collect();
+
+    let (last, rest) = builtins.split_last().unwrap();
+
+    format!("use {} or {last}", rest.join(", "))
+}
+
+impl std::str::FromStr for Builtin {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Builtin, String> {
+        match value {
+            "echo" => Ok(Builtin::Echo),
+            "null" => Ok(Builtin::Null),
+            #[cfg(feature = "nat")]
+            "nat" => Ok(Builtin::Nat),
+            #[cfg(all(target_os = "linux", feature = "tun"))]
+            "tun" => Ok(Builtin::Tun),
+            _ => Err(builtin_usage()),
+        }
+    }
+}
+
+impl std::fmt::Display for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Builtin::Echo => write!(f, "echo"),
+            Builtin::Null => write!(f, "null"),
+            #[cfg(feature = "nat")]
+            Builtin::Nat => write!(f, "nat"),
+            #[cfg(all(target_os = "linux", feature = "tun"))]
+            Builtin::Tun => write!(f, "tun"),
+        }
+    }
+}
+
+pub struct RemotePpp {
+    pub socket_addresses: Vec<RemoteAddr>,
+    pub connect_timeout: Duration,
+    pub connect_retries: u32,
+    pub is_sticky: bool,
+    pub is_exclusive: bool,
+    // Where connections go through instead of straight to the servers, from --remote-ssh.
+    #[cfg(all(unix, feature = "ssh"))]
+    pub ssh: Option<Arc<crate::ssh::Tunnel>>,
+    // How MAME's bytes are gathered up on their way to the server. None sends each read as it comes.
+    pub coalesce: Option<Coalesce>,
+    pub socket_options: SocketOptions,
+    pub bind_device: Option<String>,
+    pub resolver: Arc<NameResolver>,
+    // Index into socket_addresses of the last server that worked. Only used with remote_sticky.
+    pub last_working: AtomicUsize,
+    // Flipped by the health check. Only consulted when health_check_interval is set.
+    pub healthy: AtomicBool,
+    pub active_sessions: AtomicUsize,
+}
+
+impl RemotePpp {
+    pub fn describe_addresses(&self) -> String {
+        self.socket_addresses.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(", ")
+    }
+}
+
+// A PPP server that takes its frames a datagram at a time, from --remote-udp.
+pub struct UdpPpp {
+    pub address: RemoteAddr,
+    pub is_exclusive: bool,
+    pub bind_device: Option<String>,
+    pub resolver: Arc<NameResolver>,
+}
+
+pub struct LocalPpp {
+    pub command: String,
+    pub env: BTreeMap<String, String>,
+    pub is_exclusive: bool,
+}
+
+pub enum BackendKind {
+    Remote(RemotePpp),
+    Exec(LocalPpp),
+    Udp(UdpPpp),
+    // Sends every byte straight back. `touchppp test` and --backend-builtin echo use it.
+    Echo,
+    // Swallows every byte and sends nothing. Only from --backend-builtin null.
+    Null,
+    // Answers PPP itself and gets the box online through this machine. Only from --backend-builtin nat.
+    #[cfg(feature = "nat")]
+    Nat(Nat),
+    // Answers PPP itself onto a TUN device, for this machine to route. Only from --backend-builtin tun.
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    Tun(Tun),
+    // Anything else that can reach PPP, for when TouchPPP's used as a library. Never comes from the config file.
+    Custom(Box<dyn PppBackend>),
+}
+
+pub struct Backend {
+    pub name: String,
+    pub kind: BackendKind,
+}
+
+impl Backend {
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            #[cfg(all(unix, feature = "ssh"))]
+            BackendKind::Remote(remote_ppp @ RemotePpp { ssh: Some(tunnel), .. }) => format!("{} (connect {} through ssh {})", self.name, remote_ppp.describe_addresses(), tunnel.server),
+            BackendKind::Remote(remote_ppp) => format!("{} (connect {})", self.name, remote_ppp.describe_addresses()),
+            BackendKind::Exec(local_ppp) => format!("{} (exec '{}')", self.name, local_ppp.command),
+            BackendKind::Udp(udp_ppp) => format!("{} (udp {})", self.name, udp_ppp.address),
+            BackendKind::Echo => format!("{} (built-in echo)", self.name),
+            BackendKind::Null => format!("{} (built-in null)", self.name),
+            #[cfg(feature = "nat")]
+            BackendKind::Nat(nat) => format!("{} (built-in nat, {})", self.name, nat.pool),
+            #[cfg(all(target_os = "linux", feature = "tun"))]
+            BackendKind::Tun(tun) => format!("{} (built-in tun, {} with {} for the box)", self.name, tun.name, tun.peer),
+            BackendKind::Custom(_) => format!("{} (custom)", self.name),
+        }
+    }
+
+    /// Whether a dial while another session's online with the backend should get BUSY instead of reaching it.
+    pub fn is_exclusive(&self) -> bool {
+        match &self.kind {
+            BackendKind::Remote(remote_ppp) => remote_ppp.is_exclusive,
+            BackendKind::Exec(local_ppp) => local_ppp.is_exclusive,
+            BackendKind::Udp(udp_ppp) => udp_ppp.is_exclusive,
+            _ => false,
+        }
+    }
+
+    /// Whether the backend answers the box's PPP (or SLIP) itself, like nat and tun, instead of passing its bytes
+    /// on to something that does.
+    pub fn answers_the_link(&self) -> bool {
+        match &self.kind {
+            #[cfg(feature = "nat")]
+            BackendKind::Nat(_) => true,
+            #[cfg(all(target_os = "linux", feature = "tun"))]
+            BackendKind::Tun(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn ppp(&self) -> &dyn PppBackend {
+        match &self.kind {
+            BackendKind::Remote(remote_ppp) => remote_ppp,
+            BackendKind::Exec(local_ppp) => local_ppp,
+            BackendKind::Udp(udp_ppp) => udp_ppp,
+            BackendKind::Echo => &Echo,
+            BackendKind::Null => &Null,
+            #[cfg(feature = "nat")]
+            BackendKind::Nat(nat) => nat,
+            #[cfg(all(target_os = "linux", feature = "tun"))]
+            BackendKind::Tun(tun) => tun,
+            BackendKind::Custom(custom) => custom.as_ref(),
+        }
+    }
+}
+
+pub struct Config {
+    // The [[modem]] section this came from, when the config file has them.
+    pub modem: Option<String>,
+    pub listen_address: ListenAddr,
+    // Set by -l pipe:\\.\pipe\NAME, in which case listen_address isn't used. Windows only.
+    pub listen_pipe: Option<String>,
+    pub is_silent: bool,
+    // 0 is connection lifecycle only, 1 (-v) adds AT transcripts and backend decisions, 2 (-vv) adds hexdumps.
+    pub verbosity: u8,
+    pub log_file: Option<String>,
+    pub log_max_size: u64,
+    pub log_keep: usize,
+    // Keep logging to stdout/stderr even with a log file.
+    pub log_stdout: bool,
+    pub log_format: LogFormat,
+    // RUST_LOG-style directives on top of -q/-v, checked when the config's built.
+    pub log_filter: Option<String>,
+    pub color: Color,
+    pub log_syslog: Option<SyslogAddr>,
+    pub syslog_facility: Facility,
+    pub health_check: bool,
+    pub resolve_at_start: bool,
+    pub health_check_interval: Option<Duration>,
+    // How long sessions get to hang up when we're asked to stop before they're killed outright.
+    pub shutdown_timeout: Duration,
+    // How long a drain waits for calls to end before hanging up on them like a stop would, if it doesn't wait
+    // for as long as they take.
+    pub drain_timeout: Option<Duration>,
+    // How many sessions to take before stopping, shared by every modem, if we don't keep going.
+    pub exit_after_sessions: Option<u64>,
+    // What CONNECT says, and the bytes that says it.
+    pub connect_speed: u32,
+    pub carrier_speed: CarrierSpeed,
+    pub suppress_intermediates: bool,
+    pub protocol_line: Option<Protocol>,
+    pub connect_report: at::ConnectReport,
+    // What --throttle, --throttle-down and --throttle-up hold data mode to, where the phone book doesn't say.
+    pub throttle: Throttle,
+    // Hold data mode to the carrier speed instead of going as fast as the bytes come.
+    pub throttle_to_carrier: bool,
+    // Tell MAME how a call did ahead of NO CARRIER.
+    pub report_throughput: bool,
+    // DELAYED after this many failed dials in a row to a number within delay_window, until delay_cooldown is up.
+    pub delay_after: Option<u32>,
+    pub delay_window: Duration,
+    pub delay_cooldown: Duration,
+    // Normalized numbers (or patterns) that are always BLACKLISTED.
+    pub blacklist: Vec<String>,
+    // --force-56k or --no-56k, and the normalized numbers (or patterns) --56k-numbers and --no-56k-numbers pin
+    // either way.
+    pub force_56k: Option<bool>,
+    pub numbers_56k: Vec<String>,
+    pub no_56k_numbers: Vec<String>,
+    // Where what's been learned about each number is kept between runs, if anywhere.
+    pub state_file: Option<String>,
+    // Hang up on calls this long after CONNECT, at most drop_count times (if set) for as long as we're running.
+    pub drop_after: Option<CarrierDrop>,
+    pub drop_count: Option<u32>,
+    // Longer AT command lines are thrown away and answered with ERROR.
+    pub max_command_length: usize,
+    // How often each address can connect, and how many connections can be waiting on their first command.
+    // Anything over is closed before it gets a session.
+    pub accept_rate: AcceptRate,
+    pub max_pending: usize,
+    // How long result codes wait before they're sent, and the commands (as tokenize names them) that wait longer
+    // or shorter.
+    pub command_delay: Duration,
+    pub command_delays: Vec<(String, Duration)>,
+    // Which modem the caller gets: the WebTV one, or a plain Hayes one for other dialers.
+    pub profile: Profile,
+    // Who that modem says it is when it's asked, and what it can do.
+    pub modem_profile: &'static ModemPreset,
+    // Whether ATZ and AT&F forget the number ATDL dials again.
+    pub reset_forgets_number: bool,
+    // PPP, or SLIP for boxes and other systems that dial in with that instead.
+    pub link_protocol: LinkProtocol,
+    // Going online by itself when PPP shows up in command mode, for boxes that never send ATD.
+    pub auto_data: Option<AutoData>,
+    // Leave the AT commands to the backend's own modem and only step in once it says CONNECT.
+    pub at_passthrough: bool,
+    // MAME command line to launch once we're listening.
+    pub launch_mame: Option<String>,
+    pub mame_slot: MameSlot,
+    pub mame_restart: bool,
+    pub daemon: bool,
+    pub pid_file: Option<String>,
+    // Where to take admin commands (list, kill, stats), if anywhere.
+    pub admin: Option<AdminAddr>,
+    // Whether the admin interface can inject bytes into calls.
+    pub enable_inject: bool,
+    // Where to serve the status page, if anywhere.
+    pub status_http: Option<ListenAddr>,
+    // Where to POST session events, if anywhere, and what to sign them with.
+    pub webhook: Option<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_retries: u32,
+    // Where to append a JSON line for every dial and how each call ended, and whether to sync after each one.
+    pub dial_log: Option<String>,
+    pub dial_log_sync: bool,
+    // Where a CDR for every session goes: a file to append to, or a directory to write one per session into.
+    pub cdr_file: Option<String>,
+    // The directory to write a transcript of each session's AT commands to, if any.
+    pub at_transcript: Option<String>,
+    pub backend_defaults: BackendDefaults,
+    // Set when -c or -e is given. This beats the phone book and the default backend.
+    pub cli_backend: Option<Arc<Backend>>,
+    pub default_backend: Arc<Backend>,
+    pub backends: BTreeMap<String, Arc<Backend>>,
+    // Sorted so the most specific pattern comes first.
+    pub phone_book: Vec<(String, Arc<Backend>)>,
+    // What phone book entries change about dials, sorted the same way. An entry here needn't have a backend.
+    pub dial_overrides: Vec<(String, DialOverrides)>,
+    // Where each setting that isn't a default came from, keyed by long option name.
+    pub sources: BTreeMap<String, SettingSource>,
+    // tcpser options on the command line and the native setting each one became.
+    pub tcpser_aliases: Vec<(String, String)>,
+}
+
+// Settings every backend profile falls back to when it doesn't set its own.
+pub struct BackendDefaults {
+    pub connect_timeout: u64,
+    pub connect_retries: u32,
+    pub remote_sticky: bool,
+    pub remote_exclusive: bool,
+    pub exec_concurrent: bool,
+    pub coalesce_bytes: usize,
+    pub coalesce_ms: u64,
+    // Sets TCP_NODELAY and turns coalescing off, whatever the profile says.
+    pub low_latency: bool,
+    pub so_rcvbuf: Option<usize>,
+    pub so_sndbuf: Option<usize>,
+    // The network interface everything for remote servers goes out over, from --bind-device.
+    pub bind_device: Option<String>,
+    // How remote servers' names get looked up, from --resolve and --dns.
+    pub resolver: Arc<NameResolver>,
+    #[cfg(all(unix, feature = "ssh"))]
+    pub ssh: Option<Arc<crate::ssh::Tunnel>>,
+}
+
+impl BackendDefaults {
+    // These go for MAME's end of every call too, not just the remote server's.
+    pub fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            no_delay: self.low_latency,
+            recv_buffer: self.so_rcvbuf,
+            send_buffer: self.so_sndbuf,
+        }
+    }
+}
+
+fn build_backend(name: &str, profile: BackendProfile, defaults: &BackendDefaults) -> Result<Backend, Box<dyn std::error::Error>> {
+    let kind = match (profile.connect, profile.exec, profile.udp) {
+        (None, None, None) => {
+            return Err(format!("backend '{name}' needs one of connect, exec or udp").into());
+        },
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+            return Err(format!("backend '{name}' can only have one of connect, exec or udp").into());
+        },
+        (None, None, Some(udp)) => {
+            let address = address::parse_remote(&udp).map_err(|e| format!("backend '{name}' has a bad udp address: {e}"))?;
+
+            BackendKind::Udp(UdpPpp {
+                address,
+                is_exclusive: profile.exclusive.unwrap_or(defaults.remote_exclusive),
+                bind_device: defaults.bind_device.clone(),
+                resolver: defaults.resolver.clone(),
+            })
+        },
+        (Some(connect), None, None) => {
+            let connect = connect.into_vec();
+
+            if connect.is_empty() {
+                return Err(format!("backend '{name}' has an empty connect list").into());
+            }
+
+            let mut socket_addresses = Vec::new();
+            for remote_socket_address in connect {
+                match address::parse_remote(&remote_socket_address) {
+                    Ok(r) => socket_addresses.push(r),
+                    Err(e) => return Err(format!("backend '{name}' has a bad connect address: {e}").into()),
+                }
+            }
+
+            let coalesce_bytes = profile.coalesce_bytes.unwrap_or(defaults.coalesce_bytes);
+            let coalesce_ms = profile.coalesce_ms.unwrap_or(defaults.coalesce_ms);
+            check_coalesce(coalesce_bytes, coalesce_ms).map_err(|e| format!("backend '{name}' has a bad coalesce_{e}"))?;
+
+            BackendKind::Remote(RemotePpp {
+                socket_addresses,
+                connect_timeout: Duration::from_secs(profile.connect_timeout.unwrap_or(defaults.connect_timeout)),
+                connect_retries: profile.connect_retries.unwrap_or(defaults.connect_retries),
+                is_sticky: profile.remote_sticky.unwrap_or(defaults.remote_sticky),
+                is_exclusive: profile.exclusive.unwrap_or(defaults.remote_exclusive),
+                #[cfg(all(unix, feature = "ssh"))]
+                ssh: defaults.ssh.clone(),
+                coalesce: (!defaults.low_latency && coalesce_bytes > 1 && coalesce_ms > 0).then(|| Coalesce { bytes: coalesce_bytes, wait: Duration::from_millis(coalesce_ms) }),
+                socket_options: defaults.socket_options(),
+                bind_device: defaults.bind_device.clone(),
+                resolver: defaults.resolver.clone(),
+                last_working: AtomicUsize::new(NO_WORKING_REMOTE),
+                healthy: AtomicBool::new(true),
+                active_sessions: AtomicUsize::new(0),
+            })
+        },
+        (None, Some(command), None) => {
+            if command.trim().is_empty() {
+                return Err(format!("backend '{name}' has an empty exec command").into());
+            }
+
+            BackendKind::Exec(LocalPpp {
+                command,
+                env: profile.env,
+                is_exclusive: profile.exclusive.unwrap_or(!defaults.exec_concurrent),
+            })
+        },
+    };
+
+    Ok(Backend {
+        name: name.to_string(),
+        kind,
+    })
+}
+
+// Without --nat-dns, the box's lookups go wherever this machine's do.
+#[cfg(feature = "nat")]
+fn build_nat(pool: Option<NatPool>, dns: Vec<String>) -> Result<Nat, Box<dyn std::error::Error>> {
+    let dns = if dns.is_empty() {
+        nat::system_dns()
+    } else {
+        dns.iter().map(|server| nat::parse_dns(server).map_err(|e| format!("bad --nat-dns: {e}"))).collect::<Result<_, _>>()?
+    };
+
+    if dns.is_empty() {
+        return Err("the nat backend needs --nat-dns since /etc/resolv.conf doesn't have an IPv4 name server".into());
+    }
+
+    Ok(Nat::new(pool.unwrap_or_default(), dns))
+}
+
+// Without --tun-dns, the box is told about the name servers this machine uses.
+#[cfg(all(target_os = "linux", feature = "tun"))]
+fn build_tun(name: Option<String>, local: Option<Ipv4Addr>, peer: Option<Ipv4Addr>, dns: Vec<String>) -> Result<Tun, Box<dyn std::error::Error>> {
+    let dns = if dns.is_empty() {
+        tun::system_dns()
+    } else {
+        dns.iter().map(|server| server.parse().map_err(|_| format!("bad --tun-dns: '{server}' isn't an IPv4 address"))).collect::<Result<_, _>>()?
+    };
+
+    let name = name.unwrap_or_else(|| tun::DEFAULT_NAME.to_string());
+
+    Ok(Tun::new(name, local.unwrap_or(tun::DEFAULT_LOCAL), peer.unwrap_or(tun::DEFAULT_PEER), dns)?)
+}
+
+fn check_socket_buffer(long_name: &str, size: Option<usize>) -> Result<(), String> {
+    match size {
+        Some(size) if !(MIN_SOCKET_BUFFER..=MAX_SOCKET_BUFFER).contains(&size) => {
+            Err(format!("bad --{long_name}: {size} bytes isn't between {MIN_SOCKET_BUFFER} and {MAX_SOCKET_BUFFER}"))
+        },
+        _ => Ok(()),
+    }
+}
+
+// Interface names fit in IFNAMSIZ (16, with the nul) and are never blank.
+fn check_device(device: &str) -> Result<(), String> {
+    if device.is_empty() || device.len() > 15 || device.contains(['/', ' ', '\0']) {
+        return Err(format!("bad --bind-device: '{device}' isn't a network interface's name"));
+    }
+
+    Ok(())
+}
+
+// Says which of the two is wrong (bytes or ms) and why.
+fn check_coalesce(bytes: usize, ms: u64) -> Result<(), String> {
+    if bytes > BUFFER_SIZE {
+        return Err(format!("bytes: {bytes} is more than the {BUFFER_SIZE} a read can hold"));
+    }
+    if ms > MAX_COALESCE_MS {
+        return Err(format!("ms: {ms} would hold bytes back for more than {MAX_COALESCE_MS}ms"));
+    }
+
+    Ok(())
+}
+
+// --command-delay-for's COMMAND=MS. The command's upper cased to match what tokenize gives.
+pub fn parse_command_delay(value: &str) -> Result<(String, Duration), String> {
+    let (command, ms) = value.split_once('=').ok_or("use COMMAND=MS, like I3=250")?;
+
+    let command = command.trim().to_ascii_uppercase();
+    if command.is_empty() {
+        return Err("give the command it's for, like I3".to_string());
+    }
+
+    let ms = ms.trim().parse().map_err(|_| format!("'{ms}' isn't a number of milliseconds"))?;
+
+    Ok((command, Duration::from_millis(ms)))
+}
+
+// Only digits (and the wildcard, for patterns) matter when matching phone book entries.
+pub fn normalize_number(number: &str) -> String {
+    number.chars().filter(|c| c.is_ascii_digit() || *c == '*').collect()
+}
+
+// Exact numbers first, then the pattern with the most digits.
+fn pattern_order(pattern: &str) -> (bool, usize) {
+    (pattern.contains('*'), usize::MAX - pattern.chars().filter(|c| *c != '*').count())
+}
+
+fn number_matches(pattern: &str, number: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == number,
+        Some((prefix, rest)) => {
+            if !number.starts_with(prefix) {
+                return false;
+            }
+
+            let number = &number[prefix.len()..];
+
+            (0..=number.len()).any(|skip| number_matches(rest, &number[skip..]))
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum SettingSource {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl std::fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SettingSource::Default => write!(f, "default"),
+            SettingSource::File => write!(f, "file"),
+            SettingSource::Env => write!(f, "env"),
+            SettingSource::Cli => write!(f, "cli"),
+        }
+    }
+}
+
+// --connect-timeout is TOUCHPPP_CONNECT_TIMEOUT, and so on.
+pub fn env_name(long_name: &str) -> String {
+    format!("TOUCHPPP_{}", long_name.to_uppercase().replace('-', "_"))
+}
+
+pub fn env_flag(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" | "" => Some(false),
+        _ => None,
+    }
+}
+
+// Looks up each setting on the command line, then the environment, then the config file, remembering where it came from.
+struct Resolver<'a> {
+    params: &'a clap::ArgMatches,
+    sources: BTreeMap<String, SettingSource>,
+    // Values tcpser's options gave native ones, keyed by the native long name.
+    aliased: BTreeMap<String, String>,
+    // What each tcpser option was taken to mean, for the log.
+    tcpser_aliases: Vec<(String, String)>,
+}
+
+// clap names each setting after its field, so --log-file is log_file.
+fn arg_id(long_name: &str) -> String {
+    long_name.replace('-', "_")
+}
+
+// Names the setting the way it was given, so the message points at the typo.
+fn bad_value(long_name: &str, source: SettingSource, value: &str, problem: impl std::fmt::Display) -> String {
+    match source {
+        SettingSource::Env => format!("bad value '{value}' for {}: {problem}", env_name(long_name)),
+        _ => format!("bad value '{value}' for --{long_name}: {problem}"),
+    }
+}
+
+impl Resolver<'_> {
+    fn new(params: &clap::ArgMatches) -> Resolver<'_> {
+        Resolver {
+            params,
+            sources: BTreeMap::new(),
+            aliased: BTreeMap::new(),
+            tcpser_aliases: Vec::new(),
+        }
+    }
+
+    // The values as typed rather than clap's parsed ones, so every source goes through the same parsing below.
+    fn cli_values(&self, long_name: &str) -> Vec<String> {
+        match self.params.get_raw(&arg_id(long_name)) {
+            Some(values) => values.map(|value| value.to_string_lossy().to_string()).collect(),
+            None => self.aliased.get(long_name).cloned().into_iter().collect(),
+        }
+    }
+
+    fn is_on_cli(&self, long_name: &str) -> bool {
+        self.params.value_source(&arg_id(long_name)) == Some(clap::parser::ValueSource::CommandLine)
+    }
+
+    // A tcpser option standing in for a native one, as if `value` had been given to that on the command line.
+    // Giving both is a mistake rather than something for precedence to sort out.
+    fn alias(&mut self, alias: String, long_name: &str, value: String, means: String) -> Result<(), String> {
+        if self.is_on_cli(long_name) {
+            return Err(format!("{alias} is tcpser's way of saying {means}, so give it or --{long_name}, not both"));
+        }
+
+        self.aliased.insert(long_name.to_string(), value);
+        self.tcpser_aliases.push((alias, means));
+
+        Ok(())
+    }
+
+    // tcpser's -s, -p and -t, turned into the native options they stand for.
+    fn tcpser_aliases(&mut self) -> Result<(), String> {
+        let typed = |id: &str| self.params.get_raw(id).and_then(|mut values| values.next_back()).map(|value| value.to_string_lossy().to_string());
+
+        let speed = typed("tcpser_speed");
+        let port = typed("tcpser_port");
+        let trace = typed("tcpser_trace");
+
+        if let Some(speed) = speed {
+            self.alias(format!("-s {speed}"), "connect-speed", speed.clone(), format!("--connect-speed {speed}"))?;
+        }
+
+        if let Some(port) = port {
+            self.alias(format!("-p {port}"), "listen", port.clone(), format!("-l {port}"))?;
+        }
+
+        // Byte logging is at the trace level, which is -vv.
+        if let Some(flags) = trace {
+            self.alias(format!("-t{flags}"), "verbose", "2".to_string(), "-vv".to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn lookup(&self, long_name: &str) -> Option<(String, SettingSource)> {
+        if let Some(value) = self.cli_values(long_name).pop() {
+            return Some((value, SettingSource::Cli));
+        }
+
+        env::var(env_name(long_name)).ok().map(|value| (value, SettingSource::Env))
+    }
+
+    fn note(&mut self, long_name: &str, source: SettingSource) {
+        self.sources.insert(long_name.to_string(), source);
+    }
+
+    fn string(&mut self, long_name: &str, file: Option<String>) -> Option<String> {
+        match self.lookup(long_name) {
+            Some((value, source)) => {
+                self.note(long_name, source);
+
+                Some(value)
+            },
+            None => {
+                if file.is_some() {
+                    self.note(long_name, SettingSource::File);
+                }
+
+                file
+            },
+        }
+    }
+
+    // Lists come from repeating the option on the command line or from a comma separated environment variable.
+    fn strings(&mut self, long_name: &str) -> Option<(Vec<String>, SettingSource)> {
+        let values = self.cli_values(long_name);
+
+        let found = if !values.is_empty() {
+            Some((values, SettingSource::Cli))
+        } else {
+            env::var(env_name(long_name)).ok().map(|value| {
+                (value.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect(), SettingSource::Env)
+            })
+        };
+
+        if let Some((_, source)) = &found {
+            self.note(long_name, *source);
+        }
+
+        found
+    }
+
+    fn parsed<T: std::str::FromStr>(&mut self, long_name: &str, file: Option<T>) -> Result<Option<T>, Box<dyn std::error::Error>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match self.lookup(long_name) {
+            Some((value, source)) => match value.parse::<T>() {
+                Ok(r) => {
+                    self.note(long_name, source);
+
+                    Ok(Some(r))
+                },
+                Err(e) => Err(bad_value(long_name, source, &value, e).into()),
+            },
+            None => {
+                if file.is_some() {
+                    self.note(long_name, SettingSource::File);
+                }
+
+                Ok(file)
+            },
+        }
+    }
+
+    // Repeatable flags like -vv. The environment variable can be a count or a truthy value.
+    fn count(&mut self, long_name: &str, file: Option<u8>) -> Result<u8, Box<dyn std::error::Error>> {
+        let cli_count = match self.aliased.get(long_name) {
+            Some(count) => count.parse()?,
+            None => self.params.get_count(&arg_id(long_name)),
+        };
+        if cli_count > 0 {
+            self.note(long_name, SettingSource::Cli);
+
+            return Ok(cli_count);
+        }
+
+        if let Ok(value) = env::var(env_name(long_name)) {
+            let count = match value.trim().parse::<u8>() {
+                Ok(count) => count,
+                Err(_) => match env_flag(&value) {
+                    Some(is_set) => is_set as u8,
+                    None => return Err(format!("bad value '{value}' for {}: use a count or 1/true/yes or 0/false/no", env_name(long_name)).into()),
+                },
+            };
+
+            self.note(long_name, SettingSource::Env);
+
+            return Ok(count);
+        }
+
+        if file.is_some() {
+            self.note(long_name, SettingSource::File);
+        }
+
+        Ok(file.unwrap_or(0))
+    }
+
+    fn flag(&mut self, long_name: &str, file: Option<bool>) -> Result<bool, Box<dyn std::error::Error>> {
+        if self.params.get_flag(&arg_id(long_name)) {
+            self.note(long_name, SettingSource::Cli);
+
+            return Ok(true);
+        }
+
+        if let Ok(value) = env::var(env_name(long_name)) {
+            return match env_flag(&value) {
+                Some(is_set) => {
+                    self.note(long_name, SettingSource::Env);
+
+                    Ok(is_set)
+                },
+                None => Err(format!("bad value '{value}' for {}: use 1/true/yes or 0/false/no", env_name(long_name)).into()),
+            };
+        }
+
+        if file.is_some() {
+            self.note(long_name, SettingSource::File);
+        }
+
+        Ok(file.unwrap_or(false))
+    }
+}
+
+// Settings every [[modem]] shares, since there's one of each for the whole process. They can only be set
+// outside a [[modem]] section.
+const SHARED_SETTINGS: [&str; 25] = [
+    "silent", "verbose", "log_file", "log_max_size", "log_keep", "log_stdout", "log_format", "log_filter", "color",
+    "log_syslog", "syslog_facility", "daemon", "pid_file", "admin", "enable_inject", "status_http", "webhook",
+    "webhook_secret", "webhook_retries", "dial_log", "dial_log_sync", "state_file", "persist_dial_state",
+    "exit_after_sessions", "cdr_file",
+];
+
+// The config file as each modem sees it: just the file when it has no [[modem]] sections, otherwise the top level
+// with each section's settings on top, its backends added to the top level's.
+fn modem_files(config_path: &str, contents: &str) -> Result<Vec<(Option<String>, ConfigFile)>, String> {
+    let mut table: toml::Table = toml::from_str(contents).map_err(|e| format!("bad config file '{config_path}': {e}"))?;
+
+    let Some(modems) = table.remove("modem") else {
+        let file = toml::from_str(contents).map_err(|e| format!("bad config file '{config_path}': {e}"))?;
+
+        return Ok(vec![(None, file)]);
+    };
+
+    let toml::Value::Array(modems) = modems else {
+        return Err(format!("bad config file '{config_path}': modem has to be [[modem]] sections"));
+    };
+
+    let mut files: Vec<(Option<String>, ConfigFile)> = Vec::new();
+    for modem in modems {
+        let toml::Value::Table(mut section) = modem else {
+            return Err(format!("bad config file '{config_path}': modem has to be [[modem]] sections"));
+        };
+
+        let Some(toml::Value::String(name)) = section.remove("name") else {
+            return Err(format!("bad config file '{config_path}': every [[modem]] needs a name"));
+        };
+
+        if files.iter().any(|(seen, _)| seen.as_ref() == Some(&name)) {
+            return Err(format!("bad config file '{config_path}': there's more than one [[modem]] called '{name}'"));
+        }
+
+        if let Some(key) = section.keys().find(|key| SHARED_SETTINGS.contains(&key.as_str())) {
+            return Err(format!("bad config file '{config_path}': every modem shares {key}, so it goes outside [[modem]] '{name}'"));
+        }
+
+        let mut merged = table.clone();
+        for (key, value) in section {
+            if let (Some(toml::Value::Table(backends)), toml::Value::Table(more)) = (merged.get_mut("backend").filter(|_| key == "backend"), &value) {
+                backends.extend(more.clone());
+                continue;
+            }
+
+            merged.insert(key, value);
+        }
+
+        let file: ConfigFile = toml::Value::Table(merged).try_into().map_err(|e| format!("bad [[modem]] '{name}' in config file '{config_path}': {e}"))?;
+
+        files.push((Some(name), file));
+    }
+
+    if files.is_empty() {
+        return Err(format!("bad config file '{config_path}': modem has to be [[modem]] sections"));
+    }
+
+    Ok(files)
+}
+
+impl Config {
+    /// The one config the command line, environment and config file make. A config file with [[modem]] sections
+    /// makes more than one, which needs [`Config::load_all`].
+    pub fn load(params: &clap::ArgMatches) -> Result<Config, TouchPppError> {
+        let mut configs = Config::load_all(params)?;
+
+        match configs.len() {
+            1 => Ok(configs.remove(0)),
+            count => Err(TouchPppError::Config(format!("the config file has {count} [[modem]] sections, so there's a config for each"))),
+        }
+    }
+
+    /// A config for each [[modem]] section in the config file, or just the one if it hasn't got any.
+    pub fn load_all(params: &clap::ArgMatches) -> Result<Vec<Config>, TouchPppError> {
+        Config::resolve_all(params).map_err(|e| TouchPppError::Config(e.to_string()))
+    }
+
+    fn resolve_all(params: &clap::ArgMatches) -> Result<Vec<Config>, Box<dyn std::error::Error>> {
+        let config_path = Resolver::new(params).lookup("config").map(|(config_path, _)| config_path);
+
+        let files = match &config_path {
+            Some(config_path) => {
+                let contents = fs::read_to_string(config_path)
+                    .map_err(|e| format!("can't read config file '{config_path}': {e}"))?;
+
+                modem_files(config_path, &contents)?
+            },
+            None => vec![(None, ConfigFile::default())],
+        };
+
+        if files.len() > 1 {
+            if let Some((_, source)) = Resolver::new(params).lookup("listen") {
+                let given = match source {
+                    SettingSource::Env => env_name("listen"),
+                    _ => "-l".to_string(),
+                };
+
+                return Err(format!("{given} would have every [[modem]] listening in the same place, so give each one a listen of its own").into());
+            }
+        }
+
+        let configs = files.into_iter().map(|(modem, file)| Config::resolve(params, file, modem)).collect::<Result<Vec<Config>, _>>()?;
+
+        // Port 0 is a different port every time.
+        for (index, config) in configs.iter().enumerate() {
+            let listens_on = |config: &Config| config.listen_pipe.clone().unwrap_or_else(|| config.listen_address.to_string());
+
+            let same = configs[..index].iter().find(|other| listens_on(other) == listens_on(config) && (config.listen_pipe.is_some() || config.listen_address.port != 0));
+            if let Some(other) = same {
+                return Err(format!("[[modem]] '{}' and '{}' both listen on {}", other.modem.as_deref().unwrap_or_default(), config.modem.as_deref().unwrap_or_default(), listens_on(config)).into());
+            }
+        }
+
+        Ok(configs)
+    }
+
+    fn resolve(params: &clap::ArgMatches, file: ConfigFile, modem: Option<String>) -> Result<Config, Box<dyn std::error::Error>> {
+        let mut resolver = Resolver::new(params);
+
+        resolver.tcpser_aliases()?;
+
+        // Only so it's noted where the file came from.
+        resolver.string("config", None);
+
+        let mut builder = ConfigBuilder {
+            listen: resolver.string("listen", file.listen),
+            connect_timeout: resolver.parsed("connect-timeout", file.connect_timeout)?,
+            connect_retries: resolver.parsed("connect-retries", file.connect_retries)?,
+            remote_sticky: resolver.flag("remote-sticky", file.remote_sticky)?,
+            remote_exclusive: resolver.flag("remote-exclusive", file.remote_exclusive)?,
+            exec_concurrent: resolver.flag("exec-concurrent", file.exec_concurrent)?,
+            #[cfg(all(unix, feature = "ssh"))]
+            remote_ssh: resolver.string("remote-ssh", file.remote_ssh),
+            #[cfg(all(unix, feature = "ssh"))]
+            ssh_key: resolver.string("ssh-key", file.ssh_key),
+            #[cfg(all(unix, feature = "ssh"))]
+            ssh_insecure: resolver.flag("ssh-insecure", file.ssh_insecure)?,
+            #[cfg(all(unix, feature = "ssh"))]
+            ssh_persist: resolver.flag("ssh-persist", file.ssh_persist)?,
+            coalesce_bytes: resolver.parsed("coalesce-bytes", file.coalesce_bytes)?,
+            coalesce_ms: resolver.parsed("coalesce-ms", file.coalesce_ms)?,
+            low_latency: resolver.flag("low-latency", file.low_latency)?,
+            so_rcvbuf: resolver.parsed("so-rcvbuf", file.so_rcvbuf)?,
+            so_sndbuf: resolver.parsed("so-sndbuf", file.so_sndbuf)?,
+            bind_device: resolver.string("bind-device", file.bind_device),
+            dns: resolver.string("dns", file.dns),
+            default_connect: file.connect,
+            default_exec: file.exec,
+            default_udp: file.remote_udp,
+            backends: file.backend,
+            ..Default::default()
+        };
+
+        for (pattern, entry) in file.phonebook {
+            match entry {
+                PhoneBookEntry::Backend(name) => {
+                    builder.phone_book.insert(pattern, name);
+                },
+                PhoneBookEntry::Table(table) => {
+                    if let Some(name) = table.backend {
+                        builder.phone_book.insert(pattern.clone(), name);
+                    }
+
+                    builder.dial_overrides.insert(pattern, DialOverrides {
+                        connect_speed: table.connect_speed,
+                        carrier_speed: table.carrier_speed,
+                        force_56k: table.force_56k,
+                        dial_delay: table.dial_delay.map(Duration::from_millis),
+                        throttle: table.throttle,
+                        throttle_down: table.throttle_down,
+                        throttle_up: table.throttle_up,
+                    });
+                },
+            }
+        }
+
+        // -c, -e and --remote-udp (or their environment variables) skip the phone book. When -c and -e come from the
+        // same place -e wins, same as it always has, but --remote-udp alongside either is a mistake.
+        let connect = resolver.strings("connect");
+        let exec = resolver.lookup("exec");
+        let remote_udp = resolver.lookup("remote-udp");
+
+        if let Some(long_name) = ["connect", "exec"].into_iter().find(|long_name| resolver.is_on_cli("remote-udp") && resolver.is_on_cli(long_name)) {
+            return Err(format!("--remote-udp and --{long_name} both answer every call, so give one or the other").into());
+        }
+
+        // An empty -c or -e is a typo, not a request for the default remote.
+        if let Some((_, source)) = connect.as_ref().filter(|(socket_addresses, _)| socket_addresses.is_empty()) {
+            let value = env::var(env_name("connect")).unwrap_or_default();
+
+            return Err(bad_value("connect", *source, &value, "give at least one HOST[:PORT]").into());
+        }
+
+        if let Some((command, source)) = exec.as_ref().filter(|(command, _)| command.trim().is_empty()) {
+            return Err(bad_value("exec", *source, command, "give the command to run").into());
+        }
+
+        // --backend-builtin answers every call too, unless -c or -e came from somewhere that outranks it.
+        let builtin = resolver.parsed("backend-builtin", file.backend_builtin)?;
+        let builtin_source = resolver.sources.get("backend-builtin").copied().unwrap_or(SettingSource::Default);
+
+        let outranking_source = [connect.as_ref().map(|(_, source)| *source), exec.as_ref().map(|(_, source)| *source), remote_udp.as_ref().map(|(_, source)| *source)]
+            .into_iter()
+            .flatten()
+            .max();
+
+        if builtin.is_some() && outranking_source.is_some_and(|source| source > builtin_source) {
+            resolver.sources.remove("backend-builtin");
+        } else if builtin.is_some() {
+            resolver.sources.remove("connect");
+
+            builder.builtin = builtin;
+        }
+
+        if builder.builtin.is_none() && (connect.is_some() || exec.is_some() || remote_udp.is_some()) {
+            let connect_source = connect.as_ref().map(|(_, source)| *source).unwrap_or(SettingSource::Default);
+            let exec_source = exec.as_ref().map(|(_, source)| *source).unwrap_or(SettingSource::Default);
+            let udp_source = remote_udp.as_ref().map(|(_, source)| *source).unwrap_or(SettingSource::Default);
+
+            if udp_source > connect_source.max(exec_source) {
+                resolver.note("remote-udp", udp_source);
+                resolver.sources.remove("connect");
+
+                builder.remote_udp = remote_udp.map(|(remote, _)| remote);
+            } else if exec_source >= connect_source {
+                resolver.note("exec", exec_source);
+                resolver.sources.remove("connect");
+
+                builder.exec = exec.map(|(command, _)| command);
+            } else {
+                builder.connect = connect.map(|(socket_addresses, _)| socket_addresses).unwrap_or_default();
+            }
+        }
+
+        #[cfg(feature = "nat")]
+        {
+            builder.nat_pool = resolver.parsed("nat-pool", file.nat_pool)?;
+            builder.nat_dns = match resolver.strings("nat-dns") {
+                Some((servers, _)) => servers,
+                None => {
+                    if file.nat_dns.is_some() {
+                        resolver.note("nat-dns", SettingSource::File);
+                    }
+
+                    file.nat_dns.map(OneOrMany::into_vec).unwrap_or_default()
+                },
+            };
+        }
+
+        #[cfg(all(target_os = "linux", feature = "tun"))]
+        {
+            builder.tun_name = resolver.string("tun-name", file.tun_name);
+            builder.tun_local = resolver.parsed("tun-local", file.tun_local)?;
+            builder.tun_peer = resolver.parsed("tun-peer", file.tun_peer)?;
+            builder.tun_dns = match resolver.strings("tun-dns") {
+                Some((servers, _)) => servers,
+                None => {
+                    if file.tun_dns.is_some() {
+                        resolver.note("tun-dns", SettingSource::File);
+                    }
+
+                    file.tun_dns.map(OneOrMany::into_vec).unwrap_or_default()
+                },
+            };
+        }
+
+        // tcpser's -n is a phone book entry with a backend of its own.
+        let tcpser_numbers = resolver.cli_values("tcpser-number");
+        if !tcpser_numbers.is_empty() {
+            if let Some(long_name) = ["connect", "exec", "remote-udp", "backend-builtin"].into_iter().find(|long_name| resolver.is_on_cli(long_name)) {
+                return Err(format!("-n adds to the phone book, which --{long_name} skips, so give one or the other").into());
+            }
+        }
+
+        let mut tcpser_names = BTreeSet::new();
+        for entry in tcpser_numbers {
+            let Some((number, remote)) = entry.split_once('=') else {
+                return Err(bad_value("tcpser-number", SettingSource::Cli, &entry, "use NUMBER=HOST:PORT").into());
+            };
+
+            let name = format!("tcpser-{}", normalize_number(number));
+            if !tcpser_names.insert(name.clone()) {
+                return Err(format!("-n gives {number} more than once").into());
+            }
+
+            builder.phone_book.insert(number.to_string(), name.clone());
+            builder.backends.insert(name.clone(), BackendProfile { connect: Some(OneOrMany::One(remote.to_string())), ..Default::default() });
+            resolver.tcpser_aliases.push((format!("-n {entry}"), format!("a phone book entry sending {number} to backend '{name}', which connects to {remote}")));
+        }
+
+        builder.default_backend = resolver.string("backend", file.default_backend);
+        builder.health_check_interval = resolver.parsed("health-check-interval", file.health_check_interval)?;
+        builder.shutdown_timeout = resolver.parsed("shutdown-timeout", file.shutdown_timeout)?;
+        builder.drain_timeout = resolver.parsed("drain-timeout", file.drain_timeout)?;
+        builder.exit_after_sessions = resolver.parsed("exit-after-sessions", file.exit_after_sessions)?;
+        builder.connect_speed = resolver.parsed("connect-speed", file.connect_speed)?;
+        builder.carrier_speed = resolver.parsed("carrier-speed", file.carrier_speed)?;
+        builder.suppress_intermediates = resolver.flag("suppress-intermediates", file.suppress_intermediates)?;
+        builder.protocol_line = resolver.parsed("protocol-line", file.protocol_line)?;
+        // --throttle is both ways, for whichever way doesn't have one of its own.
+        let throttle = resolver.parsed("throttle", file.throttle)?;
+        builder.throttle_down = resolver.parsed("throttle-down", file.throttle_down)?.or(throttle);
+        builder.throttle_up = resolver.parsed("throttle-up", file.throttle_up)?.or(throttle);
+        builder.throttle_to_carrier = resolver.flag("throttle-to-carrier", file.throttle_to_carrier)?;
+        builder.report_throughput = resolver.flag("report-throughput", file.report_throughput)?;
+        builder.delay_after = resolver.parsed("delay-after", file.delay_after)?;
+        builder.delay_window = resolver.parsed("delay-window", file.delay_window)?;
+        builder.delay_cooldown = resolver.parsed("delay-cooldown", file.delay_cooldown)?;
+        builder.resolve = match resolver.strings("resolve") {
+            Some((overrides, _)) => overrides,
+            None => {
+                if file.resolve.is_some() {
+                    resolver.note("resolve", SettingSource::File);
+                }
+
+                file.resolve.map(OneOrMany::into_vec).unwrap_or_default()
+            },
+        };
+        builder.blacklist = match resolver.strings("blacklist") {
+            Some((numbers, _)) => numbers,
+            None => {
+                if file.blacklist.is_some() {
+                    resolver.note("blacklist", SettingSource::File);
+                }
+
+                file.blacklist.map(OneOrMany::into_vec).unwrap_or_default()
+            },
+        };
+        // --force-56k and --no-56k are the one setting, force_56k in the file. clap won't take both on the command
+        // line, but the environment can still say both.
+        builder.force_56k = match (resolver.flag("force-56k", None)?, resolver.flag("no-56k", None)?) {
+            (true, true) if resolver.is_on_cli("force-56k") => Some(true),
+            (true, true) if resolver.is_on_cli("no-56k") => Some(false),
+            (true, true) => return Err(format!("{} and {} can't both be set", env_name("force-56k"), env_name("no-56k")).into()),
+            (true, false) => Some(true),
+            (false, true) => Some(false),
+            (false, false) => {
+                if file.force_56k.is_some() {
+                    resolver.note("force-56k", SettingSource::File);
+                }
+
+                file.force_56k
+            },
+        };
+        if let Some(source) = resolver.sources.get("no-56k").copied() {
+            resolver.note("force-56k", source);
+        }
+        for (long_name, numbers, file_numbers) in [("56k-numbers", &mut builder.numbers_56k, file.numbers_56k), ("no-56k-numbers", &mut builder.no_56k_numbers, file.no_56k_numbers)] {
+            *numbers = match resolver.strings(long_name) {
+                Some((numbers, _)) => numbers,
+                None => {
+                    if file_numbers.is_some() {
+                        resolver.note(long_name, SettingSource::File);
+                    }
+
+                    file_numbers.map(OneOrMany::into_vec).unwrap_or_default()
+                },
+            };
+        }
+        builder.state_file = resolver.string("state-file", file.state_file);
+        builder.drop_after = resolver.parsed("drop-after", file.drop_after)?;
+        builder.drop_count = resolver.parsed("drop-count", file.drop_count)?;
+        builder.max_command_length = resolver.parsed("max-command-length", file.max_command_length)?;
+        builder.accept_rate = resolver.parsed("accept-rate", file.accept_rate)?;
+        builder.max_pending = resolver.parsed("max-pending", file.max_pending)?;
+        builder.command_delay = resolver.parsed("command-delay", file.command_delay)?;
+        builder.command_delay_for = match resolver.strings("command-delay-for") {
+            Some((delays, _)) => delays,
+            None => {
+                if file.command_delay_for.is_some() {
+                    resolver.note("command-delay-for", SettingSource::File);
+                }
+
+                file.command_delay_for.map(OneOrMany::into_vec).unwrap_or_default()
+            },
+        };
+        builder.profile = resolver.parsed("profile", file.profile)?;
+        builder.modem_profile = resolver.string("modem-profile", file.modem_profile);
+        builder.reset_forgets_number = resolver.flag("reset-forgets-number", file.reset_forgets_number)?;
+        builder.link_protocol = resolver.parsed("link-protocol", file.link_protocol)?;
+        builder.auto_data = resolver.parsed("auto-data", file.auto_data)?;
+        builder.at_passthrough = resolver.flag("at-passthrough", file.at_passthrough)?;
+        builder.is_silent = resolver.flag("silent", file.silent)?;
+        builder.verbosity = resolver.count("verbose", file.verbose)?;
+        builder.log_file = resolver.string("log-file", file.log_file);
+        builder.log_max_size = resolver.string("log-max-size", file.log_max_size);
+        builder.log_keep = resolver.parsed("log-keep", file.log_keep)?;
+        builder.log_stdout = resolver.flag("log-stdout", file.log_stdout)?;
+        builder.log_format = resolver.parsed("log-format", file.log_format)?;
+        builder.log_filter = resolver.string("log-filter", file.log_filter);
+        builder.color = resolver.parsed("color", file.color)?;
+        builder.log_syslog = resolver.string("log-syslog", file.log_syslog);
+        builder.syslog_facility = resolver.string("syslog-facility", file.syslog_facility);
+        builder.health_check = resolver.flag("health-check", file.health_check)?;
+        builder.resolve_at_start = resolver.flag("resolve-at-start", file.resolve_at_start)?;
+        builder.launch_mame = resolver.string("launch-mame", file.launch_mame);
+        builder.mame_slot = resolver.parsed("mame-slot", file.mame_slot)?;
+        builder.mame_restart = resolver.flag("mame-restart", file.mame_restart)?;
+        builder.daemon = resolver.flag("daemon", file.daemon)?;
+        builder.pid_file = resolver.string("pid-file", file.pid_file);
+        builder.admin = resolver.string("admin", file.admin);
+        builder.enable_inject = resolver.flag("enable-inject", file.enable_inject)?;
+        builder.status_http = resolver.string("status-http", file.status_http);
+        builder.webhook = resolver.string("webhook", file.webhook);
+        builder.webhook_secret = resolver.string("webhook-secret", file.webhook_secret);
+        builder.webhook_retries = resolver.parsed("webhook-retries", file.webhook_retries)?;
+        builder.dial_log = resolver.string("dial-log", file.dial_log);
+        builder.dial_log_sync = resolver.flag("dial-log-sync", file.dial_log_sync)?;
+        builder.cdr_file = resolver.string("cdr-file", file.cdr_file);
+        builder.at_transcript = resolver.string("at-transcript", file.at_transcript);
+
+        builder.modem = modem;
+        builder.sources = resolver.sources;
+        builder.tcpser_aliases = resolver.tcpser_aliases;
+
+        builder.check()
+    }
+
+    /// Settings to fill in by hand instead of from a command line, checked the same way.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    // Everything at its default except the backend, skipping the environment and config file. For running
+    // sessions in-process where whatever the user has set up shouldn't get a say.
+    pub fn for_backend(backend: Backend) -> Config {
+        Config {
+            modem: None,
+            listen_address: ListenAddr { host: DEFAULT_IP.to_string(), port: 0 },
+            listen_pipe: None,
+            is_silent: true,
+            verbosity: 0,
+            log_file: None,
+            log_max_size: logfile::DEFAULT_LOG_MAX_SIZE,
+            log_keep: logfile::DEFAULT_LOG_KEEP,
+            log_stdout: false,
+            log_format: LogFormat::Text,
+            log_filter: None,
+            color: Color::Auto,
+            log_syslog: None,
+            syslog_facility: syslog::DEFAULT_FACILITY,
+            health_check: false,
+            resolve_at_start: false,
+            health_check_interval: None,
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT),
+            drain_timeout: None,
+            exit_after_sessions: None,
+            connect_speed: at::DEFAULT_CONNECT_SPEED,
+            carrier_speed: CarrierSpeed::Auto,
+            suppress_intermediates: false,
+            protocol_line: None,
+            connect_report: at::ConnectReport::default(),
+            throttle: Throttle::default(),
+            throttle_to_carrier: false,
+            report_throughput: false,
+            delay_after: None,
+            delay_window: Duration::from_secs(DEFAULT_DELAY_WINDOW),
+            delay_cooldown: Duration::from_secs(DEFAULT_DELAY_COOLDOWN),
+            blacklist: Vec::new(),
+            force_56k: None,
+            numbers_56k: Vec::new(),
+            no_56k_numbers: Vec::new(),
+            state_file: None,
+            drop_after: None,
+            drop_count: None,
+            max_command_length: DEFAULT_MAX_COMMAND_LENGTH,
+            accept_rate: AcceptRate::DEFAULT,
+            max_pending: DEFAULT_MAX_PENDING,
+            command_delay: Duration::ZERO,
+            command_delays: Vec::new(),
+            profile: Profile::Webtv,
+            modem_profile: &preset::WEBTV_K56,
+            reset_forgets_number: false,
+            link_protocol: LinkProtocol::Ppp,
+            auto_data: None,
+            at_passthrough: false,
+            launch_mame: None,
+            mame_slot: MameSlot::Spot,
+            mame_restart: false,
+            daemon: false,
+            pid_file: None,
+            admin: None,
+            enable_inject: false,
+            status_http: None,
+            webhook: None,
+            webhook_secret: None,
+            webhook_retries: webhook::DEFAULT_WEBHOOK_RETRIES,
+            dial_log: None,
+            dial_log_sync: false,
+            cdr_file: None,
+            at_transcript: None,
+            backend_defaults: BackendDefaults {
+                connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+                connect_retries: 0,
+                remote_sticky: false,
+                remote_exclusive: false,
+                exec_concurrent: false,
+                coalesce_bytes: DEFAULT_COALESCE_BYTES,
+                coalesce_ms: DEFAULT_COALESCE_MS,
+                low_latency: false,
+                so_rcvbuf: None,
+                so_sndbuf: None,
+                bind_device: None,
+                resolver: Arc::default(),
+                #[cfg(all(unix, feature = "ssh"))]
+                ssh: None,
+            },
+            cli_backend: None,
+            default_backend: Arc::new(backend),
+            backends: BTreeMap::new(),
+            phone_book: Vec::new(),
+            dial_overrides: Vec::new(),
+            sources: BTreeMap::new(),
+            tcpser_aliases: Vec::new(),
+        }
+    }
+
+    // Command line -c/-e > phone book > default backend.
+    pub fn resolve_backend(&self, dialed_number: &str) -> Arc<Backend> {
+        if let Some(backend) = &self.cli_backend {
+            return backend.clone();
+        }
+
+        self.phone_book_backend(dialed_number).unwrap_or_else(|| self.default_backend.clone())
+    }
+
+    // The phone book's backend for a number, if it has one. The first match wins.
+    pub fn phone_book_backend(&self, dialed_number: &str) -> Option<Arc<Backend>> {
+        let dialed_number = normalize_number(dialed_number);
+        if dialed_number.is_empty() {
+            return None;
+        }
+
+        self.phone_book.iter().find(|(pattern, _)| number_matches(pattern, &dialed_number)).map(|(_, backend)| backend.clone())
+    }
+
+    // How long the answer to `at_string` waits: the longest --command-delay-for that matches one of its commands,
+    // by name alone (D) or with its argument (I3), or --command-delay if none do.
+    pub fn command_delay_for(&self, at_string: &str) -> Duration {
+        let tokens = at::tokenize(at_string);
+
+        self.command_delays.iter()
+            .filter(|(command, _)| tokens.iter().any(|token| *command == token.name || *command == format!("{}{}", token.name, token.argument)))
+            .map(|(_, delay)| *delay)
+            .max()
+            .unwrap_or(self.command_delay)
+    }
+
+    // How a dial to `dialed_number` goes: the first phone book entry with something to say about it, over the
+    // command line, over the defaults. Whether it's 56k goes by what the box's init string has said so far too.
+    pub fn dial_settings(&self, dialed_number: &str, modulation: &at::ModulationConfig) -> DialSettings {
+        let overrides = self.dial_overrides_for(dialed_number);
+        let is_56k = self.decide_56k(dialed_number, modulation);
+
+        DialSettings {
+            connect_report: connect_report_for(
+                overrides.connect_speed.unwrap_or(self.connect_speed),
+                overrides.carrier_speed.unwrap_or(self.carrier_speed),
+                is_56k.force_56k(),
+                self.modem_profile,
+                self.protocol_line,
+                !self.suppress_intermediates,
+            ),
+            dial_delay: overrides.dial_delay.unwrap_or_default(),
+            throttle: Throttle { down: overrides.throttle_down.or(overrides.throttle), up: overrides.throttle_up.or(overrides.throttle) }.or(self.throttle),
+            is_56k,
+        }
+    }
+
+    /// Whether a dial to `dialed_number` gets 56k, and why. --force-56k and --no-56k beat --56k-numbers and
+    /// --no-56k-numbers, which beat the phone book entry's force_56k, which beats the modem profile's. Past those
+    /// it's inferred: a bootstrap number never gets 56k, a carrier speed that's set is what it is, and with auto,
+    /// S51=31 or a +MS short of V.90 in `modulation` keeps it off and the modem profile's carrier decides the rest.
+    pub fn decide_56k(&self, dialed_number: &str, modulation: &at::ModulationConfig) -> Decision56k {
+        let normalized = normalize_number(dialed_number);
+        let is_listed = |patterns: &[String]| !normalized.is_empty() && patterns.iter().any(|pattern| number_matches(pattern, &normalized));
+        let overrides = self.dial_overrides_for(dialed_number);
+
+        if let Some(is_56k) = self.force_56k {
+            return Decision56k::forced(is_56k, "forced by flag");
+        }
+        if is_listed(&self.numbers_56k) {
+            return Decision56k::forced(true, "listed in --56k-numbers");
+        }
+        if is_listed(&self.no_56k_numbers) {
+            return Decision56k::forced(false, "listed in --no-56k-numbers");
+        }
+        if let Some(is_56k) = overrides.force_56k {
+            return Decision56k::forced(is_56k, "phone book entry");
+        }
+        if let Some(is_56k) = self.modem_profile.force_56k {
+            return Decision56k::forced(is_56k, "modem profile");
+        }
+        if BOOTSTRAP_NUMBERS.iter().any(|pattern| number_matches(pattern, &normalized)) {
+            return Decision56k::forced(false, "bootstrap number");
+        }
+
+        let v34 = at::Modulation::V34.top_speed();
+        match overrides.carrier_speed.unwrap_or(self.carrier_speed) {
+            CarrierSpeed::Fixed(speed) => Decision56k::inferred(speed > v34, "carrier speed set"),
+            CarrierSpeed::Auto if modulation.s51 == Some(at::S51_NO_56K) => Decision56k::inferred(false, "S51=31 seen"),
+            CarrierSpeed::Auto if !modulation.allows_56k() => Decision56k::inferred(false, "+MS holds it to V.34"),
+            CarrierSpeed::Auto => Decision56k::inferred(self.modem_profile.carrier_speed > v34, "modem profile's carrier speed"),
+        }
+    }
+
+    // The first phone book entry with something to say about how a dial to `dialed_number` goes, if there is one.
+    fn dial_overrides_for(&self, dialed_number: &str) -> DialOverrides {
+        let normalized = normalize_number(dialed_number);
+        if normalized.is_empty() {
+            return DialOverrides::default();
+        }
+
+        self.dial_overrides.iter().find(|(pattern, _)| number_matches(pattern, &normalized)).map(|(_, overrides)| *overrides).unwrap_or_default()
+    }
+
+    // Whether a number's never to be dialed, going by the same patterns the phone book uses.
+    pub fn is_blacklisted(&self, dialed_number: &str) -> bool {
+        let dialed_number = normalize_number(dialed_number);
+
+        self.blacklist.iter().any(|pattern| number_matches(pattern, &dialed_number))
+    }
+
+    // Every backend a dial could end up on, without duplicates.
+    pub fn reachable_backends(&self) -> Vec<Arc<Backend>> {
+        if let Some(backend) = &self.cli_backend {
+            return vec![backend.clone()];
+        }
+
+        let mut reachable: Vec<Arc<Backend>> = Vec::new();
+
+        let candidates = std::iter::once(&self.default_backend)
+            .chain(self.phone_book.iter().map(|(_, backend)| backend));
+
+        for backend in candidates {
+            if !reachable.iter().any(|b| Arc::ptr_eq(b, backend)) {
+                reachable.push(backend.clone());
+            }
+        }
+
+        reachable
+    }
+
+    // The config as it ended up, in config file form with where each setting came from. Backend env values
+    // that look like secrets are masked since this is meant to be pasted into bug reports.
+    pub fn to_toml(&self) -> String {
+        let mut toml = String::from("# What TouchPPP ended up with. Each setting says where it came from: default, file, env or cli.\n");
+
+        if let Some(modem) = &self.modem {
+            toml.push_str(&format!("# For [[modem]] '{modem}'.\n"));
+        }
+
+        let mut setting = |key: &str, long_name: &str, value: Option<toml::Value>| {
+            let source = self.sources.get(long_name).copied().unwrap_or(SettingSource::Default);
+
+            match value {
+                Some(value) => toml.push_str(&format!("{key} = {value}  # {source}\n")),
+                None => toml.push_str(&format!("# {key} isn't set ({source})\n")),
+            }
+        };
+
+        let listen = match &self.listen_pipe {
+            Some(pipe_name) => format!("{}{pipe_name}", address::PIPE_PREFIX),
+            None => self.listen_address.to_string(),
+        };
+        setting("listen", "listen", Some(listen.into()));
+
+        // The backend that isn't in the [backend] tables: -c/-e, or the file's own connect/exec when there's no default_backend.
+        let unnamed_backend = match &self.cli_backend {
+            Some(backend) => Some(backend),
+            None if !self.sources.contains_key("backend") => Some(&self.default_backend),
+            None => None,
+        };
+
+        match unnamed_backend.map(|backend| &backend.kind) {
+            Some(BackendKind::Remote(remote_ppp)) => setting("connect", "connect", Some(remote_addresses(remote_ppp))),
+            Some(BackendKind::Exec(local_ppp)) => setting("exec", "exec", Some(local_ppp.command.clone().into())),
+            Some(BackendKind::Udp(udp_ppp)) => setting("remote_udp", "remote-udp", Some(udp_ppp.address.to_string().into())),
+            Some(BackendKind::Echo) => setting("backend_builtin", "backend-builtin", Some("echo".into())),
+            Some(BackendKind::Null) => setting("backend_builtin", "backend-builtin", Some("null".into())),
+            #[cfg(feature = "nat")]
+            Some(BackendKind::Nat(nat)) => {
+                setting("backend_builtin", "backend-builtin", Some("nat".into()));
+                setting("nat_pool", "nat-pool", Some(nat.pool.to_string().into()));
+                setting("nat_dns", "nat-dns", Some(toml::Value::Array(nat.dns.iter().map(|server| server.to_string().into()).collect())));
+            },
+            #[cfg(all(target_os = "linux", feature = "tun"))]
+            Some(BackendKind::Tun(tun)) => {
+                setting("backend_builtin", "backend-builtin", Some("tun".into()));
+                setting("tun_name", "tun-name", Some(tun.name.clone().into()));
+                setting("tun_local", "tun-local", Some(tun.local.to_string().into()));
+                setting("tun_peer", "tun-peer", Some(tun.peer.to_string().into()));
+                setting("tun_dns", "tun-dns", Some(toml::Value::Array(tun.dns.iter().map(|server| server.to_string().into()).collect())));
+            },
+            _ => {},
+        }
+
+        if self.sources.contains_key("backend") {
+            setting("default_backend", "backend", Some(self.default_backend.name.clone().into()));
+        }
+
+        setting("connect_timeout", "connect-timeout", Some((self.backend_defaults.connect_timeout as i64).into()));
+        setting("connect_retries", "connect-retries", Some((self.backend_defaults.connect_retries as i64).into()));
+        setting("remote_sticky", "remote-sticky", Some(self.backend_defaults.remote_sticky.into()));
+        setting("remote_exclusive", "remote-exclusive", Some(self.backend_defaults.remote_exclusive.into()));
+        setting("exec_concurrent", "exec-concurrent", Some(self.backend_defaults.exec_concurrent.into()));
+        setting("coalesce_bytes", "coalesce-bytes", Some((self.backend_defaults.coalesce_bytes as i64).into()));
+        setting("coalesce_ms", "coalesce-ms", Some((self.backend_defaults.coalesce_ms as i64).into()));
+        setting("low_latency", "low-latency", Some(self.backend_defaults.low_latency.into()));
+        setting("so_rcvbuf", "so-rcvbuf", self.backend_defaults.so_rcvbuf.map(|size| (size as i64).into()));
+        setting("so_sndbuf", "so-sndbuf", self.backend_defaults.so_sndbuf.map(|size| (size as i64).into()));
+        setting("bind_device", "bind-device", self.backend_defaults.bind_device.clone().map(toml::Value::from));
+        setting("resolve", "resolve", Some(toml::Value::Array(self.backend_defaults.resolver.overrides().into_iter().map(toml::Value::from).collect())));
+        setting("dns", "dns", self.backend_defaults.resolver.dns_server().map(|server| server.to_string().into()));
+        #[cfg(all(unix, feature = "ssh"))]
+        {
+            let ssh = self.backend_defaults.ssh.as_ref();
+            setting("remote_ssh", "remote-ssh", ssh.map(|tunnel| tunnel.server.to_string().into()));
+            setting("ssh_key", "ssh-key", ssh.and_then(|tunnel| tunnel.key.as_ref()).map(|key| key.display().to_string().into()));
+            setting("ssh_insecure", "ssh-insecure", Some(ssh.is_some_and(|tunnel| tunnel.is_insecure).into()));
+            setting("ssh_persist", "ssh-persist", Some(ssh.is_some_and(|tunnel| tunnel.is_persistent).into()));
+        }
+        setting("health_check", "health-check", Some(self.health_check.into()));
+        setting("health_check_interval", "health-check-interval", self.health_check_interval.map(|interval| (interval.as_secs() as i64).into()));
+        setting("resolve_at_start", "resolve-at-start", Some(self.resolve_at_start.into()));
+        setting("shutdown_timeout", "shutdown-timeout", Some((self.shutdown_timeout.as_secs() as i64).into()));
+        setting("drain_timeout", "drain-timeout", self.drain_timeout.map(|timeout| (timeout.as_secs() as i64).into()));
+        setting("exit_after_sessions", "exit-after-sessions", self.exit_after_sessions.map(|sessions| (sessions as i64).into()));
+        setting("connect_speed", "connect-speed", Some((self.connect_speed as i64).into()));
+        setting("carrier_speed", "carrier-speed", Some(match self.carrier_speed {
+            CarrierSpeed::Auto => "auto".into(),
+            CarrierSpeed::Fixed(speed) => (speed as i64).into(),
+        }));
+        setting("suppress_intermediates", "suppress-intermediates", Some(self.suppress_intermediates.into()));
+        setting("protocol_line", "protocol-line", self.protocol_line.map(|protocol| protocol.to_string().into()));
+        setting("throttle_down", "throttle-down", self.throttle.down.map(|throttle| (throttle as i64).into()));
+        setting("throttle_up", "throttle-up", self.throttle.up.map(|throttle| (throttle as i64).into()));
+        setting("throttle_to_carrier", "throttle-to-carrier", Some(self.throttle_to_carrier.into()));
+        setting("report_throughput", "report-throughput", Some(self.report_throughput.into()));
+        setting("delay_after", "delay-after", self.delay_after.map(|count| (count as i64).into()));
+        setting("delay_window", "delay-window", Some((self.delay_window.as_secs() as i64).into()));
+        setting("delay_cooldown", "delay-cooldown", Some((self.delay_cooldown.as_secs() as i64).into()));
+        setting("blacklist", "blacklist", Some(toml::Value::Array(self.blacklist.iter().map(|number| number.clone().into()).collect())));
+        setting("force_56k", "force-56k", self.force_56k.map(toml::Value::from));
+        setting("56k_numbers", "56k-numbers", Some(toml::Value::Array(self.numbers_56k.iter().map(|number| number.clone().into()).collect())));
+        setting("no_56k_numbers", "no-56k-numbers", Some(toml::Value::Array(self.no_56k_numbers.iter().map(|number| number.clone().into()).collect())));
+        setting("state_file", "state-file", self.state_file.clone().map(|file| file.into()));
+        setting("drop_after", "drop-after", self.drop_after.map(|drop_after| drop_after.to_string().into()));
+        setting("drop_count", "drop-count", self.drop_count.map(|count| (count as i64).into()));
+        setting("max_command_length", "max-command-length", Some((self.max_command_length as i64).into()));
+        setting("accept_rate", "accept-rate", Some(self.accept_rate.to_string().into()));
+        setting("max_pending", "max-pending", Some((self.max_pending as i64).into()));
+        setting("command_delay", "command-delay", Some((self.command_delay.as_millis() as i64).into()));
+        setting("command_delay_for", "command-delay-for", Some(toml::Value::Array(self.command_delays.iter().map(|(command, delay)| format!("{command}={}", delay.as_millis()).into()).collect())));
+        setting("profile", "profile", Some(self.profile.to_string().into()));
+        setting("modem_profile", "modem-profile", Some(self.modem_profile.name.into()));
+        setting("reset_forgets_number", "reset-forgets-number", Some(self.reset_forgets_number.into()));
+        setting("link_protocol", "link-protocol", Some(self.link_protocol.to_string().into()));
+        setting("auto_data", "auto-data", self.auto_data.map(|auto_data| auto_data.to_string().into()));
+        setting("at_passthrough", "at-passthrough", Some(self.at_passthrough.into()));
+        setting("silent", "silent", Some(self.is_silent.into()));
+        setting("verbose", "verbose", Some((self.verbosity as i64).into()));
+        setting("log_file", "log-file", self.log_file.clone().map(|log_file| log_file.into()));
+        setting("log_max_size", "log-max-size", Some(logfile::format_size(self.log_max_size).into()));
+        setting("log_keep", "log-keep", Some((self.log_keep as i64).into()));
+        setting("log_stdout", "log-stdout", Some(self.log_stdout.into()));
+        setting("log_format", "log-format", Some(self.log_format.to_string().into()));
+        setting("log_filter", "log-filter", self.log_filter.clone().map(|log_filter| log_filter.into()));
+        setting("color", "color", Some(self.color.to_string().into()));
+        setting("log_syslog", "log-syslog", self.log_syslog.as_ref().map(|log_syslog| log_syslog.to_string().into()));
+        setting("syslog_facility", "syslog-facility", Some(self.syslog_facility.to_string().into()));
+        setting("launch_mame", "launch-mame", self.launch_mame.clone().map(|command| command.into()));
+        setting("mame_slot", "mame-slot", Some(self.mame_slot.to_string().into()));
+        setting("mame_restart", "mame-restart", Some(self.mame_restart.into()));
+        setting("daemon", "daemon", Some(self.daemon.into()));
+        setting("pid_file", "pid-file", self.pid_file.clone().map(|pid_file| pid_file.into()));
+        setting("admin", "admin", self.admin.as_ref().map(|admin| admin.to_string().into()));
+        setting("enable_inject", "enable-inject", Some(self.enable_inject.into()));
+        setting("status_http", "status-http", self.status_http.as_ref().map(|status_http| status_http.to_string().into()));
+        setting("webhook", "webhook", self.webhook.clone().map(|webhook| webhook.into()));
+        setting("webhook_secret", "webhook-secret", self.webhook_secret.as_ref().map(|_| "********".into()));
+        setting("webhook_retries", "webhook-retries", Some((self.webhook_retries as i64).into()));
+        setting("dial_log", "dial-log", self.dial_log.clone().map(|file| file.into()));
+        setting("dial_log_sync", "dial-log-sync", Some(self.dial_log_sync.into()));
+        setting("cdr_file", "cdr-file", self.cdr_file.clone().map(|path| path.into()));
+        setting("at_transcript", "at-transcript", self.at_transcript.clone().map(|at_transcript| at_transcript.into()));
+
+        for (name, backend) in self.backends.iter() {
+            toml.push_str(&format!("\n[backend.{}]  # file\n", toml_key(name)));
+
+            match &backend.kind {
+                BackendKind::Remote(remote_ppp) => {
+                    toml.push_str(&format!("connect = {}\n", remote_addresses(remote_ppp)));
+                    toml.push_str(&format!("connect_timeout = {}\n", remote_ppp.connect_timeout.as_secs()));
+                    toml.push_str(&format!("connect_retries = {}\n", remote_ppp.connect_retries));
+                    toml.push_str(&format!("remote_sticky = {}\n", remote_ppp.is_sticky));
+                    toml.push_str(&format!("exclusive = {}\n", remote_ppp.is_exclusive));
+                    let (coalesce_bytes, coalesce_ms) = remote_ppp.coalesce.map_or((0, 0), |coalesce| (coalesce.bytes, coalesce.wait.as_millis()));
+                    toml.push_str(&format!("coalesce_bytes = {coalesce_bytes}\ncoalesce_ms = {coalesce_ms}\n"));
+                },
+                BackendKind::Exec(local_ppp) => {
+                    toml.push_str(&format!("exec = {}\n", toml::Value::from(local_ppp.command.clone())));
+
+                    if !local_ppp.env.is_empty() {
+                        let env: toml::Table = local_ppp.env.iter().map(|(key, value)| {
+                            let value = if looks_secret(key) { "********".to_string() } else { value.clone() };
+
+                            (key.clone(), toml::Value::from(value))
+                        }).collect();
+
+                        toml.push_str(&format!("env = {}\n", toml::Value::Table(env)));
+                    }
+
+                    toml.push_str(&format!("exclusive = {}\n", local_ppp.is_exclusive));
+                },
+                BackendKind::Udp(udp_ppp) => {
+                    toml.push_str(&format!("udp = {}\n", toml::Value::from(udp_ppp.address.to_string())));
+                    toml.push_str(&format!("exclusive = {}\n", udp_ppp.is_exclusive));
+                },
+                BackendKind::Echo | BackendKind::Null | BackendKind::Custom(_) => {},
+                #[cfg(feature = "nat")]
+                BackendKind::Nat(_) => {},
+                #[cfg(all(target_os = "linux", feature = "tun"))]
+                BackendKind::Tun(_) => {},
+            }
+        }
+
+        if !self.phone_book.is_empty() || !self.dial_overrides.is_empty() {
+            toml.push_str("\n# Checked in this order; the first match wins.\n[phonebook]  # file\n");
+
+            let mut patterns: Vec<&String> = self.phone_book.iter().map(|(pattern, _)| pattern).chain(self.dial_overrides.iter().map(|(pattern, _)| pattern)).collect();
+            patterns.sort_by_key(|pattern| pattern_order(pattern));
+            patterns.dedup();
+
+            for pattern in patterns {
+                let backend = self.phone_book.iter().find(|(entry, _)| entry == pattern).map(|(_, backend)| toml::Value::from(backend.name.clone()));
+
+                let Some((_, overrides)) = self.dial_overrides.iter().find(|(entry, _)| entry == pattern) else {
+                    toml.push_str(&format!("{} = {}\n", toml_key(pattern), backend.unwrap()));
+                    continue;
+                };
+
+                let mut table = toml::Table::new();
+                let mut set = |key: &str, value: Option<toml::Value>| {
+                    if let Some(value) = value {
+                        table.insert(key.to_string(), value);
+                    }
+                };
+                set("backend", backend);
+                set("connect_speed", overrides.connect_speed.map(|speed| (speed as i64).into()));
+                set("carrier_speed", overrides.carrier_speed.map(|speed| match speed {
+                    CarrierSpeed::Auto => "auto".into(),
+                    CarrierSpeed::Fixed(speed) => (speed as i64).into(),
+                }));
+                set("force_56k", overrides.force_56k.map(Into::into));
+                set("dial_delay", overrides.dial_delay.map(|delay| (delay.as_millis() as i64).into()));
+                set("throttle", overrides.throttle.map(|throttle| (throttle as i64).into()));
+                set("throttle_down", overrides.throttle_down.map(|throttle| (throttle as i64).into()));
+                set("throttle_up", overrides.throttle_up.map(|throttle| (throttle as i64).into()));
+
+                toml.push_str(&format!("{} = {}\n", toml_key(pattern), toml::Value::Table(table)));
+            }
+        }
+
+        toml
+    }
+}
+
+fn remote_addresses(remote_ppp: &RemotePpp) -> toml::Value {
+    remote_ppp.socket_addresses.iter().map(|a| toml::Value::from(a.to_string())).collect::<Vec<toml::Value>>().into()
+}
+
+// Bare keys are fine for plain names, anything else (like phone book patterns with *) needs quotes.
+fn toml_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        toml::Value::from(key).to_string()
+    }
+}
+
+fn looks_secret(key: &str) -> bool {
+    let key = key.to_uppercase();
+
+    ["PASS", "SECRET", "TOKEN", "KEY"].iter().any(|word| key.contains(word))
+}
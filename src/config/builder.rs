@@ -0,0 +1,1256 @@
+// Turning settings into a Config. The command line, environment and config file are resolved into one of these
+// (see Config::load), and embedders fill one in directly, so both get exactly the same checks.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::*;
+
+/// Settings on their way to being a Config. Anything left unset gets the same default the command line would
+/// give it, and [`build`](ConfigBuilder::build) checks everything the way `touchppp --check` would.
+///
+/// ```
+/// use touchppp::ServerConfig;
+///
+/// let config = ServerConfig::builder()
+///     .listen("127.0.0.1:0")
+///     .remote_backend("isp", ["ppp.example.com:2323"])
+///     .phone_book("1800*", "isp")
+///     .connect("127.0.0.1:2323")
+///     .build()?;
+///
+/// assert_eq!(config.resolve_backend("5551212").name, "command line");
+/// # Ok::<(), touchppp::TouchPppError>(())
+/// ```
+#[derive(Default)]
+pub struct ConfigBuilder {
+    pub(super) modem: Option<String>,
+    pub(super) listen: Option<String>,
+    // The backend that answers every call, skipping the phone book: -c, -e, --remote-udp, --backend-builtin or a
+    // ready-made one.
+    pub(super) connect: Vec<String>,
+    pub(super) exec: Option<String>,
+    pub(super) remote_udp: Option<String>,
+    pub(super) builtin: Option<Builtin>,
+    pub(super) backend: Option<Backend>,
+    // Only for --backend-builtin nat.
+    #[cfg(feature = "nat")]
+    pub(super) nat_pool: Option<NatPool>,
+    #[cfg(feature = "nat")]
+    pub(super) nat_dns: Vec<String>,
+    // Only for --backend-builtin tun.
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub(super) tun_name: Option<String>,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub(super) tun_local: Option<Ipv4Addr>,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub(super) tun_peer: Option<Ipv4Addr>,
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub(super) tun_dns: Vec<String>,
+    // The config file's own connect/exec, which is the default backend when default_backend isn't set.
+    pub(super) default_connect: Option<OneOrMany>,
+    pub(super) default_exec: Option<String>,
+    pub(super) default_udp: Option<String>,
+    pub(super) default_backend: Option<String>,
+    pub(super) backends: BTreeMap<String, BackendProfile>,
+    pub(super) phone_book: BTreeMap<String, String>,
+    pub(super) dial_overrides: BTreeMap<String, DialOverrides>,
+    pub(super) connect_timeout: Option<u64>,
+    pub(super) connect_retries: Option<u32>,
+    pub(super) remote_sticky: bool,
+    pub(super) remote_exclusive: bool,
+    pub(super) exec_concurrent: bool,
+    pub(super) coalesce_bytes: Option<usize>,
+    pub(super) coalesce_ms: Option<u64>,
+    pub(super) low_latency: bool,
+    pub(super) so_rcvbuf: Option<usize>,
+    pub(super) so_sndbuf: Option<usize>,
+    pub(super) bind_device: Option<String>,
+    pub(super) resolve: Vec<String>,
+    pub(super) dns: Option<String>,
+    #[cfg(all(unix, feature = "ssh"))]
+    pub(super) remote_ssh: Option<String>,
+    #[cfg(all(unix, feature = "ssh"))]
+    pub(super) ssh_key: Option<String>,
+    #[cfg(all(unix, feature = "ssh"))]
+    pub(super) ssh_insecure: bool,
+    #[cfg(all(unix, feature = "ssh"))]
+    pub(super) ssh_persist: bool,
+    pub(super) health_check: bool,
+    pub(super) health_check_interval: Option<u64>,
+    pub(super) resolve_at_start: bool,
+    pub(super) shutdown_timeout: Option<u64>,
+    pub(super) drain_timeout: Option<u64>,
+    pub(super) exit_after_sessions: Option<u64>,
+    pub(super) connect_speed: Option<u32>,
+    pub(super) carrier_speed: Option<CarrierSpeed>,
+    pub(super) suppress_intermediates: bool,
+    pub(super) protocol_line: Option<Protocol>,
+    pub(super) throttle_down: Option<u32>,
+    pub(super) throttle_up: Option<u32>,
+    pub(super) throttle_to_carrier: bool,
+    pub(super) report_throughput: bool,
+    pub(super) delay_after: Option<u32>,
+    pub(super) delay_window: Option<u64>,
+    pub(super) delay_cooldown: Option<u64>,
+    pub(super) blacklist: Vec<String>,
+    pub(super) force_56k: Option<bool>,
+    pub(super) numbers_56k: Vec<String>,
+    pub(super) no_56k_numbers: Vec<String>,
+    pub(super) state_file: Option<String>,
+    pub(super) drop_after: Option<CarrierDrop>,
+    pub(super) drop_count: Option<u32>,
+    pub(super) max_command_length: Option<usize>,
+    pub(super) accept_rate: Option<AcceptRate>,
+    pub(super) max_pending: Option<usize>,
+    pub(super) command_delay: Option<u64>,
+    pub(super) command_delay_for: Vec<String>,
+    pub(super) profile: Option<Profile>,
+    pub(super) modem_profile: Option<String>,
+    pub(super) reset_forgets_number: bool,
+    pub(super) link_protocol: Option<LinkProtocol>,
+    pub(super) auto_data: Option<AutoData>,
+    pub(super) at_passthrough: bool,
+    pub(super) is_silent: bool,
+    pub(super) verbosity: u8,
+    pub(super) log_file: Option<String>,
+    pub(super) log_max_size: Option<String>,
+    pub(super) log_keep: Option<usize>,
+    pub(super) log_stdout: bool,
+    pub(super) log_format: Option<LogFormat>,
+    pub(super) log_filter: Option<String>,
+    pub(super) color: Option<Color>,
+    pub(super) log_syslog: Option<String>,
+    pub(super) syslog_facility: Option<String>,
+    pub(super) launch_mame: Option<String>,
+    pub(super) mame_slot: Option<MameSlot>,
+    pub(super) mame_restart: bool,
+    pub(super) daemon: bool,
+    pub(super) pid_file: Option<String>,
+    pub(super) admin: Option<String>,
+    pub(super) enable_inject: bool,
+    pub(super) status_http: Option<String>,
+    pub(super) webhook: Option<String>,
+    pub(super) webhook_secret: Option<String>,
+    pub(super) webhook_retries: Option<u32>,
+    pub(super) dial_log: Option<String>,
+    pub(super) dial_log_sync: bool,
+    pub(super) cdr_file: Option<String>,
+    pub(super) at_transcript: Option<String>,
+    pub(super) sources: BTreeMap<String, SettingSource>,
+    pub(super) tcpser_aliases: Vec<(String, String)>,
+}
+
+impl ConfigBuilder {
+    /// What to call this modem in the logs and metrics, like a [[modem]] section's name.
+    pub fn modem(mut self, name: impl Into<String>) -> ConfigBuilder {
+        self.modem = Some(name.into());
+        self
+    }
+
+    /// Where MAME calls, like -l: `[HOST:]PORT`, or `pipe:\\.\pipe\NAME` on Windows.
+    pub fn listen(mut self, listen: impl Into<String>) -> ConfigBuilder {
+        self.listen = Some(listen.into());
+        self
+    }
+
+    /// A remote PPP server that answers every call, like -c. More than one are tried in order.
+    pub fn connect(mut self, remote: impl Into<String>) -> ConfigBuilder {
+        self.connect.push(remote.into());
+        self
+    }
+
+    /// A local PPP program that answers every call, like -e.
+    pub fn exec(mut self, command: impl Into<String>) -> ConfigBuilder {
+        self.exec = Some(command.into());
+        self
+    }
+
+    /// A remote PPP server that answers every call a frame per datagram, like --remote-udp.
+    pub fn remote_udp(mut self, remote: impl Into<String>) -> ConfigBuilder {
+        self.remote_udp = Some(remote.into());
+        self
+    }
+
+    /// A built-in stand-in for PPP that answers every call, like --backend-builtin.
+    pub fn builtin(mut self, builtin: Builtin) -> ConfigBuilder {
+        self.builtin = Some(builtin);
+        self
+    }
+
+    /// The addresses the built-in nat backend hands out, like --nat-pool.
+    #[cfg(feature = "nat")]
+    pub fn nat_pool(mut self, pool: NatPool) -> ConfigBuilder {
+        self.nat_pool = Some(pool);
+        self
+    }
+
+    /// Where the built-in nat backend sends the box's DNS, like --nat-dns: `IP[:PORT]`. Only the first is used
+    /// for now; without any, it's the IPv4 name servers in /etc/resolv.conf.
+    #[cfg(feature = "nat")]
+    pub fn nat_dns(mut self, server: impl Into<String>) -> ConfigBuilder {
+        self.nat_dns.push(server.into());
+        self
+    }
+
+    /// The TUN device the built-in tun backend creates, like --tun-name.
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub fn tun_name(mut self, name: impl Into<String>) -> ConfigBuilder {
+        self.tun_name = Some(name.into());
+        self
+    }
+
+    /// Our end of the built-in tun backend's link, like --tun-local.
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub fn tun_local(mut self, address: Ipv4Addr) -> ConfigBuilder {
+        self.tun_local = Some(address);
+        self
+    }
+
+    /// The address the built-in tun backend gives the box, like --tun-peer.
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub fn tun_peer(mut self, address: Ipv4Addr) -> ConfigBuilder {
+        self.tun_peer = Some(address);
+        self
+    }
+
+    /// A name server the built-in tun backend tells the box about, like --tun-dns. Up to two.
+    #[cfg(all(target_os = "linux", feature = "tun"))]
+    pub fn tun_dns(mut self, server: impl Into<String>) -> ConfigBuilder {
+        self.tun_dns.push(server.into());
+        self
+    }
+
+    /// A ready-made backend that answers every call, such as a [`BackendKind::Custom`] one.
+    pub fn backend(mut self, backend: Backend) -> ConfigBuilder {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// A named backend reaching remote PPP servers, like a `[backend.NAME]` table with connect.
+    pub fn remote_backend<I: IntoIterator<Item = S>, S: Into<String>>(mut self, name: impl Into<String>, remotes: I) -> ConfigBuilder {
+        let remotes = remotes.into_iter().map(Into::into).collect();
+
+        self.backends.insert(name.into(), BackendProfile { connect: Some(OneOrMany::Many(remotes)), ..Default::default() });
+        self
+    }
+
+    /// A named backend launching a local PPP program, like a `[backend.NAME]` table with exec.
+    pub fn exec_backend(mut self, name: impl Into<String>, command: impl Into<String>) -> ConfigBuilder {
+        self.backends.insert(name.into(), BackendProfile { exec: Some(command.into()), ..Default::default() });
+        self
+    }
+
+    /// Which named backend takes calls the phone book doesn't match, like --backend.
+    pub fn default_backend(mut self, name: impl Into<String>) -> ConfigBuilder {
+        self.default_backend = Some(name.into());
+        self
+    }
+
+    /// Sends numbers matching `pattern` (like "1800*") to the named backend, like a `[phonebook]` entry.
+    pub fn phone_book(mut self, pattern: impl Into<String>, backend: impl Into<String>) -> ConfigBuilder {
+        self.phone_book.insert(pattern.into(), backend.into());
+        self
+    }
+
+    /// Changes how dials to numbers matching `pattern` go, like a `[phonebook]` entry with a table. The backend's
+    /// still whatever the rest of the phone book (or the default) gives, unless [`phone_book`](ConfigBuilder::phone_book) has
+    /// the same pattern.
+    pub fn dial_overrides(mut self, pattern: impl Into<String>, overrides: DialOverrides) -> ConfigBuilder {
+        self.dial_overrides.insert(pattern.into(), overrides);
+        self
+    }
+
+    pub fn connect_timeout(mut self, seconds: u64) -> ConfigBuilder {
+        self.connect_timeout = Some(seconds);
+        self
+    }
+
+    pub fn connect_retries(mut self, retries: u32) -> ConfigBuilder {
+        self.connect_retries = Some(retries);
+        self
+    }
+
+    pub fn remote_sticky(mut self, is_sticky: bool) -> ConfigBuilder {
+        self.remote_sticky = is_sticky;
+        self
+    }
+
+    /// Gives remote backends one call at a time, like --remote-exclusive.
+    pub fn remote_exclusive(mut self, is_exclusive: bool) -> ConfigBuilder {
+        self.remote_exclusive = is_exclusive;
+        self
+    }
+
+    /// Lets exec backends take more than one call at a time, like --exec-concurrent.
+    pub fn exec_concurrent(mut self, is_concurrent: bool) -> ConfigBuilder {
+        self.exec_concurrent = is_concurrent;
+        self
+    }
+
+    /// How many of MAME's bytes a remote backend gathers up before sending them on, like --coalesce-bytes. 0 sends
+    /// each read as it comes.
+    pub fn coalesce_bytes(mut self, bytes: usize) -> ConfigBuilder {
+        self.coalesce_bytes = Some(bytes);
+        self
+    }
+
+    /// The longest a remote backend holds MAME's bytes back, like --coalesce-ms.
+    pub fn coalesce_ms(mut self, ms: u64) -> ConfigBuilder {
+        self.coalesce_ms = Some(ms);
+        self
+    }
+
+    /// Sets TCP_NODELAY on MAME's connection and the remote server's, with no coalescing, like --low-latency.
+    pub fn low_latency(mut self, low_latency: bool) -> ConfigBuilder {
+        self.low_latency = low_latency;
+        self
+    }
+
+    /// SO_RCVBUF for MAME's connection and the remote server's, like --so-rcvbuf.
+    pub fn so_rcvbuf(mut self, bytes: usize) -> ConfigBuilder {
+        self.so_rcvbuf = Some(bytes);
+        self
+    }
+
+    /// Reaches remote backends through an SSH server (USER@HOST[:PORT]), like --remote-ssh.
+    #[cfg(all(unix, feature = "ssh"))]
+    pub fn remote_ssh(mut self, server: impl Into<String>) -> ConfigBuilder {
+        self.remote_ssh = Some(server.into());
+        self
+    }
+
+    /// Logs in to --remote-ssh with this private key instead of the agent, like --ssh-key.
+    #[cfg(all(unix, feature = "ssh"))]
+    pub fn ssh_key(mut self, file: impl Into<String>) -> ConfigBuilder {
+        self.ssh_key = Some(file.into());
+        self
+    }
+
+    /// Skips checking --remote-ssh's host key, like --ssh-insecure.
+    #[cfg(all(unix, feature = "ssh"))]
+    pub fn ssh_insecure(mut self, is_insecure: bool) -> ConfigBuilder {
+        self.ssh_insecure = is_insecure;
+        self
+    }
+
+    /// Stays logged in to --remote-ssh between dials, like --ssh-persist.
+    #[cfg(all(unix, feature = "ssh"))]
+    pub fn ssh_persist(mut self, is_persistent: bool) -> ConfigBuilder {
+        self.ssh_persist = is_persistent;
+        self
+    }
+
+    /// SO_SNDBUF for MAME's connection and the remote server's, like --so-sndbuf.
+    pub fn so_sndbuf(mut self, bytes: usize) -> ConfigBuilder {
+        self.so_sndbuf = Some(bytes);
+        self
+    }
+
+    /// Sends everything for remote servers out over this network interface, like --bind-device.
+    pub fn bind_device(mut self, device: impl Into<String>) -> ConfigBuilder {
+        self.bind_device = Some(device.into());
+        self
+    }
+
+    /// Looks `name` up as `ip` (NAME=IP) instead of asking DNS, like --resolve.
+    pub fn resolve(mut self, entry: impl Into<String>) -> ConfigBuilder {
+        self.resolve.push(entry.into());
+        self
+    }
+
+    /// Looks remote servers' names up with this name server (IP[:PORT]), like --dns.
+    pub fn dns(mut self, server: impl Into<String>) -> ConfigBuilder {
+        self.dns = Some(server.into());
+        self
+    }
+
+    pub fn health_check(mut self, health_check: bool) -> ConfigBuilder {
+        self.health_check = health_check;
+        self
+    }
+
+    pub fn health_check_interval(mut self, seconds: u64) -> ConfigBuilder {
+        self.health_check_interval = Some(seconds);
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, seconds: u64) -> ConfigBuilder {
+        self.shutdown_timeout = Some(seconds);
+        self
+    }
+
+    /// How long a drain waits for calls to end before hanging up on them, like --drain-timeout.
+    pub fn drain_timeout(mut self, seconds: u64) -> ConfigBuilder {
+        self.drain_timeout = Some(seconds);
+        self
+    }
+
+    /// How many sessions to take before stopping, like --exit-after-sessions.
+    pub fn exit_after_sessions(mut self, sessions: u64) -> ConfigBuilder {
+        self.exit_after_sessions = Some(sessions);
+        self
+    }
+
+    /// The DTE rate CONNECT reports, like --connect-speed.
+    pub fn connect_speed(mut self, speed: u32) -> ConfigBuilder {
+        self.connect_speed = Some(speed);
+        self
+    }
+
+    /// The CARRIER speed CONNECT reports, like --carrier-speed.
+    pub fn carrier_speed(mut self, speed: CarrierSpeed) -> ConfigBuilder {
+        self.carrier_speed = Some(speed);
+        self
+    }
+
+    /// Leaves COMPRESSION out of what CONNECT reports, like --suppress-intermediates.
+    pub fn suppress_intermediates(mut self, suppress: bool) -> ConfigBuilder {
+        self.suppress_intermediates = suppress;
+        self
+    }
+
+    /// Adds a PROTOCOL line to what CONNECT reports, like --protocol-line.
+    pub fn protocol_line(mut self, protocol: Protocol) -> ConfigBuilder {
+        self.protocol_line = Some(protocol);
+        self
+    }
+
+    /// Holds data mode to this many bits per second each way, like --throttle.
+    pub fn throttle(self, bits_per_second: u32) -> ConfigBuilder {
+        self.throttle_down(bits_per_second).throttle_up(bits_per_second)
+    }
+
+    /// Holds what goes to the box to this many bits per second, like --throttle-down.
+    pub fn throttle_down(mut self, bits_per_second: u32) -> ConfigBuilder {
+        self.throttle_down = Some(bits_per_second);
+        self
+    }
+
+    /// Holds what the box sends to this many bits per second, like --throttle-up.
+    pub fn throttle_up(mut self, bits_per_second: u32) -> ConfigBuilder {
+        self.throttle_up = Some(bits_per_second);
+        self
+    }
+
+    /// Holds data mode to the carrier speed CONNECT reports, like --throttle-to-carrier.
+    pub fn throttle_to_carrier(mut self, throttle: bool) -> ConfigBuilder {
+        self.throttle_to_carrier = throttle;
+        self
+    }
+
+    /// Tells MAME how a call did ahead of NO CARRIER, like --report-throughput.
+    pub fn report_throughput(mut self, report: bool) -> ConfigBuilder {
+        self.report_throughput = report;
+        self
+    }
+
+    /// Answers DELAYED once a number's failed this many times in a row, like --delay-after.
+    pub fn delay_after(mut self, failures: u32) -> ConfigBuilder {
+        self.delay_after = Some(failures);
+        self
+    }
+
+    pub fn delay_window(mut self, seconds: u64) -> ConfigBuilder {
+        self.delay_window = Some(seconds);
+        self
+    }
+
+    pub fn delay_cooldown(mut self, seconds: u64) -> ConfigBuilder {
+        self.delay_cooldown = Some(seconds);
+        self
+    }
+
+    /// Answers BLACKLISTED to numbers matching `pattern` (like "1900*"), like --blacklist.
+    pub fn blacklist(mut self, pattern: impl Into<String>) -> ConfigBuilder {
+        self.blacklist.push(pattern.into());
+        self
+    }
+
+    /// Reports a 56k CARRIER on every dial, or never does, like --force-56k and --no-56k.
+    pub fn force_56k(mut self, is_56k: bool) -> ConfigBuilder {
+        self.force_56k = Some(is_56k);
+        self
+    }
+
+    /// Reports 56k to numbers matching `pattern` whatever else says not to, like --56k-numbers.
+    pub fn numbers_56k(mut self, pattern: impl Into<String>) -> ConfigBuilder {
+        self.numbers_56k.push(pattern.into());
+        self
+    }
+
+    /// Never reports 56k to numbers matching `pattern`, like --no-56k-numbers.
+    pub fn no_56k_numbers(mut self, pattern: impl Into<String>) -> ConfigBuilder {
+        self.no_56k_numbers.push(pattern.into());
+        self
+    }
+
+    /// Keeps what's learned about each number in `file` between runs, like --state-file.
+    pub fn state_file(mut self, file: impl Into<String>) -> ConfigBuilder {
+        self.state_file = Some(file.into());
+        self
+    }
+
+    /// Drops the carrier this long after CONNECT, like --drop-after.
+    pub fn drop_after(mut self, drop_after: CarrierDrop) -> ConfigBuilder {
+        self.drop_after = Some(drop_after);
+        self
+    }
+
+    pub fn drop_count(mut self, count: u32) -> ConfigBuilder {
+        self.drop_count = Some(count);
+        self
+    }
+
+    /// How many connections each address can make in how long, like --accept-rate.
+    pub fn accept_rate(mut self, accept_rate: AcceptRate) -> ConfigBuilder {
+        self.accept_rate = Some(accept_rate);
+        self
+    }
+
+    /// How many connections can be waiting on their first AT command, like --max-pending. 0 is no limit.
+    pub fn max_pending(mut self, max_pending: usize) -> ConfigBuilder {
+        self.max_pending = Some(max_pending);
+        self
+    }
+
+    /// Answers ERROR to AT command lines longer than this, like --max-command-length.
+    pub fn max_command_length(mut self, length: usize) -> ConfigBuilder {
+        self.max_command_length = Some(length);
+        self
+    }
+
+    /// How long result codes wait before they're sent, in milliseconds, like --command-delay.
+    pub fn command_delay(mut self, ms: u64) -> ConfigBuilder {
+        self.command_delay = Some(ms);
+        self
+    }
+
+    /// Waits `ms` instead to answer a line with `command` in it (like "I3"), like --command-delay-for.
+    pub fn command_delay_for(mut self, command: impl Into<String>, ms: u64) -> ConfigBuilder {
+        self.command_delay_for.push(format!("{}={ms}", command.into()));
+        self
+    }
+
+    /// Which modem callers get, like --profile.
+    pub fn profile(mut self, profile: Profile) -> ConfigBuilder {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Which modem it is when it's asked, one of the presets, like --modem-profile.
+    pub fn modem_profile(mut self, name: impl Into<String>) -> ConfigBuilder {
+        self.modem_profile = Some(name.into());
+        self
+    }
+
+    /// Has ATZ and AT&F forget the number ATDL dials again, like --reset-forgets-number.
+    pub fn reset_forgets_number(mut self, forgets: bool) -> ConfigBuilder {
+        self.reset_forgets_number = forgets;
+        self
+    }
+
+    /// What the box speaks once it's online, like --link-protocol.
+    pub fn link_protocol(mut self, link_protocol: LinkProtocol) -> ConfigBuilder {
+        self.link_protocol = Some(link_protocol);
+        self
+    }
+
+    /// Goes online when PPP shows up in command mode without a dial, like --auto-data.
+    pub fn auto_data(mut self, auto_data: AutoData) -> ConfigBuilder {
+        self.auto_data = Some(auto_data);
+        self
+    }
+
+    /// Passes the AT commands through to the backend's modem instead of answering them, like --at-passthrough.
+    pub fn at_passthrough(mut self, at_passthrough: bool) -> ConfigBuilder {
+        self.at_passthrough = at_passthrough;
+        self
+    }
+
+    pub fn webhook(mut self, url: impl Into<String>) -> ConfigBuilder {
+        self.webhook = Some(url.into());
+        self
+    }
+
+    /// Appends a JSON line to `file` for every dial and how each call ended, like --dial-log.
+    pub fn dial_log(mut self, file: impl Into<String>) -> ConfigBuilder {
+        self.dial_log = Some(file.into());
+        self
+    }
+
+    /// Syncs the --dial-log to disk after every line, like --dial-log-sync.
+    pub fn dial_log_sync(mut self, sync: bool) -> ConfigBuilder {
+        self.dial_log_sync = sync;
+        self
+    }
+
+    /// Writes a CDR for every session to `path`, a file or a directory, like --cdr-file.
+    pub fn cdr_file(mut self, path: impl Into<String>) -> ConfigBuilder {
+        self.cdr_file = Some(path.into());
+        self
+    }
+
+    pub fn admin(mut self, admin: impl Into<String>) -> ConfigBuilder {
+        self.admin = Some(admin.into());
+        self
+    }
+
+    /// Lets the admin interface inject bytes into calls, like --enable-inject.
+    pub fn enable_inject(mut self, enable: bool) -> ConfigBuilder {
+        self.enable_inject = enable;
+        self
+    }
+
+    pub fn status_http(mut self, status_http: impl Into<String>) -> ConfigBuilder {
+        self.status_http = Some(status_http.into());
+        self
+    }
+
+    pub fn at_transcript(mut self, directory: impl Into<String>) -> ConfigBuilder {
+        self.at_transcript = Some(directory.into());
+        self
+    }
+
+    /// Checks everything and gives back the Config, or the first thing wrong with it.
+    pub fn build(self) -> Result<Config, TouchPppError> {
+        self.check().map_err(|e| TouchPppError::Config(e.to_string()))
+    }
+
+    pub(super) fn check(mut self) -> Result<Config, Box<dyn std::error::Error>> {
+        let default_listen_address = ListenAddr { host: DEFAULT_IP.to_string(), port: DEFAULT_LISTEN_PORT };
+        let (listen_address, listen_pipe) = match self.listen {
+            Some(listen) if listen.starts_with(address::PIPE_PREFIX) => {
+                (default_listen_address, Some(address::parse_pipe(&listen).map_err(|e| format!("bad listen pipe: {e}"))?))
+            },
+            Some(listen) => (address::parse_listen(&listen).map_err(|e| format!("bad listen address: {e}"))?, None),
+            None => (default_listen_address, None),
+        };
+
+        // Nothing's connected until a dial needs it, but a key that isn't there is worth hearing about now.
+        #[cfg(all(unix, feature = "ssh"))]
+        let ssh = match self.remote_ssh {
+            Some(remote_ssh) => {
+                let server = address::parse_ssh(&remote_ssh).map_err(|e| format!("bad --remote-ssh: {e}"))?;
+
+                if let Some(key) = &self.ssh_key {
+                    std::fs::metadata(key).map_err(|e| format!("can't read --ssh-key '{key}': {e}"))?;
+                }
+
+                Some(Arc::new(crate::ssh::Tunnel::new(server, self.ssh_key.map(std::path::PathBuf::from), self.ssh_insecure, self.ssh_persist).with_bind_device(self.bind_device.clone())))
+            },
+            None if self.ssh_key.is_some() || self.ssh_insecure || self.ssh_persist => {
+                return Err("--ssh-key, --ssh-insecure and --ssh-persist only go with --remote-ssh".into());
+            },
+            None => None,
+        };
+
+        let defaults = BackendDefaults {
+            connect_timeout: self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            connect_retries: self.connect_retries.unwrap_or(0),
+            remote_sticky: self.remote_sticky,
+            remote_exclusive: self.remote_exclusive,
+            exec_concurrent: self.exec_concurrent,
+            coalesce_bytes: self.coalesce_bytes.unwrap_or(DEFAULT_COALESCE_BYTES),
+            coalesce_ms: self.coalesce_ms.unwrap_or(DEFAULT_COALESCE_MS),
+            low_latency: self.low_latency,
+            so_rcvbuf: self.so_rcvbuf,
+            so_sndbuf: self.so_sndbuf,
+            bind_device: self.bind_device,
+            resolver: Arc::new(NameResolver::new(&self.resolve, self.dns.as_deref())?),
+            #[cfg(all(unix, feature = "ssh"))]
+            ssh,
+        };
+        check_coalesce(defaults.coalesce_bytes, defaults.coalesce_ms).map_err(|e| format!("bad --coalesce-{e}"))?;
+        check_socket_buffer("so-rcvbuf", defaults.so_rcvbuf)?;
+        check_socket_buffer("so-sndbuf", defaults.so_sndbuf)?;
+        if let Some(device) = &defaults.bind_device {
+            check_device(device)?;
+        }
+
+        let mut backends = BTreeMap::new();
+        for (name, profile) in self.backends {
+            let backend = build_backend(&name, profile, &defaults)?;
+
+            backends.insert(name, Arc::new(backend));
+        }
+
+        let overrides = [!self.connect.is_empty(), self.exec.is_some(), self.remote_udp.is_some(), self.builtin.is_some(), self.backend.is_some()];
+        if overrides.iter().filter(|is_set| **is_set).count() > 1 {
+            return Err("only one of connect, exec, remote UDP, a built-in backend or a ready-made backend can answer every call".into());
+        }
+
+        let cli_backend = match (self.backend, self.builtin) {
+            (Some(backend), _) => Some(Arc::new(backend)),
+            (None, Some(builtin)) => Some(Arc::new(Backend {
+                name: "builtin".to_string(),
+                kind: match builtin {
+                    Builtin::Echo => BackendKind::Echo,
+                    Builtin::Null => BackendKind::Null,
+                    #[cfg(feature = "nat")]
+                    Builtin::Nat => BackendKind::Nat(build_nat(self.nat_pool, self.nat_dns)?),
+                    #[cfg(all(target_os = "linux", feature = "tun"))]
+                    Builtin::Tun => BackendKind::Tun(build_tun(self.tun_name, self.tun_local, self.tun_peer, self.tun_dns)?),
+                },
+            })),
+            (None, None) if !self.connect.is_empty() || self.exec.is_some() || self.remote_udp.is_some() => {
+                let (profile, source) = match (self.exec, self.remote_udp) {
+                    (Some(command), _) => (BackendProfile { exec: Some(command), ..Default::default() }, self.sources.get("exec")),
+                    (None, Some(remote)) => (BackendProfile { udp: Some(remote), ..Default::default() }, self.sources.get("remote-udp")),
+                    (None, None) => (BackendProfile { connect: Some(OneOrMany::Many(self.connect)), ..Default::default() }, self.sources.get("connect")),
+                };
+
+                let name = match source {
+                    Some(SettingSource::Env) => "environment",
+                    _ => "command line",
+                };
+
+                Some(Arc::new(build_backend(name, profile, &defaults)?))
+            },
+            (None, None) => None,
+        };
+
+        let default_backend = match self.default_backend {
+            Some(name) => match backends.get(&name) {
+                Some(backend) => backend.clone(),
+                None => return Err(format!("default backend '{name}' isn't defined in the config file").into()),
+            },
+            None => {
+                let mut profile = BackendProfile { connect: self.default_connect, exec: self.default_exec, udp: self.default_udp, ..Default::default() };
+
+                // -c or -e may have already claimed these names, and they'd be the ones in effect.
+                if profile.exec.is_some() {
+                    profile.connect = None;
+                    profile.udp = None;
+                    self.sources.entry("exec".to_string()).or_insert(SettingSource::File);
+                } else if profile.udp.is_some() {
+                    profile.connect = None;
+                    self.sources.entry("remote-udp".to_string()).or_insert(SettingSource::File);
+                } else if profile.connect.is_none() {
+                    profile.connect = Some(OneOrMany::One(format!("{}:{}", DEFAULT_IP, DEFAULT_REMOTE_PORT)));
+
+                    if cli_backend.is_none() {
+                        self.sources.entry("connect".to_string()).or_insert(SettingSource::Default);
+                    }
+                } else {
+                    self.sources.entry("connect".to_string()).or_insert(SettingSource::File);
+                }
+
+                Arc::new(build_backend("default", profile, &defaults)?)
+            },
+        };
+
+        let mut phone_book = Vec::new();
+        for (pattern, name) in self.phone_book {
+            let backend = match backends.get(&name) {
+                Some(backend) => backend.clone(),
+                None => return Err(format!("phone book entry '{pattern}' points to backend '{name}' which isn't defined").into()),
+            };
+
+            let normalized_pattern = normalize_number(&pattern);
+            if normalized_pattern.is_empty() {
+                return Err(format!("phone book entry '{pattern}' doesn't have any digits to match").into());
+            }
+
+            phone_book.push((normalized_pattern, backend));
+        }
+
+        phone_book.sort_by_key(|(pattern, _)| pattern_order(pattern));
+
+        let health_check_interval = self.health_check_interval.map(|seconds| Duration::from_secs(seconds.max(HEALTH_CHECK_MIN_INTERVAL)));
+
+        let log_max_size = match self.log_max_size {
+            Some(size) => logfile::parse_size(&size).map_err(|e| format!("bad value for log-max-size: {e}"))?,
+            None => logfile::DEFAULT_LOG_MAX_SIZE,
+        };
+        if let Some(log_filter) = &self.log_filter {
+            crate::server::log_filter("info", log_filter)?;
+        }
+        let log_syslog = match self.log_syslog {
+            Some(log_syslog) => Some(address::parse_syslog(&log_syslog).map_err(|e| format!("bad syslog address: {e}"))?),
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if let Some(SyslogAddr::Unix(path)) = &log_syslog {
+            return Err(format!("--log-syslog {path}: unix sockets only work on unix, use a syslog server's address").into());
+        }
+        let syslog_facility = match self.syslog_facility {
+            Some(facility) => facility.parse().map_err(|e| format!("bad value '{facility}' for syslog-facility: {e}"))?,
+            None => syslog::DEFAULT_FACILITY,
+        };
+        let admin = match self.admin {
+            Some(admin) => Some(address::parse_admin(&admin).map_err(|e| format!("bad admin address: {e}"))?),
+            None => None,
+        };
+        let status_http = match self.status_http {
+            Some(status_http) => Some(address::parse_listen(&status_http).map_err(|e| format!("bad status page address: {e}"))?),
+            None => None,
+        };
+        if let Some(webhook) = &self.webhook {
+            if !webhook.starts_with("http://") && !webhook.starts_with("https://") {
+                return Err(format!("bad webhook URL '{webhook}': it has to start with http:// or https://").into());
+            }
+        }
+
+        let modem_profile = match &self.modem_profile {
+            Some(name) => preset::find(name).map_err(|e| format!("bad value '{name}' for --modem-profile: {e}"))?,
+            None => &preset::WEBTV_K56,
+        };
+
+        let connect_speed = self.connect_speed.unwrap_or(at::DEFAULT_CONNECT_SPEED);
+        let carrier_speed = self.carrier_speed.unwrap_or(CarrierSpeed::Auto);
+        let connect_report = connect_report_for(connect_speed, carrier_speed, self.force_56k, modem_profile, self.protocol_line, !self.suppress_intermediates);
+        connect_report.sequence().map_err(|e| format!("can't report that connect speed: {e}"))?;
+
+        for (long_name, throttle) in [("throttle-down", self.throttle_down), ("throttle-up", self.throttle_up)] {
+            if throttle == Some(0) {
+                return Err(format!("bad --{long_name}: 0 would never send anything, leave it out to not throttle").into());
+            }
+        }
+
+        let mut dial_overrides = Vec::new();
+        for (pattern, overrides) in self.dial_overrides {
+            let normalized_pattern = normalize_number(&pattern);
+            if normalized_pattern.is_empty() {
+                return Err(format!("phone book entry '{pattern}' doesn't have any digits to match").into());
+            }
+
+            let report = connect_report_for(overrides.connect_speed.unwrap_or(connect_speed), overrides.carrier_speed.unwrap_or(carrier_speed), overrides.force_56k, modem_profile, self.protocol_line, !self.suppress_intermediates);
+            report.sequence().map_err(|e| format!("phone book entry '{pattern}' can't report that connect speed: {e}"))?;
+
+            for (key, throttle) in [("throttle", overrides.throttle), ("throttle_down", overrides.throttle_down), ("throttle_up", overrides.throttle_up)] {
+                if throttle == Some(0) {
+                    return Err(format!("phone book entry '{pattern}' has a {key} of 0, leave it out to not throttle").into());
+                }
+            }
+
+            dial_overrides.push((normalized_pattern, overrides));
+        }
+        dial_overrides.sort_by_key(|(pattern, _)| pattern_order(pattern));
+
+        if self.delay_after == Some(0) {
+            return Err("--delay-after has to be at least 1".into());
+        }
+
+        if self.max_command_length == Some(0) {
+            return Err("--max-command-length has to be at least 1".into());
+        }
+
+        if self.exit_after_sessions == Some(0) {
+            return Err("--exit-after-sessions has to be at least 1".into());
+        }
+
+        let command_delays = self.command_delay_for.iter()
+            .map(|delay| parse_command_delay(delay).map_err(|e| format!("bad --command-delay-for '{delay}': {e}")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut blacklist = Vec::new();
+        for pattern in self.blacklist {
+            let normalized_pattern = normalize_number(&pattern);
+            if normalized_pattern.is_empty() {
+                return Err(format!("blacklist entry '{pattern}' doesn't have any digits to match").into());
+            }
+
+            blacklist.push(normalized_pattern);
+        }
+
+        let mut numbers_56k = Vec::new();
+        let mut no_56k_numbers = Vec::new();
+        for (long_name, patterns, normalized_patterns) in [("56k-numbers", self.numbers_56k, &mut numbers_56k), ("no-56k-numbers", self.no_56k_numbers, &mut no_56k_numbers)] {
+            for pattern in patterns {
+                let normalized_pattern = normalize_number(&pattern);
+                if normalized_pattern.is_empty() {
+                    return Err(format!("--{long_name} entry '{pattern}' doesn't have any digits to match").into());
+                }
+
+                normalized_patterns.push(normalized_pattern);
+            }
+        }
+
+        if self.daemon && self.log_file.is_none() {
+            return Err("--daemon needs --log-file since there's no terminal to log to".into());
+        }
+
+        Ok(Config {
+            modem: self.modem,
+            listen_address,
+            listen_pipe,
+            is_silent: self.is_silent,
+            verbosity: self.verbosity,
+            log_file: self.log_file,
+            log_max_size,
+            log_keep: self.log_keep.unwrap_or(logfile::DEFAULT_LOG_KEEP),
+            log_stdout: self.log_stdout,
+            log_format: self.log_format.unwrap_or(LogFormat::Text),
+            log_filter: self.log_filter,
+            color: self.color.unwrap_or(Color::Auto),
+            log_syslog,
+            syslog_facility,
+            health_check: self.health_check || health_check_interval.is_some(),
+            resolve_at_start: self.resolve_at_start,
+            health_check_interval,
+            shutdown_timeout: Duration::from_secs(self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT)),
+            drain_timeout: self.drain_timeout.map(Duration::from_secs),
+            exit_after_sessions: self.exit_after_sessions,
+            connect_speed,
+            carrier_speed,
+            suppress_intermediates: self.suppress_intermediates,
+            protocol_line: self.protocol_line,
+            connect_report,
+            throttle: Throttle { down: self.throttle_down, up: self.throttle_up },
+            throttle_to_carrier: self.throttle_to_carrier,
+            report_throughput: self.report_throughput,
+            delay_after: self.delay_after,
+            delay_window: Duration::from_secs(self.delay_window.unwrap_or(DEFAULT_DELAY_WINDOW)),
+            delay_cooldown: Duration::from_secs(self.delay_cooldown.unwrap_or(DEFAULT_DELAY_COOLDOWN)),
+            blacklist,
+            force_56k: self.force_56k,
+            numbers_56k,
+            no_56k_numbers,
+            state_file: self.state_file,
+            drop_after: self.drop_after,
+            drop_count: self.drop_count,
+            max_command_length: self.max_command_length.unwrap_or(DEFAULT_MAX_COMMAND_LENGTH),
+            accept_rate: self.accept_rate.unwrap_or(AcceptRate::DEFAULT),
+            max_pending: self.max_pending.unwrap_or(DEFAULT_MAX_PENDING),
+            command_delay: Duration::from_millis(self.command_delay.unwrap_or(0)),
+            command_delays,
+            profile: self.profile.unwrap_or_default(),
+            modem_profile,
+            reset_forgets_number: self.reset_forgets_number,
+            link_protocol: self.link_protocol.unwrap_or_default(),
+            auto_data: self.auto_data,
+            at_passthrough: self.at_passthrough,
+            launch_mame: self.launch_mame,
+            mame_slot: self.mame_slot.unwrap_or(MameSlot::Spot),
+            mame_restart: self.mame_restart,
+            daemon: self.daemon,
+            pid_file: self.pid_file,
+            admin,
+            enable_inject: self.enable_inject,
+            status_http,
+            webhook: self.webhook,
+            webhook_secret: self.webhook_secret,
+            webhook_retries: self.webhook_retries.unwrap_or(webhook::DEFAULT_WEBHOOK_RETRIES),
+            dial_log: self.dial_log,
+            dial_log_sync: self.dial_log_sync,
+            cdr_file: self.cdr_file,
+            at_transcript: self.at_transcript,
+            backend_defaults: defaults,
+            cli_backend,
+            default_backend,
+            backends,
+            phone_book,
+            dial_overrides,
+            sources: self.sources,
+            tcpser_aliases: self.tcpser_aliases,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_what_the_command_line_would() {
+        let config = Config::builder()
+            .listen("6400")
+            .exec_backend("local", "pppd notty")
+            .remote_backend("isp", ["10.0.0.2:2323", "10.0.0.3:2323"])
+            .phone_book("1800*", "isp")
+            .phone_book("5551212", "local")
+            .default_backend("isp")
+            .connect_timeout(5)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.listen_address.to_string(), "127.0.0.1:6400");
+        assert_eq!(config.resolve_backend("5551212").name, "local");
+        assert_eq!(config.resolve_backend("18006138199").name, "isp");
+        assert_eq!(config.resolve_backend("911").name, "isp");
+        assert_eq!(config.shutdown_timeout, Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT));
+
+        let BackendKind::Remote(remote_ppp) = &config.default_backend.kind else {
+            panic!("isp should be remote");
+        };
+        assert_eq!(remote_ppp.connect_timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn only_remote_backends_coalesce() {
+        let config = Config::builder()
+            .exec_backend("local", "pppd notty")
+            .remote_backend("isp", ["10.0.0.2:2323"])
+            .connect("127.0.0.1:2323")
+            .coalesce_ms(20)
+            .build()
+            .unwrap();
+
+        let BackendKind::Remote(remote_ppp) = &config.cli_backend.as_ref().unwrap().kind else {
+            panic!("-c should be remote");
+        };
+        assert_eq!(remote_ppp.coalesce, Some(Coalesce { bytes: DEFAULT_COALESCE_BYTES, wait: Duration::from_millis(20) }));
+        assert!(matches!(config.backends["local"].kind, BackendKind::Exec(_)));
+
+        let config = Config::builder().connect("127.0.0.1:2323").coalesce_bytes(0).build().unwrap();
+        let BackendKind::Remote(remote_ppp) = &config.cli_backend.as_ref().unwrap().kind else {
+            panic!("-c should be remote");
+        };
+        assert_eq!(remote_ppp.coalesce, None);
+
+        let too_long = Config::builder().connect("127.0.0.1:2323").coalesce_ms(5000).build();
+        assert!(too_long.is_err_and(|e| e.to_string().contains("--coalesce-ms: 5000")));
+
+        let too_big = Config::builder().remote_backend("isp", ["10.0.0.2:2323"]).coalesce_bytes(BUFFER_SIZE + 1).build();
+        assert!(too_big.is_err_and(|e| e.to_string().contains("--coalesce-bytes")));
+    }
+
+    #[test]
+    fn socket_buffers_have_to_be_sensible() {
+        let config = Config::builder().connect("127.0.0.1:2323").so_rcvbuf(0x40000).build().unwrap();
+        assert_eq!(config.backend_defaults.socket_options().recv_buffer, Some(0x40000));
+
+        let too_small = Config::builder().so_rcvbuf(10).build();
+        assert!(too_small.is_err_and(|e| e.to_string().contains("bad --so-rcvbuf: 10 bytes")));
+
+        let too_big = Config::builder().so_sndbuf(1 << 40).build();
+        assert!(too_big.is_err_and(|e| e.to_string().contains("bad --so-sndbuf")));
+    }
+
+    #[test]
+    fn only_one_backend_can_answer_every_call() {
+        let both = Config::builder().connect("127.0.0.1:2323").exec("pppd notty").build();
+        assert!(both.is_err_and(|e| e.to_string().contains("only one of connect, exec")));
+
+        let both = Config::builder().exec("pppd notty").builtin(Builtin::Echo).build();
+        assert!(both.is_err());
+
+        let both = Config::builder().connect("127.0.0.1:2323").remote_udp("127.0.0.1:2323").build();
+        assert!(both.is_err());
+
+        // One on its own beats the phone book.
+        let config = Config::builder().exec("pppd notty").remote_backend("isp", ["10.0.0.2:2323"]).phone_book("1800*", "isp").build().unwrap();
+        assert_eq!(config.resolve_backend("18006138199").describe(), "command line (exec 'pppd notty')");
+
+        let config = Config::builder().remote_udp("127.0.0.1").build().unwrap();
+        assert_eq!(config.resolve_backend("").describe(), "command line (udp 127.0.0.1:2323)");
+    }
+
+    #[test]
+    fn carrier_drops_land_within_the_jitter() {
+        let drop_after: CarrierDrop = "30,10".parse().unwrap();
+        assert_eq!(drop_after.to_string(), "30,10");
+
+        for seed in [0, 1, 9_999, 10_000, 20_000, u64::MAX] {
+            let picked = drop_after.pick(seed);
+            assert!(picked >= Duration::from_secs(20) && picked <= Duration::from_secs(40), "{picked:?} for {seed}");
+        }
+
+        let drop_after: CarrierDrop = "5".parse().unwrap();
+        assert_eq!(drop_after.pick(12345), Duration::from_secs(5));
+        assert!("5,".parse::<CarrierDrop>().is_err());
+    }
+
+    #[test]
+    fn only_exec_backends_are_exclusive_unless_asked() {
+        assert!(Config::builder().exec("pppd notty").build().unwrap().resolve_backend("").is_exclusive());
+        assert!(!Config::builder().exec("pppd notty").exec_concurrent(true).build().unwrap().resolve_backend("").is_exclusive());
+        assert!(!Config::builder().connect("127.0.0.1:2323").build().unwrap().resolve_backend("").is_exclusive());
+        assert!(Config::builder().connect("127.0.0.1:2323").remote_exclusive(true).build().unwrap().resolve_backend("").is_exclusive());
+    }
+
+    #[test]
+    fn phone_book_entries_beat_the_command_line() {
+        let overrides = DialOverrides { connect_speed: Some(57600), ..Default::default() };
+        let config = Config::builder().connect_speed(38400).carrier_speed(CarrierSpeed::Fixed(31200)).dial_overrides("1800*", overrides).build().unwrap();
+
+        // Just the speed's the entry's. The carrier's still the command line's, and the backend's still the default.
+        let settings = config.dial_settings("18006138199", &at::ModulationConfig::default());
+        assert_eq!((settings.connect_report.connect_speed, settings.connect_report.carrier_speed), (57600, 31200));
+        assert_eq!(config.resolve_backend("18006138199").name, "default");
+
+        let settings = config.dial_settings("5551212", &at::ModulationConfig::default());
+        assert_eq!((settings.connect_report.connect_speed, settings.connect_report.carrier_speed), (38400, 31200));
+
+        // And the defaults when nothing has anything to say.
+        let settings = Config::builder().dial_overrides("1800*", overrides).build().unwrap().dial_settings("5551212", &at::ModulationConfig::default());
+        assert_eq!(settings.connect_report, at::ConnectReport::default());
+        assert_eq!((settings.dial_delay, settings.throttle), (Duration::ZERO, Throttle::default()));
+    }
+
+    #[test]
+    fn throttles_are_each_way_wherever_they_come_from() {
+        let config = Config::builder()
+            .throttle(28800)
+            .throttle_up(9600)
+            .dial_overrides("1800*", DialOverrides { throttle: Some(14400), throttle_down: Some(19200), ..Default::default() })
+            .dial_overrides("5551212", DialOverrides { throttle_up: Some(2400), ..Default::default() })
+            .build()
+            .unwrap();
+
+        // The entry's own way beats its both ways, which beats the command line's.
+        assert_eq!(config.dial_settings("18006138199", &at::ModulationConfig::default()).throttle, Throttle { down: Some(19200), up: Some(14400) });
+        assert_eq!(config.dial_settings("5551212", &at::ModulationConfig::default()).throttle, Throttle { down: Some(28800), up: Some(2400) });
+        assert_eq!(config.dial_settings("5550000", &at::ModulationConfig::default()).throttle, Throttle { down: Some(28800), up: Some(9600) });
+
+        // 56k's only 56k one way.
+        assert_eq!(Throttle::for_carrier(53333), Throttle { down: Some(53333), up: Some(33600) });
+        assert_eq!(Throttle::for_carrier(26400), Throttle::both(26400));
+        assert_eq!(Throttle { down: Some(9600), up: None }.or(Throttle::for_carrier(56000)), Throttle { down: Some(9600), up: Some(33600) });
+    }
+
+    #[test]
+    fn forcing_56k_ignores_what_the_box_says() {
+        let config = Config::builder()
+            .dial_overrides("1800*", DialOverrides { force_56k: Some(true), ..Default::default() })
+            .dial_overrides("5551212", DialOverrides { force_56k: Some(false), carrier_speed: Some(CarrierSpeed::Fixed(50000)), ..Default::default() })
+            .build()
+            .unwrap();
+
+        let forced_on = config.dial_settings("18006138199", &at::ModulationConfig::default()).connect_report;
+        assert_eq!((forced_on.carrier_speed, forced_on.follows_modulation), (FORCED_56K_CARRIER_SPEED, false));
+
+        let forced_off = config.dial_settings("5551212", &at::ModulationConfig::default()).connect_report;
+        assert_eq!(forced_off.carrier_speed, 33600);
+    }
+
+    #[test]
+    fn modem_profiles_set_the_56k_policy_unless_told_otherwise() {
+        let config = Config::builder().modem_profile("rockwell-v34").carrier_speed(CarrierSpeed::Fixed(50000)).build().unwrap();
+        assert_eq!(config.connect_report.carrier_speed, 33600);
+
+        let config = Config::builder()
+            .modem_profile("softmodem")
+            .dial_overrides("5551212", DialOverrides { force_56k: Some(false), ..Default::default() })
+            .build()
+            .unwrap();
+        assert_eq!((config.connect_report.carrier_speed, config.connect_report.follows_modulation), (50000, true));
+        assert_eq!(config.dial_settings("5551212", &at::ModulationConfig::default()).connect_report.carrier_speed, 33600);
+
+        let config = Config::builder().modem_profile("softmodem").carrier_speed(CarrierSpeed::Fixed(31200)).build().unwrap();
+        assert_eq!(config.connect_report.carrier_speed, 31200);
+
+        assert!(Config::builder().modem_profile("hayes").build().is_err_and(|e| e.to_string().contains("bad value 'hayes' for --modem-profile: use webtv-k56")));
+    }
+
+    #[test]
+    fn decides_56k_from_whatever_has_a_say() {
+        let nothing = at::ModulationConfig::default();
+        let s51 = at::ModulationConfig { s51: Some(at::S51_NO_56K), ..Default::default() };
+        let v34 = at::ModulationConfig { carrier: Some(at::Modulation::V34), ..Default::default() };
+        let softmodem = || Config::builder().modem_profile("softmodem");
+
+        for (builder, number, modulation, expected) in [
+            (softmodem(), "5551212", &nothing, Decision56k::inferred(true, "modem profile's carrier speed")),
+            (Config::builder(), "5551212", &nothing, Decision56k::inferred(false, "modem profile's carrier speed")),
+            (softmodem(), "5551212", &s51, Decision56k::inferred(false, "S51=31 seen")),
+            (softmodem(), "5551212", &v34, Decision56k::inferred(false, "+MS holds it to V.34")),
+            (softmodem().carrier_speed(CarrierSpeed::Fixed(50000)), "5551212", &s51, Decision56k::inferred(true, "carrier speed set")),
+            (softmodem(), "1-800-613-8199", &nothing, Decision56k::forced(false, "bootstrap number")),
+            (Config::builder().modem_profile("rockwell-v34"), "5551212", &nothing, Decision56k::forced(false, "modem profile")),
+            (softmodem().dial_overrides("555*", DialOverrides { force_56k: Some(false), ..Default::default() }), "5551212", &nothing, Decision56k::forced(false, "phone book entry")),
+        ] {
+            assert_eq!(builder.build().unwrap().decide_56k(number, modulation), expected, "{number} {modulation:?}");
+        }
+    }
+
+    #[test]
+    fn the_56k_overrides_beat_every_inference() {
+        let nothing = at::ModulationConfig::default();
+        let s51 = at::ModulationConfig { s51: Some(at::S51_NO_56K), ..Default::default() };
+        let ms = at::ModulationConfig { carrier: Some(at::Modulation::V34), max_rate: Some(28800), ..Default::default() };
+        fn softmodem() -> ConfigBuilder {
+            Config::builder().modem_profile("softmodem")
+        }
+        fn entry(force_56k: bool) -> DialOverrides {
+            DialOverrides { force_56k: Some(force_56k), ..Default::default() }
+        }
+
+        // Where 56k would be decided without the overrides: the init string (through allows_56k), the phone book
+        // entry's force_56k, the modem profile's and the bootstrap numbers.
+        let sources = [
+            ("S51=31", softmodem as fn() -> ConfigBuilder, "5551212", &s51),
+            ("+MS", softmodem, "5551212", &ms),
+            ("no init", softmodem, "5551212", &nothing),
+            ("entry on", || Config::builder().dial_overrides("5551212", entry(true)), "5551212", &nothing),
+            ("entry off", || softmodem().dial_overrides("5551212", entry(false)), "5551212", &nothing),
+            ("rockwell-v34", || Config::builder().modem_profile("rockwell-v34"), "5551212", &nothing),
+            ("bootstrap", softmodem, "18006138199", &nothing),
+        ];
+
+        for (source, builder, number, modulation) in sources {
+            let overrides = [
+                (builder().force_56k(true), Decision56k::forced(true, "forced by flag")),
+                (builder().force_56k(false), Decision56k::forced(false, "forced by flag")),
+                (builder().numbers_56k(number), Decision56k::forced(true, "listed in --56k-numbers")),
+                (builder().no_56k_numbers(number), Decision56k::forced(false, "listed in --no-56k-numbers")),
+            ];
+
+            for (builder, expected) in overrides {
+                let config = builder.build().unwrap();
+                assert_eq!(config.decide_56k(number, modulation), expected, "{source}");
+
+                // And it's what gets reported.
+                let report = config.dial_settings(number, modulation).connect_report;
+                match expected.is_56k {
+                    true => assert_eq!((report.carrier_speed, report.follows_modulation), (FORCED_56K_CARRIER_SPEED, false), "{source}"),
+                    false => assert!(report.carrier_speed <= at::Modulation::V34.top_speed(), "{source}"),
+                }
+            }
+        }
+
+        // The flags beat the lists too.
+        let config = Config::builder().force_56k(false).numbers_56k("5551212").build().unwrap();
+        assert_eq!(config.decide_56k("5551212", &nothing), Decision56k::forced(false, "forced by flag"));
+        let config = Config::builder().numbers_56k("555*").no_56k_numbers("5551212").build().unwrap();
+        assert_eq!(config.decide_56k("5551212", &nothing), Decision56k::forced(true, "listed in --56k-numbers"));
+
+        assert!(Config::builder().numbers_56k("#").build().is_err_and(|e| e.to_string().contains("--56k-numbers entry '#' doesn't have any digits")));
+    }
+
+    #[test]
+    fn the_longest_command_delay_that_matches_wins() {
+        let config = Config::builder().command_delay(20).command_delay_for("i3", 250).command_delay_for("D", 500).command_delay_for("&F", 100).build().unwrap();
+
+        assert_eq!(config.command_delay_for("ATI3\r"), Duration::from_millis(250));
+        assert_eq!(config.command_delay_for("ATI0\r"), Duration::from_millis(20));
+        assert_eq!(config.command_delay_for("AT&FI3\r"), Duration::from_millis(250));
+        assert_eq!(config.command_delay_for("ATDT18006138199\r"), Duration::from_millis(500));
+        assert_eq!(config.command_delay_for("ATE0\r"), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn catches_the_same_mistakes_the_command_line_does() {
+        for (builder, problem) in [
+            (Config::builder().listen("99999"), "bad listen address"),
+            (Config::builder().connect("nowhere:0"), "bad connect address"),
+            (Config::builder().exec(" "), "empty exec command"),
+            (Config::builder().default_backend("isp"), "default backend 'isp' isn't defined"),
+            (Config::builder().phone_book("1800*", "isp"), "points to backend 'isp'"),
+            (Config::builder().webhook("ftp://example.com"), "bad webhook URL"),
+            (Config::builder().connect_speed(12345), "no result code for CONNECT 12345"),
+            (Config::builder().delay_after(0), "at least 1"),
+            (Config::builder().max_command_length(0), "--max-command-length has to be at least 1"),
+            (Config::builder().exit_after_sessions(0), "--exit-after-sessions has to be at least 1"),
+            (Config::builder().blacklist("#"), "blacklist entry '#'"),
+            (Config::builder().resolve("wtv-ppp.lan"), "bad --resolve 'wtv-ppp.lan'"),
+            (Config::builder().dns("ns.lan"), "bad --dns 'ns.lan'"),
+            (Config::builder().bind_device("a-name-that-is-too-long"), "bad --bind-device: 'a-name-that-is-too-long'"),
+            #[cfg(all(unix, feature = "ssh"))]
+            (Config::builder().connect("127.0.0.1:2323").remote_ssh("gateway.example.com"), "bad --remote-ssh"),
+            #[cfg(all(unix, feature = "ssh"))]
+            (Config::builder().connect("127.0.0.1:2323").ssh_persist(true), "only go with --remote-ssh"),
+            (Config::builder().dial_overrides("1800*", DialOverrides { throttle: Some(0), ..Default::default() }), "has a throttle of 0"),
+            (Config::builder().dial_overrides("1800*", DialOverrides { throttle_up: Some(0), ..Default::default() }), "has a throttle_up of 0"),
+            (Config::builder().throttle_down(0), "bad --throttle-down: 0"),
+            (Config::builder().command_delay_for("I3", 250).command_delay_for("", 10), "bad --command-delay-for '=10'"),
+        ] {
+            match builder.build() {
+                Ok(_) => panic!("expected '{problem}'"),
+                Err(e) => assert!(e.to_string().contains(problem), "expected '{problem}' but got '{e}'"),
+            }
+        }
+    }
+}
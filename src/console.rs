@@ -0,0 +1,41 @@
+// The admin commands typed straight into the terminal TouchPPP's running in, for when there's no --admin to
+// connect to. quit stops TouchPPP here rather than just closing the connection.
+
+use std::io::{IsTerminal, Write};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::admin;
+use crate::config::Config;
+use crate::stats::Stats;
+
+// Only when there's someone at the terminal to type, which a daemon never has.
+pub fn is_wanted(config: &Config) -> bool {
+    !config.daemon && std::io::stdin().is_terminal()
+}
+
+// Takes commands from stdin until it's closed, cancelling `quit` if asked to.
+pub async fn run(stats: Arc<Stats>, quit: CancellationToken) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Stopped taking commands on the console: error={e}");
+                return;
+            },
+        };
+
+        let Some(reply) = admin::reply(&line, &stats).await else {
+            info!("Asked to quit on the console.");
+            return quit.cancel();
+        };
+
+        // In one write, so a log line can only land between replies, never in the middle of one.
+        let _ = std::io::stdout().lock().write_all(reply.as_bytes());
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::unistd::{dup2_stderr, dup2_stdin, dup2_stdout, fork, pipe, setsid, ForkResult, Pid};
+
+// What the daemon tells the waiting parent once the pid file is written.
+const READY: &str = "ready";
+
+// Removed when the daemon exits cleanly.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    fn create(path: &str, pid: Pid) -> io::Result<PidFile> {
+        fs::write(path, format!("{pid}\n"))?;
+
+        Ok(PidFile {
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// A pid file left behind by a crash is fine to take over, one belonging to a running process isn't.
+pub fn check_pid_file(path: &str) -> Result<(), String> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Ok(());
+    };
+
+    match contents.trim().parse::<i32>() {
+        Ok(pid) if pid > 0 && kill(Pid::from_raw(pid), None) != Err(Errno::ESRCH) => {
+            Err(format!("pid file '{path}' says touchppp is already running as pid {pid}"))
+        },
+        _ => Ok(()),
+    }
+}
+
+// Forks into the background with stdio pointed at the log file. Only the daemon returns from this.
+// The parent waits until the daemon has written its pid file so an init script can rely on it,
+// then exits. This has to happen before the tokio runtime starts any threads.
+pub fn daemonize(pid_file: Option<&str>, log_file: &str) -> io::Result<Option<PidFile>> {
+    let null = File::open("/dev/null")?;
+    let log = OpenOptions::new().create(true).append(true).open(log_file)?;
+
+    let (ready_reader, ready_writer) = pipe()?;
+
+    match unsafe { fork() }? {
+        ForkResult::Parent { .. } => {
+            drop(ready_writer);
+
+            let mut message = String::new();
+            let _ = File::from(ready_reader).read_to_string(&mut message);
+
+            if message == READY {
+                std::process::exit(0);
+            }
+
+            if message.is_empty() {
+                message = format!("it died before it was ready, check {log_file}");
+            }
+
+            eprintln!("Can't start the daemon: {message}");
+            std::process::exit(1);
+        },
+        ForkResult::Child => {
+            drop(ready_reader);
+
+            let mut ready_writer = File::from(ready_writer);
+
+            setsid()?;
+
+            let pid_file = match pid_file {
+                Some(path) => match PidFile::create(path, Pid::this()) {
+                    Ok(r) => Some(r),
+                    Err(e) => {
+                        let _ = write!(ready_writer, "can't write pid file '{path}': {e}");
+                        std::process::exit(1);
+                    },
+                },
+                None => None,
+            };
+
+            dup2_stdin(&null)?;
+            dup2_stdout(&log)?;
+            dup2_stderr(&log)?;
+
+            ready_writer.write_all(READY.as_bytes())?;
+
+            Ok(pid_file)
+        },
+    }
+}
+
+// Without --daemon the pid file just holds our own pid.
+pub fn write_pid_file(path: &str) -> io::Result<PidFile> {
+    PidFile::create(path, Pid::this())
+}
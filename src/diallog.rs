@@ -0,0 +1,149 @@
+// --dial-log: a JSON line for every dial and another for how each call that went through ended, appended to a
+// file for operators who need to know who called what. Unlike the log, the lines always have the same fields
+// whatever the verbosity, and they get written on every path a dial can take, errors included.
+//
+//   {"record":"attempt","attempt":"1700000000000-1","ts":"...","session":1,"client":"127.0.0.1:51234",
+//    "number":"18006138199","backend":"default","outcome":"connected","result":"CONNECT",
+//    "connect_speed":115200,"carrier_speed":33600}
+//   {"record":"disconnect","attempt":"1700000000000-1","ts":"...","session":1,"duration_ms":61234,
+//    "bytes_up":1234,"bytes_down":56789,"reason":"call ended"}
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tracing::warn;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{self, FormatTime};
+
+use crate::at;
+use crate::error::TouchPppError;
+use crate::stats;
+
+/// One line of the dial log.
+#[derive(Serialize, Debug)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum Record<'a> {
+    /// A dial, however it went. The speeds are only there for one that connected.
+    Attempt {
+        attempt: &'a str,
+        ts: String,
+        session: u64,
+        client: &'a str,
+        number: String,
+        backend: &'a str,
+        outcome: &'static str,
+        // What MAME was told, or why the dial failed.
+        result: &'a str,
+        connect_speed: Option<u32>,
+        carrier_speed: Option<u32>,
+    },
+    /// How a call that connected ended, for the attempt with the same id.
+    Disconnect {
+        attempt: &'a str,
+        ts: String,
+        session: u64,
+        duration_ms: u64,
+        bytes_up: u64,
+        bytes_down: u64,
+        reason: &'a str,
+    },
+}
+
+pub struct DialLog {
+    file: Mutex<File>,
+    sync: bool,
+    // Attempt ids are the millisecond we started and a count, so they're still unique in a file that's been
+    // appended to over a few runs.
+    run: u64,
+    attempts: AtomicU64,
+}
+
+impl DialLog {
+    /// Opens `file` to append to, making it if it isn't there. `sync` syncs it to disk after every line.
+    pub fn open(file: &str, sync: bool) -> Result<DialLog, String> {
+        let opened = OpenOptions::new().create(true).append(true).open(file).map_err(|e| format!("can't open dial log '{file}': {e}"))?;
+
+        Ok(DialLog {
+            file: Mutex::new(opened),
+            sync,
+            run: SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_millis() as u64).unwrap_or(0),
+            attempts: AtomicU64::new(0),
+        })
+    }
+
+    /// A new attempt id.
+    pub fn next_attempt(&self) -> String {
+        format!("{}-{}", self.run, self.attempts.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    /// Writes `record` as one line. A line that can't be written is logged, but never stops a call.
+    pub fn write(&self, record: &Record) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Can't write to the dial log: error={e}");
+                return;
+            },
+        };
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        let written = file.write_all(&line).and_then(|_| match self.sync {
+            true => file.sync_data(),
+            false => Ok(()),
+        });
+
+        if let Err(e) = written {
+            warn!("Can't write to the dial log: error={e}");
+        }
+    }
+}
+
+/// Now, the way the JSON log writes it.
+pub fn timestamp() -> String {
+    let mut ts = String::new();
+    let _ = time::SystemTime.format_time(&mut Writer::new(&mut ts));
+
+    ts
+}
+
+/// What the dial log calls a dial that ended with `outcome`, one of the outcomes in [`stats`].
+pub fn outcome_of(outcome: &str) -> &'static str {
+    match outcome {
+        stats::CONNECTED => "connected",
+        stats::BUSY => "busy",
+        stats::DELAYED => "delayed",
+        stats::BLACKLISTED => "blacklisted",
+        _ => "failed",
+    }
+}
+
+/// What the dial log calls a dial the backend couldn't take, going by what MAME was told.
+pub fn outcome_of_error(e: &TouchPppError) -> &'static str {
+    match e.result_code() {
+        Some(at::BUSY) => "busy",
+        Some(at::NO_CARRIER) => "no-carrier",
+        Some(at::NO_DIALTONE) => "no-dialtone",
+        _ => "failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn failed_dials_say_what_mame_heard() {
+        let refused = TouchPppError::BackendConnect { endpoint: "127.0.0.1:1".to_string(), source: io::Error::from(io::ErrorKind::ConnectionRefused) };
+        let unreachable = TouchPppError::BackendConnect { endpoint: "127.0.0.1:1".to_string(), source: io::Error::from(io::ErrorKind::HostUnreachable) };
+
+        assert_eq!(outcome_of_error(&refused), "busy");
+        assert_eq!(outcome_of_error(&unreachable), "no-dialtone");
+        assert_eq!(outcome_of(stats::CONNECTED), "connected");
+        assert_eq!(outcome_of("can't touch PPP"), "failed");
+    }
+}
@@ -0,0 +1,324 @@
+// What's been learned about each number. For --delay-after, how many times in a row it's failed lately, so one
+// that keeps failing gets DELAYED for a while the way a real modem's call limiting would, without bothering the
+// backend. And from the last call that went through, which server answered (tried first next time with
+// --remote-sticky), whether it got 56k and when. Numbers are kept normalized and times are seconds since the
+// epoch, so --state-file can carry them over a restart. The modem settings AT&W0 and &W1 saved for ATZ0 and ATZ1
+// to bring back go in there too.
+//
+//   [number.18006138199]
+//   failures = [1700000000, 1700000030]
+//   delayed_until = 1700000300
+//   endpoint = "ppp2.example.com:2323"
+//   is_56k = false
+//   connected_at = 1699999000
+//
+//   [profile.0]
+//   echo = false
+//   ...
+//   s_registers = { S0 = 0, S7 = 60 }
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::{normalize_number, Config};
+use crate::modem::StoredProfile;
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct NumberState {
+    // When each failure since the last call that went through happened, oldest first.
+    #[serde(default)]
+    failures: Vec<u64>,
+    delayed_until: Option<u64>,
+    endpoint: Option<String>,
+    #[serde(default)]
+    is_56k: bool,
+    connected_at: Option<u64>,
+}
+
+impl NumberState {
+    // Whether there's anything left worth saving.
+    fn is_worth_keeping(&self, now: u64) -> bool {
+        !self.failures.is_empty() || self.delayed_until.is_some_and(|until| until > now) || self.connected_at.is_some()
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct StateFile {
+    #[serde(default)]
+    number: BTreeMap<String, NumberState>,
+    // By slot number.
+    #[serde(default)]
+    profile: BTreeMap<String, StoredProfile>,
+}
+
+/// Everything known about every number, and the modem settings &W saved, shared by all sessions for as long as
+/// the process runs.
+#[derive(Default)]
+pub struct DialState {
+    numbers: Mutex<BTreeMap<String, NumberState>>,
+    profiles: Mutex<BTreeMap<String, StoredProfile>>,
+    file: Option<PathBuf>,
+}
+
+fn seconds(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+impl DialState {
+    /// Counts that last until the process exits.
+    pub fn new() -> DialState {
+        DialState::default()
+    }
+
+    /// Picks up where the last run left off if `file` is there, and saves every change to it. A file that isn't
+    /// there yet is fine, and so is one that's been mangled (it's started over, with a warning), but one that
+    /// can't be read isn't.
+    pub fn load(file: Option<&str>) -> Result<DialState, String> {
+        let Some(file) = file else {
+            return Ok(DialState::new());
+        };
+
+        let state = match fs::read_to_string(file) {
+            Ok(contents) => match toml::from_str::<StateFile>(&contents) {
+                Ok(state) => state,
+                Err(e) => {
+                    warn!("The state file {file} is no good, starting over: error={e}");
+                    StateFile::default()
+                },
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StateFile::default(),
+            Err(e) => return Err(format!("can't read state file '{file}': {e}")),
+        };
+
+        Ok(DialState {
+            numbers: Mutex::new(state.number),
+            profiles: Mutex::new(state.profile),
+            file: Some(PathBuf::from(file)),
+        })
+    }
+
+    /// Whether `number` is still cooling off from failing too many times in a row.
+    pub fn is_delayed(&self, number: &str, now: SystemTime) -> bool {
+        let numbers = self.numbers.lock().unwrap();
+
+        numbers.get(&normalize_number(number)).and_then(|state| state.delayed_until).is_some_and(|until| until > seconds(now))
+    }
+
+    /// Counts a failed dial to `number`, giving back true if that's the one that puts it on delay. Does nothing
+    /// without --delay-after.
+    pub fn failed(&self, number: &str, config: &Config, now: SystemTime) -> bool {
+        let Some(delay_after) = config.delay_after else {
+            return false;
+        };
+
+        let now = seconds(now);
+        let mut numbers = self.numbers.lock().unwrap();
+        let state = numbers.entry(normalize_number(number)).or_default();
+
+        // Failures from before the window don't count towards this run of them.
+        state.failures.retain(|at| at + config.delay_window.as_secs() > now);
+        state.failures.push(now);
+
+        let is_delayed = state.failures.len() >= delay_after as usize;
+        if is_delayed {
+            state.failures.clear();
+            state.delayed_until = Some(now + config.delay_cooldown.as_secs());
+        }
+
+        self.save(&mut numbers, now);
+
+        is_delayed
+    }
+
+    /// A call to `number` went through, so its failures start over. `endpoint` is the server that answered, for
+    /// backends that have more than one.
+    pub fn connected(&self, number: &str, endpoint: Option<&str>, is_56k: bool, now: SystemTime) {
+        let now = seconds(now);
+        let mut numbers = self.numbers.lock().unwrap();
+
+        numbers.insert(normalize_number(number), NumberState {
+            failures: Vec::new(),
+            delayed_until: None,
+            endpoint: endpoint.map(String::from),
+            is_56k,
+            connected_at: Some(now),
+        });
+
+        self.save(&mut numbers, now);
+    }
+
+    /// The server that answered the last call to `number` that went through, if it said.
+    pub fn endpoint(&self, number: &str) -> Option<String> {
+        self.numbers.lock().unwrap().get(&normalize_number(number)).and_then(|state| state.endpoint.clone())
+    }
+
+    /// What &W last saved to `slot`, if it ever has.
+    pub fn stored_profile(&self, slot: u32) -> Option<StoredProfile> {
+        self.profiles.lock().unwrap().get(&slot.to_string()).cloned()
+    }
+
+    /// Saves `profile` to `slot` for ATZ to bring back, this run and the next.
+    pub fn store_profile(&self, slot: u32, profile: StoredProfile) {
+        self.profiles.lock().unwrap().insert(slot.to_string(), profile);
+
+        let mut numbers = self.numbers.lock().unwrap();
+        self.save(&mut numbers, seconds(SystemTime::now()));
+    }
+
+    // Numbers with nothing left to remember are dropped first so the file doesn't grow any more than it has to.
+    fn save(&self, numbers: &mut BTreeMap<String, NumberState>, now: u64) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        numbers.retain(|_, state| state.is_worth_keeping(now));
+
+        let profile = self.profiles.lock().unwrap().clone();
+
+        let contents = match toml::to_string(&StateFile { number: numbers.clone(), profile }) {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Can't save the state: error={e}");
+                return;
+            },
+        };
+
+        if let Err(e) = write_atomically(file, &contents) {
+            warn!("Can't save the state to {}: error={e}", file.display());
+        }
+    }
+}
+
+// Written next to `file` and renamed over it, so a crash part way through leaves the old one rather than half of
+// the new one.
+fn write_atomically(file: &Path, contents: &str) -> std::io::Result<()> {
+    let mut temporary = file.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::modem::ModemSession;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds)
+    }
+
+    #[test]
+    fn delays_a_number_that_keeps_failing() {
+        let config = Config::builder().delay_after(3).delay_window(60).delay_cooldown(300).build().unwrap();
+        let state = DialState::new();
+
+        assert!(!state.failed("1-800-613-8199", &config, at(0)));
+        assert!(!state.failed("18006138199", &config, at(10)));
+        assert!(!state.is_delayed("18006138199", at(10)));
+        assert!(state.failed("18006138199", &config, at(20)));
+
+        assert!(state.is_delayed("1 (800) 613-8199", at(21)));
+        assert!(!state.is_delayed("5551212", at(21)));
+        assert!(!state.is_delayed("18006138199", at(320)));
+    }
+
+    #[test]
+    fn only_failures_in_a_row_within_the_window_count() {
+        let config = Config::builder().delay_after(2).delay_window(60).build().unwrap();
+        let state = DialState::new();
+
+        state.failed("5551212", &config, at(0));
+        state.connected("5551212", None, false, at(1));
+        assert!(!state.failed("5551212", &config, at(1)));
+
+        // Too long after the one before.
+        assert!(!state.failed("5551212", &config, at(100)));
+        assert!(state.failed("5551212", &config, at(110)));
+    }
+
+    #[test]
+    fn counts_nothing_without_delay_after() {
+        let config = Config::builder().build().unwrap();
+        let state = DialState::new();
+
+        for seconds in 0..10 {
+            assert!(!state.failed("5551212", &config, at(seconds)));
+        }
+        assert!(!state.is_delayed("5551212", at(10)));
+    }
+
+    #[test]
+    fn survives_a_restart_with_a_file() {
+        let file = std::env::temp_dir().join(format!("touchppp-dial-state-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let file = file.to_str().unwrap();
+
+        let config = Config::builder().delay_after(1).delay_cooldown(300).build().unwrap();
+
+        let state = DialState::load(Some(file)).unwrap();
+        state.failed("18006138199", &config, SystemTime::now());
+        drop(state);
+
+        let state = DialState::load(Some(file)).unwrap();
+        assert!(state.is_delayed("18006138199", SystemTime::now()));
+        state.connected("1-800-613-8199", Some("ppp2.example.com:2323"), true, at(0));
+        drop(state);
+
+        let state = DialState::load(Some(file)).unwrap();
+        assert!(!state.is_delayed("18006138199", SystemTime::now()));
+        assert_eq!(state.endpoint("18006138199").as_deref(), Some("ppp2.example.com:2323"));
+        assert_eq!(state.numbers.lock().unwrap()["18006138199"].connected_at, Some(1_700_000_000));
+        assert!(state.numbers.lock().unwrap()["18006138199"].is_56k);
+        drop(state);
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn saved_profiles_survive_a_restart() {
+        let file = std::env::temp_dir().join(format!("touchppp-profile-state-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&file);
+        let file = file.to_str().unwrap();
+
+        let mut modem = ModemSession::new().with_profile(&crate::at::GENERIC).with_saved_profiles(&Arc::new(DialState::load(Some(file)).unwrap()));
+        modem.answer("ATE0S7=60&W1\r");
+        let profile = modem.active_profile();
+        drop(modem);
+
+        let state = DialState::load(Some(file)).unwrap();
+        assert_eq!(state.stored_profile(1), Some(profile));
+        assert_eq!(state.stored_profile(0), None);
+        drop(state);
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn a_mangled_file_is_started_over() {
+        let file = std::env::temp_dir().join(format!("touchppp-mangled-state-{}.toml", std::process::id()));
+        let file = file.to_str().unwrap();
+
+        fs::write(file, "numbers = 1\n[[[").unwrap();
+
+        let state = DialState::load(Some(file)).unwrap();
+        assert_eq!(state.endpoint("18006138199"), None);
+
+        // And the next save replaces it with one that's fine.
+        state.connected("18006138199", Some("ppp2.example.com:2323"), false, SystemTime::now());
+        assert!(toml::from_str::<StateFile>(&fs::read_to_string(file).unwrap()).is_ok());
+        assert!(!Path::new(&format!("{file}.tmp")).exists());
+
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+// What can go wrong, typed so the dial handler and main can each tell what happened. What MAME gets told and
+// what we exit with are both decided here.
+
+use std::io;
+use thiserror::Error;
+
+use crate::at;
+
+#[derive(Debug, Error)]
+pub enum TouchPppError {
+    /// Bad options or config.
+    #[error("{0}")]
+    Config(String),
+    /// The port (or pipe) MAME calls couldn't be bound.
+    #[error("can't listen on {address}: {source}")]
+    Listen { address: String, source: io::Error },
+    /// A remote PPP server didn't answer.
+    #[error("can't touch PPP @ {endpoint}: {source}")]
+    BackendConnect { endpoint: String, source: io::Error },
+    /// The SSH server a remote PPP server's reached through wouldn't let us through.
+    #[error("can't get through SSH @ {server}: {reason}")]
+    BackendSsh { server: String, reason: String },
+    /// A local PPP program couldn't be started.
+    #[error("can't launch PPP '{command}': {source}")]
+    BackendSpawn { command: String, source: io::Error },
+    /// Copying between MAME and PPP failed part way through a call.
+    #[error("PPP loop failed: {0}")]
+    Bridge(#[from] io::Error),
+    /// A line from MAME that isn't a command we know.
+    #[error("don't know what to do with '{}'", .0.escape_debug())]
+    AtParse(String),
+}
+
+impl TouchPppError {
+    /// The result code MAME gets when this ends a dial or a call. None means it's ignored.
+    pub fn result_code(&self) -> Option<&'static [u8]> {
+        match self {
+            // Something's there but won't take the call, or isn't saying either way.
+            TouchPppError::BackendConnect { source, .. } if matches!(source.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::TimedOut) => Some(at::BUSY),
+            // Nowhere to call: the name didn't resolve or there's no route.
+            TouchPppError::BackendConnect { .. } => Some(at::NO_DIALTONE),
+            TouchPppError::BackendSsh { .. } | TouchPppError::BackendSpawn { .. } | TouchPppError::Bridge(_) => Some(at::NO_CARRIER),
+            // The box doesn't get an answer to a line it wasn't expecting one for.
+            TouchPppError::AtParse(_) => None,
+            TouchPppError::Config(_) | TouchPppError::Listen { .. } => Some(at::ERROR),
+        }
+    }
+
+    /// What the process exits with when this stops it: 2 for something the options or config got wrong, 1 for
+    /// anything else.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            TouchPppError::Config(_) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Whether fixing this means fixing the config, which is worth shouting about.
+    pub fn is_config_problem(&self) -> bool {
+        matches!(self, TouchPppError::Config(_) | TouchPppError::BackendSsh { .. } | TouchPppError::BackendSpawn { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect_error(kind: io::ErrorKind) -> TouchPppError {
+        TouchPppError::BackendConnect {
+            endpoint: "127.0.0.1:2323".to_string(),
+            source: io::Error::new(kind, "nope"),
+        }
+    }
+
+    #[test]
+    fn failures_map_to_result_codes() {
+        assert_eq!(connect_error(io::ErrorKind::ConnectionRefused).result_code(), Some(at::BUSY));
+        assert_eq!(connect_error(io::ErrorKind::TimedOut).result_code(), Some(at::BUSY));
+        assert_eq!(connect_error(io::ErrorKind::Other).result_code(), Some(at::NO_DIALTONE));
+
+        let spawn = TouchPppError::BackendSpawn {
+            command: "/no/such/pppd".to_string(),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        assert_eq!(spawn.result_code(), Some(at::NO_CARRIER));
+        assert!(spawn.is_config_problem());
+
+        let ssh = TouchPppError::BackendSsh {
+            server: "ppp@gateway.example.com:22".to_string(),
+            reason: "its host key isn't in known_hosts".to_string(),
+        };
+        assert_eq!(ssh.result_code(), Some(at::NO_CARRIER));
+        assert!(ssh.is_config_problem());
+
+        assert_eq!(TouchPppError::Bridge(io::Error::from(io::ErrorKind::BrokenPipe)).result_code(), Some(at::NO_CARRIER));
+        assert_eq!(TouchPppError::AtParse("\u{1}\r".to_string()).result_code(), None);
+    }
+
+    #[test]
+    fn only_config_problems_exit_with_2() {
+        assert_eq!(TouchPppError::Config("bad".to_string()).exit_code(), 2);
+        assert_eq!(connect_error(io::ErrorKind::ConnectionRefused).exit_code(), 1);
+
+        let listen = TouchPppError::Listen {
+            address: "127.0.0.1:1122".to_string(),
+            source: io::Error::from(io::ErrorKind::AddrInUse),
+        };
+        assert_eq!(listen.exit_code(), 1);
+        assert_eq!(listen.to_string(), "can't listen on 127.0.0.1:1122: address in use");
+    }
+}
@@ -0,0 +1,173 @@
+// Turning away connections before they cost anything, for a listener scanners can reach. Each source address
+// gets a token bucket (--accept-rate), and there's a cap on connections that haven't sent an AT command yet
+// (--max-pending) so a pile of silent ones can't tie up sessions either. The table of sources is bounded,
+// dropping whoever was seen longest ago, so a scan from everywhere can't grow it forever.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::config::AcceptRate;
+
+// How many source addresses are remembered at once.
+const MAX_SOURCES: usize = 4096;
+
+// How long an offender goes between log lines, however much it keeps at it.
+const LOG_EVERY: Duration = Duration::from_secs(60);
+
+struct Source {
+    tokens: f64,
+    refilled: Instant,
+    last_seen: Instant,
+    logged: Option<Instant>,
+}
+
+/// Whether a connection gets a session.
+pub enum Admission {
+    /// Hold on to the slot until MAME sends its first command.
+    Admitted(PendingSlot),
+    /// Close it right away. Only worth a log line if `is_worth_logging`.
+    Refused { why: &'static str, is_worth_logging: bool },
+}
+
+/// One of --max-pending's connections, given back when it's dropped.
+pub struct PendingSlot(Arc<AtomicUsize>);
+
+impl Drop for PendingSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Default)]
+pub struct AcceptLimiter {
+    sources: HashMap<IpAddr, Source>,
+    pending: Arc<AtomicUsize>,
+    pending_logged: Option<Instant>,
+}
+
+fn is_worth_logging(logged: &mut Option<Instant>, now: Instant) -> bool {
+    if logged.is_some_and(|logged| now.duration_since(logged) < LOG_EVERY) {
+        return false;
+    }
+
+    *logged = Some(now);
+
+    true
+}
+
+impl AcceptLimiter {
+    pub fn new() -> AcceptLimiter {
+        AcceptLimiter::default()
+    }
+
+    /// Decides on a connection from `source` (None for a named pipe, which only gets the pending cap). `rate` and
+    /// `max_pending` come from the config each time, so a reload changes them for the next one.
+    pub fn admit(&mut self, source: Option<IpAddr>, rate: AcceptRate, max_pending: usize, now: Instant) -> Admission {
+        if max_pending > 0 && self.pending.load(Ordering::SeqCst) >= max_pending {
+            return Admission::Refused { why: "too many connections haven't sent a command yet", is_worth_logging: is_worth_logging(&mut self.pending_logged, now) };
+        }
+
+        if let (Some(source), false) = (source, rate.is_off()) {
+            if !self.sources.contains_key(&source) && self.sources.len() >= MAX_SOURCES {
+                self.forget_oldest();
+            }
+
+            let entry = self.sources.entry(source).or_insert(Source { tokens: rate.count as f64, refilled: now, last_seen: now, logged: None });
+
+            let refill = now.duration_since(entry.refilled).as_secs_f64() * rate.count as f64 / rate.per.as_secs_f64();
+            entry.tokens = (entry.tokens + refill).min(rate.count as f64);
+            entry.refilled = now;
+            entry.last_seen = now;
+
+            if entry.tokens < 1.0 {
+                return Admission::Refused { why: "it's connecting too often", is_worth_logging: is_worth_logging(&mut entry.logged, now) };
+            }
+
+            entry.tokens -= 1.0;
+        }
+
+        self.pending.fetch_add(1, Ordering::SeqCst);
+
+        Admission::Admitted(PendingSlot(self.pending.clone()))
+    }
+
+    fn forget_oldest(&mut self) {
+        let oldest = self.sources.iter().min_by_key(|(_, source)| source.last_seen).map(|(address, _)| *address);
+
+        if let Some(oldest) = oldest {
+            self.sources.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIVE_PER_TEN: AcceptRate = AcceptRate { count: 5, per: Duration::from_secs(10) };
+
+    fn is_admitted(limiter: &mut AcceptLimiter, source: &str, rate: AcceptRate, max_pending: usize, now: Instant) -> bool {
+        matches!(limiter.admit(Some(source.parse().unwrap()), rate, max_pending, now), Admission::Admitted(_))
+    }
+
+    #[test]
+    fn each_source_gets_its_own_bucket() {
+        let mut limiter = AcceptLimiter::new();
+        let now = Instant::now();
+
+        let admitted = (0..50).filter(|_| is_admitted(&mut limiter, "192.0.2.1", FIVE_PER_TEN, 0, now)).count();
+        assert_eq!(admitted, 5);
+
+        assert!(is_admitted(&mut limiter, "192.0.2.2", FIVE_PER_TEN, 0, now));
+
+        // One more every two seconds.
+        assert!(!is_admitted(&mut limiter, "192.0.2.1", FIVE_PER_TEN, 0, now + Duration::from_secs(1)));
+        assert!(is_admitted(&mut limiter, "192.0.2.1", FIVE_PER_TEN, 0, now + Duration::from_secs(3)));
+        assert!(!is_admitted(&mut limiter, "192.0.2.1", FIVE_PER_TEN, 0, now + Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn offenders_are_logged_once_a_minute() {
+        let mut limiter = AcceptLimiter::new();
+        let now = Instant::now();
+        let rate = AcceptRate { count: 1, per: Duration::from_secs(3600) };
+
+        let mut logged = 0;
+        for second in 0..120 {
+            if let Admission::Refused { is_worth_logging: true, .. } = limiter.admit(Some("192.0.2.1".parse().unwrap()), rate, 0, now + Duration::from_secs(second)) {
+                logged += 1;
+            }
+        }
+
+        assert_eq!(logged, 2);
+    }
+
+    #[test]
+    fn pending_slots_come_back_when_dropped() {
+        let mut limiter = AcceptLimiter::new();
+        let now = Instant::now();
+
+        let first = limiter.admit(None, AcceptRate::OFF, 2, now);
+        let _second = limiter.admit(None, AcceptRate::OFF, 2, now);
+        assert!(matches!(limiter.admit(None, AcceptRate::OFF, 2, now), Admission::Refused { .. }));
+
+        drop(first);
+        assert!(matches!(limiter.admit(None, AcceptRate::OFF, 2, now), Admission::Admitted(_)));
+    }
+
+    #[test]
+    fn the_table_of_sources_stays_bounded() {
+        let mut limiter = AcceptLimiter::new();
+        let now = Instant::now();
+
+        for n in 0..(MAX_SOURCES as u32 + 100) {
+            limiter.admit(Some(IpAddr::from(n.to_be_bytes())), FIVE_PER_TEN, 0, now + Duration::from_millis(n as u64));
+        }
+
+        assert_eq!(limiter.sources.len(), MAX_SOURCES);
+        assert!(!limiter.sources.contains_key(&IpAddr::from(0u32.to_be_bytes())));
+    }
+}
@@ -0,0 +1,119 @@
+// By: Eric MacDonald (eMac)
+
+//! TouchPPP answers WebTV MAME modem calls and touches some PPP.
+//!
+//! [`server::Server`] takes calls the way the `touchppp` binary does. [`session::Session`] answers one call
+//! over any connection, for embedding or testing without a socket.
+//!
+//! ```no_run
+//! use touchppp::{Server, ServerConfig};
+//!
+//! # async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+//! let config = ServerConfig::builder().listen("127.0.0.1:0").connect("127.0.0.1:2323").build()?;
+//! let server = Server::from_config(config)?;
+//!
+//! println!("MAME can call {:?}", server.local_addrs());
+//! server.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod address;
+pub mod admin;
+pub mod at;
+pub mod backend;
+pub mod bench;
+pub mod bridge;
+pub mod cdr;
+pub mod check;
+pub mod config;
+pub mod console;
+#[cfg(unix)]
+pub mod daemon;
+pub mod diallog;
+pub mod dialstate;
+pub mod error;
+pub mod flood;
+pub mod jsonlog;
+pub mod listener;
+pub mod logfile;
+pub mod mame;
+pub mod modem;
+#[cfg(feature = "nat")]
+pub mod nat;
+pub mod passthrough;
+pub mod preset;
+mod ppp;
+pub mod resolve;
+pub mod selftest;
+pub mod server;
+pub mod session;
+pub mod slip;
+pub mod soak;
+#[cfg(all(unix, feature = "ssh"))]
+pub mod ssh;
+pub mod stats;
+pub mod status;
+pub mod syslog;
+#[cfg(unix)]
+pub mod systemd;
+pub mod textlog;
+pub mod transcript;
+#[cfg(all(target_os = "linux", feature = "tun"))]
+pub mod tun;
+pub mod udp;
+pub mod webhook;
+
+pub use config::Config;
+// What embedders build with Config::builder(), under the name they'd look for.
+pub use config::Config as ServerConfig;
+pub use error::TouchPppError;
+pub use server::Server;
+pub use session::Session;
+// The HDLC framing on its own, for fuzz/.
+#[doc(hidden)]
+pub use ppp::hdlc;
+
+/// Why starting (or running) didn't work out.
+pub enum StartError {
+    /// Bad options or config. Exits with 2.
+    Usage(String),
+    /// Something went wrong while running. Exits with 1.
+    Runtime(Box<dyn std::error::Error>),
+    /// --exit-after-sessions got through its sessions, but this many of them ended in an error. Exits with 3.
+    SessionsFailed(u64),
+}
+
+impl From<std::io::Error> for StartError {
+    fn from(e: std::io::Error) -> StartError {
+        StartError::Runtime(e.into())
+    }
+}
+
+// Whatever the error says about how to exit decides which kind it is.
+impl From<error::TouchPppError> for StartError {
+    fn from(e: error::TouchPppError) -> StartError {
+        match e.exit_code() {
+            2 => StartError::Usage(e.to_string()),
+            _ => StartError::Runtime(e.into()),
+        }
+    }
+}
+
+impl std::fmt::Debug for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartError::Usage(message) => write!(f, "{message}"),
+            StartError::Runtime(e) => write!(f, "{e}"),
+            StartError::SessionsFailed(failed) => write!(f, "{failed} sessions ended in an error"),
+        }
+    }
+}
+
+impl std::error::Error for StartError {}
@@ -0,0 +1,256 @@
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+// Whatever MAME connected over. The modem doesn't care if it's TCP or a named pipe.
+pub trait MameStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MameStream for T {}
+
+/// What gets set on every TCP connection a call goes over, MAME's and the remote PPP server's alike.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct SocketOptions {
+    /// TCP_NODELAY, for --low-latency.
+    pub no_delay: bool,
+    /// SO_RCVBUF and SO_SNDBUF in bytes, for --so-rcvbuf and --so-sndbuf. None leaves the kernel's default.
+    pub recv_buffer: Option<usize>,
+    pub send_buffer: Option<usize>,
+}
+
+impl SocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        if self.no_delay {
+            stream.set_nodelay(true)?;
+        }
+
+        let socket = socket2::SockRef::from(stream);
+
+        // The kernel's free to round these (Linux doubles them, then clamps to its max), so say what it gave us.
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+            debug!(target: "touchppp::socket", "Asked for a {size} byte receive buffer and got {}.", socket.recv_buffer_size()?);
+        }
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+            debug!(target: "touchppp::socket", "Asked for a {size} byte send buffer and got {}.", socket.send_buffer_size()?);
+        }
+
+        Ok(())
+    }
+
+    pub fn sets_buffers(&self) -> bool {
+        self.recv_buffer.is_some() || self.send_buffer.is_some()
+    }
+}
+
+/// Ties an outbound socket to a network interface with SO_BINDTODEVICE, for --bind-device, so what's sent to the
+/// PPP server leaves over it whatever the routing tables say. Only Linux has it, so anywhere else it's left alone
+/// (and warned about once at startup).
+pub fn bind_device(socket: socket2::SockRef, device: &str) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return socket.bind_device(Some(device.as_bytes())).map_err(|e| explain_bind_device(device, e));
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (socket, device);
+
+        Ok(())
+    }
+}
+
+// Some kernels only let root (or CAP_NET_RAW) do it, which the bare EPERM doesn't say.
+#[cfg(target_os = "linux")]
+fn explain_bind_device(device: &str, e: io::Error) -> io::Error {
+    let why = match e.kind() {
+        io::ErrorKind::PermissionDenied => "binding to a device takes root or CAP_NET_RAW here".to_string(),
+        _ => e.to_string(),
+    };
+
+    io::Error::new(e.kind(), format!("can't send over {device}: {why}"))
+}
+
+// Everything we take calls on: TCP sockets (more than one with systemd) and, on Windows, a named pipe.
+pub struct Listeners {
+    tcp: Vec<TcpListener>,
+    options: SocketOptions,
+    #[cfg(windows)]
+    pipe: Option<pipe::PipeListener>,
+}
+
+impl Listeners {
+    pub fn new(tcp: Vec<TcpListener>) -> Listeners {
+        Listeners {
+            tcp,
+            options: SocketOptions::default(),
+            #[cfg(windows)]
+            pipe: None,
+        }
+    }
+
+    pub fn with_options(mut self, options: SocketOptions) -> Listeners {
+        self.options = options;
+
+        self
+    }
+
+    #[cfg(windows)]
+    pub fn with_pipe(mut self, name: &str) -> io::Result<Listeners> {
+        self.pipe = Some(pipe::PipeListener::create(name)?);
+
+        Ok(self)
+    }
+
+    pub fn tcp(&self) -> &[TcpListener] {
+        &self.tcp
+    }
+
+    // What MAME's -bitb should point at.
+    pub fn describe(&self) -> io::Result<Vec<String>> {
+        let mut described = Vec::new();
+
+        #[cfg(windows)]
+        if let Some(pipe) = &self.pipe {
+            described.push(pipe.name.clone());
+        }
+
+        for listener in self.tcp.iter() {
+            described.push(listener.local_addr()?.to_string());
+        }
+
+        Ok(described)
+    }
+
+    // The next MAME to call, with something to call it by in the logs.
+    pub async fn accept(&mut self) -> io::Result<(Box<dyn MameStream>, String)> {
+        let tcp_accepted = accept_tcp(&self.tcp, self.options);
+
+        #[cfg(windows)]
+        if let Some(pipe) = &mut self.pipe {
+            return tokio::select! {
+                accepted = tcp_accepted => {
+                    let (stream, address) = accepted?;
+                    Ok((Box::new(stream), address.to_string()))
+                },
+                accepted = pipe.accept() => Ok((Box::new(accepted?), pipe.name.clone())),
+            };
+        }
+
+        let (stream, address) = tcp_accepted.await?;
+
+        Ok((Box::new(stream), address.to_string()))
+    }
+}
+
+// The next MAME to call over TCP, with the socket options already set.
+async fn accept_tcp(tcp_listeners: &[TcpListener], options: SocketOptions) -> io::Result<(TcpStream, SocketAddr)> {
+    if tcp_listeners.is_empty() {
+        return futures::future::pending().await;
+    }
+
+    let (accepted, _, _) = futures::future::select_all(tcp_listeners.iter().map(|listener| Box::pin(listener.accept()))).await;
+    let (stream, address) = accepted?;
+
+    // Not worth turning the call away over.
+    if let Err(e) = options.apply(&stream) {
+        warn!("Couldn't set socket options for MAME @ {address}: error={e}");
+    }
+
+    Ok((stream, address))
+}
+
+#[cfg(windows)]
+mod pipe {
+    use std::io;
+    use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+
+    pub struct PipeListener {
+        pub name: String,
+        // The instance waiting for the next client.
+        server: NamedPipeServer,
+    }
+
+    impl PipeListener {
+        pub fn create(name: &str) -> io::Result<PipeListener> {
+            // first_pipe_instance so we fail like a busy port would if another TouchPPP has the name.
+            let server = ServerOptions::new().first_pipe_instance(true).create(name)?;
+
+            Ok(PipeListener {
+                name: name.to_string(),
+                server,
+            })
+        }
+
+        // connect() is cancel safe, so losing a select to a TCP client doesn't lose a pipe client.
+        pub async fn accept(&mut self) -> io::Result<NamedPipeServer> {
+            self.server.connect().await?;
+
+            // A pipe instance only ever serves one client, so line up the next one before handing this one
+            // over. Creating it first also means there's no moment where a client would find no pipe at all.
+            let next = ServerOptions::new().create(&self.name)?;
+
+            Ok(std::mem::replace(&mut self.server, next))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn accepted_calls_get_the_socket_options() {
+        for no_delay in [false, true] {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let _mame = TcpStream::connect(address).await.unwrap();
+            let (accepted, _) = accept_tcp(&[listener], SocketOptions { no_delay, ..Default::default() }).await.unwrap();
+
+            assert_eq!(accepted.nodelay().unwrap(), no_delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn socket_buffers_get_the_size_asked_for() {
+        let options = SocketOptions { recv_buffer: Some(0x10000), send_buffer: Some(0x20000), ..Default::default() };
+
+        for host in ["127.0.0.1:0", "[::1]:0"] {
+            // Not every box has IPv6 on loopback.
+            let Ok(listener) = TcpListener::bind(host).await else {
+                continue;
+            };
+            let address = listener.local_addr().unwrap();
+
+            let _mame = TcpStream::connect(address).await.unwrap();
+            let (accepted, _) = accept_tcp(&[listener], options).await.unwrap();
+
+            let socket = socket2::SockRef::from(&accepted);
+            let recv_buffer = socket.recv_buffer_size().unwrap();
+            let send_buffer = socket.send_buffer_size().unwrap();
+
+            // Linux doubles what it's asked for, to leave room for its own bookkeeping.
+            assert!((0x10000..=0x20000).contains(&recv_buffer), "{host} got a {recv_buffer} byte receive buffer");
+            assert!((0x20000..=0x40000).contains(&send_buffer), "{host} got a {send_buffer} byte send buffer");
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn binding_to_a_device_says_why_it_cant() {
+        let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+
+        let e = bind_device(socket2::SockRef::from(&socket), "touchppp0").unwrap_err();
+        assert!(e.to_string().starts_with("can't send over touchppp0: "), "{e}");
+
+        // lo's always there, so only not being allowed to stops this.
+        if let Err(e) = bind_device(socket2::SockRef::from(&socket), "lo") {
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied, "{e}");
+        }
+
+        let e = explain_bind_device("eth0.20", io::Error::from_raw_os_error(1));
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(e.to_string(), "can't send over eth0.20: binding to a device takes root or CAP_NET_RAW here");
+    }
+}
@@ -0,0 +1,250 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing_subscriber::fmt::MakeWriter;
+
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_LOG_KEEP: usize = 5;
+
+struct ActiveFile {
+    writer: LineWriter<File>,
+    size: u64,
+}
+
+// A log file that gets moved to PATH.1 (PATH.1 to PATH.2 and so on) once it would grow past max_size.
+pub struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    active: Mutex<ActiveFile>,
+}
+
+fn open_active(path: &PathBuf) -> io::Result<ActiveFile> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+
+    Ok(ActiveFile {
+        writer: LineWriter::new(file),
+        size,
+    })
+}
+
+// Sizes like 1048576, 512K, 10M or 1G.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+
+    let (digits, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    match digits.trim().parse::<u64>() {
+        Ok(size) if size > 0 => Ok(size * multiplier),
+        _ => Err(format!("'{value}' isn't a size (try something like 1048576, 512K or 10M)")),
+    }
+}
+
+// The other way around, in the biggest unit that keeps it exact.
+pub fn format_size(size: u64) -> String {
+    for (suffix, multiplier) in [("G", 1024 * 1024 * 1024), ("M", 1024 * 1024), ("K", 1024)] {
+        if size >= multiplier && size.is_multiple_of(multiplier) {
+            return format!("{}{suffix}", size / multiplier);
+        }
+    }
+
+    size.to_string()
+}
+
+impl LogFile {
+    pub fn open(path: &str, max_size: u64, keep: usize) -> io::Result<LogFile> {
+        let path = PathBuf::from(path);
+        let active = open_active(&path)?;
+
+        Ok(LogFile {
+            path,
+            max_size,
+            keep,
+            active: Mutex::new(active),
+        })
+    }
+
+    fn numbered_path(&self, number: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{number}"));
+
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self, active: &mut ActiveFile) -> io::Result<()> {
+        active.writer.flush()?;
+
+        // Oldest first so nothing gets overwritten. Every step is a rename, so a crash part way through
+        // leaves the old lines in one of the numbered files rather than losing them.
+        if self.keep > 0 {
+            let _ = fs::remove_file(self.numbered_path(self.keep));
+
+            for number in (1..self.keep).rev() {
+                let from = self.numbered_path(number);
+
+                if from.exists() {
+                    fs::rename(&from, self.numbered_path(number + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.numbered_path(1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        *active = open_active(&self.path)?;
+
+        Ok(())
+    }
+
+    fn write_record(&self, record: &[u8], is_urgent: bool) -> io::Result<()> {
+        let mut active = match self.active.lock() {
+            Ok(r) => r,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if active.size > 0 && active.size + record.len() as u64 > self.max_size {
+            self.rotate(&mut active)?;
+        }
+
+        active.writer.write_all(record)?;
+        active.size += record.len() as u64;
+
+        if is_urgent {
+            active.writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+// Collects one log record so it lands in the file in one piece.
+pub struct LogFileWriter<'a> {
+    log_file: &'a LogFile,
+    record: Vec<u8>,
+    is_urgent: bool,
+}
+
+impl Write for LogFileWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.record.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogFileWriter<'_> {
+    fn drop(&mut self) {
+        if !self.record.is_empty() {
+            // Nowhere left to log a logging failure, so stderr it is.
+            if let Err(e) = self.log_file.write_record(&self.record, self.is_urgent) {
+                eprintln!("Can't write to the log file {}: error={e}", self.log_file.path.display());
+            }
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = LogFileWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFileWriter {
+            log_file: self,
+            record: Vec::new(),
+            is_urgent: true,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        LogFileWriter {
+            log_file: self,
+            record: Vec::new(),
+            // Warnings and errors are flushed right away in case they're the last thing we get to say.
+            is_urgent: *meta.level() <= tracing::Level::WARN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("touchppp-log-{}-{name}", std::process::id()))
+    }
+
+    fn log(log_file: &LogFile, line: &str) {
+        let mut writer = log_file.make_writer();
+        writer.write_all(line.as_bytes()).unwrap();
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn rotates_past_the_size_keeping_as_many_as_asked() {
+        let path = scratch_path("rotate.log");
+        let log_file = LogFile::open(path.to_str().unwrap(), 100, 2).unwrap();
+
+        // Two 40 byte lines fit in 100 and a third doesn't, so they go two to a file.
+        let lines: Vec<String> = (0..10).map(|n| format!("{n:<39}\n")).collect();
+        for line in &lines {
+            log(&log_file, line);
+        }
+
+        assert_eq!(read(path.clone()), lines[8..10].concat());
+        assert_eq!(read(log_file.numbered_path(1)), lines[6..8].concat());
+        assert_eq!(read(log_file.numbered_path(2)), lines[4..6].concat());
+        // The ones before that are gone, with nothing past what was asked to be kept.
+        assert!(!log_file.numbered_path(3).exists());
+
+        for number in 0..=2 {
+            let _ = fs::remove_file(log_file.numbered_path(number));
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn keeping_none_starts_over() {
+        let path = scratch_path("keep-none.log");
+        let log_file = LogFile::open(path.to_str().unwrap(), 100, 0).unwrap();
+
+        for n in 0..3 {
+            log(&log_file, &format!("{n:<59}\n"));
+        }
+
+        assert_eq!(read(path.clone()), format!("{:<59}\n", 2));
+        assert!(!log_file.numbered_path(1).exists());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn carries_on_from_the_size_already_there() {
+        let path = scratch_path("reopened.log");
+        fs::write(&path, "x".repeat(90)).unwrap();
+
+        // A line that's bigger than the limit by itself still goes in whole, in a file of its own.
+        let log_file = LogFile::open(path.to_str().unwrap(), 100, 1).unwrap();
+        let long_line = format!("{}\n", "y".repeat(150));
+        log(&log_file, &long_line);
+
+        assert_eq!(read(path.clone()), long_line);
+        assert_eq!(read(log_file.numbered_path(1)), "x".repeat(90));
+
+        let _ = fs::remove_file(log_file.numbered_path(1));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,135 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tracing::{error, info, warn};
+
+use crate::config::MameSlot;
+
+// Don't spin if MAME dies right away every time.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+// Where MAME should connect. Listening on 0.0.0.0 or [::] means MAME can reach us over loopback.
+pub fn connect_address(bound: SocketAddr) -> SocketAddr {
+    match bound.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), bound.port()),
+        IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), bound.port()),
+        _ => bound,
+    }
+}
+
+// What -bitb needs to reach us over TCP.
+pub fn socket_target(address: SocketAddr) -> String {
+    format!("socket.{}", connect_address(address))
+}
+
+// The arguments that plug a null modem into the box and point its bitbanger at target.
+pub fn bitbanger_args(slot: MameSlot, target: &str) -> Vec<String> {
+    vec![
+        format!("-{slot}:modem"),
+        "null_modem".to_string(),
+        "-bitb".to_string(),
+        target.to_string(),
+    ]
+}
+
+async fn log_lines<R: AsyncRead + Unpin>(reader: R) {
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        info!(target: "touchppp::mame", "mame: {line}");
+    }
+}
+
+async fn launch_once(command: &str, args: &[String]) -> std::io::Result<ExitStatus> {
+    let mut the_args = command.split(' ');
+    let first: &str = the_args.next().unwrap();
+    let rest: Vec<&str> = the_args.collect::<Vec<&str>>();
+
+    let mut mame = Command::new(first)
+        .args(rest)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdout = mame.stdout.take().expect("No MAME STDOUT?");
+    let stderr = mame.stderr.take().expect("No MAME STDERR?");
+
+    let (_, _, status) = tokio::join!(log_lines(stdout), log_lines(stderr), mame.wait());
+
+    status
+}
+
+// Runs MAME until it exits for good. With restart set, that only happens if it can't be launched at all.
+pub async fn supervise(command: String, slot: MameSlot, target: String, restart: bool) {
+    let args = bitbanger_args(slot, &target);
+
+    loop {
+        info!(target: "touchppp::mame", "Launching MAME: '{command} {}'", args.join(" "));
+
+        match launch_once(&command, &args).await {
+            Ok(status) if status.success() => info!(target: "touchppp::mame", "MAME exited."),
+            Ok(status) => warn!(target: "touchppp::mame", "MAME exited with {status}."),
+            Err(e) => {
+                error!(target: "touchppp::mame", "Can't launch MAME '{command}': error={e}");
+                return;
+            },
+        }
+
+        if !restart {
+            return;
+        }
+
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unspecified_listen_connects_over_loopback() {
+        assert_eq!(connect_address("0.0.0.0:1122".parse().unwrap()).to_string(), "127.0.0.1:1122");
+        assert_eq!(connect_address("[::]:1122".parse().unwrap()).to_string(), "[::1]:1122");
+        assert_eq!(connect_address("10.0.0.5:41234".parse().unwrap()).to_string(), "10.0.0.5:41234");
+    }
+
+    #[test]
+    fn bitbanger_args_follow_the_slot() {
+        let target = socket_target("127.0.0.1:41234".parse().unwrap());
+
+        assert_eq!(bitbanger_args(MameSlot::Spot, &target), ["-spot:modem", "null_modem", "-bitb", "socket.127.0.0.1:41234"]);
+        assert_eq!(bitbanger_args(MameSlot::Solo, &target), ["-solo:modem", "null_modem", "-bitb", "socket.127.0.0.1:41234"]);
+        assert_eq!(bitbanger_args(MameSlot::Spot, r"\\.\pipe\touchppp"), ["-spot:modem", "null_modem", "-bitb", r"\\.\pipe\touchppp"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stub_mame_gets_the_bitbanger_args() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let stub = std::env::temp_dir().join(format!("touchppp-stub-mame-{}", std::process::id()));
+        let argv = stub.with_extension("argv");
+
+        // Stands in for MAME and writes down the argv it was launched with.
+        std::fs::write(&stub, "#!/bin/sh\necho \"$@\" > \"$0.argv\"\n").unwrap();
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let command = format!("{} wtv1sony -window", stub.display());
+        let args = bitbanger_args(MameSlot::Spot, &socket_target("0.0.0.0:1122".parse().unwrap()));
+
+        let status = launch_once(&command, &args).await.unwrap();
+        let written = std::fs::read_to_string(&argv).unwrap();
+
+        let _ = std::fs::remove_file(&stub);
+        let _ = std::fs::remove_file(&argv);
+
+        assert!(status.success());
+        assert_eq!(written, "wtv1sony -window -spot:modem null_modem -bitb socket.127.0.0.1:1122\n");
+    }
+}
@@ -0,0 +1,843 @@
+// The modem's side of a call as a state machine. AT commands and whatever happens on the PPP side go in, the
+// result codes MAME should get come out.
+//
+//   CommandMode --ATD--> Dialing --connected--> Online --PPP done--> CommandMode
+//                           '--busy/delayed/blacklisted--> CommandMode
+//                                                   '--escape--> Suspended --ATO--> Online
+//                                                                   '--ATH--> CommandMode
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::at::{self, Command};
+use crate::dialstate::DialState;
+use crate::error::TouchPppError;
+use crate::preset::{self, ModemPreset};
+
+/// Where a call is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModemState {
+    /// Taking AT commands, with no call up.
+    CommandMode,
+    /// ATD's been sent and the backend hasn't been sorted out yet.
+    Dialing,
+    /// Bridged to PPP.
+    Online,
+    /// Taking AT commands with the call still up, until ATO goes back to it or ATH drops it.
+    Suspended,
+}
+
+impl fmt::Display for ModemState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModemState::CommandMode => write!(f, "command mode"),
+            ModemState::Dialing => write!(f, "dialing"),
+            ModemState::Online => write!(f, "online"),
+            ModemState::Suspended => write!(f, "suspended"),
+        }
+    }
+}
+
+/// Something that moves a call along.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A whole AT command line from MAME.
+    Command(&'a Command),
+    /// PPP showed up in command mode and --auto-data says that's as good as a dial.
+    AutoData,
+    /// The backend's there to take the call.
+    Connected,
+    /// The backend isn't answering.
+    Busy,
+    /// The number's failed too often lately to be dialed again yet.
+    Delayed,
+    /// The number's never to be dialed.
+    Blacklisted,
+    /// The backend couldn't be reached, or the call to it broke.
+    Failed(&'a TouchPppError),
+    /// Data mode ended from the PPP side.
+    BridgeDone,
+    /// MAME escaped back to commands without hanging up.
+    Escaped,
+    /// We're hanging up on MAME.
+    Killed,
+}
+
+/// How many profiles &W can save, &W0 and &W1 like most modems.
+pub const PROFILE_SLOTS: u32 = 2;
+
+/// The settings &W saves and ATZ brings back: E, Q, V, X, %C, \N and the S registers, keyed like "S7".
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StoredProfile {
+    pub echo: bool,
+    pub verbose: bool,
+    pub quiet: bool,
+    pub level: u8,
+    pub compression: bool,
+    pub error_correction: bool,
+    pub s_registers: BTreeMap<String, u32>,
+}
+
+// The way &V lists one: the flags on a line, then the S registers.
+impl fmt::Display for StoredProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "E{} Q{} V{} X{} %C{} \\N{}\r\n",
+            self.echo as u8,
+            self.quiet as u8,
+            self.verbose as u8,
+            self.level,
+            self.compression as u8,
+            if self.error_correction { 3 } else { 0 },
+        )?;
+
+        let registers = self.registers().into_iter().map(|(register, value)| format!("S{register:02}:{value:03}")).collect::<Vec<_>>();
+
+        write!(f, "{}", registers.join(" "))
+    }
+}
+
+impl StoredProfile {
+    fn registers(&self) -> BTreeMap<u32, u32> {
+        self.s_registers.iter().filter_map(|(register, value)| Some((register.strip_prefix('S')?.parse().ok()?, *value))).collect()
+    }
+}
+
+/// One call's modem: its state, the number it was last told to dial and what it says when a call goes through.
+pub struct ModemSession {
+    profile: &'static at::ModemProfile,
+    preset: &'static ModemPreset,
+    state: ModemState,
+    dialed_number: String,
+    // What the config says to report, before %C and \N have their say, and what the phone book entry for the
+    // number being dialed says instead. Each D starts over without the last dial's.
+    report: at::ConnectReport,
+    dial_report: Option<at::ConnectReport>,
+    compression: bool,
+    error_correction: bool,
+    modulation: at::ModulationConfig,
+    // The report as it stands, ready to send, and the CARRIER it says.
+    connect: Vec<u8>,
+    carrier_speed: u32,
+    // E, V, Q and X as they stand, for a profile that follows them.
+    echo: bool,
+    form: at::ResultForm,
+    // The last result, rewritten for the profile.
+    reply: Vec<u8>,
+    // What Sn=v has set, on top of the preset's.
+    s_registers: BTreeMap<u32, u32>,
+    // Where &W saves to and ATZ brings back from.
+    saved: Arc<DialState>,
+    // Whether ATZ and AT&F forget dialed_number, which ATDL dials again.
+    reset_forgets_number: bool,
+}
+
+// What a modem is like when it's switched on: whatever &W0 saved.
+fn power_up() -> at::Settings {
+    at::Settings { reset: true, recall: Some(0), ..Default::default() }
+}
+
+impl Default for ModemSession {
+    fn default() -> ModemSession {
+        ModemSession::new()
+    }
+}
+
+impl ModemSession {
+    pub fn new() -> ModemSession {
+        ModemSession::with_report(at::ConnectReport::default())
+    }
+
+    /// A modem that reports `report` when a call goes through instead of the usual CONNECT sequence. Its
+    /// speeds have to have result codes, which Config has already made sure of.
+    pub fn with_report(report: at::ConnectReport) -> ModemSession {
+        let mut modem = ModemSession {
+            profile: &at::WEBTV,
+            preset: &preset::WEBTV_K56,
+            state: ModemState::CommandMode,
+            dialed_number: "".to_string(),
+            report,
+            dial_report: None,
+            compression: true,
+            error_correction: true,
+            modulation: at::ModulationConfig::default(),
+            connect: Vec::new(),
+            carrier_speed: 0,
+            echo: false,
+            form: at::ResultForm::default(),
+            reply: Vec::new(),
+            s_registers: BTreeMap::new(),
+            saved: Arc::new(DialState::new()),
+            reset_forgets_number: false,
+        };
+
+        modem.configure(at::Settings::default());
+
+        modem
+    }
+
+    /// The same modem with `profile`'s personality, starting out the way ATZ would leave it.
+    pub fn with_profile(mut self, profile: &'static at::ModemProfile) -> ModemSession {
+        self.profile = profile;
+        self.configure(power_up());
+
+        self
+    }
+
+    /// The same modem, but `preset`'s make and model. Starts out the way ATZ would leave it too.
+    pub fn with_preset(mut self, preset: &'static ModemPreset) -> ModemSession {
+        self.preset = preset;
+        self.configure(power_up());
+
+        self
+    }
+
+    /// The same modem, saving profiles with &W to `saved` and starting out with whatever &W0 saved there.
+    pub fn with_saved_profiles(mut self, saved: &Arc<DialState>) -> ModemSession {
+        self.saved = saved.clone();
+        self.configure(power_up());
+
+        self
+    }
+
+    /// The same modem, but one that forgets the last number dialed on ATZ and AT&F when `forgets`.
+    pub fn with_reset_forgetting_number(mut self, forgets: bool) -> ModemSession {
+        self.reset_forgets_number = forgets;
+
+        self
+    }
+
+    /// Takes on whatever compression, error correction and modulation a command line asked for, which changes
+    /// what the next CONNECT reports. They stick from one call to the next, same as a real modem, until ATZ or
+    /// AT&F.
+    pub fn configure(&mut self, settings: at::Settings) {
+        if settings.reset {
+            let profile = settings.recall.and_then(|slot| self.saved.stored_profile(slot)).unwrap_or_else(|| self.factory_profile());
+
+            self.restore(profile);
+            if self.reset_forgets_number {
+                self.dialed_number.clear();
+            }
+            self.modulation = at::ModulationConfig { s51: self.s_registers.get(&51).copied(), ..Default::default() };
+        }
+
+        self.echo = settings.echo.unwrap_or(self.echo);
+        self.form.verbose = settings.verbose.unwrap_or(self.form.verbose);
+        self.form.quiet = settings.quiet.unwrap_or(self.form.quiet);
+        self.form.level = settings.level.unwrap_or(self.form.level);
+
+        self.compression = settings.compression.unwrap_or(self.compression);
+        self.error_correction = settings.error_correction.unwrap_or(self.error_correction);
+        if let Some(modulation) = settings.modulation {
+            self.modulation = at::ModulationConfig { s51: self.modulation.s51, ..modulation };
+        }
+        if let Some(s51) = settings.s51 {
+            self.modulation.s51 = Some(s51);
+        }
+
+        let configured = self.dial_report.as_ref().unwrap_or(&self.report);
+
+        // Only ever slower than what the config says, and always a speed with a result code.
+        self.carrier_speed = match self.modulation.top_speed() {
+            Some(top_speed) if configured.follows_modulation && top_speed < configured.carrier_speed => at::carrier_at_most(top_speed),
+            _ => configured.carrier_speed,
+        };
+
+        let report = at::ConnectReport {
+            carrier_speed: self.carrier_speed,
+            compression: configured.compression && self.compression,
+            protocol: if self.error_correction { configured.protocol } else { Some(at::Protocol::None) },
+            ..configured.clone()
+        };
+
+        self.connect = report.sequence().unwrap_or_else(|_| at::CONNECT.to_vec());
+    }
+
+    /// The settings as they stand, the way &W saves them.
+    pub fn active_profile(&self) -> StoredProfile {
+        StoredProfile {
+            echo: self.echo,
+            verbose: self.form.verbose,
+            quiet: self.form.quiet,
+            level: self.form.level,
+            compression: self.compression,
+            error_correction: self.error_correction,
+            s_registers: self.s_registers.iter().map(|(register, value)| (format!("S{register}"), *value)).collect(),
+        }
+    }
+
+    // What AT&F leaves, and what ATZ does when &W never saved anything.
+    fn factory_profile(&self) -> StoredProfile {
+        let form = at::ResultForm::default();
+
+        StoredProfile {
+            echo: self.profile.follows_result_settings,
+            verbose: form.verbose,
+            quiet: form.quiet,
+            level: form.level,
+            compression: true,
+            error_correction: true,
+            s_registers: self.preset.s_registers.iter().map(|(register, value)| (format!("S{register}"), *value)).collect(),
+        }
+    }
+
+    fn restore(&mut self, profile: StoredProfile) {
+        self.s_registers = profile.registers();
+        self.echo = profile.echo;
+        self.compression = profile.compression;
+        self.error_correction = profile.error_correction;
+        self.form = at::ResultForm {
+            verbose: profile.verbose,
+            quiet: profile.quiet,
+            level: profile.level,
+            intermediates: self.profile.reports_intermediates,
+            connect_suffix: self.preset.connect_suffix,
+        };
+    }
+
+    /// Reports `report` if this dial goes through, for a number whose phone book entry changes it. What the box's
+    /// init string turned off still counts, and the next D goes back to the config's.
+    pub fn set_report(&mut self, report: at::ConnectReport) {
+        self.dial_report = Some(report);
+        self.configure(at::Settings::default());
+    }
+
+    // A new dial: nothing the last number decided carries over, and the carrier's worked out again from the
+    // init string as it stands now.
+    fn start_dial(&mut self, number: &str) {
+        self.dialed_number = number.to_string();
+        self.dial_report = None;
+        self.configure(at::Settings::default());
+    }
+
+    pub fn state(&self) -> ModemState {
+        self.state
+    }
+
+    /// What CARRIER says on the next CONNECT.
+    pub fn carrier_speed(&self) -> u32 {
+        self.carrier_speed
+    }
+
+    /// What +MS and S51 have said so far.
+    pub fn modulation(&self) -> &at::ModulationConfig {
+        &self.modulation
+    }
+
+    /// The number from the last ATDT, which the phone book picks a backend with once ATD (or ATDL) comes.
+    pub fn dialed_number(&self) -> &str {
+        &self.dialed_number
+    }
+
+    /// Whether command lines should be sent back as they come (E1), for a profile that follows E.
+    pub fn echoes(&self) -> bool {
+        self.profile.follows_result_settings && self.echo
+    }
+
+    /// Answers a whole command line: takes on its settings, then does what it asks, giving back what MAME should
+    /// get for it.
+    pub fn answer(&mut self, at_string: &str) -> Option<&[u8]> {
+        if self.profile.rejects_unknown_commands && !at::is_well_formed(at_string) {
+            debug!(target: "touchppp::at", "Not a command this modem knows: {}", at_string.trim_end());
+
+            return self.respond(at::ERROR);
+        }
+
+        let settings = at::settings(at_string);
+        if self.profile.rejects_unknown_commands && [settings.recall, settings.store].into_iter().flatten().any(|slot| slot >= PROFILE_SLOTS) {
+            debug!(target: "touchppp::at", "There's no profile slot like that: {}", at_string.trim_end());
+
+            return self.respond(at::ERROR);
+        }
+
+        self.configure(settings);
+        self.s_registers.extend(at::s_register_writes(at_string));
+
+        if let Some(slot) = settings.store.filter(|slot| *slot < PROFILE_SLOTS) {
+            self.saved.store_profile(slot, self.active_profile());
+        }
+
+        if self.profile.answers_queries && (self.state == ModemState::CommandMode || self.state == ModemState::Suspended) {
+            if matches!(&at::tokenize(at_string)[..], [token] if token.name == "&V") {
+                return self.identity(&self.listing());
+            }
+
+            match self.preset.identify(at_string) {
+                Some(Some(text)) => return self.identity(text),
+                Some(None) => return self.respond(at::ERROR),
+                None => {},
+            }
+
+            if let Some(register) = at::s_register_query(at_string) {
+                let value = self.s_registers.get(&register).copied().unwrap_or(0);
+
+                return self.identity(&format!("{value:03}"));
+            }
+        }
+
+        match at::parse_as(at_string, self.profile) {
+            Ok(command) => self.handle(Event::Command(&command)),
+            Err(e) => {
+                debug!(target: "touchppp::at", "{e}");
+
+                match e.result_code() {
+                    Some(code) => self.respond(code),
+                    None => None,
+                }
+            },
+        }
+    }
+
+    /// `canned` (one of the V0 results in [`at`]) the way this modem would say it.
+    pub fn respond(&mut self, canned: &[u8]) -> Option<&[u8]> {
+        self.reply = match self.profile.follows_result_settings {
+            true => at::hayes_result(canned, self.form),
+            false => canned.to_vec(),
+        };
+
+        (!self.reply.is_empty()).then_some(&self.reply[..])
+    }
+
+    // What &V shows: the settings as they stand, then each slot's, which is the factory's if &W never saved it,
+    // then the number ATDL would dial.
+    fn listing(&self) -> String {
+        let mut listing = format!("ACTIVE PROFILE:\r\n{}", self.active_profile());
+
+        for slot in 0..PROFILE_SLOTS {
+            let profile = self.saved.stored_profile(slot).unwrap_or_else(|| self.factory_profile());
+
+            listing += &format!("\r\n\r\nSTORED PROFILE {slot}:\r\n{profile}");
+        }
+
+        if !self.dialed_number.is_empty() {
+            listing += &format!("\r\n\r\nLAST DIALED NUMBER: {}", self.dialed_number);
+        }
+
+        listing
+    }
+
+    // ATI's (or Sn?'s) answer, then OK.
+    fn identity(&mut self, text: &str) -> Option<&[u8]> {
+        let mut reply = match (text, self.form.verbose) {
+            ("", _) => Vec::new(),
+            (text, true) => format!("\r\n{text}\r\n").into_bytes(),
+            (text, false) => format!("{text}\r\n").into_bytes(),
+        };
+        if self.form.quiet {
+            reply.clear();
+        }
+        reply.extend(at::hayes_result(at::OK, self.form));
+
+        self.reply = reply;
+
+        (!self.reply.is_empty()).then_some(&self.reply[..])
+    }
+
+    /// Moves to wherever `event` takes us, giving back the result code MAME should get for it, if any. Events
+    /// that don't make sense where we are leave the state alone.
+    pub fn handle(&mut self, event: Event) -> Option<&[u8]> {
+        let canned = self.step(event)?.to_vec();
+
+        self.respond(&canned)
+    }
+
+    fn step(&mut self, event: Event) -> Option<&[u8]> {
+        use ModemState::*;
+
+        let (next, reply): (ModemState, Option<&[u8]>) = match (self.state, &event) {
+            (CommandMode, Event::Command(command)) => match command {
+                Command::Init => (CommandMode, Some(at::OK)),
+                Command::DialSetup | Command::HangUp => (CommandMode, Some(at::SETUP_OK)),
+                Command::Dial(number) if self.profile.dial_goes_online => {
+                    self.start_dial(number);
+
+                    (Dialing, None)
+                },
+                Command::Dial(number) => {
+                    self.start_dial(number);
+
+                    (CommandMode, Some(at::DIAL_OK))
+                },
+                // CONNECT or BUSY comes once the session knows which.
+                Command::DataMode => (Dialing, None),
+                // The whole dial over again, whichever profile this is.
+                Command::Redial if !self.dialed_number.is_empty() => (Dialing, None),
+                Command::Redial => (CommandMode, Some(at::ERROR)),
+                // Nothing to go back to.
+                Command::Resume => (CommandMode, Some(at::NO_CARRIER)),
+            },
+            (Suspended, Event::Command(command)) => match command {
+                Command::Resume => (Online, Some(&self.connect)),
+                Command::HangUp => (CommandMode, Some(at::SETUP_OK)),
+                Command::Init => (Suspended, Some(at::OK)),
+                Command::DialSetup => (Suspended, Some(at::SETUP_OK)),
+                // There's already a call up.
+                Command::Dial(_) | Command::DataMode | Command::Redial => (Suspended, Some(at::ERROR)),
+            },
+            (CommandMode, Event::AutoData) => (Dialing, None),
+            (Dialing, Event::Connected) => (Online, Some(&self.connect)),
+            (Dialing, Event::Busy) => (CommandMode, Some(at::BUSY)),
+            (Dialing, Event::Delayed) => (CommandMode, Some(at::DELAYED)),
+            (Dialing, Event::Blacklisted) => (CommandMode, Some(at::BLACKLISTED)),
+            (Dialing, Event::Failed(e)) | (Online, Event::Failed(e)) => (CommandMode, e.result_code()),
+            (Online, Event::BridgeDone) | (Suspended, Event::BridgeDone) => (CommandMode, None),
+            (Online, Event::Escaped) => (Suspended, Some(at::OK)),
+            (Dialing, Event::Killed) | (Online, Event::Killed) | (Suspended, Event::Killed) => (CommandMode, Some(at::NO_CARRIER)),
+            (CommandMode, Event::Killed) => (CommandMode, None),
+            (state, event) => {
+                debug!(target: "touchppp::modem", "Ignoring {event:?} while {state}.");
+
+                (state, None)
+            },
+        };
+
+        if next != self.state {
+            debug!(target: "touchppp::modem", from = %self.state, to = %next, "Modem went from {} to {next} on {event:?}.", self.state);
+
+            self.state = next;
+        }
+
+        reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modem_in(state: ModemState) -> ModemSession {
+        let mut modem = ModemSession::new();
+
+        if state != ModemState::CommandMode {
+            modem.handle(Event::Command(&Command::DataMode));
+        }
+        if state == ModemState::Online || state == ModemState::Suspended {
+            modem.handle(Event::Connected);
+        }
+        if state == ModemState::Suspended {
+            modem.handle(Event::Escaped);
+        }
+
+        assert_eq!(modem.state(), state);
+
+        modem
+    }
+
+    #[test]
+    fn goes_through_a_call() {
+        let mut modem = ModemSession::new();
+
+        assert_eq!(modem.handle(Event::Command(&Command::Init)), Some(at::OK));
+        assert_eq!(modem.handle(Event::Command(&Command::DialSetup)), Some(at::SETUP_OK));
+        assert_eq!(modem.handle(Event::Command(&Command::Dial("18006138199".to_string()))), Some(at::DIAL_OK));
+        assert_eq!(modem.dialed_number(), "18006138199");
+
+        assert_eq!(modem.handle(Event::Command(&Command::DataMode)), None);
+        assert_eq!(modem.state(), ModemState::Dialing);
+
+        assert_eq!(modem.handle(Event::Connected), Some(at::CONNECT));
+        assert_eq!(modem.state(), ModemState::Online);
+
+        assert_eq!(modem.handle(Event::BridgeDone), None);
+        assert_eq!(modem.state(), ModemState::CommandMode);
+        // The number sticks around for the next ATD.
+        assert_eq!(modem.dialed_number(), "18006138199");
+    }
+
+    #[test]
+    fn busy_goes_back_to_commands() {
+        let mut modem = modem_in(ModemState::Dialing);
+
+        assert_eq!(modem.handle(Event::Busy), Some(at::BUSY));
+        assert_eq!(modem.state(), ModemState::CommandMode);
+    }
+
+    #[test]
+    fn refusing_a_number_goes_back_to_commands() {
+        let mut modem = modem_in(ModemState::Dialing);
+        assert_eq!(modem.handle(Event::Delayed), Some(at::DELAYED));
+        assert_eq!(modem.state(), ModemState::CommandMode);
+
+        let mut modem = modem_in(ModemState::Dialing);
+        assert_eq!(modem.handle(Event::Blacklisted), Some(at::BLACKLISTED));
+        assert_eq!(modem.state(), ModemState::CommandMode);
+
+        // Only a dial can be refused.
+        let mut modem = modem_in(ModemState::Online);
+        assert_eq!(modem.handle(Event::Delayed), None);
+        assert_eq!(modem.state(), ModemState::Online);
+    }
+
+    #[test]
+    fn failing_says_why() {
+        let refused = TouchPppError::BackendConnect {
+            endpoint: "127.0.0.1:2323".to_string(),
+            source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+        };
+
+        let mut modem = modem_in(ModemState::Dialing);
+        assert_eq!(modem.handle(Event::Failed(&refused)), Some(at::BUSY));
+        assert_eq!(modem.state(), ModemState::CommandMode);
+
+        let broken = TouchPppError::Bridge(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+
+        let mut modem = modem_in(ModemState::Online);
+        assert_eq!(modem.handle(Event::Failed(&broken)), Some(at::NO_CARRIER));
+        assert_eq!(modem.state(), ModemState::CommandMode);
+
+        // Nothing to fail with no call up.
+        assert_eq!(ModemSession::new().handle(Event::Failed(&broken)), None);
+    }
+
+    #[test]
+    fn escaping_and_going_back_online() {
+        let mut modem = modem_in(ModemState::Suspended);
+
+        assert_eq!(modem.handle(Event::Command(&Command::DialSetup)), Some(at::SETUP_OK));
+        assert_eq!(modem.handle(Event::Command(&Command::Dial("5551212".to_string()))), Some(at::ERROR));
+        assert_eq!(modem.state(), ModemState::Suspended);
+
+        assert_eq!(modem.handle(Event::Command(&Command::Resume)), Some(at::CONNECT));
+        assert_eq!(modem.state(), ModemState::Online);
+
+        let mut modem = modem_in(ModemState::Suspended);
+
+        assert_eq!(modem.handle(Event::Command(&Command::HangUp)), Some(at::SETUP_OK));
+        assert_eq!(modem.state(), ModemState::CommandMode);
+    }
+
+    #[test]
+    fn going_back_online_with_no_call_is_no_carrier() {
+        let mut modem = ModemSession::new();
+
+        assert_eq!(modem.handle(Event::Command(&Command::Resume)), Some(at::NO_CARRIER));
+        assert_eq!(modem.state(), ModemState::CommandMode);
+    }
+
+    #[test]
+    fn killing_sends_no_carrier_only_with_a_call_up() {
+        for state in [ModemState::Dialing, ModemState::Online, ModemState::Suspended] {
+            let mut modem = modem_in(state);
+
+            assert_eq!(modem.handle(Event::Killed), Some(at::NO_CARRIER), "killed while {state}");
+            assert_eq!(modem.state(), ModemState::CommandMode);
+        }
+
+        assert_eq!(ModemSession::new().handle(Event::Killed), None);
+    }
+
+    #[test]
+    fn ppp_without_a_dial_only_dials_from_command_mode() {
+        let mut modem = ModemSession::new();
+
+        assert_eq!(modem.handle(Event::AutoData), None);
+        assert_eq!(modem.state(), ModemState::Dialing);
+        assert_eq!(modem.handle(Event::Connected), Some(at::CONNECT));
+
+        // A call that's escaped from is still up.
+        modem.handle(Event::Escaped);
+        assert_eq!(modem.handle(Event::AutoData), None);
+        assert_eq!(modem.state(), ModemState::Suspended);
+    }
+
+    #[test]
+    fn connects_with_whatever_its_told_to_say() {
+        let report = at::ConnectReport { carrier_speed: 31200, intermediates: false, ..Default::default() };
+
+        let mut modem = ModemSession::with_report(report.clone());
+        modem.handle(Event::Command(&Command::DataMode));
+
+        assert_eq!(modem.handle(Event::Connected), Some(&report.sequence().unwrap()[..]));
+    }
+
+    #[test]
+    fn reports_what_the_init_string_turned_off() {
+        let mut modem = ModemSession::with_report(at::ConnectReport { protocol: Some(at::Protocol::Lapm), ..Default::default() });
+
+        modem.configure(at::settings("ATE0%C0\\N0\r"));
+        modem.handle(Event::Command(&Command::DataMode));
+        assert_eq!(modem.handle(Event::Connected), Some(&b"79\r\n70\r\n69\r\n19\r\n"[..]));
+
+        // \N3 brings LAPM back, and compression stays off.
+        let mut modem = ModemSession::with_report(at::ConnectReport { protocol: Some(at::Protocol::Lapm), ..Default::default() });
+        modem.configure(at::settings("ATE0%C0\\N0\r"));
+        modem.configure(at::settings("AT\\N3\r"));
+        modem.handle(Event::Command(&Command::DataMode));
+        assert_eq!(modem.handle(Event::Connected), Some(&b"79\r\n77\r\n69\r\n19\r\n"[..]));
+    }
+
+    #[test]
+    fn carrier_follows_what_the_box_allows() {
+        let mut modem = ModemSession::new();
+
+        // What WebTV boxes always send changes nothing.
+        modem.configure(at::settings("ATS51=31\r"));
+        modem.configure(at::settings("AT+MS=11,1\r"));
+        assert_eq!(modem.carrier_speed(), 33600);
+
+        modem.configure(at::settings("AT+MS=V34,1,300,28800\r"));
+        assert_eq!(modem.carrier_speed(), 28800);
+        assert!(!modem.modulation().allows_56k());
+        modem.handle(Event::Command(&Command::DataMode));
+        assert_eq!(modem.handle(Event::Connected), Some(&b"58\r\n67\r\n19\r\n"[..]));
+        modem.handle(Event::BridgeDone);
+
+        modem.configure(at::settings("ATZ\r"));
+        assert_eq!(modem.carrier_speed(), 33600);
+
+        // A carrier speed that's set rather than auto stays put.
+        let mut modem = ModemSession::with_report(at::ConnectReport { carrier_speed: 31200, follows_modulation: false, ..Default::default() });
+        modem.configure(at::settings("AT+MS=V32B\r"));
+        assert_eq!(modem.carrier_speed(), 31200);
+    }
+
+    #[test]
+    fn a_phone_book_report_lasts_one_dial() {
+        let mut modem = ModemSession::with_report(at::ConnectReport { carrier_speed: 56000, ..Default::default() });
+
+        modem.handle(Event::Command(&Command::Dial("18006138199".to_string())));
+        modem.set_report(at::ConnectReport { carrier_speed: 33600, ..Default::default() });
+        assert_eq!(modem.carrier_speed(), 33600);
+        modem.handle(Event::Command(&Command::DataMode));
+        modem.handle(Event::Busy);
+
+        // The next number's back to the config's, against whatever the box has said since.
+        modem.handle(Event::Command(&Command::Dial("5551212".to_string())));
+        assert_eq!(modem.carrier_speed(), 56000);
+        modem.configure(at::settings("ATS51=31\r"));
+        modem.handle(Event::Command(&Command::Dial("5551212".to_string())));
+        assert_eq!(modem.carrier_speed(), 33600);
+    }
+
+    #[test]
+    fn a_reset_starts_the_next_call_over() {
+        let mut modem = ModemSession::with_report(at::ConnectReport { protocol: Some(at::Protocol::Lapm), ..Default::default() });
+
+        modem.configure(at::settings("ATE0%C0\\N0\r"));
+        modem.handle(Event::Command(&Command::DataMode));
+        assert_eq!(modem.handle(Event::Connected), Some(&b"79\r\n70\r\n69\r\n19\r\n"[..]));
+        modem.handle(Event::BridgeDone);
+
+        // Still off without a reset.
+        modem.configure(at::settings("ATE0\r"));
+        modem.handle(Event::Command(&Command::DataMode));
+        assert_eq!(modem.handle(Event::Connected), Some(&b"79\r\n70\r\n69\r\n19\r\n"[..]));
+        modem.handle(Event::BridgeDone);
+
+        modem.configure(at::settings("ATZ\r"));
+        modem.handle(Event::Command(&Command::DataMode));
+        assert_eq!(modem.handle(Event::Connected), Some(&b"79\r\n77\r\n67\r\n19\r\n"[..]));
+    }
+
+    #[test]
+    fn z_brings_back_what_w_saved_and_f_doesnt() {
+        let saved = Arc::new(DialState::new());
+        let mut modem = ModemSession::new().with_profile(&at::GENERIC).with_saved_profiles(&saved);
+        let factory = modem.active_profile();
+
+        // Nothing saved yet, so ATZ is the factory settings.
+        modem.answer("ATE0V0S7=90\r");
+        modem.answer("ATZ\r");
+        assert_eq!(modem.active_profile(), factory);
+
+        modem.answer("ATE0V0S7=90&W\r");
+        modem.answer("ATE1V1S7=10\r");
+        modem.answer("ATZ\r");
+        assert_eq!((modem.echoes(), modem.answer("ATS7?\r")), (false, Some(&b"090\r\n0\r"[..])));
+
+        modem.answer("AT&F\r");
+        assert_eq!(modem.active_profile(), factory);
+
+        // Slot 1's still the factory's, and a new modem starts out with slot 0.
+        modem.answer("ATZ1\r");
+        assert_eq!(modem.active_profile(), factory);
+        assert!(!ModemSession::new().with_profile(&at::GENERIC).with_saved_profiles(&saved).echoes());
+
+        assert_eq!(modem.answer("AT&W2\r"), Some(&b"\r\nERROR\r\n"[..]));
+    }
+
+    #[test]
+    fn v_lists_the_active_and_stored_profiles() {
+        let mut modem = ModemSession::new().with_profile(&at::GENERIC);
+
+        modem.answer("ATS7=60&W1\r");
+        let listing = String::from_utf8(modem.answer("AT&V\r").unwrap().to_vec()).unwrap();
+
+        assert!(listing.starts_with("\r\nACTIVE PROFILE:\r\nE1 Q0 V1 X4 %C1 \\N3\r\nS00:000 S01:000 S02:043"), "{listing}");
+        assert!(listing.contains("STORED PROFILE 0:\r\nE1 Q0 V1 X4 %C1 \\N3\r\nS00:000 S01:000 S02:043 S03:013 S04:010 S05:008 S06:002 S07:050 "), "{listing}");
+        assert!(listing.contains("STORED PROFILE 1:\r\nE1 Q0 V1 X4 %C1 \\N3\r\nS00:000 S01:000 S02:043 S03:013 S04:010 S05:008 S06:002 S07:060 "), "{listing}");
+        assert!(listing.ends_with("\r\nOK\r\n"), "{listing}");
+    }
+
+    #[test]
+    fn atdl_dials_the_last_number_again() {
+        let mut modem = ModemSession::new().with_profile(&at::GENERIC);
+        assert_eq!(modem.answer("ATDL\r"), Some(&b"\r\nERROR\r\n"[..]));
+
+        modem.answer("ATDT5551212\r");
+        modem.handle(Event::Busy);
+        assert!(String::from_utf8_lossy(modem.answer("AT&V\r").unwrap()).contains("LAST DIALED NUMBER: 5551212\r\n"));
+
+        // Kept over a reset unless it's told otherwise.
+        modem.answer("ATZ\r");
+        assert_eq!(modem.answer("ATDL\r"), None);
+        assert_eq!((modem.state(), modem.dialed_number()), (ModemState::Dialing, "5551212"));
+
+        let mut modem = ModemSession::new().with_profile(&at::GENERIC).with_reset_forgetting_number(true);
+        modem.answer("ATDT5551212\r");
+        modem.handle(Event::Busy);
+        modem.answer("AT&F\r");
+        assert_eq!(modem.answer("ATDL\r"), Some(&b"\r\nERROR\r\n"[..]));
+    }
+
+    #[test]
+    fn ignores_what_makes_no_sense() {
+        let mut modem = ModemSession::new();
+        assert_eq!(modem.handle(Event::Connected), None);
+        assert_eq!(modem.handle(Event::BridgeDone), None);
+        assert_eq!(modem.handle(Event::Escaped), None);
+        assert_eq!(modem.state(), ModemState::CommandMode);
+
+        // Anything MAME sends while online is data, not commands.
+        let mut modem = modem_in(ModemState::Online);
+        assert_eq!(modem.handle(Event::Command(&Command::Init)), None);
+        assert_eq!(modem.handle(Event::Busy), None);
+        assert_eq!(modem.state(), ModemState::Online);
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        // Mostly S registers, since those are what a modem holds on to.
+        fn line() -> impl Strategy<Value = String> {
+            prop_oneof![
+                4 => "[Aa][Tt]([Ss][0-9]{1,5}=[0-9]{1,3}){1,8}\r",
+                1 => "(?i)AT[ -~]{0,40}\r",
+                1 => "(?s).{0,40}",
+            ]
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(64))]
+
+            #[test]
+            fn no_run_of_lines_grows_the_modem(lines in proptest::collection::vec(line(), 0..300)) {
+                for profile in [&at::WEBTV, &at::GENERIC] {
+                    let mut modem = ModemSession::new().with_profile(profile);
+
+                    for line in &lines {
+                        modem.answer(line);
+                        prop_assert!(modem.s_registers.len() <= at::MAX_S_REGISTER as usize + 1, "{} registers", modem.s_registers.len());
+                    }
+                }
+            }
+        }
+    }
+}
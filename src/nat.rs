@@ -0,0 +1,320 @@
+// The built-in nat backend: answers PPP itself and hands the box's TCP, UDP and pings to this machine's own
+// sockets, the way slirp does. Nothing else has to be installed, and nothing needs root. PPP itself is in ppp.rs;
+// what's here is just enough IPv4 and TCP to get a WebTV box online.
+
+use std::collections::BTreeSet;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::backend::{BackendStream, DialContext, PppBackend};
+use crate::bridge;
+use crate::error::TouchPppError;
+use crate::ppp;
+
+mod link;
+mod packet;
+mod tcp;
+
+const DNS_PORT: u16 = 53;
+
+/// The addresses the nat backend hands out: the first is the gateway (which also stands in for this machine
+/// and answers DNS), and each call gets one of the rest.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "toml::Value")]
+pub struct NatPool {
+    pub network: Ipv4Addr,
+    pub prefix_length: u8,
+}
+
+impl NatPool {
+    pub fn gateway(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.network) + 1)
+    }
+
+    /// Every address a box can be given, leaving out the gateway and the broadcast address.
+    pub fn clients(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let network = u32::from(self.network);
+        let broadcast = network | (u32::MAX >> self.prefix_length);
+
+        (network + 2..broadcast).map(Ipv4Addr::from)
+    }
+}
+
+// Same as slirp (and so QEMU's user networking), which anyone who's used either will recognize.
+impl Default for NatPool {
+    fn default() -> NatPool {
+        NatPool { network: Ipv4Addr::new(10, 0, 2, 0), prefix_length: 24 }
+    }
+}
+
+impl std::str::FromStr for NatPool {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<NatPool, String> {
+        let usage = || "use a network in CIDR form, like 10.0.2.0/24".to_string();
+
+        let (network, prefix_length) = value.split_once('/').ok_or_else(usage)?;
+        let network: Ipv4Addr = network.parse().map_err(|_| usage())?;
+        let prefix_length: u8 = prefix_length.parse().map_err(|_| usage())?;
+
+        // Anything smaller than a /30 has no room for a gateway and a box.
+        if !(8..=30).contains(&prefix_length) {
+            return Err(format!("/{prefix_length} won't do, use a network from /8 to /30"));
+        }
+
+        if u32::from(network) & (u32::MAX >> prefix_length) != 0 {
+            return Err(format!("{network} isn't where a /{prefix_length} starts"));
+        }
+
+        Ok(NatPool { network, prefix_length })
+    }
+}
+
+impl TryFrom<toml::Value> for NatPool {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<NatPool, String> {
+        match value {
+            toml::Value::String(pool) => pool.parse(),
+            _ => Err("use a network in CIDR form, like \"10.0.2.0/24\"".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for NatPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_length)
+    }
+}
+
+/// Parses a --nat-dns server, which is an IPv4 address with an optional port.
+pub fn parse_dns(value: &str) -> Result<SocketAddrV4, String> {
+    if let Ok(ip) = value.parse::<Ipv4Addr>() {
+        return Ok(SocketAddrV4::new(ip, DNS_PORT));
+    }
+
+    value.parse().map_err(|_| format!("'{value}' isn't an IPv4 address, like 192.168.1.1 or 192.168.1.1:5353"))
+}
+
+/// The IPv4 name servers in /etc/resolv.conf, which is where the nat backend sends DNS without --nat-dns.
+pub fn system_dns() -> Vec<SocketAddrV4> {
+    ppp::system_dns().into_iter().map(|ip| SocketAddrV4::new(ip, DNS_PORT)).collect()
+}
+
+/// Answers PPP itself, giving each box an address from `pool` and getting it online through this machine.
+pub struct Nat {
+    pub pool: NatPool,
+    // Where the gateway's port 53 really goes. The first one's used.
+    pub dns: Vec<SocketAddrV4>,
+    leases: Arc<Mutex<BTreeSet<Ipv4Addr>>>,
+}
+
+impl Nat {
+    pub fn new(pool: NatPool, dns: Vec<SocketAddrV4>) -> Nat {
+        Nat { pool, dns, leases: Arc::new(Mutex::new(BTreeSet::new())) }
+    }
+
+    fn lease(&self) -> Option<Lease> {
+        let mut leases = self.leases.lock().unwrap();
+        let address = self.pool.clients().find(|address| !leases.contains(address))?;
+
+        leases.insert(address);
+
+        Some(Lease { address, leases: self.leases.clone() })
+    }
+}
+
+// A box's address, given back to the pool however the call ends.
+struct Lease {
+    address: Ipv4Addr,
+    leases: Arc<Mutex<BTreeSet<Ipv4Addr>>>,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.leases.lock().unwrap().remove(&self.address);
+    }
+}
+
+impl PppBackend for Nat {
+    fn establish<'a>(&'a self, _context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            let lease = self.lease().ok_or_else(|| TouchPppError::BackendConnect {
+                endpoint: format!("the nat pool {}", self.pool),
+                source: std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "every address is in use"),
+            })?;
+
+            info!("Touching the built-in NAT, giving the box {}.", lease.address);
+
+            let (call, ppp) = tokio::io::duplex(bridge::BUFFER_SIZE);
+            let addresses = link::Addresses { gateway: self.pool.gateway(), client: lease.address, dns: self.dns.clone() };
+
+            // The link ends when the call drops its end of the pipe, and the address goes back with it.
+            tokio::spawn(async move {
+                link::run(ppp, addresses).await;
+
+                drop(lease);
+            });
+
+            let (reader, writer) = tokio::io::split(call);
+
+            Ok(BackendStream::new(reader, writer))
+        }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, UdpSocket};
+    use crate::config::{DialSettings, LinkProtocol};
+    use crate::ppp::client::Client;
+    use crate::ppp::control::IPV4;
+    use packet::{Echo, Ipv4, Segment, Tcp, Udp, ACK, FIN, PSH, SYN};
+
+    async fn call(nat: &Nat) -> Client {
+        Client::new(nat.establish(&DialContext { number: "5551212", session: 1, link_protocol: LinkProtocol::Ppp, settings: &DialSettings::default(), endpoint: None }).await.unwrap())
+    }
+
+    async fn send_tcp(client: &mut Client, source: SocketAddrV4, destination: SocketAddrV4, segment: Segment, payload: &[u8]) {
+        client.send(IPV4, &packet::tcp(source, destination, &segment, payload)).await;
+    }
+
+    #[tokio::test]
+    async fn a_box_gets_online_and_fetches_a_page_through_the_gateway() {
+        const REQUEST: &[u8] = b"GET /index.html HTTP/1.0\r\n\r\n";
+        const RESPONSE: &[u8] = b"HTTP/1.0 200 OK\r\nContent-Type: text/html\r\n\r\n<html>Touched!</html>";
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; REQUEST.len()];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, REQUEST);
+
+            stream.write_all(RESPONSE).await.unwrap();
+        });
+
+        let pool = NatPool::default();
+        let nat = Nat::new(pool, vec![SocketAddrV4::new(Ipv4Addr::LOCALHOST, DNS_PORT)]);
+        let mut client = call(&nat).await;
+        let address = client.connect(pool.gateway(), [pool.gateway(); 2]).await;
+        assert_eq!(address, Ipv4Addr::new(10, 0, 2, 2));
+
+        let inside = SocketAddrV4::new(address, 1025);
+        let gateway = SocketAddrV4::new(pool.gateway(), port);
+
+        send_tcp(&mut client, inside, gateway, Segment { seq: 1000, flags: SYN, window: 8192, mss: Some(1460), ..Default::default() }, &[]).await;
+        let syn_ack = client.ip().await;
+        let syn_ack = Tcp::parse(&Ipv4::parse(&syn_ack).unwrap()).unwrap().segment;
+        assert_eq!((syn_ack.flags, syn_ack.ack), (SYN | ACK, 1001));
+
+        let mut seq = 1001;
+        let mut ack = syn_ack.seq.wrapping_add(1);
+        send_tcp(&mut client, inside, gateway, Segment { seq, ack, flags: ACK | PSH, window: 8192, ..Default::default() }, REQUEST).await;
+        seq += REQUEST.len() as u32;
+
+        // The page, then the FIN once the server's hung up, in however many segments they come.
+        let mut page = Vec::new();
+        loop {
+            let packet = client.ip().await;
+            let ip = Ipv4::parse(&packet).unwrap();
+            let tcp = Tcp::parse(&ip).unwrap();
+            assert_eq!((tcp.source, tcp.destination), (gateway, inside));
+
+            if tcp.segment.seq != ack {
+                continue;
+            }
+
+            page.extend_from_slice(tcp.payload);
+            ack = ack.wrapping_add(tcp.payload.len() as u32);
+
+            if tcp.segment.flags & FIN != 0 {
+                ack = ack.wrapping_add(1);
+                break;
+            }
+        }
+        assert_eq!(page, RESPONSE);
+
+        send_tcp(&mut client, inside, gateway, Segment { seq, ack, flags: FIN | ACK, window: 8192, ..Default::default() }, &[]).await;
+        let last_ack = client.ip().await;
+        let last_ack = Tcp::parse(&Ipv4::parse(&last_ack).unwrap()).unwrap().segment;
+        assert_eq!((last_ack.flags, last_ack.ack), (ACK, seq + 1));
+    }
+
+    #[tokio::test]
+    async fn dns_goes_upstream_and_the_gateway_answers_pings() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let SocketAddr::V4(upstream_address) = upstream.local_addr().unwrap() else {
+            panic!("bound to IPv4");
+        };
+
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            let (bytes_found, from) = upstream.recv_from(&mut buf).await.unwrap();
+
+            upstream.send_to(&buf[..bytes_found].to_ascii_uppercase(), from).await.unwrap();
+        });
+
+        let pool = NatPool::default();
+        let nat = Nat::new(pool, vec![upstream_address]);
+        let mut client = call(&nat).await;
+        let address = client.connect(pool.gateway(), [pool.gateway(); 2]).await;
+
+        let inside = SocketAddrV4::new(address, 5353);
+        let dns = SocketAddrV4::new(pool.gateway(), DNS_PORT);
+        client.send(IPV4, &packet::udp(inside, dns, b"lookup")).await;
+
+        let answer = client.ip().await;
+        let answer = Ipv4::parse(&answer).unwrap();
+        let answer = Udp::parse(&answer).unwrap();
+        assert_eq!((answer.source, answer.destination, answer.payload), (dns, inside, &b"LOOKUP"[..]));
+
+        let ping = Echo { id: 7, seq: 1, data: b"ping" };
+        client.send(IPV4, &packet::ipv4(address, pool.gateway(), packet::ICMP, &ping.request())).await;
+
+        let pong = client.ip().await;
+        let pong = Ipv4::parse(&pong).unwrap();
+        let pong = Echo::parse_reply(pong.payload).unwrap();
+        assert_eq!((pong.id, pong.seq, pong.data), (7, 1, &b"ping"[..]));
+    }
+
+    #[tokio::test]
+    async fn a_full_pool_is_busy() {
+        let pool: NatPool = "192.168.7.0/30".parse().unwrap();
+        let nat = Nat::new(pool, vec![SocketAddrV4::new(Ipv4Addr::LOCALHOST, DNS_PORT)]);
+        let context = DialContext { number: "5551212", session: 1, link_protocol: LinkProtocol::Ppp, settings: &DialSettings::default(), endpoint: None };
+
+        let _first = nat.establish(&context).await.unwrap();
+        let second = nat.establish(&context).await;
+
+        assert!(second.is_err_and(|e| e.to_string().contains("the nat pool 192.168.7.0/30") && e.result_code() == Some(crate::at::BUSY)));
+    }
+
+    #[test]
+    fn pools_have_to_be_networks() {
+        let pool: NatPool = "10.0.2.0/24".parse().unwrap();
+        assert_eq!(pool, NatPool::default());
+        assert_eq!(pool.gateway(), Ipv4Addr::new(10, 0, 2, 1));
+        assert_eq!(pool.clients().count(), 253);
+
+        assert!("10.0.2.1/24".parse::<NatPool>().is_err());
+        assert!("10.0.0.0/31".parse::<NatPool>().is_err());
+        assert!("10.0.2.0".parse::<NatPool>().is_err());
+    }
+
+    #[test]
+    fn dns_servers_default_to_port_53() {
+        assert_eq!(parse_dns("1.1.1.1"), Ok(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 53)));
+        assert_eq!(parse_dns("127.0.0.1:5353"), Ok(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5353)));
+        assert!(parse_dns("dns.example.com").is_err());
+    }
+}
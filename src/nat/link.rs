@@ -0,0 +1,387 @@
+// One call's trip through the nat: the box's IP packets handed to sockets on this machine once PPP's up, with
+// whatever comes back sent to the box.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use tracing::{debug, warn};
+
+use crate::bridge;
+use crate::ppp;
+
+use super::packet::{self, Echo, Ipv4, Tcp, Udp};
+use super::tcp::{self, Connections};
+
+const DNS_PORT: u16 = 53;
+// How often retransmits, LCP and IPCP resends and idle UDP sockets are looked at.
+const TICK: Duration = Duration::from_millis(100);
+const UDP_IDLE: Duration = Duration::from_secs(60);
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the link's sockets have to say. They run in their own tasks, so it all comes through one channel.
+pub enum HostEvent {
+    Tcp(tcp::Key, tcp::Event),
+    Udp { port: u16, from: SocketAddrV4, payload: Vec<u8> },
+    EchoReply { destination: Ipv4Addr, id: u16, seq: u16, data: Vec<u8> },
+}
+
+/// The addresses on the box's side of the link, and the DNS servers its lookups really go to.
+pub struct Addresses {
+    pub gateway: Ipv4Addr,
+    pub client: Ipv4Addr,
+    pub dns: Vec<SocketAddrV4>,
+}
+
+impl Addresses {
+    // Where something the box sent to `destination` really goes, if anywhere. The gateway stands in for this
+    // machine, except that its port 53 is the DNS servers.
+    fn outside(&self, destination: SocketAddrV4) -> Option<SocketAddrV4> {
+        let ip = *destination.ip();
+
+        if ip == self.gateway && destination.port() == DNS_PORT {
+            self.dns.first().copied()
+        } else if ip == self.gateway {
+            Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, destination.port()))
+        } else if ip.is_broadcast() || ip.is_multicast() || ip.is_unspecified() || ip == self.client {
+            None
+        } else {
+            Some(destination)
+        }
+    }
+}
+
+// A host socket for one of the box's UDP ports, and which of the addresses it's sent to were really somewhere else.
+struct UdpBinding {
+    socket: Arc<UdpSocket>,
+    inside: HashMap<SocketAddrV4, SocketAddrV4>,
+    used_at: Instant,
+    task: AbortHandle,
+}
+
+impl Drop for UdpBinding {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+struct Link {
+    ppp: ppp::Link,
+    addresses: Addresses,
+    connections: Connections,
+    udp: HashMap<u16, UdpBinding>,
+    events: mpsc::UnboundedSender<HostEvent>,
+}
+
+/// Runs the link over `ppp` until the call's over: the other end's dropped, or the box hangs up PPP.
+pub async fn run(ppp: DuplexStream, addresses: Addresses) {
+    let (mut reader, mut writer) = tokio::io::split(ppp);
+    let (events, mut from_host) = mpsc::unbounded_channel();
+    let mut link = Link::new(addresses, events);
+    let mut buf = vec![0; bridge::BUFFER_SIZE];
+
+    let mut tick = tokio::time::interval(TICK);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    while !link.ppp.is_done {
+        if !link.ppp.out.is_empty() {
+            if writer.write_all(&link.ppp.out).await.is_err() {
+                break;
+            }
+
+            link.ppp.out.clear();
+        }
+
+        tokio::select! {
+            read = reader.read(&mut buf) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(bytes_found) => {
+                    for packet in link.ppp.read(&buf[..bytes_found]) {
+                        link.ip_in(&packet);
+                    }
+                },
+            },
+            Some(event) = from_host.recv() => link.host_event(event),
+            _ = tick.tick() => link.tick(),
+        }
+    }
+
+    // The Terminate-Ack, if that's how it ended.
+    let _ = writer.write_all(&link.ppp.out).await;
+}
+
+impl Link {
+    fn new(addresses: Addresses, events: mpsc::UnboundedSender<HostEvent>) -> Link {
+        Link {
+            // The gateway answers DNS too.
+            ppp: ppp::Link::new(addresses.gateway, addresses.client, [addresses.gateway; 2]),
+            addresses,
+            connections: Connections::new(events.clone()),
+            udp: HashMap::new(),
+            events,
+        }
+    }
+
+    fn send_tcp(&mut self) {
+        for packet in std::mem::take(&mut self.connections.outgoing) {
+            self.ppp.send_ip(&packet);
+        }
+    }
+
+    fn ip_in(&mut self, information: &[u8]) {
+        let Some(ip) = Ipv4::parse(information) else {
+            return;
+        };
+
+        if ip.source != self.addresses.client {
+            return;
+        }
+
+        match ip.protocol {
+            packet::TCP => {
+                if let Some(tcp) = Tcp::parse(&ip) {
+                    let outside = self.addresses.outside(tcp.destination);
+
+                    self.connections.set_mru(self.ppp.peer_mru());
+                    self.connections.segment(&tcp, outside);
+                    self.send_tcp();
+                }
+            },
+            packet::UDP => {
+                if let Some(udp) = Udp::parse(&ip) {
+                    self.udp_out(&udp);
+                }
+            },
+            packet::ICMP => {
+                if let Some(echo) = Echo::parse(&ip) {
+                    self.ping(ip.destination, &echo);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn udp_out(&mut self, udp: &Udp) {
+        let Some(outside) = self.addresses.outside(udp.destination) else {
+            return;
+        };
+
+        let port = udp.source.port();
+        if !self.udp.contains_key(&port) {
+            let socket = match bind_udp() {
+                Ok(socket) => Arc::new(socket),
+                Err(e) => {
+                    warn!(target: "touchppp::nat", "Couldn't open a UDP socket for the box: error={e}");
+
+                    return;
+                },
+            };
+
+            let task = tokio::spawn(udp_replies(port, socket.clone(), self.events.clone())).abort_handle();
+
+            self.udp.insert(port, UdpBinding { socket, inside: HashMap::new(), used_at: Instant::now(), task });
+        }
+
+        let Some(binding) = self.udp.get_mut(&port) else {
+            return;
+        };
+
+        binding.used_at = Instant::now();
+        binding.inside.insert(outside, udp.destination);
+
+        // Sent straight on the socket, since tokio won't know a new one's writable until its driver's had a look. A full
+        // socket buffer drops it, same as a busy network would.
+        if let Err(e) = socket2::SockRef::from(binding.socket.as_ref()).send_to(udp.payload, &SocketAddr::from(outside).into()) {
+            debug!(target: "touchppp::nat", "Couldn't send UDP to {outside} for the box: error={e}");
+        }
+    }
+
+    fn ping(&mut self, destination: Ipv4Addr, echo: &Echo) {
+        if destination == self.addresses.gateway {
+            let reply = echo.reply(self.addresses.gateway, self.addresses.client);
+            self.ppp.send_ip(&reply);
+
+            return;
+        }
+
+        let Some(outside) = self.addresses.outside(SocketAddrV4::new(destination, 0)) else {
+            return;
+        };
+
+        tokio::spawn(ping(*outside.ip(), destination, echo.id, echo.seq, echo.data.to_vec(), self.events.clone()));
+    }
+
+    fn host_event(&mut self, event: HostEvent) {
+        match event {
+            HostEvent::Tcp(key, event) => {
+                self.connections.event(key, event);
+                self.send_tcp();
+            },
+            HostEvent::Udp { port, from, payload } => {
+                let Some(binding) = self.udp.get_mut(&port) else {
+                    return;
+                };
+
+                binding.used_at = Instant::now();
+
+                let source = binding.inside.get(&from).copied().unwrap_or(from);
+                let reply = packet::udp(source, SocketAddrV4::new(self.addresses.client, port), &payload);
+                self.ppp.send_ip(&reply);
+            },
+            HostEvent::EchoReply { destination, id, seq, data } => {
+                let reply = Echo { id, seq, data: &data }.reply(destination, self.addresses.client);
+                self.ppp.send_ip(&reply);
+            },
+        }
+    }
+
+    fn tick(&mut self) {
+        self.ppp.tick();
+
+        self.connections.tick();
+        self.send_tcp();
+
+        self.udp.retain(|_, binding| binding.used_at.elapsed() < UDP_IDLE);
+    }
+}
+
+fn bind_udp() -> std::io::Result<UdpSocket> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_nonblocking(true)?;
+
+    UdpSocket::from_std(socket)
+}
+
+async fn udp_replies(port: u16, socket: Arc<UdpSocket>, events: mpsc::UnboundedSender<HostEvent>) {
+    let mut buf = vec![0; 0x10000];
+
+    while let Ok((bytes_found, from)) = socket.recv_from(&mut buf).await {
+        let SocketAddr::V4(from) = from else {
+            continue;
+        };
+
+        if events.send(HostEvent::Udp { port, from, payload: buf[..bytes_found].to_vec() }).is_err() {
+            return;
+        }
+    }
+}
+
+// Pings `outside` for the box with an unprivileged ICMP socket, which not every system allows. If it's not
+// allowed, or nothing answers, the box just doesn't hear back.
+async fn ping(outside: Ipv4Addr, destination: Ipv4Addr, id: u16, seq: u16, data: Vec<u8>, events: mpsc::UnboundedSender<HostEvent>) {
+    let pinging = async {
+        let socket = icmp_socket()?;
+        socket.send_to(&Echo { id, seq, data: &data }.request(), (outside, 0)).await?;
+
+        let mut buf = vec![0; 0x10000];
+        loop {
+            let (bytes_found, from) = socket.recv_from(&mut buf).await?;
+
+            if from.ip() == outside {
+                if let Some(reply) = Echo::parse_reply(&buf[..bytes_found]) {
+                    if reply.seq == seq {
+                        return Ok::<Vec<u8>, std::io::Error>(reply.data.to_vec());
+                    }
+                }
+            }
+        }
+    };
+
+    match tokio::time::timeout(PING_TIMEOUT, pinging).await {
+        Ok(Ok(data)) => {
+            let _ = events.send(HostEvent::EchoReply { destination, id, seq, data });
+        },
+        Ok(Err(e)) => debug!(target: "touchppp::nat", "Couldn't ping {outside} for the box: error={e}"),
+        Err(_) => {},
+    }
+}
+
+fn icmp_socket() -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))?;
+    socket.set_nonblocking(true)?;
+
+    UdpSocket::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendStream;
+    use crate::ppp::client::Client;
+    use crate::ppp::control::IPV4;
+    use super::super::packet::{Segment, ACK, RST, SYN};
+
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 1);
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+
+    fn addresses() -> Addresses {
+        Addresses { gateway: GATEWAY, client: CLIENT, dns: vec![SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 53), DNS_PORT)] }
+    }
+
+    // A link that's up, with the box's end of it.
+    async fn online() -> (Client, tokio::task::JoinHandle<()>) {
+        let (call, ppp) = tokio::io::duplex(bridge::BUFFER_SIZE);
+        let running = tokio::spawn(run(ppp, addresses()));
+
+        let (reader, writer) = tokio::io::split(call);
+        let mut client = Client::new(BackendStream::new(reader, writer));
+        assert_eq!(client.connect(GATEWAY, [GATEWAY; 2]).await, CLIENT);
+
+        (client, running)
+    }
+
+    async fn syn(client: &mut Client, source: SocketAddrV4, destination: SocketAddrV4) {
+        let segment = Segment { seq: 1000, flags: SYN, window: 8192, ..Default::default() };
+
+        client.send(IPV4, &packet::tcp(source, destination, &segment, &[])).await;
+    }
+
+    #[test]
+    fn the_gateway_stands_in_for_this_machine_and_its_dns() {
+        let addresses = addresses();
+        let outside = |ip: Ipv4Addr, port: u16| addresses.outside(SocketAddrV4::new(ip, port));
+
+        assert_eq!(outside(GATEWAY, 80), Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80)));
+        assert_eq!(outside(GATEWAY, DNS_PORT), Some(addresses.dns[0]));
+        assert_eq!(outside(Ipv4Addr::new(198, 51, 100, 7), 80), Some(SocketAddrV4::new(Ipv4Addr::new(198, 51, 100, 7), 80)));
+
+        for nowhere in [CLIENT, Ipv4Addr::BROADCAST, Ipv4Addr::new(224, 0, 0, 1), Ipv4Addr::UNSPECIFIED] {
+            assert_eq!(outside(nowhere, 80), None, "{nowhere}");
+        }
+
+        // With no DNS servers, lookups go nowhere.
+        let addresses = Addresses { dns: Vec::new(), ..self::addresses() };
+        assert_eq!(addresses.outside(SocketAddrV4::new(GATEWAY, DNS_PORT)), None);
+    }
+
+    #[tokio::test]
+    async fn only_the_box_address_is_listened_to() {
+        let (mut client, _running) = online().await;
+
+        // Someone else's, which would otherwise be reset too.
+        syn(&mut client, SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, 99), 1025), SocketAddrV4::new(Ipv4Addr::BROADCAST, 80)).await;
+
+        let inside = SocketAddrV4::new(CLIENT, 1026);
+        syn(&mut client, inside, SocketAddrV4::new(Ipv4Addr::BROADCAST, 80)).await;
+
+        let reset = client.ip().await;
+        let reset = Ipv4::parse(&reset).unwrap();
+        let reset = Tcp::parse(&reset).unwrap();
+        assert_eq!((reset.destination, reset.segment.flags, reset.segment.ack), (inside, RST | ACK, 1001));
+    }
+
+    #[tokio::test]
+    async fn the_link_ends_when_the_call_does() {
+        let (client, running) = online().await;
+
+        drop(client);
+
+        tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+// Just enough IPv4, TCP, UDP and ICMP to pick apart what the box sends and build what goes back to it.
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::atomic::{AtomicU16, Ordering};
+
+pub const ICMP: u8 = 1;
+pub const TCP: u8 = 6;
+pub const UDP: u8 = 17;
+
+pub const FIN: u8 = 0x01;
+pub const SYN: u8 = 0x02;
+pub const RST: u8 = 0x04;
+pub const PSH: u8 = 0x08;
+pub const ACK: u8 = 0x10;
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+
+const IPV4_HEADER: usize = 20;
+const TCP_HEADER: usize = 20;
+const UDP_HEADER: usize = 8;
+const TTL: u8 = 64;
+const DONT_FRAGMENT: u16 = 0x4000;
+const MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1fff;
+const MSS_OPTION: u8 = 2;
+
+/// The IPv4 header and TCP header together, which the MSS leaves room for.
+pub const HEADERS: u16 = (IPV4_HEADER + TCP_HEADER) as u16;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+// The Internet checksum's running sum. Folded and flipped by `finish`.
+fn sum(mut sum: u32, bytes: &[u8]) -> u32 {
+    let mut words = bytes.chunks_exact(2);
+
+    for word in words.by_ref() {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+
+    if let [odd] = words.remainder() {
+        sum += (*odd as u32) << 8;
+    }
+
+    sum
+}
+
+fn finish(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn pseudo_header(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, length: usize) -> u32 {
+    let sum = sum(0, &source.octets());
+    let sum = self::sum(sum, &destination.octets());
+
+    sum + protocol as u32 + length as u32
+}
+
+/// An IPv4 packet from the box. Fragments aren't put back together, so they don't parse.
+pub struct Ipv4<'a> {
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub protocol: u8,
+    pub payload: &'a [u8],
+}
+
+impl Ipv4<'_> {
+    pub fn parse(bytes: &[u8]) -> Option<Ipv4<'_>> {
+        let header_length = (*bytes.first()? & 0x0f) as usize * 4;
+        if bytes[0] >> 4 != 4 || header_length < IPV4_HEADER || bytes.len() < header_length {
+            return None;
+        }
+
+        let total_length = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        let fragment = u16::from_be_bytes([bytes[6], bytes[7]]);
+        if total_length < header_length || total_length > bytes.len() || fragment & (MORE_FRAGMENTS | FRAGMENT_OFFSET) != 0 {
+            return None;
+        }
+
+        if finish(sum(0, &bytes[..header_length])) != 0 {
+            return None;
+        }
+
+        Some(Ipv4 {
+            source: Ipv4Addr::new(bytes[12], bytes[13], bytes[14], bytes[15]),
+            destination: Ipv4Addr::new(bytes[16], bytes[17], bytes[18], bytes[19]),
+            protocol: bytes[9],
+            payload: &bytes[header_length..total_length],
+        })
+    }
+
+    /// Whether the payload's checksum, which covers a pseudo header made from this packet's addresses, adds up.
+    fn is_checksum_good(&self) -> bool {
+        finish(sum(pseudo_header(self.source, self.destination, self.protocol, self.payload.len()), self.payload)) == 0
+    }
+}
+
+pub fn ipv4(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let total_length = (IPV4_HEADER + payload.len()) as u16;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let mut packet = Vec::with_capacity(total_length as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_length.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&DONT_FRAGMENT.to_be_bytes());
+    packet.extend_from_slice(&[TTL, protocol, 0, 0]);
+    packet.extend_from_slice(&source.octets());
+    packet.extend_from_slice(&destination.octets());
+
+    let checksum = finish(sum(0, &packet));
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    packet.extend_from_slice(payload);
+
+    packet
+}
+
+/// A TCP segment's header, as far as we look at it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Segment {
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    // Only ever on a SYN.
+    pub mss: Option<u16>,
+}
+
+pub struct Tcp<'a> {
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    pub segment: Segment,
+    pub payload: &'a [u8],
+}
+
+impl Tcp<'_> {
+    pub fn parse<'a>(ip: &Ipv4<'a>) -> Option<Tcp<'a>> {
+        let bytes = ip.payload;
+        if bytes.len() < TCP_HEADER || !ip.is_checksum_good() {
+            return None;
+        }
+
+        let header_length = (bytes[12] >> 4) as usize * 4;
+        if header_length < TCP_HEADER || header_length > bytes.len() {
+            return None;
+        }
+
+        let u16_at = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let u32_at = |at: usize| u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+
+        Some(Tcp {
+            source: SocketAddrV4::new(ip.source, u16_at(0)),
+            destination: SocketAddrV4::new(ip.destination, u16_at(2)),
+            segment: Segment {
+                seq: u32_at(4),
+                ack: u32_at(8),
+                flags: bytes[13],
+                window: u16_at(14),
+                mss: mss_option(&bytes[TCP_HEADER..header_length]),
+            },
+            payload: &bytes[header_length..],
+        })
+    }
+}
+
+// Options are a kind, then (for everything but end and no-op) a length counting both and the value.
+fn mss_option(mut options: &[u8]) -> Option<u16> {
+    loop {
+        match *options {
+            [0, ..] | [] => return None,
+            [1, ..] => options = &options[1..],
+            [MSS_OPTION, 4, high, low, ..] => return Some(u16::from_be_bytes([high, low])),
+            [_, length, ..] if length >= 2 && length as usize <= options.len() => options = &options[length as usize..],
+            _ => return None,
+        }
+    }
+}
+
+/// A whole IPv4 packet carrying a TCP segment.
+pub fn tcp(source: SocketAddrV4, destination: SocketAddrV4, segment: &Segment, payload: &[u8]) -> Vec<u8> {
+    let header_length = TCP_HEADER + if segment.mss.is_some() { 4 } else { 0 };
+
+    let mut bytes = Vec::with_capacity(header_length + payload.len());
+    bytes.extend_from_slice(&source.port().to_be_bytes());
+    bytes.extend_from_slice(&destination.port().to_be_bytes());
+    bytes.extend_from_slice(&segment.seq.to_be_bytes());
+    bytes.extend_from_slice(&segment.ack.to_be_bytes());
+    bytes.extend_from_slice(&[(header_length as u8 / 4) << 4, segment.flags]);
+    bytes.extend_from_slice(&segment.window.to_be_bytes());
+    bytes.extend_from_slice(&[0, 0, 0, 0]);
+
+    if let Some(mss) = segment.mss {
+        bytes.extend_from_slice(&[MSS_OPTION, 4]);
+        bytes.extend_from_slice(&mss.to_be_bytes());
+    }
+
+    bytes.extend_from_slice(payload);
+
+    let checksum = finish(sum(pseudo_header(*source.ip(), *destination.ip(), TCP, bytes.len()), &bytes));
+    bytes[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+    ipv4(*source.ip(), *destination.ip(), TCP, &bytes)
+}
+
+pub struct Udp<'a> {
+    pub source: SocketAddrV4,
+    pub destination: SocketAddrV4,
+    pub payload: &'a [u8],
+}
+
+impl Udp<'_> {
+    pub fn parse<'a>(ip: &Ipv4<'a>) -> Option<Udp<'a>> {
+        let bytes = ip.payload;
+        if bytes.len() < UDP_HEADER {
+            return None;
+        }
+
+        let length = u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+        let has_checksum = bytes[6] != 0 || bytes[7] != 0;
+        if length < UDP_HEADER || length > bytes.len() || (has_checksum && !ip.is_checksum_good()) {
+            return None;
+        }
+
+        Some(Udp {
+            source: SocketAddrV4::new(ip.source, u16::from_be_bytes([bytes[0], bytes[1]])),
+            destination: SocketAddrV4::new(ip.destination, u16::from_be_bytes([bytes[2], bytes[3]])),
+            payload: &bytes[UDP_HEADER..length],
+        })
+    }
+}
+
+/// A whole IPv4 packet carrying a UDP datagram.
+pub fn udp(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let length = UDP_HEADER + payload.len();
+
+    let mut bytes = Vec::with_capacity(length);
+    bytes.extend_from_slice(&source.port().to_be_bytes());
+    bytes.extend_from_slice(&destination.port().to_be_bytes());
+    bytes.extend_from_slice(&(length as u16).to_be_bytes());
+    bytes.extend_from_slice(&[0, 0]);
+    bytes.extend_from_slice(payload);
+
+    // All zeros means no checksum, so a checksum that comes to zero is sent as all ones.
+    let checksum = match finish(sum(pseudo_header(*source.ip(), *destination.ip(), UDP, length), &bytes)) {
+        0 => 0xffff,
+        checksum => checksum,
+    };
+    bytes[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+    ipv4(*source.ip(), *destination.ip(), UDP, &bytes)
+}
+
+/// An ICMP Echo Request: what ping sends.
+pub struct Echo<'a> {
+    pub id: u16,
+    pub seq: u16,
+    pub data: &'a [u8],
+}
+
+impl Echo<'_> {
+    pub fn parse<'a>(ip: &Ipv4<'a>) -> Option<Echo<'a>> {
+        Echo::parse_icmp(ip.payload, ECHO_REQUEST)
+    }
+
+    /// The reply to one of ours, as an ICMP socket hands it over without the IP header.
+    pub fn parse_reply(icmp: &[u8]) -> Option<Echo<'_>> {
+        Echo::parse_icmp(icmp, ECHO_REPLY)
+    }
+
+    fn parse_icmp(icmp: &[u8], kind: u8) -> Option<Echo<'_>> {
+        match *icmp {
+            [icmp_kind, 0, _, _, id_high, id_low, seq_high, seq_low, ..] if icmp_kind == kind && finish(sum(0, icmp)) == 0 => Some(Echo {
+                id: u16::from_be_bytes([id_high, id_low]),
+                seq: u16::from_be_bytes([seq_high, seq_low]),
+                data: &icmp[8..],
+            }),
+            _ => None,
+        }
+    }
+
+    /// Just the ICMP part of a request like this one, for sending on an ICMP socket.
+    pub fn request(&self) -> Vec<u8> {
+        self.icmp(ECHO_REQUEST)
+    }
+
+    /// A whole IPv4 packet answering this request.
+    pub fn reply(&self, source: Ipv4Addr, destination: Ipv4Addr) -> Vec<u8> {
+        ipv4(source, destination, ICMP, &self.icmp(ECHO_REPLY))
+    }
+
+    fn icmp(&self, kind: u8) -> Vec<u8> {
+        let mut icmp = vec![kind, 0, 0, 0];
+        icmp.extend_from_slice(&self.id.to_be_bytes());
+        icmp.extend_from_slice(&self.seq.to_be_bytes());
+        icmp.extend_from_slice(self.data);
+
+        let checksum = finish(sum(0, &icmp));
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        icmp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(last: u8, port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(10, 0, 2, last), port)
+    }
+
+    #[test]
+    fn tcp_segments_parse_back_the_way_they_were_built() {
+        let segment = Segment { seq: 1000, ack: 2000, flags: SYN | ACK, window: 8192, mss: Some(1460) };
+        let packet = tcp(address(1, 80), address(15, 40000), &segment, b"odd length");
+
+        let ip = Ipv4::parse(&packet).unwrap();
+        assert_eq!(ip.protocol, TCP);
+
+        let parsed = Tcp::parse(&ip).unwrap();
+        assert_eq!((parsed.source, parsed.destination), (address(1, 80), address(15, 40000)));
+        assert_eq!(parsed.segment, segment);
+        assert_eq!(parsed.payload, b"odd length");
+
+        // A flipped bit anywhere is caught by one checksum or the other.
+        for at in [5, 25, packet.len() - 1] {
+            let mut damaged = packet.clone();
+            damaged[at] ^= 0x10;
+
+            assert!(Ipv4::parse(&damaged).and_then(|ip| Tcp::parse(&ip)).is_none(), "byte {at}");
+        }
+    }
+
+    #[test]
+    fn udp_and_echo_parse_back_too() {
+        let packet = udp(address(15, 1024), address(1, 53), b"query");
+        let udp = Udp::parse(&Ipv4::parse(&packet).unwrap()).unwrap();
+        assert_eq!((udp.source, udp.destination, udp.payload), (address(15, 1024), address(1, 53), &b"query"[..]));
+
+        let request = Echo { id: 7, seq: 3, data: b"ping" };
+        let reply = request.reply(Ipv4Addr::new(10, 0, 2, 1), Ipv4Addr::new(10, 0, 2, 15));
+        let ip = Ipv4::parse(&reply).unwrap();
+        assert_eq!(ip.protocol, ICMP);
+
+        let echoed = Echo::parse_reply(ip.payload).unwrap();
+        assert_eq!((echoed.id, echoed.seq, echoed.data), (7, 3, &b"ping"[..]));
+        assert!(Echo::parse(&ip).is_none());
+        assert!(Echo::parse_reply(&request.request()).is_none());
+    }
+
+    #[test]
+    fn fragments_are_left_alone() {
+        let mut packet = udp(address(15, 1024), address(1, 53), b"query");
+        packet[6] |= (MORE_FRAGMENTS >> 8) as u8;
+
+        // Fix the header checksum so the fragment bit's the only thing wrong.
+        packet[10..12].copy_from_slice(&[0, 0]);
+        let checksum = finish(sum(0, &packet[..IPV4_HEADER]));
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        assert!(Ipv4::parse(&packet).is_none());
+    }
+}
@@ -0,0 +1,842 @@
+// The box's TCP connections, each relayed to an ordinary socket on this machine the way slirp does it. We only
+// answer the box's SYN once the real connection's been made, then copy bytes both ways, resending to the box
+// whatever it doesn't ack. The link to the box doesn't reorder, so anything out of order is dropped and resent.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddrV4;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::AbortHandle;
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::ppp::{self, control::DEFAULT_MRU};
+use super::link::HostEvent;
+use super::packet::{self, Segment, Tcp, ACK, FIN, HEADERS, PSH, RST, SYN};
+
+/// The box's end of a connection, then the end it called.
+pub type Key = (SocketAddrV4, SocketAddrV4);
+
+// What we tell the box it can send before waiting for an ack.
+const WINDOW: u16 = 16 * 1024;
+// The box's segments waiting to be written to the host socket. When it's full they're dropped, unacked, and the
+// box sends them again later.
+const QUEUED_SEGMENTS: usize = 32;
+// What's been read from the host socket but not yet acked by the box.
+const SEND_BUFFER: usize = 64 * 1024;
+const READ_CHUNK: usize = 4096;
+// More than a WebTV ever has open at once, so only a box that never closes what it opens gets near it.
+const MAX_CONNECTIONS: usize = 64;
+// What the box is taken to handle when its SYN doesn't say (RFC 1122).
+const DEFAULT_MSS: u16 = 536;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(16);
+const MAX_RETRANSMITS: u32 = 8;
+
+/// What a connection's host socket has to say.
+pub enum Event {
+    /// Where the box's bytes go, and the task writing them.
+    Connected(mpsc::Sender<Vec<u8>>, AbortHandle),
+    Failed,
+    Data(Vec<u8>),
+    Closed,
+    Reset,
+}
+
+#[derive(PartialEq)]
+enum State {
+    // Waiting on the host socket before answering the SYN.
+    Connecting,
+    // SYN-ACK sent.
+    SynReceived,
+    Established,
+}
+
+struct Connection {
+    state: State,
+    // Sequence numbers: the oldest byte the box hasn't acked, the next one to send it, the furthest we've sent, and
+    // the next one expected from it.
+    snd_una: u32,
+    snd_nxt: u32,
+    snd_max: u32,
+    rcv_nxt: u32,
+    // Everything from snd_una on, sent or not. The FIN comes after it once the host's done.
+    unacked: VecDeque<u8>,
+    box_window: u16,
+    mss: u16,
+    to_host: Option<mpsc::Sender<Vec<u8>>>,
+    // Room left in SEND_BUFFER. The reader takes from it and acks from the box give it back.
+    credit: Arc<Semaphore>,
+    is_host_done: bool,
+    is_fin_sent: bool,
+    is_box_done: bool,
+    rto: Duration,
+    retransmit_at: Option<Instant>,
+    retransmits: u32,
+    // When the box last sent anything on it, so the quietest can make way when there are too many.
+    heard_at: Instant,
+    tasks: Vec<AbortHandle>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+impl Connection {
+    fn segment(&self, flags: u8) -> Segment {
+        Segment { seq: self.snd_nxt, ack: self.rcv_nxt, flags, window: WINDOW, mss: None }
+    }
+
+    fn syn_ack(&self) -> Segment {
+        Segment { seq: self.snd_una, mss: Some(DEFAULT_MRU - HEADERS), ..self.segment(SYN | ACK) }
+    }
+
+    fn reset(&self) -> Segment {
+        Segment { seq: self.snd_nxt, ack: self.rcv_nxt, flags: RST | ACK, ..Default::default() }
+    }
+
+    // The FIN's always the last thing sent, so it's acked once everything is.
+    fn is_fin_acked(&self) -> bool {
+        self.is_fin_sent && self.snd_una == self.snd_max
+    }
+
+    fn is_finished(&self) -> bool {
+        self.is_box_done && self.is_fin_acked()
+    }
+
+    fn acked(&mut self, ack: u32) {
+        let acked = ack.wrapping_sub(self.snd_una);
+        if acked == 0 || acked > self.snd_max.wrapping_sub(self.snd_una) {
+            return;
+        }
+
+        // The FIN counts as one more than the data.
+        let data = (acked as usize).min(self.unacked.len());
+        self.unacked.drain(..data);
+        self.credit.add_permits(data);
+
+        // An ack for more than we've resent since going back.
+        if acked > self.snd_nxt.wrapping_sub(self.snd_una) {
+            self.snd_nxt = ack;
+        }
+
+        self.snd_una = ack;
+        self.rto = INITIAL_RTO;
+        self.retransmits = 0;
+        self.retransmit_at = None;
+    }
+
+    fn sent(&mut self, key: Key, outgoing: &mut Vec<Vec<u8>>, flags: u8, payload: &[u8]) {
+        outgoing.push(packet::tcp(key.1, key.0, &self.segment(flags), payload));
+
+        self.snd_nxt = self.snd_nxt.wrapping_add(payload.len() as u32 + (flags & FIN != 0) as u32);
+        if self.snd_nxt.wrapping_sub(self.snd_max) as i32 > 0 {
+            self.snd_max = self.snd_nxt;
+        }
+    }
+
+    // Sends as much as the box's window has room for, then the FIN once the host's done and everything's sent.
+    fn transmit(&mut self, key: Key, outgoing: &mut Vec<Vec<u8>>) {
+        if self.state != State::Established {
+            return;
+        }
+
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let window = self.box_window as usize;
+
+            if in_flight < self.unacked.len() && in_flight < window {
+                let length = (self.mss as usize).min(window - in_flight).min(self.unacked.len() - in_flight);
+                let payload: Vec<u8> = self.unacked.range(in_flight..in_flight + length).copied().collect();
+
+                self.sent(key, outgoing, ACK | PSH, &payload);
+            } else if self.is_host_done && in_flight == self.unacked.len() && !self.is_fin_acked() {
+                self.sent(key, outgoing, FIN | ACK, &[]);
+                self.is_fin_sent = true;
+            } else {
+                break;
+            }
+        }
+
+        // Anything unacked, or held back by a shut window, is looked at again on the retransmit timer.
+        if self.snd_una != self.snd_max || !self.unacked.is_empty() {
+            self.retransmit_at.get_or_insert(Instant::now() + self.rto);
+        }
+    }
+
+    // Goes back to the oldest unacked byte and sends everything from there again.
+    fn retransmit(&mut self, key: Key, outgoing: &mut Vec<Vec<u8>>) {
+        self.retransmits += 1;
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        self.retransmit_at = Some(Instant::now() + self.rto);
+
+        match self.state {
+            State::Connecting => {},
+            State::SynReceived => outgoing.push(packet::tcp(key.1, key.0, &self.syn_ack(), &[])),
+            State::Established => {
+                self.snd_nxt = self.snd_una;
+
+                // One byte past a shut window, which the box acks with its window as it is now.
+                if self.box_window == 0 && !self.unacked.is_empty() {
+                    let probe = [self.unacked[0]];
+
+                    self.sent(key, outgoing, ACK | PSH, &probe);
+                }
+
+                self.transmit(key, outgoing);
+            },
+        }
+    }
+}
+
+pub struct Connections {
+    table: HashMap<Key, Connection>,
+    events: mpsc::UnboundedSender<HostEvent>,
+    // What the box said it can take in one frame, which caps our MSS.
+    mru: u16,
+    /// IP packets for the box.
+    pub outgoing: Vec<Vec<u8>>,
+}
+
+impl Connections {
+    pub fn new(events: mpsc::UnboundedSender<HostEvent>) -> Connections {
+        Connections {
+            table: HashMap::new(),
+            events,
+            mru: DEFAULT_MRU,
+            outgoing: Vec::new(),
+        }
+    }
+
+    pub fn set_mru(&mut self, mru: u16) {
+        self.mru = mru;
+    }
+
+    // Tells the box there's nothing at the other end of `tcp`, the way a closed port would.
+    fn reset(&mut self, tcp: &Tcp) {
+        let segment = if tcp.segment.flags & ACK != 0 {
+            Segment { seq: tcp.segment.ack, flags: RST, ..Default::default() }
+        } else {
+            let length = tcp.payload.len() as u32 + (tcp.segment.flags & SYN != 0) as u32 + (tcp.segment.flags & FIN != 0) as u32;
+
+            Segment { ack: tcp.segment.seq.wrapping_add(length), flags: RST | ACK, ..Default::default() }
+        };
+
+        self.outgoing.push(packet::tcp(tcp.destination, tcp.source, &segment, &[]));
+    }
+
+    // Resets whichever connection the box has been quiet on longest, to make room for another.
+    fn evict(&mut self) {
+        let Some((&key, connection)) = self.table.iter().min_by_key(|(_, connection)| connection.heard_at) else {
+            return;
+        };
+
+        debug!(target: "touchppp::nat", "The box has too many connections open, resetting the one to {}.", key.1);
+        self.outgoing.push(packet::tcp(key.1, key.0, &connection.reset(), &[]));
+
+        self.table.remove(&key);
+    }
+
+    fn open(&mut self, tcp: &Tcp, outside: SocketAddrV4) {
+        let key = (tcp.source, tcp.destination);
+        let credit = Arc::new(Semaphore::new(SEND_BUFFER));
+        let isn = ppp::random();
+
+        debug!(target: "touchppp::nat", "The box is connecting to {} ({outside}) from port {}.", tcp.destination, tcp.source.port());
+
+        if self.table.len() >= MAX_CONNECTIONS {
+            self.evict();
+        }
+
+        let relaying = tokio::spawn(relay(key, outside, credit.clone(), self.events.clone()));
+
+        self.table.insert(key, Connection {
+            state: State::Connecting,
+            snd_una: isn,
+            snd_nxt: isn,
+            snd_max: isn,
+            rcv_nxt: tcp.segment.seq.wrapping_add(1),
+            unacked: VecDeque::new(),
+            box_window: tcp.segment.window,
+            mss: tcp.segment.mss.unwrap_or(DEFAULT_MSS).min(self.mru.saturating_sub(HEADERS)),
+            to_host: None,
+            credit,
+            is_host_done: false,
+            is_fin_sent: false,
+            is_box_done: false,
+            rto: INITIAL_RTO,
+            retransmit_at: None,
+            retransmits: 0,
+            heard_at: Instant::now(),
+            tasks: vec![relaying.abort_handle()],
+        });
+    }
+
+    /// Takes a segment from the box. `outside` is where the address it's for really is, if it's anywhere.
+    pub fn segment(&mut self, tcp: &Tcp, outside: Option<SocketAddrV4>) {
+        let key = (tcp.source, tcp.destination);
+        let flags = tcp.segment.flags;
+
+        let Some(connection) = self.table.get_mut(&key) else {
+            match outside {
+                Some(outside) if flags & (SYN | ACK | RST) == SYN => self.open(tcp, outside),
+                _ if flags & RST == 0 => self.reset(tcp),
+                _ => {},
+            }
+
+            return;
+        };
+
+        if flags & RST != 0 {
+            debug!(target: "touchppp::nat", "The box reset its connection to {}.", tcp.destination);
+            self.table.remove(&key);
+
+            return;
+        }
+
+        connection.heard_at = Instant::now();
+
+        match connection.state {
+            // The box sending its SYN again while the host socket's still connecting.
+            State::Connecting => return,
+            State::SynReceived if flags & SYN != 0 => {
+                connection.retransmit(key, &mut self.outgoing);
+
+                return;
+            },
+            State::SynReceived if flags & ACK != 0 && tcp.segment.ack == connection.snd_nxt => {
+                connection.state = State::Established;
+                connection.acked(tcp.segment.ack);
+            },
+            State::SynReceived => return,
+            State::Established => {},
+        }
+
+        if flags & ACK != 0 {
+            connection.acked(tcp.segment.ack);
+        }
+        connection.box_window = tcp.segment.window;
+
+        if !tcp.payload.is_empty() || flags & FIN != 0 {
+            // Whatever's new in it, if it starts at or before what we're expecting.
+            let already_had = connection.rcv_nxt.wrapping_sub(tcp.segment.seq) as usize;
+
+            if already_had < tcp.payload.len() {
+                let is_queued = connection.to_host.as_ref()
+                    .is_some_and(|to_host| to_host.try_send(tcp.payload[already_had..].to_vec()).is_ok());
+
+                if is_queued {
+                    connection.rcv_nxt = tcp.segment.seq.wrapping_add(tcp.payload.len() as u32);
+                }
+            }
+
+            if flags & FIN != 0 && !connection.is_box_done && tcp.segment.seq.wrapping_add(tcp.payload.len() as u32) == connection.rcv_nxt {
+                connection.rcv_nxt = connection.rcv_nxt.wrapping_add(1);
+                connection.is_box_done = true;
+                // Dropping it shuts down the host socket's sending side once what's queued is written.
+                connection.to_host = None;
+            }
+
+            self.outgoing.push(packet::tcp(key.1, key.0, &connection.segment(ACK), &[]));
+        }
+
+        connection.transmit(key, &mut self.outgoing);
+
+        if connection.is_finished() {
+            self.table.remove(&key);
+        }
+    }
+
+    /// Takes news from a connection's host socket.
+    pub fn event(&mut self, key: Key, event: Event) {
+        let Some(connection) = self.table.get_mut(&key) else {
+            return;
+        };
+
+        match event {
+            Event::Connected(to_host, writing) => {
+                connection.to_host = Some(to_host);
+                connection.tasks.push(writing);
+                connection.state = State::SynReceived;
+                connection.snd_nxt = connection.snd_una.wrapping_add(1);
+                connection.snd_max = connection.snd_nxt;
+
+                self.outgoing.push(packet::tcp(key.1, key.0, &connection.syn_ack(), &[]));
+                connection.retransmit_at = Some(Instant::now() + connection.rto);
+            },
+            Event::Failed | Event::Reset => {
+                self.outgoing.push(packet::tcp(key.1, key.0, &connection.reset(), &[]));
+
+                self.table.remove(&key);
+            },
+            Event::Data(bytes) => {
+                connection.unacked.extend(bytes);
+                connection.transmit(key, &mut self.outgoing);
+            },
+            Event::Closed => {
+                connection.is_host_done = true;
+                connection.transmit(key, &mut self.outgoing);
+
+                if connection.is_finished() {
+                    self.table.remove(&key);
+                }
+            },
+        }
+    }
+
+    /// Resends whatever's gone unacked for too long, giving up on connections that have been resent too often.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let mut given_up = Vec::new();
+
+        for (key, connection) in self.table.iter_mut() {
+            if connection.retransmit_at.is_none_or(|retransmit_at| now < retransmit_at) {
+                continue;
+            }
+
+            if connection.retransmits == MAX_RETRANSMITS {
+                debug!(target: "touchppp::nat", "The box stopped acking its connection to {}, resetting it.", key.1);
+
+                self.outgoing.push(packet::tcp(key.1, key.0, &connection.reset(), &[]));
+                given_up.push(*key);
+
+                continue;
+            }
+
+            connection.retransmit(*key, &mut self.outgoing);
+        }
+
+        for key in given_up {
+            self.table.remove(&key);
+        }
+    }
+}
+
+// Connects to where the box wanted to go, then hands over a way to write to it and reads from it until it's done,
+// only reading as much as the box has room to be sent.
+async fn relay(key: Key, outside: SocketAddrV4, credit: Arc<Semaphore>, events: mpsc::UnboundedSender<HostEvent>) {
+    let send = |event: Event| events.send(HostEvent::Tcp(key, event)).is_ok();
+
+    let host = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(outside)).await {
+        Ok(Ok(host)) => host,
+        Ok(Err(e)) => {
+            debug!(target: "touchppp::nat", "Couldn't connect to {outside} for the box: error={e}");
+            send(Event::Failed);
+
+            return;
+        },
+        Err(_) => {
+            debug!(target: "touchppp::nat", "Couldn't connect to {outside} for the box: timed out");
+            send(Event::Failed);
+
+            return;
+        },
+    };
+
+    let _ = host.set_nodelay(true);
+    let (mut reader, mut writer) = host.into_split();
+    let (to_host, mut from_box) = mpsc::channel::<Vec<u8>>(QUEUED_SEGMENTS);
+
+    let writing = tokio::spawn(async move {
+        while let Some(bytes) = from_box.recv().await {
+            if writer.write_all(&bytes).await.is_err() {
+                return;
+            }
+        }
+
+        let _ = writer.shutdown().await;
+    });
+
+    if !send(Event::Connected(to_host, writing.abort_handle())) {
+        return;
+    }
+
+    let mut buf = vec![0; READ_CHUNK];
+
+    loop {
+        let Ok(permit) = credit.acquire_many(READ_CHUNK as u32).await else {
+            return;
+        };
+        permit.forget();
+
+        let event = match reader.read(&mut buf).await {
+            Ok(0) => Event::Closed,
+            Ok(bytes_found) => {
+                credit.add_permits(READ_CHUNK - bytes_found);
+
+                Event::Data(buf[..bytes_found].to_vec())
+            },
+            Err(e) => {
+                debug!(target: "touchppp::nat", "Lost the connection to {outside} for the box: error={e}");
+
+                Event::Reset
+            },
+        };
+
+        let is_done = matches!(event, Event::Closed | Event::Reset);
+        if !send(event) || is_done {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpListener;
+    use super::super::packet::Ipv4;
+
+    const BOX: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 1);
+    const BOX_ISN: u32 = 1000;
+
+    fn from_box(connections: &mut Connections, key: Key, outside: SocketAddrV4, segment: Segment, payload: &[u8]) {
+        let packet = packet::tcp(key.0, key.1, &segment, payload);
+        let ip = Ipv4::parse(&packet).unwrap();
+
+        connections.segment(&Tcp::parse(&ip).unwrap(), Some(outside));
+    }
+
+    // The box's end of one connection to a port on this machine, keeping track of its sequence numbers.
+    struct Call {
+        connections: Connections,
+        from_host: mpsc::UnboundedReceiver<HostEvent>,
+        key: Key,
+        outside: SocketAddrV4,
+        // The next byte the box sends, the next it expects, and the window it gives.
+        seq: u32,
+        ack: u32,
+        window: u16,
+    }
+
+    impl Call {
+        // Sends the box's SYN to `port`.
+        fn dial(port: u16, window: u16, mss: Option<u16>) -> Call {
+            let (events, from_host) = mpsc::unbounded_channel();
+            let mut call = Call {
+                connections: Connections::new(events),
+                from_host,
+                key: (SocketAddrV4::new(BOX, 1025), SocketAddrV4::new(GATEWAY, port)),
+                outside: SocketAddrV4::new(Ipv4Addr::LOCALHOST, port),
+                seq: BOX_ISN,
+                ack: 0,
+                window,
+            };
+
+            call.send(Segment { mss, ..call.segment(SYN) }, &[]);
+            call.seq += 1;
+
+            call
+        }
+
+        fn segment(&self, flags: u8) -> Segment {
+            Segment { seq: self.seq, ack: self.ack, flags, window: self.window, mss: None }
+        }
+
+        fn send(&mut self, segment: Segment, payload: &[u8]) {
+            from_box(&mut self.connections, self.key, self.outside, segment, payload);
+        }
+
+        // Sends the next segment in order.
+        fn box_sends(&mut self, flags: u8, payload: &[u8]) {
+            self.send(self.segment(flags), payload);
+            self.seq = self.seq.wrapping_add(payload.len() as u32 + (flags & FIN != 0) as u32);
+        }
+
+        fn replies(&mut self) -> Vec<(Segment, Vec<u8>)> {
+            std::mem::take(&mut self.connections.outgoing).iter().map(|packet| {
+                let ip = Ipv4::parse(packet).unwrap();
+                let tcp = Tcp::parse(&ip).unwrap();
+                assert_eq!((tcp.source, tcp.destination), (self.key.1, self.key.0));
+
+                (tcp.segment, tcp.payload.to_vec())
+            }).collect()
+        }
+
+        async fn host_event(&mut self) {
+            let Some(HostEvent::Tcp(key, event)) = self.from_host.recv().await else {
+                panic!("the host socket's gone quiet");
+            };
+
+            self.connections.event(key, event);
+        }
+
+        // Passes on what the host socket reads until `bytes` of it are waiting to go to the box.
+        async fn host_reads(&mut self, bytes: usize) {
+            while self.connection().is_some_and(|connection| connection.unacked.len() < bytes) {
+                self.host_event().await;
+            }
+        }
+
+        fn connection(&self) -> Option<&Connection> {
+            self.connections.table.get(&self.key)
+        }
+
+        // As if the retransmit timer had gone off.
+        fn time_out(&mut self) {
+            if let Some(connection) = self.connections.table.get_mut(&self.key) {
+                connection.retransmit_at = Some(Instant::now());
+            }
+
+            self.connections.tick();
+        }
+    }
+
+    // Everything in `replies` one after the other, from the first one's sequence number.
+    fn stream(replies: &[(Segment, Vec<u8>)]) -> (u32, Vec<u8>) {
+        let mut next = replies[0].0.seq;
+        let mut bytes = Vec::new();
+
+        for (segment, payload) in replies {
+            assert_eq!(segment.seq, next);
+            next = next.wrapping_add(payload.len() as u32);
+            bytes.extend_from_slice(payload);
+        }
+
+        (replies[0].0.seq, bytes)
+    }
+
+    // A call that's through the handshake, and the host socket at the other end of it.
+    async fn established(window: u16, mss: Option<u16>) -> (Call, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut call = Call::dial(listener.local_addr().unwrap().port(), window, mss);
+        let (host, _) = listener.accept().await.unwrap();
+
+        call.host_event().await;
+        let syn_ack = call.replies()[0].0;
+        call.ack = syn_ack.seq.wrapping_add(1);
+        call.box_sends(ACK, &[]);
+
+        (call, host)
+    }
+
+    #[tokio::test]
+    async fn the_syn_is_only_answered_once_the_host_is() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut call = Call::dial(listener.local_addr().unwrap().port(), 8192, Some(1460));
+        assert!(call.replies().is_empty());
+
+        // Sending it again while the host's still connecting doesn't start another.
+        call.send(Segment { seq: BOX_ISN, ..call.segment(SYN) }, &[]);
+        assert!(call.replies().is_empty());
+
+        let (mut host, _) = listener.accept().await.unwrap();
+        call.host_event().await;
+
+        let replies = call.replies();
+        assert_eq!(replies.len(), 1);
+        let syn_ack = replies[0].0;
+        assert_eq!((syn_ack.flags, syn_ack.ack, syn_ack.window, syn_ack.mss), (SYN | ACK, BOX_ISN + 1, WINDOW, Some(DEFAULT_MRU - HEADERS)));
+
+        // A box that missed it sends its SYN again, and gets the same answer.
+        call.send(Segment { seq: BOX_ISN, ..call.segment(SYN) }, &[]);
+        assert_eq!(call.replies(), [(syn_ack, vec![])]);
+
+        call.ack = syn_ack.seq.wrapping_add(1);
+        call.box_sends(ACK, &[]);
+        assert!(call.replies().is_empty());
+        assert!(call.connection().is_some_and(|connection| connection.state == State::Established && connection.mss == 1460));
+
+        call.box_sends(ACK | PSH, b"hello");
+        let replies = call.replies();
+        assert_eq!((replies[0].0.flags, replies[0].0.seq, replies[0].0.ack), (ACK, call.ack, call.seq));
+
+        let mut hello = [0; 5];
+        host.read_exact(&mut hello).await.unwrap();
+        assert_eq!(&hello, b"hello");
+    }
+
+    #[tokio::test]
+    async fn refused_connections_and_strays_are_reset() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let mut call = Call::dial(port, 8192, None);
+        call.host_event().await;
+
+        let replies = call.replies();
+        assert_eq!((replies[0].0.flags, replies[0].0.ack), (RST | ACK, BOX_ISN + 1));
+        assert!(call.connection().is_none());
+
+        // Something for a connection that isn't there is answered the way a closed port would.
+        call.send(Segment { seq: 5000, ack: 7000, flags: ACK | PSH, window: 8192, mss: None }, b"stray");
+        let replies = call.replies();
+        assert_eq!((replies[0].0.flags, replies[0].0.seq), (RST, 7000));
+
+        // And a reset for one is let go.
+        call.send(Segment { seq: 5000, ack: 7000, flags: RST, window: 8192, mss: None }, &[]);
+        assert!(call.replies().is_empty());
+    }
+
+    #[tokio::test]
+    async fn what_the_box_does_not_ack_is_resent_from_the_oldest_byte() {
+        let (mut call, mut host) = established(8192, None).await;
+        let sent: Vec<u8> = (0..1500).map(|byte| byte as u8).collect();
+
+        host.write_all(&sent).await.unwrap();
+        call.host_reads(sent.len()).await;
+
+        // In segments no bigger than the box's default MSS, since its SYN didn't give one.
+        let replies = call.replies();
+        assert!(replies.iter().all(|(_, payload)| payload.len() <= DEFAULT_MSS as usize));
+        assert_eq!(stream(&replies), (call.ack, sent.clone()));
+
+        // Only the first segment gets through.
+        call.ack = call.ack.wrapping_add(DEFAULT_MSS as u32);
+        call.box_sends(ACK, &[]);
+        assert!(call.replies().is_empty());
+
+        call.time_out();
+        assert_eq!(stream(&call.replies()), (call.ack, sent[DEFAULT_MSS as usize..].to_vec()));
+        assert_eq!(call.connection().unwrap().rto, INITIAL_RTO * 2);
+
+        // Going on unacked, it's given up on.
+        for _ in 1..MAX_RETRANSMITS {
+            call.time_out();
+            assert!(call.replies().iter().all(|(segment, _)| segment.flags & RST == 0));
+        }
+        assert_eq!(call.connection().unwrap().rto, MAX_RTO);
+
+        call.time_out();
+        assert_eq!(call.replies().last().unwrap().0.flags, RST | ACK);
+        assert!(call.connection().is_none());
+    }
+
+    #[tokio::test]
+    async fn out_of_order_segments_are_dropped_and_overlaps_trimmed() {
+        let (mut call, mut host) = established(8192, None).await;
+        let start = call.seq;
+
+        // Ahead of what's expected, so it's acked for what is.
+        call.send(Segment { seq: start.wrapping_add(5), ..call.segment(ACK | PSH) }, b" world");
+        assert_eq!(call.replies()[0].0.ack, start);
+
+        call.box_sends(ACK | PSH, b"hello");
+        assert_eq!(call.replies()[0].0.ack, start.wrapping_add(5));
+
+        // Sent again from the start, only what's new in it goes on.
+        call.send(Segment { seq: start, ..call.segment(ACK | PSH) }, b"hello world");
+        assert_eq!(call.replies()[0].0.ack, start.wrapping_add(11));
+
+        let mut received = [0; 11];
+        host.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn fins_close_each_way_in_turn() {
+        let (mut call, mut host) = established(8192, None).await;
+
+        call.box_sends(FIN | ACK, &[]);
+        assert_eq!(call.replies()[0].0.ack, call.seq);
+        assert_eq!(host.read(&mut [0; 16]).await.unwrap(), 0);
+
+        // The host can still send once the box is done.
+        host.write_all(b"bye").await.unwrap();
+        call.host_reads(3).await;
+        assert_eq!(stream(&call.replies()), (call.ack, b"bye".to_vec()));
+
+        drop(host);
+        call.host_event().await;
+        let replies = call.replies();
+        assert_eq!((replies[0].0.flags, replies[0].0.seq), (FIN | ACK, call.ack.wrapping_add(3)));
+        assert!(call.connection().is_some());
+
+        call.ack = call.ack.wrapping_add(4);
+        call.box_sends(ACK, &[]);
+        assert!(call.connection().is_none());
+    }
+
+    #[tokio::test]
+    async fn resets_drop_the_connection_either_way() {
+        let (mut call, mut host) = established(8192, None).await;
+
+        call.box_sends(RST, &[]);
+        assert!(call.replies().is_empty());
+        assert!(call.connection().is_none());
+        assert!(host.read(&mut [0; 16]).await.is_ok_and(|bytes_found| bytes_found == 0));
+
+        let (mut call, host) = established(8192, None).await;
+
+        socket2::SockRef::from(&host).set_linger(Some(Duration::ZERO)).unwrap();
+        drop(host);
+        call.host_event().await;
+
+        let replies = call.replies();
+        assert_eq!((replies[0].0.flags, replies[0].0.seq, replies[0].0.ack), (RST | ACK, call.ack, call.seq));
+        assert!(call.connection().is_none());
+    }
+
+    #[tokio::test]
+    async fn only_as_much_as_the_box_has_room_for_is_sent() {
+        let (mut call, mut host) = established(1000, None).await;
+        let sent = vec![b'x'; 3000];
+
+        host.write_all(&sent).await.unwrap();
+        call.host_reads(sent.len()).await;
+        assert_eq!(stream(&call.replies()).1.len(), 1000);
+
+        // The box takes it all, but has no room for more.
+        call.ack = call.ack.wrapping_add(1000);
+        call.window = 0;
+        call.box_sends(ACK, &[]);
+        assert!(call.replies().is_empty());
+
+        // So it's asked again with a byte past the window.
+        call.time_out();
+        let replies = call.replies();
+        assert_eq!((replies.len(), replies[0].0.seq, replies[0].1.len()), (1, call.ack, 1));
+
+        // Once there's room, the rest follows the probe.
+        call.window = 8192;
+        call.box_sends(ACK, &[]);
+        assert_eq!(stream(&call.replies()), (call.ack.wrapping_add(1), vec![b'x'; 1999]));
+
+        call.ack = call.ack.wrapping_add(2000);
+        call.box_sends(ACK, &[]);
+        assert!(call.connection().is_some_and(|connection| connection.unacked.is_empty() && connection.retransmit_at.is_none()));
+    }
+
+    #[tokio::test]
+    async fn the_quietest_connection_makes_way_when_there_are_too_many() {
+        tokio::time::pause();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let outside = SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+        let key = |source: usize| (SocketAddrV4::new(BOX, 1025 + source as u16), SocketAddrV4::new(GATEWAY, port));
+        let syn = Segment { seq: BOX_ISN, flags: SYN, window: 8192, ..Default::default() };
+
+        let (events, _from_host) = mpsc::unbounded_channel();
+        let mut connections = Connections::new(events);
+
+        for source in 0..MAX_CONNECTIONS {
+            from_box(&mut connections, key(source), outside, syn, &[]);
+            tokio::time::advance(Duration::from_millis(10)).await;
+        }
+
+        // The first's heard from again, which leaves the second the quietest.
+        from_box(&mut connections, key(0), outside, syn, &[]);
+        from_box(&mut connections, key(MAX_CONNECTIONS), outside, syn, &[]);
+
+        assert_eq!(connections.table.len(), MAX_CONNECTIONS);
+        assert!(connections.table.contains_key(&key(0)) && connections.table.contains_key(&key(MAX_CONNECTIONS)));
+        assert!(!connections.table.contains_key(&key(1)));
+
+        let reset = connections.outgoing.pop().unwrap();
+        let reset = Ipv4::parse(&reset).unwrap();
+        let reset = Tcp::parse(&reset).unwrap();
+        assert_eq!((reset.destination, reset.segment.flags, reset.segment.ack), (key(1).0, RST | ACK, BOX_ISN + 1));
+        assert!(connections.outgoing.is_empty());
+    }
+}
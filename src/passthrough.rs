@@ -0,0 +1,144 @@
+// --at-passthrough: the backend is a modem (or something that acts like one, like tcpser) and answers MAME's AT
+// commands itself. All TouchPPP does in command mode is watch them go by, to spot the CONNECT that starts the
+// call and to point dials at the addresses the phone book has for them.
+
+use crate::at;
+
+// The longest result line worth keeping. Anything longer isn't a CONNECT.
+const MAX_RESULT_LENGTH: usize = 64;
+
+/// Whether a result line from the backend's modem, either form, says it's connected.
+pub fn is_connect(line: &str) -> bool {
+    let line = line.trim();
+
+    match line.parse::<u8>() {
+        Ok(code) => at::result_text(code).is_some_and(|text| text.starts_with("CONNECT")),
+        Err(_) => line.starts_with("CONNECT"),
+    }
+}
+
+/// Watches the backend's modem for the CONNECT that ends command mode. Only a result to a command that can go
+/// online (a dial, ATA or ATO) counts, so a number ATI or an S register answers with in V0 isn't taken for one.
+#[derive(Default)]
+pub struct ConnectWatch {
+    line: Vec<u8>,
+    is_going_online: bool,
+}
+
+impl ConnectWatch {
+    /// Notes a command line that went to the modem.
+    pub fn sent(&mut self, at_string: &str) {
+        self.is_going_online = at::tokenize(at_string).iter().any(|token| matches!(token.name.as_str(), "D" | "A" | "O"));
+    }
+
+    /// Takes what the modem said next, giving back how much of it is the modem talking if that's where it says
+    /// CONNECT: everything up to the end of that line. The rest is the call.
+    pub fn received(&mut self, bytes: &[u8]) -> Option<usize> {
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte != b'\r' && byte != b'\n' {
+                if self.line.len() < MAX_RESULT_LENGTH {
+                    self.line.push(byte);
+                }
+
+                continue;
+            }
+
+            let line = std::mem::take(&mut self.line);
+            if self.is_going_online && is_connect(&String::from_utf8_lossy(&line)) {
+                self.is_going_online = false;
+
+                // A CRLF's LF is still the modem's, if it came in the same read.
+                let end = match bytes.get(i + 1) {
+                    Some(b'\n') if byte == b'\r' => i + 2,
+                    _ => i + 1,
+                };
+
+                return Some(end);
+            }
+        }
+
+        None
+    }
+}
+
+/// The number a command line dials, if it dials one.
+pub fn dialed_number(at_string: &str) -> Option<String> {
+    at::tokenize(at_string).into_iter().find(|token| token.name == "D").map(|token| token.argument).filter(|number| !number.is_empty())
+}
+
+/// `at_string` with what it dials swapped for `target` (a host:port, for a modem that dials by address). Tone or
+/// pulse and a ; on the end stay as they were.
+pub fn redial(at_string: &str, target: &str) -> String {
+    let mut line = String::from("AT");
+
+    for token in at::tokenize(at_string) {
+        line.push_str(&token.name);
+
+        if token.name == "D" {
+            line.extend(token.argument.chars().next().filter(|c| matches!(c, 'T' | 'P')));
+            line.push_str(target);
+            if token.argument.ends_with(';') {
+                line.push(';');
+            }
+        } else {
+            line.push_str(&token.argument);
+            // What tokenize took off the end of an extended command.
+            if token.name.starts_with('+') {
+                line.push(';');
+            }
+        }
+    }
+
+    line.push('\r');
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn knows_connect_in_either_form() {
+        assert!(is_connect("CONNECT"));
+        assert!(is_connect("CONNECT 33600/ARQ"));
+        assert!(is_connect("19"));
+        assert!(is_connect("1"));
+        assert!(!is_connect("79"));
+        assert!(!is_connect("0"));
+        assert!(!is_connect("NO CARRIER"));
+    }
+
+    #[test]
+    fn only_a_dial_can_connect() {
+        let mut watch = ConnectWatch::default();
+
+        watch.sent("ATI0\r");
+        assert_eq!(watch.received(b"\r\n1\r\n0\r\n"), None);
+
+        watch.sent("ATDT18006138199\r");
+        assert_eq!(watch.received(b"79\r\n67"), None);
+        assert_eq!(watch.received(b"\r\n19\r\n~\xff\x03"), Some(6));
+
+        // And only once.
+        assert_eq!(watch.received(b"CONNECT\r\n"), None);
+    }
+
+    #[test]
+    fn connect_can_end_with_just_a_cr() {
+        let mut watch = ConnectWatch::default();
+
+        watch.sent("ATD\r");
+        assert_eq!(watch.received(b"\r\nCONNECT 115200\r~"), Some(17));
+    }
+
+    #[test]
+    fn dials_by_address() {
+        assert_eq!(dialed_number("ATDT18006138199\r").as_deref(), Some("T18006138199"));
+        assert_eq!(dialed_number("ATD\r"), None);
+        assert_eq!(dialed_number("ATE0Q0V0\r"), None);
+
+        assert_eq!(redial("ATDT18006138199\r", "wni.example:1515"), "ATDTwni.example:1515\r");
+        assert_eq!(redial("atx3+ms=v34;d 1800;\r", "10.0.0.2:23"), "ATX3+MS=V34;D10.0.0.2:23;\r");
+    }
+}
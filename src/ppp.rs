@@ -0,0 +1,397 @@
+// PPP answered by TouchPPP itself, for the built-in nat and tun backends: HDLC framing, and LCP, PAP and IPCP to
+// bring the link up. Link hands over the box's IP packets once it's online and frames up whatever the backend
+// has for it; where those packets go is up to the backend. For every other backend, watch just looks on.
+
+use std::hash::BuildHasher;
+use std::net::Ipv4Addr;
+use tracing::{debug, info, warn};
+
+pub mod control;
+pub mod hdlc;
+pub mod watch;
+#[cfg(test)]
+pub mod client;
+
+use control::{Negotiation, Packet, ACCM, IPCP, IPV4, LCP, MRU, PAP};
+use control::{CODE_REJECT, CONFIGURE_ACK, CONFIGURE_NAK, CONFIGURE_REJECT, CONFIGURE_REQUEST, DEFAULT_MRU};
+use control::{DISCARD_REQUEST, ECHO_REPLY, ECHO_REQUEST, PROTOCOL_REJECT, TERMINATE_ACK, TERMINATE_REQUEST};
+use hdlc::{Deframer, ALL_ESCAPED};
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// The IPv4 name servers in /etc/resolv.conf, which is where the box's DNS goes when it isn't told otherwise.
+pub fn system_dns() -> Vec<Ipv4Addr> {
+    let Ok(resolv_conf) = std::fs::read_to_string(RESOLV_CONF) else {
+        return Vec::new();
+    };
+
+    resolv_conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|server| server.trim().parse().ok())
+        .collect()
+}
+
+// Not for anything cryptographic; just so TCP sequence numbers and LCP's magic number aren't the same every call.
+pub fn random() -> u32 {
+    std::collections::hash_map::RandomState::new().hash_one(std::time::Instant::now()) as u32
+}
+
+/// One call's PPP link, from the first LCP request to the box hanging up.
+pub struct Link {
+    // Our end of the link, the box's, and the name servers the box is told about.
+    local: Ipv4Addr,
+    peer: Ipv4Addr,
+    dns: [Ipv4Addr; 2],
+    deframer: Deframer,
+    lcp: Negotiation,
+    ipcp: Negotiation,
+    magic: u32,
+    // Our own identifiers for LCP packets that aren't Configure-Requests.
+    next_id: u8,
+    is_lcp_up: bool,
+    is_ipcp_up: bool,
+    // What LCP agreed the box wants escaped, and the biggest frame it'll take.
+    peer_accm: u32,
+    peer_mru: u16,
+    /// Framed and ready to go to the box.
+    pub out: Vec<u8>,
+    /// Set once the box hangs up PPP, or never finishes bringing it up.
+    pub is_done: bool,
+}
+
+impl Link {
+    /// Starts a link that gives the box `peer` as its address, with our LCP request already in `out`.
+    pub fn new(local: Ipv4Addr, peer: Ipv4Addr, dns: [Ipv4Addr; 2]) -> Link {
+        let magic = random();
+
+        let mut link = Link {
+            local,
+            peer,
+            dns,
+            deframer: Deframer::default(),
+            lcp: Negotiation::new(control::lcp_options(magic)),
+            ipcp: Negotiation::new(control::ipcp_options(local)),
+            magic,
+            next_id: 0,
+            is_lcp_up: false,
+            is_ipcp_up: false,
+            peer_accm: ALL_ESCAPED,
+            peer_mru: DEFAULT_MRU,
+            out: Vec::new(),
+            is_done: false,
+        };
+
+        let request = link.lcp.request();
+        link.send(LCP, &request);
+
+        link
+    }
+
+    /// The biggest packet the box will take.
+    pub fn peer_mru(&self) -> u16 {
+        self.peer_mru
+    }
+
+    /// Whether IPCP's up, so IP packets go both ways.
+    #[cfg(test)]
+    pub fn is_online(&self) -> bool {
+        self.is_ipcp_up
+    }
+
+    /// Takes bytes from the box, answering whatever control packets they hold and giving back its IP packets.
+    pub fn read(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+
+        for byte in bytes {
+            let Some(frame) = self.deframer.push(*byte) else {
+                continue;
+            };
+
+            if let Some(packet) = self.frame(&frame) {
+                packets.push(packet.to_vec());
+            }
+        }
+
+        packets
+    }
+
+    /// Frames up an IP packet for the box, if it's online and the packet fits.
+    pub fn send_ip(&mut self, packet: &[u8]) {
+        if !self.is_ipcp_up {
+            return;
+        }
+
+        // There's no fragmenting, so anything too big for the box is lost the way it'd be on a real link.
+        if packet.len() > self.peer_mru as usize {
+            debug!(target: "touchppp::ppp", "Dropping a {} byte packet for the box, which only takes {}.", packet.len(), self.peer_mru);
+
+            return;
+        }
+
+        self.send(IPV4, packet);
+    }
+
+    /// Resends LCP or IPCP requests that haven't been answered, giving up on the link after enough of them.
+    pub fn tick(&mut self) {
+        if let Some(request) = self.lcp.resend() {
+            self.send(LCP, &request);
+        }
+
+        if self.is_lcp_up {
+            if let Some(request) = self.ipcp.resend() {
+                self.send(IPCP, &request);
+            }
+        }
+
+        if self.lcp.has_given_up() || (self.is_lcp_up && self.ipcp.has_given_up()) {
+            warn!("The box never finished setting up PPP, hanging up.");
+
+            self.is_done = true;
+        }
+    }
+
+    // LCP always goes out with every control character escaped, since the peer may not have agreed otherwise yet.
+    fn send(&mut self, protocol: u16, information: &[u8]) {
+        let accm = if protocol == LCP { ALL_ESCAPED } else { self.peer_accm };
+
+        self.out.extend(hdlc::frame(protocol, information, accm));
+    }
+
+    fn next_id(&mut self) -> u8 {
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.next_id
+    }
+
+    fn frame<'a>(&mut self, frame: &'a [u8]) -> Option<&'a [u8]> {
+        let (protocol, information) = hdlc::split(frame)?;
+
+        match protocol {
+            LCP => self.lcp_in(information),
+            // RFC 1661 says anything else is dropped until LCP's up.
+            _ if !self.is_lcp_up => {},
+            PAP => self.pap_in(information),
+            IPCP => self.ipcp_in(information),
+            IPV4 if self.is_ipcp_up => return Some(information),
+            IPV4 => {},
+            _ => {
+                debug!(target: "touchppp::ppp", "The box sent protocol {protocol:#06x}, rejecting it.");
+
+                let mut rejected = protocol.to_be_bytes().to_vec();
+                rejected.extend_from_slice(information);
+                rejected.truncate(self.peer_mru as usize - 4);
+
+                let id = self.next_id();
+                self.send(LCP, &control::packet(PROTOCOL_REJECT, id, &rejected));
+            },
+        }
+
+        None
+    }
+
+    fn lcp_in(&mut self, information: &[u8]) {
+        let Some(packet) = Packet::parse(information) else {
+            return;
+        };
+
+        match packet.code {
+            CONFIGURE_REQUEST => {
+                // Asking again once it's up starts everything over, IPCP included.
+                if self.lcp.is_open() {
+                    self.link_down();
+
+                    let request = self.lcp.restart();
+                    self.send(LCP, &request);
+                }
+
+                let answer = self.lcp.answer(&packet, control::judge_lcp);
+                self.send(LCP, &answer);
+            },
+            CONFIGURE_ACK | CONFIGURE_NAK | CONFIGURE_REJECT => {
+                if let Some(request) = self.lcp.answered(&packet) {
+                    self.send(LCP, &request);
+                }
+            },
+            TERMINATE_REQUEST => {
+                info!("The box hung up PPP.");
+
+                self.send(LCP, &control::packet(TERMINATE_ACK, packet.id, &[]));
+                self.is_done = true;
+            },
+            ECHO_REQUEST if self.is_lcp_up => {
+                let mut reply = self.magic.to_be_bytes().to_vec();
+                reply.extend_from_slice(packet.data.get(4..).unwrap_or_default());
+
+                self.send(LCP, &control::packet(ECHO_REPLY, packet.id, &reply));
+            },
+            TERMINATE_ACK | CODE_REJECT | PROTOCOL_REJECT | ECHO_REQUEST | ECHO_REPLY | DISCARD_REQUEST => {},
+            _ => {
+                let id = self.next_id();
+                self.send(LCP, &control::packet(CODE_REJECT, id, information));
+            },
+        }
+
+        if self.lcp.is_open() && !self.is_lcp_up {
+            self.lcp_up();
+        }
+    }
+
+    fn lcp_up(&mut self) {
+        self.is_lcp_up = true;
+        self.peer_accm = self.lcp.theirs(ACCM).and_then(|accm| accm.try_into().ok()).map(u32::from_be_bytes).unwrap_or(ALL_ESCAPED);
+        self.peer_mru = self.lcp.theirs(MRU).and_then(|mru| mru.try_into().ok()).map(u16::from_be_bytes).unwrap_or(DEFAULT_MRU);
+
+        debug!(target: "touchppp::ppp", "LCP is up, the box takes frames of up to {} bytes.", self.peer_mru);
+
+        let request = self.ipcp.request();
+        self.send(IPCP, &request);
+    }
+
+    fn link_down(&mut self) {
+        self.is_lcp_up = false;
+        self.is_ipcp_up = false;
+        self.peer_accm = ALL_ESCAPED;
+        self.ipcp = Negotiation::new(control::ipcp_options(self.local));
+    }
+
+    fn pap_in(&mut self, information: &[u8]) {
+        let Some(packet) = Packet::parse(information) else {
+            return;
+        };
+
+        if let Some(answer) = control::pap_answer(&packet) {
+            debug!(target: "touchppp::ppp", "The box logged in with PAP, letting it in.");
+
+            self.send(PAP, &answer);
+        }
+    }
+
+    fn ipcp_in(&mut self, information: &[u8]) {
+        let Some(packet) = Packet::parse(information) else {
+            return;
+        };
+
+        match packet.code {
+            CONFIGURE_REQUEST => {
+                if self.ipcp.is_open() {
+                    self.is_ipcp_up = false;
+
+                    let request = self.ipcp.restart();
+                    self.send(IPCP, &request);
+                }
+
+                let answer = self.ipcp.answer(&packet, control::judge_ipcp(self.peer, self.dns));
+                self.send(IPCP, &answer);
+            },
+            CONFIGURE_ACK | CONFIGURE_NAK | CONFIGURE_REJECT => {
+                if let Some(request) = self.ipcp.answered(&packet) {
+                    self.send(IPCP, &request);
+                }
+            },
+            TERMINATE_REQUEST => {
+                self.send(IPCP, &control::packet(TERMINATE_ACK, packet.id, &[]));
+
+                self.is_ipcp_up = false;
+                self.ipcp = Negotiation::new(control::ipcp_options(self.local));
+            },
+            TERMINATE_ACK | CODE_REJECT => {},
+            _ => {
+                let id = self.next_id();
+                self.send(IPCP, &control::packet(CODE_REJECT, id, information));
+            },
+        }
+
+        if self.ipcp.is_open() && !self.is_ipcp_up {
+            self.is_ipcp_up = true;
+
+            info!("The box is online as {}.", self.peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use control::{option, IP_ADDRESS, PRIMARY_DNS, SECONDARY_DNS};
+
+    // A box dialing in, as it comes off the wire. LCP goes out with every control character escaped; once LCP's
+    // up the box stops escaping them and leaves out the address, control and (for IP) protocol fields.
+    const LCP_REQUEST: &str = "7e ff 7d 23 c0 21 7d 21 7d 21 7d 20 7d 38 7d 21 7d 24 7d 25 dc 7d 22 7d 26 7d 20 7d 20 7d 20 7d 20 \
+                               7d 25 7d 26 4b 6f 2c 7d 31 7d 27 7d 22 7d 28 7d 22 62 7b 7e";
+    // Asking for VJ compression, an address and both name servers.
+    const IPCP_REQUEST_VJ: &str = "7e 80 21 01 01 00 1c 02 06 00 2d 0f 01 03 06 00 00 00 00 81 06 00 00 00 00 83 06 00 00 00 00 f1 ac 7e";
+    const IPCP_REQUEST: &str = "7e 80 21 01 02 00 16 03 06 00 00 00 00 81 06 00 00 00 00 83 06 00 00 00 00 ba 12 7e";
+    // Taking 10.0.3.2, with 1.1.1.1 and 9.9.9.9 for DNS.
+    const IPCP_REQUEST_NAKED: &str = "7e 80 21 01 03 00 16 03 06 0a 00 03 02 81 06 01 01 01 01 83 06 09 09 09 09 53 b9 7e";
+    // A UDP packet from 10.0.3.2 to 1.1.1.1:53.
+    const IP_PACKET: &str = "7e 21 45 00 00 1c 00 01 00 00 40 11 6b cd 0a 00 03 02 01 01 01 01 04 00 00 35 00 08 00 00 6c 02 7e";
+
+    fn wire(hex: &str) -> Vec<u8> {
+        hex.split_whitespace().map(|byte| u8::from_str_radix(byte, 16).unwrap()).collect()
+    }
+
+    // Everything the link's sent since last time: protocol, code, identifier and data.
+    fn sent(link: &mut Link) -> Vec<(u16, u8, u8, Vec<u8>)> {
+        let mut deframer = Deframer::default();
+
+        std::mem::take(&mut link.out).iter().filter_map(|byte| deframer.push(*byte)).map(|frame| {
+            let (protocol, information) = hdlc::split(&frame).unwrap();
+            let packet = Packet::parse(information).unwrap();
+
+            (protocol, packet.code, packet.id, packet.data.to_vec())
+        }).collect()
+    }
+
+    #[test]
+    fn a_webtv_box_gets_online() {
+        let (local, peer) = (Ipv4Addr::new(10, 0, 3, 1), Ipv4Addr::new(10, 0, 3, 2));
+        let dns = [Ipv4Addr::new(1, 1, 1, 1), Ipv4Addr::new(9, 9, 9, 9)];
+        let mut link = Link::new(local, peer, dns);
+
+        let [(LCP, CONFIGURE_REQUEST, our_id, our_options)] = &sent(&mut link)[..] else {
+            panic!("the link should start by asking for LCP");
+        };
+        let our_lcp = control::packet(CONFIGURE_ACK, *our_id, our_options);
+
+        assert!(link.read(&wire(LCP_REQUEST)).is_empty());
+        let lcp_options = [option(MRU, &[0x05, 0xdc]), option(ACCM, &[0; 4]), option(5, &[0x4b, 0x6f, 0x2c, 0x11]), option(7, &[]), option(8, &[])];
+        assert_eq!(sent(&mut link), [(LCP, CONFIGURE_ACK, 1, lcp_options.concat())]);
+
+        // Once ours is acked too, LCP's up and IPCP starts.
+        link.read(&hdlc::frame(LCP, &our_lcp, ALL_ESCAPED));
+        let [(IPCP, CONFIGURE_REQUEST, our_id, our_options)] = &sent(&mut link)[..] else {
+            panic!("IPCP should start once LCP's up");
+        };
+        assert_eq!(our_options, &option(IP_ADDRESS, &local.octets()));
+        link.read(&hdlc::frame(IPCP, &control::packet(CONFIGURE_ACK, *our_id, our_options), 0));
+
+        link.read(&wire(IPCP_REQUEST_VJ));
+        assert_eq!(sent(&mut link), [(IPCP, CONFIGURE_REJECT, 1, option(2, &[0x00, 0x2d, 0x0f, 0x01]))]);
+
+        link.read(&wire(IPCP_REQUEST));
+        let naked = [option(IP_ADDRESS, &peer.octets()), option(PRIMARY_DNS, &dns[0].octets()), option(SECONDARY_DNS, &dns[1].octets())].concat();
+        assert_eq!(sent(&mut link), [(IPCP, CONFIGURE_NAK, 2, naked.clone())]);
+        assert!(!link.is_online());
+
+        link.read(&wire(IPCP_REQUEST_NAKED));
+        assert_eq!(sent(&mut link), [(IPCP, CONFIGURE_ACK, 3, naked)]);
+        assert!(link.is_online());
+
+        let packets = link.read(&wire(IP_PACKET));
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0][12..20], [10, 0, 3, 2, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn ip_waits_for_ipcp() {
+        let mut link = Link::new(Ipv4Addr::new(10, 0, 3, 1), Ipv4Addr::new(10, 0, 3, 2), [Ipv4Addr::new(1, 1, 1, 1); 2]);
+        link.out.clear();
+
+        // Not even LCP's up, so it's dropped without a word.
+        assert!(link.read(&wire(IP_PACKET)).is_empty());
+        assert!(link.out.is_empty());
+
+        link.send_ip(&[0x45; 20]);
+        assert!(link.out.is_empty());
+    }
+}
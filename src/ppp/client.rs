@@ -0,0 +1,90 @@
+// The box's side of a link, for testing the built-in backends: just enough PPP to get online, then IP packets
+// in and out.
+
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::backend::BackendStream;
+
+use super::control::{self, option, Packet, CONFIGURE_ACK, CONFIGURE_NAK, CONFIGURE_REQUEST, IPCP, IPV4, IP_ADDRESS, LCP, PAP};
+use super::control::{PAP_ACK, PAP_REQUEST, PRIMARY_DNS, SECONDARY_DNS};
+use super::hdlc::{self, Deframer, ALL_ESCAPED};
+
+pub struct Client {
+    reader: Box<dyn AsyncRead + Unpin + Send>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+    deframer: Deframer,
+    pending: Vec<Vec<u8>>,
+}
+
+impl Client {
+    pub fn new(stream: BackendStream) -> Client {
+        Client { reader: stream.reader, writer: stream.writer, deframer: Deframer::default(), pending: Vec::new() }
+    }
+
+    pub async fn send(&mut self, protocol: u16, information: &[u8]) {
+        self.writer.write_all(&hdlc::frame(protocol, information, ALL_ESCAPED)).await.unwrap();
+    }
+
+    pub async fn receive(&mut self) -> (u16, Vec<u8>) {
+        while self.pending.is_empty() {
+            let mut buf = [0; 1024];
+            let bytes_found = tokio::time::timeout(Duration::from_secs(5), self.reader.read(&mut buf)).await.unwrap().unwrap();
+            assert!(bytes_found > 0, "the backend hung up");
+
+            self.pending.extend(buf[..bytes_found].iter().filter_map(|byte| self.deframer.push(*byte)));
+        }
+
+        let frame = self.pending.remove(0);
+        let (protocol, information) = hdlc::split(&frame).unwrap();
+
+        (protocol, information.to_vec())
+    }
+
+    pub async fn control(&mut self, expected_protocol: u16, expected_code: u8) -> (u8, Vec<u8>) {
+        let (protocol, information) = self.receive().await;
+        let packet = Packet::parse(&information).unwrap();
+
+        assert_eq!((protocol, packet.code), (expected_protocol, expected_code));
+
+        (packet.id, packet.data.to_vec())
+    }
+
+    /// LCP, PAP (which the box does whether it's asked or not), then IPCP, starting out not knowing its address.
+    /// Gives back the address it was given, after checking the backend's own address and the name servers.
+    pub async fn connect(&mut self, local: Ipv4Addr, dns: [Ipv4Addr; 2]) -> Ipv4Addr {
+        self.send(LCP, &control::packet(CONFIGURE_REQUEST, 1, &[])).await;
+
+        let (id, request) = self.control(LCP, CONFIGURE_REQUEST).await;
+        self.control(LCP, CONFIGURE_ACK).await;
+        self.send(LCP, &control::packet(CONFIGURE_ACK, id, &request)).await;
+
+        let (id, request) = self.control(IPCP, CONFIGURE_REQUEST).await;
+        assert_eq!(request, option(IP_ADDRESS, &local.octets()));
+        self.send(IPCP, &control::packet(CONFIGURE_ACK, id, &request)).await;
+
+        self.send(PAP, &control::packet(PAP_REQUEST, 1, &[4, b'w', b't', b'v', b'1', 0])).await;
+        self.control(PAP, PAP_ACK).await;
+
+        let unknown = [option(IP_ADDRESS, &[0; 4]), option(PRIMARY_DNS, &[0; 4]), option(SECONDARY_DNS, &[0; 4])].concat();
+        self.send(IPCP, &control::packet(CONFIGURE_REQUEST, 1, &unknown)).await;
+
+        let (_, suggested) = self.control(IPCP, CONFIGURE_NAK).await;
+        let address = Ipv4Addr::from(<[u8; 4]>::try_from(&suggested[2..6]).unwrap());
+        let expected = [option(IP_ADDRESS, &address.octets()), option(PRIMARY_DNS, &dns[0].octets()), option(SECONDARY_DNS, &dns[1].octets())];
+        assert_eq!(suggested, expected.concat());
+
+        self.send(IPCP, &control::packet(CONFIGURE_REQUEST, 2, &suggested)).await;
+        self.control(IPCP, CONFIGURE_ACK).await;
+
+        address
+    }
+
+    pub async fn ip(&mut self) -> Vec<u8> {
+        let (protocol, information) = self.receive().await;
+        assert_eq!(protocol, IPV4);
+
+        information
+    }
+}
@@ -0,0 +1,404 @@
+// The parts of PPP that bring the link up: RFC 1661's LCP, RFC 1332's IPCP (with RFC 1877's DNS options) and RFC
+// 1334's PAP. These are plain functions over packets so the exchanges can be tested without a link.
+
+use std::net::Ipv4Addr;
+use tokio::time::{Duration, Instant};
+
+pub const IPV4: u16 = 0x0021;
+pub const IPCP: u16 = 0x8021;
+pub const LCP: u16 = 0xc021;
+pub const PAP: u16 = 0xc023;
+
+pub const CONFIGURE_REQUEST: u8 = 1;
+pub const CONFIGURE_ACK: u8 = 2;
+pub const CONFIGURE_NAK: u8 = 3;
+pub const CONFIGURE_REJECT: u8 = 4;
+pub const TERMINATE_REQUEST: u8 = 5;
+pub const TERMINATE_ACK: u8 = 6;
+pub const CODE_REJECT: u8 = 7;
+pub const PROTOCOL_REJECT: u8 = 8;
+pub const ECHO_REQUEST: u8 = 9;
+pub const ECHO_REPLY: u8 = 10;
+pub const DISCARD_REQUEST: u8 = 11;
+
+pub const PAP_REQUEST: u8 = 1;
+pub const PAP_ACK: u8 = 2;
+
+// LCP options.
+pub const MRU: u8 = 1;
+pub const ACCM: u8 = 2;
+pub const AUTH_PROTOCOL: u8 = 3;
+pub const MAGIC_NUMBER: u8 = 5;
+pub const PFC: u8 = 7;
+pub const ACFC: u8 = 8;
+
+// IPCP options.
+pub const IP_ADDRESS: u8 = 3;
+pub const PRIMARY_DNS: u8 = 129;
+pub const SECONDARY_DNS: u8 = 131;
+
+/// What both ends can send until LCP agrees on something else.
+pub const DEFAULT_MRU: u16 = 1500;
+// Anything smaller can't carry a TCP segment worth sending.
+const MIN_MRU: u16 = 128;
+
+// RFC 1661's Restart timer and Max-Configure.
+const RESTART: Duration = Duration::from_secs(3);
+const MAX_CONFIGURE: u32 = 10;
+
+/// An LCP, IPCP or PAP packet: code, identifier, then whatever the code carries.
+#[derive(Debug, PartialEq)]
+pub struct Packet<'a> {
+    pub code: u8,
+    pub id: u8,
+    pub data: &'a [u8],
+}
+
+impl Packet<'_> {
+    /// Anything after the length field says is padding and left off.
+    pub fn parse(information: &[u8]) -> Option<Packet<'_>> {
+        let [code, id, high, low, ..] = *information else {
+            return None;
+        };
+
+        let length = u16::from_be_bytes([high, low]) as usize;
+
+        (4..=information.len()).contains(&length).then(|| Packet { code, id, data: &information[4..length] })
+    }
+}
+
+pub fn packet(code: u8, id: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![code, id];
+    packet.extend_from_slice(&(data.len() as u16 + 4).to_be_bytes());
+    packet.extend_from_slice(data);
+
+    packet
+}
+
+/// Configuration options are a type, a length counting both, then the value.
+pub fn options(data: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut options = Vec::new();
+    let mut rest = data;
+
+    while let [kind, length, ..] = *rest {
+        let length = length as usize;
+        if length < 2 || length > rest.len() {
+            return None;
+        }
+
+        options.push((kind, &rest[2..length]));
+        rest = &rest[length..];
+    }
+
+    rest.is_empty().then_some(options)
+}
+
+pub fn option(kind: u8, value: &[u8]) -> Vec<u8> {
+    let mut option = vec![kind, value.len() as u8 + 2];
+    option.extend_from_slice(value);
+
+    option
+}
+
+/// What we make of one of the options the peer asked for.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Ack,
+    /// Not that value, this one.
+    Nak(Vec<u8>),
+    Reject,
+}
+
+/// One control protocol's Configure-Request exchange, both ways: whether the peer's taken our options and whether
+/// we've taken theirs. It's open once both have.
+pub struct Negotiation {
+    ours: Vec<(u8, Vec<u8>)>,
+    // The options in the peer's last request we Ack'd.
+    theirs: Vec<(u8, Vec<u8>)>,
+    id: u8,
+    is_acked: bool,
+    is_peer_acked: bool,
+    resend_at: Option<Instant>,
+    requests_left: u32,
+}
+
+impl Negotiation {
+    pub fn new(ours: Vec<(u8, Vec<u8>)>) -> Negotiation {
+        Negotiation {
+            ours,
+            theirs: Vec::new(),
+            id: 0,
+            is_acked: false,
+            is_peer_acked: false,
+            resend_at: None,
+            requests_left: MAX_CONFIGURE,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_acked && self.is_peer_acked
+    }
+
+    /// Whether we've asked as many times as RFC 1661 allows without an answer.
+    pub fn has_given_up(&self) -> bool {
+        !self.is_acked && self.requests_left == 0 && self.resend_at.is_some_and(|resend_at| Instant::now() >= resend_at)
+    }
+
+    /// The value of an option from the peer's Ack'd request.
+    pub fn theirs(&self, kind: u8) -> Option<&[u8]> {
+        self.theirs.iter().find(|(their_kind, _)| *their_kind == kind).map(|(_, value)| &value[..])
+    }
+
+    /// Our Configure-Request, with a new identifier so a late answer to an older one isn't taken for this one.
+    pub fn request(&mut self) -> Vec<u8> {
+        self.id = self.id.wrapping_add(1);
+        self.is_acked = false;
+        self.resend_at = Some(Instant::now() + RESTART);
+        self.requests_left = self.requests_left.saturating_sub(1);
+
+        let data: Vec<u8> = self.ours.iter().flat_map(|(kind, value)| option(*kind, value)).collect();
+
+        packet(CONFIGURE_REQUEST, self.id, &data)
+    }
+
+    /// Our request again if it's gone unanswered for too long.
+    pub fn resend(&mut self) -> Option<Vec<u8>> {
+        let is_due = !self.is_acked && self.requests_left > 0 && self.resend_at.is_some_and(|resend_at| Instant::now() >= resend_at);
+
+        is_due.then(|| self.request())
+    }
+
+    /// Starts over, as when the peer asks for new options on a link that's already open.
+    pub fn restart(&mut self) -> Vec<u8> {
+        self.is_peer_acked = false;
+        self.requests_left = MAX_CONFIGURE;
+
+        self.request()
+    }
+
+    /// Takes the peer's Ack, Nak or Reject of our request. A Nak'd option takes the value the peer suggested and a
+    /// Reject'd one is dropped, and either way there's a new request to send.
+    pub fn answered(&mut self, packet: &Packet) -> Option<Vec<u8>> {
+        if packet.id != self.id || self.is_acked {
+            return None;
+        }
+
+        match packet.code {
+            CONFIGURE_ACK => {
+                self.is_acked = true;
+                self.resend_at = None;
+
+                None
+            },
+            CONFIGURE_NAK => {
+                for (kind, value) in options(packet.data)? {
+                    if let Some((_, ours)) = self.ours.iter_mut().find(|(our_kind, _)| *our_kind == kind) {
+                        *ours = value.to_vec();
+                    }
+                }
+
+                Some(self.request())
+            },
+            CONFIGURE_REJECT => {
+                let rejected = options(packet.data)?;
+                self.ours.retain(|(kind, _)| !rejected.iter().any(|(rejected_kind, _)| rejected_kind == kind));
+
+                Some(self.request())
+            },
+            _ => None,
+        }
+    }
+
+    /// Answers the peer's Configure-Request, judging each option with `judge`: every Reject'd option if there are
+    /// any, otherwise every Nak'd one with what we'd take instead, otherwise an Ack.
+    pub fn answer(&mut self, packet: &Packet, judge: impl Fn(u8, &[u8]) -> Verdict) -> Vec<u8> {
+        let Some(options) = options(packet.data) else {
+            self.is_peer_acked = false;
+
+            return self::packet(CONFIGURE_REJECT, packet.id, packet.data);
+        };
+
+        let mut naks = Vec::new();
+        let mut rejects = Vec::new();
+
+        for (kind, value) in options.iter() {
+            match judge(*kind, value) {
+                Verdict::Ack => {},
+                Verdict::Nak(suggested) => naks.extend(option(*kind, &suggested)),
+                Verdict::Reject => rejects.extend(option(*kind, value)),
+            }
+        }
+
+        self.is_peer_acked = rejects.is_empty() && naks.is_empty();
+
+        if !rejects.is_empty() {
+            self::packet(CONFIGURE_REJECT, packet.id, &rejects)
+        } else if !naks.is_empty() {
+            self::packet(CONFIGURE_NAK, packet.id, &naks)
+        } else {
+            self.theirs = options.into_iter().map(|(kind, value)| (kind, value.to_vec())).collect();
+
+            self::packet(CONFIGURE_ACK, packet.id, packet.data)
+        }
+    }
+}
+
+/// The LCP options we ask for: no control characters escaped, and a magic number to spot a looped back line.
+pub fn lcp_options(magic: u32) -> Vec<(u8, Vec<u8>)> {
+    vec![(ACCM, 0u32.to_be_bytes().to_vec()), (MAGIC_NUMBER, magic.to_be_bytes().to_vec())]
+}
+
+/// What we make of each LCP option the peer asks for. Anything we don't know is rejected.
+pub fn judge_lcp(kind: u8, value: &[u8]) -> Verdict {
+    match (kind, value.len()) {
+        (MRU, 2) if u16::from_be_bytes([value[0], value[1]]) < MIN_MRU => Verdict::Nak(DEFAULT_MRU.to_be_bytes().to_vec()),
+        (MRU, 2) | (ACCM, 4) | (MAGIC_NUMBER, 4) | (PFC, 0) | (ACFC, 0) => Verdict::Ack,
+        // We don't authenticate to the peer, so asking us to is rejected too.
+        (AUTH_PROTOCOL, _) => Verdict::Reject,
+        _ => Verdict::Reject,
+    }
+}
+
+/// The IPCP option we ask for: our own address.
+pub fn ipcp_options(gateway: Ipv4Addr) -> Vec<(u8, Vec<u8>)> {
+    vec![(IP_ADDRESS, gateway.octets().to_vec())]
+}
+
+/// What we make of each IPCP option the peer asks for: it gets `client` as its address and `dns` for its primary
+/// and secondary name servers, whatever it asked for. VJ header compression and anything else is rejected.
+pub fn judge_ipcp(client: Ipv4Addr, dns: [Ipv4Addr; 2]) -> impl Fn(u8, &[u8]) -> Verdict {
+    move |kind, value| {
+        let wanted = match kind {
+            IP_ADDRESS => client,
+            PRIMARY_DNS => dns[0],
+            SECONDARY_DNS => dns[1],
+            _ => return Verdict::Reject,
+        };
+
+        if value == wanted.octets() {
+            Verdict::Ack
+        } else {
+            Verdict::Nak(wanted.octets().to_vec())
+        }
+    }
+}
+
+/// Answers a PAP Authenticate-Request with an Ack, whatever the name and password.
+pub fn pap_answer(packet: &Packet) -> Option<Vec<u8>> {
+    // An empty message.
+    (packet.code == PAP_REQUEST).then(|| self::packet(PAP_ACK, packet.id, &[0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u8, options: &[Vec<u8>]) -> Vec<u8> {
+        packet(CONFIGURE_REQUEST, id, &options.concat())
+    }
+
+    fn answer(negotiation: &mut Negotiation, sent: &[u8], judge: impl Fn(u8, &[u8]) -> Verdict) -> (u8, Vec<u8>) {
+        let answer = negotiation.answer(&Packet::parse(sent).unwrap(), judge);
+        let answer = Packet::parse(&answer).unwrap();
+
+        (answer.code, answer.data.to_vec())
+    }
+
+    #[test]
+    fn packets_ignore_padding_and_refuse_bad_lengths() {
+        let sent = [CONFIGURE_ACK, 7, 0, 6, 1, 2, 0xaa, 0xbb];
+        assert_eq!(Packet::parse(&sent), Some(Packet { code: CONFIGURE_ACK, id: 7, data: &[1, 2] }));
+
+        assert_eq!(Packet::parse(&[CONFIGURE_ACK, 7, 0, 9, 1, 2]), None);
+        assert_eq!(Packet::parse(&[CONFIGURE_ACK, 7, 0, 3]), None);
+        assert_eq!(options(&[MRU, 4, 5]), None);
+    }
+
+    #[test]
+    fn lcp_takes_what_a_box_usually_asks_for() {
+        let mut lcp = Negotiation::new(lcp_options(0x1234_5678));
+
+        let sent = request(1, &[option(MRU, &[5, 0xdc]), option(ACCM, &[0, 0, 0, 0]), option(MAGIC_NUMBER, &[1, 2, 3, 4]), option(PFC, &[]), option(ACFC, &[])]);
+        let (code, data) = answer(&mut lcp, &sent, judge_lcp);
+
+        assert_eq!(code, CONFIGURE_ACK);
+        assert_eq!(data, sent[4..]);
+        assert_eq!(lcp.theirs(MRU), Some(&[5, 0xdc][..]));
+
+        // Not open until our own request is Ack'd too.
+        assert!(!lcp.is_open());
+        let ours = lcp.request();
+        let ours = Packet::parse(&ours).unwrap();
+        assert_eq!(ours.data, [option(ACCM, &[0, 0, 0, 0]), option(MAGIC_NUMBER, &[0x12, 0x34, 0x56, 0x78])].concat());
+
+        assert_eq!(lcp.answered(&Packet { code: CONFIGURE_ACK, id: ours.id, data: ours.data }), None);
+        assert!(lcp.is_open());
+    }
+
+    #[test]
+    fn lcp_rejects_authenticating_to_the_box_and_naks_a_tiny_mru() {
+        let mut lcp = Negotiation::new(lcp_options(1));
+
+        // CHAP with MD5, and callback.
+        let chap = option(AUTH_PROTOCOL, &[0xc2, 0x23, 5]);
+        let callback = option(13, &[6]);
+        let sent = request(2, &[option(MRU, &[0, 64]), chap.clone(), callback.clone()]);
+        assert_eq!(answer(&mut lcp, &sent, judge_lcp), (CONFIGURE_REJECT, [chap, callback].concat()));
+
+        // With those gone, it's the MRU's turn.
+        let sent = request(3, &[option(MRU, &[0, 64])]);
+        assert_eq!(answer(&mut lcp, &sent, judge_lcp), (CONFIGURE_NAK, option(MRU, &[5, 0xdc])));
+
+        assert_eq!(answer(&mut lcp, &request(4, &[]), judge_lcp), (CONFIGURE_ACK, Vec::new()));
+    }
+
+    #[test]
+    fn our_requests_follow_naks_and_rejects() {
+        let mut lcp = Negotiation::new(lcp_options(1));
+        let first = lcp.request();
+        let first = Packet::parse(&first).unwrap();
+
+        // A new magic number from the peer, then the ACCM rejected.
+        let nak = option(MAGIC_NUMBER, &[9, 9, 9, 9]);
+        let second = lcp.answered(&Packet { code: CONFIGURE_NAK, id: first.id, data: &nak }).unwrap();
+        let second = Packet::parse(&second).unwrap();
+        assert_eq!((second.id, second.data), (first.id + 1, &[option(ACCM, &[0, 0, 0, 0]), nak.clone()].concat()[..]));
+
+        // An answer to the old request counts for nothing.
+        assert_eq!(lcp.answered(&Packet { code: CONFIGURE_ACK, id: first.id, data: &[] }), None);
+        assert!(!lcp.is_open());
+
+        let reject = option(ACCM, &[0, 0, 0, 0]);
+        let third = lcp.answered(&Packet { code: CONFIGURE_REJECT, id: second.id, data: &reject }).unwrap();
+        assert_eq!(Packet::parse(&third).unwrap().data, nak);
+    }
+
+    #[test]
+    fn ipcp_hands_out_the_address_and_dns() {
+        let client = Ipv4Addr::new(10, 0, 2, 15);
+        let dns = [Ipv4Addr::new(10, 0, 2, 1), Ipv4Addr::new(10, 0, 2, 3)];
+        let mut ipcp = Negotiation::new(ipcp_options(Ipv4Addr::new(10, 0, 2, 1)));
+
+        // What a client that wants to be told everything sends, VJ compression included.
+        let vj = option(2, &[0, 0x2d, 15, 1]);
+        let unknown = [option(IP_ADDRESS, &[0; 4]), option(PRIMARY_DNS, &[0; 4]), option(SECONDARY_DNS, &[0; 4])];
+        let sent = request(1, &[&unknown[..], std::slice::from_ref(&vj)].concat());
+        assert_eq!(answer(&mut ipcp, &sent, judge_ipcp(client, dns)), (CONFIGURE_REJECT, vj));
+
+        let sent = request(2, &unknown);
+        let naked = [option(IP_ADDRESS, &client.octets()), option(PRIMARY_DNS, &dns[0].octets()), option(SECONDARY_DNS, &dns[1].octets())].concat();
+        assert_eq!(answer(&mut ipcp, &sent, judge_ipcp(client, dns)), (CONFIGURE_NAK, naked.clone()));
+
+        let sent = packet(CONFIGURE_REQUEST, 3, &naked);
+        assert_eq!(answer(&mut ipcp, &sent, judge_ipcp(client, dns)), (CONFIGURE_ACK, naked));
+    }
+
+    #[test]
+    fn any_pap_password_will_do() {
+        let mut request = vec![4, b'u', b's', b'e', b'r', 2, b'p', b'w'];
+        request = packet(PAP_REQUEST, 5, &request);
+
+        assert_eq!(pap_answer(&Packet::parse(&request).unwrap()), Some(vec![PAP_ACK, 5, 0, 5, 0]));
+        assert_eq!(pap_answer(&Packet { code: PAP_ACK, id: 5, data: &[] }), None);
+    }
+}
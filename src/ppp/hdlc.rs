@@ -0,0 +1,426 @@
+// RFC 1662's HDLC-like framing: each frame sits between 0x7e flags, with 0x7d escaping and a 16 bit FCS on the end.
+
+pub const FLAG: u8 = 0x7e;
+const ESCAPE: u8 = 0x7d;
+const ESCAPE_BIT: u8 = 0x20;
+const ADDRESS_CONTROL: [u8; 2] = [0xff, 0x03];
+
+const INITIAL_FCS: u16 = 0xffff;
+// What the FCS comes to over a frame with its own FCS still on the end.
+const GOOD_FCS: u16 = 0xf0b8;
+
+// Longer than any frame an MRU of 1500 allows, so it's bytes run together by a lost flag.
+const MAX_FRAME: usize = 4096;
+
+/// Every control character escaped. LCP always goes out like this, and so does everything else until LCP says otherwise.
+pub const ALL_ESCAPED: u32 = 0xffff_ffff;
+
+pub fn fcs(mut fcs: u16, bytes: &[u8]) -> u16 {
+    for byte in bytes {
+        fcs ^= *byte as u16;
+
+        for _ in 0..8 {
+            fcs = if fcs & 1 == 1 { (fcs >> 1) ^ 0x8408 } else { fcs >> 1 };
+        }
+    }
+
+    fcs
+}
+
+/// Picks frames out of the bytes coming off the link.
+#[derive(Default)]
+pub struct Deframer {
+    frame: Vec<u8>,
+    is_escaped: bool,
+    is_too_long: bool,
+}
+
+impl Deframer {
+    /// Takes the next byte, giving back the frame it ended (FCS checked and taken off) if it was a closing flag.
+    /// Frames with a bad FCS, or cut short by 0x7d 0x7e, are dropped the way RFC 1662 says.
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match byte {
+            FLAG => {
+                let mut frame = std::mem::take(&mut self.frame);
+                let is_good = !self.is_escaped && !self.is_too_long && frame.len() > 2 && fcs(INITIAL_FCS, &frame) == GOOD_FCS;
+
+                self.is_escaped = false;
+                self.is_too_long = false;
+
+                is_good.then(|| {
+                    frame.truncate(frame.len() - 2);
+
+                    frame
+                })
+            },
+            ESCAPE => {
+                self.is_escaped = true;
+
+                None
+            },
+            _ if self.is_too_long => None,
+            _ => {
+                let byte = if self.is_escaped { byte ^ ESCAPE_BIT } else { byte };
+                self.is_escaped = false;
+
+                if self.frame.len() == MAX_FRAME {
+                    self.frame.clear();
+                    self.is_too_long = true;
+                } else {
+                    self.frame.push(byte);
+                }
+
+                None
+            },
+        }
+    }
+}
+
+/// Cuts the bytes going out over the link into whole frames, flags at either end and still escaped, for carrying
+/// them one at a time over something that keeps them apart, like UDP.
+#[derive(Default)]
+pub struct Splitter {
+    // Since the last flag.
+    frame: Vec<u8>,
+    is_too_long: bool,
+    dropped: u64,
+}
+
+impl Splitter {
+    /// Takes the next lot of bytes, giving back every frame they finished. Frames too long to be real are dropped,
+    /// and so are the empty ones between back to back flags.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+
+        for byte in bytes {
+            match *byte {
+                FLAG => {
+                    let frame = std::mem::take(&mut self.frame);
+
+                    if self.is_too_long {
+                        self.dropped += 1;
+                    } else if !frame.is_empty() {
+                        let mut framed = Vec::with_capacity(frame.len() + 2);
+                        framed.push(FLAG);
+                        framed.extend_from_slice(&frame);
+                        framed.push(FLAG);
+
+                        frames.push(framed);
+                    }
+
+                    self.is_too_long = false;
+                },
+                _ if self.is_too_long => {},
+                byte => {
+                    if self.frame.len() == MAX_FRAME {
+                        self.frame.clear();
+                        self.is_too_long = true;
+                    } else {
+                        self.frame.push(byte);
+                    }
+                },
+            }
+        }
+
+        frames
+    }
+
+    /// How many frames have been dropped for being too long.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Watches bytes that weren't meant to be PPP for the start of a frame: a flag, then the address and control
+/// fields, escaped or not.
+#[derive(Default)]
+pub struct FrameSpotter {
+    // From a flag that might yet turn out to start a frame.
+    pending: Vec<u8>,
+}
+
+// Whether what follows a flag starts a frame. None until there's enough of it to tell.
+fn starts_frame(mut after_flag: &[u8]) -> Option<bool> {
+    for wanted in ADDRESS_CONTROL {
+        let (byte, rest) = match after_flag {
+            [] | [ESCAPE] => return None,
+            [ESCAPE, byte, rest @ ..] => (byte ^ ESCAPE_BIT, rest),
+            [byte, rest @ ..] => (*byte, rest),
+        };
+
+        if byte != wanted {
+            return Some(false);
+        }
+        after_flag = rest;
+    }
+
+    Some(true)
+}
+
+impl FrameSpotter {
+    /// Takes the next lot of bytes, giving back everything from the frame's opening flag on (which can go back
+    /// into earlier lots) once one shows up. The rest of the frame is still to come.
+    pub fn push(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        self.pending.extend_from_slice(bytes);
+
+        let flags: Vec<usize> = self.pending.iter().enumerate().filter(|(_, byte)| **byte == FLAG).map(|(at, _)| at).collect();
+
+        for at in flags {
+            match starts_frame(&self.pending[at + 1..]) {
+                Some(true) => {
+                    let spotted = self.pending.split_off(at);
+                    self.pending.clear();
+
+                    return Some(spotted);
+                },
+                Some(false) => continue,
+                None => {
+                    self.pending.drain(..at);
+
+                    return None;
+                },
+            }
+        }
+
+        self.pending.clear();
+
+        None
+    }
+}
+
+/// Splits a frame into its protocol and information, allowing for the address, control and protocol fields
+/// being compressed.
+pub fn split(frame: &[u8]) -> Option<(u16, &[u8])> {
+    let frame = frame.strip_prefix(&ADDRESS_CONTROL[..]).unwrap_or(frame);
+
+    match frame {
+        [protocol, information @ ..] if protocol & 1 == 1 => Some((*protocol as u16, information)),
+        [high, low, information @ ..] if low & 1 == 1 => Some((u16::from_be_bytes([*high, *low]), information)),
+        _ => None,
+    }
+}
+
+/// Frames `information` for `protocol`, escaping whichever control characters `accm` says the peer wants escaped.
+/// Nothing's compressed, which a peer always has to take.
+pub fn frame(protocol: u16, information: &[u8], accm: u32) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(information.len() + 6);
+    unescaped.extend_from_slice(&ADDRESS_CONTROL);
+    unescaped.extend_from_slice(&protocol.to_be_bytes());
+    unescaped.extend_from_slice(information);
+
+    let fcs = !fcs(INITIAL_FCS, &unescaped);
+    unescaped.extend_from_slice(&fcs.to_le_bytes());
+
+    let mut framed = Vec::with_capacity(unescaped.len() * 2 + 2);
+    framed.push(FLAG);
+
+    for byte in unescaped {
+        if byte == FLAG || byte == ESCAPE || (byte < 0x20 && accm & (1 << byte) != 0) {
+            framed.extend_from_slice(&[ESCAPE, byte ^ ESCAPE_BIT]);
+        } else {
+            framed.push(byte);
+        }
+    }
+
+    framed.push(FLAG);
+
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deframe(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut deframer = Deframer::default();
+
+        bytes.iter().filter_map(|byte| deframer.push(*byte)).collect()
+    }
+
+    #[test]
+    fn fcs_is_the_x25_crc() {
+        assert_eq!(!fcs(INITIAL_FCS, b"123456789"), 0x906e);
+    }
+
+    #[test]
+    fn frames_come_back_out_as_they_went_in() {
+        let information = [0x01, 0x7e, 0x7d, 0x11, 0x13, 0x00, 0xff];
+
+        for accm in [ALL_ESCAPED, 0] {
+            let framed = frame(0xc021, &information, accm);
+
+            // Only the flags at either end are left as flags.
+            assert_eq!(framed.iter().filter(|byte| **byte == FLAG).count(), 2);
+            assert_eq!(framed.contains(&0x11), accm == 0);
+
+            let frames = deframe(&framed);
+            assert_eq!(frames.len(), 1);
+            assert_eq!(split(&frames[0]), Some((0xc021, &information[..])));
+        }
+    }
+
+    #[test]
+    fn broken_frames_are_dropped() {
+        let mut framed = frame(0x0021, b"hello", ALL_ESCAPED);
+
+        let mut bad_fcs = framed.clone();
+        bad_fcs[6] ^= 1;
+        assert!(deframe(&bad_fcs).is_empty());
+
+        // Aborted with 0x7d 0x7e, then a good one right after.
+        let mut aborted = framed[..6].to_vec();
+        aborted.extend_from_slice(&[ESCAPE, FLAG]);
+        aborted.append(&mut framed);
+        assert_eq!(deframe(&aborted).len(), 1);
+    }
+
+    #[test]
+    fn splits_bytes_into_whole_frames() {
+        let hello = frame(0x0021, b"hello", ALL_ESCAPED);
+        let there = frame(0x0021, b"there", ALL_ESCAPED);
+        let mut splitter = Splitter::default();
+
+        // A frame and a half, then the rest.
+        let mut bytes = hello.clone();
+        bytes.extend_from_slice(&there[..4]);
+        assert_eq!(splitter.push(&bytes), vec![hello.clone()]);
+        assert_eq!(splitter.push(&there[4..]), vec![there.clone()]);
+
+        // Too long to be a frame, then a good one.
+        let mut bytes = vec![FLAG];
+        bytes.extend(std::iter::repeat_n(0x21, MAX_FRAME + 1));
+        bytes.extend_from_slice(&hello);
+        assert_eq!(splitter.push(&bytes), vec![hello]);
+        assert_eq!(splitter.dropped(), 1);
+    }
+
+    #[test]
+    fn spots_a_frame_among_at_commands() {
+        let lcp = frame(0xc021, &[0x01, 0x01, 0x00, 0x04], ALL_ESCAPED);
+
+        let mut spotter = FrameSpotter::default();
+        assert_eq!(spotter.push(b"ATZ\r~~AT\r"), None);
+        assert_eq!(spotter.push(&[b"AT\r".as_slice(), &lcp].concat()), Some(lcp.clone()));
+
+        // Split up anywhere, what's spotted and what's still to come make up the whole frame.
+        for split in 1..lcp.len() {
+            let mut spotter = FrameSpotter::default();
+            let (first, second) = lcp.split_at(split);
+
+            let spotted = match spotter.push(first) {
+                Some(spotted) => [spotted, second.to_vec()].concat(),
+                None => spotter.push(second).unwrap(),
+            };
+            assert_eq!(spotted, lcp, "split at {split}");
+        }
+
+        // Unescaped fields count too, but not a flag that's followed by anything else.
+        assert_eq!(FrameSpotter::default().push(&[FLAG, 0xff, 0x03, 0xc0]), Some(vec![FLAG, 0xff, 0x03, 0xc0]));
+        assert_eq!(FrameSpotter::default().push(&[FLAG, 0xff, 0x04, FLAG, b'A']), None);
+    }
+
+    #[test]
+    fn compressed_fields_are_understood() {
+        assert_eq!(split(&[0x21, 0x45]), Some((0x0021, &[0x45][..])));
+        assert_eq!(split(&[0xc0, 0x21, 0x01]), Some((0xc021, &[0x01][..])));
+        assert_eq!(split(&[0xff, 0x03, 0x80, 0x21]), Some((0x8021, &[][..])));
+        assert_eq!(split(&[0xc0, 0x20]), None);
+    }
+
+    // Whatever comes off the link, however it's cut up into reads.
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        // Flags and escapes turn up a lot more than they would by chance, since they're what makes these go.
+        fn link() -> impl Strategy<Value = Vec<u8>> {
+            let special = [FLAG, ESCAPE, ADDRESS_CONTROL[0], ADDRESS_CONTROL[1]];
+
+            proptest::collection::vec(any::<u8>(), 0..6000).prop_map(move |bytes| bytes.into_iter().map(|byte| if byte < 0x40 { special[byte as usize % 4] } else { byte }).collect())
+        }
+
+        // Where to cut those bytes into reads.
+        fn reads(bytes: &[u8], cuts: &[usize]) -> Vec<Vec<u8>> {
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut % (bytes.len() + 1)).collect();
+            cuts.sort();
+
+            [0].iter().chain(&cuts).zip(cuts.iter().chain([&bytes.len()])).map(|(from, to)| bytes[*from..*to].to_vec()).collect()
+        }
+
+        proptest! {
+            #[test]
+            fn frames_come_back_out_whatever_is_in_them(protocol in any::<u16>(), information in proptest::collection::vec(any::<u8>(), 0..1500), accm in any::<u32>()) {
+                // The low bit of the first byte says the protocol's one byte long, so real ones never have it.
+                let protocol = protocol & 0xfeff | 0x0001;
+                let framed = frame(protocol, &information, accm);
+
+                prop_assert_eq!(framed.iter().filter(|byte| **byte == FLAG).count(), 2);
+                let frames = deframe(&framed);
+                prop_assert_eq!(frames.iter().map(|frame| split(frame)).collect::<Vec<_>>(), vec![Some((protocol, &information[..]))]);
+            }
+
+            #[test]
+            fn deframed_frames_come_from_the_bytes(bytes in link()) {
+                let mut deframer = Deframer::default();
+                let mut frames = 0;
+                let mut taken = 0;
+
+                for &byte in &bytes {
+                    if let Some(frame) = deframer.push(byte) {
+                        prop_assert_eq!(byte, FLAG);
+                        prop_assert!(frame.len() <= MAX_FRAME - 2);
+                        frames += 1;
+                        taken += frame.len() + 2;
+                    }
+
+                    prop_assert!(deframer.frame.len() <= MAX_FRAME);
+                }
+
+                prop_assert!(frames <= bytes.iter().filter(|byte| **byte == FLAG).count());
+                prop_assert!(taken <= bytes.len());
+            }
+
+            #[test]
+            fn split_frames_are_the_same_however_the_bytes_come(bytes in link(), cuts in proptest::collection::vec(any::<usize>(), 0..8)) {
+                let whole = Splitter::default().push(&bytes);
+
+                let mut splitter = Splitter::default();
+                let mut frames = Vec::new();
+                for read in reads(&bytes, &cuts) {
+                    frames.extend(splitter.push(&read));
+                    prop_assert!(splitter.frame.len() <= MAX_FRAME);
+                }
+                prop_assert_eq!(&frames, &whole);
+
+                for frame in &frames {
+                    prop_assert!(frame.len() > 2 && frame[0] == FLAG && frame[frame.len() - 1] == FLAG);
+                    prop_assert!(!frame[1..frame.len() - 1].contains(&FLAG));
+                }
+                prop_assert!(frames.iter().map(|frame| frame.len() - 1).sum::<usize>() <= bytes.len());
+            }
+
+            #[test]
+            fn spotted_frames_come_from_the_bytes(bytes in link(), cuts in proptest::collection::vec(any::<usize>(), 0..8)) {
+                let mut spotter = FrameSpotter::default();
+                let mut so_far = Vec::new();
+
+                for read in reads(&bytes, &cuts) {
+                    so_far.extend_from_slice(&read);
+
+                    match spotter.push(&read) {
+                        // Picked up where it left off, and always from a flag.
+                        Some(spotted) => {
+                            prop_assert!(spotted[0] == FLAG && so_far.ends_with(&spotted));
+                            prop_assert_eq!(starts_frame(&spotted[1..]), Some(true));
+
+                            spotter = FrameSpotter::default();
+                            so_far.clear();
+                        },
+                        // Only ever a flag and the start of the address and control fields left waiting.
+                        None => prop_assert!(spotter.pending.len() <= 4 && so_far.ends_with(&spotter.pending)),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// Keeping an eye on IPCP as it goes by between the box and whatever PPP it dialed, so a call can say which
+// addresses it ended up with even when pppd (or someone else's server) handed them out.
+
+use std::net::Ipv4Addr;
+
+use crate::stats::NegotiatedIp;
+
+use super::control::{options, Packet, CONFIGURE_ACK, CONFIGURE_REQUEST, IPCP, IP_ADDRESS, PRIMARY_DNS, SECONDARY_DNS};
+use super::hdlc;
+
+/// Which way a frame was going.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Direction {
+    FromBox,
+    ToBox,
+}
+
+/// What IPCP's settled on so far. Only Acks count: whatever was Nak'd on the way there was just a proposal.
+#[derive(Default)]
+pub struct IpcpWatch {
+    // From the server's Ack of the box's request.
+    client: Option<Ipv4Addr>,
+    dns: Vec<Ipv4Addr>,
+    // From the box's Ack of the server's request.
+    server: Option<Ipv4Addr>,
+    is_reported: bool,
+}
+
+fn address(value: &[u8]) -> Option<Ipv4Addr> {
+    <[u8; 4]>::try_from(value).ok().map(Ipv4Addr::from)
+}
+
+impl IpcpWatch {
+    /// Takes a whole frame (FCS already off) going `direction`, giving back the addresses the first time both
+    /// ends have Ack'd each other. A new request after that starts over, so a renegotiation is reported again.
+    pub fn frame(&mut self, direction: Direction, frame: &[u8]) -> Option<NegotiatedIp> {
+        let (IPCP, information) = hdlc::split(frame)? else {
+            return None;
+        };
+        let packet = Packet::parse(information)?;
+
+        match (packet.code, direction) {
+            (CONFIGURE_REQUEST, _) if self.is_reported => *self = IpcpWatch::default(),
+            (CONFIGURE_ACK, Direction::ToBox) => {
+                let options = options(packet.data)?;
+                let value = |wanted: u8| options.iter().find(|(kind, _)| *kind == wanted).and_then(|(_, value)| address(value));
+
+                self.client = value(IP_ADDRESS);
+                self.dns = [value(PRIMARY_DNS), value(SECONDARY_DNS)].into_iter().flatten().collect();
+            },
+            (CONFIGURE_ACK, Direction::FromBox) => {
+                self.server = options(packet.data)?.into_iter().find(|(kind, _)| *kind == IP_ADDRESS).and_then(|(_, value)| address(value));
+            },
+            _ => {},
+        }
+
+        let (Some(client_ip), Some(server_ip)) = (self.client, self.server) else {
+            return None;
+        };
+
+        if self.is_reported {
+            return None;
+        }
+        self.is_reported = true;
+
+        Some(NegotiatedIp { client_ip, server_ip, dns: self.dns.clone() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppp::control::{option, packet};
+    use crate::ppp::hdlc::Deframer;
+
+    // pppd handing out 192.168.1.100 and name servers to a box that asks for 0.0.0.0, as it went over the wire,
+    // escapes and all. pppd's end is 192.168.1.1, and it asks for VJ compression too.
+    const FROM_PPPD: &str = "\
+        7eff7d2380217d217d217d207d307d227d267d202d7d2f7d217d237d26c0a87d217d21b0637e\
+        7eff7d2380217d237d217d207d367d237d26c0a87d2164817d26c0a87d217d21837d267d287d287d287d28f26e7e\
+        7eff7d2380217d227d227d207d367d237d26c0a87d2164817d26c0a87d217d21837d267d287d287d287d287d3b9e7e";
+    const FROM_BOX: &str = "\
+        7eff7d2380217d217d217d207d367d237d267d207d207d207d20817d267d207d207d207d20837d267d207d207d207d206edb7e\
+        7eff7d2380217d227d217d207d307d227d267d202d7d2f7d217d237d26c0a87d217d2191f97e\
+        7eff7d2380217d217d227d207d367d237d26c0a87d2164817d26c0a87d217d21837d267d287d287d287d28ed6d7e";
+
+    fn frames(hex: &str) -> Vec<Vec<u8>> {
+        let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        let mut deframer = Deframer::default();
+
+        bytes.iter().filter_map(|byte| deframer.push(*byte)).collect()
+    }
+
+    // A deframed IPCP frame, address and control fields and all.
+    fn ipcp(code: u8, id: u8, options: &[Vec<u8>]) -> Vec<u8> {
+        [vec![0xff, 0x03, 0x80, 0x21], packet(code, id, &options.concat())].concat()
+    }
+
+    #[test]
+    fn reports_what_was_acked_not_what_was_asked_for() {
+        let (from_pppd, from_box) = (frames(FROM_PPPD), frames(FROM_BOX));
+        let mut watch = IpcpWatch::default();
+
+        // pppd's request, the box's 0.0.0.0 request, pppd's Nak with its suggestions, the box's Ack of pppd's
+        // address, the box asking again for what it was offered, and pppd's Ack.
+        assert_eq!(watch.frame(Direction::ToBox, &from_pppd[0]), None);
+        assert_eq!(watch.frame(Direction::FromBox, &from_box[0]), None);
+        assert_eq!(watch.frame(Direction::ToBox, &from_pppd[1]), None);
+        assert_eq!(watch.frame(Direction::FromBox, &from_box[1]), None);
+        assert_eq!(watch.frame(Direction::FromBox, &from_box[2]), None);
+
+        let negotiated = watch.frame(Direction::ToBox, &from_pppd[2]);
+        assert_eq!(negotiated, Some(NegotiatedIp {
+            client_ip: Ipv4Addr::new(192, 168, 1, 100),
+            server_ip: Ipv4Addr::new(192, 168, 1, 1),
+            dns: vec![Ipv4Addr::new(192, 168, 1, 1), Ipv4Addr::new(8, 8, 8, 8)],
+        }));
+
+        // Only the once.
+        assert_eq!(watch.frame(Direction::ToBox, &from_pppd[2]), None);
+    }
+
+    #[test]
+    fn a_renegotiation_is_reported_again() {
+        let mut watch = IpcpWatch::default();
+        let (client, server, other) = ([10, 0, 0, 2], [10, 0, 0, 1], [10, 0, 0, 3]);
+
+        assert_eq!(watch.frame(Direction::FromBox, &ipcp(CONFIGURE_ACK, 1, &[option(IP_ADDRESS, &server)])), None);
+        assert!(watch.frame(Direction::ToBox, &ipcp(CONFIGURE_ACK, 1, &[option(IP_ADDRESS, &client)])).is_some());
+
+        assert_eq!(watch.frame(Direction::ToBox, &ipcp(CONFIGURE_REQUEST, 2, &[option(IP_ADDRESS, &server)])), None);
+        assert_eq!(watch.frame(Direction::FromBox, &ipcp(CONFIGURE_ACK, 2, &[option(IP_ADDRESS, &server)])), None);
+
+        let negotiated = watch.frame(Direction::ToBox, &ipcp(CONFIGURE_ACK, 3, &[option(IP_ADDRESS, &other)])).unwrap();
+        assert_eq!((negotiated.client_ip, negotiated.dns), (Ipv4Addr::from(other), vec![]));
+    }
+
+    #[test]
+    fn other_protocols_are_left_alone() {
+        let mut watch = IpcpWatch::default();
+        let lcp_ack = [vec![0xff, 0x03, 0xc0, 0x21], packet(CONFIGURE_ACK, 1, &option(IP_ADDRESS, &[10, 0, 0, 2]))].concat();
+
+        assert_eq!(watch.frame(Direction::ToBox, &lcp_ack), None);
+        assert_eq!(watch.client, None);
+    }
+}
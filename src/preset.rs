@@ -0,0 +1,133 @@
+// --modem-profile: who the modem says it is, as data. --profile decides how commands are taken; a preset decides
+// what ATI and Sn? answer, what the S registers start out as, whether 56k is on the cards and how CONNECT reads.
+// Another modem is just another entry in PRESETS.
+
+use crate::at;
+
+/// One modem's personality.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ModemPreset {
+    /// What --modem-profile calls it.
+    pub name: &'static str,
+    /// What ATIn answers, by n, for a --profile that answers ATI. "" is just OK, and anything missing is ERROR.
+    pub identity: &'static [(&'static str, &'static str)],
+    /// The S registers as ATZ leaves them. Any the modem's never heard of read back as 0.
+    pub s_registers: &'static [(u32, u32)],
+    /// What CARRIER says with --carrier-speed auto, before the box's +MS and S51 have their say.
+    pub carrier_speed: u32,
+    /// Some(false) for a modem that can't do 56k whatever it's asked, Some(true) for one that always does. A phone
+    /// book entry's force_56k and the 56k flags beat it.
+    pub force_56k: Option<bool>,
+    /// Tacked onto CONNECT when it's sent as words, like a Courier's /ARQ.
+    pub connect_suffix: &'static str,
+}
+
+// What a Hayes modem's S registers start out as.
+const HAYES_S_REGISTERS: &[(u32, u32)] = &[(0, 0), (1, 0), (2, 43), (3, 13), (4, 10), (5, 8), (6, 2), (7, 50), (8, 2), (10, 14)];
+
+/// What TouchPPP has always been: the WebTV's K56 modem, reporting V.34 unless told otherwise.
+pub const WEBTV_K56: ModemPreset = ModemPreset {
+    name: "webtv-k56",
+    identity: &[
+        ("", "33600"),
+        ("0", "33600"),
+        ("1", "255"),
+        ("2", ""),
+        ("3", "TouchPPP V.34 Data Modem"),
+        ("4", "TouchPPP"),
+    ],
+    s_registers: HAYES_S_REGISTERS,
+    carrier_speed: at::DEFAULT_CARRIER_SPEED,
+    force_56k: None,
+    connect_suffix: "",
+};
+
+pub const PRESETS: &[ModemPreset] = &[
+    WEBTV_K56,
+    // A Rockwell V.34 chipset, which never heard of 56k.
+    ModemPreset {
+        name: "rockwell-v34",
+        identity: &[
+            ("", "33600"),
+            ("0", "33600"),
+            ("1", "255"),
+            ("2", ""),
+            ("3", "V1.610-V34_DS"),
+            ("4", "Rockwell RC336DPFL"),
+        ],
+        s_registers: HAYES_S_REGISTERS,
+        carrier_speed: at::DEFAULT_CARRIER_SPEED,
+        force_56k: Some(false),
+        connect_suffix: "",
+    },
+    // The softmodem later firmware drives with S51, which is 56k until S51=31 says otherwise.
+    ModemPreset {
+        name: "softmodem",
+        identity: &[
+            ("", "56000"),
+            ("0", "56000"),
+            ("1", "255"),
+            ("2", ""),
+            ("3", "V2.201-K56_DLP_RAM"),
+            ("4", "TouchPPP Soft K56"),
+        ],
+        s_registers: &[(0, 0), (1, 0), (2, 43), (3, 13), (4, 10), (5, 8), (6, 2), (7, 50), (8, 2), (10, 14), (51, 0)],
+        carrier_speed: 50000,
+        force_56k: None,
+        connect_suffix: "",
+    },
+    // For fun.
+    ModemPreset {
+        name: "usr-courier",
+        identity: &[
+            ("", "5601"),
+            ("0", "5601"),
+            ("1", "OK"),
+            ("2", ""),
+            ("3", "U.S. Robotics Courier V.Everything Rev. 5.1"),
+            ("4", "USRobotics Courier V.Everything Settings..."),
+        ],
+        s_registers: &[(0, 0), (1, 0), (2, 43), (3, 13), (4, 10), (5, 8), (6, 2), (7, 60), (8, 2), (10, 7)],
+        carrier_speed: at::DEFAULT_CARRIER_SPEED,
+        force_56k: None,
+        connect_suffix: "/ARQ",
+    },
+];
+
+/// The preset called `name`, or what there is to pick from.
+pub fn find(name: &str) -> Result<&'static ModemPreset, String> {
+    PRESETS.iter().find(|preset| preset.name.eq_ignore_ascii_case(name)).ok_or_else(|| format!("use {}", names()))
+}
+
+/// Every preset's name, for messages.
+pub fn names() -> String {
+    PRESETS.iter().map(|preset| preset.name).collect::<Vec<_>>().join(", ")
+}
+
+impl ModemPreset {
+    /// What ATIn answers ahead of OK, or None if `at_string` isn't an ATI at all. Some(None) is an ATI this modem
+    /// doesn't know.
+    pub fn identify(&self, at_string: &str) -> Option<Option<&'static str>> {
+        match &at::tokenize(at_string)[..] {
+            [token] if token.name == "I" => Some(self.identity.iter().find(|(n, _)| *n == token.argument).map(|(_, text)| *text)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_preset_can_be_found_and_has_the_basics() {
+        for preset in PRESETS {
+            assert_eq!(find(&preset.name.to_uppercase()), Ok(preset));
+            assert!(preset.identify("ATI0\r").is_some_and(|text| text.is_some()), "{}", preset.name);
+            assert!(at::result_code(&format!("CARRIER {}", preset.carrier_speed)).is_some(), "{}", preset.name);
+        }
+
+        assert_eq!(PRESETS.iter().filter(|preset| preset.name == "webtv-k56").count(), 1);
+        assert!(find("hayes-smartmodem").is_err_and(|e| e.contains("rockwell-v34")));
+    }
+}
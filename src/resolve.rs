@@ -0,0 +1,223 @@
+// Where host names for remote backends get looked up: --resolve's overrides first, then the system's resolver, or
+// the name server --dns points at instead. Air-gapped retro LANs get by with the overrides alone.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::TokioResolver;
+use tracing::debug;
+
+use crate::address::RemoteAddr;
+
+const DNS_PORT: u16 = 53;
+
+#[derive(Default)]
+pub struct NameResolver {
+    // Lowercased names, from --resolve.
+    overrides: BTreeMap<String, IpAddr>,
+    dns: Option<(SocketAddr, TokioResolver)>,
+}
+
+impl NameResolver {
+    /// `overrides` are --resolve's NAME=IP, and `dns` is --dns's SERVER[:PORT], if there is one.
+    pub fn new(overrides: &[String], dns: Option<&str>) -> Result<NameResolver, String> {
+        let overrides = overrides.iter().map(|entry| parse_override(entry).map_err(|e| format!("bad --resolve '{entry}': {e}"))).collect::<Result<_, _>>()?;
+
+        let dns = match dns {
+            Some(server) => {
+                let server = parse_dns(server).map_err(|e| format!("bad --dns '{server}': {e}"))?;
+
+                let config = ResolverConfig::from_parts(None, Vec::new(), NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true));
+                let mut builder = TokioResolver::builder_with_config(config, TokioConnectionProvider::default());
+                // Both, so a dual-stack server gets raced.
+                builder.options_mut().ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+
+                Some((server, builder.build()))
+            },
+            None => None,
+        };
+
+        Ok(NameResolver { overrides, dns })
+    }
+
+    /// --resolve's overrides, as NAME=IP.
+    pub fn overrides(&self) -> Vec<String> {
+        self.overrides.iter().map(|(name, ip)| format!("{name}={ip}")).collect()
+    }
+
+    /// The name server from --dns.
+    pub fn dns_server(&self) -> Option<SocketAddr> {
+        self.dns.as_ref().map(|(server, _)| *server)
+    }
+
+    // What `host` is without asking anyone, and where that came from.
+    fn known(&self, host: &str) -> Option<(IpAddr, &'static str)> {
+        if let Some(ip) = self.overrides.get(&host.to_ascii_lowercase()) {
+            return Some((*ip, "override"));
+        }
+
+        host.parse().ok().map(|ip| (ip, "static"))
+    }
+
+    /// Every address `server` has.
+    pub async fn lookup(&self, server: &RemoteAddr) -> io::Result<Vec<SocketAddr>> {
+        if let Some((ip, source)) = self.known(&server.host) {
+            debug!(target: "touchppp::backend", "Resolved {server} to {ip} from {source}");
+
+            return Ok(vec![SocketAddr::new(ip, server.port)]);
+        }
+
+        let (addresses, source): (Vec<SocketAddr>, _) = match &self.dns {
+            Some((name_server, resolver)) => {
+                let found = resolver.lookup_ip(server.host.as_str()).await.map_err(io::Error::other)?;
+
+                (found.iter().map(|ip| SocketAddr::new(ip, server.port)).collect(), format!("dns {name_server}"))
+            },
+            None => (tokio::net::lookup_host(server.target()).await?.collect(), "dns".to_string()),
+        };
+
+        if addresses.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", server.host)));
+        }
+
+        debug!(target: "touchppp::backend", "Resolved {server} to {} from {source}", addresses.iter().map(|address| address.ip().to_string()).collect::<Vec<_>>().join(", "));
+
+        Ok(addresses)
+    }
+
+    /// [`lookup`](Self::lookup) for somewhere without a runtime to wait in, like --check.
+    pub fn lookup_blocking(&self, server: &RemoteAddr) -> io::Result<Vec<SocketAddr>> {
+        if let Some((ip, _)) = self.known(&server.host) {
+            return Ok(vec![SocketAddr::new(ip, server.port)]);
+        }
+
+        if self.dns.is_none() {
+            return Ok(server.target().to_socket_addrs()?.collect());
+        }
+
+        // On a thread of its own, since this might be inside a runtime already.
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+
+                runtime.block_on(self.lookup(server))
+            }).join().unwrap_or_else(|_| Err(io::Error::other("lookup panicked")))
+        })
+    }
+}
+
+// NAME=IP.
+pub fn parse_override(entry: &str) -> Result<(String, IpAddr), String> {
+    let (name, ip) = entry.split_once('=').ok_or("it needs to be NAME=IP")?;
+    let name = name.trim();
+
+    if name.is_empty() || name.parse::<IpAddr>().is_ok() {
+        return Err("it needs a host name before the =".to_string());
+    }
+
+    let ip = ip.trim().trim_start_matches('[').trim_end_matches(']');
+    let ip = ip.parse().map_err(|_| format!("'{ip}' isn't an IP address"))?;
+
+    Ok((name.to_ascii_lowercase(), ip))
+}
+
+// An IP address, with a port if it isn't 53.
+pub fn parse_dns(server: &str) -> Result<SocketAddr, String> {
+    if let Ok(server) = server.parse::<SocketAddr>() {
+        return Ok(server);
+    }
+
+    let ip = server.trim_start_matches('[').trim_end_matches(']');
+
+    ip.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DNS_PORT)).map_err(|_| "it needs to be an IP address, with :PORT if it isn't 53".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UdpSocket;
+
+    fn remote(host: &str) -> RemoteAddr {
+        RemoteAddr { host: host.to_string(), port: 2323 }
+    }
+
+    // A name server that says every name is `answer`, for A queries; anything else has no answers.
+    async fn fake_name_server(answer: [u8; 4]) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut query = [0; 512];
+
+            loop {
+                let Ok((read, asker)) = socket.recv_from(&mut query).await else {
+                    return;
+                };
+                let query = &query[..read];
+
+                // The question's name ends at the first empty label, then comes its type and class.
+                let mut end = 12;
+                while query[end] != 0 {
+                    end += query[end] as usize + 1;
+                }
+                let question = &query[12..end + 5];
+                let is_a = question[question.len() - 4..question.len() - 2] == [0, 1];
+
+                let mut reply = Vec::new();
+                reply.extend(&query[..2]);
+                reply.extend([0x81, 0x80, 0, 1, 0, is_a as u8, 0, 0, 0, 0]);
+                reply.extend(question);
+                if is_a {
+                    reply.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+                    reply.extend(answer);
+                }
+
+                let _ = socket.send_to(&reply, asker).await;
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn overrides_come_before_anything_else() {
+        let resolver = NameResolver::new(&["WTV-PPP.lan=10.0.0.5".to_string(), "v6.lan=[::1]".to_string()], Some("127.0.0.1:1")).unwrap();
+
+        assert_eq!(resolver.lookup(&remote("wtv-ppp.LAN")).await.unwrap(), ["10.0.0.5:2323".parse().unwrap()]);
+        assert_eq!(resolver.lookup(&remote("v6.lan")).await.unwrap(), ["[::1]:2323".parse().unwrap()]);
+        assert_eq!(resolver.lookup(&remote("192.168.1.1")).await.unwrap(), ["192.168.1.1:2323".parse().unwrap()]);
+        assert_eq!(resolver.lookup_blocking(&remote("wtv-ppp.lan")).unwrap(), ["10.0.0.5:2323".parse().unwrap()]);
+        assert_eq!(resolver.overrides(), ["v6.lan=::1", "wtv-ppp.lan=10.0.0.5"]);
+    }
+
+    // With a thread to spare for the name server while lookup_blocking waits.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dns_asks_the_name_server_it_was_given() {
+        let name_server = fake_name_server([10, 1, 2, 3]).await;
+        let resolver = NameResolver::new(&[], Some(&name_server.to_string())).unwrap();
+
+        assert_eq!(resolver.dns_server(), Some(name_server));
+        assert_eq!(resolver.lookup(&remote("wtv-ppp.lan")).await.unwrap(), ["10.1.2.3:2323".parse().unwrap()]);
+        assert_eq!(resolver.lookup_blocking(&remote("another.lan")).unwrap(), ["10.1.2.3:2323".parse().unwrap()]);
+    }
+
+    #[test]
+    fn bad_overrides_and_name_servers_are_caught() {
+        for (overrides, dns, problem) in [
+            (&["wtv-ppp.lan"][..], None, "bad --resolve 'wtv-ppp.lan': it needs to be NAME=IP"),
+            (&["wtv-ppp.lan=ppp.lan"], None, "'ppp.lan' isn't an IP address"),
+            (&["=10.0.0.5"], None, "it needs a host name"),
+            (&[], Some("ns.lan"), "bad --dns 'ns.lan'"),
+        ] {
+            let overrides: Vec<String> = overrides.iter().map(|entry| entry.to_string()).collect();
+            let e = NameResolver::new(&overrides, dns).err().unwrap();
+
+            assert!(e.contains(problem), "{e}");
+        }
+
+        assert_eq!(parse_dns("10.0.0.1").unwrap(), "10.0.0.1:53".parse().unwrap());
+        assert_eq!(parse_dns("[::1]:5353").unwrap(), "[::1]:5353".parse().unwrap());
+    }
+}
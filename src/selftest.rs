@@ -0,0 +1,152 @@
+// `touchppp test`: a whole call in-process, from the WebTV init string to data mode, against the built-in echo.
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+
+use crate::config::{Backend, BackendKind, Config};
+use crate::session::Session;
+use crate::stats::Stats;
+use crate::StartError;
+
+// Generous, since nothing here should take more than a few milliseconds.
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How much data to push through once the call is up. Big enough to need more than one read either way.
+const PATTERN_SIZE: usize = 0x10000;
+
+pub(crate) struct Step {
+    pub(crate) name: &'static str,
+    pub(crate) send: &'static str,
+    pub(crate) expect: &'static [u8],
+}
+
+// What a WebTV says while dialing, with what the modem should answer.
+pub(crate) const CALL: [Step; 4] = [
+    Step { name: "init string", send: "ATE0Q0V0&C1&D2S0=0\r", expect: b"OK\r\n" },
+    Step { name: "dial setup", send: "ATS7=60L3\r", expect: b"\r\n0\r\n" },
+    Step { name: "dial", send: "ATDT18006138199\r", expect: b"0\r\n" },
+    // CARRIER 33600, COMPRESSION V.42bis, CONNECT 115200
+    Step { name: "data mode", send: "ATD\r", expect: b"79\r\n67\r\n19\r\n" },
+];
+
+fn fail(step: &str, details: String) -> StartError {
+    println!("FAIL  {step}: {details}");
+
+    StartError::Runtime(format!("self-test failed at {step}").into())
+}
+
+// Reads exactly as much as expected, keeping whatever showed up if it was less or it never came.
+pub(crate) async fn read_expected(modem: &mut TcpStream, expected_len: usize) -> (Vec<u8>, Option<String>) {
+    let mut received = Vec::new();
+    let mut buf = [0; 0x400];
+
+    while received.len() < expected_len {
+        let wanted = (expected_len - received.len()).min(buf.len());
+
+        match tokio::time::timeout(STEP_TIMEOUT, modem.read(&mut buf[0..wanted])).await {
+            Ok(Ok(0)) => return (received, Some("the modem hung up".to_string())),
+            Ok(Ok(n)) => received.extend_from_slice(&buf[0..n]),
+            Ok(Err(e)) => return (received, Some(e.to_string())),
+            Err(_) => return (received, Some(format!("nothing more after {} seconds", STEP_TIMEOUT.as_secs()))),
+        }
+    }
+
+    (received, None)
+}
+
+async fn push_pattern(modem: &mut TcpStream) -> Result<(), String> {
+    let pattern: Vec<u8> = (0..PATTERN_SIZE).map(|i| (i % 251) as u8).collect();
+
+    let (mut reader, mut writer) = modem.split();
+
+    // Write and read at the same time so neither side's buffers have to hold the whole thing.
+    let (written, echoed) = tokio::join!(writer.write_all(&pattern), async {
+        let mut echoed = Vec::with_capacity(PATTERN_SIZE);
+        let mut buf = [0; 0x1000];
+
+        while echoed.len() < PATTERN_SIZE {
+            match tokio::time::timeout(STEP_TIMEOUT, reader.read(&mut buf)).await {
+                Ok(Ok(0)) => break,
+                Ok(Ok(n)) => echoed.extend_from_slice(&buf[0..n]),
+                Ok(Err(_)) | Err(_) => break,
+            }
+        }
+
+        echoed
+    });
+
+    written.map_err(|e| format!("couldn't send: {e}"))?;
+
+    if echoed.len() != PATTERN_SIZE {
+        return Err(format!("sent {PATTERN_SIZE} bytes but only {} came back", echoed.len()));
+    }
+
+    match pattern.iter().zip(echoed.iter()).position(|(sent, got)| sent != got) {
+        Some(offset) => Err(format!("byte {offset} was sent as {:#04x} but came back as {:#04x}", pattern[offset], echoed[offset])),
+        None => Ok(()),
+    }
+}
+
+#[tokio::main]
+pub async fn run() -> Result<(), StartError> {
+    let config = Arc::new(Config::for_backend(Backend {
+        name: "echo".to_string(),
+        kind: BackendKind::Echo,
+    }));
+
+    let listener = TcpListener::bind(config.listen_address.target()).await?;
+    let listen_address = listener.local_addr()?;
+
+    println!("Self-test on {listen_address} with the built-in echo backend.");
+
+    let (_config_sender, config_receiver) = watch::channel(config);
+
+    let session = tokio::spawn(async move {
+        let (mame, mame_socket_address) = listener.accept().await?;
+
+        Session::new(&Stats::new(), 1, &mame_socket_address.to_string(), config_receiver).run(mame).await;
+
+        Ok::<(), std::io::Error>(())
+    });
+
+    let mut modem = TcpStream::connect(listen_address).await.map_err(|e| fail("connect", e.to_string()))?;
+    println!("PASS  connect");
+
+    for step in CALL.iter() {
+        modem.write_all(step.send.as_bytes()).await.map_err(|e| fail(step.name, format!("couldn't send: {e}")))?;
+
+        let (received, problem) = read_expected(&mut modem, step.expect.len()).await;
+
+        if received != step.expect {
+            let sent = step.send.escape_debug();
+            let expected = String::from_utf8_lossy(step.expect).escape_debug().to_string();
+            let received = String::from_utf8_lossy(&received).escape_debug().to_string();
+
+            return Err(fail(step.name, match problem {
+                Some(problem) => format!("sent '{sent}', expected '{expected}', got '{received}' then {problem}"),
+                None => format!("sent '{sent}', expected '{expected}', got '{received}'"),
+            }));
+        }
+
+        println!("PASS  {}", step.name);
+    }
+
+    push_pattern(&mut modem).await.map_err(|e| fail("data", e))?;
+    println!("PASS  data ({PATTERN_SIZE} bytes each way)");
+
+    drop(modem);
+
+    match tokio::time::timeout(STEP_TIMEOUT, session).await {
+        Ok(Ok(Ok(()))) => println!("PASS  hang up"),
+        Ok(Ok(Err(e))) => return Err(fail("hang up", e.to_string())),
+        Ok(Err(e)) => return Err(fail("hang up", format!("the session died: {e}"))),
+        Err(_) => return Err(fail("hang up", "the session was still going after we hung up".to_string())),
+    }
+
+    println!("All good! This build of TouchPPP works, so any trouble is between it and MAME or PPP.");
+
+    Ok(())
+}
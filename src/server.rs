@@ -0,0 +1,870 @@
+// Taking calls: binding everything the config asks for, then answering MAME on it until we're asked to stop.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument};
+
+use crate::address::{DEFAULT_IP, DEFAULT_REMOTE_PORT};
+use crate::admin;
+use crate::backend;
+use crate::check;
+use crate::config::{Config, LogFormat, SettingSource};
+use crate::console;
+#[cfg(unix)]
+use crate::daemon;
+use crate::cdr::CdrLog;
+use crate::diallog::DialLog;
+use crate::dialstate::DialState;
+use crate::flood::{AcceptLimiter, Admission};
+use crate::jsonlog;
+use crate::listener;
+use crate::logfile;
+use crate::mame;
+use crate::session::Session;
+use crate::stats;
+use crate::status;
+use crate::syslog;
+#[cfg(unix)]
+use crate::systemd;
+use crate::textlog;
+use crate::webhook;
+use crate::error::TouchPppError;
+use crate::StartError;
+
+/// Everything the config asks us to listen on, bound and ready to take calls.
+pub struct Server {
+    config: Arc<Config>,
+    listeners: Vec<std::net::TcpListener>,
+    admin_listener: Option<admin::AdminListener>,
+    status_listener: Option<std::net::TcpListener>,
+    // What's been learned about each number (failures for --delay-after, the server that answered), kept across
+    // every call (and run, with --state-file).
+    dial_state: Arc<DialState>,
+    // Where every dial gets written, with --dial-log. Opened once, so a reload can't point it somewhere else.
+    dial_log: Option<DialLog>,
+    // Where every session's CDR goes, with --cdr-file. Opened once too.
+    cdr_log: Option<CdrLog>,
+    // Stats shared with other servers, for a config file with [[modem]] sections. None keeps stats of our own.
+    stats: Option<Arc<stats::Stats>>,
+    // Where SIGHUP reloads the config from. None keeps the config we started with.
+    reload_params: Option<clap::ArgMatches>,
+    // Whether to take admin commands on stdin too. Only the binary does, stdin isn't an embedder's to take.
+    has_console: bool,
+}
+
+#[cfg(windows)]
+static STOP: std::sync::OnceLock<tokio::sync::Notify> = std::sync::OnceLock::new();
+
+#[cfg(windows)]
+fn stop() -> &'static tokio::sync::Notify {
+    STOP.get_or_init(tokio::sync::Notify::new)
+}
+
+/// Stops a running server the way Ctrl-C would. For when Windows asks the service to stop.
+#[cfg(windows)]
+pub fn request_stop() {
+    stop().notify_one();
+}
+
+/// The targets with a name of their own, for --log-filter. Everything else logs under touchppp::MODULE.
+pub const LOG_TARGETS: [&str; 10] = [
+    "touchppp::at",
+    "touchppp::backend",
+    "touchppp::bridge",
+    "touchppp::config",
+    "touchppp::mame",
+    "touchppp::modem",
+    "touchppp::nat",
+    "touchppp::ppp",
+    "touchppp::socket",
+    "touchppp::tun",
+];
+
+// So the filter init_logging set up can be swapped out while we're running, along with the level -q or -v
+// asked for.
+static LOG_FILTER: std::sync::OnceLock<(tracing_subscriber::reload::Handle<tracing_subscriber::EnvFilter, tracing_subscriber::Registry>, &'static str)> = std::sync::OnceLock::new();
+
+/// `filter` in RUST_LOG's syntax (like touchppp::at=debug,touchppp::bridge=warn) on top of `level`, which only
+/// counts for targets `filter` leaves alone. A plain level in `filter` replaces it.
+pub fn log_filter(level: &str, filter: &str) -> Result<tracing_subscriber::EnvFilter, String> {
+    use tracing_subscriber::filter::LevelFilter;
+
+    let has_level = filter.split(',').any(|directive| directive.trim().parse::<LevelFilter>().is_ok());
+    let directives = match has_level {
+        true => filter.to_string(),
+        false => format!("{level},{filter}"),
+    };
+
+    tracing_subscriber::EnvFilter::try_new(directives).map_err(|e| format!("'{filter}' isn't a log filter: {e}"))
+}
+
+/// Lets through what `filter` asks for from now on, the same way --log-filter does. Only for logging the
+/// touchppp binary set up, not an embedder's.
+pub fn set_log_filter(filter: &str) -> Result<(), String> {
+    let (handle, level) = LOG_FILTER.get().ok_or("logging isn't ours to change")?;
+
+    handle.reload(log_filter(level, filter)?).map_err(|e| e.to_string())
+}
+
+fn init_logging(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::IsTerminal;
+    use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::{EnvFilter, Layer};
+
+    let level = match (config.is_silent, config.verbosity) {
+        (true, _) => "error",
+        (false, 0) => "info",
+        (false, 1) => "debug",
+        (false, _) => "trace",
+    };
+
+    // --log-filter goes on top of -q/-v. Without it RUST_LOG wins if it's there (and -q doesn't say otherwise).
+    let filter = match &config.log_filter {
+        Some(log_filter) => self::log_filter(level, log_filter)?,
+        None if config.is_silent => EnvFilter::new(level),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level)),
+    };
+
+    // Problems go to stderr, everything else to stdout.
+    let stdio_writer = std::io::stderr.with_max_level(tracing::Level::WARN)
+        .or_else(std::io::stdout);
+
+    let no_color = std::env::var("NO_COLOR").ok();
+    let mut is_colored = textlog::is_colored(config.color, std::io::stdout().is_terminal(), no_color.as_deref());
+
+    let writer = match &config.log_file {
+        Some(log_file_path) => {
+            let log_file = logfile::LogFile::open(log_file_path, config.log_max_size, config.log_keep)
+                .map_err(|e| format!("can't open log file '{log_file_path}': {e}"))?;
+
+            // Color codes don't belong in a file.
+            is_colored = false;
+
+            if config.log_stdout {
+                BoxMakeWriter::new(log_file.and(stdio_writer))
+            } else {
+                BoxMakeWriter::new(log_file)
+            }
+        },
+        None => BoxMakeWriter::new(stdio_writer),
+    };
+
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .with_writer(writer);
+
+    let fmt_layer = match config.log_format {
+        LogFormat::Text => fmt_layer.with_ansi(false).event_format(textlog::TextFormat { is_colored }).boxed(),
+        LogFormat::Json => fmt_layer.with_ansi(false).event_format(jsonlog::JsonFormat).boxed(),
+    };
+
+    let syslog_layer = config.log_syslog.clone().map(|log_syslog| syslog::SyslogLayer::new(log_syslog, config.syslog_facility));
+
+    let (filter, filter_handle) = tracing_subscriber::reload::Layer::new(filter);
+    let _ = LOG_FILTER.set((filter_handle, level));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(jsonlog::SpanFieldsLayer)
+        .with(fmt_layer)
+        .with(syslog_layer)
+        .init();
+
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(params: clap::ArgMatches, config_sender: watch::Sender<Arc<Config>>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Can't listen for SIGHUP, config reloading is off: error={e}");
+            return;
+        }
+    };
+
+    let modem = config_sender.borrow().modem.clone();
+
+    while hangups.recv().await.is_some() {
+        info!("Got SIGHUP, reloading the config.");
+
+        let new_config = match Config::load_all(&params).map(|configs| configs.into_iter().find(|config| config.modem == modem)) {
+            Ok(Some(r)) => r,
+            Ok(None) => {
+                error!("Couldn't reload the config, sticking with the old one: error=[[modem]] '{}' isn't in it anymore", modem.as_deref().unwrap_or_default());
+                continue;
+            },
+            Err(e) => {
+                error!("Couldn't reload the config, sticking with the old one: error={e}");
+                continue;
+            }
+        };
+
+        let old_config = config_sender.borrow().clone();
+
+        if new_config.listen_address != old_config.listen_address {
+            warn!("listen changed from {} to {} but that requires restart.", old_config.listen_address, new_config.listen_address);
+        }
+
+        if new_config.health_check {
+            backend::start_health_checks(&new_config).await;
+        }
+
+        config_sender.send_replace(Arc::new(new_config));
+
+        info!("Config reloaded. New dials will use it; sessions already touching PPP keep the old one.");
+    }
+}
+
+#[cfg(unix)]
+async fn drain_on_user2(stats: Arc<stats::Stats>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut user2s = match signal(SignalKind::user_defined2()) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Can't listen for SIGUSR2, draining is only up to the admin interface: error={e}");
+            return;
+        }
+    };
+
+    if user2s.recv().await.is_some() {
+        info!("Got SIGUSR2, draining.");
+        stats.drain();
+    }
+}
+
+#[cfg(unix)]
+async fn dump_stats_on_user1(stats: Arc<stats::Stats>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut user1s = match signal(SignalKind::user_defined1()) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Can't listen for SIGUSR1, stats dumps are off: error={e}");
+            return;
+        }
+    };
+
+    while user1s.recv().await.is_some() {
+        info!(event = "stats", "{}", stats.snapshot());
+    }
+}
+
+// Resolves once SIGTERM or Ctrl-C (or Windows stopping the service) asks us to stop. The unix handlers are
+// registered right away rather than on first poll, so a SIGTERM that comes early doesn't just kill us.
+fn shutdown_requested() -> impl Future<Output = ()> {
+    #[cfg(unix)]
+    let signals = {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        signal(SignalKind::terminate()).and_then(|terminate| Ok((terminate, signal(SignalKind::interrupt())?)))
+    };
+
+    async move {
+        #[cfg(unix)]
+        match signals {
+            Ok((mut terminate, mut interrupt)) => {
+                tokio::select! {
+                    _ = terminate.recv() => {},
+                    _ = interrupt.recv() => {},
+                }
+            },
+            Err(e) => {
+                warn!("Can't listen for SIGTERM: error={e}");
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        }
+
+        #[cfg(windows)]
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = stop().notified() => {},
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+impl Server {
+    /// Checks the config for problems and binds its listeners (or takes systemd's), so anything that can go
+    /// wrong at startup already has.
+    pub async fn bind(config: Config) -> Result<Server, StartError> {
+        Server::bind_now(config)
+    }
+
+    /// Server::bind for callers that aren't in a runtime yet.
+    pub fn from_config(config: Config) -> Result<Server, StartError> {
+        Server::bind_now(config)
+    }
+
+    // Blocking, so it can run before --daemon forks and there's no runtime yet.
+    fn bind_now(config: Config) -> Result<Server, StartError> {
+        if let Some(problem) = check::problems(&config, false).into_iter().next() {
+            return Err(StartError::Usage(problem));
+        }
+
+        // With socket activation systemd already holds the port(s) and -l doesn't matter.
+        #[cfg(unix)]
+        let socket_activated = systemd::listeners().map_err(StartError::Usage)?;
+        #[cfg(not(unix))]
+        let socket_activated = None;
+
+        let listeners = match socket_activated {
+            Some(listeners) => listeners,
+            // The pipe gets created once we're in the runtime.
+            None if config.listen_pipe.is_some() => {
+                if !cfg!(windows) {
+                    return Err(StartError::Usage("-l pipe: only works on Windows".to_string()));
+                }
+
+                vec![]
+            },
+            None => {
+                let listener = std::net::TcpListener::bind(config.listen_address.target())
+                    .map_err(|source| TouchPppError::Listen { address: config.listen_address.to_string(), source })?;
+                listener.set_nonblocking(true)?;
+
+                vec![listener]
+            },
+        };
+
+        let admin_listener = match &config.admin {
+            Some(admin) => Some(admin::bind(admin).map_err(StartError::Usage)?),
+            None => None,
+        };
+
+        let status_listener = match &config.status_http {
+            Some(status_http) => {
+                let listener = std::net::TcpListener::bind(status_http.target())
+                    .map_err(|e| StartError::Usage(format!("can't serve the status page on {status_http}: {e}")))?;
+                listener.set_nonblocking(true)?;
+
+                Some(listener)
+            },
+            None => None,
+        };
+
+        let dial_state = DialState::load(config.state_file.as_deref()).map_err(StartError::Usage)?;
+
+        let dial_log = match &config.dial_log {
+            Some(file) => Some(DialLog::open(file, config.dial_log_sync).map_err(StartError::Usage)?),
+            None => None,
+        };
+
+        let cdr_log = match &config.cdr_file {
+            Some(path) => Some(CdrLog::open(path).map_err(StartError::Usage)?),
+            None => None,
+        };
+
+        Ok(Server {
+            config: Arc::new(config),
+            listeners,
+            admin_listener,
+            status_listener,
+            dial_state: Arc::new(dial_state),
+            dial_log,
+            cdr_log,
+            stats: None,
+            reload_params: None,
+            has_console: false,
+        })
+    }
+
+    /// Counts calls in `stats` alongside other servers' instead of keeping stats of its own, for several modems in
+    /// one process. Whoever made `stats` reports them; this server leaves that to them.
+    pub fn with_stats(mut self, stats: &Arc<stats::Stats>) -> Server {
+        self.stats = Some(stats.clone());
+
+        self
+    }
+
+    /// Where MAME can reach us over TCP, which is the port we actually got when -l asked for port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().first().copied()
+    }
+
+    /// Every TCP address MAME can reach us on, which is more than one with socket activation.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().filter_map(|listener| listener.local_addr().ok()).collect()
+    }
+
+    /// Takes calls until MAME exits (with --launch-mame) or we're asked to stop by SIGTERM or Ctrl-C, then hangs
+    /// up on everyone. Asking again stops right away.
+    pub async fn run(self) -> Result<(), StartError> {
+        self.serve(shutdown_requested(), shutdown_requested).await
+    }
+
+    /// Takes calls until `stop` resolves (or MAME exits), then hangs up on everyone the same way SIGTERM would.
+    /// For embedding, where signals are someone else's business.
+    pub async fn run_until(self, stop: impl Future<Output = ()>) -> Result<(), StartError> {
+        self.serve(stop, futures::future::pending).await
+    }
+
+    async fn serve<S, F>(self, stop: impl Future<Output = ()>, stop_now: S) -> Result<(), StartError>
+    where
+        S: FnOnce() -> F,
+        F: Future<Output = ()>,
+    {
+        let config = self.config;
+
+        for (long_name, source) in config.sources.iter() {
+            debug!(target: "touchppp::config", "Setting {long_name} came from {source}.");
+        }
+
+        for (alias, means) in config.tcpser_aliases.iter() {
+            info!("Taking tcpser's {alias} as {means}.");
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        if let Some(device) = &config.backend_defaults.bind_device {
+            warn!("--bind-device only works on Linux, so remote PPP servers are reached however the routes say instead of over {device}.");
+        }
+
+        if config.health_check {
+            backend::start_health_checks(&config).await;
+        }
+
+        let listeners = self.listeners.into_iter().map(TcpListener::from_std).collect::<tokio::io::Result<Vec<TcpListener>>>()?;
+        let socket_options = config.backend_defaults.socket_options();
+        // Only a pipe on Windows changes them after this.
+        #[cfg_attr(not(windows), allow(unused_mut))]
+        let mut listeners = listener::Listeners::new(listeners).with_options(socket_options);
+
+        #[cfg(windows)]
+        if let Some(pipe_name) = &config.listen_pipe {
+            if socket_options.sets_buffers() {
+                warn!("--so-rcvbuf and --so-sndbuf don't apply to calls over {pipe_name}, only TCP ones.");
+            }
+
+            listeners = listeners.with_pipe(pipe_name)?;
+        }
+
+        let (config_sender, config_receiver) = watch::channel(config.clone());
+
+        match self.reload_params {
+            #[cfg(unix)]
+            Some(params) => {
+                tokio::spawn(reload_on_hangup(params, config_sender));
+            },
+            _ => drop(config_sender),
+        }
+
+        let described_listeners = listeners.describe()?;
+
+        for described in described_listeners.iter() {
+            match &config.modem {
+                Some(modem) => info!("Listening on {described} for modem {modem}."),
+                None => info!("Listening on {described}."),
+            }
+        }
+
+        // Otherwise a mistyped -e only shows up when the first dial can't reach anything.
+        if config.sources.get("connect") == Some(&SettingSource::Default) {
+            info!("No -c, -e or default_backend given, so calls go to {DEFAULT_IP}:{DEFAULT_REMOTE_PORT} unless the phone book says otherwise.");
+        }
+
+        // The bound address rather than -l, so port 0 gives MAME the port we actually got.
+        let bitbanger_target = match (&config.listen_pipe, listeners.tcp().first()) {
+            (Some(pipe_name), _) => pipe_name.clone(),
+            (None, Some(listener)) => mame::socket_target(listener.local_addr()?),
+            (None, None) => described_listeners.first().cloned().unwrap_or_default(),
+        };
+
+        let mame_exited = async {
+            match &config.launch_mame {
+                Some(command) => mame::supervise(command.clone(), config.mame_slot, bitbanger_target.clone(), config.mame_restart).await,
+                None => {
+                    let mame_args = mame::bitbanger_args(config.mame_slot, &bitbanger_target).join(" ");
+
+                    info!("You need to add '{mame_args}' to the MAME command line.");
+
+                    futures::future::pending().await
+                },
+            }
+        };
+        tokio::pin!(mame_exited);
+
+        tokio::pin!(stop);
+
+        let is_sharing_stats = self.stats.is_some();
+        let stats = match self.stats {
+            Some(stats) => stats,
+            None => stats::Stats::with_outputs(webhook::start(&config), self.dial_log, self.cdr_log),
+        };
+
+        #[cfg(unix)]
+        if !is_sharing_stats {
+            tokio::spawn(dump_stats_on_user1(stats.clone()));
+            tokio::spawn(drain_on_user2(stats.clone()));
+        }
+
+        if let Some(limit) = config.exit_after_sessions {
+            stats.limit_sessions(limit);
+        }
+
+        // Once for every modem sharing the stats.
+        if config.enable_inject && !stats.is_inject_enabled() {
+            warn!("--enable-inject is on, so anyone who can send admin commands can put any bytes into any call.");
+            stats.enable_inject();
+        }
+
+        if let (Some(admin_listener), Some(admin)) = (self.admin_listener, &config.admin) {
+            info!("Taking admin commands on {admin}.");
+
+            tokio::spawn(admin::serve(admin_listener, stats.clone()));
+        }
+
+        // Typing quit stops us the same way `stop` would.
+        let console_quit = CancellationToken::new();
+        if self.has_console {
+            info!("Taking admin commands on the console too; try status.");
+
+            tokio::spawn(console::run(stats.clone(), console_quit.clone()));
+        }
+
+        if let Some(status_listener) = self.status_listener {
+            info!("Serving the status page on http://{}/", status_listener.local_addr()?);
+
+            tokio::spawn(status::serve(status_listener, status::StatusPage {
+                stats: stats.clone(),
+                config: config_receiver.clone(),
+                listeners: described_listeners.clone(),
+            }));
+        }
+
+        // So the status page can tell the loop below is still going around.
+        let mut heartbeat = tokio::time::interval(Duration::from_secs(1));
+
+        // Turns away floods before they get as far as a session. Sticks around across reloads like `stats`.
+        let mut limiter = AcceptLimiter::new();
+
+        // Every session hangs off this, and stays in `sessions` until it's over, so stopping can hang up on all of
+        // them and wait for them to finish.
+        let shutdown = CancellationToken::new();
+        let mut sessions = JoinSet::new();
+
+        // Once we're draining, calls that are up on this modem get until the --drain-timeout deadline (if there is
+        // one) to end. Sessions without a call up are hung up on along with everyone else once they have.
+        let draining = stats.draining();
+        let modem = config.modem.as_deref().unwrap_or(stats::DEFAULT_MODEM);
+        let mut is_draining = false;
+        let mut drain_deadline = None;
+
+        // Once --exit-after-sessions has handed out its last session id (on any modem), the listeners go, and we
+        // stop once this modem's sessions are over.
+        let out_of_sessions = stats.out_of_sessions();
+        let mut listeners = Some(listeners);
+
+        #[cfg(unix)]
+        systemd::notify("READY=1");
+
+        loop {
+            if is_draining && stats.calls_up(Some(modem)) == 0 {
+                info!("Every call's ended, so we're done draining.");
+                break;
+            }
+
+            if listeners.is_none() && sessions.is_empty() {
+                info!("That's all {} sessions --exit-after-sessions wanted, so we're done.", config.exit_after_sessions.unwrap_or_default());
+                break;
+            }
+
+            let (mame, mame_socket_address) = tokio::select! {
+                accepted = accept(&mut listeners) => accepted?,
+                _ = heartbeat.tick() => {
+                    stats.heartbeat();
+                    continue;
+                },
+                Some(_) = sessions.join_next() => continue,
+                _ = draining.cancelled(), if !is_draining => {
+                    let calls = stats.calls_up(Some(modem));
+
+                    match config.drain_timeout {
+                        Some(timeout) => info!("Draining: taking no new calls, and giving the {calls} that are up {}s to end.", timeout.as_secs()),
+                        None => info!("Draining: taking no new calls, and waiting for the {calls} that are up to end."),
+                    }
+
+                    is_draining = true;
+                    drain_deadline = config.drain_timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+                    continue;
+                },
+                _ = until(drain_deadline) => {
+                    info!("--drain-timeout ran out with {} calls still up, hanging up on them.", stats.calls_up(Some(modem)));
+                    break;
+                },
+                _ = &mut mame_exited => {
+                    info!("MAME is gone, so we're done.");
+                    break;
+                },
+                _ = &mut stop => {
+                    info!("Asked to stop, so we're done.");
+                    break;
+                },
+                _ = console_quit.cancelled() => break,
+                _ = out_of_sessions.cancelled(), if listeners.is_some() => {
+                    info!("No more connections, now that --exit-after-sessions has its {} sessions.", config.exit_after_sessions.unwrap_or_default());
+                    listeners = None;
+                    continue;
+                },
+            };
+
+            let (accept_rate, max_pending) = {
+                let config = config_receiver.borrow();
+                (config.accept_rate, config.max_pending)
+            };
+
+            // Named pipes don't have a source address, so they only count against --max-pending.
+            let source = mame_socket_address.parse::<SocketAddr>().ok().map(|address| address.ip());
+
+            let pending = match limiter.admit(source, accept_rate, max_pending, Instant::now()) {
+                Admission::Admitted(pending) => pending,
+                Admission::Refused { why, is_worth_logging } => {
+                    stats.record_refused_accept();
+
+                    if is_worth_logging {
+                        warn!("Turning away connections from MAME @ {mame_socket_address} because {why}. Not saying so again for a minute.");
+                    }
+
+                    continue;
+                },
+            };
+
+            // Only one of the connections that got here at the same time gets the last session id.
+            let Some(session_id) = stats.next_session_id() else {
+                stats.record_refused_accept();
+                listeners = None;
+                continue;
+            };
+
+            // Everything logged from this connection's task (copy loops included) gets tagged with the session.
+            let session_span = tracing::info_span!("session", session = session_id, client_addr = %mame_socket_address);
+
+            let session = Session::new(&stats, session_id, &mame_socket_address, config_receiver.clone()).with_shutdown(&shutdown).with_dial_state(&self.dial_state).with_pending(pending);
+
+            sessions.spawn(session.run(mame).instrument(session_span));
+        }
+
+        #[cfg(unix)]
+        systemd::notify("STOPPING=1");
+
+        // No new calls while we hang up on the ones we've got.
+        drop(listeners);
+
+        let hung_up = hang_up_on_everyone(sessions, &shutdown, config.shutdown_timeout, stop_now).await;
+
+        if !is_sharing_stats {
+            info!(event = "stats", "{}", stats.snapshot());
+            stats.finish_cdrs();
+        }
+
+        match stats.failed_sessions() {
+            failed if hung_up.is_ok() && failed > 0 && config.exit_after_sessions.is_some() => Err(StartError::SessionsFailed(failed)),
+            _ => hung_up,
+        }
+    }
+}
+
+// The next connection, or never once the listeners are gone.
+async fn accept(listeners: &mut Option<listener::Listeners>) -> tokio::io::Result<(Box<dyn listener::MameStream>, String)> {
+    match listeners {
+        Some(listeners) => listeners.accept().await,
+        None => futures::future::pending().await,
+    }
+}
+
+// Waits for `deadline`, or forever without one.
+async fn until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
+
+// Hangs up on every session (NO CARRIER for the ones with a call up) and waits for them to finish, cutting off
+// whoever's left when `timeout` runs out or `stop_now` resolves.
+async fn hang_up_on_everyone<F: Future<Output = ()>>(mut sessions: JoinSet<()>, shutdown: &CancellationToken, timeout: Duration, stop_now: impl FnOnce() -> F) -> Result<(), StartError> {
+    if !sessions.is_empty() {
+        info!("Hanging up on {} sessions.", sessions.len());
+    }
+
+    shutdown.cancel();
+
+    let cut_off = tokio::select! {
+        _ = async { while sessions.join_next().await.is_some() {} } => None,
+        _ = tokio::time::sleep(timeout) => Some(format!("{} sessions were still hanging up after {}s, cut them off", sessions.len(), timeout.as_secs())),
+        _ = stop_now() => Some(format!("asked to stop again, cut off {} sessions", sessions.len())),
+    };
+
+    let Some(cut_off) = cut_off else {
+        return Ok(());
+    };
+
+    // Dropping a session's task hangs up on its PPP the hard way.
+    sessions.shutdown().await;
+
+    Err(StartError::Runtime(cut_off.into()))
+}
+
+// Each [[modem]] in a server of its own, all sharing the first one's stats, which it reports. Once one stops, for
+// whatever reason, they all do.
+async fn run_together(mut servers: Vec<Server>) -> Result<(), StartError> {
+    let stats = stats::Stats::with_outputs(webhook::start(&servers[0].config), servers[0].dial_log.take(), servers[0].cdr_log.take());
+
+    #[cfg(unix)]
+    {
+        tokio::spawn(dump_stats_on_user1(stats.clone()));
+        tokio::spawn(drain_on_user2(stats.clone()));
+    }
+
+    let stop = CancellationToken::new();
+
+    let stop_on_signal = shutdown_requested();
+    let stopping = stop.clone();
+    tokio::spawn(async move {
+        stop_on_signal.await;
+        stopping.cancel();
+    });
+
+    let serving = servers.into_iter().map(|server| {
+        let server = server.with_stats(&stats);
+        let stats = stats.clone();
+        let stop = stop.clone();
+
+        async move {
+            let served = server.serve(stop.clone().cancelled_owned(), shutdown_requested).await;
+
+            // A drain (or running out of sessions) finishes with each modem's own calls, so one that's done doesn't
+            // hang up on the others'.
+            if !stats.is_draining() && !stats.is_out_of_sessions() {
+                stop.cancel();
+            }
+
+            served
+        }
+    });
+
+    let served = futures::future::join_all(serving).await;
+
+    info!(event = "stats", "{}", stats.snapshot());
+    stats.finish_cdrs();
+
+    served.into_iter().collect()
+}
+
+// Anything that can go wrong at startup happens here, before --daemon forks, so it still reaches the terminal.
+pub fn start(params: &clap::ArgMatches) -> Result<(), StartError> {
+    let mut configs = Config::load_all(params)?.into_iter();
+    let config = configs.next().expect("there's always a config");
+
+    let mut server = Server::bind_now(config)?;
+    server.reload_params = Some(params.clone());
+    server.has_console = console::is_wanted(&server.config);
+
+    // The other modems share the first one's admin socket, status page, dial log, CDR file and what's been learned
+    // about each number, so they don't get their own.
+    let mut others = Vec::new();
+    for mut config in configs {
+        config.admin = None;
+        config.status_http = None;
+        config.dial_log = None;
+        config.cdr_file = None;
+        config.state_file = None;
+
+        let mut other = Server::bind_now(config)?;
+        other.dial_state = server.dial_state.clone();
+        other.reload_params = Some(params.clone());
+
+        others.push(other);
+    }
+
+    let config = server.config.clone();
+
+    // Held until we're done so the pid file goes away on a clean exit.
+    #[cfg(unix)]
+    let _pid_file = match (&config.log_file, &config.pid_file) {
+        (Some(log_file), pid_file) if config.daemon => daemon::daemonize(pid_file.as_deref(), log_file)?,
+        (_, Some(pid_file)) => Some(daemon::write_pid_file(pid_file)?),
+        (_, None) => None,
+    };
+    #[cfg(not(unix))]
+    if config.daemon || config.pid_file.is_some() {
+        return Err(StartError::Usage("--daemon and --pid-file only work on unix".to_string()));
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(async {
+        init_logging(&config).map_err(StartError::Runtime)?;
+
+        match others.is_empty() {
+            true => server.run().await,
+            false => run_together(std::iter::once(server).chain(others).collect()).await,
+        }
+    });
+
+    // The console's read of stdin can't be called off, so there's no waiting for it to finish.
+    runtime.shutdown_background();
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    // Every event that got past the filter, as target and level.
+    struct Passed(Arc<Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> Layer<S> for Passed {
+        fn on_event(&self, event: &tracing::Event<'_>, _: Context<'_, S>) {
+            self.0.lock().unwrap().push(format!("{} {}", event.metadata().target(), event.metadata().level()));
+        }
+    }
+
+    fn passed(level: &str, filter: &str) -> Vec<String> {
+        let passed = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(log_filter(level, filter).unwrap()).with(Passed(passed.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            debug!(target: "touchppp::at", "ATDT18006138199");
+            tracing::trace!(target: "touchppp::at", "Not this far.");
+            info!(target: "touchppp::bridge", "Bridging.");
+            warn!(target: "touchppp::bridge", "Slow.");
+            info!(target: "touchppp::session", "Hello.");
+            debug!(target: "touchppp::session", "Not this one.");
+        });
+
+        let passed = passed.lock().unwrap().clone();
+        passed
+    }
+
+    #[test]
+    fn filters_go_on_top_of_the_level() {
+        assert_eq!(passed("info", "touchppp::at=debug,touchppp::bridge=warn"), [
+            "touchppp::at DEBUG",
+            "touchppp::bridge WARN",
+            "touchppp::session INFO",
+        ]);
+
+        // A level of its own replaces the one from -q/-v.
+        assert_eq!(passed("info", "warn,touchppp::at=debug"), ["touchppp::at DEBUG", "touchppp::bridge WARN"]);
+    }
+
+    #[test]
+    fn bad_filters_are_refused() {
+        assert!(log_filter("info", "touchppp::at=loud").unwrap_err().starts_with("'touchppp::at=loud' isn't a log filter: "));
+    }
+}
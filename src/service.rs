@@ -0,0 +1,261 @@
+// `touchppp service install|uninstall|run` for running as a Windows service. Only the argument
+// handling is built outside of Windows, so it can be tested anywhere.
+
+use clap::Subcommand;
+
+use crate::StartError;
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum ServiceCommand {
+    /// Register the service to start at boot with these serve options.
+    Install {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "OPTIONS")]
+        options: Vec<String>,
+    },
+    /// Stop and remove the service.
+    Uninstall,
+    /// What the service control manager runs. Not meant to be typed in.
+    #[command(hide = true)]
+    Run {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "OPTIONS")]
+        options: Vec<String>,
+    },
+}
+
+// What the service gets started with: the installed options behind "service run".
+pub fn launch_arguments(options: &[String]) -> Vec<String> {
+    ["service", "run"].iter().map(|s| s.to_string()).chain(options.iter().cloned()).collect()
+}
+
+// The serve options as if they'd been given without "service install", for parse_options.
+pub fn server_args(program: &str, options: &[String]) -> Vec<String> {
+    std::iter::once(program.to_string()).chain(options.iter().cloned()).collect()
+}
+
+#[cfg(not(windows))]
+pub fn dispatch(_program: &str, _command: ServiceCommand) -> Result<(), StartError> {
+    Err(StartError::Usage("service only works on Windows. Try --daemon or a systemd unit instead".to_string()))
+}
+
+#[cfg(windows)]
+pub use self::windows::dispatch;
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::OsString;
+    use std::sync::OnceLock;
+    use std::time::Duration;
+    use windows_service::service::{
+        ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+        ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    use super::{launch_arguments, server_args, ServiceCommand};
+    use crate::config::Config;
+    use crate::{parse_options, server, StartCommand, StartError};
+
+    const SERVICE_NAME: &str = "touchppp";
+    const SERVICE_DISPLAY_NAME: &str = "WebTV Touch PPP";
+    const SERVICE_DESCRIPTION: &str = "Answers WebTV MAME modem calls and touches some PPP.";
+    const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+    // There's no console for a service, so without a log file we log next to the exe.
+    const DEFAULT_SERVICE_LOG_FILE: &str = "touchppp.log";
+
+    // The options "service run" was given, for service_main to pick up once the dispatcher calls it.
+    static RUN_ARGS: OnceLock<Vec<String>> = OnceLock::new();
+
+    // The installed options are only ever serve options.
+    fn serve_command(args: &[String]) -> Result<StartCommand, StartError> {
+        match parse_options(args) {
+            Ok(Ok(start_cmd)) => Ok(start_cmd),
+            Ok(Err(_)) => Err(StartError::Usage("the service options can't have a subcommand in them".to_string())),
+            Err(e) => Err(StartError::Usage(e.to_string())),
+        }
+    }
+
+    fn runtime_error(e: windows_service::Error) -> StartError {
+        StartError::Runtime(Box::new(e))
+    }
+
+    pub fn dispatch(program: &str, command: ServiceCommand) -> Result<(), StartError> {
+        match command {
+            ServiceCommand::Install { options } => install(program, &options),
+            ServiceCommand::Uninstall => uninstall(),
+            ServiceCommand::Run { options } => {
+                let _ = RUN_ARGS.set(server_args(program, &options));
+
+                service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(runtime_error)
+            },
+        }
+    }
+
+    fn install(program: &str, options: &[String]) -> Result<(), StartError> {
+        // Catch bad options now rather than when the service fails to start at boot.
+        let start_cmd = serve_command(&server_args(program, options))?;
+        Config::load(&start_cmd.params)?;
+
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)
+            .map_err(runtime_error)?;
+
+        let service_info = ServiceInfo {
+            name: OsString::from(SERVICE_NAME),
+            display_name: OsString::from(SERVICE_DISPLAY_NAME),
+            service_type: SERVICE_TYPE,
+            start_type: ServiceStartType::AutoStart,
+            error_control: ServiceErrorControl::Normal,
+            executable_path: std::env::current_exe()?,
+            launch_arguments: launch_arguments(options).into_iter().map(OsString::from).collect(),
+            dependencies: vec![],
+            account_name: None,
+            account_password: None,
+        };
+
+        let service = manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG).map_err(runtime_error)?;
+        service.set_description(SERVICE_DESCRIPTION).map_err(runtime_error)?;
+
+        println!("Installed the {SERVICE_NAME} service. It starts at boot, or now with 'sc start {SERVICE_NAME}'.");
+
+        Ok(())
+    }
+
+    fn uninstall() -> Result<(), StartError> {
+        let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT).map_err(runtime_error)?;
+        let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)
+            .map_err(runtime_error)?;
+
+        if service.query_status().map_err(runtime_error)?.current_state != ServiceState::Stopped {
+            let _ = service.stop();
+        }
+
+        service.delete().map_err(runtime_error)?;
+
+        println!("Uninstalled the {SERVICE_NAME} service.");
+
+        Ok(())
+    }
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    fn service_main(_arguments: Vec<OsString>) {
+        // Nobody's watching stderr here, so this is all we can do if the service can't even report in.
+        if let Err(e) = run_service() {
+            eprintln!("The {SERVICE_NAME} service failed: {e}");
+        }
+    }
+
+    fn set_state(status_handle: &service_control_handler::ServiceStatusHandle, state: ServiceState, exit_code: u32) -> windows_service::Result<()> {
+        status_handle.set_service_status(ServiceStatus {
+            service_type: SERVICE_TYPE,
+            current_state: state,
+            controls_accepted: match state {
+                ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+                _ => ServiceControlAccept::empty(),
+            },
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    }
+
+    fn run_service() -> windows_service::Result<()> {
+        let status_handle = service_control_handler::register(SERVICE_NAME, |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                // Joins in on the same shutdown as Ctrl-C.
+                server::request_stop();
+                ServiceControlHandlerResult::NoError
+            },
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+        set_state(&status_handle, ServiceState::Running, 0)?;
+
+        let result = match with_log_file(RUN_ARGS.get().cloned().unwrap_or_default()) {
+            Ok(args) => serve_command(&args).and_then(|start_cmd| server::start(&start_cmd.params)),
+            Err(e) => Err(e),
+        };
+
+        let exit_code = match result {
+            Ok(_) => 0,
+            Err(StartError::Usage(message)) => {
+                eprintln!("{message}");
+                2
+            },
+            Err(StartError::Runtime(e)) => {
+                eprintln!("{e}");
+                1
+            },
+        };
+
+        set_state(&status_handle, ServiceState::Stopped, exit_code)
+    }
+
+    // Adds --log-file next to the exe unless the options or the config file already log somewhere.
+    fn with_log_file(mut args: Vec<String>) -> Result<Vec<String>, StartError> {
+        let start_cmd = serve_command(&args)?;
+        let config = Config::load(&start_cmd.params)?;
+
+        if config.log_file.is_none() {
+            let log_file = std::env::current_exe()?.with_file_name(DEFAULT_SERVICE_LOG_FILE);
+
+            args.push("--log-file".to_string());
+            args.push(log_file.display().to_string());
+        }
+
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use crate::cli::{Cli, Command};
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn parse(values: &[&str]) -> Option<ServiceCommand> {
+        match Cli::try_parse_from(args(values)).ok()?.command? {
+            Command::Service { action } => Some(action),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_service_actions() {
+        assert_eq!(parse(&["touchppp", "service", "install", "-l", "1122"]), Some(ServiceCommand::Install { options: args(&["-l", "1122"]) }));
+        assert_eq!(parse(&["touchppp", "service", "run", "-c", "10.0.0.2:2323"]), Some(ServiceCommand::Run { options: args(&["-c", "10.0.0.2:2323"]) }));
+        assert_eq!(parse(&["touchppp", "service", "uninstall"]), Some(ServiceCommand::Uninstall));
+    }
+
+    #[test]
+    fn rejects_bad_service_actions() {
+        assert_eq!(parse(&["touchppp", "service"]), None);
+        assert_eq!(parse(&["touchppp", "service", "start"]), None);
+        assert_eq!(parse(&["touchppp", "service", "uninstall", "now"]), None);
+    }
+
+    #[test]
+    fn installed_options_round_trip() {
+        let options = args(&["-l", "1122", "--log-file", "C:\\touchppp\\touchppp.log"]);
+        let launched = launch_arguments(&options);
+
+        assert_eq!(launched, args(&["service", "run", "-l", "1122", "--log-file", "C:\\touchppp\\touchppp.log"]));
+
+        let mut argv = vec!["touchppp"];
+        argv.extend(launched.iter().map(|s| s.as_str()));
+
+        let Some(ServiceCommand::Run { options: run_options }) = parse(&argv) else {
+            panic!("launch arguments should parse as service run");
+        };
+
+        assert_eq!(server_args("touchppp", &run_options), args(&["touchppp", "-l", "1122", "--log-file", "C:\\touchppp\\touchppp.log"]));
+    }
+}
@@ -0,0 +1,853 @@
+// One MAME from the first AT command until it hangs up: the modem emulation, then the bridge to PPP once it
+// dials. Doesn't care what the connection is, so the server, the self-test and tests can all drive one.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::at;
+use crate::backend::{ActiveSession, DialContext};
+use crate::bridge::{bridge, BUFFER_SIZE};
+use crate::config::{AutoData, BackendKind, Config, LinkProtocol, Throttle};
+use crate::dialstate::DialState;
+use crate::flood::PendingSlot;
+use crate::modem::{Event, ModemSession, ModemState};
+use crate::passthrough;
+use crate::ppp::hdlc;
+use crate::slip;
+use crate::stats::{self, SessionCommand, SessionGuard, SessionState, Stats, Throughput};
+use crate::transcript::Transcript;
+
+/// A call from one MAME, counted in the stats from the moment it's made.
+pub struct Session {
+    guard: SessionGuard,
+    commands: mpsc::Receiver<SessionCommand>,
+    client: String,
+    config: watch::Receiver<Arc<Config>>,
+    shutdown: CancellationToken,
+    dial_state: Arc<DialState>,
+}
+
+// Everything the modem says goes through here so it makes it into the transcript.
+async fn send_result<S: AsyncWrite + Unpin>(mame: &mut S, transcript: &mut Transcript, result: &[u8]) -> tokio::io::Result<()> {
+    transcript.sent(result);
+
+    mame.write_all(result).await
+}
+
+// A result code answering a command, sent after `delay` (--command-delay, or a --command-delay-for) like a real
+// modem working the command through. Being hung up on cuts the wait short.
+async fn send_result_after<S: AsyncWrite + Unpin>(mame: &mut S, transcript: &mut Transcript, result: &[u8], delay: Duration, cancel: &CancellationToken) -> tokio::io::Result<()> {
+    if !delay.is_zero() {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = cancel.cancelled() => (),
+        }
+    }
+
+    send_result(mame, transcript, result).await
+}
+
+// Asked to hang up with a call up or on its way: MAME gets NO CARRIER. The backend's already been hung up on,
+// either by its own cleanup or by dropping it before it was done dialing.
+async fn hang_up<S: AsyncWrite + Unpin>(mame: &mut S, transcript: &mut Transcript, modem: &mut ModemSession, session: &SessionGuard, shutdown: &CancellationToken) {
+    info!(event = "killed", "Asked to hang up, taking my hands off PPP.");
+    transcript.note("asked to hang up");
+
+    if let Some(reply) = modem.handle(Event::Killed) {
+        let _ = send_result(mame, transcript, reply).await;
+    }
+
+    session.set_end_reason(hang_up_reason(shutdown));
+}
+
+// With --report-throughput, MAME hears how the call did ahead of NO CARRIER. Only worth a try, like NO CARRIER.
+async fn report_throughput<S: AsyncWrite + Unpin>(mame: &mut S, transcript: &mut Transcript, config: &Config, throughput: Throughput) {
+    if !config.report_throughput {
+        return;
+    }
+
+    let bits = |bytes_per_second: u64| bytes_per_second * 8;
+    let line = at::throughput_line((bits(throughput.average_up), bits(throughput.average_down)), (bits(throughput.peak_up), bits(throughput.peak_down)));
+
+    let _ = send_result(mame, transcript, &line).await;
+}
+
+// Whether we were asked to hang up on just this session or on everyone.
+fn hang_up_reason(shutdown: &CancellationToken) -> &'static str {
+    if shutdown.is_cancelled() {
+        "shutting down"
+    } else {
+        "killed"
+    }
+}
+
+// Counts a failed dial, saying so if that's the one that gets the number DELAYED.
+fn delay_if_failing(dial_state: &DialState, dialed_number: &str, config: &Config, transcript: &mut Transcript) {
+    if dial_state.failed(dialed_number, config, SystemTime::now()) {
+        info!("'{dialed_number}' has failed {} times in a row, it's DELAYED for {}s.", config.delay_after.unwrap_or_default(), config.delay_cooldown.as_secs());
+        transcript.note(format_args!("'{dialed_number}' is DELAYED for {}s", config.delay_cooldown.as_secs()));
+    }
+}
+
+// Waits out --drop-after and takes one of --drop-count's drops. Never finishes if the carrier isn't to be
+// dropped, so it can sit in a select next to the bridge.
+async fn drop_carrier(config: &Config, session: &SessionGuard) {
+    if let Some(drop_after) = config.drop_after {
+        let seed = std::collections::hash_map::RandomState::new().hash_one(session.id);
+
+        // The clock stops while the call's paused.
+        let mut left = drop_after.pick(seed);
+        let mut pause = session.watch_pause();
+        loop {
+            let started = Instant::now();
+
+            tokio::select! {
+                _ = tokio::time::sleep(left) => break,
+                is_paused = async { pause.wait_for(|paused| *paused).await.is_ok() } => {
+                    // Nothing can pause it anymore.
+                    if !is_paused {
+                        tokio::time::sleep(left.saturating_sub(started.elapsed())).await;
+                        break;
+                    }
+
+                    left = left.saturating_sub(started.elapsed());
+                    let _ = pause.wait_for(|paused| !*paused).await;
+                },
+            }
+        }
+
+        if session.take_carrier_drop(config.drop_count) {
+            return;
+        }
+    }
+
+    futures::future::pending().await
+}
+
+impl Session {
+    /// Opens session `id` for `client` (just a name for the logs). Dials use whatever `config` holds at the
+    /// time, so a reload only reaches calls that haven't dialed yet.
+    pub fn new(stats: &Arc<Stats>, id: u64, client: &str, config: watch::Receiver<Arc<Config>>) -> Session {
+        let modem = config.borrow().modem.clone();
+        let (guard, commands) = stats.open_session_on(id, client, modem.as_deref().unwrap_or(stats::DEFAULT_MODEM));
+
+        Session {
+            guard,
+            commands,
+            client: client.to_string(),
+            config,
+            shutdown: CancellationToken::new(),
+            dial_state: Arc::new(DialState::new()),
+        }
+    }
+
+    /// Hangs up the same way a kill does once `shutdown` is cancelled, which is how the server stops every
+    /// session at once.
+    pub fn with_shutdown(mut self, shutdown: &CancellationToken) -> Session {
+        self.shutdown = shutdown.clone();
+
+        self
+    }
+
+    /// Counts failed dials in `dial_state` instead of a fresh one, which is how the server gets DELAYED to
+    /// stick across calls.
+    pub fn with_dial_state(mut self, dial_state: &Arc<DialState>) -> Session {
+        self.dial_state = dial_state.clone();
+
+        self
+    }
+
+    /// Counts the session against --max-pending until MAME sends its first command.
+    pub fn with_pending(self, pending: PendingSlot) -> Session {
+        self.guard.hold_pending(pending);
+
+        self
+    }
+
+    /// Answers `mame` until it hangs up, the connection fails or the session's killed.
+    pub async fn run<S: AsyncRead + AsyncWrite + Unpin + Send>(self, mame: S) {
+        let Session { guard: session, commands, client: mame_socket_address, config: config_receiver, shutdown, dial_state } = self;
+
+        // Everything the call starts hangs off this token and runs in `children`, so hanging up stops all of it
+        // and the session isn't over until they are.
+        let cancel = shutdown.child_token();
+        let mut children = JoinSet::new();
+
+        children.spawn(take_commands(commands, session.shared(), cancel.clone()));
+        children.spawn(session.sample_peaks(cancel.clone()));
+
+        if config_receiver.borrow().at_passthrough {
+            let config = config_receiver.borrow().clone();
+            pass_through(mame, &session, &mame_socket_address, &config, &cancel, &shutdown).await;
+        } else {
+            answer(mame, &session, &mame_socket_address, config_receiver, &dial_state, &cancel, &shutdown).await;
+        }
+
+        cancel.cancel();
+        while children.join_next().await.is_some() {}
+    }
+}
+
+// Turns a kill from the admin interface into cancelling the session, a carrier drop into a nudge for the bridge,
+// injected bytes into the bridge's queue for that way, and a pause or resume into one for the bridge to see. Any
+// of those with no call up goes nowhere.
+async fn take_commands(mut commands: mpsc::Receiver<SessionCommand>, session: Arc<stats::Session>, cancel: CancellationToken) {
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(SessionCommand::Kill) => return cancel.cancel(),
+                Some(SessionCommand::DropCarrier) => session.carrier_drop.notify_waiters(),
+                Some(SessionCommand::Inject(to, bytes)) => {
+                    let length = bytes.len();
+
+                    if !session.inject(to, bytes) {
+                        warn!("Dropping {length} bytes injected for {to}, the call's gone.");
+                    }
+                },
+                Some(SessionCommand::Pause) => {
+                    session.pause();
+                },
+                Some(SessionCommand::Resume) => {
+                    session.resume();
+                },
+                None => return,
+            },
+            _ = cancel.cancelled() => return,
+        }
+    }
+}
+
+async fn answer<S: AsyncRead + AsyncWrite + Unpin + Send>(mut mame: S, session: &SessionGuard, mame_socket_address: &str, config_receiver: watch::Receiver<Arc<Config>>, dial_state: &Arc<DialState>, cancel: &CancellationToken, shutdown: &CancellationToken) {
+    let mut buf = [0; BUFFER_SIZE];
+
+    info!(event = "connect", "Looks like we got a wild MAME @ {mame_socket_address}");
+
+    let mut transcript = Transcript::start(config_receiver.borrow().at_transcript.as_deref(), session.id, mame_socket_address);
+
+    let max_command_length = config_receiver.borrow().max_command_length;
+    let mut command_line = at::CommandLine::new(max_command_length);
+    // Lines that came in the same read as the one being answered wait their turn here.
+    let mut lines = VecDeque::new();
+    let mut has_warned_too_long = false;
+    // With --auto-data, what MAME sends in command mode is watched for PPP. What's spotted goes to the backend
+    // ahead of the rest.
+    let mut spotter = hdlc::FrameSpotter::default();
+    let mut spotted = None;
+    // What it was if MAME hangs up now. A dropped carrier is why, until the next call goes through.
+    let mut disconnect_reason = "MAME hung up";
+    // Set once a Windows CE caller's got its generic modem, which it keeps whatever it sends after.
+    let mut answering_as_generic = false;
+    let mut modem = {
+        let config = config_receiver.borrow();
+
+        ModemSession::with_report(config.connect_report.clone())
+            .with_profile(config.profile.modem())
+            .with_preset(config.modem_profile)
+            .with_saved_profiles(dial_state)
+            .with_reset_forgetting_number(config.reset_forgets_number)
+    };
+    session.connect_speed.store(config_receiver.borrow().connect_speed, Ordering::SeqCst);
+    session.carrier_speed.store(modem.carrier_speed(), Ordering::SeqCst);
+
+    loop {
+        if let Some(line) = lines.pop_front() {
+            // With E1 the line goes back as it was typed, ahead of whatever it gets.
+            if let at::Line::Command(at_string) = &line {
+                // Windows CE's Unimodem asks for words, which a WebTV modem never answers in, so it gets a generic
+                // one from its init string on. That's before the echo, since a generic modem echoes until E0. It only
+                // happens the once, since switching resets the registers.
+                if session.spot_caller(at_string) == Some(at::Caller::WindowsCe) && config_receiver.borrow().profile == at::Profile::Webtv && !answering_as_generic {
+                    info!("Answering MAME @ {mame_socket_address} as a generic modem, since it's Windows CE.");
+
+                    modem = std::mem::take(&mut modem).with_profile(at::Profile::Generic.modem());
+                    answering_as_generic = true;
+                }
+
+                if modem.echoes() {
+                    if let Err(e) = send_result(&mut mame, &mut transcript, at_string.as_bytes()).await {
+                        error!("Can't talk to MAME: error={e}");
+                        session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                        return;
+                    }
+                }
+            }
+
+            // Draining, so nothing new starts: without a call up, whatever MAME says gets BUSY and a hang up.
+            if session.is_draining() && modem.state() == ModemState::CommandMode {
+                info!(event = "draining", "Turning MAME @ {mame_socket_address} away with BUSY, since we're draining.");
+                transcript.note("draining");
+
+                if let Some(reply) = modem.respond(at::BUSY) {
+                    let _ = send_result(&mut mame, &mut transcript, reply).await;
+                }
+
+                session.set_end_reason("draining");
+                return;
+            }
+
+            let (reply, delay) = match line {
+                at::Line::TooLong => {
+                    transcript.note("command line too long");
+
+                    (modem.respond(at::ERROR), config_receiver.borrow().command_delay)
+                },
+                at::Line::Command(at_string) => {
+                    debug!(target: "touchppp::at", "{}", at_string.trim_end());
+                    transcript.received(&at_string);
+                    session.record_at(&at_string);
+
+                    let delay = config_receiver.borrow().command_delay_for(&at_string);
+
+                    (modem.answer(&at_string), delay)
+                },
+            };
+
+            if let Some(reply) = reply {
+                if let Err(e) = send_result_after(&mut mame, &mut transcript, reply, delay, cancel).await {
+                    error!("Can't talk to MAME: error={e}");
+                    session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                    return;
+                }
+            }
+        } else {
+            let read = tokio::select! {
+                read = mame.read(&mut buf) => read,
+                _ = cancel.cancelled() => {
+                    info!(event = "killed", "Asked to hang up on MAME @ {mame_socket_address}.");
+                    transcript.note("asked to hang up");
+                    session.set_end_reason(hang_up_reason(shutdown));
+                    return;
+                },
+            };
+
+            let n: usize = match read {
+                Ok(0) => {
+                    info!(event = "disconnect", "MAME @ {mame_socket_address} hung up.");
+                    session.set_end_reason(disconnect_reason);
+                    return;
+                },
+                Ok(n) => n,
+                Err(e) => {
+                    error!("Can't listen to MAME: error={e}");
+                    session.set_error_reason(&format!("can't listen to MAME: {e}"));
+                    return;
+                }
+            };
+
+            let config = config_receiver.borrow().clone();
+            if config.auto_data.is_some() && config.link_protocol == LinkProtocol::Ppp && modem.state() == ModemState::CommandMode {
+                spotted = spotter.push(&buf[..n]);
+            }
+
+            // Whatever's PPP isn't part of a command line.
+            let commands = match &spotted {
+                Some(frames) => &buf[..n.saturating_sub(frames.len())],
+                None => &buf[..n],
+            };
+            lines.extend(commands.iter().filter_map(|&byte| command_line.push(byte)));
+
+            if (command_line.is_too_long() || lines.contains(&at::Line::TooLong)) && !has_warned_too_long {
+                warn!("MAME @ {mame_socket_address} sent a command line longer than {max_command_length} bytes, answering ERROR.");
+                has_warned_too_long = true;
+            }
+
+            if spotted.is_none() {
+                continue;
+            }
+
+            info!("MAME @ {mame_socket_address} started PPP without dialing, going online like --auto-data asks.");
+            transcript.note("PPP without a dial");
+            modem.handle(Event::AutoData);
+        }
+
+        if modem.state() != ModemState::Dialing {
+            continue;
+        }
+
+        // Only for this dial, however it goes.
+        let spotted = spotted.take();
+
+        let dialed_number = modem.dialed_number().to_string();
+
+        // Grab whatever config is current right now; a reload mid-session won't pull it out from under us.
+        let config = config_receiver.borrow().clone();
+
+        // Picked afresh for every dial, like everything else the number decides.
+        let backend = config.resolve_backend(&dialed_number);
+
+        // However the dial goes, what MAME hears back is D's answer.
+        let result_delay = config.command_delay_for("ATD\r");
+
+        // The phone book entry's say on how the dial goes, over the command line's, and 56k against the init string
+        // as it stands now.
+        let settings = config.dial_settings(&dialed_number, modem.modulation());
+        modem.set_report(settings.connect_report.clone());
+        session.connect_speed.store(settings.connect_report.connect_speed, Ordering::SeqCst);
+
+        debug!(target: "touchppp::backend", event = "dial", dialed_number = %dialed_number, backend = %backend.name, "Dialed '{dialed_number}', using backend {}", backend.describe());
+        transcript.note(format_args!("dialed '{dialed_number}', using backend {}", backend.describe()));
+        debug!(target: "touchppp::at", "{} for '{dialed_number}': {}", if settings.is_56k.is_56k { "56k" } else { "No 56k" }, settings.is_56k.reason);
+
+        // Numbers we won't dial at all, or not again just yet, never get as far as the backend.
+        let refused = if config.is_blacklisted(&dialed_number) {
+            Some((Event::Blacklisted, stats::BLACKLISTED))
+        } else if dial_state.is_delayed(&dialed_number, SystemTime::now()) {
+            Some((Event::Delayed, stats::DELAYED))
+        } else {
+            None
+        };
+
+        if let Some((event, outcome)) = refused {
+            info!("Not dialing '{dialed_number}', telling MAME it's {outcome}.");
+            transcript.note(format_args!("'{dialed_number}' is {outcome}"));
+
+            session.record_dial(&dialed_number, &backend.name, outcome);
+
+            if let Some(reply) = modem.handle(event) {
+                if let Err(e) = send_result_after(&mut mame, &mut transcript, reply, result_delay, cancel).await {
+                    error!("Can't talk to MAME: error={e}");
+                    session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                    return;
+                }
+            }
+
+            continue;
+        }
+
+        // A backend that takes one call at a time is BUSY to everyone else until that call's over, however it ends.
+        let _claim = if backend.is_exclusive() {
+            match session.claim_backend(&backend.name) {
+                Some(claim) => Some(claim),
+                None => {
+                    info!("Backend {} already has a call on it, telling MAME it's BUSY.", backend.name);
+                    transcript.note(format_args!("backend {} already has a call on it, so it's BUSY", backend.name));
+
+                    session.record_dial(&dialed_number, &backend.name, stats::BUSY);
+
+                    if let Some(reply) = modem.handle(Event::Busy) {
+                        if let Err(e) = send_result_after(&mut mame, &mut transcript, reply, result_delay, cancel).await {
+                            error!("Can't talk to MAME: error={e}");
+                            session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                            return;
+                        }
+                    }
+
+                    continue;
+                },
+            }
+        } else {
+            None
+        };
+
+        // Dialing takes as long as the phone book entry says it does.
+        if !settings.dial_delay.is_zero() {
+            tokio::select! {
+                _ = tokio::time::sleep(settings.dial_delay) => (),
+                _ = cancel.cancelled() => {
+                    hang_up(&mut mame, &mut transcript, &mut modem, session, shutdown).await;
+                    return;
+                },
+            }
+        }
+
+        // Don't bother going into data mode if the health check says PPP is down.
+        if let BackendKind::Remote(remote_ppp) = &backend.kind {
+            if config.health_check_interval.is_some() && !remote_ppp.healthy.load(Ordering::SeqCst) {
+                info!("PPP for backend {} isn't answering, telling MAME it's BUSY.", backend.name);
+                transcript.note(format_args!("PPP for backend {} isn't answering, so it's BUSY", backend.name));
+
+                session.record_dial(&dialed_number, &backend.name, stats::BUSY);
+                delay_if_failing(dial_state, &dialed_number, &config, &mut transcript);
+
+                if let Some(reply) = modem.handle(Event::Busy) {
+                    if let Err(e) = send_result_after(&mut mame, &mut transcript, reply, result_delay, cancel).await {
+                        error!("Can't talk to MAME: error={e}");
+                        session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                        return;
+                    }
+                }
+
+                continue;
+            }
+        }
+
+        // Held for the whole call so the health check leaves a remote that's in use alone.
+        let _active = match &backend.kind {
+            BackendKind::Remote(remote_ppp) => Some(ActiveSession::start(&remote_ppp.active_sessions)),
+            _ => None,
+        };
+
+        let endpoint = dial_state.endpoint(&dialed_number);
+        let context = DialContext {
+            number: &dialed_number,
+            session: session.id,
+            link_protocol: config.link_protocol,
+            settings: &settings,
+            endpoint: endpoint.as_deref(),
+        };
+
+        let established = tokio::select! {
+            established = backend.ppp().establish(&context) => established,
+            _ = cancel.cancelled() => {
+                hang_up(&mut mame, &mut transcript, &mut modem, session, shutdown).await;
+                return;
+            },
+        };
+
+        let mut ppp = match established {
+            // A backend that answers the link itself takes SLIP as it comes.
+            Ok(r) if config.link_protocol == LinkProtocol::Slip && !backend.answers_the_link() => slip::reframe(r),
+            Ok(r) => r,
+            Err(e) => {
+                if e.is_config_problem() {
+                    error!("********** Couldn't reach backend {}, check its config! error={e} **********", backend.name);
+                } else {
+                    error!("Couldn't reach backend {}: error={e}", backend.name);
+                }
+                transcript.note(&e);
+
+                session.record_failed_dial(&dialed_number, &backend.name, &e);
+                delay_if_failing(dial_state, &dialed_number, &config, &mut transcript);
+
+                if let Some(reply) = modem.handle(Event::Failed(&e)) {
+                    if let Err(e) = send_result_after(&mut mame, &mut transcript, reply, result_delay, cancel).await {
+                        error!("Can't talk to MAME: error={e}");
+                        session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                        return;
+                    }
+                }
+
+                continue;
+            },
+        };
+
+        session.carrier_speed.store(modem.carrier_speed(), Ordering::SeqCst);
+        session.record_dial(&dialed_number, &backend.name, stats::CONNECTED);
+        dial_state.connected(&dialed_number, ppp.endpoint.as_deref(), modem.carrier_speed() > at::Modulation::V34.top_speed(), SystemTime::now());
+        *session.endpoint.lock().unwrap() = ppp.endpoint.clone();
+        disconnect_reason = "MAME hung up";
+
+        // MAME's already past waiting for CONNECT if it went straight to PPP, so quiet doesn't send it one.
+        let is_quiet = spotted.is_some() && config.auto_data == Some(AutoData::Quiet);
+        let reply = modem.handle(Event::Connected).filter(|_| !is_quiet);
+        if let Some(reply) = reply {
+            if let Err(e) = send_result_after(&mut mame, &mut transcript, reply, result_delay, cancel).await {
+                error!("Can't talk to MAME: error={e}");
+                session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                return;
+            }
+        }
+
+        session.set_state(SessionState::Online);
+        transcript.note("online");
+
+        // Whatever MAME sent ahead of CONNECT doesn't carry over into the call.
+        if !lines.is_empty() {
+            debug!(target: "touchppp::at", "Dropping {} command lines that came after the one that went online: {lines:?}", lines.len());
+        }
+        command_line.clear();
+        lines.clear();
+        spotter = hdlc::FrameSpotter::default();
+        let online = session.mark();
+
+        // The PPP that took it online is the first thing the backend sees.
+        if let Some(frames) = &spotted {
+            if let Err(e) = ppp.writer.write_all(frames).await {
+                error!("Can't pass MAME's first PPP on: error={e}");
+            }
+            session.bytes_up.fetch_add(frames.len() as u64, Ordering::SeqCst);
+        }
+
+        // Held to the phone book entry's rates or --throttle's, or the carrier speed MAME was told, if asked.
+        let carrier = match config.throttle_to_carrier {
+            true => Throttle::for_carrier(session.carrier_speed.load(Ordering::SeqCst)),
+            false => Throttle::default(),
+        };
+        let throttle = settings.throttle.or(carrier);
+        session.throttle_down.store(throttle.down.unwrap_or(0), Ordering::SeqCst);
+        session.throttle_up.store(throttle.up.unwrap_or(0), Ordering::SeqCst);
+
+        // Only a remote server is worth gathering MAME's bytes up for.
+        let coalesce = match &backend.kind {
+            BackendKind::Remote(remote_ppp) => remote_ppp.coalesce,
+            _ => None,
+        };
+
+        // A carrier drop ends just the bridge. The call can be dialed again after.
+        let bridge_cancel = cancel.child_token();
+        // Who asked for the carrier to be dropped, if anyone did.
+        let mut carrier_dropped = None;
+        let bridged = {
+            let bridging = bridge(&mut mame, ppp, session, bridge_cancel.clone(), throttle, coalesce, config.link_protocol == LinkProtocol::Ppp);
+            tokio::pin!(bridging);
+
+            tokio::select! {
+                bridged = &mut bridging => bridged,
+                _ = drop_carrier(&config, session) => {
+                    carrier_dropped = Some("--drop-after");
+                    bridge_cancel.cancel();
+
+                    bridging.await
+                },
+                _ = session.carrier_drop.notified() => {
+                    carrier_dropped = Some("an admin");
+                    bridge_cancel.cancel();
+
+                    bridging.await
+                },
+            }
+        };
+
+        // One line for the call, however it ended.
+        let throughput = session.throughput_since(online);
+        info!(event = "ppp_done", bytes_up = throughput.bytes_up, bytes_down = throughput.bytes_down, "Taking my hands off PPP. {} bytes copied from MAME to PPP; {} bytes copied from PPP to MAME; {throughput}.", throughput.bytes_up, throughput.bytes_down);
+
+        if cancel.is_cancelled() {
+            report_throughput(&mut mame, &mut transcript, &config, throughput).await;
+            hang_up(&mut mame, &mut transcript, &mut modem, session, shutdown).await;
+            return;
+        }
+
+        if let Some(asker) = carrier_dropped {
+            info!(event = "carrier_drop", "Dropping the carrier on MAME @ {mame_socket_address} like {asker} asks.");
+            transcript.note("simulated carrier drop");
+
+            disconnect_reason = "simulated carrier drop";
+            session.set_end_reason(disconnect_reason);
+            session.end_call(disconnect_reason);
+            session.set_state(SessionState::Command);
+
+            report_throughput(&mut mame, &mut transcript, &config, throughput).await;
+
+            if let Some(reply) = modem.handle(Event::Killed) {
+                if let Err(e) = send_result(&mut mame, &mut transcript, reply).await {
+                    error!("Can't talk to MAME: error={e}");
+                    session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                    return;
+                }
+            }
+
+            continue;
+        }
+
+        if let Err(e) = bridged {
+            error!("Error in PPP loop: error={e}");
+            transcript.note(&e);
+
+            // MAME may well be what broke, so this is only worth a try.
+            report_throughput(&mut mame, &mut transcript, &config, throughput).await;
+
+            if let Some(reply) = modem.handle(Event::Failed(&e)) {
+                let _ = send_result(&mut mame, &mut transcript, reply).await;
+            }
+
+            session.set_error_reason(&e.to_string());
+            return;
+        }
+
+        modem.handle(Event::BridgeDone);
+        session.end_call("call ended");
+        session.set_state(SessionState::Command);
+        transcript.note(format_args!("back to commands after {} bytes up and {} bytes down", throughput.bytes_up, throughput.bytes_down));
+    }
+}
+
+// With --at-passthrough the backend's modem answers MAME, and the call's only bridged once it says CONNECT. A
+// dial to a number the phone book has a remote backend for goes to the modem as a dial to that backend's address.
+async fn pass_through<S: AsyncRead + AsyncWrite + Unpin + Send>(mut mame: S, session: &SessionGuard, mame_socket_address: &str, config: &Config, cancel: &CancellationToken, shutdown: &CancellationToken) {
+    info!(event = "connect", "Looks like we got a wild MAME @ {mame_socket_address}");
+
+    let mut transcript = Transcript::start(config.at_transcript.as_deref(), session.id, mame_socket_address);
+
+    // There's no number yet, so it's always the backend -c (or the config file's default) gives.
+    let backend = config.resolve_backend("");
+
+    debug!(target: "touchppp::backend", backend = %backend.name, "Passing AT commands through to backend {}", backend.describe());
+    transcript.note(format_args!("passing through to backend {}", backend.describe()));
+
+    let context = DialContext {
+        number: "",
+        session: session.id,
+        link_protocol: config.link_protocol,
+        settings: &config.dial_settings("", &at::ModulationConfig::default()),
+        endpoint: None,
+    };
+
+    let established = tokio::select! {
+        established = backend.ppp().establish(&context) => established,
+        _ = cancel.cancelled() => {
+            info!(event = "killed", "Asked to hang up on MAME @ {mame_socket_address}.");
+            session.set_end_reason(hang_up_reason(shutdown));
+            return;
+        },
+    };
+
+    let mut ppp = match established {
+        Ok(ppp) => ppp,
+        Err(e) => {
+            error!("Couldn't reach backend {}: error={e}", backend.name);
+            transcript.note(&e);
+            session.set_error_reason(&e.to_string());
+            return;
+        },
+    };
+
+    let mut buf = [0; BUFFER_SIZE];
+    let mut from_modem = [0; BUFFER_SIZE];
+    let mut command_line = at::CommandLine::new(config.max_command_length);
+    // MAME's bytes wait here until their line's done, in case it's a dial that's going somewhere else.
+    let mut pending = Vec::new();
+    let mut watch = passthrough::ConnectWatch::default();
+    let mut dialed_number = String::new();
+
+    loop {
+        tokio::select! {
+            read = mame.read(&mut buf) => {
+                let n = match read {
+                    Ok(0) => {
+                        info!(event = "disconnect", "MAME @ {mame_socket_address} hung up.");
+                        session.set_end_reason("MAME hung up");
+                        return;
+                    },
+                    Ok(n) => n,
+                    Err(e) => {
+                        error!("Can't listen to MAME: error={e}");
+                        session.set_error_reason(&format!("can't listen to MAME: {e}"));
+                        return;
+                    },
+                };
+
+                for &byte in &buf[..n] {
+                    pending.push(byte);
+
+                    match command_line.push(byte) {
+                        Some(at::Line::Command(at_string)) => {
+                            debug!(target: "touchppp::at", "{}", at_string.trim_end());
+                            transcript.received(&at_string);
+                            session.record_at(&at_string);
+                            session.spot_caller(&at_string);
+                            watch.sent(&at_string);
+
+                            if let Some(number) = passthrough::dialed_number(&at_string) {
+                                if let Some(target) = dial_target(config, &number) {
+                                    info!("Dialing {target} for '{number}' like the phone book says.");
+                                    transcript.note(format_args!("dialing {target} for '{number}'"));
+
+                                    pending = passthrough::redial(&at_string, &target).into_bytes();
+                                }
+
+                                dialed_number = number;
+                            }
+                        },
+                        // Nothing that long can be a dial, so it goes as it is.
+                        Some(at::Line::TooLong) => (),
+                        None if pending.len() < config.max_command_length => continue,
+                        None => (),
+                    }
+
+                    if let Err(e) = ppp.writer.write_all(&pending).await {
+                        error!("Can't talk to backend {}: error={e}", backend.name);
+                        session.set_error_reason(&format!("can't talk to backend {}: {e}", backend.name));
+                        return;
+                    }
+                    pending.clear();
+                }
+            },
+            read = ppp.reader.read(&mut from_modem) => {
+                let n = match read {
+                    Ok(0) => {
+                        info!(event = "disconnect", "Backend {} hung up on MAME @ {mame_socket_address}.", backend.name);
+                        session.set_end_reason("backend hung up");
+                        return;
+                    },
+                    Ok(n) => n,
+                    Err(e) => {
+                        error!("Can't listen to backend {}: error={e}", backend.name);
+                        session.set_error_reason(&format!("can't listen to backend {}: {e}", backend.name));
+                        return;
+                    },
+                };
+
+                let connected = watch.received(&from_modem[..n]);
+
+                if let Err(e) = send_result(&mut mame, &mut transcript, &from_modem[..connected.unwrap_or(n)]).await {
+                    error!("Can't talk to MAME: error={e}");
+                    session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                    return;
+                }
+
+                // The call's started, with whatever came after CONNECT as the first of it.
+                if let Some(end) = connected {
+                    if let Err(e) = mame.write_all(&from_modem[end..n]).await {
+                        error!("Can't talk to MAME: error={e}");
+                        session.set_error_reason(&format!("can't talk to MAME: {e}"));
+                        return;
+                    }
+                    session.bytes_down.fetch_add((n - end) as u64, Ordering::SeqCst);
+
+                    break;
+                }
+            },
+            _ = cancel.cancelled() => {
+                info!(event = "killed", "Asked to hang up on MAME @ {mame_socket_address}.");
+                transcript.note("asked to hang up");
+                session.set_end_reason(hang_up_reason(shutdown));
+                return;
+            },
+        }
+    }
+
+    info!("Backend {} says CONNECT to '{dialed_number}', bridging MAME @ {mame_socket_address}.", backend.name);
+    session.record_dial(&dialed_number, &backend.name, stats::CONNECTED);
+    session.set_state(SessionState::Online);
+    transcript.note("online");
+
+    // Whatever's left of MAME's last line is the start of the call.
+    if !pending.is_empty() {
+        if let Err(e) = ppp.writer.write_all(&pending).await {
+            error!("Can't talk to backend {}: error={e}", backend.name);
+        }
+        session.bytes_up.fetch_add(pending.len() as u64, Ordering::SeqCst);
+    }
+
+    let online = session.mark();
+
+    let coalesce = match &backend.kind {
+        BackendKind::Remote(remote_ppp) => remote_ppp.coalesce,
+        _ => None,
+    };
+
+    // The backend's modem holds the call to its own carrier, so there's nothing to throttle to.
+    let bridged = bridge(&mut mame, ppp, session, cancel.child_token(), Throttle::default(), coalesce, config.link_protocol == LinkProtocol::Ppp).await;
+
+    let throughput = session.throughput_since(online);
+    info!(event = "ppp_done", bytes_up = throughput.bytes_up, bytes_down = throughput.bytes_down, "Taking my hands off PPP. {} bytes copied from MAME to PPP; {} bytes copied from PPP to MAME; {throughput}.", throughput.bytes_up, throughput.bytes_down);
+    session.set_state(SessionState::Command);
+
+    match bridged {
+        _ if cancel.is_cancelled() => session.set_end_reason(hang_up_reason(shutdown)),
+        Ok(_) => session.set_end_reason("call ended"),
+        Err(e) => {
+            error!("Error in PPP loop: error={e}");
+            transcript.note(&e);
+            session.set_error_reason(&e.to_string());
+        },
+    }
+}
+
+// Where the backend's modem should dial for `number`: the address of the remote backend the phone book has for
+// it, if it has one.
+fn dial_target(config: &Config, number: &str) -> Option<String> {
+    match &config.phone_book_backend(number)?.kind {
+        BackendKind::Remote(remote_ppp) => remote_ppp.socket_addresses.first().map(ToString::to_string),
+        _ => None,
+    }
+}
@@ -0,0 +1,258 @@
+// RFC 1055's SLIP framing, for boxes and other retro systems that dial in with it instead of PPP: IP packets
+// ending in 0xc0, with 0xdb escaping. There's nothing to negotiate, so the box has to know its own address.
+
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+
+use crate::backend::BackendStream;
+
+pub const END: u8 = 0xc0;
+pub const ESC: u8 = 0xdb;
+pub const ESC_END: u8 = 0xdc;
+pub const ESC_ESC: u8 = 0xdd;
+
+// Longer than any packet the box could send, so it's packets run together by a lost END.
+const MAX_PACKET: usize = 4096;
+
+/// Frames up one packet. It starts with an END too, which flushes out any line noise ahead of it.
+pub fn encode(packet: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(packet.len() + 2);
+    encoded.push(END);
+
+    for byte in packet {
+        match *byte {
+            END => encoded.extend_from_slice(&[ESC, ESC_END]),
+            ESC => encoded.extend_from_slice(&[ESC, ESC_ESC]),
+            byte => encoded.push(byte),
+        }
+    }
+
+    encoded.push(END);
+
+    encoded
+}
+
+/// Picks packets out of the bytes coming off the link.
+#[derive(Default)]
+pub struct Decoder {
+    packet: Vec<u8>,
+    is_escaped: bool,
+    is_bad: bool,
+}
+
+impl Decoder {
+    /// Takes the next byte, giving back the packet it ended if it was an END. Empty packets (back to back ENDs)
+    /// are skipped, and so are ones cut short by ESC END or run past MAX_PACKET. An ESC before anything other
+    /// than ESC_END or ESC_ESC is left out and the byte kept as is, the way Linux does it.
+    pub fn push(&mut self, byte: u8) -> Option<Vec<u8>> {
+        match byte {
+            END => {
+                let packet = std::mem::take(&mut self.packet);
+                let is_good = !self.is_escaped && !self.is_bad && !packet.is_empty();
+
+                self.is_escaped = false;
+                self.is_bad = false;
+
+                is_good.then_some(packet)
+            },
+            ESC => {
+                self.is_escaped = true;
+
+                None
+            },
+            _ if self.is_bad => None,
+            _ => {
+                let byte = match (self.is_escaped, byte) {
+                    (true, ESC_END) => END,
+                    (true, ESC_ESC) => ESC,
+                    (_, byte) => byte,
+                };
+                self.is_escaped = false;
+
+                if self.packet.len() == MAX_PACKET {
+                    self.packet.clear();
+                    self.is_bad = true;
+                } else {
+                    self.packet.push(byte);
+                }
+
+                None
+            },
+        }
+    }
+}
+
+/// Tidies up the box's SLIP on its way to a backend that just takes bytes: each packet is decoded, then framed
+/// up again the same way every time, so line noise and empty packets don't make it through. What the backend
+/// sends goes to the box as is.
+pub fn reframe(stream: BackendStream) -> BackendStream {
+    BackendStream {
+        writer: Box::new(Reframer { writer: stream.writer, decoder: Decoder::default(), out: Vec::new() }),
+        ..stream
+    }
+}
+
+struct Reframer<W> {
+    writer: W,
+    decoder: Decoder,
+    // Framed up and waiting for the writer.
+    out: Vec<u8>,
+}
+
+impl<W: AsyncWrite + Unpin> Reframer<W> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while !self.out.is_empty() {
+            let written = ready!(Pin::new(&mut self.writer).poll_write(cx, &self.out))?;
+            if written == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+
+            self.out.drain(..written);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Reframer<W> {
+    // Everything's taken once the last lot's gone, so a slow backend holds the box back instead of piling up here.
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_drain(cx))?;
+
+        for byte in buf {
+            if let Some(packet) = self.decoder.push(*byte) {
+                self.out.extend_from_slice(&encode(&packet));
+            }
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+
+        Pin::new(&mut self.writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+
+        Pin::new(&mut self.writer).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn decode(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut decoder = Decoder::default();
+
+        bytes.iter().filter_map(|byte| decoder.push(*byte)).collect()
+    }
+
+    #[test]
+    fn escapes_end_and_esc() {
+        assert_eq!(encode(b"ip"), [END, b'i', b'p', END]);
+        assert_eq!(encode(&[END]), [END, ESC, ESC_END, END]);
+        assert_eq!(encode(&[ESC]), [END, ESC, ESC_ESC, END]);
+        // Escaped bytes stay as they are when they're not after an ESC.
+        assert_eq!(encode(&[ESC_END, ESC_ESC]), [END, ESC_END, ESC_ESC, END]);
+        assert_eq!(encode(&[ESC, END, ESC_END]), [END, ESC, ESC_ESC, ESC, ESC_END, ESC_END, END]);
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let awkward = [vec![END], vec![ESC], vec![ESC, ESC_END], vec![END, ESC, ESC_ESC, END], (0..=255).collect()];
+
+        for packet in awkward {
+            assert_eq!(decode(&encode(&packet)), [packet.as_slice()], "{packet:02x?}");
+        }
+
+        let both = [encode(&[END, 1]), encode(&[ESC, 2])].concat();
+        assert_eq!(decode(&both), [vec![END, 1], vec![ESC, 2]]);
+    }
+
+    #[test]
+    fn only_the_closing_end_is_needed() {
+        assert_eq!(decode(&[b'i', b'p', END]), [b"ip"]);
+        assert_eq!(decode(&[END, END, END]), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn drops_packets_cut_short_by_esc_end() {
+        assert_eq!(decode(&[END, b'x', ESC, END, b'o', b'k', END]), [b"ok"]);
+    }
+
+    #[test]
+    fn keeps_the_byte_after_a_stray_esc() {
+        assert_eq!(decode(&[END, ESC, b'x', ESC, ESC, ESC_ESC, END]), [vec![b'x', ESC]]);
+    }
+
+    #[test]
+    fn drops_packets_that_run_on() {
+        let long = [vec![END], vec![0; MAX_PACKET + 1], vec![END], encode(b"ok")].concat();
+
+        assert_eq!(decode(&long), [b"ok"]);
+    }
+
+    #[tokio::test]
+    async fn reframing_tidies_up_the_box_side() {
+        let (to_backend, mut backend) = tokio::io::duplex(1024);
+        let mut writer = reframe(BackendStream::new(tokio::io::empty(), to_backend)).writer;
+
+        // Noise and empty packets go, a missing opening END comes back, and a packet split across writes is whole.
+        writer.write_all(&[b'n', ESC, END, END, END, b'i', ESC]).await.unwrap();
+        writer.write_all(&[ESC_END, b'p', END]).await.unwrap();
+        writer.shutdown().await.unwrap();
+        drop(writer);
+
+        let mut sent = Vec::new();
+        backend.read_to_end(&mut sent).await.unwrap();
+        assert_eq!(sent, encode(&[b'i', END, b'p']));
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::prelude::*;
+
+        // ENDs and ESCs turn up a lot more than they would by chance.
+        fn link() -> impl Strategy<Value = Vec<u8>> {
+            let special = [END, ESC, ESC_END, ESC_ESC];
+
+            proptest::collection::vec(any::<u8>(), 0..6000).prop_map(move |bytes| bytes.into_iter().map(|byte| if byte < 0x40 { special[byte as usize % 4] } else { byte }).collect())
+        }
+
+        proptest! {
+            #[test]
+            fn packets_come_back_out_whatever_is_in_them(packets in proptest::collection::vec(proptest::collection::vec(any::<u8>(), 1..1500), 0..4)) {
+                let encoded: Vec<u8> = packets.iter().flat_map(|packet| encode(packet)).collect();
+
+                prop_assert_eq!(decode(&encoded), packets);
+            }
+
+            #[test]
+            fn decoded_packets_come_from_the_bytes(bytes in link()) {
+                let mut decoder = Decoder::default();
+                let mut packets = 0;
+                let mut taken = 0;
+
+                for &byte in &bytes {
+                    if let Some(packet) = decoder.push(byte) {
+                        prop_assert_eq!(byte, END);
+                        prop_assert!(!packet.is_empty() && packet.len() <= MAX_PACKET);
+                        packets += 1;
+                        taken += packet.len() + 1;
+                    }
+
+                    prop_assert!(decoder.packet.len() <= MAX_PACKET);
+                }
+
+                prop_assert!(packets <= bytes.iter().filter(|byte| **byte == END).count());
+                prop_assert!(taken <= bytes.len());
+            }
+        }
+    }
+}
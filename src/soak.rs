@@ -0,0 +1,459 @@
+// `touchppp soak`: one long call through an in-process TouchPPP with a known byte sequence going each way, and
+// every byte that comes out either end checked against it, for settling whether the bridge ever corrupts, drops
+// or makes up data under load. The PPP end is a built-in remote backend over TCP, so coalescing and throttling
+// are in the path the same as they'd be for a real PPP server.
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::config::Config;
+use crate::selftest::{read_expected, CALL};
+use crate::server::Server;
+use crate::StartError;
+
+// What the box's end sends, and what the PPP end sends.
+const UP_SEED: u64 = 0x5745_4254_5655_5000;
+const DOWN_SEED: u64 = 0x5745_4254_5644_4e00;
+
+// How often each end tops up what it's sent to what the rate says it should have by now.
+const PACE: Duration = Duration::from_millis(10);
+
+// Once the box's end is done sending, how long it waits for the PPP end to go quiet before what it's got is all
+// it's getting.
+const SETTLE: Duration = Duration::from_secs(2);
+
+// Mismatches kept for the report. The rest are only counted.
+const MISMATCHES_KEPT: usize = 10;
+
+const WRITE_SIZE: usize = 0x4000;
+
+/// A byte sequence (from splitmix64) either end can work out from the seed, so neither has to keep what it sent.
+pub struct Pattern {
+    state: u64,
+    word: [u8; 8],
+    used: usize,
+}
+
+impl Pattern {
+    pub fn new(seed: u64) -> Pattern {
+        Pattern {
+            state: seed,
+            word: [0; 8],
+            used: 8,
+        }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        if self.used == self.word.len() {
+            self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+            let mut z = self.state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+            self.word = (z ^ (z >> 31)).to_le_bytes();
+            self.used = 0;
+        }
+
+        self.used += 1;
+
+        self.word[self.used - 1]
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte = self.next_byte();
+        }
+    }
+}
+
+/// A byte that came out different from what went in, `offset` bytes into its way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mismatch {
+    pub offset: u64,
+    pub expected: u8,
+    pub got: u8,
+}
+
+/// How one way through the call went.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Way {
+    pub sent: u64,
+    pub received: u64,
+    pub mismatches: u64,
+    // The first few, in the order they came.
+    pub first_mismatches: Vec<Mismatch>,
+}
+
+impl Way {
+    /// Bytes that were sent and never showed up.
+    pub fn missing(&self) -> u64 {
+        self.sent.saturating_sub(self.received)
+    }
+
+    /// Bytes that showed up without being sent.
+    pub fn extra(&self) -> u64 {
+        self.received.saturating_sub(self.sent)
+    }
+
+    pub fn is_intact(&self) -> bool {
+        self.mismatches == 0 && self.sent == self.received
+    }
+}
+
+impl fmt::Display for Way {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sent {} bytes, got {} back, {} didn't match", self.sent, self.received, self.mismatches)?;
+
+        for mismatch in &self.first_mismatches {
+            write!(f, "\n    byte {}: expected {:#04x}, got {:#04x}", mismatch.offset, mismatch.expected, mismatch.got)?;
+        }
+
+        if self.mismatches > self.first_mismatches.len() as u64 {
+            write!(f, "\n    and {} more", self.mismatches - self.first_mismatches.len() as u64)?;
+        }
+
+        match (self.missing(), self.extra()) {
+            (0, 0) => Ok(()),
+            (missing, 0) => write!(f, "\n    {missing} bytes never showed up"),
+            (_, extra) => write!(f, "\n    {extra} bytes showed up that were never sent"),
+        }
+    }
+}
+
+/// What a soak run does.
+#[derive(Clone, Copy, Debug)]
+pub struct SoakOptions {
+    pub duration: Duration,
+    // Bytes per second, each way.
+    pub rate: u64,
+    // Bits per second, each way, like --throttle.
+    pub throttle: Option<u32>,
+    // Like --coalesce-bytes, None leaving it at the default.
+    pub coalesce_bytes: Option<usize>,
+}
+
+/// How a soak run went, up being the box's bytes on their way to PPP.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoakReport {
+    pub up: Way,
+    pub down: Way,
+}
+
+impl SoakReport {
+    pub fn is_intact(&self) -> bool {
+        self.up.is_intact() && self.down.is_intact()
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "up:   {}\ndown: {}", self.up, self.down)
+    }
+}
+
+// Checks what comes in against the sequence the other end is sending.
+struct Verifier {
+    expected: Pattern,
+    way: Way,
+}
+
+impl Verifier {
+    fn new(seed: u64) -> Verifier {
+        Verifier {
+            expected: Pattern::new(seed),
+            way: Way::default(),
+        }
+    }
+
+    fn check(&mut self, bytes: &[u8]) {
+        for &got in bytes {
+            let expected = self.expected.next_byte();
+
+            if got != expected {
+                self.way.mismatches += 1;
+
+                if self.way.first_mismatches.len() < MISMATCHES_KEPT {
+                    self.way.first_mismatches.push(Mismatch { offset: self.way.received, expected, got });
+                }
+            }
+
+            self.way.received += 1;
+        }
+    }
+}
+
+// One end of the call: sends `send_seed`'s sequence at `rate` bytes per second for `duration`, while checking
+// everything that comes in against `expect_seed`'s. The box's end (`settles`) stops reading once the other end's
+// been quiet for SETTLE after it's done sending; the PPP end reads until it's hung up on, so it never hangs up
+// first and gets NO CARRIER sent into the middle of the box's bytes. Gives back how many bytes it sent and how
+// the way in went.
+async fn run_end<S: AsyncRead + AsyncWrite>(stream: S, send_seed: u64, expect_seed: u64, options: SoakOptions, settles: bool) -> io::Result<(u64, Way)> {
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let done_sending = AtomicBool::new(false);
+
+    let sending = async {
+        let mut pattern = Pattern::new(send_seed);
+        let mut buf = vec![0; WRITE_SIZE];
+        let mut sent = 0;
+
+        let mut pace = tokio::time::interval(PACE);
+        pace.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let started = Instant::now();
+        while started.elapsed() < options.duration {
+            pace.tick().await;
+
+            let due = (options.rate as f64 * started.elapsed().min(options.duration).as_secs_f64()) as u64;
+
+            while sent < due {
+                let chunk = &mut buf[..(due - sent).min(WRITE_SIZE as u64) as usize];
+                pattern.fill(chunk);
+
+                writer.write_all(chunk).await?;
+                sent += chunk.len() as u64;
+            }
+        }
+
+        writer.flush().await?;
+        done_sending.store(true, Ordering::SeqCst);
+
+        Ok::<_, io::Error>(sent)
+    };
+
+    let receiving = async {
+        let mut verifier = Verifier::new(expect_seed);
+        let mut buf = vec![0; WRITE_SIZE];
+
+        loop {
+            let read = match settles {
+                true => tokio::time::timeout(SETTLE, reader.read(&mut buf)).await,
+                false => Ok(reader.read(&mut buf).await),
+            };
+
+            match read {
+                Ok(Ok(0)) => break,
+                Ok(Ok(n)) => verifier.check(&buf[..n]),
+                Ok(Err(e)) => return Err(e),
+                Err(_) if done_sending.load(Ordering::SeqCst) => break,
+                Err(_) => continue,
+            }
+        }
+
+        Ok(verifier.way)
+    };
+
+    tokio::try_join!(sending, receiving)
+}
+
+// The PPP end: a remote backend that takes the one call.
+async fn verifying_backend(options: SoakOptions) -> io::Result<(u16, tokio::task::JoinHandle<io::Result<(u64, Way)>>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+
+    let backend = tokio::spawn(async move {
+        let (ppp, _) = listener.accept().await?;
+        ppp.set_nodelay(true)?;
+
+        run_end(ppp, DOWN_SEED, UP_SEED, options, false).await
+    });
+
+    Ok((port, backend))
+}
+
+// Gets through the init string and dial, up to the last byte of CONNECT, so the first byte after it is the
+// first byte of the PPP end's sequence.
+async fn dial(modem: &mut TcpStream) -> Result<(), String> {
+    for step in &CALL {
+        modem.write_all(step.send.as_bytes()).await.map_err(|e| format!("{}: couldn't send: {e}", step.name))?;
+
+        let (received, problem) = read_expected(modem, step.expect.len()).await;
+        if received != step.expect {
+            return Err(format!("{}: got '{}'{}", step.name, String::from_utf8_lossy(&received).escape_debug(), problem.map(|p| format!(" then {p}")).unwrap_or_default()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts a TouchPPP with the built-in verifying backend, calls it, and keeps both ways busy for
+/// `options.duration`, checking every byte.
+pub async fn soak(options: SoakOptions) -> Result<SoakReport, Box<dyn std::error::Error>> {
+    let (backend_port, backend) = verifying_backend(options).await?;
+
+    let mut builder = Config::builder().listen("127.0.0.1:0").connect(format!("127.0.0.1:{backend_port}"));
+    if let Some(throttle) = options.throttle {
+        builder = builder.throttle(throttle);
+    }
+    if let Some(coalesce_bytes) = options.coalesce_bytes {
+        builder = builder.coalesce_bytes(coalesce_bytes);
+    }
+
+    let server = Server::bind(builder.build()?).await.map_err(|e| e.to_string())?;
+    let address = server.local_addr().ok_or("the soak server isn't listening on TCP")?;
+
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let soaking = async {
+        let soaked = async {
+            let mut modem = TcpStream::connect(address).await?;
+            modem.set_nodelay(true)?;
+            dial(&mut modem).await?;
+
+            let (up_sent, down) = run_end(&mut modem, UP_SEED, DOWN_SEED, options, true).await?;
+
+            // Hanging up is what lets the PPP end finish.
+            drop(modem);
+            let (down_sent, up) = backend.await??;
+
+            Ok::<_, Box<dyn std::error::Error>>(SoakReport {
+                up: Way { sent: up_sent, ..up },
+                down: Way { sent: down_sent, ..down },
+            })
+        }.await;
+
+        let _ = stop.send(());
+
+        soaked
+    };
+
+    let (_, soaked) = tokio::join!(server.run_until(async { let _ = stopped.await; }), soaking);
+
+    soaked
+}
+
+/// Seconds, or a number with s, m or h after it, like 90, 90s, 10m or 2h.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &value[number.len()..]),
+        None => (value, "s"),
+    };
+
+    let number: u64 = number.parse().map_err(|_| "use seconds, or a number with s, m or h after it".to_string())?;
+    let seconds = match unit {
+        "h" => number * 3600,
+        "m" => number * 60,
+        _ => number,
+    };
+
+    match seconds {
+        0 => Err("it has to be at least 1s".to_string()),
+        seconds => Ok(Duration::from_secs(seconds)),
+    }
+}
+
+/// Bytes per second, with k or m after it for thousands or millions, like 50k.
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let (number, multiplier) = match value.to_ascii_lowercase() {
+        value if value.ends_with('k') => (value.trim_end_matches('k').to_string(), 1_000),
+        value if value.ends_with('m') => (value.trim_end_matches('m').to_string(), 1_000_000),
+        value => (value, 1),
+    };
+
+    match number.parse::<u64>() {
+        Ok(0) => Err("it has to be at least 1".to_string()),
+        Ok(number) => Ok(number * multiplier),
+        Err(_) => Err("use bytes per second, with k or m after it for thousands or millions".to_string()),
+    }
+}
+
+/// Soaks for `options.duration`, printing the report, and fails if anything didn't come through intact.
+#[tokio::main]
+pub async fn run(options: SoakOptions) -> Result<(), StartError> {
+    println!("Soaking for {}s at {} bytes per second each way...", options.duration.as_secs(), options.rate);
+
+    // Everything that's sent still gets checked, it just takes the throttle's time to get there.
+    if let Some(throttle) = options.throttle.filter(|&throttle| u64::from(throttle) / 8 < options.rate) {
+        let draining = (options.rate * options.duration.as_secs()).div_ceil(u64::from(throttle) / 8).saturating_sub(options.duration.as_secs());
+
+        println!("--throttle {throttle} lets less than that through, so it'll take about {draining}s more to get the rest across.");
+    }
+
+    let report = soak(options).await.map_err(|e| StartError::Runtime(format!("soak failed: {e}").into()))?;
+
+    println!("{report}");
+
+    match report.is_intact() {
+        true => {
+            println!("Every byte made it through intact.");
+
+            Ok(())
+        },
+        false => Err(StartError::Runtime("soak found bytes that didn't make it through intact".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_pattern_is_the_same_however_its_read() {
+        let mut whole = vec![0; 100];
+        Pattern::new(UP_SEED).fill(&mut whole);
+
+        let mut pieces = Pattern::new(UP_SEED);
+        let mut read = Vec::new();
+        for size in [1, 7, 8, 13, 71] {
+            let mut piece = vec![0; size];
+            pieces.fill(&mut piece);
+            read.extend(piece);
+        }
+
+        assert_eq!(&read[..100], &whole[..]);
+
+        // And the two ways aren't the same sequence.
+        let mut down = vec![0; 100];
+        Pattern::new(DOWN_SEED).fill(&mut down);
+        assert_ne!(down, whole);
+    }
+
+    #[test]
+    fn the_verifier_says_where_bytes_went_wrong() {
+        let mut sent = vec![0; 50];
+        Pattern::new(UP_SEED).fill(&mut sent);
+        sent[10] ^= 0xff;
+        sent[42] ^= 0x01;
+
+        let mut verifier = Verifier::new(UP_SEED);
+        verifier.check(&sent[..30]);
+        verifier.check(&sent[30..]);
+
+        let way = Way { sent: 52, ..verifier.way };
+        assert_eq!(way.received, 50);
+        assert_eq!(way.mismatches, 2);
+        assert_eq!(way.first_mismatches.iter().map(|mismatch| mismatch.offset).collect::<Vec<_>>(), [10, 42]);
+        assert_eq!(way.first_mismatches[0].got, way.first_mismatches[0].expected ^ 0xff);
+        assert_eq!(way.missing(), 2);
+        assert!(!way.is_intact());
+        assert!(way.to_string().ends_with("\n    2 bytes never showed up"), "{way}");
+    }
+
+    #[test]
+    fn durations_and_rates_take_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("soon").is_err());
+
+        assert_eq!(parse_rate("50k"), Ok(50_000));
+        assert_eq!(parse_rate("2M"), Ok(2_000_000));
+        assert_eq!(parse_rate("1200"), Ok(1200));
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[tokio::test]
+    async fn a_short_soak_comes_through_intact() {
+        for (throttle, coalesce_bytes) in [(None, None), (Some(115200), Some(0))] {
+            let report = soak(SoakOptions { duration: Duration::from_secs(1), rate: 20_000, throttle, coalesce_bytes }).await.unwrap();
+
+            assert!(report.is_intact(), "{report}");
+            assert!(report.up.sent > 10_000 && report.down.sent > 10_000, "{report}");
+        }
+    }
+}
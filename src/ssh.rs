@@ -0,0 +1,328 @@
+// --remote-ssh: reaching a remote backend's PPP servers through an SSH server, for PPP that's only reachable from
+// there. Each call gets a direct-tcpip channel (what `ssh -W` opens) over a connection that's logged in with
+// --ssh-key or the agent and checked against known_hosts. libssh2 doesn't do async, so each channel is pumped on a
+// thread of its own and handed to the bridge as one end of a socket pair.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use ssh2::{Channel, CheckResult, ErrorCode, KnownHostFileKind, Session};
+use tracing::{debug, info, warn};
+
+use crate::address::{RemoteAddr, SshAddr};
+use crate::backend::BackendStream;
+use crate::bridge;
+use crate::config::RemotePpp;
+use crate::error::TouchPppError;
+use crate::listener;
+
+// libssh2's codes for "try again" and for the SSH server saying no to a channel.
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+const LIBSSH2_ERROR_CHANNEL_FAILURE: i32 = -21;
+
+// How long a pump waits on its sockets before trying anyway. On a kept connection libssh2 can read ahead for
+// another call's channel, which wouldn't wake this one.
+const PUMP_WAIT_MS: u16 = 10;
+
+// How long to wait between tries at opening a channel while another call has the connection busy.
+const OPEN_RETRY: Duration = Duration::from_millis(5);
+
+// A logged-in connection to the SSH server, with its socket kept alongside for the pumps to wait on.
+#[derive(Clone)]
+struct Connection {
+    session: Session,
+    socket: Arc<TcpStream>,
+}
+
+/// The SSH server from --remote-ssh and how to log in to it, shared by every remote backend. Nothing's connected
+/// until the first dial.
+pub struct Tunnel {
+    pub server: SshAddr,
+    pub key: Option<PathBuf>,
+    pub is_insecure: bool,
+    pub is_persistent: bool,
+    // The network interface to reach the SSH server over, from --bind-device.
+    pub bind_device: Option<String>,
+    // The connection kept between dials with --ssh-persist.
+    kept: Mutex<Option<Connection>>,
+}
+
+impl Tunnel {
+    pub fn new(server: SshAddr, key: Option<PathBuf>, is_insecure: bool, is_persistent: bool) -> Tunnel {
+        Tunnel {
+            server,
+            key,
+            is_insecure,
+            is_persistent,
+            bind_device: None,
+            kept: Mutex::new(None),
+        }
+    }
+
+    pub fn with_bind_device(mut self, device: Option<String>) -> Tunnel {
+        self.bind_device = device;
+        self
+    }
+
+    /// Tries each of the backend's servers in turn through the SSH server, giving up early if it's the SSH
+    /// server that won't have it.
+    pub async fn connect_remote(self: &Arc<Tunnel>, remote_ppp: &RemotePpp, endpoint: Option<&str>) -> Result<BackendStream, TouchPppError> {
+        let mut last_error = TouchPppError::BackendConnect {
+            endpoint: remote_ppp.describe_addresses(),
+            source: io::Error::new(ErrorKind::NotFound, "no remote PPP servers to touch"),
+        };
+
+        for index in crate::backend::try_order(remote_ppp, endpoint) {
+            let remote_socket_address = &remote_ppp.socket_addresses[index];
+
+            match self.open(remote_socket_address, remote_ppp.connect_timeout).await {
+                Ok(ppp) => {
+                    info!("Touched PPP @ {remote_socket_address} through SSH @ {}", self.server);
+                    remote_ppp.last_working.store(index, Ordering::SeqCst);
+
+                    return Ok(ppp.with_endpoint(remote_socket_address.to_string()));
+                },
+                Err(e @ TouchPppError::BackendConnect { .. }) => {
+                    warn!(target: "touchppp::backend", "Couldn't touch PPP @ {remote_socket_address} through SSH @ {}: error={e}", self.server);
+
+                    last_error = e;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Opens a channel to `target` and starts pumping it.
+    pub async fn open(self: &Arc<Tunnel>, target: &RemoteAddr, timeout: Duration) -> Result<BackendStream, TouchPppError> {
+        let tunnel = self.clone();
+        let target = target.clone();
+
+        let (connection, channel) = tokio::task::spawn_blocking(move || tunnel.open_channel(&target, timeout)).await
+            .map_err(|e| TouchPppError::BackendSsh { server: self.server.to_string(), reason: e.to_string() })??;
+
+        let (pumped, ours) = UnixStream::pair()?;
+        ours.set_nonblocking(true)?;
+        let ours = tokio::net::UnixStream::from_std(ours)?;
+
+        std::thread::spawn(move || pump(connection, channel, pumped));
+
+        let (reader, writer) = ours.into_split();
+
+        Ok(BackendStream::new(reader, writer))
+    }
+
+    // Uses the kept connection if there is one and it still works, and logs in again if not.
+    fn open_channel(&self, target: &RemoteAddr, timeout: Duration) -> Result<(Connection, Channel), TouchPppError> {
+        let kept = self.kept.lock().unwrap().clone();
+
+        if let Some(connection) = kept {
+            match self.open_on(&connection, target, timeout) {
+                Ok(channel) => return Ok((connection, channel)),
+                // The PPP server said no, which logging in again won't change.
+                Err(e @ TouchPppError::BackendConnect { .. }) => return Err(e),
+                Err(e) => {
+                    warn!(target: "touchppp::backend", "The connection to SSH @ {} stopped working, logging in again: error={e}", self.server);
+
+                    *self.kept.lock().unwrap() = None;
+                },
+            }
+        }
+
+        let connection = self.connect(target, timeout)?;
+        let channel = self.open_on(&connection, target, timeout)?;
+
+        if self.is_persistent {
+            *self.kept.lock().unwrap() = Some(connection.clone());
+        }
+
+        Ok((connection, channel))
+    }
+
+    fn connect(&self, target: &RemoteAddr, timeout: Duration) -> Result<Connection, TouchPppError> {
+        info!("Logging in to SSH @ {}", self.server);
+
+        let socket = connect_socket(&self.server.server, self.bind_device.as_deref(), timeout)
+            .map_err(|source| TouchPppError::BackendConnect { endpoint: format!("{target} through {}", self.server), source })?;
+
+        let mut session = Session::new().map_err(|e| self.refused(e.message()))?;
+        session.set_timeout(timeout.as_millis().try_into().unwrap_or(u32::MAX));
+        session.set_tcp_stream(socket.try_clone()?);
+        session.handshake().map_err(|e| self.refused(e.message()))?;
+
+        self.check_host_key(&session)?;
+
+        match &self.key {
+            Some(key) => session.userauth_pubkey_file(&self.server.user, None, key, None),
+            None => session.userauth_agent(&self.server.user),
+        }.map_err(|e| self.refused(&format!("couldn't log in as {}: {}", self.server.user, e.message())))?;
+
+        debug!(target: "touchppp::backend", "Logged in to SSH @ {}", self.server);
+
+        // From here on every call's pump shares it.
+        session.set_blocking(false);
+
+        Ok(Connection { session, socket: Arc::new(socket) })
+    }
+
+    fn check_host_key(&self, session: &Session) -> Result<(), TouchPppError> {
+        if self.is_insecure {
+            return Ok(());
+        }
+
+        let refused = |reason: String| self.refused(&format!("{reason} (--ssh-insecure skips this check)"));
+
+        let Some(known_hosts_file) = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".ssh").join("known_hosts")) else {
+            return Err(refused("there's no home directory to find known_hosts in".to_string()));
+        };
+
+        let mut known_hosts = session.known_hosts().map_err(|e| self.refused(e.message()))?;
+        known_hosts.read_file(&known_hosts_file, KnownHostFileKind::OpenSSH)
+            .map_err(|e| refused(format!("can't read {}: {}", known_hosts_file.display(), e.message())))?;
+
+        let Some((key, _)) = session.host_key() else {
+            return Err(refused("it didn't send a host key".to_string()));
+        };
+
+        let server = &self.server.server;
+        match known_hosts.check_port(&server.host, server.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => Err(refused(format!("its host key isn't in {}", known_hosts_file.display()))),
+            CheckResult::Mismatch => Err(refused(format!("its host key doesn't match the one in {}!", known_hosts_file.display()))),
+            CheckResult::Failure => Err(refused("its host key couldn't be checked".to_string())),
+        }
+    }
+
+    // Waits out any other call that has the connection busy.
+    fn open_on(&self, connection: &Connection, target: &RemoteAddr, timeout: Duration) -> Result<Channel, TouchPppError> {
+        let started = Instant::now();
+        let endpoint = || format!("{target} through {}", self.server);
+
+        loop {
+            match connection.session.channel_direct_tcpip(&target.host, target.port, None) {
+                Ok(channel) => return Ok(channel),
+                Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) && started.elapsed() < timeout => std::thread::sleep(OPEN_RETRY),
+                Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+                    return Err(TouchPppError::BackendConnect { endpoint: endpoint(), source: io::Error::new(ErrorKind::TimedOut, "the SSH server didn't open a channel in time") });
+                },
+                Err(e) if e.code() == ErrorCode::Session(LIBSSH2_ERROR_CHANNEL_FAILURE) => {
+                    return Err(TouchPppError::BackendConnect { endpoint: endpoint(), source: io::Error::new(ErrorKind::ConnectionRefused, e.message()) });
+                },
+                Err(e) => return Err(self.refused(e.message())),
+            }
+        }
+    }
+
+    fn refused(&self, reason: &str) -> TouchPppError {
+        TouchPppError::BackendSsh { server: self.server.to_string(), reason: reason.to_string() }
+    }
+}
+
+fn connect_socket(server: &RemoteAddr, bind_device: Option<&str>, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(ErrorKind::NotFound, format!("{} has no addresses", server.host));
+
+    for address in server.target().to_socket_addrs()? {
+        let connected = || {
+            let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+            if let Some(device) = bind_device {
+                listener::bind_device(SockRef::from(&socket), device)?;
+            }
+            socket.connect_timeout(&address.into(), timeout)?;
+
+            Ok::<_, io::Error>(TcpStream::from(socket))
+        };
+
+        match connected() {
+            Ok(socket) => return Ok(socket),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+// Copies between the channel and the bridge's end of the socket pair until either one's done. Neither side
+// blocks, so whichever way has something to send gets its turn and the rest of the time is spent in poll.
+fn pump(connection: Connection, mut channel: Channel, mut bridge_end: UnixStream) {
+    if let Err(e) = bridge_end.set_nonblocking(true) {
+        warn!(target: "touchppp::backend", "Can't pump the SSH channel: error={e}");
+        return;
+    }
+
+    let mut buf = vec![0; bridge::BUFFER_SIZE];
+    let mut to_box = Vec::new();
+    let mut to_server = Vec::new();
+
+    loop {
+        let mut is_busy = false;
+
+        if to_box.is_empty() {
+            match channel.read(&mut buf) {
+                Ok(0) if channel.eof() => break,
+                Ok(n) => to_box.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(e) => {
+                    debug!(target: "touchppp::backend", "The SSH channel failed: error={e}");
+                    break;
+                },
+            }
+        }
+
+        if !to_box.is_empty() {
+            match bridge_end.write(&to_box) {
+                Ok(n) => {
+                    to_box.drain(..n);
+                    is_busy = true;
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(_) => break,
+            }
+        }
+
+        if to_server.is_empty() {
+            match bridge_end.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => to_server.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(_) => break,
+            }
+        }
+
+        if !to_server.is_empty() {
+            match channel.write(&to_server) {
+                Ok(n) => {
+                    to_server.drain(..n);
+                    is_busy = true;
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(e) => {
+                    debug!(target: "touchppp::backend", "The SSH channel failed: error={e}");
+                    break;
+                },
+            }
+        }
+
+        if is_busy {
+            continue;
+        }
+
+        // Only wait on what there's room for, or a full buffer would wake this straight back up.
+        let readable = |is_wanted: bool| if is_wanted { PollFlags::POLLIN } else { PollFlags::empty() };
+        let writable = |is_wanted: bool| if is_wanted { PollFlags::POLLOUT } else { PollFlags::empty() };
+        let mut fds = [
+            PollFd::new(connection.socket.as_fd(), readable(to_box.is_empty()) | writable(!to_server.is_empty())),
+            PollFd::new(bridge_end.as_fd(), readable(to_server.is_empty()) | writable(!to_box.is_empty())),
+        ];
+        let _ = poll(&mut fds, PollTimeout::from(PUMP_WAIT_MS));
+    }
+
+    let _ = channel.close();
+}
@@ -0,0 +1,1346 @@
+// Who's connected and how much has gone through, readable while sessions are running. Anything that reports on
+// a running TouchPPP (SIGUSR1, the admin socket, the status page) takes a Snapshot instead of poking at the
+// sessions directly, anything that wants a session to do something sends it a SessionCommand, and anything that
+// wants to hear about sessions coming and going (webhooks) gets SessionEvents. Every dial, and how each call ended,
+// goes to the --dial-log too.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use serde::Serialize;
+use tokio::sync::{mpsc, watch, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::at;
+use crate::cdr::{self, CdrLog};
+use crate::config::normalize_number;
+use crate::diallog::{self, DialLog, Record};
+use crate::error::TouchPppError;
+use crate::flood::PendingSlot;
+
+// How many dial outcomes to remember for snapshots.
+const RECENT_DIALS: usize = 20;
+
+// How often each session's byte counters are sampled for its peak rates.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// How many different command lines we couldn't make sense of to keep, to see what odd firmware sends.
+const UNKNOWN_AT_SAMPLES: usize = 10;
+
+// The dial outcome for a call that went through. Anything else is a failed dial.
+pub const CONNECTED: &str = "CONNECT";
+
+// What MAME is told a call that went through connected at, unless --connect-speed says otherwise.
+pub const CONNECT_SPEED: u32 = crate::at::DEFAULT_CONNECT_SPEED;
+
+// The dial outcome when the backend's health check says it's down.
+pub const BUSY: &str = "BUSY";
+
+// The dial outcomes for a number that's failed too often lately, or is never dialed.
+pub const DELAYED: &str = "DELAYED";
+pub const BLACKLISTED: &str = "BLACKLISTED";
+
+// What the metrics call the modem when the config file doesn't have [[modem]] sections to name it.
+pub const DEFAULT_MODEM: &str = "default";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SessionState {
+    // Taking AT commands.
+    Command,
+    // Bridged to PPP.
+    Online,
+    // Bridged to PPP, but an admin's holding both ways still.
+    Paused,
+}
+
+impl SessionState {
+    // Whether there's a call up, paused or not.
+    pub fn has_call(&self) -> bool {
+        matches!(self, SessionState::Online | SessionState::Paused)
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionState::Command => write!(f, "command"),
+            SessionState::Online => write!(f, "online"),
+            SessionState::Paused => write!(f, "paused"),
+        }
+    }
+}
+
+// Things a session's own task is asked to do.
+#[derive(Debug)]
+pub enum SessionCommand {
+    // Hang up with NO CARRIER, the same way it would if PPP went away.
+    Kill,
+    // Drop the carrier on the call that's up, the way --drop-after does, leaving the session in command mode.
+    DropCarrier,
+    // Send these bytes to one end of the call that's up, between whatever else is going that way.
+    Inject(InjectTo, Vec<u8>),
+    // Stop the call that's up where it is, without dropping the carrier, and carry on again.
+    Pause,
+    Resume,
+}
+
+// Which end of a call injected bytes are for.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InjectTo {
+    Mame,
+    Backend,
+}
+
+impl fmt::Display for InjectTo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InjectTo::Mame => write!(f, "mame"),
+            InjectTo::Backend => write!(f, "backend"),
+        }
+    }
+}
+
+// The bridge's ends of a call's inject queues, toward MAME and toward the backend.
+pub struct InjectQueues {
+    pub mame: mpsc::UnboundedReceiver<Vec<u8>>,
+    pub backend: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+// The session's ends of them.
+struct Injectors {
+    mame: mpsc::UnboundedSender<Vec<u8>>,
+    backend: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+// One MAME connection. The byte counters are bumped by the copy loops as data goes by, not when they finish.
+pub struct Session {
+    pub id: u64,
+    pub client: String,
+    // The modem MAME called, by its [[modem]] name.
+    pub modem: String,
+    // Who's calling, once an init string says.
+    pub caller: Mutex<Option<at::Caller>>,
+    pub started: Instant,
+    pub state: Mutex<SessionState>,
+    pub dial: Mutex<Option<Dial>>,
+    // The remote PPP server the latest call that connected went to, if it went to one.
+    pub endpoint: Mutex<Option<String>>,
+    // The DTE rate and carrier speed MAME's told on CONNECT.
+    pub connect_speed: AtomicU32,
+    pub carrier_speed: AtomicU32,
+    // The bits per second the call's held to each way, or 0 for a way that isn't.
+    pub throttle_down: AtomicU32,
+    pub throttle_up: AtomicU32,
+    // Why the session ended, once it has, and whether that was an error.
+    pub end_reason: Mutex<Option<String>>,
+    pub failed: AtomicBool,
+    // What IPCP settled on, for the latest call that got that far.
+    pub ip: Mutex<Option<NegotiatedIp>>,
+    // The dial log's attempt id for the call that's up and where it stood when it connected, until it's logged
+    // as over.
+    call: Mutex<Option<(String, Mark)>>,
+    commands: mpsc::Sender<SessionCommand>,
+    // Wakes the bridge when it's asked to drop the carrier. Nobody's waiting unless a call is up.
+    pub carrier_drop: Notify,
+    // Where injected bytes go while a call is up.
+    injectors: Mutex<Option<Injectors>>,
+    // True while an admin has the call paused.
+    paused: watch::Sender<bool>,
+    // MAME to PPP.
+    pub bytes_up: AtomicU64,
+    // PPP to MAME.
+    pub bytes_down: AtomicU64,
+    // The fastest either way over one heartbeat, in bytes per second.
+    pub peak_up: AtomicU64,
+    pub peak_down: AtomicU64,
+    // The byte counters as of the last heartbeat.
+    last_sample: Mutex<(Instant, u64, u64)>,
+}
+
+// Where a call's counters stood when it went online, to work out how it did once it's over.
+#[derive(Clone, Copy, Debug)]
+pub struct Mark {
+    at: Instant,
+    bytes_up: u64,
+    bytes_down: u64,
+}
+
+// How a call did each way: how many bytes, and how fast in bytes per second. The peaks are the session's.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Throughput {
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub average_up: u64,
+    pub average_down: u64,
+    pub peak_up: u64,
+    pub peak_down: u64,
+}
+
+#[derive(Clone)]
+pub struct Dial {
+    pub session: u64,
+    pub number: String,
+    pub backend: String,
+    // What MAME was told, like CONNECT or BUSY.
+    pub outcome: String,
+    pub at: Instant,
+}
+
+// Everything here is only ever added to (the sessions map and claimed backends aside), so the totals are all plain atomics and
+// bumping one never waits on a reporter.
+pub struct Stats {
+    started: Instant,
+    started_at: SystemTime,
+    sessions: Mutex<BTreeMap<u64, Arc<Session>>>,
+    total_sessions: AtomicU64,
+    // The last session id handed out. Every modem takes them from here, so they're unique across all of them.
+    last_session_id: AtomicU64,
+    // How many session ids --exit-after-sessions lets us hand out, or 0 for as many as we like, and a token
+    // that's cancelled once the last one's gone.
+    session_limit: AtomicU64,
+    out_of_sessions: CancellationToken,
+    // Sessions that ended in an error.
+    failed_sessions: AtomicU64,
+    // Every session so far, by the modem MAME called.
+    modem_sessions: Mutex<BTreeMap<String, u64>>,
+    // Only sessions that have hung up. Snapshots add in the live ones.
+    finished_bytes_up: AtomicU64,
+    finished_bytes_down: AtomicU64,
+    recent_dials: Mutex<VecDeque<Dial>>,
+    dials_connected: AtomicU64,
+    dials_busy: AtomicU64,
+    dials_failed: AtomicU64,
+    // Of the busy ones, how many were for an exclusive backend another session had.
+    dials_in_use: AtomicU64,
+    // The exclusive backends that have a call on them, by name. Names outlast a reload where the backends don't.
+    claimed_backends: Mutex<BTreeSet<String>>,
+    // Connections --accept-rate or --max-pending closed before they got a session.
+    refused_accepts: AtomicU64,
+    // How many calls --drop-after has hung up on.
+    carrier_drops: AtomicU64,
+    // Every AT command we've been sent by name, and the first few command lines that weren't well formed.
+    at_commands: Mutex<BTreeMap<String, u64>>,
+    unknown_at: Mutex<Vec<String>>,
+    // When the accept loop last went around, in milliseconds since started. 0 until it starts.
+    heartbeat: AtomicU64,
+    // Whether --enable-inject lets the admin interface inject bytes into calls.
+    inject_enabled: AtomicBool,
+    // Cancelled once we're asked to drain: no new calls, and stop once the ones that are up have ended.
+    draining: CancellationToken,
+    events: Option<mpsc::Sender<SessionEvent>>,
+    dial_log: Option<DialLog>,
+    cdrs: Option<CdrLog>,
+}
+
+// Something that happened to a session, for anyone outside that wants to know.
+#[derive(Serialize, Clone, Debug)]
+pub struct SessionEvent {
+    // connect, dial_failed, ip_up or disconnect.
+    pub event: &'static str,
+    pub session: u64,
+    pub client: String,
+    pub caller: Option<at::Caller>,
+    pub number: Option<String>,
+    pub backend: Option<String>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub duration_ms: u64,
+    // Why a dial failed or the session ended.
+    pub reason: Option<String>,
+    // On ip_up, and on disconnect if the call got that far.
+    #[serde(flatten)]
+    pub ip: Option<NegotiatedIp>,
+    // Only on disconnect.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+}
+
+// The addresses IPCP settled on: the box's, the other end's, and the name servers the box was given (if it asked).
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct NegotiatedIp {
+    pub client_ip: Ipv4Addr,
+    pub server_ip: Ipv4Addr,
+    pub dns: Vec<Ipv4Addr>,
+}
+
+// How a session went, once it's over.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub session: u64,
+    pub client: String,
+    pub caller: Option<at::Caller>,
+    pub number: Option<String>,
+    pub backend: Option<String>,
+    // Only if a dial went through, and the throttle only if it was held to one.
+    pub connect_speed: Option<u32>,
+    pub carrier_speed: Option<u32>,
+    pub throttle_down: Option<u32>,
+    pub throttle_up: Option<u32>,
+    pub duration_ms: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    // Bytes per second over the whole session.
+    pub average_up: u64,
+    pub average_down: u64,
+    pub peak_up: u64,
+    pub peak_down: u64,
+    // Only if PPP got as far as IPCP.
+    #[serde(flatten)]
+    pub ip: Option<NegotiatedIp>,
+    pub reason: String,
+}
+
+// Keeps a session listed for as long as it's held, and folds its counters into the totals when dropped, so a
+// session task that dies early doesn't stay listed forever.
+pub struct SessionGuard {
+    stats: Arc<Stats>,
+    session: Arc<Session>,
+    // Counted against --max-pending until MAME sends its first command.
+    pending: Mutex<Option<PendingSlot>>,
+}
+
+impl std::ops::Deref for SessionGuard {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl SessionGuard {
+    // The session itself, for a task that runs alongside it and can't borrow the guard.
+    pub fn shared(&self) -> Arc<Session> {
+        self.session.clone()
+    }
+
+    // Whether we're draining, so nothing new should start.
+    pub fn is_draining(&self) -> bool {
+        self.stats.is_draining()
+    }
+
+    pub fn set_end_reason(&self, reason: &str) {
+        *self.session.end_reason.lock().unwrap() = Some(reason.to_string());
+    }
+
+    // The same, for a session that ended because something went wrong.
+    pub fn set_error_reason(&self, reason: &str) {
+        self.set_end_reason(reason);
+        self.session.failed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn record_dial(&self, number: &str, backend: &str, outcome: &str) {
+        self.stats.record_dial(&self.session, number, backend, outcome);
+    }
+
+    // A dial the backend couldn't take, because of `e`.
+    pub fn record_failed_dial(&self, number: &str, backend: &str, e: &TouchPppError) {
+        self.stats.record_dial_as(&self.session, number, backend, &e.to_string(), diallog::outcome_of_error(e));
+    }
+
+    // Logs the call that's up as over, if there is one. One that's still up when the session ends is logged then,
+    // with the session's end reason.
+    pub fn end_call(&self, reason: &str) {
+        self.stats.end_call(&self.session, reason);
+    }
+
+    // Takes an exclusive backend for this session until the claim's dropped, or None (counting it) if another
+    // session already has it.
+    pub fn claim_backend(&self, backend: &str) -> Option<BackendClaim> {
+        if !self.stats.claimed_backends.lock().unwrap().insert(backend.to_string()) {
+            self.stats.dials_in_use.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+
+        Some(BackendClaim {
+            stats: self.stats.clone(),
+            backend: backend.to_string(),
+        })
+    }
+
+    // Works out who's calling from `at_string`, giving it back if that's news. A generic init string can still be
+    // followed by one that says more.
+    pub fn spot_caller(&self, at_string: &str) -> Option<at::Caller> {
+        let mut caller = self.session.caller.lock().unwrap();
+        if caller.is_some_and(|caller| caller != at::Caller::Generic) {
+            return None;
+        }
+
+        let spotted = at::caller(at_string).filter(|spotted| Some(*spotted) != *caller)?;
+        *caller = Some(spotted);
+        info!(event = "caller", "Session {} looks like {spotted}.", self.session.id);
+
+        Some(spotted)
+    }
+
+    pub fn record_at(&self, at_string: &str) {
+        self.pending.lock().unwrap().take();
+
+        self.stats.record_at(at_string);
+    }
+
+    // Holds `pending` until the first command's recorded, or the session's over if there never is one.
+    pub fn hold_pending(&self, pending: PendingSlot) {
+        *self.pending.lock().unwrap() = Some(pending);
+    }
+
+    // Samples the session's peak rates every SAMPLE_INTERVAL until `cancel` is, to run alongside the session.
+    pub fn sample_peaks(&self, cancel: CancellationToken) -> impl Future<Output = ()> + Send + 'static {
+        let session = self.session.clone();
+
+        async move {
+            let mut samples = tokio::time::interval(SAMPLE_INTERVAL);
+            samples.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = samples.tick() => session.sample(),
+                    _ = cancel.cancelled() => return,
+                }
+            }
+        }
+    }
+
+    // Keeps what IPCP settled on, logging it and telling anyone who wants to know.
+    pub fn record_ip(&self, ip: NegotiatedIp) {
+        info!(event = "ip_up", client_ip = %ip.client_ip, server_ip = %ip.server_ip, "The box is online as {ip}.");
+
+        *self.session.ip.lock().unwrap() = Some(ip);
+
+        self.stats.emit("ip_up", &self.session, None);
+    }
+
+    // Counts a simulated carrier drop, unless `limit` of them have already happened.
+    pub fn take_carrier_drop(&self, limit: Option<u32>) -> bool {
+        self.stats.carrier_drops.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |drops| {
+            limit.is_none_or(|limit| drops < limit as u64).then_some(drops + 1)
+        }).is_ok()
+    }
+}
+
+impl Session {
+    pub fn summary(&self) -> Summary {
+        let dial = self.dial.lock().unwrap().clone();
+        let duration = self.started.elapsed();
+        let bytes_up = self.bytes_up.load(Ordering::SeqCst);
+        let bytes_down = self.bytes_down.load(Ordering::SeqCst);
+
+        let per_second = |bytes: u64| (bytes * 1000).checked_div(duration.as_millis() as u64).unwrap_or(0);
+        let connected = dial.as_ref().is_some_and(|dial| dial.outcome == CONNECTED);
+
+        Summary {
+            session: self.id,
+            client: self.client.clone(),
+            caller: *self.caller.lock().unwrap(),
+            number: dial.as_ref().map(|dial| dial.number.clone()),
+            backend: dial.as_ref().map(|dial| dial.backend.clone()),
+            connect_speed: connected.then(|| self.connect_speed.load(Ordering::SeqCst)),
+            carrier_speed: connected.then(|| self.carrier_speed.load(Ordering::SeqCst)),
+            throttle_down: Some(self.throttle_down.load(Ordering::SeqCst)).filter(|throttle| connected && *throttle > 0),
+            throttle_up: Some(self.throttle_up.load(Ordering::SeqCst)).filter(|throttle| connected && *throttle > 0),
+            duration_ms: duration.as_millis() as u64,
+            bytes_up,
+            bytes_down,
+            average_up: per_second(bytes_up),
+            average_down: per_second(bytes_down),
+            // A session shorter than a sample never got sampled, but then its average is its peak.
+            peak_up: self.peak_up.load(Ordering::SeqCst).max(per_second(bytes_up)),
+            peak_down: self.peak_down.load(Ordering::SeqCst).max(per_second(bytes_down)),
+            ip: self.ip.lock().unwrap().clone(),
+            reason: self.end_reason.lock().unwrap().clone().unwrap_or_else(|| "gone".to_string()),
+        }
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark {
+            at: Instant::now(),
+            bytes_up: self.bytes_up.load(Ordering::SeqCst),
+            bytes_down: self.bytes_down.load(Ordering::SeqCst),
+        }
+    }
+
+    // How the call that went online at `mark` did, up to now.
+    pub fn throughput_since(&self, mark: Mark) -> Throughput {
+        let millis = mark.at.elapsed().as_millis() as u64;
+        let per_second = |bytes: u64| (bytes * 1000).checked_div(millis).unwrap_or(0);
+
+        let bytes_up = self.bytes_up.load(Ordering::SeqCst) - mark.bytes_up;
+        let bytes_down = self.bytes_down.load(Ordering::SeqCst) - mark.bytes_down;
+        let average_up = per_second(bytes_up);
+        let average_down = per_second(bytes_down);
+
+        Throughput {
+            bytes_up,
+            bytes_down,
+            average_up,
+            average_down,
+            peak_up: self.peak_up.load(Ordering::SeqCst).max(average_up),
+            peak_down: self.peak_down.load(Ordering::SeqCst).max(average_down),
+        }
+    }
+
+    // Moves the session on to `state`. Going back to commands lets go of a pause, so the next call isn't born paused.
+    pub fn set_state(&self, state: SessionState) {
+        *self.state.lock().unwrap() = state;
+
+        if state == SessionState::Command {
+            self.paused.send_replace(false);
+        }
+    }
+
+    // Holds the call that's up still. False if there isn't one, or it's already paused.
+    pub fn pause(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if *state != SessionState::Online {
+            return false;
+        }
+
+        *state = SessionState::Paused;
+        self.paused.send_replace(true);
+
+        true
+    }
+
+    // Lets a paused call carry on. False if it isn't paused.
+    pub fn resume(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if *state != SessionState::Paused {
+            return false;
+        }
+
+        *state = SessionState::Online;
+        self.paused.send_replace(false);
+
+        true
+    }
+
+    // Sees the call paused and resumed, for anything that should stop while it's paused.
+    pub fn watch_pause(&self) -> watch::Receiver<bool> {
+        self.paused.subscribe()
+    }
+
+    // New inject queues for the call that's going online, replacing any from the last one.
+    pub fn open_inject_queues(&self) -> InjectQueues {
+        let (mame, mame_queue) = mpsc::unbounded_channel();
+        let (backend, backend_queue) = mpsc::unbounded_channel();
+
+        *self.injectors.lock().unwrap() = Some(Injectors { mame, backend });
+
+        InjectQueues { mame: mame_queue, backend: backend_queue }
+    }
+
+    // Queues `bytes` for one end of the call that's up. False if there isn't one.
+    pub fn inject(&self, to: InjectTo, bytes: Vec<u8>) -> bool {
+        let injectors = self.injectors.lock().unwrap();
+        let Some(injectors) = &*injectors else {
+            return false;
+        };
+
+        let sent = match to {
+            InjectTo::Mame => injectors.mame.send(bytes),
+            InjectTo::Backend => injectors.backend.send(bytes),
+        };
+
+        sent.is_ok()
+    }
+
+    // Folds what went by since the last sample into the peaks.
+    fn sample(&self) {
+        let mut last_sample = self.last_sample.lock().unwrap();
+        let (sampled_at, sampled_up, sampled_down) = *last_sample;
+
+        let now = Instant::now();
+        let bytes_up = self.bytes_up.load(Ordering::SeqCst);
+        let bytes_down = self.bytes_down.load(Ordering::SeqCst);
+
+        let millis = (now - sampled_at).as_millis() as u64;
+        if let Some(rate) = ((bytes_up - sampled_up) * 1000).checked_div(millis) {
+            self.peak_up.fetch_max(rate, Ordering::SeqCst);
+        }
+        if let Some(rate) = ((bytes_down - sampled_down) * 1000).checked_div(millis) {
+            self.peak_down.fetch_max(rate, Ordering::SeqCst);
+        }
+
+        *last_sample = (now, bytes_up, bytes_down);
+    }
+}
+
+// An exclusive backend a session has a call on. Dropping it, however the session ends, lets the next one dial.
+pub struct BackendClaim {
+    stats: Arc<Stats>,
+    backend: String,
+}
+
+impl Drop for BackendClaim {
+    fn drop(&mut self) {
+        self.stats.claimed_backends.lock().unwrap().remove(&self.backend);
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let summary = self.session.summary();
+
+        info!(event = "summary", "{summary}");
+
+        self.stats.end_call(&self.session, &summary.reason);
+
+        if let Some(cdrs) = &self.stats.cdrs {
+            cdrs.write(cdr::Record::new(&self.session, &summary));
+        }
+
+        self.stats.emit_summary(summary);
+
+        self.stats.finished_bytes_up.fetch_add(self.session.bytes_up.load(Ordering::SeqCst), Ordering::SeqCst);
+        self.stats.finished_bytes_down.fetch_add(self.session.bytes_down.load(Ordering::SeqCst), Ordering::SeqCst);
+
+        if self.session.failed.load(Ordering::SeqCst) {
+            self.stats.failed_sessions.fetch_add(1, Ordering::SeqCst);
+        }
+
+        self.stats.sessions.lock().unwrap().remove(&self.session.id);
+    }
+}
+
+impl Stats {
+    pub fn new() -> Arc<Stats> {
+        Stats::with_events(None)
+    }
+
+    // Events are dropped rather than waited on if the receiver falls behind.
+    pub fn with_events(events: Option<mpsc::Sender<SessionEvent>>) -> Arc<Stats> {
+        Stats::with_outputs(events, None, None)
+    }
+
+    // Events, dials written to `dial_log` as they happen, and a CDR written to `cdrs` as each session ends.
+    pub fn with_outputs(events: Option<mpsc::Sender<SessionEvent>>, dial_log: Option<DialLog>, cdrs: Option<CdrLog>) -> Arc<Stats> {
+        Arc::new(Stats {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            sessions: Mutex::new(BTreeMap::new()),
+            total_sessions: AtomicU64::new(0),
+            last_session_id: AtomicU64::new(0),
+            session_limit: AtomicU64::new(0),
+            out_of_sessions: CancellationToken::new(),
+            failed_sessions: AtomicU64::new(0),
+            modem_sessions: Mutex::new(BTreeMap::new()),
+            finished_bytes_up: AtomicU64::new(0),
+            finished_bytes_down: AtomicU64::new(0),
+            recent_dials: Mutex::new(VecDeque::new()),
+            dials_connected: AtomicU64::new(0),
+            dials_busy: AtomicU64::new(0),
+            dials_failed: AtomicU64::new(0),
+            dials_in_use: AtomicU64::new(0),
+            refused_accepts: AtomicU64::new(0),
+            claimed_backends: Mutex::new(BTreeSet::new()),
+            carrier_drops: AtomicU64::new(0),
+            at_commands: Mutex::new(BTreeMap::new()),
+            unknown_at: Mutex::new(Vec::new()),
+            heartbeat: AtomicU64::new(0),
+            inject_enabled: AtomicBool::new(false),
+            draining: CancellationToken::new(),
+            events,
+            dial_log,
+            cdrs,
+        })
+    }
+
+    /// An id for the next session, which no other session sharing these stats has, or None once
+    /// --exit-after-sessions has had all it's getting.
+    pub fn next_session_id(&self) -> Option<u64> {
+        let limit = self.session_limit.load(Ordering::SeqCst);
+
+        let last = self.last_session_id.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            (limit == 0 || last < limit).then_some(last + 1)
+        }).ok()?;
+
+        if last + 1 == limit {
+            self.out_of_sessions.cancel();
+        }
+
+        Some(last + 1)
+    }
+
+    // Stops handing out session ids after `limit`, for --exit-after-sessions.
+    pub fn limit_sessions(&self, limit: u64) {
+        self.session_limit.store(limit, Ordering::SeqCst);
+    }
+
+    // Cancelled once the last session id --exit-after-sessions allows is handed out.
+    pub fn out_of_sessions(&self) -> CancellationToken {
+        self.out_of_sessions.clone()
+    }
+
+    pub fn is_out_of_sessions(&self) -> bool {
+        self.out_of_sessions.is_cancelled()
+    }
+
+    // How many sessions have ended in an error so far.
+    pub fn failed_sessions(&self) -> u64 {
+        self.failed_sessions.load(Ordering::SeqCst)
+    }
+
+    // The receiver is for the session's task, which should keep an eye on it the whole time.
+    pub fn open_session(self: &Arc<Stats>, id: u64, client: &str) -> (SessionGuard, mpsc::Receiver<SessionCommand>) {
+        self.open_session_on(id, client, DEFAULT_MODEM)
+    }
+
+    // The same, for a session on the modem called `modem`.
+    pub fn open_session_on(self: &Arc<Stats>, id: u64, client: &str, modem: &str) -> (SessionGuard, mpsc::Receiver<SessionCommand>) {
+        let (commands, command_receiver) = mpsc::channel(4);
+
+        let session = Arc::new(Session {
+            id,
+            client: client.to_string(),
+            modem: modem.to_string(),
+            caller: Mutex::new(None),
+            started: Instant::now(),
+            state: Mutex::new(SessionState::Command),
+            dial: Mutex::new(None),
+            endpoint: Mutex::new(None),
+            connect_speed: AtomicU32::new(CONNECT_SPEED),
+            carrier_speed: AtomicU32::new(crate::at::DEFAULT_CARRIER_SPEED),
+            throttle_down: AtomicU32::new(0),
+            throttle_up: AtomicU32::new(0),
+            end_reason: Mutex::new(None),
+            failed: AtomicBool::new(false),
+            ip: Mutex::new(None),
+            call: Mutex::new(None),
+            commands,
+            carrier_drop: Notify::new(),
+            injectors: Mutex::new(None),
+            paused: watch::Sender::new(false),
+            bytes_up: AtomicU64::new(0),
+            bytes_down: AtomicU64::new(0),
+            peak_up: AtomicU64::new(0),
+            peak_down: AtomicU64::new(0),
+            last_sample: Mutex::new((Instant::now(), 0, 0)),
+        });
+
+        self.total_sessions.fetch_add(1, Ordering::SeqCst);
+        *self.modem_sessions.lock().unwrap().entry(modem.to_string()).or_default() += 1;
+        self.sessions.lock().unwrap().insert(id, session.clone());
+
+        self.emit("connect", &session, None);
+
+        let guard = SessionGuard {
+            stats: self.clone(),
+            session,
+            pending: Mutex::new(None),
+        };
+
+        (guard, command_receiver)
+    }
+
+    // False if there's no such session, or it hung up before it got the command.
+    pub async fn send(&self, id: u64, command: SessionCommand) -> bool {
+        let commands = match self.sessions.lock().unwrap().get(&id) {
+            Some(session) => session.commands.clone(),
+            None => return false,
+        };
+
+        commands.send(command).await.is_ok()
+    }
+
+    // Lets the admin interface inject bytes into calls, for --enable-inject.
+    pub fn enable_inject(&self) {
+        self.inject_enabled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_inject_enabled(&self) -> bool {
+        self.inject_enabled.load(Ordering::SeqCst)
+    }
+
+    // Asks every modem sharing these stats to drain, for the admin drain command or SIGUSR2.
+    pub fn drain(&self) {
+        self.draining.cancel();
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.is_cancelled()
+    }
+
+    // Cancelled once we're draining.
+    pub fn draining(&self) -> CancellationToken {
+        self.draining.clone()
+    }
+
+    // How many sessions on `modem` have a call up, or on every modem for None.
+    pub fn calls_up(&self, modem: Option<&str>) -> usize {
+        self.sessions.lock().unwrap().values().filter(|session| {
+            modem.is_none_or(|modem| session.modem == modem) && session.state.lock().unwrap().has_call()
+        }).count()
+    }
+
+    // Waits for the CDRs of the sessions that have ended to be written, for just before we exit.
+    pub fn finish_cdrs(&self) {
+        if let Some(cdrs) = &self.cdrs {
+            cdrs.finish();
+        }
+    }
+
+    // A connection that was closed before it got a session.
+    pub fn record_refused_accept(&self) {
+        self.refused_accepts.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Called about once a second by the accept loop.
+    pub fn heartbeat(&self) {
+        self.heartbeat.store((self.started.elapsed().as_millis() as u64).max(1), Ordering::SeqCst);
+    }
+
+    // Whether the accept loop has checked in recently.
+    pub fn is_accepting(&self, max_age: Duration) -> bool {
+        let heartbeat = self.heartbeat.load(Ordering::SeqCst);
+
+        heartbeat > 0 && self.started.elapsed().saturating_sub(Duration::from_millis(heartbeat)) <= max_age
+    }
+
+    // Counts each command in an AT command line (AT on its own counts as AT), keeping the line as a sample if
+    // it isn't well formed.
+    pub fn record_at(&self, at_string: &str) {
+        let tokens = at::tokenize(at_string);
+
+        let mut at_commands = self.at_commands.lock().unwrap();
+        if tokens.is_empty() {
+            *at_commands.entry("AT".to_string()).or_default() += 1;
+        }
+        for token in tokens {
+            *at_commands.entry(token.name).or_default() += 1;
+        }
+        drop(at_commands);
+
+        if at::is_well_formed(at_string) {
+            return;
+        }
+
+        let line = at_string.trim_end_matches(['\x0d', '\x0a']).to_string();
+        let mut unknown_at = self.unknown_at.lock().unwrap();
+        if unknown_at.len() < UNKNOWN_AT_SAMPLES && !unknown_at.contains(&line) {
+            unknown_at.push(line);
+        }
+    }
+
+    pub fn record_dial(&self, session: &Session, number: &str, backend: &str, outcome: &str) {
+        self.record_dial_as(session, number, backend, outcome, diallog::outcome_of(outcome));
+    }
+
+    // `dial_outcome` is what the dial log calls it.
+    fn record_dial_as(&self, session: &Session, number: &str, backend: &str, outcome: &str, dial_outcome: &'static str) {
+        let dial = Dial {
+            session: session.id,
+            number: number.to_string(),
+            backend: backend.to_string(),
+            outcome: outcome.to_string(),
+            at: Instant::now(),
+        };
+
+        *session.dial.lock().unwrap() = Some(dial.clone());
+
+        let count = match outcome {
+            CONNECTED => &self.dials_connected,
+            BUSY => &self.dials_busy,
+            _ => &self.dials_failed,
+        };
+        count.fetch_add(1, Ordering::SeqCst);
+
+        let mut recent_dials = self.recent_dials.lock().unwrap();
+        if recent_dials.len() == RECENT_DIALS {
+            recent_dials.pop_front();
+        }
+        recent_dials.push_back(dial);
+        drop(recent_dials);
+
+        if outcome != CONNECTED {
+            self.emit("dial_failed", session, Some(outcome.to_string()));
+        }
+
+        let Some(dial_log) = &self.dial_log else {
+            return;
+        };
+
+        let connected = outcome == CONNECTED;
+        let attempt = dial_log.next_attempt();
+
+        dial_log.write(&Record::Attempt {
+            attempt: &attempt,
+            ts: diallog::timestamp(),
+            session: session.id,
+            client: &session.client,
+            number: normalize_number(number),
+            backend,
+            outcome: dial_outcome,
+            result: outcome,
+            connect_speed: connected.then(|| session.connect_speed.load(Ordering::SeqCst)),
+            carrier_speed: connected.then(|| session.carrier_speed.load(Ordering::SeqCst)),
+        });
+
+        if connected {
+            *session.call.lock().unwrap() = Some((attempt, session.mark()));
+        }
+    }
+
+    fn end_call(&self, session: &Session, reason: &str) {
+        let (Some(dial_log), Some((attempt, mark))) = (&self.dial_log, session.call.lock().unwrap().take()) else {
+            return;
+        };
+
+        let throughput = session.throughput_since(mark);
+
+        dial_log.write(&Record::Disconnect {
+            attempt: &attempt,
+            ts: diallog::timestamp(),
+            session: session.id,
+            duration_ms: mark.at.elapsed().as_millis() as u64,
+            bytes_up: throughput.bytes_up,
+            bytes_down: throughput.bytes_down,
+            reason,
+        });
+    }
+
+    fn emit(&self, event: &'static str, session: &Session, reason: Option<String>) {
+        if self.events.is_none() {
+            return;
+        }
+
+        let dial = session.dial.lock().unwrap().clone();
+
+        let event = SessionEvent {
+            event,
+            session: session.id,
+            client: session.client.clone(),
+            caller: *session.caller.lock().unwrap(),
+            number: dial.as_ref().map(|dial| dial.number.clone()),
+            backend: dial.as_ref().map(|dial| dial.backend.clone()),
+            bytes_up: session.bytes_up.load(Ordering::SeqCst),
+            bytes_down: session.bytes_down.load(Ordering::SeqCst),
+            duration_ms: session.started.elapsed().as_millis() as u64,
+            reason,
+            ip: session.ip.lock().unwrap().clone(),
+            summary: None,
+        };
+
+        self.send_event(event);
+    }
+
+    fn emit_summary(&self, summary: Summary) {
+        let event = SessionEvent {
+            event: "disconnect",
+            session: summary.session,
+            client: summary.client.clone(),
+            caller: summary.caller,
+            number: summary.number.clone(),
+            backend: summary.backend.clone(),
+            bytes_up: summary.bytes_up,
+            bytes_down: summary.bytes_down,
+            duration_ms: summary.duration_ms,
+            reason: Some(summary.reason.clone()),
+            ip: summary.ip.clone(),
+            summary: Some(summary),
+        };
+
+        self.send_event(event);
+    }
+
+    fn send_event(&self, event: SessionEvent) {
+        let Some(events) = &self.events else {
+            return;
+        };
+
+        let (name, session) = (event.event, event.session);
+        if let Err(e) = events.try_send(event) {
+            warn!("Dropped a {name} event for session {session}: error={e}");
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let now = Instant::now();
+
+        let sessions: Vec<SessionSnapshot> = self.sessions.lock().unwrap().values().map(|session| SessionSnapshot {
+            id: session.id,
+            client: session.client.clone(),
+            modem: session.modem.clone(),
+            caller: *session.caller.lock().unwrap(),
+            state: *session.state.lock().unwrap(),
+            dial: session.dial.lock().unwrap().clone(),
+            bytes_up: session.bytes_up.load(Ordering::SeqCst),
+            bytes_down: session.bytes_down.load(Ordering::SeqCst),
+            duration: now - session.started,
+        }).collect();
+
+        Snapshot {
+            uptime: now - self.started,
+            started_at: self.started_at,
+            total_sessions: self.total_sessions.load(Ordering::SeqCst),
+            modem_sessions: self.modem_sessions.lock().unwrap().clone(),
+            bytes_up: self.finished_bytes_up.load(Ordering::SeqCst) + sessions.iter().map(|s| s.bytes_up).sum::<u64>(),
+            bytes_down: self.finished_bytes_down.load(Ordering::SeqCst) + sessions.iter().map(|s| s.bytes_down).sum::<u64>(),
+            dials: DialCounts {
+                connected: self.dials_connected.load(Ordering::SeqCst),
+                busy: self.dials_busy.load(Ordering::SeqCst),
+                failed: self.dials_failed.load(Ordering::SeqCst),
+                in_use: self.dials_in_use.load(Ordering::SeqCst),
+            },
+            refused_accepts: self.refused_accepts.load(Ordering::SeqCst),
+            sessions,
+            recent_dials: self.recent_dials.lock().unwrap().iter().map(|dial| (dial.clone(), now - dial.at)).collect(),
+            at_commands: self.at_commands.lock().unwrap().clone(),
+            unknown_at: self.unknown_at.lock().unwrap().clone(),
+            draining: self.is_draining().then(|| self.calls_up(None)),
+        }
+    }
+}
+
+pub struct SessionSnapshot {
+    pub id: u64,
+    pub client: String,
+    pub modem: String,
+    pub caller: Option<at::Caller>,
+    pub state: SessionState,
+    pub dial: Option<Dial>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub duration: Duration,
+}
+
+// Every dial since we started, by how it went.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DialCounts {
+    pub connected: u64,
+    pub busy: u64,
+    // Including ones that got CONNECT but then couldn't reach PPP.
+    pub failed: u64,
+    // Of the busy ones, how many were for an exclusive backend that already had a call on it.
+    pub in_use: u64,
+}
+
+pub struct Snapshot {
+    pub uptime: Duration,
+    pub started_at: SystemTime,
+    pub total_sessions: u64,
+    pub modem_sessions: BTreeMap<String, u64>,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+    pub dials: DialCounts,
+    pub refused_accepts: u64,
+    pub sessions: Vec<SessionSnapshot>,
+    // Oldest first, with how long ago each was.
+    pub recent_dials: Vec<(Dial, Duration)>,
+    // AT commands by name, and samples of command lines that weren't well formed.
+    pub at_commands: BTreeMap<String, u64>,
+    pub unknown_at: Vec<String>,
+    // How many calls a drain is still waiting on, once we're draining.
+    pub draining: Option<usize>,
+}
+
+// 1h02m03s, 2m03s or 3s.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s:02}s"),
+        (h, m, s) => format!("{h}h{m:02}m{s:02}s"),
+    }
+}
+
+impl fmt::Display for SessionSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "session {} from {}", self.id, self.client)?;
+
+        match &self.dial {
+            Some(dial) => write!(f, " dialed {} on backend {}", dial.number, dial.backend)?,
+            None => write!(f, " hasn't dialed")?,
+        }
+
+        write!(f, ", {} bytes up, {} bytes down, connected {}", self.bytes_up, self.bytes_down, format_duration(self.duration))
+    }
+}
+
+// Throughput in whatever unit keeps it readable.
+fn format_rate(bytes_per_second: u64) -> String {
+    match bytes_per_second {
+        0..1024 => format!("{bytes_per_second} B/s"),
+        _ => format!("{:.1} KiB/s", bytes_per_second as f64 / 1024.0),
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "average {} up and {} down, peak {} up and {} down",
+            format_rate(self.average_up),
+            format_rate(self.average_down),
+            format_rate(self.peak_up),
+            format_rate(self.peak_down),
+        )
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Session {} from {}", self.session, self.client)?;
+
+        if let Some(caller) = self.caller {
+            write!(f, " ({caller})")?;
+        }
+
+        if let (Some(number), Some(backend)) = (&self.number, &self.backend) {
+            write!(f, " dialed {number} on backend {backend}")?;
+        }
+
+        if let Some(connect_speed) = self.connect_speed {
+            write!(f, " at {connect_speed}")?;
+        }
+
+        if let Some(carrier_speed) = self.carrier_speed {
+            match (self.throttle_down, self.throttle_up) {
+                (Some(down), Some(up)) if down == up => write!(f, " (carrier {carrier_speed}, held to {down} bps)")?,
+                (Some(down), Some(up)) => write!(f, " (carrier {carrier_speed}, held to {down} bps down and {up} up)")?,
+                (Some(down), None) => write!(f, " (carrier {carrier_speed}, held to {down} bps down)")?,
+                (None, Some(up)) => write!(f, " (carrier {carrier_speed}, held to {up} bps up)")?,
+                (None, None) => write!(f, " (carrier {carrier_speed})")?,
+            }
+        }
+
+        if let Some(ip) = &self.ip {
+            write!(f, " as {ip}")?;
+        }
+
+        write!(
+            f,
+            ", lasted {}: {} bytes up (average {}, peak {}), {} bytes down (average {}, peak {}). Ended because: {}.",
+            format_duration(Duration::from_millis(self.duration_ms)),
+            self.bytes_up,
+            format_rate(self.average_up),
+            format_rate(self.peak_up),
+            self.bytes_down,
+            format_rate(self.average_down),
+            format_rate(self.peak_down),
+            self.reason,
+        )
+    }
+}
+
+// 192.168.1.100 (server 192.168.1.1, DNS 192.168.1.1 and 8.8.8.8)
+impl fmt::Display for NegotiatedIp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (server {}", self.client_ip, self.server_ip)?;
+
+        if !self.dns.is_empty() {
+            let dns: Vec<String> = self.dns.iter().map(Ipv4Addr::to_string).collect();
+            write!(f, ", DNS {}", dns.join(" and "))?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Up {}, {} sessions so far, {} active, {} bytes up and {} bytes down in total. Dials: {} connected, {} busy ({} in use), {} failed.",
+            format_duration(self.uptime),
+            self.total_sessions,
+            self.sessions.len(),
+            self.bytes_up,
+            self.bytes_down,
+            self.dials.connected,
+            self.dials.busy,
+            self.dials.in_use,
+            self.dials.failed,
+        )?;
+
+        if self.refused_accepts > 0 {
+            write!(f, " Turned away {} connections.", self.refused_accepts)?;
+        }
+
+        if let Some(calls) = self.draining {
+            write!(f, " Draining, with {calls} calls left.")?;
+        }
+
+        for session in self.sessions.iter() {
+            write!(f, "\n  Active: {session}")?;
+        }
+
+        for (dial, ago) in self.recent_dials.iter() {
+            write!(f, "\n  Dial: session {} dialed {} on backend {}, {} ({} ago)", dial.session, dial.number, dial.backend, dial.outcome, format_duration(*ago))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_adds_up() {
+        let stats = Stats::new();
+        let (session, _commands) = stats.open_session(4, "127.0.0.1:40000");
+
+        stats.record_dial(&session, "18006138199", "default", CONNECTED);
+        session.bytes_up.store(2048, Ordering::SeqCst);
+        session.bytes_down.store(4096, Ordering::SeqCst);
+        session.set_end_reason("MAME hung up");
+
+        let summary = session.summary();
+
+        assert_eq!(summary.number.as_deref(), Some("18006138199"));
+        assert_eq!(summary.backend.as_deref(), Some("default"));
+        assert_eq!(summary.connect_speed, Some(CONNECT_SPEED));
+        assert_eq!(summary.carrier_speed, Some(crate::at::DEFAULT_CARRIER_SPEED));
+        assert_eq!((summary.throttle_down, summary.throttle_up), (None, None));
+        assert_eq!((summary.bytes_up, summary.bytes_down), (2048, 4096));
+        assert!(summary.peak_up >= summary.average_up && summary.peak_down >= summary.average_down);
+        assert_eq!(summary.reason, "MAME hung up");
+    }
+
+    #[tokio::test]
+    async fn totals_survive_sessions_ending_any_which_way() {
+        let stats = Stats::new();
+
+        let (first, _commands) = stats.open_session(1, "127.0.0.1:40001");
+        stats.record_dial(&first, "18006138199", "default", CONNECTED);
+        first.bytes_up.store(100, Ordering::SeqCst);
+        first.bytes_down.store(200, Ordering::SeqCst);
+        drop(first);
+
+        let (second, _commands) = stats.open_session(2, "127.0.0.1:40002");
+        stats.record_dial(&second, "18006138199", "default", BUSY);
+        drop(second);
+
+        let (third, _commands) = stats.open_session(3, "127.0.0.1:40003");
+        stats.record_dial(&third, "18006138199", "default", "can't touch PPP @ 127.0.0.1:2323: connection refused");
+        drop(third);
+
+        // Dies part way through a dial, like a session task that hit a bug.
+        let (fourth, _commands) = stats.open_session(4, "127.0.0.1:40004");
+        let aborted = tokio::spawn(async move {
+            fourth.bytes_up.store(10, Ordering::SeqCst);
+
+            panic!("mid-dial");
+        });
+        assert!(aborted.await.is_err());
+
+        let (_fifth, _commands) = stats.open_session(5, "127.0.0.1:40005");
+
+        let snapshot = stats.snapshot();
+
+        assert_eq!(snapshot.total_sessions, 5);
+        assert_eq!(snapshot.sessions.iter().map(|session| session.id).collect::<Vec<u64>>(), [5]);
+        assert_eq!((snapshot.bytes_up, snapshot.bytes_down), (110, 200));
+        assert_eq!(snapshot.dials, DialCounts { connected: 1, busy: 1, failed: 1, in_use: 0 });
+        assert_eq!(snapshot.recent_dials.last().unwrap().0.outcome, "can't touch PPP @ 127.0.0.1:2323: connection refused");
+    }
+
+    #[test]
+    fn summary_reads_well() {
+        let summary = Summary {
+            session: 4,
+            client: "127.0.0.1:40000".to_string(),
+            caller: None,
+            number: Some("18006138199".to_string()),
+            backend: Some("default".to_string()),
+            connect_speed: Some(CONNECT_SPEED),
+            carrier_speed: Some(31200),
+            throttle_down: None,
+            throttle_up: None,
+            duration_ms: 62_500,
+            bytes_up: 62_500,
+            bytes_down: 250_000,
+            average_up: 1000,
+            average_down: 4000,
+            peak_up: 1500,
+            peak_down: 6144,
+            ip: None,
+            reason: "MAME hung up".to_string(),
+        };
+
+        assert_eq!(
+            summary.to_string(),
+            "Session 4 from 127.0.0.1:40000 dialed 18006138199 on backend default at 115200 (carrier 31200), lasted 1m02s: 62500 bytes up (average 1000 B/s, peak 1.5 KiB/s), 250000 bytes down (average 3.9 KiB/s, peak 6.0 KiB/s). Ended because: MAME hung up.",
+        );
+
+        let ip = NegotiatedIp { client_ip: Ipv4Addr::new(192, 168, 1, 100), server_ip: Ipv4Addr::new(192, 168, 1, 1), dns: vec![Ipv4Addr::new(8, 8, 8, 8)] };
+        let summary = Summary { ip: Some(ip), ..summary };
+
+        assert!(summary.to_string().contains(" at 115200 (carrier 31200) as 192.168.1.100 (server 192.168.1.1, DNS 8.8.8.8), lasted 1m02s"), "{summary}");
+
+        let summary = Summary { caller: Some(at::Caller::WindowsCe), ..summary };
+        assert!(summary.to_string().starts_with("Session 4 from 127.0.0.1:40000 (windows-ce) dialed "), "{summary}");
+
+        let summary = Summary { carrier_speed: Some(53333), throttle_down: Some(53333), throttle_up: Some(33600), ..summary };
+        assert!(summary.to_string().contains(" (carrier 53333, held to 53333 bps down and 33600 up) as "), "{summary}");
+
+        let summary = Summary { throttle_down: None, ..summary };
+        assert!(summary.to_string().contains(" (carrier 53333, held to 33600 bps up) as "), "{summary}");
+    }
+
+    #[test]
+    fn counts_every_at_command_in_a_line() {
+        let stats = Stats::new();
+
+        for at_string in ["AT\r", "AT&F&C1&D2\r", "ATE0Q0V0&C1&D2S0=0\r", "ATS7=60L3\r", "ATDT18006138199\r", "ATD\r", "AT**\r", "AT**\r"] {
+            stats.record_at(at_string);
+        }
+
+        let snapshot = stats.snapshot();
+        let counts: Vec<String> = snapshot.at_commands.iter().map(|(name, count)| format!("{name}={count}")).collect();
+
+        assert_eq!(counts, ["&C=2", "&D=2", "&F=1", "*=2", "AT=1", "D=2", "E=1", "L=1", "Q=1", "S=2", "V=1"]);
+        assert_eq!(snapshot.unknown_at, ["AT**"]);
+    }
+
+    #[test]
+    fn keeps_only_a_few_unknown_lines() {
+        let stats = Stats::new();
+
+        for n in 0..UNKNOWN_AT_SAMPLES + 5 {
+            stats.record_at(&format!("AT*{n}\r"));
+        }
+
+        let unknown_at = stats.snapshot().unknown_at;
+        assert_eq!(unknown_at.len(), UNKNOWN_AT_SAMPLES);
+        assert_eq!(unknown_at[0], "AT*0");
+    }
+    #[test]
+    fn hands_out_exactly_as_many_session_ids_as_its_limited_to() {
+        let stats = Stats::new();
+        stats.limit_sessions(5);
+
+        // Everyone at once, so they race for the last few.
+        let mut ids: Vec<u64> = std::thread::scope(|scope| {
+            let takers: Vec<_> = (0..8).map(|_| scope.spawn(|| (0..4).filter_map(|_| stats.next_session_id()).collect::<Vec<_>>())).collect();
+
+            takers.into_iter().flat_map(|taker| taker.join().unwrap()).collect()
+        });
+        ids.sort();
+
+        assert_eq!(ids, [1, 2, 3, 4, 5]);
+        assert!(stats.is_out_of_sessions());
+    }
+
+    #[test]
+    fn counts_sessions_that_ended_in_an_error() {
+        let stats = Stats::new();
+
+        let (session, _commands) = stats.open_session(1, "127.0.0.1:1");
+        session.set_end_reason("MAME hung up");
+        drop(session);
+
+        let (session, _commands) = stats.open_session(2, "127.0.0.1:2");
+        session.set_error_reason("can't talk to MAME: broken pipe");
+        drop(session);
+
+        assert_eq!(stats.failed_sessions(), 1);
+        assert!(!stats.is_out_of_sessions());
+    }
+}
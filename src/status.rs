@@ -0,0 +1,193 @@
+// --status-http: a plain HTML page with what's going on, for folks who'd rather not use the admin socket.
+//
+//   /          the status page, which refreshes itself
+//   /healthz   200 while we're still taking calls, 503 if the accept loop has stopped going around or we're
+//              draining
+//   /metrics   counters in Prometheus's text format
+
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::stats::{self, Stats};
+
+const REFRESH_SECONDS: u64 = 5;
+
+// The accept loop checks in every second; give it some slack before calling it dead.
+const HEARTBEAT_MAX_AGE: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct StatusPage {
+    pub stats: Arc<Stats>,
+    pub config: watch::Receiver<Arc<Config>>,
+    // What we're listening on, as logged at startup.
+    pub listeners: Vec<String>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// A label value the way Prometheus wants it quoted.
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl StatusPage {
+    fn metrics(&self) -> String {
+        let snapshot = self.stats.snapshot();
+
+        let mut metrics = String::new();
+
+        metrics.push_str("# HELP sessions_total Sessions so far, by the modem MAME called.\n# TYPE sessions_total counter\n");
+        for (modem, count) in snapshot.modem_sessions.iter() {
+            let _ = writeln!(metrics, "sessions_total{{modem=\"{}\"}} {count}", label(modem));
+        }
+
+        metrics.push_str("# HELP sessions_active Sessions going on now, by the modem MAME called.\n# TYPE sessions_active gauge\n");
+        for modem in snapshot.modem_sessions.keys() {
+            let active = snapshot.sessions.iter().filter(|session| &session.modem == modem).count();
+            let _ = writeln!(metrics, "sessions_active{{modem=\"{}\"}} {active}", label(modem));
+        }
+
+        metrics.push_str("# HELP at_commands_total AT commands MAME has sent, by command.\n# TYPE at_commands_total counter\n");
+        for (command, count) in snapshot.at_commands.iter() {
+            let _ = writeln!(metrics, "at_commands_total{{command=\"{}\"}} {count}", label(command));
+        }
+
+        metrics
+    }
+
+    fn html(&self) -> String {
+        let snapshot = self.stats.snapshot();
+        let config = self.config.borrow().clone();
+
+        let mut html = String::new();
+
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<meta http-equiv=\"refresh\" content=\"{REFRESH_SECONDS}\">\n<title>TouchPPP</title>\n</head>\n<body>\n<h1>TouchPPP {}</h1>\n",
+            env!("CARGO_PKG_VERSION"),
+        );
+
+        let _ = writeln!(html, "<p>Up {}. {} sessions so far, {} active. {} bytes up and {} bytes down in total.</p>", stats::format_duration(snapshot.uptime), snapshot.total_sessions, snapshot.sessions.len(), snapshot.bytes_up, snapshot.bytes_down);
+        let _ = writeln!(html, "<p>Dials: {} connected, {} busy ({} in use), {} failed.</p>", snapshot.dials.connected, snapshot.dials.busy, snapshot.dials.in_use, snapshot.dials.failed);
+
+    if snapshot.refused_accepts > 0 {
+        let _ = writeln!(html, "<p>Turned away {} connections.</p>", snapshot.refused_accepts);
+    }
+        if let Some(calls) = snapshot.draining {
+            let _ = writeln!(html, "<p>Draining: taking no new calls, and stopping once the {calls} still up have ended.</p>");
+        }
+        let _ = writeln!(html, "<p>Listening on {}.</p>", escape(&self.listeners.join(", ")));
+
+        let backend = match &config.cli_backend {
+            Some(backend) => format!("Every dial goes to backend {}.", backend.describe()),
+            None => format!("Dials go to backend {} unless one of the {} phone book entries says otherwise.", config.default_backend.describe(), config.phone_book.len()),
+        };
+        let _ = writeln!(html, "<p>{}</p>", escape(&backend));
+
+        html.push_str("<h2>Active sessions</h2>\n<table border=\"1\">\n<tr><th>Session</th><th>Client</th><th>State</th><th>Number</th><th>Backend</th><th>Bytes up</th><th>Bytes down</th><th>Connected</th></tr>\n");
+        for session in snapshot.sessions.iter() {
+            let (number, backend) = match &session.dial {
+                Some(dial) => (dial.number.as_str(), dial.backend.as_str()),
+                None => ("", ""),
+            };
+
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                session.id,
+                escape(&session.client),
+                session.state,
+                escape(number),
+                escape(backend),
+                session.bytes_up,
+                session.bytes_down,
+                stats::format_duration(session.duration),
+            );
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Recent dials</h2>\n<table border=\"1\">\n<tr><th>When</th><th>Session</th><th>Number</th><th>Backend</th><th>Result</th></tr>\n");
+        for (dial, ago) in snapshot.recent_dials.iter().rev() {
+            let _ = writeln!(
+                html,
+                "<tr><td>{} ago</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                stats::format_duration(*ago),
+                dial.session,
+                escape(&dial.number),
+                escape(&dial.backend),
+                escape(&dial.outcome),
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+
+        html
+    }
+
+    fn respond(&self, request: &Request<Incoming>) -> Response<Full<Bytes>> {
+        let (status, content_type, body) = match request.uri().path() {
+            "/" => (StatusCode::OK, "text/html; charset=utf-8", self.html()),
+            "/healthz" if self.stats.is_draining() => (StatusCode::SERVICE_UNAVAILABLE, "text/plain", "Draining, so not taking new calls.\n".to_string()),
+            "/healthz" if self.stats.is_accepting(HEARTBEAT_MAX_AGE) => (StatusCode::OK, "text/plain", "OK\n".to_string()),
+            "/healthz" => (StatusCode::SERVICE_UNAVAILABLE, "text/plain", "The accept loop stopped checking in.\n".to_string()),
+            "/metrics" => (StatusCode::OK, "text/plain; version=0.0.4", self.metrics()),
+            _ => (StatusCode::NOT_FOUND, "text/plain", "Nothing here. Try /\n".to_string()),
+        };
+
+        let mut response = Response::new(Full::new(Bytes::from(body)));
+        *response.status_mut() = status;
+
+        if let Ok(content_type) = content_type.parse() {
+            response.headers_mut().insert(hyper::header::CONTENT_TYPE, content_type);
+        }
+
+        response
+    }
+}
+
+// Runs on its own listener and its own tasks, so a slow browser never holds up MAME.
+pub async fn serve(listener: std::net::TcpListener, page: StatusPage) {
+    let listener = match tokio::net::TcpListener::from_std(listener) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Can't serve the status page: error={e}");
+            return;
+        }
+    };
+
+    loop {
+        let (browser, browser_socket_address) = match listener.accept().await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Stopped serving the status page: error={e}");
+                return;
+            }
+        };
+
+        let page = page.clone();
+
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let response = page.respond(&request);
+
+                async move { Ok::<_, Infallible>(response) }
+            });
+
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(browser), service).await {
+                debug!("Status page request from {browser_socket_address} went wrong: error={e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,356 @@
+// --log-syslog: every log record as a syslog message too, on top of the file or terminal. The local socket gets
+// RFC 3164 since that's what syslog daemons and journald expect on /dev/log; remote servers get RFC 5424 with
+// the session id in the structured data.
+//
+//   <30>Oct 16 20:15:02 touchppp[4242]: [session 1] Looks like we got a wild MAME @ 127.0.0.1:50000
+//   <30>1 2026-10-16T20:15:02.000000Z retro touchppp 4242 connect [touchppp@32473 session="1"] Looks like we got...
+//
+// Messages are sent from their own thread so a syslog server that's slow or gone never holds up a session.
+
+use std::fmt::{self, Write as _};
+use std::io::{self, Write as _};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::address::SyslogAddr;
+use crate::jsonlog::SpanFields;
+
+const TARGET: &str = "touchppp::syslog";
+
+const APP_NAME: &str = "touchppp";
+
+// The enterprise number RFC 5612 set aside for examples, since we don't have one of our own.
+const SD_ID: &str = "touchppp@32473";
+
+// How many messages can be waiting on the syslog server before new ones are dropped.
+const QUEUE_SIZE: usize = 1024;
+
+// How long to wait before trying a syslog server again once sending to it has failed.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+const FACILITIES: [(&str, u8); 20] = [
+    ("kern", 0), ("user", 1), ("mail", 2), ("daemon", 3), ("auth", 4), ("syslog", 5), ("lpr", 6), ("news", 7),
+    ("uucp", 8), ("cron", 9), ("authpriv", 10), ("ftp", 11), ("local0", 16), ("local1", 17), ("local2", 18),
+    ("local3", 19), ("local4", 20), ("local5", 21), ("local6", 22), ("local7", 23),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Facility(u8);
+
+pub const DEFAULT_FACILITY: Facility = Facility(3);
+
+impl std::str::FromStr for Facility {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Facility, String> {
+        match FACILITIES.iter().find(|(name, _)| *name == value) {
+            Some((_, code)) => Ok(Facility(*code)),
+            None => Err("use daemon, user, local0 through local7 or another syslog facility name".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Facility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match FACILITIES.iter().find(|(_, code)| *code == self.0) {
+            Some((name, _)) => write!(f, "{name}"),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+// The message plus any other fields as key=value, the way the text log shows them.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    event: Option<String>,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message = value.to_string(),
+            "event" => self.event = Some(value.to_string()),
+            name => { let _ = write!(self.fields, " {name}={value}"); },
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => self.message = format!("{value:?}"),
+            "event" => self.event = Some(format!("{value:?}").trim_matches('"').to_string()),
+            name => { let _ = write!(self.fields, " {name}={value:?}"); },
+        }
+    }
+}
+
+// One log record, before it's been made into either kind of syslog message.
+pub struct Record<'a> {
+    pub priority: u8,
+    // RFC 3339, like 2026-10-16T20:15:02.000000Z.
+    pub timestamp: &'a str,
+    pub hostname: &'a str,
+    pub pid: u32,
+    pub event: Option<&'a str>,
+    pub session: Option<&'a str>,
+    pub message: &'a str,
+}
+
+// <PRI>Mmm dd hh:mm:ss TAG[PID]: MSG. There's nowhere structured to put the session, so it leads the message.
+pub fn format_rfc3164(record: &Record) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let timestamp = record.timestamp;
+    let month = timestamp.get(5..7).and_then(|month| month.parse::<usize>().ok()).and_then(|month| MONTHS.get(month.wrapping_sub(1)));
+    let day = timestamp.get(8..10).and_then(|day| day.parse::<u8>().ok());
+    let time = timestamp.get(11..19);
+
+    let mut message = format!("<{}>", record.priority);
+
+    if let (Some(month), Some(day), Some(time)) = (month, day, time) {
+        let _ = write!(message, "{month} {day:>2} {time} ");
+    }
+
+    let _ = write!(message, "{APP_NAME}[{}]: ", record.pid);
+
+    if let Some(session) = record.session {
+        let _ = write!(message, "[session {session}] ");
+    }
+
+    message + record.message
+}
+
+// <PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [SD] MSG, with the event name as the MSGID.
+pub fn format_rfc5424(record: &Record) -> String {
+    let structured_data = match record.session {
+        Some(session) => format!("[{SD_ID} session=\"{}\"]", session.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")),
+        None => "-".to_string(),
+    };
+
+    format!(
+        "<{}>1 {} {} {APP_NAME} {} {} {structured_data} {}",
+        record.priority,
+        record.timestamp,
+        record.hostname,
+        record.pid,
+        record.event.unwrap_or("-"),
+        record.message,
+    )
+}
+
+enum Connection {
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+fn connect(target: &SyslogAddr) -> io::Result<Connection> {
+    match target {
+        #[cfg(unix)]
+        SyslogAddr::Unix(path) => {
+            let socket = std::os::unix::net::UnixDatagram::unbound()?;
+            socket.connect(path)?;
+
+            Ok(Connection::Unix(socket))
+        },
+        #[cfg(not(unix))]
+        SyslogAddr::Unix(path) => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{path} is a unix socket"))),
+        SyslogAddr::Udp(remote_address) => {
+            let address = remote_address.target().to_socket_addrs()?.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{remote_address} doesn't resolve")))?;
+
+            let socket = UdpSocket::bind(if address.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" })?;
+            socket.connect(address)?;
+
+            Ok(Connection::Udp(socket))
+        },
+        SyslogAddr::Tcp(remote_address) => {
+            let address = remote_address.target().to_socket_addrs()?.next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{remote_address} doesn't resolve")))?;
+
+            let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+            stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+            Ok(Connection::Tcp(stream))
+        },
+    }
+}
+
+impl Connection {
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Connection::Unix(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            Connection::Udp(socket) => socket.send(message.as_bytes()).map(|_| ()),
+            // Octet counting from RFC 6587, so a message with a newline in it doesn't get split.
+            Connection::Tcp(stream) => stream.write_all(format!("{} {message}", message.len()).as_bytes()),
+        }
+    }
+}
+
+// Keeps going whatever happens to the syslog server, warning the first time it can't be reached.
+fn send_loop(target: SyslogAddr, messages: Receiver<String>) {
+    let mut connection: Option<Connection> = None;
+    let mut last_failure: Option<Instant> = None;
+    let mut has_warned = false;
+
+    for message in messages {
+        if connection.is_none() && last_failure.is_some_and(|failed_at| failed_at.elapsed() < RETRY_DELAY) {
+            continue;
+        }
+
+        let result = match connection.as_mut() {
+            Some(connection) => connection.send(&message),
+            None => connect(&target).and_then(|mut new_connection| {
+                new_connection.send(&message)?;
+                connection = Some(new_connection);
+
+                Ok(())
+            }),
+        };
+
+        if let Err(e) = result {
+            connection = None;
+            last_failure = Some(Instant::now());
+
+            if !has_warned {
+                has_warned = true;
+
+                tracing::warn!(target: TARGET, "Can't send logs to syslog at {target}, dropping them until it's back: error={e}");
+            }
+        }
+    }
+}
+
+pub struct SyslogLayer {
+    is_local: bool,
+    facility: Facility,
+    hostname: String,
+    pid: u32,
+    messages: SyncSender<String>,
+}
+
+impl SyslogLayer {
+    pub fn new(target: SyslogAddr, facility: Facility) -> SyslogLayer {
+        let (messages, message_receiver) = mpsc::sync_channel(QUEUE_SIZE);
+
+        let is_local = matches!(target, SyslogAddr::Unix(_));
+
+        std::thread::spawn(move || send_loop(target, message_receiver));
+
+        SyslogLayer {
+            is_local,
+            facility,
+            hostname: hostname(),
+            pid: std::process::id(),
+            messages,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    nix::unistd::gethostname().ok().and_then(|hostname| hostname.into_string().ok()).unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "-".to_string())
+}
+
+impl<S> Layer<S> for SyslogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // The warning about syslog being unreachable would only go to syslog.
+        if event.metadata().target() == TARGET {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let mut session = None;
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if let Some(id) = fields.get("session") {
+                        session = Some(id.to_string());
+                    }
+                }
+            }
+        }
+
+        let mut timestamp = String::new();
+        let _ = SystemTime.format_time(&mut Writer::new(&mut timestamp));
+
+        let message = visitor.message + &visitor.fields;
+
+        let record = Record {
+            priority: self.facility.0 * 8 + severity(event.metadata().level()),
+            timestamp: &timestamp,
+            hostname: &self.hostname,
+            pid: self.pid,
+            event: visitor.event.as_deref(),
+            session: session.as_deref(),
+            message: &message,
+        };
+
+        let message = if self.is_local { format_rfc3164(&record) } else { format_rfc5424(&record) };
+
+        // Full means the syslog server can't keep up; dropping is better than holding up a session.
+        let _ = self.messages.try_send(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record<'a>(session: Option<&'a str>) -> Record<'a> {
+        Record {
+            priority: 30,
+            timestamp: "2026-10-06T20:15:02.123456Z",
+            hostname: "retro",
+            pid: 4242,
+            event: Some("connect"),
+            session,
+            message: "Looks like we got a wild MAME @ 127.0.0.1:50000",
+        }
+    }
+
+    #[test]
+    fn formats_both_kinds() {
+        assert_eq!(format_rfc3164(&record(Some("1"))), "<30>Oct  6 20:15:02 touchppp[4242]: [session 1] Looks like we got a wild MAME @ 127.0.0.1:50000");
+        assert_eq!(format_rfc5424(&record(Some("1"))), "<30>1 2026-10-06T20:15:02.123456Z retro touchppp 4242 connect [touchppp@32473 session=\"1\"] Looks like we got a wild MAME @ 127.0.0.1:50000");
+        assert_eq!(format_rfc5424(&record(None)), "<30>1 2026-10-06T20:15:02.123456Z retro touchppp 4242 connect - Looks like we got a wild MAME @ 127.0.0.1:50000");
+    }
+
+    #[test]
+    fn facilities() {
+        assert_eq!("local3".parse::<Facility>().unwrap(), Facility(19));
+        assert_eq!(DEFAULT_FACILITY.to_string(), "daemon");
+        assert!("nope".parse::<Facility>().is_err());
+    }
+}
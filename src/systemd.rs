@@ -0,0 +1,132 @@
+use std::env;
+use std::net::TcpListener;
+use std::ops::Range;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::socket::{getsockopt, sockopt, SockType};
+use tracing::debug;
+
+// systemd hands sockets over starting at fd 3, right after stdio.
+const LISTEN_FDS_START: RawFd = 3;
+
+// The fds systemd passed, or None if there weren't any for this process. LISTEN_PID is checked
+// because the variables get inherited, and a parent's sockets aren't ours to take.
+fn passed_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Result<Option<Range<RawFd>>, String> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(None);
+    };
+
+    match listen_pid.trim().parse::<u32>() {
+        Ok(listen_pid) if listen_pid == pid => {},
+        Ok(_) => return Ok(None),
+        Err(_) => return Err(format!("LISTEN_PID '{listen_pid}' from systemd isn't a pid")),
+    }
+
+    match listen_fds.trim().parse::<RawFd>() {
+        Ok(0) => Ok(None),
+        Ok(count) if count > 0 => Ok(Some(LISTEN_FDS_START..LISTEN_FDS_START + count)),
+        _ => Err(format!("LISTEN_FDS '{listen_fds}' from systemd isn't a number of sockets")),
+    }
+}
+
+// Takes ownership of fd, which has to be a listening stream socket.
+fn adopt(fd: RawFd) -> Result<TcpListener, String> {
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let is_listening_stream = matches!(getsockopt(&owned, sockopt::SockType), Ok(SockType::Stream))
+        && getsockopt(&owned, sockopt::AcceptConn).unwrap_or(false);
+
+    if !is_listening_stream {
+        return Err(format!("fd {fd} from systemd isn't a listening stream socket (check ListenStream= in the .socket unit)"));
+    }
+
+    // Don't hand the listener down to pppd or MAME.
+    fcntl(&owned, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(|e| format!("can't set up fd {fd} from systemd: {e}"))?;
+
+    let listener = TcpListener::from(owned);
+    listener.set_nonblocking(true).map_err(|e| format!("can't set up fd {fd} from systemd: {e}"))?;
+
+    Ok(listener)
+}
+
+// The listeners systemd opened for us with socket activation, if it did.
+pub fn listeners() -> Result<Option<Vec<TcpListener>>, String> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fds = env::var("LISTEN_FDS").ok();
+
+    let fds = passed_fds(listen_pid.as_deref(), listen_fds.as_deref(), std::process::id())?;
+
+    // So anything we launch doesn't think the sockets are meant for it.
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    match fds {
+        Some(fds) => Ok(Some(fds.map(adopt).collect::<Result<Vec<TcpListener>, String>>()?)),
+        None => Ok(None),
+    }
+}
+
+// Tells systemd how we're doing (READY=1, STOPPING=1) for Type=notify units. Does nothing outside systemd.
+pub fn notify(state: &str) {
+    let Ok(notify_socket) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        #[cfg(target_os = "linux")]
+        if let Some(name) = notify_socket.strip_prefix('@') {
+            use std::os::linux::net::SocketAddrExt;
+
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(state.as_bytes(), &address).map(|_| ());
+        }
+
+        socket.send_to(state.as_bytes(), &notify_socket).map(|_| ())
+    });
+
+    if let Err(e) = result {
+        debug!("Can't tell systemd {state}: error={e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::IntoRawFd;
+    use nix::sys::socket::{socketpair, AddressFamily, SockFlag};
+
+    #[test]
+    fn fds_are_only_ours_when_listen_pid_matches() {
+        assert_eq!(passed_fds(None, None, 100), Ok(None));
+        assert_eq!(passed_fds(Some("100"), None, 100), Ok(None));
+        assert_eq!(passed_fds(Some("99"), Some("2"), 100), Ok(None));
+        assert_eq!(passed_fds(Some("100"), Some("0"), 100), Ok(None));
+        assert_eq!(passed_fds(Some("100"), Some("2"), 100), Ok(Some(3..5)));
+    }
+
+    #[test]
+    fn garbage_from_systemd_is_an_error() {
+        assert!(passed_fds(Some("me"), Some("1"), 100).is_err());
+        assert!(passed_fds(Some("100"), Some("-1"), 100).is_err());
+        assert!(passed_fds(Some("100"), Some("lots"), 100).is_err());
+    }
+
+    #[test]
+    fn adopts_a_listening_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let adopted = adopt(listener.into_raw_fd()).unwrap();
+
+        assert_eq!(adopted.local_addr().unwrap(), address);
+    }
+
+    #[test]
+    fn refuses_a_socket_that_isnt_listening() {
+        let (ours, _theirs) = socketpair(AddressFamily::Unix, SockType::Stream, None, SockFlag::empty()).unwrap();
+
+        assert!(adopt(ours.into_raw_fd()).is_err());
+    }
+}
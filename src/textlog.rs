@@ -0,0 +1,195 @@
+// --log-format text, in color on a terminal: warnings and errors stand out, each session's tag keeps the same
+// color for as long as it's up, and the hexdumps and AT lines -v and -vv add fade into the background. The
+// colors are only ever added here, so a log file or JSON never sees an escape code.
+
+use std::fmt;
+use serde_json::Value;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::Color;
+use crate::jsonlog::SpanFields;
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+
+// Red's left out so a session never looks like an error.
+const SESSION_COLORS: [&str; 6] = ["\x1b[36m", "\x1b[35m", "\x1b[32m", "\x1b[33m", "\x1b[34m", "\x1b[96m"];
+
+// Where the noisy detail comes from.
+const DIM_TARGETS: [&str; 2] = ["touchppp::bridge", "touchppp::at"];
+
+/// Whether `color` means color for a terminal (or not) with NO_COLOR set to `no_color`. NO_COLOR only turns
+/// off auto, since asking for always is asking for color.
+pub fn is_colored(color: Color, is_terminal: bool, no_color: Option<&str>) -> bool {
+    match color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto => is_terminal && no_color.is_none_or(str::is_empty),
+    }
+}
+
+fn level_color(level: Level) -> &'static str {
+    match level {
+        Level::ERROR => "\x1b[31m",
+        Level::WARN => "\x1b[33m",
+        Level::INFO => "\x1b[32m",
+        Level::DEBUG => "\x1b[34m",
+        Level::TRACE => "\x1b[35m",
+    }
+}
+
+// The same color for a session every time, from its id.
+fn session_color(id: u64) -> &'static str {
+    SESSION_COLORS[(id % SESSION_COLORS.len() as u64) as usize]
+}
+
+/// Timestamp, level, spans and then the message and its fields, like tracing's own format. The fmt layer it's
+/// on shouldn't add ANSI itself, or the fields come with escapes of their own.
+pub struct TextFormat {
+    pub is_colored: bool,
+}
+
+impl TextFormat {
+    fn paint(&self, writer: &mut Writer<'_>, color: &str, text: impl fmt::Display) -> fmt::Result {
+        match self.is_colored {
+            true => write!(writer, "{color}{text}{RESET}"),
+            false => write!(writer, "{text}"),
+        }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for TextFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let metadata = event.metadata();
+
+        let mut ts = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut ts))?;
+        self.paint(&mut writer, DIM, ts)?;
+        write!(writer, " ")?;
+
+        self.paint(&mut writer, level_color(*metadata.level()), format_args!("{:>5}", metadata.level()))?;
+        write!(writer, " ")?;
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+
+                let session = extensions.get::<SpanFields>().and_then(|SpanFields(fields)| fields.get("session")).and_then(Value::as_u64);
+                let color = session.map(session_color).unwrap_or(DIM);
+
+                let tag = match extensions.get::<FormattedFields<N>>() {
+                    Some(fields) if !fields.is_empty() => format!("{}{{{fields}}}", span.name()),
+                    _ => span.name().to_string(),
+                };
+
+                self.paint(&mut writer, color, tag)?;
+                write!(writer, ": ")?;
+            }
+        }
+
+        let is_dim = self.is_colored && DIM_TARGETS.contains(&metadata.target());
+        if is_dim {
+            write!(writer, "{DIM}")?;
+        }
+
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+
+        if is_dim {
+            write!(writer, "{RESET}")?;
+        }
+
+        writeln!(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::MakeWriter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Captured {
+            self.clone()
+        }
+    }
+
+    // What a session's worth of logging looks like, without the timestamps.
+    fn log_a_session(is_colored: bool) -> Vec<String> {
+        let captured = Captured::default();
+
+        let subscriber = tracing_subscriber::registry()
+            .with(crate::jsonlog::SpanFieldsLayer)
+            .with(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(captured.clone()).event_format(TextFormat { is_colored }));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _session = tracing::info_span!("session", session = 2).entered();
+
+            tracing::info!("Looks like we got a wild MAME");
+            tracing::debug!(target: "touchppp::at", "ATDT18006138199");
+            tracing::warn!("Backend's slow");
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+
+        output.lines().map(|line| line.split_once(' ').unwrap().1.to_string()).collect()
+    }
+
+    #[test]
+    fn plain_text_has_no_escapes() {
+        assert_eq!(log_a_session(false), [
+            " INFO session{session=2}: Looks like we got a wild MAME",
+            "DEBUG session{session=2}: ATDT18006138199",
+            " WARN session{session=2}: Backend's slow",
+        ]);
+    }
+
+    #[test]
+    fn colors_levels_sessions_and_noise() {
+        assert_eq!(log_a_session(true), [
+            "\x1b[32m INFO\x1b[0m \x1b[32msession{session=2}\x1b[0m: Looks like we got a wild MAME",
+            "\x1b[34mDEBUG\x1b[0m \x1b[32msession{session=2}\x1b[0m: \x1b[2mATDT18006138199\x1b[0m",
+            "\x1b[33m WARN\x1b[0m \x1b[32msession{session=2}\x1b[0m: Backend's slow",
+        ]);
+    }
+
+    #[test]
+    fn no_color_only_turns_off_auto() {
+        assert!(is_colored(Color::Auto, true, None));
+        assert!(is_colored(Color::Auto, true, Some("")));
+        assert!(!is_colored(Color::Auto, true, Some("1")));
+        assert!(!is_colored(Color::Auto, false, None));
+        assert!(is_colored(Color::Always, false, Some("1")));
+        assert!(!is_colored(Color::Never, true, None));
+    }
+
+    #[test]
+    fn sessions_keep_their_color() {
+        assert_eq!(session_color(2), session_color(2 + SESSION_COLORS.len() as u64));
+        assert_ne!(session_color(2), session_color(3));
+    }
+}
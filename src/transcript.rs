@@ -0,0 +1,90 @@
+// --at-transcript: one text file per session with every AT command line MAME sent, every result code we sent
+// back and the decisions in between, for when someone says "it won't dial".
+//
+//   [   0.000] session 1 from 127.0.0.1:50000
+//   [   0.002] <- ATE0Q0V0&C1&D2S0=0\r
+//   [   0.002] -> OK\r\n
+//   [   0.031] == dialed '18006138199', using backend default (connect 127.0.0.1:2323)
+
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Set after the first failed write so a directory we can't write to gets one warning rather than one per session.
+static GAVE_UP: AtomicBool = AtomicBool::new(false);
+
+pub struct Transcript {
+    file: Option<File>,
+    started: Instant,
+}
+
+impl Transcript {
+    // Writes nothing if there's no directory, or once writing to it has failed.
+    pub fn start(dir: Option<&str>, session: u64, client: &str) -> Transcript {
+        let mut transcript = Transcript {
+            file: None,
+            started: Instant::now(),
+        };
+
+        let Some(dir) = dir.filter(|_| !GAVE_UP.load(Ordering::SeqCst)) else {
+            return transcript;
+        };
+
+        // The session ids start over with each run, so the start time keeps the names apart.
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+        let path = Path::new(dir).join(format!("{started_at}-session-{session}.txt"));
+
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => transcript.file = Some(file),
+            Err(e) => give_up(&path.display(), e),
+        }
+
+        transcript.line("", format_args!("session {session} from {client}"));
+
+        transcript
+    }
+
+    // A whole command line from MAME.
+    pub fn received(&mut self, line: &str) {
+        self.line("<- ", line.escape_debug());
+    }
+
+    pub fn sent(&mut self, reply: &[u8]) {
+        self.line("-> ", String::from_utf8_lossy(reply).escape_debug());
+    }
+
+    // Something we decided, like which backend a dial goes to.
+    pub fn note(&mut self, what: impl Display) {
+        self.line("== ", what);
+    }
+
+    fn line(&mut self, prefix: &str, text: impl Display) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+
+        if let Err(e) = writeln!(file, "[{elapsed:8.3}] {prefix}{text}") {
+            self.file = None;
+
+            give_up(&"the AT transcript", e);
+        }
+    }
+}
+
+impl Drop for Transcript {
+    fn drop(&mut self) {
+        self.note("hung up");
+    }
+}
+
+fn give_up(what: &dyn Display, e: std::io::Error) {
+    if !GAVE_UP.swap(true, Ordering::SeqCst) {
+        warn!("Can't write to {what}, so no more AT transcripts until restarted: error={e}");
+    }
+}
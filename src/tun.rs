@@ -0,0 +1,414 @@
+// The built-in tun backend: answers PPP itself like nat does, but hands the box's IP packets to a TUN device
+// instead, so routing, NAT and firewalling are whatever this machine's netfilter says. With --link-protocol slip
+// there's no PPP, just the packets. It needs root (or
+// CAP_NET_ADMIN) to create the device. Each call gets its own device, which goes away when the call's over.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use nix::libc;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::time::{Duration, MissedTickBehavior};
+use tracing::{debug, info};
+
+use crate::backend::{BackendStream, DialContext, PppBackend};
+use crate::bridge;
+use crate::error::TouchPppError;
+use crate::config::LinkProtocol;
+use crate::ppp::{self, control::DEFAULT_MRU};
+use crate::slip;
+
+const TUN_DEVICE: &str = "/dev/net/tun";
+// Linux's IFNAMSIZ, less the terminating nul.
+const MAX_NAME: usize = 15;
+// How often LCP and IPCP resends are looked at.
+const TICK: Duration = Duration::from_millis(100);
+
+pub const DEFAULT_NAME: &str = "touchppp0";
+pub const DEFAULT_LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 3, 1);
+pub const DEFAULT_PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 3, 2);
+
+/// The IPv4 name servers in /etc/resolv.conf that the box could reach, which leaves out loopback ones like
+/// systemd-resolved's 127.0.0.53.
+pub fn system_dns() -> Vec<Ipv4Addr> {
+    ppp::system_dns().into_iter().filter(|ip| !ip.is_loopback()).collect()
+}
+
+/// Answers PPP itself and routes the box's packets through a TUN device with `local` on our end and the box's
+/// `peer` address on the other. Only one box can be on it at a time.
+pub struct Tun {
+    pub name: String,
+    pub local: Ipv4Addr,
+    pub peer: Ipv4Addr,
+    // Handed to the box as its primary and secondary name servers. The first is used for both if there's one.
+    pub dns: Vec<Ipv4Addr>,
+    is_busy: Arc<AtomicBool>,
+}
+
+impl Tun {
+    pub fn new(name: impl Into<String>, local: Ipv4Addr, peer: Ipv4Addr, dns: Vec<Ipv4Addr>) -> Result<Tun, String> {
+        let name = name.into();
+
+        if name.is_empty() || name.len() > MAX_NAME || name.contains(['/', ' ', '\0']) {
+            return Err(format!("'{name}' can't name a network device; use up to {MAX_NAME} letters and numbers, like {DEFAULT_NAME}"));
+        }
+
+        if local == peer {
+            return Err(format!("the tun backend's local and peer addresses are both {local}"));
+        }
+
+        if dns.is_empty() {
+            return Err("the tun backend needs --tun-dns since /etc/resolv.conf doesn't have a name server the box could reach".to_string());
+        }
+
+        if dns.len() > 2 {
+            return Err("the box only takes two name servers, so give --tun-dns at most twice".to_string());
+        }
+
+        Ok(Tun { name, local, peer, dns, is_busy: Arc::new(AtomicBool::new(false)) })
+    }
+
+    /// Creates the device and closes it straight away, so not being allowed to shows up at startup instead of
+    /// on the first call.
+    pub fn check_access(&self) -> Result<(), String> {
+        create(&self.name, self.local, self.peer).map(drop).map_err(|e| match e.kind() {
+            ErrorKind::PermissionDenied => format!("can't create TUN device {}: it needs root or CAP_NET_ADMIN (try sudo setcap cap_net_admin+ep on touchppp)", self.name),
+            _ => format!("can't create TUN device {}: {e}", self.name),
+        })
+    }
+}
+
+// Lets the next box on once the call's over, however it ends.
+struct InUse(Arc<AtomicBool>);
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl PppBackend for Tun {
+    fn establish<'a>(&'a self, context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            let endpoint = || format!("the tun device {}", self.name);
+
+            if self.is_busy.swap(true, Ordering::SeqCst) {
+                return Err(TouchPppError::BackendConnect {
+                    endpoint: endpoint(),
+                    source: std::io::Error::new(ErrorKind::ConnectionRefused, "another box is already using it"),
+                });
+            }
+
+            let in_use = InUse(self.is_busy.clone());
+
+            let device = create(&self.name, self.local, self.peer).and_then(AsyncFd::new)
+                .map_err(|source| TouchPppError::BackendConnect { endpoint: endpoint(), source })?;
+
+            info!("Touching the built-in TUN device {}, giving the box {}.", self.name, self.peer);
+
+            let (call, ppp) = tokio::io::duplex(bridge::BUFFER_SIZE);
+            let link = match context.link_protocol {
+                LinkProtocol::Ppp => Link::Ppp(ppp::Link::new(self.local, self.peer, [self.dns[0], self.dns[self.dns.len() - 1]])),
+                LinkProtocol::Slip => Link::Slip { decoder: slip::Decoder::default(), out: Vec::new() },
+            };
+            let (name, peer) = (self.name.clone(), self.peer);
+
+            // The device goes away when the call drops its end of the pipe, and the next box can have it.
+            tokio::spawn(async move {
+                run(ppp, link, device, name, peer).await;
+
+                drop(in_use);
+            });
+
+            let (reader, writer) = tokio::io::split(call);
+
+            Ok(BackendStream::new(reader, writer))
+        }.boxed()
+    }
+}
+
+// The box's side of the device.
+enum Link {
+    Ppp(ppp::Link),
+    Slip { decoder: slip::Decoder, out: Vec<u8> },
+}
+
+impl Link {
+    fn read(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        match self {
+            Link::Ppp(link) => link.read(bytes),
+            Link::Slip { decoder, .. } => bytes.iter().filter_map(|byte| decoder.push(*byte)).collect(),
+        }
+    }
+
+    fn send_ip(&mut self, packet: &[u8]) {
+        match self {
+            Link::Ppp(link) => link.send_ip(packet),
+            Link::Slip { out, .. } => out.extend_from_slice(&slip::encode(packet)),
+        }
+    }
+
+    fn tick(&mut self) {
+        if let Link::Ppp(link) = self {
+            link.tick();
+        }
+    }
+
+    // What's waiting to go to the box.
+    fn out(&mut self) -> &mut Vec<u8> {
+        match self {
+            Link::Ppp(link) => &mut link.out,
+            Link::Slip { out, .. } => out,
+        }
+    }
+
+    // SLIP has no way to hang up; the call just ends.
+    fn is_done(&self) -> bool {
+        matches!(self, Link::Ppp(link) if link.is_done)
+    }
+}
+
+async fn run(ppp: DuplexStream, mut link: Link, device: AsyncFd<File>, name: String, peer: Ipv4Addr) {
+    let (mut reader, mut writer) = tokio::io::split(ppp);
+    let mut buf = vec![0; bridge::BUFFER_SIZE];
+    let mut packet = vec![0; DEFAULT_MRU as usize];
+
+    let mut tick = tokio::time::interval(TICK);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    while !link.is_done() {
+        if !link.out().is_empty() {
+            if writer.write_all(link.out()).await.is_err() {
+                break;
+            }
+
+            link.out().clear();
+        }
+
+        tokio::select! {
+            read = reader.read(&mut buf) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(bytes_found) => {
+                    for packet in link.read(&buf[..bytes_found]) {
+                        // Only what the box sends as itself, so it can't slip anything else past the firewall.
+                        if packet.get(12..16) != Some(&peer.octets()[..]) {
+                            continue;
+                        }
+
+                        // A full queue drops it, same as a busy network would.
+                        if let Err(e) = device.get_ref().write(&packet) {
+                            debug!(target: "touchppp::tun", "Couldn't hand the box's packet to the device: error={e}");
+                        }
+                    }
+                },
+            },
+            readable = device.readable() => {
+                let Ok(mut guard) = readable else {
+                    break;
+                };
+
+                loop {
+                    match guard.try_io(|device| device.get_ref().read(&mut packet)) {
+                        // The kernel has its own ideas about IPv6, which the box can't take.
+                        Ok(Ok(bytes_found)) if packet[0] >> 4 == 4 => link.send_ip(&packet[..bytes_found]),
+                        Ok(Ok(_)) => {},
+                        Ok(Err(e)) => {
+                            info!("Lost TUN device {name}, hanging up: error={e}");
+
+                            return;
+                        },
+                        // Nothing more to read for now.
+                        Err(_) => break,
+                    }
+                }
+            },
+            _ = tick.tick() => link.tick(),
+        }
+    }
+
+    // The Terminate-Ack, if that's how it ended.
+    let _ = writer.write_all(link.out()).await;
+}
+
+// Creates the TUN device, gives it its addresses and brings it up. It's gone again once the file's closed.
+fn create(name: &str, local: Ipv4Addr, peer: Ipv4Addr) -> std::io::Result<File> {
+    let device = std::fs::OpenOptions::new().read(true).write(true).custom_flags(libc::O_NONBLOCK).open(TUN_DEVICE)?;
+
+    let mut request = ifreq(name);
+    request.ifr_ifru.ifru_flags = (libc::IFF_TUN | libc::IFF_NO_PI) as libc::c_short;
+    ioctl(device.as_raw_fd(), libc::TUNSETIFF as libc::Ioctl, &mut request)?;
+
+    // Addresses and flags are set through any old socket.
+    let socket: OwnedFd = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?.into();
+    let socket = socket.as_raw_fd();
+
+    let mut request = ifreq(name);
+    request.ifr_ifru.ifru_addr = sockaddr(local);
+    ioctl(socket, libc::SIOCSIFADDR as libc::Ioctl, &mut request)?;
+
+    let mut request = ifreq(name);
+    request.ifr_ifru.ifru_dstaddr = sockaddr(peer);
+    ioctl(socket, libc::SIOCSIFDSTADDR as libc::Ioctl, &mut request)?;
+
+    let mut request = ifreq(name);
+    request.ifr_ifru.ifru_mtu = DEFAULT_MRU as libc::c_int;
+    ioctl(socket, libc::SIOCSIFMTU as libc::Ioctl, &mut request)?;
+
+    let mut request = ifreq(name);
+    ioctl(socket, libc::SIOCGIFFLAGS as libc::Ioctl, &mut request)?;
+    // SAFETY: SIOCGIFFLAGS just filled in the flags.
+    let flags = unsafe { request.ifr_ifru.ifru_flags };
+    request.ifr_ifru.ifru_flags = flags | (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+    ioctl(socket, libc::SIOCSIFFLAGS as libc::Ioctl, &mut request)?;
+
+    Ok(device)
+}
+
+fn ifreq(name: &str) -> libc::ifreq {
+    // SAFETY: ifreq is plain old data, and all zeros is an empty request.
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+
+    for (to, from) in request.ifr_name.iter_mut().zip(name.bytes()) {
+        *to = from as libc::c_char;
+    }
+
+    request
+}
+
+fn sockaddr(ip: Ipv4Addr) -> libc::sockaddr {
+    let address = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: 0,
+        sin_addr: libc::in_addr { s_addr: u32::from(ip).to_be() },
+        sin_zero: [0; 8],
+    };
+
+    // SAFETY: sockaddr_in is how an IPv4 sockaddr is laid out, and they're the same size.
+    unsafe { std::mem::transmute::<libc::sockaddr_in, libc::sockaddr>(address) }
+}
+
+fn ioctl(fd: RawFd, request: libc::Ioctl, ifreq: &mut libc::ifreq) -> std::io::Result<()> {
+    // SAFETY: every request used here reads or writes an ifreq, and ifreq is a valid one.
+    match unsafe { libc::ioctl(fd, request, ifreq as *mut libc::ifreq) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UdpSocket;
+    use crate::config::DialSettings;
+    use crate::ppp::client::Client;
+    use crate::ppp::control::IPV4;
+
+    const DNS: Ipv4Addr = Ipv4Addr::new(1, 1, 1, 1);
+
+    // An IPv4 packet with a UDP datagram in it, without a UDP checksum since that's optional.
+    fn udp(source: Ipv4Addr, destination: Ipv4Addr, source_port: u16, destination_port: u16, payload: &[u8]) -> Vec<u8> {
+        let length = 28 + payload.len() as u16;
+
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0];
+        packet[2..4].copy_from_slice(&length.to_be_bytes());
+        packet.extend_from_slice(&source.octets());
+        packet.extend_from_slice(&destination.octets());
+
+        let sum = packet.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]]) as u32).sum::<u32>();
+        let checksum = !((sum & 0xffff) + (sum >> 16)) as u16;
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        packet.extend_from_slice(&source_port.to_be_bytes());
+        packet.extend_from_slice(&destination_port.to_be_bytes());
+        packet.extend_from_slice(&(length - 20).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+
+        packet
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        let (local, peer) = (DEFAULT_LOCAL, DEFAULT_PEER);
+
+        assert!(Tun::new(DEFAULT_NAME, local, peer, vec![DNS]).is_ok());
+        assert!(Tun::new("a-name-far-too-long", local, peer, vec![DNS]).is_err_and(|e| e.contains("can't name a network device")));
+        assert!(Tun::new(DEFAULT_NAME, local, local, vec![DNS]).is_err_and(|e| e.contains("are both 10.0.3.1")));
+        assert!(Tun::new(DEFAULT_NAME, local, peer, vec![]).is_err_and(|e| e.contains("needs --tun-dns")));
+        assert!(Tun::new(DEFAULT_NAME, local, peer, vec![DNS; 3]).is_err_and(|e| e.contains("at most twice")));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs root or CAP_NET_ADMIN"]
+    async fn packets_go_through_the_device() {
+        let (local, peer) = (Ipv4Addr::new(10, 0, 99, 1), Ipv4Addr::new(10, 0, 99, 2));
+        let tun = Tun::new("touchppptest0", local, peer, vec![DNS]).unwrap();
+        tun.check_access().unwrap();
+
+        let context = DialContext { number: "5551212", session: 1, link_protocol: LinkProtocol::Ppp, settings: &DialSettings::default(), endpoint: None };
+        let mut client = Client::new(tun.establish(&context).await.unwrap());
+        assert_eq!(client.connect(local, [DNS; 2]).await, peer);
+
+        // Only one box at a time.
+        assert!(tun.establish(&context).await.is_err());
+
+        // This machine to the box, skipping whatever else the kernel has to say.
+        let socket = UdpSocket::bind((local, 0)).await.unwrap();
+        socket.send_to(b"hello box", (peer, 4000)).await.unwrap();
+        loop {
+            let packet = client.ip().await;
+
+            if packet[9] == 17 {
+                assert_eq!(&packet[28..], b"hello box");
+                break;
+            }
+        }
+
+        // The box to this machine.
+        let port = socket.local_addr().unwrap().port();
+        client.send(IPV4, &udp(peer, local, 4000, port, b"hello host")).await;
+
+        let mut buf = [0; 64];
+        let (bytes_found, from) = tokio::time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!((&buf[..bytes_found], from), (&b"hello host"[..], (peer, 4000).into()));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs root or CAP_NET_ADMIN"]
+    async fn slip_packets_go_through_the_device() {
+        let (local, peer) = (Ipv4Addr::new(10, 0, 98, 1), Ipv4Addr::new(10, 0, 98, 2));
+        let tun = Tun::new("touchppptest1", local, peer, vec![DNS]).unwrap();
+
+        let context = DialContext { number: "5551212", session: 1, link_protocol: LinkProtocol::Slip, settings: &DialSettings::default(), endpoint: None };
+        let BackendStream { mut reader, mut writer, .. } = tun.establish(&context).await.unwrap();
+
+        // No negotiating, so the box can send straight away.
+        let socket = UdpSocket::bind((local, 0)).await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        writer.write_all(&slip::encode(&udp(peer, local, 4000, port, &[slip::END, slip::ESC]))).await.unwrap();
+
+        let mut buf = [0; 64];
+        let (bytes_found, _) = tokio::time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..bytes_found], [slip::END, slip::ESC]);
+
+        socket.send_to(&[slip::ESC, slip::END], (peer, 4000)).await.unwrap();
+
+        let mut decoder = slip::Decoder::default();
+        loop {
+            let bytes_found = tokio::time::timeout(Duration::from_secs(5), reader.read(&mut buf)).await.unwrap().unwrap();
+            let packets: Vec<Vec<u8>> = buf[..bytes_found].iter().filter_map(|byte| decoder.push(*byte)).collect();
+
+            if let Some(packet) = packets.iter().find(|packet| packet[9] == 17) {
+                assert_eq!(&packet[28..], [slip::ESC, slip::END]);
+                break;
+            }
+        }
+    }
+}
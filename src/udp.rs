@@ -0,0 +1,234 @@
+// --remote-udp: PPP carried a frame per datagram instead of as a stream. What MAME sends is cut up on its HDLC
+// flags and each frame goes out on its own; what comes back is strung together for MAME, with flags put back on
+// any datagram that came without them. The bridge only knows AsyncRead and AsyncWrite, so that's what this hands
+// it.
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncWrite, Interest, ReadBuf, Ready};
+use tokio::net::UdpSocket;
+use tracing::{info, warn};
+
+use crate::backend::{BackendStream, DialContext, PppBackend};
+use crate::config::UdpPpp;
+use crate::error::TouchPppError;
+use crate::listener;
+use crate::ppp::hdlc::{Splitter, FLAG};
+
+// Bigger than any datagram can be.
+const MAX_DATAGRAM: usize = 65535;
+
+impl PppBackend for UdpPpp {
+    fn establish<'a>(&'a self, _context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            info!("Touching PPP over UDP! '{}'", self.address);
+
+            let socket = connect(self).await.map_err(|source| TouchPppError::BackendConnect { endpoint: self.address.to_string(), source })?;
+
+            info!("Touched PPP over UDP @ {} from {}", self.address, socket.local_addr().map(|a| a.to_string()).unwrap_or_default());
+
+            let socket = Arc::new(socket);
+
+            Ok(BackendStream::new(DatagramReader::new(socket.clone()), DatagramWriter::new(socket)).with_endpoint(self.address.to_string()))
+        }.boxed()
+    }
+}
+
+// There's no handshake, so this only fails if the server's name doesn't look up. A server that isn't there shows up
+// as an error on the first send or receive after it, once its ICMP unreachable comes back.
+async fn connect(udp_ppp: &UdpPpp) -> io::Result<UdpSocket> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses to touch");
+
+    for server in udp_ppp.resolver.lookup(&udp_ppp.address).await? {
+        let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+        let connected = async {
+            let socket = UdpSocket::bind(local).await?;
+            if let Some(device) = &udp_ppp.bind_device {
+                listener::bind_device(socket2::SockRef::from(&socket), device)?;
+            }
+            socket.connect(server).await?;
+
+            Ok::<_, io::Error>(socket)
+        };
+
+        match connected.await {
+            Ok(socket) => return Ok(socket),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// What the server sends, a datagram at a time, as one stream of flag delimited frames.
+pub struct DatagramReader {
+    socket: Arc<UdpSocket>,
+    // Waits for a datagram, or the error an ICMP unreachable leaves on the socket, which plain reads never wake for.
+    ready: Option<BoxFuture<'static, io::Result<Ready>>>,
+    datagram: Vec<u8>,
+    // What's left of the last datagram for MAME.
+    pending: VecDeque<u8>,
+}
+
+impl DatagramReader {
+    pub fn new(socket: Arc<UdpSocket>) -> DatagramReader {
+        DatagramReader {
+            socket,
+            ready: None,
+            datagram: vec![0; MAX_DATAGRAM],
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl AsyncRead for DatagramReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        // An empty datagram would read as the end of the stream, so they're skipped.
+        while this.pending.is_empty() {
+            let read = match this.socket.try_recv(&mut this.datagram) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    let socket = this.socket.clone();
+                    let ready = this.ready.get_or_insert_with(|| async move { socket.ready(Interest::READABLE | Interest::ERROR).await }.boxed());
+
+                    let ready = ready!(ready.as_mut().poll(cx))?;
+                    this.ready = None;
+
+                    if ready.is_error() {
+                        if let Some(e) = this.socket.take_error()? {
+                            return Poll::Ready(Err(e));
+                        }
+
+                        // Nothing there after all, so it's not worth waking for again.
+                        let _ = this.socket.try_io(Interest::ERROR, || Err::<(), _>(io::ErrorKind::WouldBlock.into()));
+                    }
+
+                    continue;
+                },
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+
+            let frame = &this.datagram[..read];
+            if frame.is_empty() {
+                continue;
+            }
+
+            if frame[0] != FLAG {
+                this.pending.push_back(FLAG);
+            }
+            this.pending.extend(frame);
+            if frame[frame.len() - 1] != FLAG {
+                this.pending.push_back(FLAG);
+            }
+        }
+
+        let (front, _) = this.pending.as_slices();
+        let count = front.len().min(buf.remaining());
+        buf.put_slice(&front[..count]);
+        this.pending.drain(..count);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// MAME's bytes, cut into frames and sent a frame per datagram.
+pub struct DatagramWriter {
+    socket: Arc<UdpSocket>,
+    splitter: Splitter,
+    // Frames that are cut but not sent yet.
+    queue: VecDeque<Vec<u8>>,
+}
+
+impl DatagramWriter {
+    pub fn new(socket: Arc<UdpSocket>) -> DatagramWriter {
+        DatagramWriter {
+            socket,
+            splitter: Splitter::default(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn poll_send_queue(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(frame) = self.queue.front() {
+            ready!(self.socket.poll_send(cx, frame))?;
+            self.queue.pop_front();
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for DatagramWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Frames from the last write go first, and nothing more's taken until they've gone.
+        ready!(this.poll_send_queue(cx))?;
+
+        let dropped = this.splitter.dropped();
+        this.queue.extend(this.splitter.push(buf));
+        if this.splitter.dropped() > dropped {
+            warn!(target: "touchppp::backend", "Dropped a frame from MAME too long to send over UDP: dropped={}", this.splitter.dropped());
+        }
+
+        // What doesn't go now goes on the next write or flush.
+        if let Poll::Ready(Err(e)) = this.poll_send_queue(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_queue(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_queue(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn frames_go_a_datagram_each_and_come_back_with_flags() {
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(peer.local_addr().unwrap()).await.unwrap();
+        peer.connect(socket.local_addr().unwrap()).await.unwrap();
+
+        let socket = Arc::new(socket);
+        let mut reader = DatagramReader::new(socket.clone());
+        let mut writer = DatagramWriter::new(socket);
+
+        // Two frames and the start of a third in one write.
+        writer.write_all(b"\x7eone\x7e\x7etwo\x7e\x7ethr").await.unwrap();
+        writer.flush().await.unwrap();
+
+        let mut datagram = [0; 64];
+        for frame in [&b"\x7eone\x7e"[..], b"\x7etwo\x7e"] {
+            let read = peer.recv(&mut datagram).await.unwrap();
+            assert_eq!(&datagram[..read], frame);
+        }
+
+        // One without its flags, an empty one and one with them.
+        peer.send(b"back").await.unwrap();
+        peer.send(b"").await.unwrap();
+        peer.send(b"\x7eagain\x7e").await.unwrap();
+
+        let mut read = vec![0; 13];
+        tokio::time::timeout(std::time::Duration::from_secs(5), reader.read_exact(&mut read)).await.expect("nothing came back").unwrap();
+        assert_eq!(read, b"\x7eback\x7e\x7eagain\x7e");
+    }
+}
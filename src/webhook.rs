@@ -0,0 +1,131 @@
+// --webhook: session events POSTed as JSON from their own task, so a slow or dead endpoint only ever costs us
+// events, never calls. Events go out one at a time in the order they happened.
+
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::stats::SessionEvent;
+
+pub const DEFAULT_WEBHOOK_RETRIES: u32 = 3;
+
+pub const SIGNATURE_HEADER: &str = "X-TouchPPP-Signature";
+
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How many events can be waiting on the endpoint before new ones are dropped.
+const QUEUE_SIZE: usize = 256;
+
+// sha256= then the hex HMAC-SHA256 of the body, like GitHub does it.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+
+    let signature: String = mac.finalize().into_bytes().iter().map(|byte| format!("{byte:02x}")).collect();
+
+    format!("sha256={signature}")
+}
+
+struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+    retries: u32,
+}
+
+impl Webhook {
+    async fn post(&self, body: &[u8]) -> Result<(), String> {
+        let mut request = self.client.post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body));
+        }
+
+        let response = request.send().await.map_err(|e| e.to_string())?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(format!("got {status}")),
+        }
+    }
+
+    async fn deliver(&self, event: &SessionEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Can't make a webhook body for session {}: error={e}", event.session);
+                return;
+            }
+        };
+
+        let mut backoff = RETRY_BACKOFF;
+
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+
+            match self.post(&body).await {
+                Ok(()) => {
+                    debug!("Sent the {} webhook for session {}.", event.event, event.session);
+                    return;
+                },
+                Err(e) => warn!("Couldn't send the {} webhook for session {} (attempt {}/{}): error={e}", event.event, event.session, attempt + 1, self.retries + 1),
+            }
+        }
+
+        warn!("Gave up on the {} webhook for session {}.", event.event, event.session);
+    }
+}
+
+// None if there's no --webhook. Picked up once at startup; a config reload doesn't change where events go.
+pub fn start(config: &Config) -> Option<mpsc::Sender<SessionEvent>> {
+    let url = config.webhook.clone()?;
+
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("Can't send webhooks: error={e}");
+            return None;
+        }
+    };
+
+    let webhook = Webhook {
+        client,
+        url,
+        secret: config.webhook_secret.clone(),
+        retries: config.webhook_retries,
+    };
+
+    let (events, mut event_receiver) = mpsc::channel::<SessionEvent>(QUEUE_SIZE);
+
+    tokio::spawn(async move {
+        while let Some(event) = event_receiver.recv().await {
+            webhook.deliver(&event).await;
+        }
+    });
+
+    Some(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_like_rfc_4231() {
+        // Test case 2 from RFC 4231.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+    }
+}
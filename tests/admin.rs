@@ -0,0 +1,300 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::*;
+
+struct Admin {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Admin {
+    fn connect(path: &std::path::Path) -> Admin {
+        let started = Instant::now();
+
+        let stream = loop {
+            match UnixStream::connect(path) {
+                Ok(stream) => break stream,
+                Err(_) if started.elapsed() < Duration::from_secs(5) => sleep(Duration::from_millis(50)),
+                Err(e) => panic!("no admin socket: {e}"),
+            }
+        };
+
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        Admin {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        }
+    }
+
+    // Every line up to OK or ERROR, that one included.
+    fn command(&mut self, command: &str) -> Vec<String> {
+        writeln!(self.writer, "{command}").unwrap();
+
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+
+            let line = line.trim_end().to_string();
+            let is_done = line == "OK" || line.starts_with("ERROR") || line.is_empty();
+            lines.push(line);
+
+            if is_done {
+                return lines;
+            }
+        }
+    }
+}
+
+#[test]
+fn list_kill_stats_and_quit() {
+    let port = free_port();
+    let admin_path = scratch_path("admin.sock");
+
+    let _touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--admin", admin_path.to_str().unwrap()]);
+
+    // One MAME that's only said hello and one that's online.
+    let mut idle_mame = connect(port);
+    at(&mut idle_mame, "ATE0\r", b"OK\r\n");
+
+    let mut online_mame = connect(port);
+    dial(&mut online_mame, "18006138199");
+    online_mame.write_all(b"~hello~").unwrap();
+    let mut echoed = [0; 7];
+    online_mame.read_exact(&mut echoed).unwrap();
+
+    sleep(Duration::from_millis(200));
+
+    let mut admin = Admin::connect(&admin_path);
+
+    let list = admin.command("list");
+    assert_eq!(list.len(), 3, "{list:?}");
+    assert!(list[0].starts_with("1 client=127.0.0.1:") && list[0].contains(" state=command number=- backend=- bytes_up=0 bytes_down=0 "), "{list:?}");
+    assert!(list[1].starts_with("2 client=127.0.0.1:") && list[1].contains(" state=online number=18006138199 backend=command line bytes_up=7 bytes_down=7 "), "{list:?}");
+    assert_eq!(list[2], "OK");
+
+    let stats = admin.command("stats");
+    assert!(stats[0].contains(" sessions=2 active=2 bytes_up=7 bytes_down=7"), "{stats:?}");
+
+    // The online one gets NO CARRIER and then gets hung up on.
+    assert_eq!(admin.command("kill 2"), ["OK"]);
+
+    let mut rest = Vec::new();
+    online_mame.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"3\r\n");
+
+    sleep(Duration::from_millis(200));
+
+    let list = admin.command("list");
+    assert_eq!(list.len(), 2, "{list:?}");
+    assert!(list[0].starts_with("1 client="));
+
+    // The totals keep what session 2 did after it's gone.
+    let stats = admin.command("stats");
+    assert!(stats[0].contains(" sessions=2 active=1 bytes_up=7 bytes_down=7"), "{stats:?}");
+    assert!(stats[0].ends_with(" dials_connected=1 dials_busy=0 dials_failed=0 dials_in_use=0 refused_accepts=0"), "{stats:?}");
+
+    assert_eq!(admin.command("kill 2"), ["ERROR no session 2"]);
+
+    // Idle sessions just get hung up on.
+    assert_eq!(admin.command("kill 1"), ["OK"]);
+    let mut rest = Vec::new();
+    idle_mame.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+
+    writeln!(admin.writer, "quit").unwrap();
+    let mut line = String::new();
+    assert_eq!(admin.reader.read_line(&mut line).unwrap(), 0, "quit should close the connection");
+
+    let _ = std::fs::remove_file(&admin_path);
+}
+
+#[test]
+fn injected_bytes_go_out_with_the_call() {
+    let port = free_port();
+    let admin_path = scratch_path("inject.sock");
+
+    let _touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--admin", admin_path.to_str().unwrap(), "--enable-inject"]);
+
+    let mut mame = connect(port);
+    dial(&mut mame, "18006138199");
+
+    let mut admin = Admin::connect(&admin_path);
+
+    // Toward the backend, the echo sends it straight back.
+    assert_eq!(admin.command("inject 1 backend 7e 4d 41 52 4b 7e"), ["OK"]);
+    let mut reflected = [0; 6];
+    mame.read_exact(&mut reflected).unwrap();
+    assert_eq!(&reflected, b"~MARK~");
+
+    // Toward MAME, it just turns up.
+    assert_eq!(admin.command("inject 1 mame 7e4d414d457e"), ["OK"]);
+    let mut injected = [0; 6];
+    mame.read_exact(&mut injected).unwrap();
+    assert_eq!(&injected, b"~MAME~");
+
+    // And everything else still goes both ways around it.
+    mame.write_all(b"~ppp~").unwrap();
+    let mut echoed = [0; 5];
+    mame.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"~ppp~");
+
+    let _ = std::fs::remove_file(&admin_path);
+}
+
+#[test]
+fn injecting_takes_enable_inject() {
+    let port = free_port();
+    let admin_path = scratch_path("no-inject.sock");
+
+    let _touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--admin", admin_path.to_str().unwrap()]);
+
+    let mut mame = connect(port);
+    dial(&mut mame, "18006138199");
+
+    let mut admin = Admin::connect(&admin_path);
+    assert_eq!(admin.command("inject 1 backend 7e"), ["ERROR injecting bytes takes --enable-inject"]);
+
+    let _ = std::fs::remove_file(&admin_path);
+}
+
+#[test]
+fn drain_waits_for_the_call_thats_up_then_stops() {
+    let port = free_port();
+    let admin_path = scratch_path("drain.sock");
+
+    let mut touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--admin", admin_path.to_str().unwrap()]);
+
+    let mut online_mame = connect(port);
+    dial(&mut online_mame, "18006138199");
+
+    // Connected before the drain, but never dialed.
+    let mut idle_mame = connect(port);
+    at(&mut idle_mame, "ATE0\r", b"OK\r\n");
+
+    let mut admin = Admin::connect(&admin_path);
+    assert_eq!(admin.command("drain"), ["OK"]);
+    sleep(Duration::from_millis(200));
+
+    // Nobody gets to start a call now.
+    at(&mut idle_mame, "ATDT18006138199\r", b"7\r\n");
+    let mut late_mame = connect(port);
+    late_mame.write_all(b"AT\r").unwrap();
+    let mut rest = Vec::new();
+    late_mame.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"7\r\n", "BUSY, then hung up on");
+
+    // The call that's up carries on, and is all there's left to wait for.
+    let stats = admin.command("stats");
+    assert!(stats[0].ends_with(" draining=1"), "{stats:?}");
+    online_mame.write_all(b"~ppp~").unwrap();
+    let mut echoed = [0; 5];
+    online_mame.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"~ppp~");
+    sleep(Duration::from_millis(500));
+    assert!(touchppp.0.try_wait().unwrap().is_none(), "touchppp stopped with a call still up");
+
+    drop(online_mame);
+    assert!(exited(&mut touchppp).success());
+
+    let _ = std::fs::remove_file(&admin_path);
+}
+
+#[test]
+fn drain_timeout_hangs_up_on_calls_that_keep_going() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let port = free_port();
+
+    let mut touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--drain-timeout", "1"]);
+
+    let mut mame = connect(port);
+    dial(&mut mame, "18006138199");
+
+    kill(Pid::from_raw(touchppp.0.id() as i32), Signal::SIGUSR2).unwrap();
+
+    // NO CARRIER, the same as stopping would give it.
+    let started = Instant::now();
+    let mut rest = Vec::new();
+    mame.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"3\r\n");
+    assert!(started.elapsed() >= Duration::from_millis(900), "hung up after {:?}", started.elapsed());
+
+    assert!(exited(&mut touchppp).success());
+}
+
+// Reads touchppp's stdout a line at a time on another thread, so waiting for one can time out.
+fn stdout_lines(stdout: std::process::ChildStdout) -> std::sync::mpsc::Receiver<String> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+fn wait_for_line(lines: &std::sync::mpsc::Receiver<String>, wanted: impl Fn(&str) -> bool) -> String {
+    loop {
+        let line = lines.recv_timeout(Duration::from_secs(5)).expect("touchppp never said it");
+        if wanted(&line) {
+            return line;
+        }
+    }
+}
+
+#[test]
+fn console_takes_commands_on_a_terminal() {
+    use std::process::{Command, Stdio};
+
+    let port = free_port();
+    let terminal = nix::pty::openpty(None, None).unwrap();
+
+    let mut touchppp = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_touchppp"))
+            .args(["-l", &port.to_string(), "-c", &echo_server().to_string()])
+            .stdin(Stdio::from(terminal.slave))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let lines = stdout_lines(touchppp.0.stdout.take().unwrap());
+    let mut keyboard = std::fs::File::from(terminal.master);
+
+    wait_for_line(&lines, |line| line.contains("Taking admin commands on the console too"));
+
+    let mut mame = connect(port);
+    dial(&mut mame, "18006138199");
+    sleep(Duration::from_millis(200));
+
+    keyboard.write_all(b"status\n").unwrap();
+    let status = wait_for_line(&lines, |line| line.starts_with("uptime="));
+    assert!(status.contains(" sessions=1 active=1 "), "{status}");
+
+    // The call goes, but the session's still there to dial again.
+    keyboard.write_all(b"drop\n").unwrap();
+    wait_for_line(&lines, |line| line.contains("like an admin asks"));
+    at(&mut mame, "", b"3\r\n");
+    at(&mut mame, "AT\r", b"\r\n0\r\n");
+
+    keyboard.write_all(b"quit\n").unwrap();
+    let started = Instant::now();
+    while touchppp.0.try_wait().unwrap().is_none() {
+        assert!(started.elapsed() < Duration::from_secs(5), "quit should stop touchppp");
+        sleep(Duration::from_millis(50));
+    }
+}
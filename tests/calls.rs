@@ -0,0 +1,289 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use touchppp::backend::{BackendStream, DialContext, PppBackend};
+use touchppp::config::{AcceptRate, Backend, BackendKind, Builtin, CarrierSpeed, Config};
+use touchppp::TouchPppError;
+
+use common::fake_mame::*;
+
+#[test]
+fn webtv_init_string_gets_ok() {
+    let touchppp = Harness::start(BackendKind::Echo);
+    let mut mame = touchppp.call();
+
+    mame.at(WEBTV_INIT, OK);
+    mame.at("ATS7=60L3", SETUP_OK);
+}
+
+#[test]
+fn dialing_connects_and_bridges_to_ppp() {
+    let touchppp = Harness::start(BackendKind::Echo);
+    let mut mame = touchppp.call();
+
+    mame.enter_data_mode("18006138199");
+
+    // Every byte value, to be sure nothing in between thinks it's text.
+    let everything: Vec<u8> = (0..=255).collect();
+    mame.push_bytes(&everything);
+    mame.expect_bytes(&everything);
+}
+
+#[test]
+fn only_whole_lines_get_answered() {
+    let touchppp = Harness::start(BackendKind::Echo);
+    let mut mame = touchppp.call();
+
+    mame.push_bytes(b"ATE0");
+    mame.expect_nothing();
+    mame.push_bytes(b"\r");
+    mame.expect_result(OK);
+
+    // ATD with something after it that can't be dialed isn't anything we answer.
+    mame.send_at("ATDX");
+    mame.expect_nothing();
+}
+
+#[test]
+fn going_online_with_no_call_is_no_carrier() {
+    let touchppp = Harness::start(BackendKind::Echo);
+    let mut mame = touchppp.call();
+
+    mame.at("ATE0", OK);
+    mame.at("ATO", NO_CARRIER);
+}
+
+#[test]
+fn hanging_up_leaves_touchppp_ready_for_the_next_call() {
+    let touchppp = Harness::start(BackendKind::Echo);
+
+    let mut mame = touchppp.call();
+    mame.enter_data_mode("18006138199");
+    mame.push_bytes(b"~ppp~");
+    mame.hang_up();
+
+    // A new call starts from scratch, back in command mode.
+    let mut mame = touchppp.call();
+    mame.enter_data_mode("18006138199");
+    mame.push_bytes(b"~again~");
+    mame.expect_bytes(b"~again~");
+}
+
+#[test]
+fn the_null_backend_takes_everything_and_says_nothing() {
+    let touchppp = Harness::start(BackendKind::Null);
+    let mut mame = touchppp.call();
+
+    mame.enter_data_mode("18006138199");
+    mame.push_bytes(b"~ppp~");
+    mame.expect_nothing();
+}
+
+#[test]
+fn shutting_down_sends_no_carrier_to_calls_that_are_up() {
+    let touchppp = Harness::start(BackendKind::Echo);
+
+    let mut online = touchppp.call();
+    online.enter_data_mode("18006138199");
+
+    // Nothing to hang up on here, so it just gets dropped.
+    let mut idle = touchppp.call();
+    idle.at("ATE0", OK);
+
+    assert_eq!(touchppp.shut_down(), Ok(()));
+
+    online.expect_hang_up(NO_CARRIER);
+    idle.expect_hang_up(b"");
+}
+
+#[cfg(unix)]
+#[test]
+fn shutting_down_reaps_local_ppp() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = std::env::temp_dir().join(format!("touchppp-calls-{}", std::process::id()));
+    std::fs::create_dir_all(&scratch).unwrap();
+
+    let pid_file = scratch.join("ppp.pid");
+    let script = scratch.join("ppp.sh");
+    std::fs::write(&script, format!("#!/bin/sh\necho $$ > {}\nexec cat\n", pid_file.display())).unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let touchppp = Harness::start(BackendKind::Exec(touchppp::config::LocalPpp {
+        command: script.display().to_string(),
+        env: Default::default(),
+        is_exclusive: true,
+    }));
+
+    let mut mame = touchppp.call();
+    mame.enter_data_mode("18006138199");
+
+    // CONNECT comes once it's launched, which can be before it's got as far as writing its pid.
+    for _ in 0..50 {
+        if std::fs::read_to_string(&pid_file).is_ok_and(|pid| pid.ends_with('\n')) {
+            break;
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    assert_eq!(touchppp.shut_down(), Ok(()));
+    mame.expect_hang_up(NO_CARRIER);
+
+    let pid: i32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+    let _ = std::fs::remove_dir_all(&scratch);
+
+    // Not even a zombie left.
+    assert!(nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None).is_err(), "PPP {pid} is still around");
+}
+
+// Answers, then never finishes hanging up.
+struct Stubborn;
+
+impl PppBackend for Stubborn {
+    fn establish<'a>(&'a self, _context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            let (ppp, _) = tokio::io::duplex(64);
+            let (reader, writer) = tokio::io::split(ppp);
+
+            Ok(BackendStream::new(reader, writer).with_cleanup(futures::future::pending()))
+        }.boxed()
+    }
+}
+
+#[test]
+fn sessions_that_wont_hang_up_get_cut_off() {
+    let mut config = Config::for_backend(Backend {
+        name: "stubborn".to_string(),
+        kind: BackendKind::Custom(Box::new(Stubborn)),
+    });
+    config.shutdown_timeout = Duration::from_millis(200);
+
+    let touchppp = Harness::start_with(config);
+
+    let mut mame = touchppp.call();
+    mame.enter_data_mode("18006138199");
+
+    let stopped = touchppp.shut_down();
+    assert!(stopped.as_ref().is_err_and(|e| e.contains("1 sessions were still hanging up")), "{stopped:?}");
+
+    mame.expect_hang_up(b"");
+}
+
+fn dial_in_with(config: Config, connect: &[u8]) {
+    let touchppp = Harness::start_with(config);
+    let mut mame = touchppp.call();
+
+    mame.at(WEBTV_INIT, OK);
+    mame.at("ATS7=60L3", SETUP_OK);
+    mame.at("ATDT18006138199", DIAL_OK);
+    mame.at("ATD", connect);
+
+    mame.push_bytes(b"~ppp~");
+    mame.expect_bytes(b"~ppp~");
+}
+
+#[test]
+fn connect_reports_the_carrier_speed_its_told_to() {
+    let config = Config::builder().listen("127.0.0.1:0").builtin(Builtin::Echo).carrier_speed(CarrierSpeed::Fixed(31200)).build().unwrap();
+
+    // CARRIER 31200, COMPRESSION: V.42 bis, CONNECT 115200
+    dial_in_with(config, b"78\r\n67\r\n19\r\n");
+}
+
+#[test]
+fn connect_can_leave_out_compression() {
+    let config = Config::builder().listen("127.0.0.1:0").builtin(Builtin::Echo).connect_speed(57600).suppress_intermediates(true).build().unwrap();
+
+    // CARRIER 33600, CONNECT 57600
+    dial_in_with(config, b"79\r\n18\r\n");
+}
+
+#[test]
+fn data_mode_can_be_held_to_the_carrier_speed() {
+    let config = Config::builder().listen("127.0.0.1:0").builtin(Builtin::Echo).carrier_speed(CarrierSpeed::Fixed(9600)).throttle_to_carrier(true).build().unwrap();
+    let touchppp = Harness::start_with(config);
+    let mut mame = touchppp.call();
+
+    mame.at(WEBTV_INIT, OK);
+    mame.at("ATS7=60L3", SETUP_OK);
+    mame.at("ATDT18006138199", DIAL_OK);
+    // CARRIER 9600, COMPRESSION: V.42 bis, CONNECT 115200
+    mame.at("ATD", b"50\r\n67\r\n19\r\n");
+
+    // A second's worth at 9600 bits per second.
+    let data = vec![b'~'; 1200];
+    let started = std::time::Instant::now();
+    mame.push_bytes(&data);
+    mame.expect_bytes(&data);
+
+    assert!(started.elapsed() >= Duration::from_millis(900), "echoed in {:?}, faster than the carrier", started.elapsed());
+}
+
+#[test]
+fn low_latency_sends_each_byte_on_right_away() {
+    let port = common::echo_server();
+    let config = Config::builder().listen("127.0.0.1:0").connect(format!("127.0.0.1:{port}")).coalesce_ms(1000).low_latency(true).build().unwrap();
+    let touchppp = Harness::start_with(config);
+    let mut mame = touchppp.call();
+
+    mame.enter_data_mode("18006138199");
+
+    // Coalescing would hold this back for the whole second.
+    let started = std::time::Instant::now();
+    mame.push_bytes(b"~");
+    mame.expect_bytes(b"~");
+
+    assert!(started.elapsed() < Duration::from_millis(500), "echoed in {:?}", started.elapsed());
+}
+
+#[test]
+fn a_flood_of_connections_gets_turned_away() {
+    let mut config = Config::for_backend(Backend {
+        name: "test".to_string(),
+        kind: BackendKind::Echo,
+    });
+    config.accept_rate = AcceptRate { count: 5, per: Duration::from_secs(60) };
+
+    let touchppp = Harness::start_with(config);
+
+    let flood: Vec<TcpStream> = (0..50).map(|_| TcpStream::connect(touchppp.address()).unwrap()).collect();
+
+    let answered = flood.into_iter().filter(|mut stream| {
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        // The ones that were turned away are already closed, so either of these can fail.
+        let mut reply = [0; 16];
+        stream.write_all(b"AT\r").is_ok() && stream.read(&mut reply).is_ok_and(|n| n > 0)
+    }).count();
+
+    assert_eq!(answered, 5);
+}
+
+#[test]
+fn exit_after_sessions_takes_that_many_then_stops() {
+    let port = common::free_port();
+    let mut touchppp = common::spawn_touchppp(&["-l", &port.to_string(), "--backend-builtin", "echo", "--exit-after-sessions", "2"]);
+
+    // The first waits for it to be listening, and the other two are right behind it.
+    let eager: Vec<TcpStream> = (0..3).map(|_| common::connect(port)).collect();
+
+    let answered: Vec<TcpStream> = eager.into_iter().filter(|mut mame| {
+        // The one that was turned away is already closed, so either of these can fail.
+        let mut reply = [0; 16];
+        mame.write_all(b"AT\r").is_ok() && mame.read(&mut reply).is_ok_and(|n| n > 0)
+    }).collect();
+    assert_eq!(answered.len(), 2);
+
+    // Nobody else gets in, and it's done once the two hang up.
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err(), "still listening");
+    assert!(touchppp.0.try_wait().unwrap().is_none(), "touchppp stopped with calls still up");
+    drop(answered);
+
+    assert!(common::exited(&mut touchppp).success());
+}
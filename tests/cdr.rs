@@ -0,0 +1,99 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+
+use common::*;
+
+// The CDR file's records once there are `count` of them.
+fn records(file: &str, count: usize) -> Vec<Value> {
+    let started = Instant::now();
+
+    loop {
+        let lines = std::fs::read_to_string(file).unwrap_or_default();
+        if lines.lines().count() >= count || started.elapsed() > Duration::from_secs(5) {
+            return lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        }
+
+        sleep(Duration::from_millis(50));
+    }
+}
+
+// Every field every record has, whatever happened in the session.
+fn assert_schema(record: &Value) {
+    let fields: Vec<&str> = record.as_object().unwrap().keys().map(String::as_str).collect();
+
+    assert_eq!(fields, [
+        "v", "session", "started_ms", "ended_ms", "duration_ms", "client", "caller", "modem", "number", "backend", "endpoint",
+        "outcome", "connect_speed", "carrier_speed", "bytes_up", "bytes_down", "reason", "ip",
+    ]);
+    assert_eq!(record["v"], 1);
+    assert!(record["client"].as_str().unwrap().starts_with("127.0.0.1:"));
+    assert_eq!(record["ended_ms"].as_u64().unwrap() - record["started_ms"].as_u64().unwrap(), record["duration_ms"].as_u64().unwrap());
+}
+
+#[test]
+fn every_session_gets_a_record_once_its_over() {
+    let cdr_file = scratch_path("cdrs.jsonl");
+    let _ = std::fs::remove_file(&cdr_file);
+    let cdr_file = cdr_file.to_str().unwrap();
+
+    // One that goes through.
+    let backend = echo_server();
+    let port = free_port();
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &format!("127.0.0.1:{backend}"), "--cdr-file", cdr_file]);
+    let mut mame = connect(port);
+    dial(&mut mame, "18006138199");
+    mame.write_all(b"~ppp~").unwrap();
+    let mut echoed = [0; 5];
+    mame.read_exact(&mut echoed).unwrap();
+    drop(mame);
+
+    let written = records(cdr_file, 1);
+    drop(touchppp);
+
+    // And one whose dial never gets anywhere, with nobody there to answer. Its record's written before
+    // --exit-after-sessions lets touchppp exit.
+    let nobody = free_port();
+    let port = free_port();
+    let mut touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &format!("127.0.0.1:{nobody}"), "--cdr-file", cdr_file, "--exit-after-sessions", "1"]);
+    let mut mame = connect(port);
+    at(&mut mame, "ATE0\r", b"OK\r\n");
+    at(&mut mame, "ATDT5551212\r", b"0\r\n");
+    at(&mut mame, "ATD\r", b"7\r\n");
+    drop(mame);
+
+    assert!(exited(&mut touchppp).success());
+    let records: Vec<Value> = std::fs::read_to_string(cdr_file).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    std::fs::remove_file(cdr_file).unwrap();
+
+    assert_eq!(records.len(), 2, "{records:?}");
+    assert_eq!(records[0], written[0]);
+
+    let (connected, failed) = (&records[0], &records[1]);
+
+    assert_schema(connected);
+    assert_eq!(connected["session"], 1);
+    assert_eq!(connected["modem"], "default");
+    // A bare ATE0 is an init string, but not one that says any more than that.
+    assert_eq!(connected["caller"], "generic");
+    assert_eq!(connected["number"], "18006138199");
+    assert_eq!(connected["backend"], "command line");
+    assert_eq!(connected["endpoint"], format!("127.0.0.1:{backend}"));
+    assert_eq!(connected["outcome"], "connected");
+    assert_eq!((&connected["connect_speed"], &connected["carrier_speed"]), (&Value::from(115200), &Value::from(33600)));
+    assert_eq!((&connected["bytes_up"], &connected["bytes_down"]), (&Value::from(5), &Value::from(5)));
+    assert_eq!(connected["reason"], "MAME hung up");
+    // The echo doesn't speak PPP, so IPCP never got anywhere.
+    assert_eq!(connected["ip"], Value::Null);
+
+    assert_schema(failed);
+    assert_eq!(failed["number"], "5551212");
+    assert_eq!(failed["outcome"], "failed");
+    assert_eq!(failed["endpoint"], Value::Null);
+    assert_eq!(failed["connect_speed"], Value::Null);
+    assert_eq!(failed["bytes_up"], 0);
+    assert_eq!(failed["reason"], "MAME hung up");
+}
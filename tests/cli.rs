@@ -0,0 +1,535 @@
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+use assert_cmd::Command;
+
+use common::{echo_server, free_port, scratch_path, KillOnDrop};
+
+fn touchppp() -> Command {
+    Command::cargo_bin("touchppp").unwrap()
+}
+
+fn stdout_of(command: &mut Command) -> String {
+    String::from_utf8(command.output().unwrap().stdout).unwrap()
+}
+
+fn stderr_of(command: &mut Command) -> String {
+    String::from_utf8(command.output().unwrap().stderr).unwrap()
+}
+
+#[test]
+fn help_keeps_the_description_examples_and_thanks() {
+    let help = stdout_of(touchppp().arg("--help"));
+
+    // Long lines get wrapped to the terminal, so only look for pieces that fit on one.
+    assert!(help.contains("Provides a way for the WebTV MAME driver"));
+    assert!(help.contains("Example: -l 6400"));
+    assert!(help.contains("TOUCHPPP_CONNECT_TIMEOUT"));
+    assert!(help.contains("Special thanks to: Zefie, MattMan, and others in the WebTV hacking community!"));
+
+    for subcommand in ["serve", "replay", "dial", "test", "bench", "completions"] {
+        assert!(help.contains(subcommand), "--help should list {subcommand}");
+    }
+
+    touchppp().arg("-h").assert().success();
+}
+
+#[test]
+fn unknown_flag_is_a_usage_error() {
+    touchppp().arg("--touch-harder").assert().code(2);
+
+    assert!(stderr_of(touchppp().arg("--touch-harder")).contains("unexpected argument '--touch-harder'"));
+}
+
+#[test]
+fn serve_checks_its_values() {
+    // A bare port is still fine for -l; this only fails later because --daemon needs --log-file.
+    touchppp().args(["-l", "1122", "--daemon"]).assert().code(2);
+
+    for args in [
+        ["-l", "99999"],
+        ["-c", "fd00::2:2323"],
+        ["--connect-timeout", "soon"],
+        ["--log-format", "xml"],
+        ["--mame-slot", "bay"],
+        ["--backend-builtin", "loopback"],
+        ["--carrier-speed", "fast"],
+        ["--connect-speed", "12345"],
+        ["--log-max-size", "big"],
+        ["--drop-after", "30,soon"],
+        ["--blacklist", "555-CALL"],
+    ] {
+        touchppp().args(args).assert().code(2);
+        touchppp().arg("serve").args(args).assert().code(2);
+    }
+
+    assert!(stderr_of(touchppp().args(["serve", "-l", "99999"])).contains("'99999' has a port that isn't a number from 0 to 65535"));
+}
+
+#[test]
+fn bad_options_say_what_was_wrong_and_exit_with_2() {
+    for (args, problem) in [
+        (&["--connect-timeout", "soon"][..], "invalid value 'soon' for '--connect-timeout <SECONDS>'"),
+        (&["--log-format", "xml"][..], "invalid value 'xml' for '--log-format <text|json>': use text or json"),
+        (&["--carrier-speed", "fast"][..], "use auto or a speed in bits per second, like 31200"),
+        (&["--log-max-size", "big"][..], "'big' isn't a size (try something like 1048576, 512K or 10M)"),
+        (&["--listen"][..], "a value is required for '--listen <[HOST:]PORT>' but none was supplied"),
+        (&["serve", "--nope"][..], "unexpected argument '--nope' found"),
+        // Ones that only turn out wrong once the config's put together get said the same way.
+        (&["--connect-speed", "12345"][..], "can't report that connect speed: there's no result code for CONNECT 12345"),
+        (&["-l", "1122", "--daemon"][..], "--daemon needs --log-file since there's no terminal to log to"),
+    ] {
+        let output = touchppp().args(args).output().unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+
+        assert_eq!(output.status.code(), Some(2), "{args:?}: {stderr}");
+        assert!(stderr.contains(problem), "{args:?}: {stderr}");
+        assert!(stderr.contains("--help"), "{args:?}: {stderr}");
+        assert!(!stderr.contains("panicked"), "{args:?}: {stderr}");
+        assert!(output.stdout.is_empty(), "{args:?}");
+    }
+}
+
+#[test]
+fn empty_backends_say_which_option_was_empty() {
+    assert!(stderr_of(touchppp().args(["-c", ""])).contains("'' is empty. Try something like -c ppp.cool.com:2323"));
+    assert!(stderr_of(touchppp().args(["-e", " "])).contains("bad value ' ' for --exec: give the command to run"));
+    assert!(stderr_of(touchppp().env("TOUCHPPP_EXEC", "")).contains("bad value '' for TOUCHPPP_EXEC: give the command to run"));
+    assert!(stderr_of(touchppp().env("TOUCHPPP_CONNECT", " , ")).contains("bad value ' , ' for TOUCHPPP_CONNECT: give at least one HOST[:PORT]"));
+
+    touchppp().args(["-e", ""]).assert().code(2);
+    touchppp().env("TOUCHPPP_CONNECT", "").assert().code(2);
+}
+
+#[test]
+fn serve_options_dont_mix_with_other_subcommands() {
+    touchppp().args(["-l", "1122", "dial", "5551212"]).assert().code(2);
+}
+
+#[test]
+fn dial_checks_its_arguments() {
+    touchppp().arg("dial").assert().code(2);
+    touchppp().args(["dial", "555-CALL"]).assert().code(2);
+    touchppp().args(["dial", "5551212", "--to", "nowhere"]).assert().code(2);
+    touchppp().args(["dial", "5551212", "--timeout", "0"]).assert().code(2);
+}
+
+#[test]
+fn replay_checks_its_arguments() {
+    touchppp().arg("replay").assert().code(2);
+    touchppp().args(["replay", "/no/such/transcript.txt"]).assert().code(2);
+    touchppp().args(["replay", "transcript.txt", "--to", "[::1"]).assert().code(2);
+}
+
+#[test]
+fn completions_checks_its_arguments() {
+    touchppp().arg("completions").assert().code(2);
+    touchppp().args(["completions", "teletype"]).assert().code(2);
+
+    assert!(stdout_of(touchppp().args(["completions", "bash"])).contains("touchppp"));
+}
+
+#[test]
+fn dial_connects_to_a_running_touchppp() {
+    let port = free_port().to_string();
+
+    // CONNECT only comes once PPP answers.
+    let _server = KillOnDrop(
+        std::process::Command::new(env!("CARGO_BIN_EXE_touchppp"))
+            .args(["-l", &port, "-c", &echo_server().to_string(), "-q"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    sleep(Duration::from_millis(300));
+
+    let output = touchppp().args(["dial", "5551212", "--to", &port]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success(), "dial failed: {stdout}");
+    assert!(stdout.contains("Connected to 5551212."));
+}
+
+#[test]
+fn dial_connects_to_the_builtin_echo() {
+    let port = free_port().to_string();
+
+    let _server = KillOnDrop(
+        std::process::Command::new(env!("CARGO_BIN_EXE_touchppp"))
+            .args(["-l", &port, "--backend-builtin", "echo", "-q"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    sleep(Duration::from_millis(300));
+
+    let output = touchppp().args(["dial", "5551212", "--to", &port]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success(), "dial failed: {stdout}");
+    assert!(stdout.contains("Connected to 5551212."));
+}
+
+#[test]
+fn self_test_passes() {
+    let output = touchppp().arg("test").output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success(), "touchppp test failed: {stdout}");
+    assert!(!stdout.contains("FAIL"));
+
+    for step in ["init string", "dial", "data mode", "data (65536 bytes each way)", "hang up"] {
+        assert!(stdout.contains(&format!("PASS  {step}")), "no PASS for {step}: {stdout}");
+    }
+}
+
+#[test]
+fn bench_prints_a_row_per_run() {
+    let output = touchppp().args(["bench", "--seconds", "1", "--payload", "64", "--mode", "commands", "--mode", "echo"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success(), "touchppp bench failed: {stdout}");
+
+    let rows: Vec<Vec<&str>> = stdout.lines().skip(1).map(|line| line.split_whitespace().collect()).collect();
+    assert_eq!(rows.len(), 2, "{stdout}");
+
+    for row in rows {
+        let round_trips: u64 = row[2].parse().unwrap();
+        let bytes_per_second: f64 = row[3].parse().unwrap();
+
+        assert!(round_trips > 0 && bytes_per_second > 0.0, "{stdout}");
+    }
+
+    touchppp().args(["bench", "--mode", "fast"]).assert().code(2);
+}
+
+#[test]
+fn soak_says_every_byte_made_it() {
+    let output = touchppp().args(["soak", "--duration", "1s", "--rate", "5k"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(output.status.success(), "touchppp soak failed: {stdout}");
+    assert!(stdout.contains("up:   sent 5000 bytes, got 5000 back, 0 didn't match\n"), "{stdout}");
+    assert!(stdout.ends_with("Every byte made it through intact.\n"), "{stdout}");
+
+    touchppp().args(["soak", "--rate", "fast"]).assert().code(2);
+}
+
+#[test]
+fn check_passes_a_good_config() {
+    let config = scratch_path("good.toml");
+    std::fs::write(&config, "default_backend = \"isp\"\n\n[backend.isp]\nconnect = \"127.0.0.1:2323\"\n\n[phonebook]\n\"1800*\" = \"isp\"\n").unwrap();
+
+    let output = touchppp().args(["--check", "-l", &free_port().to_string()]).arg("--config").arg(&config).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let _ = std::fs::remove_file(&config);
+
+    assert!(output.status.success(), "--check failed: {stdout}");
+    assert!(stdout.contains("Dials go to backend isp (connect 127.0.0.1:2323)"));
+    assert!(stdout.contains("Phone book entries: 1, named backends: 1."));
+    assert!(stdout.contains("Config is OK."));
+}
+
+#[test]
+fn check_fails_a_broken_config() {
+    let config = scratch_path("broken.toml");
+    std::fs::write(&config, "[backend.isp]\nexec = \"/no/such/pppd notty\"\n\n[phonebook]\n\"1800*\" = \"isp\"\n").unwrap();
+
+    let output = touchppp().args(["serve", "--dry-run", "-l", &free_port().to_string()]).arg("--config").arg(&config).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(1), "--dry-run should fail: {stdout}");
+    assert!(stdout.contains("Problem: can't find the program to run for backend isp (exec '/no/such/pppd notty')"));
+
+    // A phone book entry pointing nowhere stops the config from loading at all.
+    std::fs::write(&config, "[phonebook]\n\"1800*\" = \"isp\"\n").unwrap();
+
+    let output = touchppp().arg("--check").arg("--config").arg(&config).output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    let _ = std::fs::remove_file(&config);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("points to backend 'isp' which isn't defined"));
+}
+
+#[test]
+fn remote_udp_backends_come_from_the_command_line_or_the_file() {
+    let output = touchppp().args(["--print-config", "--remote-udp", "127.0.0.1:2323", "-c", "127.0.0.1:2323"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--remote-udp and --connect"));
+
+    let config = scratch_path("udp.toml");
+    std::fs::write(&config, "[backend.isp]\nudp = \"127.0.0.1:2323\"\n\n[phonebook]\n\"1800*\" = \"isp\"\n").unwrap();
+
+    let output = touchppp().args(["--print-config", "--remote-udp", "127.0.0.1:2324"]).arg("--config").arg(&config).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let _ = std::fs::remove_file(&config);
+
+    assert!(output.status.success());
+    assert!(stdout.contains("remote_udp = \"127.0.0.1:2324\"  # cli\n"), "{stdout}");
+    assert!(stdout.contains("[backend.isp]  # file\nudp = \"127.0.0.1:2323\"\nexclusive = false\n"), "{stdout}");
+}
+
+#[test]
+fn print_config_says_where_each_setting_came_from() {
+    let config = scratch_path("print.toml");
+    std::fs::write(&config, "verbose = 1\n\n[backend.isp]\nexec = \"pppd notty\"\nenv = { PPP_PASSWORD = \"hunter2\" }\n").unwrap();
+
+    let output = touchppp()
+        .args(["--print-config", "--connect-timeout", "7"])
+        .arg("--config").arg(&config)
+        .env("TOUCHPPP_LOG_KEEP", "3")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let _ = std::fs::remove_file(&config);
+
+    assert!(output.status.success());
+    assert!(stdout.contains("connect_timeout = 7  # cli\n"), "{stdout}");
+    assert!(stdout.contains("log_keep = 3  # env\n"), "{stdout}");
+    assert!(stdout.contains("verbose = 1  # file\n"), "{stdout}");
+    assert!(stdout.contains("log_format = \"text\"  # default\n"), "{stdout}");
+    assert!(stdout.contains("# log_file isn't set (default)\n"), "{stdout}");
+
+    assert!(stdout.contains("PPP_PASSWORD = \"********\""));
+    assert!(!stdout.contains("hunter2"));
+}
+
+#[test]
+fn print_config_shows_the_builtin_backend() {
+    // The environment's -c loses to the command line's --backend-builtin.
+    let stdout = stdout_of(touchppp().args(["--print-config", "--backend-builtin", "null"]).env("TOUCHPPP_CONNECT", "127.0.0.1:2323"));
+
+    assert!(stdout.contains("backend_builtin = \"null\"  # cli\n"), "{stdout}");
+    assert!(!stdout.contains("connect = "), "{stdout}");
+
+    // The other way around, -c wins.
+    let stdout = stdout_of(touchppp().args(["--print-config", "-c", "127.0.0.1:2323"]).env("TOUCHPPP_BACKEND_BUILTIN", "echo"));
+
+    assert!(stdout.contains("connect = [\"127.0.0.1:2323\"]  # cli\n"), "{stdout}");
+    assert!(!stdout.contains("backend_builtin"), "{stdout}");
+}
+
+#[cfg(feature = "nat")]
+#[test]
+fn print_config_shows_the_nat_backend() {
+    let stdout = stdout_of(touchppp().args(["--print-config", "--backend-builtin", "nat", "--nat-pool", "192.168.7.0/24"]).env("TOUCHPPP_NAT_DNS", "1.1.1.1,9.9.9.9:5353"));
+
+    assert!(stdout.contains("backend_builtin = \"nat\"  # cli\n"), "{stdout}");
+    assert!(stdout.contains("nat_pool = \"192.168.7.0/24\"  # cli\n"), "{stdout}");
+    assert!(stdout.contains("nat_dns = [\"1.1.1.1:53\", \"9.9.9.9:5353\"]  # env\n"), "{stdout}");
+
+    for args in [["--nat-pool", "192.168.7.1/24"], ["--nat-pool", "10.0.0.0/31"], ["--nat-dns", "dns.example.com"]] {
+        touchppp().args(["--backend-builtin", "nat"]).args(args).assert().code(2);
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "tun"))]
+fn print_config_shows_the_tun_backend() {
+    let stdout = stdout_of(touchppp().args(["--print-config", "--backend-builtin", "tun", "--tun-peer", "10.0.3.9"]).env("TOUCHPPP_TUN_DNS", "1.1.1.1,9.9.9.9"));
+
+    assert!(stdout.contains("backend_builtin = \"tun\"  # cli\n"), "{stdout}");
+    assert!(stdout.contains("tun_name = \"touchppp0\"  # default\n"), "{stdout}");
+    assert!(stdout.contains("tun_peer = \"10.0.3.9\"  # cli\n"), "{stdout}");
+    assert!(stdout.contains("tun_dns = [\"1.1.1.1\", \"9.9.9.9\"]  # env\n"), "{stdout}");
+
+    for args in [["--tun-peer", "10.0.3.1"], ["--tun-name", "a-name-far-too-long"], ["--tun-dns", "dns.example.com"]] {
+        touchppp().args(["--backend-builtin", "tun"]).args(args).assert().code(2);
+    }
+}
+
+#[test]
+#[cfg(feature = "nat")]
+fn check_says_nat_cant_take_slip() {
+    let output = touchppp().args(["--check", "-l", &free_port().to_string(), "--backend-builtin", "nat", "--link-protocol", "slip"]).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    assert!(stdout.contains("backend builtin is the built-in nat, which only speaks PPP, not --link-protocol slip"), "{stdout}");
+}
+
+#[test]
+fn phone_book_entries_can_change_how_a_dial_goes() {
+    let config = scratch_path("dial-overrides.toml");
+    std::fs::write(&config, "[backend.isp]\nconnect = \"127.0.0.1:2323\"\n\n[phonebook]\n\"1800*\" = { backend = \"isp\", connect_speed = 57600, force_56k = true }\n5551212 = { carrier_speed = 26400, dial_delay = 2000 }\n").unwrap();
+
+    let stdout = stdout_of(touchppp().arg("--print-config").arg("--config").arg(&config));
+
+    assert!(stdout.contains("5551212 = { carrier_speed = 26400, dial_delay = 2000 }\n"), "{stdout}");
+    assert!(stdout.contains("\"1800*\" = { backend = \"isp\", connect_speed = 57600, force_56k = true }\n"), "{stdout}");
+
+    // A speed there's no result code for is caught the same as --connect-speed's.
+    std::fs::write(&config, "[phonebook]\n5551212 = { connect_speed = 12345 }\n").unwrap();
+    let output = touchppp().arg("--check").arg("--config").arg(&config).output().unwrap();
+
+    let _ = std::fs::remove_file(&config);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("phone book entry '5551212' can't report that connect speed"));
+}
+
+#[test]
+fn the_56k_flags_pin_it_one_way() {
+    assert!(stderr_of(touchppp().args(["--force-56k", "--no-56k"])).contains("the argument '--force-56k' cannot be used with '--no-56k'"));
+    assert!(stderr_of(touchppp().env("TOUCHPPP_FORCE_56K", "1").env("TOUCHPPP_NO_56K", "1")).contains("TOUCHPPP_FORCE_56K and TOUCHPPP_NO_56K can't both be set"));
+    touchppp().args(["--56k-numbers", "555-CALL"]).assert().code(2);
+
+    // The command line's beats the environment's, the same as any other setting.
+    let stdout = stdout_of(touchppp().args(["--print-config", "--no-56k", "--56k-numbers", "1-800-613-8199"]).env("TOUCHPPP_FORCE_56K", "1"));
+    assert!(stdout.contains("force_56k = false  # cli\n"), "{stdout}");
+    assert!(stdout.contains("56k_numbers = [\"18006138199\"]  # cli\n"), "{stdout}");
+    assert!(stdout.contains("no_56k_numbers = []  # default\n"), "{stdout}");
+
+    let stdout = stdout_of(touchppp().arg("--print-config"));
+    assert!(stdout.contains("# force_56k isn't set (default)\n"), "{stdout}");
+}
+
+#[test]
+fn tcpser_options_map_to_native_ones() {
+    let stdout = stdout_of(touchppp().args(["--print-config", "-s", "57600", "-p", "6400", "-tSs", "-n", "5551212=127.0.0.1:2323"]));
+
+    assert!(stdout.contains("connect_speed = 57600  # cli\n"), "{stdout}");
+    assert!(stdout.contains("listen = \"127.0.0.1:6400\"  # cli\n"), "{stdout}");
+    assert!(stdout.contains("verbose = 2  # cli\n"), "{stdout}");
+    assert!(stdout.contains("[backend.tcpser-5551212]"), "{stdout}");
+    assert!(stdout.contains("5551212 = \"tcpser-5551212\"\n"), "{stdout}");
+
+    let help = stdout_of(touchppp().arg("--help"));
+    assert!(help.contains("tcpser compatibility:"));
+    assert!(help.contains("Example: -tSs"));
+}
+
+#[test]
+fn tcpser_options_clash_with_native_ones() {
+    for (args, problem) in [
+        (&["-s", "57600", "--connect-speed", "115200"][..], "-s 57600 is tcpser's way of saying --connect-speed 57600, so give it or --connect-speed, not both"),
+        (&["-p", "6400", "-l", "1122"][..], "-p 6400 is tcpser's way of saying -l 6400, so give it or --listen, not both"),
+        (&["-tSs", "-v"][..], "-tSs is tcpser's way of saying -vv, so give it or --verbose, not both"),
+        (&["-n", "5551212=127.0.0.1:2323", "-c", "127.0.0.1:2323"][..], "-n adds to the phone book, which --connect skips"),
+        (&["-n", "5551212=127.0.0.1:2323", "-n", "555-1212=127.0.0.1:2324"][..], "-n gives 555-1212 more than once"),
+    ] {
+        let output = touchppp().args(args).output().unwrap();
+
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert!(String::from_utf8(output.stderr).unwrap().contains(problem), "{args:?}");
+    }
+
+    for args in [["-t", "x"], ["-n", "5551212"], ["-p", "99999"]] {
+        touchppp().args(args).assert().code(2);
+    }
+}
+
+#[test]
+fn tcpser_numbers_get_dialed() {
+    let port = free_port().to_string();
+
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_touchppp"))
+        .args(["-p", &port, "-n", &format!("5551212={}", echo_server())])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    sleep(Duration::from_millis(300));
+
+    let output = touchppp().args(["dial", "5551212", "--to", &port]).output().unwrap();
+
+    let _ = server.kill();
+    let log = String::from_utf8(server.wait_with_output().unwrap().stdout).unwrap();
+
+    assert!(output.status.success(), "dial failed: {}", String::from_utf8_lossy(&output.stdout));
+    assert!(log.contains(&format!("Taking tcpser's -p {port} as -l {port}.")), "{log}");
+    assert!(log.contains("as a phone book entry sending 5551212 to backend 'tcpser-5551212'"), "{log}");
+}
+
+#[test]
+fn logs_only_get_color_when_asked_for() {
+    let log = |color: &str| {
+        let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_touchppp"))
+            .args(["-l", &free_port().to_string(), "--color", color])
+            .env_remove("NO_COLOR")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        sleep(Duration::from_millis(300));
+
+        let _ = server.kill();
+        String::from_utf8(server.wait_with_output().unwrap().stdout).unwrap()
+    };
+
+    // A pipe isn't a terminal, so auto means none.
+    let plain = log("auto");
+    assert!(plain.contains(" INFO "), "{plain}");
+    assert!(!plain.contains('\x1b'), "{plain}");
+
+    assert!(log("always").contains("\x1b[32m INFO\x1b[0m"));
+}
+
+#[test]
+fn help_names_every_log_target() {
+    let help = String::from_utf8(touchppp().arg("--help").output().unwrap().stdout).unwrap();
+
+    for target in touchppp::server::LOG_TARGETS {
+        assert!(help.contains(target), "--help doesn't mention {target}");
+    }
+}
+
+#[test]
+fn bad_log_filters_are_usage_errors() {
+    touchppp().args(["--log-filter", "touchppp::at=loud"]).assert().code(2);
+}
+
+#[test]
+fn flags_beat_the_environment_which_beats_the_file() {
+    let config = scratch_path("precedence.toml");
+    std::fs::write(&config, "connect_timeout = 3\nverbose = 1\n").unwrap();
+
+    let print_config = |args: &[&str], env: &[(&str, &str)]| {
+        let mut command = touchppp();
+        command.arg("--print-config").args(args).env_remove("TOUCHPPP_CONNECT_TIMEOUT").env_remove("TOUCHPPP_VERBOSE");
+        for (name, value) in env {
+            command.env(name, value);
+        }
+
+        stdout_of(&mut command)
+    };
+    let with_file = ["--config", config.to_str().unwrap()];
+    let env = [("TOUCHPPP_CONNECT_TIMEOUT", "5"), ("TOUCHPPP_VERBOSE", "2")];
+
+    let stdout = print_config(&[&with_file[..], &["--connect-timeout", "7", "-v"]].concat(), &env);
+    assert!(stdout.contains("connect_timeout = 7  # cli\n"), "{stdout}");
+    assert!(stdout.contains("verbose = 1  # cli\n"), "{stdout}");
+
+    let stdout = print_config(&with_file, &env);
+    assert!(stdout.contains("connect_timeout = 5  # env\n"), "{stdout}");
+    assert!(stdout.contains("verbose = 2  # env\n"), "{stdout}");
+
+    let stdout = print_config(&with_file, &[]);
+    assert!(stdout.contains("connect_timeout = 3  # file\n"), "{stdout}");
+    assert!(stdout.contains("verbose = 1  # file\n"), "{stdout}");
+
+    let stdout = print_config(&[], &[]);
+    assert!(stdout.contains("connect_timeout = 10  # default\n"), "{stdout}");
+    assert!(stdout.contains("verbose = 0  # default\n"), "{stdout}");
+
+    // With the flag there, what's in the environment isn't even looked at.
+    let stdout = print_config(&["--connect-timeout", "7"], &[("TOUCHPPP_CONNECT_TIMEOUT", "soon")]);
+    assert!(stdout.contains("connect_timeout = 7  # cli\n"), "{stdout}");
+    touchppp().args(["--print-config"]).env("TOUCHPPP_CONNECT_TIMEOUT", "soon").assert().code(2);
+
+    let _ = std::fs::remove_file(&config);
+}
@@ -0,0 +1,201 @@
+// An in-process TouchPPP on a port of its own and a fake MAME to call it with. Every read has a timeout, so a
+// TouchPPP that goes quiet fails the test instead of hanging it.
+//
+//   let touchppp = Harness::start(BackendKind::Echo);
+//   let mut mame = touchppp.call();
+//
+//   mame.enter_data_mode("18006138199");
+//   mame.push_bytes(b"~ppp~");
+//   mame.expect_bytes(b"~ppp~");
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use touchppp::config::{Backend, BackendKind, Config};
+use touchppp::Server;
+
+// How long any one reply gets before the test gives up on it.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long to wait to be sure nothing's coming.
+pub const QUIET_TIME: Duration = Duration::from_millis(100);
+
+pub const OK: &[u8] = b"OK\r\n";
+pub const SETUP_OK: &[u8] = b"\r\n0\r\n";
+pub const DIAL_OK: &[u8] = b"0\r\n";
+pub const CONNECT: &[u8] = b"79\r\n67\r\n19\r\n";
+pub const BUSY: &[u8] = b"7\r\n";
+pub const NO_CARRIER: &[u8] = b"3\r\n";
+
+// The init string a WebTV box sends first.
+pub const WEBTV_INIT: &str = "ATE0Q0V0&C1&D2S0=0";
+
+// A TouchPPP serving in its own thread until it's dropped.
+pub struct Harness {
+    address: SocketAddr,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<Result<(), String>>>,
+}
+
+impl Harness {
+    // Takes every call with `kind` as the backend.
+    pub fn start(kind: BackendKind) -> Harness {
+        Harness::start_with(Config::for_backend(Backend {
+            name: "test".to_string(),
+            kind,
+        }))
+    }
+
+    // For tests that need more than a backend changed. Leave listen_address on port 0.
+    pub fn start_with(config: Config) -> Harness {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let server = runtime.block_on(Server::bind(config)).unwrap();
+        let address = server.local_addr().expect("the harness only listens on TCP");
+
+        let (stop, stopped) = oneshot::channel();
+
+        let thread = std::thread::spawn(move || {
+            runtime.block_on(server.run_until(async {
+                // Dropping the harness without stopping it stops it too.
+                let _ = stopped.await;
+            })).map_err(|e| e.to_string())
+        });
+
+        Harness {
+            address,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    // Stops TouchPPP the way SIGTERM would, giving back how the server says that went once it's done.
+    pub fn shut_down(mut self) -> Result<(), String> {
+        let _ = self.stop.take().unwrap().send(());
+
+        match self.thread.take().unwrap().join() {
+            Ok(ran) => ran,
+            Err(_) => panic!("the harness's server thread panicked"),
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // A new call, as MAME would make it.
+    pub fn call(&self) -> FakeMame {
+        let stream = TcpStream::connect(self.address).unwrap();
+        stream.set_read_timeout(Some(REPLY_TIMEOUT)).unwrap();
+
+        FakeMame { stream }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+
+        if let Some(thread) = self.thread.take() {
+            // A panic in there already failed the test; don't panic again while unwinding.
+            if !std::thread::panicking() {
+                match thread.join() {
+                    Ok(ran) => ran.expect("the server didn't stop cleanly"),
+                    Err(_) => panic!("the harness's server thread panicked"),
+                }
+            }
+        }
+    }
+}
+
+// MAME's end of the null modem.
+pub struct FakeMame {
+    stream: TcpStream,
+}
+
+impl FakeMame {
+    // Sends one AT command line; the \r is added.
+    pub fn send_at(&mut self, command: &str) {
+        self.push_bytes(format!("{command}\r").as_bytes());
+    }
+
+    // Fails unless exactly `result` comes next.
+    pub fn expect_result(&mut self, result: &[u8]) {
+        let got = self.read(result.len());
+
+        assert_eq!(String::from_utf8_lossy(&got), String::from_utf8_lossy(result), "wrong result code");
+    }
+
+    // send_at then expect_result.
+    pub fn at(&mut self, command: &str, result: &[u8]) {
+        self.send_at(command);
+        self.expect_result(result);
+    }
+
+    // What a WebTV does to get online: the init string, the dial setup, the number, then ATD for data mode.
+    pub fn enter_data_mode(&mut self, number: &str) {
+        self.at(WEBTV_INIT, OK);
+        self.at("ATS7=60L3", SETUP_OK);
+        self.at(&format!("ATDT{number}"), DIAL_OK);
+        self.at("ATD", CONNECT);
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    // Fails unless exactly `bytes` come next.
+    pub fn expect_bytes(&mut self, bytes: &[u8]) {
+        assert_eq!(self.read(bytes.len()), bytes, "wrong bytes");
+    }
+
+    // Fails if anything at all comes within QUIET_TIME.
+    pub fn expect_nothing(&mut self) {
+        self.stream.set_read_timeout(Some(QUIET_TIME)).unwrap();
+
+        let mut extra = [0; 64];
+        let read = self.stream.read(&mut extra);
+
+        self.stream.set_read_timeout(Some(REPLY_TIMEOUT)).unwrap();
+
+        match read {
+            Ok(0) => panic!("expected nothing but TouchPPP hung up"),
+            Ok(n) => panic!("expected nothing but got {:?}", String::from_utf8_lossy(&extra[..n])),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {},
+            Err(e) => panic!("expected nothing but the read failed: {e}"),
+        }
+    }
+
+    // Fails unless TouchPPP hangs up, with `last` (like NO_CARRIER) the only thing sent before it does.
+    pub fn expect_hang_up(&mut self, last: &[u8]) {
+        let mut rest = Vec::new();
+
+        match self.stream.read_to_end(&mut rest) {
+            Ok(_) => assert_eq!(String::from_utf8_lossy(&rest), String::from_utf8_lossy(last), "wrong goodbye"),
+            Err(e) => panic!("TouchPPP didn't hang up: {e}"),
+        }
+    }
+
+    // MAME hanging up.
+    pub fn hang_up(self) {
+        drop(self);
+    }
+
+    fn read(&mut self, len: usize) -> Vec<u8> {
+        let mut got = vec![0; len];
+
+        if let Err(e) = self.stream.read_exact(&mut got) {
+            panic!("wanted {len} bytes but {}", match e.kind() {
+                ErrorKind::WouldBlock | ErrorKind::TimedOut => "they didn't come in time".to_string(),
+                ErrorKind::UnexpectedEof => "TouchPPP hung up".to_string(),
+                _ => e.to_string(),
+            });
+        }
+
+        got
+    }
+}
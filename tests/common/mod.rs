@@ -0,0 +1,108 @@
+// Helpers for the tests that run a real touchppp and play MAME against it.
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+pub mod fake_mame;
+
+pub fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("touchppp-test-{}-{name}", std::process::id()))
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+pub struct KillOnDrop(pub Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// touchppp with the given options, quietly in the background.
+pub fn spawn_touchppp(args: &[&str]) -> KillOnDrop {
+    KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_touchppp"))
+            .args(args)
+            // Not the terminal cargo test was run from, or it would get a console.
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    )
+}
+
+// Waits for touchppp to exit by itself, for how it went.
+pub fn exited(touchppp: &mut KillOnDrop) -> std::process::ExitStatus {
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = touchppp.0.try_wait().unwrap() {
+            return status;
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(5), "touchppp should have stopped by now");
+        sleep(Duration::from_millis(50));
+    }
+}
+
+// A PPP server that sends everything straight back.
+pub fn echo_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    std::thread::spawn(move || {
+        for ppp in listener.incoming() {
+            let Ok(mut ppp) = ppp else {
+                return;
+            };
+
+            let mut reader = ppp.try_clone().unwrap();
+            std::thread::spawn(move || std::io::copy(&mut reader, &mut ppp));
+        }
+    });
+
+    port
+}
+
+// Keeps trying until touchppp is listening.
+pub fn connect(port: u16) -> TcpStream {
+    let started = Instant::now();
+
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(mame) => {
+                mame.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+                return mame;
+            },
+            Err(_) if started.elapsed() < Duration::from_secs(5) => sleep(Duration::from_millis(50)),
+            Err(e) => panic!("touchppp never started listening: {e}"),
+        }
+    }
+}
+
+pub fn at(mame: &mut TcpStream, command: &str, reply: &[u8]) {
+    mame.write_all(command.as_bytes()).unwrap();
+
+    let mut got = vec![0; reply.len()];
+    mame.read_exact(&mut got).unwrap();
+
+    assert_eq!(got, reply, "reply to {command:?}");
+}
+
+// What a WebTV does to get into data mode.
+pub fn dial(mame: &mut TcpStream, number: &str) {
+    at(mame, "ATE0\r", b"OK\r\n");
+    at(mame, &format!("ATDT{number}\r"), b"0\r\n");
+    at(mame, "ATD\r", b"79\r\n67\r\n19\r\n");
+}
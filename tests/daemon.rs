@@ -0,0 +1,62 @@
+#![cfg(unix)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use assert_cmd::Command;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("touchppp-test-{}-{name}", std::process::id()))
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+#[test]
+fn daemon_answers_and_cleans_up_its_pid_file() {
+    let port = free_port();
+    let log_file = scratch_path("daemon.log");
+    let pid_file = scratch_path("daemon.pid");
+
+    Command::cargo_bin("touchppp").unwrap()
+        .args(["-l", &port.to_string(), "--daemon"])
+        .arg("--log-file").arg(&log_file)
+        .arg("--pid-file").arg(&pid_file)
+        .assert()
+        .success();
+
+    // The parent only exits once the daemon has written the pid file.
+    let pid: i32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+
+    let mut mame = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    mame.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    mame.write_all(b"ATE0\r").unwrap();
+
+    let mut reply = [0; 4];
+    mame.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"OK\r\n");
+
+    kill(Pid::from_raw(pid), Signal::SIGTERM).unwrap();
+
+    let started = Instant::now();
+    while pid_file.exists() && started.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(50));
+    }
+
+    let _ = std::fs::remove_file(&log_file);
+
+    assert!(!pid_file.exists(), "the daemon should remove its pid file when it stops");
+}
+
+#[test]
+fn daemon_needs_a_log_file() {
+    Command::cargo_bin("touchppp").unwrap()
+        .args(["-l", &free_port().to_string(), "--daemon"])
+        .assert()
+        .code(2);
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use serde_json::Value;
+
+use common::*;
+
+// The dial log's lines once there are `count` of them.
+fn records(file: &str, count: usize) -> Vec<Value> {
+    let started = Instant::now();
+
+    loop {
+        let lines = std::fs::read_to_string(file).unwrap_or_default();
+        if lines.lines().count() >= count || started.elapsed() > Duration::from_secs(5) {
+            return lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        }
+
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn every_dial_is_logged_and_every_call_that_went_through_is_logged_again_when_its_over() {
+    let dial_log = scratch_path("dials.jsonl");
+    let _ = std::fs::remove_file(&dial_log);
+    let dial_log = dial_log.to_str().unwrap();
+
+    // One that goes through.
+    let backend = echo_server();
+    let port = free_port();
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &format!("127.0.0.1:{backend}"), "--dial-log", dial_log, "--dial-log-sync"]);
+    let mut mame = connect(port);
+    dial(&mut mame, "18006138199");
+    drop(mame);
+
+    let logged = records(dial_log, 2);
+    drop(touchppp);
+
+    // And one that's BUSY, with nobody there to answer.
+    let nobody = free_port();
+    let port = free_port();
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &format!("127.0.0.1:{nobody}"), "--dial-log", dial_log]);
+    let mut mame = connect(port);
+    at(&mut mame, "ATE0\r", b"OK\r\n");
+    at(&mut mame, "ATDT5551212\r", b"0\r\n");
+    at(&mut mame, "ATD\r", b"7\r\n");
+
+    let records = records(dial_log, 3);
+    drop(mame);
+    drop(touchppp);
+    std::fs::remove_file(dial_log).unwrap();
+
+    assert_eq!(&records[..2], &logged[..]);
+    assert_eq!(records.len(), 3, "{records:?}");
+
+    let (connected, disconnected, busy) = (&records[0], &records[1], &records[2]);
+
+    assert_eq!(connected["record"], "attempt");
+    assert_eq!(connected["session"], 1);
+    assert_eq!(connected["number"], "18006138199");
+    assert_eq!(connected["backend"], "command line");
+    assert_eq!(connected["outcome"], "connected");
+    assert_eq!((&connected["connect_speed"], &connected["carrier_speed"]), (&Value::from(115200), &Value::from(33600)));
+    assert!(connected["client"].as_str().unwrap().starts_with("127.0.0.1:"));
+    assert!(connected["ts"].is_string());
+
+    assert_eq!(disconnected["record"], "disconnect");
+    assert_eq!(disconnected["attempt"], connected["attempt"]);
+    assert_eq!(disconnected["reason"], "call ended");
+    assert!(disconnected["duration_ms"].is_u64());
+
+    assert_eq!(busy["record"], "attempt");
+    assert_eq!(busy["number"], "5551212");
+    assert_eq!(busy["outcome"], "busy");
+    assert_eq!(busy["connect_speed"], Value::Null);
+    assert!(busy["result"].as_str().unwrap().contains("refused"), "{busy}");
+    assert_ne!(busy["attempt"], connected["attempt"]);
+}
@@ -0,0 +1,205 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{at, connect, dial, echo_server, free_port, scratch_path, KillOnDrop};
+
+// touchppp listening on `port`, with whatever it logs kept for logged.
+fn spawn_logging(port: u16, args: &[&str]) -> KillOnDrop {
+    KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_touchppp"))
+            .args(["-l", &port.to_string()])
+            .args(args)
+            .env_remove("RUST_LOG")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    )
+}
+
+// Stops touchppp, giving back what it wrote to stdout and stderr.
+fn logged(mut touchppp: KillOnDrop) -> (String, String) {
+    // Long enough for the last lines to be written.
+    sleep(Duration::from_millis(300));
+
+    let _ = touchppp.0.kill();
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    touchppp.0.stdout.take().unwrap().read_to_string(&mut stdout).unwrap();
+    touchppp.0.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+
+    (stdout, stderr)
+}
+
+// Two calls to a touchppp started with `args`: one through a backend whose first server refuses (a warning)
+// that goes online and sends a few bytes, and one to a backend that can't be reached at all (an error). Gives
+// back what it wrote to stdout and stderr.
+fn log_two_calls(name: &str, args: &[&str]) -> (String, String) {
+    let refused = free_port();
+    let config = scratch_path(&format!("{name}.toml"));
+    std::fs::write(&config, format!(
+        "default_backend = \"isp\"\n\n[backend.isp]\nconnect = [\"127.0.0.1:{refused}\", \"127.0.0.1:{}\"]\nconnect_retries = 0\n\n[backend.gone]\nconnect = \"127.0.0.1:{refused}\"\nconnect_retries = 0\n\n[phonebook]\n5550000 = \"gone\"\n",
+        echo_server(),
+    )).unwrap();
+
+    let port = free_port();
+    let touchppp = spawn_logging(port, &[&["--config", config.to_str().unwrap()], args].concat());
+
+    let mut mame = connect(port);
+    dial(&mut mame, "5551212");
+    mame.write_all(b"~ping~").unwrap();
+    let mut echoed = [0; 6];
+    mame.read_exact(&mut echoed).unwrap();
+    drop(mame);
+
+    let mut mame = connect(port);
+    at(&mut mame, "ATE0\r", b"OK\r\n");
+    at(&mut mame, "ATDT5550000\r", b"0\r\n");
+    mame.write_all(b"ATD\r").unwrap();
+    let _ = mame.read(&mut [0; 16]).unwrap();
+    drop(mame);
+
+    let _ = std::fs::remove_file(&config);
+
+    logged(touchppp)
+}
+
+const INFO: &str = "Listening on 127.0.0.1:";
+const WARNING: &str = "Couldn't touch PPP @ 127.0.0.1:";
+const ERROR: &str = "Couldn't reach backend gone";
+
+#[test]
+fn quiet_only_logs_errors() {
+    let (stdout, stderr) = log_two_calls("quiet", &["-q"]);
+
+    assert_eq!(stdout, "");
+    assert!(stderr.contains(ERROR), "{stderr}");
+    assert!(!stderr.contains(WARNING), "{stderr}");
+}
+
+#[test]
+fn problems_go_to_stderr_and_the_rest_to_stdout() {
+    let (stdout, stderr) = log_two_calls("default", &[]);
+
+    assert!(stdout.contains(INFO), "{stdout}");
+    assert!(stderr.contains(WARNING), "{stderr}");
+    assert!(stderr.contains(ERROR), "{stderr}");
+    assert!(!stderr.contains(INFO), "{stderr}");
+    assert!(!stdout.contains(WARNING) && !stdout.contains(ERROR), "{stdout}");
+}
+
+// -v's debug lines, and -vv's trace ones.
+const DEBUG: &str = "Dialed '5551212', using backend isp";
+const TRACE: &str = "MAME->PPP 6 bytes:";
+
+#[test]
+fn verbose_adds_debug_lines() {
+    let (stdout, stderr) = log_two_calls("verbose", &["-v"]);
+
+    assert!(stdout.contains(INFO) && stdout.contains(DEBUG), "{stdout}");
+    assert!(!stdout.contains(TRACE), "{stdout}");
+    assert!(stderr.contains(WARNING) && stderr.contains(ERROR), "{stderr}");
+    assert!(!stderr.contains(DEBUG), "{stderr}");
+
+    // Not without it.
+    let (stdout, _) = log_two_calls("not-verbose", &[]);
+    assert!(!stdout.contains(DEBUG), "{stdout}");
+}
+
+#[test]
+fn very_verbose_adds_trace_lines() {
+    let (stdout, stderr) = log_two_calls("very-verbose", &["-vv"]);
+
+    assert!(stdout.contains(INFO) && stdout.contains(DEBUG) && stdout.contains(TRACE), "{stdout}");
+    assert!(stderr.contains(WARNING) && stderr.contains(ERROR), "{stderr}");
+    assert!(!stderr.contains(TRACE), "{stderr}");
+}
+
+#[test]
+fn json_logs_are_a_json_object_a_line() {
+    let (stdout, stderr) = log_two_calls("json", &["--log-format", "json", "-v"]);
+
+    let records = |output: &str| -> Vec<serde_json::Value> {
+        output.lines().map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{line:?} isn't JSON: {e}"))).collect()
+    };
+    let stdout = records(&stdout);
+    let stderr = records(&stderr);
+
+    for record in stdout.iter().chain(&stderr) {
+        for field in ["ts", "level", "session", "event", "msg", "target"] {
+            assert!(record.get(field).is_some(), "no {field} in {record}");
+        }
+    }
+    assert!(stdout.iter().all(|record| ["info", "debug"].contains(&record["level"].as_str().unwrap())));
+    assert!(stderr.iter().all(|record| ["warn", "error"].contains(&record["level"].as_str().unwrap())));
+
+    // Events carry their own fields as JSON, and the session's from its span.
+    let connect = stdout.iter().find(|record| record["event"] == "connect").expect("no connect event");
+    assert_eq!(connect["session"], 1);
+    assert!(connect["client_addr"].as_str().unwrap().starts_with("127.0.0.1:"), "{connect}");
+
+    let dial = stdout.iter().find(|record| record["event"] == "dial").expect("no dial event");
+    assert_eq!((&dial["session"], &dial["dialed_number"], &dial["backend"], &dial["target"]), (&1.into(), &"5551212".into(), &"isp".into(), &"touchppp::backend".into()));
+
+    let error = stderr.iter().find(|record| record["level"] == "error").expect("no error");
+    assert_eq!(error["session"], 2);
+    assert!(error["msg"].as_str().unwrap().starts_with(ERROR), "{error}");
+}
+
+#[test]
+fn every_line_from_a_call_has_its_session() {
+    let port = free_port();
+    let touchppp = spawn_logging(port, &["-v"]);
+
+    // Both up at once, taking turns.
+    let mut first = connect(port);
+    let mut second = connect(port);
+    for _ in 0..3 {
+        at(&mut first, "ATS7=11\r", b"\r\n0\r\n");
+        at(&mut second, "ATS7=22\r", b"\r\n0\r\n");
+    }
+    let clients = [first.local_addr().unwrap().to_string(), second.local_addr().unwrap().to_string()];
+    drop((first, second));
+
+    let (stdout, _) = logged(touchppp);
+
+    // The session and client of each line that says which call's command it was.
+    let span_of = |command: &str| -> Vec<(String, String)> {
+        stdout.lines().filter(|line| line.ends_with(command)).map(|line| {
+            let span = line.split_once("session{").and_then(|(_, span)| span.split_once('}')).unwrap_or_else(|| panic!("no session on {line:?}")).0;
+            let session = span.split_once("session=").unwrap().1.split(' ').next().unwrap();
+            let client = span.split_once("client_addr=").unwrap().1;
+
+            (session.to_string(), client.to_string())
+        }).collect()
+    };
+    let first = span_of("ATS7=11");
+    let second = span_of("ATS7=22");
+
+    assert_eq!(first.len(), 3, "{stdout}");
+    assert_eq!(second.len(), 3, "{stdout}");
+    assert!(first.iter().all(|span| *span == first[0]) && first[0].1 == clients[0], "{first:?}");
+    assert!(second.iter().all(|span| *span == second[0]) && second[0].1 == clients[1], "{second:?}");
+    assert_ne!(first[0].0, second[0].0);
+}
+
+#[test]
+fn every_dial_says_why_it_is_or_isnt_56k() {
+    let port = free_port();
+    let touchppp = spawn_logging(port, &["-v", "-c", &format!("127.0.0.1:{}", echo_server()), "--no-56k-numbers", "5551212"]);
+
+    for number in ["5551212", "18006138199"] {
+        let mut mame = connect(port);
+        dial(&mut mame, number);
+    }
+
+    let (stdout, _) = logged(touchppp);
+
+    assert!(stdout.contains("No 56k for '5551212': listed in --no-56k-numbers"), "{stdout}");
+    assert!(stdout.contains("No 56k for '18006138199': bootstrap number"), "{stdout}");
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::process::Command;
+
+use common::*;
+
+fn get(port: u16, path: &str) -> String {
+    let mut browser = connect(port);
+    write!(browser, "GET {path} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    browser.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+#[test]
+fn two_modems_answer_on_their_own_ports_as_their_own_modems() {
+    let (webtv, courier, status_port) = (free_port(), free_port(), free_port());
+
+    let config = scratch_path("modems.toml");
+    std::fs::write(&config, format!(r#"
+backend_builtin = "echo"
+status_http = "127.0.0.1:{status_port}"
+
+[[modem]]
+name = "webtv"
+listen = "127.0.0.1:{webtv}"
+
+[[modem]]
+name = "courier"
+listen = "127.0.0.1:{courier}"
+profile = "generic"
+modem_profile = "usr-courier"
+"#)).unwrap();
+
+    let _touchppp = spawn_touchppp(&["--config", config.to_str().unwrap()]);
+
+    let mut box_ = connect(webtv);
+    dial(&mut box_, "18006138199");
+
+    let mut pc = connect(courier);
+    at(&mut pc, "ATE0\r", b"ATE0\r\r\nOK\r\n");
+    at(&mut pc, "ATDT5551212\r", b"\r\nCONNECT 115200/ARQ\r\n");
+
+    for mame in [&mut box_, &mut pc] {
+        mame.write_all(b"~ppp~").unwrap();
+        let mut echoed = [0; 5];
+        mame.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"~ppp~");
+    }
+
+    // Both are counted on the one status page, each under its own name.
+    let metrics = get(status_port, "/metrics");
+    std::fs::remove_file(&config).unwrap();
+
+    assert!(metrics.contains("\nsessions_total{modem=\"courier\"} 1\nsessions_total{modem=\"webtv\"} 1\n"), "{metrics}");
+    assert!(metrics.contains("\nsessions_active{modem=\"courier\"} 1\nsessions_active{modem=\"webtv\"} 1\n"), "{metrics}");
+}
+
+#[test]
+fn modems_that_would_listen_in_the_same_place_are_caught() {
+    let config = scratch_path("same-listen.toml");
+    std::fs::write(&config, r#"
+backend_builtin = "echo"
+listen = "127.0.0.1:1615"
+
+[[modem]]
+name = "one"
+
+[[modem]]
+name = "two"
+"#).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_touchppp")).args(["--config", config.to_str().unwrap(), "--check"]).output().unwrap();
+    std::fs::remove_file(&config).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("[[modem]] 'one' and 'two' both listen on 127.0.0.1:1615"), "{stderr}");
+}
+
+#[test]
+fn settings_every_modem_shares_stay_outside_the_sections() {
+    let config = scratch_path("shared-in-modem.toml");
+    std::fs::write(&config, r#"
+backend_builtin = "echo"
+
+[[modem]]
+name = "one"
+listen = "127.0.0.1:0"
+status_http = "127.0.0.1:0"
+"#).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_touchppp")).args(["--config", config.to_str().unwrap(), "--check"]).output().unwrap();
+    std::fs::remove_file(&config).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("every modem shares status_http, so it goes outside [[modem]] 'one'"), "{stderr}");
+}
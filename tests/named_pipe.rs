@@ -0,0 +1,57 @@
+#![cfg(windows)]
+
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
+
+// Winerror ERROR_PIPE_BUSY: every instance is taken, try again in a bit.
+const ERROR_PIPE_BUSY: i32 = 231;
+
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+    }
+}
+
+async fn open_pipe(name: &str) -> NamedPipeClient {
+    for _ in 0..100 {
+        match ClientOptions::new().open(name) {
+            Ok(client) => return client,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound || e.raw_os_error() == Some(ERROR_PIPE_BUSY) => {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            },
+            Err(e) => panic!("can't open {name}: {e}"),
+        }
+    }
+
+    panic!("touchppp never made {name}");
+}
+
+#[tokio::test]
+async fn at_handshake_over_a_named_pipe() {
+    let name = format!(r"\\.\pipe\touchppp-test-{}", std::process::id());
+
+    let _touchppp = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_touchppp"))
+            .arg("-l")
+            .arg(format!("pipe:{name}"))
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    // Twice, to make sure the next pipe instance is there for the second caller.
+    for _ in 0..2 {
+        let mut mame = open_pipe(&name).await;
+
+        mame.write_all(b"ATE0\r").await.unwrap();
+
+        let mut reply = [0; 4];
+        tokio::time::timeout(Duration::from_secs(5), mame.read_exact(&mut reply)).await.unwrap().unwrap();
+
+        assert_eq!(&reply, b"OK\r\n");
+    }
+}
@@ -0,0 +1,102 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use common::{connect, dial, free_port, scratch_path, spawn_touchppp};
+
+// A PPP server that says who it is when a call comes in, then sends everything straight back. Keeps count of
+// the bytes it's been sent.
+fn named_server(name: &'static [u8]) -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = Arc::new(AtomicUsize::new(0));
+
+    let counted = received.clone();
+    std::thread::spawn(move || {
+        for ppp in listener.incoming() {
+            let Ok(mut ppp) = ppp else {
+                return;
+            };
+
+            let counted = counted.clone();
+            std::thread::spawn(move || {
+                ppp.write_all(name)?;
+
+                let mut buf = [0; 256];
+                loop {
+                    let n = ppp.read(&mut buf)?;
+                    if n == 0 {
+                        return Ok::<_, std::io::Error>(());
+                    }
+
+                    counted.fetch_add(n, Ordering::SeqCst);
+                    ppp.write_all(&buf[..n])?;
+                }
+            });
+        }
+    });
+
+    (port, received)
+}
+
+fn config_for(port: u16) -> String {
+    format!("default_backend = \"ppp\"\n\n[backend.ppp]\nconnect = \"127.0.0.1:{port}\"\n")
+}
+
+fn read_exactly(mame: &mut std::net::TcpStream, len: usize) -> Vec<u8> {
+    let mut got = vec![0; len];
+    mame.read_exact(&mut got).unwrap();
+
+    got
+}
+
+#[test]
+fn sighup_reloads_the_config_for_new_calls_only() {
+    let (old_port, old_received) = named_server(b"old");
+    let (new_port, _) = named_server(b"new");
+
+    let config_file = scratch_path("reload.toml");
+    std::fs::write(&config_file, config_for(old_port)).unwrap();
+
+    let port = free_port();
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "--config", config_file.to_str().unwrap()]);
+
+    // Online with the old server before the reload.
+    let mut online = connect(port);
+    dial(&mut online, "5551212");
+    assert_eq!(read_exactly(&mut online, 3), b"old");
+
+    std::fs::write(&config_file, config_for(new_port)).unwrap();
+    kill(Pid::from_raw(touchppp.0.id() as i32), Signal::SIGHUP).unwrap();
+
+    // The reload's picked up in the background, so dial until a call gets the new server.
+    let started = Instant::now();
+    loop {
+        let mut mame = connect(port);
+        dial(&mut mame, "5551212");
+
+        if read_exactly(&mut mame, 3) == b"new" {
+            break;
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(5), "calls never went to the new server");
+        sleep(Duration::from_millis(50));
+    }
+
+    // The call that was already up is still with the old one.
+    let before = old_received.load(Ordering::SeqCst);
+    online.write_all(b"~still here~").unwrap();
+    assert_eq!(read_exactly(&mut online, 12), b"~still here~");
+    assert_eq!(old_received.load(Ordering::SeqCst), before + 12);
+
+    let _ = std::fs::remove_file(&config_file);
+}
@@ -0,0 +1,1272 @@
+// The library's Session driven over an in-memory pipe, with the exact bytes the binary has always sent back.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use touchppp::at::{Caller, Profile, Protocol};
+use touchppp::backend::{BackendStream, DialContext, PppBackend};
+use touchppp::config::{AutoData, Backend, BackendKind, Builtin, CarrierDrop, CarrierSpeed, Config, DialOverrides, LinkProtocol};
+use touchppp::slip::{self, END, ESC, ESC_END, ESC_ESC};
+use touchppp::stats::{SessionCommand, Stats};
+use touchppp::{admin, Server, Session, TouchPppError};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+fn answer(stats: &Arc<Stats>) -> (DuplexStream, JoinHandle<()>) {
+    answer_with(stats, BackendKind::Echo)
+}
+
+fn answer_with(stats: &Arc<Stats>, kind: BackendKind) -> (DuplexStream, JoinHandle<()>) {
+    let config = Config::for_backend(Backend {
+        name: "test".to_string(),
+        kind,
+    });
+
+    answer_config(stats, config)
+}
+
+fn answer_config(stats: &Arc<Stats>, config: Config) -> (DuplexStream, JoinHandle<()>) {
+    let (_config_sender, config_receiver) = watch::channel(Arc::new(config));
+    let (mame, modem) = tokio::io::duplex(0x1000);
+
+    let session = Session::new(stats, 1, "duplex", config_receiver);
+
+    (mame, tokio::spawn(session.run(modem)))
+}
+
+// Sends `send` and expects exactly `expect` back. Nothing at all for an empty `expect`.
+async fn at(mame: &mut DuplexStream, send: &[u8], expect: &[u8]) {
+    mame.write_all(send).await.unwrap();
+
+    if expect.is_empty() {
+        let mut extra = [0; 1];
+        let read = tokio::time::timeout(Duration::from_millis(50), mame.read(&mut extra)).await;
+
+        assert!(read.is_err(), "sent {:?} and got an answer", String::from_utf8_lossy(send));
+        return;
+    }
+
+    let mut reply = vec![0; expect.len()];
+    tokio::time::timeout(WAIT, mame.read_exact(&mut reply)).await.expect("no answer").unwrap();
+
+    assert_eq!(String::from_utf8_lossy(&reply), String::from_utf8_lossy(expect), "after sending {:?}", String::from_utf8_lossy(send));
+}
+
+async fn hang_up(mame: DuplexStream, session: JoinHandle<()>) {
+    drop(mame);
+
+    tokio::time::timeout(WAIT, session).await.expect("the session didn't end").unwrap();
+}
+
+#[tokio::test]
+async fn answers_a_box_dialing_in() {
+    let stats = Stats::new();
+    let (mut mame, session) = answer(&stats);
+
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATS7=60L3\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    // Online now, so the echo backend sends everything back.
+    at(&mut mame, b"~\xff\x7d\x23ppp~", b"~\xff\x7d\x23ppp~").await;
+
+    hang_up(mame, session).await;
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.dials.connected, 1);
+    assert_eq!(snapshot.bytes_up, 8);
+}
+
+// Up to the dial, with what each gets back under the usual --profile webtv.
+const WEBTV_OS: &[(&[u8], &[u8])] = &[(b"ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n"), (b"ATS7=60L3\r", b"\r\n0\r\n")];
+// Unimodem gets a generic modem from its init string on, which echoes the init string since E0 isn't in yet.
+const WINDOWS_CE: &[(&[u8], &[u8])] = &[
+    (b"AT\r", b"\r\n0\r\n"),
+    (b"AT&FE0V1&C1&D2S7=60S0=0\r", b"AT&FE0V1&C1&D2S7=60S0=0\r\r\nOK\r\n"),
+    (b"ATS7?\r", b"\r\n060\r\n\r\nOK\r\n"),
+];
+const DESKTOP_WINDOWS: &[(&[u8], &[u8])] = &[(b"AT\r", b"\r\n0\r\n"), (b"AT &F E0 V1 &D2 &C1 W2 S95=47 S0=0\r", b"OK\r\n")];
+
+#[tokio::test]
+async fn tells_who_is_calling_from_the_init_string() {
+    for (transcript, caller) in [(WEBTV_OS, Caller::WebtvOs), (WINDOWS_CE, Caller::WindowsCe), (DESKTOP_WINDOWS, Caller::Generic)] {
+        let stats = Stats::new();
+        let (mut mame, session) = answer(&stats);
+
+        for (send, expect) in transcript {
+            at(&mut mame, send, expect).await;
+        }
+
+        assert_eq!(stats.snapshot().sessions[0].caller, Some(caller));
+        let list = admin::reply("list", &stats).await.unwrap();
+        assert!(list.contains(&format!(" caller={caller}\n")), "{list:?}");
+
+        hang_up(mame, session).await;
+    }
+}
+
+#[tokio::test]
+async fn windows_ce_keeps_its_registers_once_its_modem_is_generic() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    for (send, expect) in WINDOWS_CE {
+        at(&mut mame, send, expect).await;
+    }
+
+    // Whatever comes after the init string, the S-registers it set stick.
+    at(&mut mame, b"ATS10=20S11=70\r", b"\r\nOK\r\n").await;
+    at(&mut mame, b"ATE0V1X4\r", b"\r\nOK\r\n").await;
+    at(&mut mame, b"ATS10?\r", b"\r\n020\r\n\r\nOK\r\n").await;
+    at(&mut mame, b"ATS11?\r", b"\r\n070\r\n\r\nOK\r\n").await;
+    at(&mut mame, b"ATS7?\r", b"\r\n060\r\n\r\nOK\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn connect_reports_what_the_init_string_turned_off() {
+    let config = || Config::builder().builtin(Builtin::Echo).protocol_line(Protocol::Lapm).build().unwrap();
+
+    // CARRIER 33600, PROTOCOL: LAPM, COMPRESSION: V.42 bis, CONNECT 115200
+    let (mut mame, session) = answer_config(&Stats::new(), config());
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n77\r\n67\r\n19\r\n").await;
+    hang_up(mame, session).await;
+
+    // PROTOCOL: NONE and COMPRESSION: NONE in their place.
+    let (mut mame, session) = answer_config(&Stats::new(), config());
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0%C0\\N0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n70\r\n69\r\n19\r\n").await;
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn throws_away_command_lines_that_never_end() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    // 10 MB with no CR is held to --max-command-length the whole way, then answered with ERROR once the CR shows up.
+    let line = vec![b'A'; 0x1000];
+    for _ in 0..(10 * 1024 * 1024 / line.len()) {
+        tokio::time::timeout(WAIT, mame.write_all(&line)).await.expect("stopped reading").unwrap();
+    }
+    at(&mut mame, b"\r", b"4\r\n").await;
+
+    at(&mut mame, b"ATZ\r", b"\r\n0\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn looks_at_every_byte_of_a_read() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    // Noise in front of a command, or in the middle of one, is dropped on its own.
+    at(&mut mame, b"\x00ATZ\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"\x00\xffATE0\r", b"OK\r\n").await;
+
+    // Everything up to the CR is answered, and the rest waits for its own.
+    at(&mut mame, b"ATZ\rAT\x00\xff", b"\r\n0\r\n").await;
+    at(&mut mame, b"\r", b"\r\n0\r\n").await;
+
+    // Lower case makes it through too.
+    at(&mut mame, b"atz\r", b"\r\n0\r\n").await;
+
+    // Two lines in one read get two answers.
+    at(&mut mame, b"ATZ\rATE0\r", b"\r\n0\r\nOK\r\n").await;
+
+    at(&mut mame, b"AT\x00DT123\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    at(&mut mame, b"ppp", b"ppp").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn answers_each_command_the_moment_its_cr_shows_up() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    // The CR in the middle of a read, with a stray space after it.
+    at(&mut mame, b"ATZ\r ", b"\r\n0\r\n").await;
+
+    // More than one in a read.
+    at(&mut mame, b"ATZ\rATI3\rAT&F\r", b"\r\n0\r\n\r\n0\r\n\r\n0\r\n").await;
+
+    // The CR on its own.
+    at(&mut mame, b"ATDT1800", b"").await;
+    at(&mut mame, b"\r", b"0\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn command_line_length_can_be_changed() {
+    let config = Config::builder().builtin(Builtin::Echo).max_command_length(8).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    at(&mut mame, b"ATE0Q0V0\r", b"4\r\n").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn results_wait_out_the_command_delay() {
+    let config = Config::builder().builtin(Builtin::Echo).command_delay(100).command_delay_for("I3", 250).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    let started = Instant::now();
+    at(&mut mame, b"ATE0Q0V0\r", b"OK\r\n").await;
+    assert!(started.elapsed() >= Duration::from_millis(100), "answered after {:?}", started.elapsed());
+
+    let started = Instant::now();
+    at(&mut mame, b"ATI3\r", b"\r\n0\r\n").await;
+    assert!(started.elapsed() >= Duration::from_millis(250), "answered after {:?}", started.elapsed());
+
+    // CONNECT is what a dial gets back, but the call itself isn't held up.
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    let started = Instant::now();
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    assert!(started.elapsed() >= Duration::from_millis(100), "answered after {:?}", started.elapsed());
+
+    let started = Instant::now();
+    at(&mut mame, b"~ppp~", b"~ppp~").await;
+    assert!(started.elapsed() < Duration::from_millis(100), "echoed after {:?}", started.elapsed());
+
+    hang_up(mame, session).await;
+
+    // 0 is no wait at all.
+    let config = Config::builder().builtin(Builtin::Echo).command_delay(0).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    let started = Instant::now();
+    at(&mut mame, b"ATE0Q0V0\r", b"OK\r\n").await;
+    assert!(started.elapsed() < Duration::from_millis(100), "answered after {:?}", started.elapsed());
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn connect_reports_the_carrier_the_init_string_allows() {
+    let config = || Config::builder().builtin(Builtin::Echo).build().unwrap();
+
+    // CARRIER 14400 for a box held to V.32bis.
+    let (mut mame, session) = answer_config(&Stats::new(), config());
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0+MS=V32B,1\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"52\r\n67\r\n19\r\n").await;
+    hang_up(mame, session).await;
+
+    // --carrier-speed wins over it.
+    let config = Config::builder().builtin(Builtin::Echo).carrier_speed(CarrierSpeed::Fixed(31200)).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0+MS=V32B,1\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"78\r\n67\r\n19\r\n").await;
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn a_phone_book_entry_changes_how_its_dials_go() {
+    let stats = Stats::new();
+    let overrides = DialOverrides { carrier_speed: Some(CarrierSpeed::Fixed(26400)), dial_delay: Some(Duration::from_millis(200)), throttle: Some(9600), ..Default::default() };
+    let config = Config::builder().builtin(Builtin::Echo).connect_speed(57600).dial_overrides("1800*", overrides).build().unwrap();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    // CARRIER 26400 from the entry, CONNECT 57600 from the command line, once dialing's taken its time.
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    let started = Instant::now();
+    at(&mut mame, b"ATD\r", b"57\r\n67\r\n18\r\n").await;
+    assert!(started.elapsed() >= Duration::from_millis(200), "connected after {:?}", started.elapsed());
+    hang_up(mame, session).await;
+
+    // A number the entry doesn't match gets the command line's.
+    let config = Config::builder().builtin(Builtin::Echo).connect_speed(57600).dial_overrides("1800*", overrides).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+    at(&mut mame, b"ATDT5551212\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n18\r\n").await;
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn answers_every_other_command_with_ok() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    at(&mut mame, b"ATZ\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"AT\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATI3\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATH\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATDT1800\r", b"0\r\n").await;
+    at(&mut mame, b"AT&F\r", b"\r\n0\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn going_back_online_without_a_call_is_no_carrier() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATO\r", b"3\r\n").await;
+    at(&mut mame, b"ATH0\r", b"\r\n0\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn answers_a_burst_of_commands_in_order() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    at(&mut mame, b"ATZ\rAT&F\rATI3\r", b"\r\n0\r\n\r\n0\r\n\r\n0\r\n").await;
+    at(&mut mame, b"ATE0\rATDT123\rATD\r", b"OK\r\n0\r\n79\r\n67\r\n19\r\n").await;
+    at(&mut mame, b"ppp", b"ppp").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn commands_after_going_online_are_dropped() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT123\rATD\rATZ\rAT", b"0\r\n79\r\n67\r\n19\r\n").await;
+
+    // Only what comes after CONNECT goes to PPP.
+    at(&mut mame, b"ppp", b"ppp").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn a_bare_d_goes_online_however_its_sent() {
+    for data_mode in [&b"ATD\r"[..], b"ATD \r", b"ATDT\r", b"ATD;\r"] {
+        let (mut mame, session) = answer(&Stats::new());
+
+        at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+        at(&mut mame, b"ATDT123;\r", b"0\r\n").await;
+        at(&mut mame, data_mode, b"79\r\n67\r\n19\r\n").await;
+        at(&mut mame, b"ppp", b"ppp").await;
+
+        hang_up(mame, session).await;
+    }
+}
+
+#[tokio::test]
+async fn waits_for_the_whole_line() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    at(&mut mame, b"ATE0", b"").await;
+    at(&mut mame, b"\r", b"OK\r\n").await;
+    at(&mut mame, b"AT", b"").await;
+    at(&mut mame, b"DT555", b"").await;
+    at(&mut mame, b"\r", b"0\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn shrugs_off_line_noise() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    // Control characters are dropped, but the \r still ends the (empty) line.
+    at(&mut mame, b"\x01\x02\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    // So is anything outside of ASCII.
+    at(&mut mame, b"\xffAT\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    // The \n after a \r isn't the start of the next line.
+    at(&mut mame, b"ATE0\r\nATS7=60\r", b"OK\r\n\r\n0\r\n").await;
+    at(&mut mame, b"ATS7=60\r", b"\r\n0\r\n").await;
+    // Something that isn't a dial string gets no answer at all.
+    at(&mut mame, b"ATDX\r", b"").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn killing_an_online_session_sends_no_carrier() {
+    let stats = Stats::new();
+    let (mut mame, session) = answer(&stats);
+
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    assert!(stats.send(1, SessionCommand::Kill).await);
+
+    let mut no_carrier = Vec::new();
+    tokio::time::timeout(WAIT, mame.read_to_end(&mut no_carrier)).await.expect("still connected").unwrap();
+    assert_eq!(no_carrier, b"3\r\n");
+
+    tokio::time::timeout(WAIT, session).await.expect("the session didn't end").unwrap();
+}
+
+#[tokio::test]
+async fn killing_a_call_to_the_null_backend_sends_no_carrier() {
+    let stats = Stats::new();
+    let (mut mame, session) = answer_with(&stats, BackendKind::Null);
+
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    // It takes whatever it's sent without a word.
+    at(&mut mame, b"~ppp~", b"").await;
+
+    assert!(stats.send(1, SessionCommand::Kill).await);
+
+    let mut no_carrier = Vec::new();
+    tokio::time::timeout(WAIT, mame.read_to_end(&mut no_carrier)).await.expect("still connected").unwrap();
+    assert_eq!(no_carrier, b"3\r\n");
+
+    tokio::time::timeout(WAIT, session).await.expect("the session didn't end").unwrap();
+}
+
+#[tokio::test]
+async fn server_takes_calls_on_the_port_it_got() {
+    let config = Config::for_backend(Backend {
+        name: "echo".to_string(),
+        kind: BackendKind::Echo,
+    });
+
+    let server = Server::bind(config).await.unwrap();
+    let address = server.local_addr().unwrap();
+
+    let call = async {
+        let mut mame = tokio::net::TcpStream::connect(address).await.unwrap();
+        mame.write_all(b"ATE0\r").await.unwrap();
+
+        let mut ok = [0; 4];
+        mame.read_exact(&mut ok).await.unwrap();
+
+        ok
+    };
+
+    tokio::select! {
+        ran = server.run() => panic!("the server stopped: {ran:?}"),
+        ok = tokio::time::timeout(WAIT, call) => assert_eq!(&ok.expect("no answer"), b"OK\r\n"),
+    }
+}
+
+// Stands in for PPP, going wrong in whichever way a test asks for.
+enum MockBackend {
+    Refuses,
+    // Counting how many times it's been asked.
+    RefusesCounting(Arc<AtomicUsize>),
+    HangsUpRightAway(Arc<AtomicBool>),
+    BrokenPipe,
+    // The test's the other end, for as long as the call lasts.
+    Pipe(std::sync::Mutex<Option<DuplexStream>>),
+}
+
+impl PppBackend for MockBackend {
+    fn establish<'a>(&'a self, context: &'a DialContext<'a>) -> BoxFuture<'a, Result<BackendStream, TouchPppError>> {
+        async move {
+            assert_eq!(context.number, "18006138199");
+
+            match self {
+                MockBackend::Refuses => Err(TouchPppError::BackendConnect {
+                    endpoint: "mock".to_string(),
+                    source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+                }),
+                MockBackend::RefusesCounting(dials) => {
+                    dials.fetch_add(1, Ordering::SeqCst);
+
+                    Err(TouchPppError::BackendConnect {
+                        endpoint: "mock".to_string(),
+                        source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+                    })
+                },
+                MockBackend::HangsUpRightAway(cleaned_up) => {
+                    let cleaned_up = cleaned_up.clone();
+
+                    Ok(BackendStream::new(tokio::io::empty(), tokio::io::sink()).with_cleanup(async move {
+                        cleaned_up.store(true, Ordering::SeqCst);
+                    }))
+                },
+                MockBackend::Pipe(pipe) => {
+                    let (reader, writer) = tokio::io::split(pipe.lock().unwrap().take().expect("only one call"));
+
+                    Ok(BackendStream::new(reader, writer))
+                },
+                MockBackend::BrokenPipe => {
+                    // Nothing ever comes from PPP, and the way to it is already closed.
+                    let (reader, quiet) = tokio::io::duplex(16);
+                    let (writer, _) = tokio::io::duplex(16);
+
+                    Ok(BackendStream::new(reader, writer).with_cleanup(async move {
+                        drop(quiet);
+                    }))
+                },
+            }
+        }.boxed()
+    }
+}
+
+// Up to the point of asking for data mode.
+async fn dial_out(mame: &mut DuplexStream) {
+    at(mame, b"ATE0\r", b"OK\r\n").await;
+    at(mame, b"ATDT18006138199\r", b"0\r\n").await;
+}
+
+#[tokio::test]
+async fn a_backend_that_refuses_is_busy() {
+    let stats = Stats::new();
+    let (mut mame, session) = answer_with(&stats, BackendKind::Custom(Box::new(MockBackend::Refuses)));
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"7\r\n").await;
+
+    // Straight back to commands.
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.dials.failed, 1);
+    assert!(snapshot.recent_dials[0].0.outcome.starts_with("can't touch PPP @ mock: "));
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn atdl_dials_the_last_number_again() {
+    let stats = Stats::new();
+    let config = Config::builder()
+        .remote_backend("wni", ["127.0.0.1:1"])
+        .phone_book("1800*", "wni")
+        .build()
+        .unwrap();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"7\r\n").await;
+    at(&mut mame, b"ATDL\r", b"7\r\n").await;
+
+    let dials = stats.snapshot().recent_dials;
+    assert_eq!(dials.len(), 2);
+    for (dial, _) in &dials {
+        assert_eq!((dial.number.as_str(), dial.backend.as_str()), ("18006138199", "wni"));
+    }
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn remote_udp_sends_a_frame_per_datagram() {
+    let peer = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let config = Config::builder().remote_udp(peer.local_addr().unwrap().to_string()).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    // Two frames in one write go as two datagrams, and come back as they went.
+    mame.write_all(b"~\xff\x03one~~\xff\x03two~").await.unwrap();
+
+    let mut datagram = [0; 64];
+    for frame in [&b"~\xff\x03one~"[..], b"~\xff\x03two~"] {
+        let (read, from) = tokio::time::timeout(WAIT, peer.recv_from(&mut datagram)).await.expect("no datagram").unwrap();
+        assert_eq!(&datagram[..read], frame);
+
+        peer.send_to(frame, from).await.unwrap();
+    }
+
+    let mut echoed = [0; 14];
+    tokio::time::timeout(WAIT, mame.read_exact(&mut echoed)).await.expect("no echo").unwrap();
+    assert_eq!(&echoed, b"~\xff\x03one~~\xff\x03two~");
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn remote_udp_with_nobody_there_drops_the_call() {
+    let nobody = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let config = Config::builder().remote_udp(nobody.to_string()).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    // The port's unreachable, which ends the call like a dropped line.
+    mame.write_all(b"~\xff\x03one~").await.unwrap();
+    at(&mut mame, b"", b"3\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn resolve_overrides_reach_servers_dns_has_never_heard_of() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = Config::builder()
+        .remote_backend("lan", [format!("wtv-ppp.invalid:{port}")])
+        .phone_book("1800*", "lan")
+        .resolve("wtv-ppp.invalid=127.0.0.1")
+        .build()
+        .unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    tokio::time::timeout(WAIT, listener.accept()).await.expect("never touched").unwrap();
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn a_number_that_keeps_failing_is_delayed() {
+    let dials = Arc::new(AtomicUsize::new(0));
+    let backend = Backend {
+        name: "test".to_string(),
+        kind: BackendKind::Custom(Box::new(MockBackend::RefusesCounting(dials.clone()))),
+    };
+    let config = Config::builder().backend(backend).delay_after(3).build().unwrap();
+
+    let stats = Stats::new();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    for _ in 0..3 {
+        dial_out(&mut mame).await;
+        at(&mut mame, b"ATD\r", b"7\r\n").await;
+    }
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"24\r\n").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+
+    assert_eq!(dials.load(Ordering::SeqCst), 3, "the backend was dialed while DELAYED");
+    assert_eq!(stats.snapshot().recent_dials[3].0.outcome, "DELAYED");
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn a_blacklisted_number_is_never_dialed() {
+    let dials = Arc::new(AtomicUsize::new(0));
+    let backend = Backend {
+        name: "test".to_string(),
+        kind: BackendKind::Custom(Box::new(MockBackend::RefusesCounting(dials.clone()))),
+    };
+    let config = Config::builder().backend(backend).blacklist("1-800-*").build().unwrap();
+
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"32\r\n").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+
+    assert_eq!(dials.load(Ordering::SeqCst), 0);
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn a_reset_between_calls_brings_back_what_the_last_one_turned_off() {
+    let drop_after = CarrierDrop { after: Duration::from_millis(100), jitter: Duration::ZERO };
+    let config = Config::builder().builtin(Builtin::Null).protocol_line(Protocol::Lapm).drop_after(drop_after).drop_count(1).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0%C0\\N0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n70\r\n69\r\n19\r\n").await;
+
+    let mut no_carrier = [0; 3];
+    tokio::time::timeout(WAIT, mame.read_exact(&mut no_carrier)).await.expect("the carrier never dropped").unwrap();
+
+    // Same connection, and the box starts the next call with a reset.
+    at(&mut mame, b"ATZ\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT5551212\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n77\r\n67\r\n19\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn each_dial_starts_over_without_the_last_numbers_carrier() {
+    let drop_after = CarrierDrop { after: Duration::from_millis(100), jitter: Duration::ZERO };
+    let config = Config::builder()
+        .builtin(Builtin::Null)
+        .carrier_speed(CarrierSpeed::Fixed(56000))
+        .dial_overrides("1800*", DialOverrides { force_56k: Some(false), ..Default::default() })
+        .drop_after(drop_after)
+        .drop_count(1)
+        .build()
+        .unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    let mut no_carrier = [0; 3];
+    tokio::time::timeout(WAIT, mame.read_exact(&mut no_carrier)).await.expect("the carrier never dropped").unwrap();
+
+    // Same connection and no ATZ, just the init string again.
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT5551212\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"162\r\n67\r\n19\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn dropping_the_carrier_sends_no_carrier_and_lets_the_box_redial() {
+    let drop_after = CarrierDrop { after: Duration::from_secs(1), jitter: Duration::ZERO };
+    let config = Config::builder().builtin(Builtin::Null).drop_after(drop_after).drop_count(1).build().unwrap();
+
+    let (events, mut event_receiver) = tokio::sync::mpsc::channel(16);
+    let stats = Stats::with_events(Some(events));
+    let (mut mame, session) = answer_config(&stats, config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    let online = std::time::Instant::now();
+    let mut no_carrier = [0; 3];
+    tokio::time::timeout(WAIT, mame.read_exact(&mut no_carrier)).await.expect("the carrier never dropped").unwrap();
+    assert_eq!(&no_carrier, b"3\r\n");
+    assert!(online.elapsed() >= Duration::from_millis(950), "dropped after {:?}", online.elapsed());
+
+    // Back to commands and free to dial again, and --drop-count 1 leaves this call alone.
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    let mut quiet = [0; 1];
+    assert!(tokio::time::timeout(Duration::from_millis(1500), mame.read(&mut quiet)).await.is_err(), "dropped twice");
+
+    hang_up(mame, session).await;
+
+    let mut reasons = Vec::new();
+    while let Ok(event) = event_receiver.try_recv() {
+        reasons.extend(event.summary.map(|summary| summary.reason));
+    }
+    assert_eq!(reasons, ["MAME hung up"]);
+}
+
+#[tokio::test]
+async fn each_way_is_held_to_its_own_throttle() {
+    // A second's worth at 9600, held back one way and not the other.
+    let data = vec![b'~'; 1200];
+
+    for (down, up) in [(None, Some(9600)), (Some(9600), None)] {
+        let stats = Stats::new();
+        let mut builder = Config::builder().builtin(Builtin::Echo).carrier_speed(CarrierSpeed::Fixed(9600));
+        if let Some(down) = down {
+            builder = builder.throttle_down(down);
+        }
+        if let Some(up) = up {
+            builder = builder.throttle_up(up);
+        }
+        let (mut mame, session) = answer_config(&stats, builder.build().unwrap());
+
+        dial_out(&mut mame).await;
+        at(&mut mame, b"ATD\r", b"50\r\n67\r\n19\r\n").await;
+
+        let started = Instant::now();
+        mame.write_all(&data).await.unwrap();
+
+        // Up only waits on its own throttle.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let bytes_up = stats.snapshot().sessions[0].bytes_up;
+        match up {
+            Some(_) => assert!(bytes_up < 800, "{bytes_up} bytes went up already"),
+            None => assert_eq!(bytes_up, 1200),
+        }
+
+        // And either way, the echo can't come back any quicker than the slower of the two.
+        let mut echoed = vec![0; data.len()];
+        tokio::time::timeout(WAIT, mame.read_exact(&mut echoed)).await.expect("no echo").unwrap();
+        assert_eq!(echoed, data);
+        assert!(started.elapsed() >= Duration::from_millis(900), "echoed in {:?}", started.elapsed());
+
+        hang_up(mame, session).await;
+    }
+}
+
+#[tokio::test]
+async fn a_paused_call_picks_up_where_it_stopped() {
+    // A call that would be over before the echo's back, if the clock kept running while it was paused.
+    let drop_after = CarrierDrop { after: Duration::from_secs(3), jitter: Duration::ZERO };
+    let config = Config::builder()
+        .builtin(Builtin::Echo)
+        .carrier_speed(CarrierSpeed::Fixed(9600))
+        .throttle(9600)
+        .drop_after(drop_after)
+        .build()
+        .unwrap();
+    let stats = Stats::new();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"50\r\n67\r\n19\r\n").await;
+    let connected = Instant::now();
+
+    // Two seconds' worth at 9600.
+    let data: Vec<u8> = (0..2400).map(|i| (i % 251) as u8).collect();
+    mame.write_all(&data).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(admin::reply("pause 1", &stats).await.unwrap(), "OK\n");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(admin::reply("list", &stats).await.unwrap().starts_with("1 client=duplex state=paused "));
+
+    // Nothing goes either way while it's paused.
+    let counted = |stats: &Stats| {
+        let snapshot = stats.snapshot();
+        (snapshot.sessions[0].bytes_up, snapshot.sessions[0].bytes_down)
+    };
+    let paused_at = counted(&stats);
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    assert_eq!(counted(&stats), paused_at);
+    assert!(paused_at.0 < data.len() as u64, "it all went up before the pause");
+
+    assert_eq!(admin::reply("resume 1", &stats).await.unwrap(), "OK\n");
+
+    let mut echoed = vec![0; data.len()];
+    tokio::time::timeout(WAIT, mame.read_exact(&mut echoed)).await.expect("no echo").unwrap();
+    assert_eq!(echoed, data);
+
+    // --drop-after only counts the time the call wasn't paused.
+    let mut no_carrier = [0; 3];
+    tokio::time::timeout(WAIT, mame.read_exact(&mut no_carrier)).await.expect("no NO CARRIER").unwrap();
+    assert_eq!(&no_carrier, b"3\r\n");
+    assert!(connected.elapsed() >= Duration::from_millis(3700), "dropped after {:?}", connected.elapsed());
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn reports_throughput_ahead_of_no_carrier() {
+    let drop_after = CarrierDrop { after: Duration::from_secs(2), jitter: Duration::ZERO };
+    let config = Config::builder()
+        .builtin(Builtin::Echo)
+        .carrier_speed(CarrierSpeed::Fixed(9600))
+        .throttle_to_carrier(true)
+        .drop_after(drop_after)
+        .report_throughput(true)
+        .build()
+        .unwrap();
+
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"50\r\n67\r\n19\r\n").await;
+
+    // A second's worth at 9600, over a call that lasts two.
+    let data = vec![b'~'; 1200];
+    at(&mut mame, &data, &data).await;
+
+    let mut report = Vec::new();
+    while !report.ends_with(b"3\r\n") {
+        let mut byte = [0; 1];
+        tokio::time::timeout(WAIT, mame.read_exact(&mut byte)).await.expect("no NO CARRIER").unwrap();
+        report.push(byte[0]);
+    }
+    let report = String::from_utf8(report).unwrap();
+
+    let rates: Vec<u64> = report
+        .strip_prefix("\r\nTHROUGHPUT ")
+        .and_then(|rest| rest.strip_suffix(" BPS\r\n3\r\n"))
+        .unwrap_or_else(|| panic!("not a throughput line: {report:?}"))
+        .split([' ', '/', ','])
+        .filter_map(|part| part.parse().ok())
+        .collect();
+    let [average_up, average_down, peak_up, peak_down] = rates[..] else {
+        panic!("not a throughput line: {report:?}");
+    };
+
+    for average in [average_up, average_down] {
+        assert!((4000..=5600).contains(&average), "averaged {average} bps in {report:?}");
+    }
+    for (peak, average) in [(peak_up, average_up), (peak_down, average_down)] {
+        assert!(peak >= average && peak <= 12000, "peaked at {peak} bps in {report:?}");
+    }
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn hanging_up_after_a_carrier_drop_blames_the_drop() {
+    let drop_after = CarrierDrop { after: Duration::ZERO, jitter: Duration::ZERO };
+    let config = Config::builder().builtin(Builtin::Null).drop_after(drop_after).build().unwrap();
+
+    let (events, mut event_receiver) = tokio::sync::mpsc::channel(16);
+    let stats = Stats::with_events(Some(events));
+    let (mut mame, session) = answer_config(&stats, config);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n3\r\n").await;
+
+    hang_up(mame, session).await;
+
+    let mut reasons = Vec::new();
+    while let Ok(event) = event_receiver.try_recv() {
+        reasons.extend(event.summary.map(|summary| summary.reason));
+    }
+    assert_eq!(reasons, ["simulated carrier drop"]);
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+// Writes `frame` to one end and waits for all of it at the other, so each step's done before the next.
+async fn pass(from: &mut DuplexStream, to: &mut DuplexStream, frame: &[u8]) {
+    from.write_all(frame).await.unwrap();
+
+    let mut got = vec![0; frame.len()];
+    tokio::time::timeout(WAIT, to.read_exact(&mut got)).await.expect("it never arrived").unwrap();
+    assert_eq!(got, frame);
+}
+
+#[tokio::test]
+async fn reports_the_addresses_pppd_settled_on() {
+    let (mut pppd, backend) = tokio::io::duplex(0x1000);
+    let kind = BackendKind::Custom(Box::new(MockBackend::Pipe(std::sync::Mutex::new(Some(backend)))));
+
+    let (events, mut event_receiver) = tokio::sync::mpsc::channel(16);
+    let stats = Stats::with_events(Some(events));
+    let (mut mame, session) = answer_with(&stats, kind);
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    // IPCP off the wire: pppd asks for 192.168.1.1 and the box asks for 0.0.0.0 and name servers, pppd Naks
+    // with 192.168.1.100, 192.168.1.1 and 8.8.8.8, the box Acks pppd and asks again for what it was offered, and
+    // pppd Acks that.
+    pass(&mut pppd, &mut mame, &unhex("7eff7d2380217d217d217d207d307d227d267d202d7d2f7d217d237d26c0a87d217d21b0637e")).await;
+    pass(&mut mame, &mut pppd, &unhex("7eff7d2380217d217d217d207d367d237d267d207d207d207d20817d267d207d207d207d20837d267d207d207d207d206edb7e")).await;
+    pass(&mut pppd, &mut mame, &unhex("7eff7d2380217d237d217d207d367d237d26c0a87d2164817d26c0a87d217d21837d267d287d287d287d28f26e7e")).await;
+    pass(&mut mame, &mut pppd, &unhex("7eff7d2380217d227d217d207d307d227d267d202d7d2f7d217d237d26c0a87d217d2191f97e")).await;
+    pass(&mut mame, &mut pppd, &unhex("7eff7d2380217d217d227d207d367d237d26c0a87d2164817d26c0a87d217d21837d267d287d287d287d28ed6d7e")).await;
+    pass(&mut pppd, &mut mame, &unhex("7eff7d2380217d227d227d207d367d237d26c0a87d2164817d26c0a87d217d21837d267d287d287d287d287d3b9e7e")).await;
+
+    hang_up(mame, session).await;
+
+    let mut ip_up = None;
+    let mut summary = None;
+    while let Ok(event) = event_receiver.try_recv() {
+        match event.event {
+            "ip_up" => ip_up = Some(serde_json::to_value(&event).unwrap()),
+            "disconnect" => summary = event.summary,
+            _ => {},
+        }
+    }
+
+    // The way the webhook gets it.
+    let ip_up = ip_up.expect("no ip_up event");
+    assert_eq!(ip_up["client_ip"], "192.168.1.100");
+    assert_eq!(ip_up["server_ip"], "192.168.1.1");
+    assert_eq!(ip_up["dns"], serde_json::json!(["192.168.1.1", "8.8.8.8"]));
+
+    let summary = summary.expect("no disconnect event");
+    assert!(summary.to_string().contains(" as 192.168.1.100 (server 192.168.1.1, DNS 192.168.1.1 and 8.8.8.8)"), "{summary}");
+}
+
+#[tokio::test]
+async fn a_backend_hanging_up_goes_back_to_commands() {
+    let cleaned_up = Arc::new(AtomicBool::new(false));
+    let (mut mame, session) = answer_with(&Stats::new(), BackendKind::Custom(Box::new(MockBackend::HangsUpRightAway(cleaned_up.clone()))));
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+
+    assert!(cleaned_up.load(Ordering::SeqCst));
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn a_backend_that_cant_be_written_to_ends_the_session() {
+    let (mut mame, session) = answer_with(&Stats::new(), BackendKind::Custom(Box::new(MockBackend::BrokenPipe)));
+
+    dial_out(&mut mame).await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    mame.write_all(b"~ppp~").await.unwrap();
+
+    let mut no_carrier = Vec::new();
+    tokio::time::timeout(WAIT, mame.read_to_end(&mut no_carrier)).await.expect("still connected").unwrap();
+    assert_eq!(no_carrier, b"3\r\n");
+
+    tokio::time::timeout(WAIT, session).await.expect("the session didn't end").unwrap();
+}
+
+#[tokio::test]
+async fn generic_profile_takes_a_windows_dial_up_script() {
+    let stats = Stats::new();
+    let config = Config::builder().builtin(Builtin::Echo).profile(Profile::Generic).build().unwrap();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    // Echo's on until the init string turns it off, and results are words.
+    at(&mut mame, b"AT\r", b"AT\r\r\nOK\r\n").await;
+    at(&mut mame, b"ATZ\r", b"ATZ\r\r\nOK\r\n").await;
+    at(&mut mame, b"AT &F E0 V1 &D2 &C1 W2 S95=47 S0=0\r", b"AT &F E0 V1 &D2 &C1 W2 S95=47 S0=0\r\r\nOK\r\n").await;
+    at(&mut mame, b"ATI3\r", b"\r\nTouchPPP V.34 Data Modem\r\n\r\nOK\r\n").await;
+    at(&mut mame, b"ATS7=60S30=0L0M1\\N3%C1&K3B0N1X4\r", b"\r\nOK\r\n").await;
+
+    // Clearly not a modem command.
+    at(&mut mame, b"ATG1\r", b"\r\nERROR\r\n").await;
+    at(&mut mame, b"HELLO\r", b"\r\nERROR\r\n").await;
+    at(&mut mame, b"ATI9\r", b"\r\nERROR\r\n").await;
+
+    // V0 for a moment, then back.
+    at(&mut mame, b"ATV0\r", b"0\r").await;
+    at(&mut mame, b"ATV1\r", b"\r\nOK\r\n").await;
+
+    // The number dials straight away, with just the one CONNECT line.
+    at(&mut mame, b"ATDT5551212\r", b"\r\nCONNECT 115200\r\n").await;
+    at(&mut mame, b"~\xff\x7d\x23ppp~", b"~\xff\x7d\x23ppp~").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn a_courier_says_arq_on_the_end_of_connect() {
+    let stats = Stats::new();
+    let config = Config::builder().builtin(Builtin::Echo).profile(Profile::Generic).modem_profile("usr-courier").build().unwrap();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    at(&mut mame, b"ATE0\r", b"ATE0\r\r\nOK\r\n").await;
+    at(&mut mame, b"ATDT5551212\r", b"\r\nCONNECT 115200/ARQ\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn slip_goes_through_the_echo_byte_for_byte() {
+    let stats = Stats::new();
+    let config = Config::builder().builtin(Builtin::Echo).link_protocol(LinkProtocol::Slip).build().unwrap();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    // The modem's the same as ever.
+    at(&mut mame, b"ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n").await;
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    // Packets made of nothing but what needs escaping, and escapes that only look like escapes.
+    for packet in [vec![END], vec![ESC], vec![ESC, END, ESC_END, ESC_ESC], vec![ESC_END, ESC_ESC, END, END], (0..=255).collect()] {
+        let framed = slip::encode(&packet);
+        at(&mut mame, &framed, &framed).await;
+    }
+
+    // Line noise and an empty packet ahead of it don't make it back.
+    at(&mut mame, &[b'~', ESC, END, END, b'i', b'p', END], &slip::encode(b"ip")).await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn webtv_profile_ignores_what_generic_follows() {
+    let stats = Stats::new();
+    let (mut mame, session) = answer(&stats);
+
+    // No echo, V1 or ERROR, and ATDT only keeps the number.
+    at(&mut mame, b"ATE1V1\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATG1\r", b"\r\n0\r\n").await;
+    at(&mut mame, b"ATDT5551212\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn auto_data_goes_online_when_ppp_shows_up_without_a_dial() {
+    let stats = Stats::new();
+    let config = Config::builder().builtin(Builtin::Echo).auto_data(AutoData::Connect).build().unwrap();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    // A cold LCP Configure-Request, with the CONNECT it never waited for ahead of it coming back.
+    let configure_request = b"~\xff\x7d\x23\xc0\x21\x7d\x21\x7d\x21\x7d\x20\x7d\x24\x7d\x20\x7d\x20~";
+    let mut expect = b"79\r\n67\r\n19\r\n".to_vec();
+    expect.extend_from_slice(configure_request);
+    at(&mut mame, configure_request, &expect).await;
+
+    // Online from then on.
+    at(&mut mame, b"~\xff\x7d\x23ppp~", b"~\xff\x7d\x23ppp~").await;
+
+    hang_up(mame, session).await;
+
+    assert_eq!(stats.snapshot().dials.connected, 1);
+}
+
+#[tokio::test]
+async fn quiet_auto_data_sends_no_connect() {
+    let config = Config::builder().builtin(Builtin::Echo).auto_data(AutoData::Quiet).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    // Commands still get answered until PPP turns up.
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+    at(&mut mame, b"~\xff\x03\xc0\x21ppp~", b"~\xff\x03\xc0\x21ppp~").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn ppp_without_a_dial_is_just_noise_without_auto_data() {
+    let (mut mame, session) = answer(&Stats::new());
+
+    at(&mut mame, b"~\xff\x7d\x23\xc0\x21ppp~", b"").await;
+    at(&mut mame, b"ATE0\r", b"OK\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+#[tokio::test]
+async fn an_exclusive_backend_is_busy_while_its_call_is_up() {
+    // A PPP server that echoes every call it gets.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = socket.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    let stats = Stats::new();
+    let config = Arc::new(Config::builder().connect(address).remote_exclusive(true).build().unwrap());
+    let call = |id| {
+        let (_config_sender, config_receiver) = watch::channel(config.clone());
+        let (mame, modem) = tokio::io::duplex(0x1000);
+
+        (mame, tokio::spawn(Session::new(&stats, id, "duplex", config_receiver).run(modem)))
+    };
+
+    let (mut first, first_session) = call(1);
+    at(&mut first, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut first, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    at(&mut first, b"~ppp~", b"~ppp~").await;
+
+    // The server never hears about the second box's dial.
+    let (mut second, second_session) = call(2);
+    at(&mut second, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut second, b"ATD\r", b"7\r\n").await;
+
+    let dials = stats.snapshot().dials;
+    assert_eq!((dials.busy, dials.in_use), (1, 1));
+
+    // Once the first one's gone, it's the second's turn.
+    hang_up(first, first_session).await;
+
+    at(&mut second, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut second, b"ATD\r", b"79\r\n67\r\n19\r\n").await;
+    at(&mut second, b"~ppp~", b"~ppp~").await;
+
+    hang_up(second, second_session).await;
+}
+
+#[cfg(all(unix, feature = "ssh"))]
+#[tokio::test]
+async fn ssh_failures_are_dial_failures() {
+    // Something that answers but isn't an SSH server.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let _ = socket.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+        }
+    });
+
+    let config = Config::builder().connect("127.0.0.1:2323").remote_ssh(format!("ppp@{address}")).ssh_insecure(true).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"3\r\n").await;
+
+    hang_up(mame, session).await;
+
+    // Nothing there at all is the same as a PPP server that isn't.
+    let port = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    let config = Config::builder().connect("127.0.0.1:2323").remote_ssh(format!("ppp@127.0.0.1:{port}")).ssh_insecure(true).build().unwrap();
+    let (mut mame, session) = answer_config(&Stats::new(), config);
+
+    at(&mut mame, b"ATDT18006138199\r", b"0\r\n").await;
+    at(&mut mame, b"ATD\r", b"7\r\n").await;
+
+    hang_up(mame, session).await;
+}
+
+// A modem server like tcpser: answers the lines it's sent from `script`, then echoes the call once it's said
+// CONNECT. Gives back the lines it got.
+async fn fake_modem(script: &'static [(&'static str, &'static str)]) -> (String, JoinHandle<Vec<String>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let modem = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        let mut buf = [0; 1024];
+
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            if n == 0 {
+                return lines;
+            }
+
+            if lines.last().is_some_and(|line: &String| line.starts_with("ATD")) {
+                socket.write_all(&buf[..n]).await.unwrap();
+                continue;
+            }
+
+            for &byte in &buf[..n] {
+                line.push(byte);
+                if byte != b'\r' {
+                    continue;
+                }
+
+                let sent = String::from_utf8(std::mem::take(&mut line)).unwrap();
+                let (_, reply) = script.iter().find(|(expected, _)| *expected == sent).expect("a line the script has");
+                socket.write_all(reply.as_bytes()).await.unwrap();
+                lines.push(sent);
+            }
+        }
+    });
+
+    (address, modem)
+}
+
+#[tokio::test]
+async fn at_passthrough_lets_the_backend_answer() {
+    let (address, modem) = fake_modem(&[
+        ("ATE0Q0V0\r", "0\r"),
+        ("ATI3\r", "\r\n1\r\n0\r"),
+        ("ATDTwni.example:1515\r", "1\r~}#"),
+    ]).await;
+
+    let stats = Stats::new();
+    let config = Config::builder()
+        .connect(address)
+        .remote_backend("wni", ["wni.example:1515"])
+        .phone_book("1800*", "wni")
+        .at_passthrough(true)
+        .build()
+        .unwrap();
+    let (mut mame, session) = answer_config(&stats, config);
+
+    // Whatever the modem says goes back as it is, and a 1 that isn't after a dial isn't CONNECT.
+    at(&mut mame, b"ATE0Q0V0\r", b"0\r").await;
+    at(&mut mame, b"ATI3\r", b"\r\n1\r\n0\r").await;
+
+    // The phone book points the dial at its backend, and what came after CONNECT is the start of the call.
+    at(&mut mame, b"ATDT18006138199\r", b"1\r~}#").await;
+    assert_eq!(stats.snapshot().sessions[0].state.to_string(), "online");
+
+    at(&mut mame, b"~\xff\x7d\x23ppp~", b"~\xff\x7d\x23ppp~").await;
+
+    hang_up(mame, session).await;
+
+    assert_eq!(modem.await.unwrap(), ["ATE0Q0V0\r", "ATI3\r", "ATDTwni.example:1515\r"]);
+    assert_eq!(stats.snapshot().dials.connected, 1);
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::net::TcpListener;
+
+use common::*;
+
+#[test]
+fn a_sticky_backend_goes_back_to_the_server_that_answered_last_run() {
+    let primary = free_port();
+    let backup = echo_server();
+    let state_file = scratch_path("state.toml");
+    let _ = std::fs::remove_file(&state_file);
+
+    let servers = ["-c", &format!("127.0.0.1:{primary}"), "-c", &format!("127.0.0.1:{backup}")];
+    let options = ["--remote-sticky", "--state-file", state_file.to_str().unwrap()];
+
+    // The primary's down, so the backup answers.
+    let port = free_port();
+    let touchppp = spawn_touchppp(&[&["-l", &port.to_string()][..], &servers, &options].concat());
+    let mut mame = connect(port);
+    dial(&mut mame, "5551212");
+    drop(mame);
+    drop(touchppp);
+
+    let state = std::fs::read_to_string(&state_file).unwrap();
+    assert!(state.contains(&format!("endpoint = \"127.0.0.1:{backup}\"")), "{state}");
+
+    // Now it's back, but this number went to the backup last time.
+    let primary = TcpListener::bind(("127.0.0.1", primary)).unwrap();
+    primary.set_nonblocking(true).unwrap();
+
+    let port = free_port();
+    let touchppp = spawn_touchppp(&[&["-l", &port.to_string()][..], &servers, &options].concat());
+    let mut mame = connect(port);
+    dial(&mut mame, "5551212");
+
+    assert!(primary.accept().is_err(), "the primary got the call");
+
+    drop(mame);
+    drop(touchppp);
+    let _ = std::fs::remove_file(&state_file);
+}
@@ -0,0 +1,120 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use common::*;
+
+#[test]
+fn user1_logs_a_snapshot_with_live_byte_counts() {
+    let port = free_port();
+    let log_file = scratch_path("stats.log");
+
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--log-file", log_file.to_str().unwrap()]);
+
+    let mut mame = connect(port);
+
+    dial(&mut mame, "5551212");
+
+    let data = [0x7e; 100];
+    mame.write_all(&data).unwrap();
+
+    let mut echoed = [0; 100];
+    mame.read_exact(&mut echoed).unwrap();
+    assert_eq!(echoed, data);
+
+    // The count is bumped just after the write that got the bytes to us.
+    sleep(Duration::from_millis(200));
+
+    kill(Pid::from_raw(touchppp.0.id() as i32), Signal::SIGUSR1).unwrap();
+
+    let started = Instant::now();
+    let mut log = String::new();
+    while !log.contains("Active:") && started.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(50));
+        log = std::fs::read_to_string(&log_file).unwrap_or_default();
+    }
+
+    drop(mame);
+    drop(touchppp);
+    let _ = std::fs::remove_file(&log_file);
+
+    assert!(log.contains("1 sessions so far, 1 active, 100 bytes up and 100 bytes down in total."), "{log}");
+    assert!(log.contains("Active: session 1 from 127.0.0.1:"), "{log}");
+    assert!(log.contains("dialed 5551212 on backend command line, 100 bytes up, 100 bytes down"), "{log}");
+    assert!(log.contains("Dial: session 1 dialed 5551212 on backend command line, CONNECT"), "{log}");
+}
+
+#[test]
+fn hanging_up_logs_a_summary() {
+    let port = free_port();
+    let log_file = scratch_path("summary.log");
+
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--log-file", log_file.to_str().unwrap()]);
+
+    let mut mame = connect(port);
+
+    dial(&mut mame, "5551212");
+
+    let data = [0x7e; 3000];
+    mame.write_all(&data).unwrap();
+
+    let mut echoed = [0; 3000];
+    mame.read_exact(&mut echoed).unwrap();
+
+    drop(mame);
+
+    let started = Instant::now();
+    let mut log = String::new();
+    while !log.contains("Ended because") && started.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(50));
+        log = std::fs::read_to_string(&log_file).unwrap_or_default();
+    }
+
+    drop(touchppp);
+    let _ = std::fs::remove_file(&log_file);
+
+    assert!(log.contains("Session 1 from 127.0.0.1:"), "{log}");
+    assert!(log.contains(" dialed 5551212 on backend command line at 115200 (carrier 33600), lasted "), "{log}");
+    assert!(log.contains(": 3000 bytes up (average "), "{log}");
+    assert!(log.contains("), 3000 bytes down (average "), "{log}");
+    assert!(log.contains("). Ended because: MAME hung up."), "{log}");
+}
+
+#[test]
+fn each_call_logs_one_summary_with_its_byte_counts() {
+    let port = free_port();
+    let log_file = scratch_path("ppp_done.log");
+
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--log-file", log_file.to_str().unwrap()]);
+
+    let mut mame = connect(port);
+
+    dial(&mut mame, "5551212");
+
+    let data = [0x7e; 2500];
+    mame.write_all(&data).unwrap();
+
+    let mut echoed = [0; 2500];
+    mame.read_exact(&mut echoed).unwrap();
+
+    drop(mame);
+
+    let started = Instant::now();
+    let mut log = String::new();
+    while !log.contains("Ended because") && started.elapsed() < Duration::from_secs(5) {
+        sleep(Duration::from_millis(50));
+        log = std::fs::read_to_string(&log_file).unwrap_or_default();
+    }
+
+    drop(touchppp);
+    let _ = std::fs::remove_file(&log_file);
+
+    assert_eq!(log.matches("Taking my hands off PPP.").count(), 1, "{log}");
+    assert!(log.contains("Taking my hands off PPP. 2500 bytes copied from MAME to PPP; 2500 bytes copied from PPP to MAME; average "), "{log}");
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::*;
+
+fn get(port: u16, path: &str) -> String {
+    let mut browser = connect(port);
+    write!(browser, "GET {path} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n").unwrap();
+
+    let mut response = String::new();
+    browser.read_to_string(&mut response).unwrap();
+
+    response
+}
+
+#[test]
+fn status_page_shows_a_session_while_its_online() {
+    let port = free_port();
+    let status_port = free_port();
+
+    let _touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--status-http", &status_port.to_string()]);
+
+    let mut mame: TcpStream = connect(port);
+    dial(&mut mame, "18006138199");
+
+    mame.write_all(b"~ppp~").unwrap();
+    let mut echoed = [0; 5];
+    mame.read_exact(&mut echoed).unwrap();
+
+    sleep(Duration::from_millis(200));
+
+    let page = get(status_port, "/");
+
+    assert!(page.starts_with("HTTP/1.1 200 OK\r\n"), "{page}");
+    assert!(page.contains("<meta http-equiv=\"refresh\""));
+    assert!(page.contains(&format!("Listening on 127.0.0.1:{port}.")), "{page}");
+    assert!(page.contains("Every dial goes to backend command line (connect 127.0.0.1:"), "{page}");
+    assert!(page.contains("<td>1</td><td>127.0.0.1:"), "{page}");
+    assert!(page.contains("</td><td>online</td><td>18006138199</td><td>command line</td><td>5</td><td>5</td>"), "{page}");
+    assert!(page.contains("</td><td>1</td><td>18006138199</td><td>command line</td><td>CONNECT</td></tr>"), "{page}");
+
+    // The heartbeat is once a second, so give the first one a moment.
+    sleep(Duration::from_millis(1100));
+    assert!(get(status_port, "/healthz").starts_with("HTTP/1.1 200 OK\r\n"));
+
+    // What dial() sent to get online: ATE0, ATDT18006138199 and ATD.
+    let metrics = get(status_port, "/metrics");
+    assert!(metrics.contains("\r\n\r\n# HELP sessions_total "), "{metrics}");
+    assert!(metrics.contains("\nsessions_total{modem=\"default\"} 1\n# HELP sessions_active "), "{metrics}");
+    assert!(metrics.contains("\nsessions_active{modem=\"default\"} 1\n# HELP at_commands_total "), "{metrics}");
+    assert!(metrics.ends_with("\nat_commands_total{command=\"D\"} 2\nat_commands_total{command=\"E\"} 1\n"), "{metrics}");
+
+    assert!(get(status_port, "/admin").starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use common::*;
+
+#[test]
+fn syslog_gets_connect_and_disconnect() {
+    let syslog = UdpSocket::bind("127.0.0.1:0").unwrap();
+    syslog.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    let syslog_port = syslog.local_addr().unwrap().port();
+
+    let port = free_port();
+
+    let touchppp = spawn_touchppp(&["-l", &port.to_string(), "--log-syslog", &format!("udp://127.0.0.1:{syslog_port}"), "--syslog-facility", "local0"]);
+
+    let mame = connect(port);
+    let client = mame.local_addr().unwrap();
+
+    let started = Instant::now();
+    let mut messages = Vec::new();
+    let mut hung_up = false;
+
+    while !messages.iter().any(|message: &String| message.contains(" disconnect ")) && started.elapsed() < Duration::from_secs(5) {
+        // Hang up once we know it saw us, so the two can't get reordered.
+        if !hung_up && messages.iter().any(|message| message.contains(" connect ")) {
+            mame.shutdown(std::net::Shutdown::Both).unwrap();
+            hung_up = true;
+        }
+
+        let mut buf = [0; 2048];
+        if let Ok(n) = syslog.recv(&mut buf) {
+            messages.push(String::from_utf8_lossy(&buf[0..n]).to_string());
+        }
+    }
+
+    let find = |msgid: &str| messages.iter().find(|message| message.contains(&format!(" {msgid} "))).cloned().unwrap_or_else(|| panic!("no {msgid} in {messages:?}"));
+
+    // local0 is 16, info is 6.
+    let connect = find("connect");
+    assert!(connect.starts_with("<134>1 "), "{connect}");
+    assert!(connect.contains(&format!(" touchppp {} connect [touchppp@32473 session=\"1\"] Looks like we got a wild MAME @ {client}", touchppp.0.id())), "{connect}");
+
+    let disconnect = find("disconnect");
+    assert!(disconnect.starts_with("<134>1 "), "{disconnect}");
+    assert!(disconnect.ends_with(&format!(" disconnect [touchppp@32473 session=\"1\"] MAME @ {client} hung up.")), "{disconnect}");
+}
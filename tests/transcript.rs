@@ -0,0 +1,163 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::*;
+
+// The transcript without the timestamps, once the session's hung up.
+fn finished_transcript(dir: &std::path::Path) -> String {
+    let started = Instant::now();
+
+    loop {
+        let transcript = std::fs::read_dir(dir).unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .next()
+            .unwrap_or_default();
+
+        if transcript.ends_with("== hung up\n") {
+            return transcript.lines().map(|line| line.split_once("] ").unwrap().1.to_string() + "\n").collect();
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(5), "the transcript never finished: {transcript}");
+        sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn transcript_has_the_whole_call() {
+    let dir = scratch_path("transcript");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let port = free_port();
+    let ppp_port = echo_server();
+
+    let _touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &ppp_port.to_string(), "--at-transcript", dir.to_str().unwrap()]);
+
+    let mut mame: TcpStream = connect(port);
+    let client = mame.local_addr().unwrap();
+
+    at(&mut mame, "ATE0Q0V0&C1&D2S0=0\r", b"OK\r\n");
+    at(&mut mame, "ATS7=60L3\r", b"\r\n0\r\n");
+    at(&mut mame, "ATDT18006138199\r", b"0\r\n");
+    at(&mut mame, "ATD\r", b"79\r\n67\r\n19\r\n");
+
+    mame.write_all(b"~ppp~").unwrap();
+    let mut echoed = [0; 5];
+    mame.read_exact(&mut echoed).unwrap();
+
+    drop(mame);
+
+    assert_eq!(finished_transcript(&dir), format!(concat!(
+        "session 1 from {client}\n",
+        "<- ATE0Q0V0&C1&D2S0=0\\r\n",
+        "-> OK\\r\\n\n",
+        "<- ATS7=60L3\\r\n",
+        "-> \\r\\n0\\r\\n\n",
+        "<- ATDT18006138199\\r\n",
+        "-> 0\\r\\n\n",
+        "<- ATD\\r\n",
+        "== dialed '18006138199', using backend command line (connect 127.0.0.1:{ppp_port})\n",
+        "-> 79\\r\\n67\\r\\n19\\r\\n\n",
+        "== online\n",
+        "== back to commands after 5 bytes up and 5 bytes down\n",
+        "== hung up\n",
+    ), client = client, ppp_port = ppp_port));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn calls_still_work_when_the_transcript_directory_is_missing() {
+    let port = free_port();
+
+    let _touchppp = spawn_touchppp(&["-l", &port.to_string(), "-c", &echo_server().to_string(), "--at-transcript", scratch_path("no-such-dir").to_str().unwrap()]);
+
+    for _ in 0..2 {
+        let mut mame: TcpStream = connect(port);
+        dial(&mut mame, "18006138199");
+    }
+}
+
+// Asks a generic modem with `modem_profile` who it is, checking each answer, and gives back the transcript of it
+// from after echo's turned off.
+fn identify(modem_profile: &str, answers: &[(&str, &str)]) -> String {
+    let dir = scratch_path(&format!("identify-{modem_profile}"));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let port = free_port();
+
+    let _touchppp = spawn_touchppp(&["-l", &port.to_string(), "--backend-builtin", "null", "--profile", "generic", "--modem-profile", modem_profile, "--at-transcript", dir.to_str().unwrap()]);
+
+    let mut mame: TcpStream = connect(port);
+    at(&mut mame, "ATE0\r", b"ATE0\r\r\nOK\r\n");
+    for (command, answer) in answers {
+        at(&mut mame, &format!("{command}\r"), answer.as_bytes());
+    }
+    drop(mame);
+
+    let transcript = finished_transcript(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    transcript.lines().skip(4).map(|line| format!("{line}\n")).collect()
+}
+
+#[test]
+fn webtv_k56_identifies_itself() {
+    assert_eq!(identify("webtv-k56", &[("ATI0", "\r\n33600\r\n\r\nOK\r\n"), ("ATI3", "\r\nTouchPPP V.34 Data Modem\r\n\r\nOK\r\n"), ("ATS7?", "\r\n050\r\n\r\nOK\r\n")]), concat!(
+        "<- ATI0\\r\n",
+        "-> \\r\\n33600\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATI3\\r\n",
+        "-> \\r\\nTouchPPP V.34 Data Modem\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATS7?\\r\n",
+        "-> \\r\\n050\\r\\n\\r\\nOK\\r\\n\n",
+        "== hung up\n",
+    ));
+}
+
+#[test]
+fn rockwell_v34_identifies_itself() {
+    assert_eq!(identify("rockwell-v34", &[("ATI3", "\r\nV1.610-V34_DS\r\n\r\nOK\r\n"), ("ATI4", "\r\nRockwell RC336DPFL\r\n\r\nOK\r\n"), ("ATS51?", "\r\n000\r\n\r\nOK\r\n")]), concat!(
+        "<- ATI3\\r\n",
+        "-> \\r\\nV1.610-V34_DS\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATI4\\r\n",
+        "-> \\r\\nRockwell RC336DPFL\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATS51?\\r\n",
+        "-> \\r\\n000\\r\\n\\r\\nOK\\r\\n\n",
+        "== hung up\n",
+    ));
+}
+
+#[test]
+fn softmodem_identifies_itself() {
+    assert_eq!(identify("softmodem", &[("ATI0", "\r\n56000\r\n\r\nOK\r\n"), ("ATS51=31", "\r\nOK\r\n"), ("ATS51?", "\r\n031\r\n\r\nOK\r\n"), ("ATZE0", "\r\nOK\r\n"), ("ATS51?", "\r\n000\r\n\r\nOK\r\n")]), concat!(
+        "<- ATI0\\r\n",
+        "-> \\r\\n56000\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATS51=31\\r\n",
+        "-> \\r\\nOK\\r\\n\n",
+        "<- ATS51?\\r\n",
+        "-> \\r\\n031\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATZE0\\r\n",
+        "-> \\r\\nOK\\r\\n\n",
+        "<- ATS51?\\r\n",
+        "-> \\r\\n000\\r\\n\\r\\nOK\\r\\n\n",
+        "== hung up\n",
+    ));
+}
+
+#[test]
+fn usr_courier_identifies_itself() {
+    assert_eq!(identify("usr-courier", &[("ATI0", "\r\n5601\r\n\r\nOK\r\n"), ("ATI3", "\r\nU.S. Robotics Courier V.Everything Rev. 5.1\r\n\r\nOK\r\n"), ("ATS7?", "\r\n060\r\n\r\nOK\r\n")]), concat!(
+        "<- ATI0\\r\n",
+        "-> \\r\\n5601\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATI3\\r\n",
+        "-> \\r\\nU.S. Robotics Courier V.Everything Rev. 5.1\\r\\n\\r\\nOK\\r\\n\n",
+        "<- ATS7?\\r\n",
+        "-> \\r\\n060\\r\\n\\r\\nOK\\r\\n\n",
+        "== hung up\n",
+    ));
+}
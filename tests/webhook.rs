@@ -0,0 +1,128 @@
+mod common;
+
+use std::convert::Infallible;
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use sha2::Sha256;
+
+use common::*;
+
+struct Hook {
+    signature: Option<String>,
+    body: Vec<u8>,
+}
+
+// Takes every POST it's sent, answering the first with a 500 so touchppp has to retry it.
+fn hook_server() -> (u16, mpsc::Receiver<Hook>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    listener.set_nonblocking(true).unwrap();
+
+    let (hooks, hook_receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        tokio::runtime::Runtime::new().unwrap().block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            let requests = Arc::new(AtomicUsize::new(0));
+
+            loop {
+                let (touchppp, _) = listener.accept().await.unwrap();
+                let hooks = hooks.clone();
+                let requests = requests.clone();
+
+                tokio::spawn(async move {
+                    let service = service_fn(move |request: hyper::Request<hyper::body::Incoming>| {
+                        let hooks = hooks.clone();
+                        let is_first = requests.fetch_add(1, Ordering::SeqCst) == 0;
+
+                        async move {
+                            let signature = request.headers().get("X-TouchPPP-Signature").map(|value| value.to_str().unwrap().to_string());
+                            let body = request.into_body().collect().await.unwrap().to_bytes().to_vec();
+
+                            let _ = hooks.send(Hook { signature, body });
+
+                            let mut response = Response::new(Full::new(Bytes::new()));
+                            if is_first {
+                                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                            }
+
+                            Ok::<_, Infallible>(response)
+                        }
+                    });
+
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(touchppp), service).await;
+                });
+            }
+        });
+    });
+
+    (port, hook_receiver)
+}
+
+fn next_event(hooks: &mpsc::Receiver<Hook>) -> serde_json::Value {
+    let hook = hooks.recv_timeout(Duration::from_secs(10)).expect("no webhook came");
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(&hook.body);
+    let expected: String = mac.finalize().into_bytes().iter().map(|byte| format!("{byte:02x}")).collect();
+
+    assert_eq!(hook.signature, Some(format!("sha256={expected}")));
+
+    serde_json::from_slice(&hook.body).unwrap()
+}
+
+#[test]
+fn webhook_gets_signed_session_events() {
+    let port = free_port();
+    let (hook_port, hooks) = hook_server();
+
+    let _touchppp = spawn_touchppp(&[
+        "-l", &port.to_string(),
+        // Nothing's listening there, so the dial fails.
+        "-c", &free_port().to_string(),
+        "--connect-retries", "0",
+        "--webhook", &format!("http://127.0.0.1:{hook_port}/touchppp"),
+        "--webhook-secret", "s3cret",
+        "--webhook-retries", "1",
+    ]);
+
+    let mut mame: TcpStream = connect(port);
+    at(&mut mame, "ATE0\r", b"OK\r\n");
+    at(&mut mame, "ATDT18006138199\r", b"0\r\n");
+    // Refused, so BUSY.
+    at(&mut mame, "ATD\r", b"7\r\n");
+
+    // The first try got a 500, so the same event comes again.
+    let connect = next_event(&hooks);
+    assert_eq!(connect["event"], "connect");
+    assert_eq!(next_event(&hooks), connect);
+    assert_eq!(connect["session"], 1);
+    assert!(connect["client"].as_str().unwrap().starts_with("127.0.0.1:"));
+
+    let dial_failed = next_event(&hooks);
+    assert_eq!(dial_failed["event"], "dial_failed");
+    assert_eq!(dial_failed["number"], "18006138199");
+    assert_eq!(dial_failed["backend"], "command line");
+    assert!(dial_failed["reason"].as_str().unwrap().starts_with("can't touch PPP @ 127.0.0.1:"), "{dial_failed}");
+
+    drop(mame);
+
+    let disconnect = next_event(&hooks);
+    assert_eq!(disconnect["event"], "disconnect");
+    assert_eq!(disconnect["session"], 1);
+    assert_eq!(disconnect["reason"], "MAME hung up");
+    assert_eq!(disconnect["bytes_up"], 0);
+    assert!(disconnect["duration_ms"].is_u64());
+    // The dial never went through, so there's no speed to report.
+    assert_eq!(disconnect["summary"]["reason"], "MAME hung up");
+    assert!(disconnect["summary"]["connect_speed"].is_null());
+}